@@ -0,0 +1,31 @@
+;; Пример фильтра для директивы wasm_filter: требует заголовок X-Tenant
+;; и передает его upstream как X-Routing-Key.
+;;
+;; Сборка в бинарный модуль (текстовый .wat загружается и напрямую):
+;;   wat2wasm tenant.wat -o /etc/adq-pingora/filters/tenant.wasm
+(module
+  (import "adq" "get_request_header" (func $get_request_header (param i32 i32 i32 i32) (result i32)))
+  (import "adq" "set_request_header" (func $set_request_header (param i32 i32 i32 i32)))
+  (import "adq" "respond" (func $respond (param i32 i32 i32)))
+
+  (memory (export "memory") 1)
+
+  (data (i32.const 0) "x-tenant")
+  (data (i32.const 16) "x-routing-key")
+  (data (i32.const 32) "missing X-Tenant")
+
+  ;; Буфер значения заголовка: 256 байт с адреса 64
+  (func (export "on_request")
+    (local $len i32)
+    (local.set $len
+      (call $get_request_header (i32.const 0) (i32.const 8) (i32.const 64) (i32.const 256)))
+
+    ;; Заголовка нет, он пустой или длиннее буфера - 400
+    (if (i32.or
+          (i32.le_s (local.get $len) (i32.const 0))
+          (i32.gt_s (local.get $len) (i32.const 256)))
+      (then
+        (call $respond (i32.const 400) (i32.const 32) (i32.const 16))
+        (return)))
+
+    (call $set_request_header (i32.const 16) (i32.const 13) (i32.const 64) (local.get $len))))
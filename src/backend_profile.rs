@@ -0,0 +1,111 @@
+use pingora::http::{RequestHeader, ResponseHeader};
+use pingora::prelude::*;
+
+use crate::config::{BackendProfileConfig, ProxyHeadersConfig};
+use crate::cors::{add_cors_headers_for_header, add_security_headers};
+
+/// Значение X-Forwarded-Proto: https для профилей с force_https_proto,
+/// иначе схема запроса клиента (RequestContext::scheme)
+pub fn forwarded_proto(scheme: &'static str, profile: Option<&BackendProfileConfig>) -> &'static str {
+    if profile.is_some_and(|p| p.force_https_proto) {
+        "https"
+    } else {
+        scheme
+    }
+}
+
+/// Добавляет X-Forwarded-Proto, а для профилей с forwarded_host еще
+/// X-Forwarded-Host и X-Forwarded-Port
+pub fn add_forwarded_headers(
+    upstream_request: &mut RequestHeader,
+    request: &RequestHeader,
+    headers: &ProxyHeadersConfig,
+    scheme: &'static str,
+    profile: Option<&BackendProfileConfig>,
+) -> Result<()> {
+    let proto = forwarded_proto(scheme, profile);
+    upstream_request.insert_header(headers.forwarded_proto.clone(), proto)?;
+
+    if profile.is_some_and(|p| p.forwarded_host) {
+        if let Some(host) = request.headers.get("host").and_then(|v| v.to_str().ok()) {
+            upstream_request.insert_header(headers.forwarded_host.clone(), host)?;
+        }
+        let port = if proto == "https" { "443" } else { "80" };
+        upstream_request.insert_header(headers.forwarded_port.clone(), port)?;
+    }
+    Ok(())
+}
+
+/// Security заголовки и CORS заголовки ответа upstream; CORS не добавляются,
+/// если бэкенд управляет ими сам (manages_own_cors)
+pub fn add_response_headers(
+    request: &RequestHeader,
+    response: &mut ResponseHeader,
+    profile: Option<&BackendProfileConfig>,
+) -> Result<()> {
+    add_security_headers(response)?;
+    if !profile.is_some_and(|p| p.manages_own_cors) {
+        add_cors_headers_for_header(request, response)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(raw_headers: &[(&str, &str)]) -> RequestHeader {
+        let mut request = RequestHeader::build("GET", b"/oauth/v2/keys", None).unwrap();
+        for (name, value) in raw_headers {
+            request.insert_header(name.to_string(), *value).unwrap();
+        }
+        request
+    }
+
+    fn identity_profile() -> BackendProfileConfig {
+        BackendProfileConfig {
+            upstreams: vec!["identity".to_string()],
+            force_https_proto: true,
+            forwarded_host: true,
+            manages_own_cors: true,
+            grpc_web: true,
+        }
+    }
+
+    #[test]
+    fn test_profile_forces_https_proto() {
+        let headers = ProxyHeadersConfig::default();
+        let client = request(&[("Host", "id.example.com")]);
+        let profile = identity_profile();
+
+        let mut upstream = request(&[]);
+        add_forwarded_headers(&mut upstream, &client, &headers, "http", Some(&profile)).unwrap();
+        assert_eq!(upstream.headers.get("x-forwarded-proto").unwrap(), "https");
+        assert_eq!(upstream.headers.get("x-forwarded-host").unwrap(), "id.example.com");
+        assert_eq!(upstream.headers.get("x-forwarded-port").unwrap(), "443");
+
+        // Без профиля передается схема клиента, Host и Port не добавляются
+        let mut upstream = request(&[]);
+        add_forwarded_headers(&mut upstream, &client, &headers, "http", None).unwrap();
+        assert_eq!(upstream.headers.get("x-forwarded-proto").unwrap(), "http");
+        assert!(upstream.headers.get("x-forwarded-host").is_none());
+
+        assert_eq!(forwarded_proto("https", None), "https");
+    }
+
+    #[test]
+    fn test_profile_skips_cors_injection() {
+        let client = request(&[("Origin", "https://api.ad-quest.ru")]);
+        let profile = identity_profile();
+
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        add_response_headers(&client, &mut response, Some(&profile)).unwrap();
+        assert!(response.headers.get("access-control-allow-origin").is_none());
+        assert!(response.headers.get("x-content-type-options").is_some());
+
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        let plain = BackendProfileConfig { manages_own_cors: false, ..identity_profile() };
+        add_response_headers(&client, &mut response, Some(&plain)).unwrap();
+        assert_eq!(response.headers.get("access-control-allow-origin").unwrap(), "https://api.ad-quest.ru");
+    }
+}
@@ -0,0 +1,402 @@
+use bytes::{Bytes, BytesMut};
+use log::{debug, warn};
+use serde_json::Value;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::config::{parse_size, RedactionConfig};
+
+/// Преобразование тела запроса или ответа, полученного целиком
+pub trait BodyTransform: fmt::Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+    /// Применяется ли преобразование к телу с таким Content-Type
+    fn applies_to(&self, content_type: &str) -> bool;
+    /// Новое тело; None - тело не изменилось
+    fn transform(&self, body: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// Шаг пути JSON
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    /// `.name` или `['name']`
+    Key(String),
+    /// `[0]`
+    Index(usize),
+    /// `.*` или `[*]` - все поля объекта или элементы массива
+    Wildcard,
+    /// `..name` - поле на любой глубине
+    Descendant(String),
+}
+
+/// Путь в JSON документе: `$.password`, `$.user.token`, `$.items[*].card`, `$..secret`
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPath {
+    segments: Vec<Segment>,
+}
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<Self, String> {
+        let invalid = |reason: &str| format!("invalid redaction path '{}': {}", path, reason);
+        let mut rest = path.trim().strip_prefix('$').ok_or_else(|| invalid("must start with $"))?;
+        let mut segments = Vec::new();
+
+        // Имя поля до следующего `.` или `[`
+        fn take_name(rest: &str) -> (&str, &str) {
+            let end = rest.find(['.', '[']).unwrap_or(rest.len());
+            rest.split_at(end)
+        }
+
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix("..") {
+                let (name, tail) = take_name(after);
+                if name.is_empty() || name == "*" {
+                    return Err(invalid("expected a field name after .."));
+                }
+                segments.push(Segment::Descendant(name.to_string()));
+                rest = tail;
+            } else if let Some(after) = rest.strip_prefix('.') {
+                let (name, tail) = take_name(after);
+                segments.push(match name {
+                    "" => return Err(invalid("empty field name")),
+                    "*" => Segment::Wildcard,
+                    name => Segment::Key(name.to_string()),
+                });
+                rest = tail;
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let (inner, tail) = after.split_once(']').ok_or_else(|| invalid("unclosed ["))?;
+                let quoted = inner
+                    .strip_prefix('\'')
+                    .and_then(|s| s.strip_suffix('\''))
+                    .or_else(|| inner.strip_prefix('"').and_then(|s| s.strip_suffix('"')));
+                segments.push(match (inner, quoted) {
+                    (_, Some(name)) => Segment::Key(name.to_string()),
+                    ("*", None) => Segment::Wildcard,
+                    (index, None) => Segment::Index(index.parse().map_err(|_| invalid("expected an index, * or a quoted name in []"))?),
+                });
+                rest = tail;
+            } else {
+                return Err(invalid("expected . or ["));
+            }
+        }
+
+        if segments.is_empty() {
+            return Err(invalid("the whole document cannot be redacted"));
+        }
+        Ok(Self { segments })
+    }
+}
+
+/// Что делать с найденным значением
+#[derive(Debug, Clone, PartialEq)]
+enum RedactAction {
+    Mask(Value),
+    Remove,
+}
+
+impl RedactAction {
+    fn entry(&self, map: &mut serde_json::Map<String, Value>, key: &str) -> usize {
+        match self {
+            RedactAction::Mask(mask) => map.get_mut(key).map_or(0, |value| {
+                *value = mask.clone();
+                1
+            }),
+            RedactAction::Remove => map.remove(key).map_or(0, |_| 1),
+        }
+    }
+
+    fn item(&self, items: &mut Vec<Value>, index: usize) -> usize {
+        if index >= items.len() {
+            return 0;
+        }
+        match self {
+            RedactAction::Mask(mask) => items[index] = mask.clone(),
+            RedactAction::Remove => {
+                items.remove(index);
+            }
+        }
+        1
+    }
+}
+
+/// Применяет путь к значению, возвращает количество скрытых значений
+fn redact(value: &mut Value, segments: &[Segment], action: &RedactAction) -> usize {
+    let Some((segment, rest)) = segments.split_first() else {
+        return 0;
+    };
+    match (segment, value) {
+        (Segment::Key(key), Value::Object(map)) => {
+            if rest.is_empty() {
+                action.entry(map, key)
+            } else {
+                map.get_mut(key).map_or(0, |child| redact(child, rest, action))
+            }
+        }
+        (Segment::Index(index), Value::Array(items)) => {
+            if rest.is_empty() {
+                action.item(items, *index)
+            } else {
+                items.get_mut(*index).map_or(0, |child| redact(child, rest, action))
+            }
+        }
+        (Segment::Wildcard, Value::Object(map)) => {
+            if rest.is_empty() {
+                let keys: Vec<String> = map.keys().cloned().collect();
+                keys.iter().map(|key| action.entry(map, key)).sum()
+            } else {
+                map.values_mut().map(|child| redact(child, rest, action)).sum()
+            }
+        }
+        (Segment::Wildcard, Value::Array(items)) => {
+            if rest.is_empty() {
+                (0..items.len()).rev().map(|index| action.item(items, index)).sum()
+            } else {
+                items.iter_mut().map(|child| redact(child, rest, action)).sum()
+            }
+        }
+        (Segment::Descendant(key), value) => {
+            let mut count = 0;
+            if let Value::Object(map) = &mut *value {
+                count += if rest.is_empty() {
+                    action.entry(map, key)
+                } else {
+                    map.get_mut(key).map_or(0, |child| redact(child, rest, action))
+                };
+            }
+            let children: Box<dyn Iterator<Item = &mut Value>> = match value {
+                Value::Object(map) => Box::new(map.values_mut()),
+                Value::Array(items) => Box::new(items.iter_mut()),
+                _ => Box::new(std::iter::empty()),
+            };
+            count + children.map(|child| redact(child, segments, action)).sum::<usize>()
+        }
+        _ => 0,
+    }
+}
+
+/// Маскирование или удаление полей JSON по путям из redaction.paths
+#[derive(Debug)]
+pub struct JsonRedactor {
+    paths: Vec<JsonPath>,
+    action: RedactAction,
+}
+
+impl JsonRedactor {
+    pub fn from_config(config: &RedactionConfig) -> Result<Self, String> {
+        let action = match config.action.as_str() {
+            "mask" => RedactAction::Mask(Value::String(config.mask.clone())),
+            "remove" => RedactAction::Remove,
+            other => return Err(format!("invalid redaction action '{}', expected mask or remove", other)),
+        };
+        let paths = config.paths.iter().map(|path| JsonPath::parse(path)).collect::<Result<_, _>>()?;
+        Ok(Self { paths, action })
+    }
+
+    /// Скрывает значения в разобранном документе, возвращает их количество
+    pub fn redact_value(&self, value: &mut Value) -> usize {
+        self.paths
+            .iter()
+            .map(|path| redact(value, &path.segments, &self.action))
+            .sum()
+    }
+}
+
+impl BodyTransform for JsonRedactor {
+    fn name(&self) -> &'static str {
+        "json_redaction"
+    }
+
+    fn applies_to(&self, content_type: &str) -> bool {
+        content_type
+            .split(';')
+            .next()
+            .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("application/json"))
+    }
+
+    fn transform(&self, body: &[u8]) -> Option<Vec<u8>> {
+        let mut value: Value = match serde_json::from_slice(body) {
+            Ok(value) => value,
+            Err(e) => {
+                debug!("Body is not valid JSON, redaction skipped: {}", e);
+                return None;
+            }
+        };
+        if self.redact_value(&mut value) == 0 {
+            return None;
+        }
+        serde_json::to_vec(&value).ok()
+    }
+}
+
+/// Набор преобразований тел для location с `redact on`
+#[derive(Debug)]
+pub struct BodyPipeline {
+    transforms: Vec<Arc<dyn BodyTransform>>,
+    max_body_size: usize,
+}
+
+impl BodyPipeline {
+    /// Pipeline по конфигурации; None - пути для маскирования не заданы
+    pub fn from_config(config: &RedactionConfig) -> Result<Option<Self>, String> {
+        let redactor = JsonRedactor::from_config(config)?;
+        let max_body_size = parse_size(&config.max_body_size)
+            .ok_or_else(|| format!("invalid redaction max_body_size '{}'", config.max_body_size))?;
+        if config.paths.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            transforms: vec![Arc::new(redactor)],
+            max_body_size,
+        }))
+    }
+
+    pub fn with_transform(mut self, transform: Arc<dyn BodyTransform>) -> Self {
+        self.transforms.push(transform);
+        self
+    }
+
+    /// Сборщик тела для преобразований, применимых к Content-Type; None - преобразовывать нечего
+    pub fn collector(&self, content_type: Option<&str>) -> Option<BodyCollector> {
+        let content_type = content_type?;
+        let transforms: Vec<Arc<dyn BodyTransform>> = self
+            .transforms
+            .iter()
+            .filter(|transform| transform.applies_to(content_type))
+            .cloned()
+            .collect();
+        (!transforms.is_empty()).then(|| BodyCollector {
+            transforms,
+            limit: self.max_body_size,
+            data: BytesMut::new(),
+            passthrough: false,
+        })
+    }
+}
+
+/// Накапливает тело и отдает его преобразованным в конце потока. Тело больше
+/// max_body_size передается дальше без изменений
+#[derive(Debug)]
+pub struct BodyCollector {
+    transforms: Vec<Arc<dyn BodyTransform>>,
+    limit: usize,
+    data: BytesMut,
+    passthrough: bool,
+}
+
+impl BodyCollector {
+    /// Принимает часть тела и возвращает то, что нужно отправить дальше
+    pub fn push(&mut self, chunk: Option<Bytes>, end_of_stream: bool) -> Option<Bytes> {
+        if self.passthrough {
+            return chunk;
+        }
+
+        if let Some(chunk) = chunk {
+            if self.data.len() + chunk.len() > self.limit {
+                warn!("Body exceeds redaction max_body_size {}, passed without transformation", self.limit);
+                self.passthrough = true;
+                let mut out = std::mem::take(&mut self.data);
+                out.extend_from_slice(&chunk);
+                return Some(out.freeze());
+            }
+            self.data.extend_from_slice(&chunk);
+        }
+
+        if !end_of_stream {
+            return None;
+        }
+        self.passthrough = true;
+        let mut body = std::mem::take(&mut self.data).freeze();
+        for transform in &self.transforms {
+            if let Some(transformed) = transform.transform(&body) {
+                debug!("Body transformed by {}", transform.name());
+                body = Bytes::from(transformed);
+            }
+        }
+        (!body.is_empty()).then_some(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config(paths: &[&str], action: &str) -> RedactionConfig {
+        RedactionConfig {
+            paths: paths.iter().map(|p| p.to_string()).collect(),
+            action: action.to_string(),
+            mask: "***".to_string(),
+            max_body_size: "1k".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_configured_paths_masked() {
+        let pipeline = BodyPipeline::from_config(&config(
+            &["$.password", "$.token", "$.user['card'].number", "$.sessions[*].secret", "$..api_key", "$.codes[1]"],
+            "mask",
+        ))
+        .unwrap()
+        .unwrap();
+        let body = json!({
+            "login": "adquest",
+            "password": "hunter2",
+            "token": {"access": "abc"},
+            "user": {"card": {"number": "4111111111111111", "exp": "12/30"}},
+            "sessions": [{"id": 1, "secret": "s1"}, {"id": 2, "secret": "s2"}],
+            "nested": {"deep": [{"api_key": "k1"}]},
+            "codes": [10, 20, 30],
+        });
+
+        let mut collector = pipeline.collector(Some("application/json; charset=utf-8")).unwrap();
+        let raw = serde_json::to_vec(&body).unwrap();
+        let (first, second) = raw.split_at(raw.len() / 2);
+        assert_eq!(collector.push(Some(Bytes::copy_from_slice(first)), false), None);
+        let out = collector.push(Some(Bytes::copy_from_slice(second)), true).unwrap();
+
+        let redacted: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(redacted["login"], "adquest");
+        assert_eq!(redacted["password"], "***");
+        assert_eq!(redacted["token"], "***");
+        assert_eq!(redacted["user"]["card"]["number"], "***");
+        assert_eq!(redacted["user"]["card"]["exp"], "12/30");
+        assert_eq!(redacted["sessions"][1]["secret"], "***");
+        assert_eq!(redacted["sessions"][1]["id"], 2);
+        assert_eq!(redacted["nested"]["deep"][0]["api_key"], "***");
+        assert_eq!(redacted["codes"], json!([10, "***", 30]));
+
+        // Только application/json
+        assert!(pipeline.collector(Some("text/plain")).is_none());
+        assert!(pipeline.collector(Some("application/x-ndjson")).is_none());
+        assert!(pipeline.collector(None).is_none());
+    }
+
+    #[test]
+    fn test_remove_and_passthrough() {
+        let pipeline = BodyPipeline::from_config(&config(&["$.password", "$.items[*].token"], "remove"))
+            .unwrap()
+            .unwrap();
+        let mut collector = pipeline.collector(Some("application/json")).unwrap();
+        let body = br#"{"password":"x","items":[{"id":1,"token":"t"}]}"#;
+        let out = collector.push(Some(Bytes::from_static(body)), true).unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&out).unwrap(), json!({"items": [{"id": 1}]}));
+
+        // Невалидный JSON и тело больше max_body_size передаются как есть
+        let mut collector = pipeline.collector(Some("application/json")).unwrap();
+        assert_eq!(collector.push(Some(Bytes::from_static(b"{not json")), true).unwrap(), "{not json");
+        let mut collector = pipeline.collector(Some("application/json")).unwrap();
+        let large = Bytes::from(format!(r#"{{"password":"{}"}}"#, "x".repeat(2048)));
+        assert_eq!(collector.push(Some(large.clone()), false).unwrap(), large);
+        assert_eq!(collector.push(None, true), None);
+    }
+
+    #[test]
+    fn test_path_validation() {
+        assert!(JsonPath::parse("password").is_err());
+        assert!(JsonPath::parse("$").is_err());
+        assert!(JsonPath::parse("$.a[x]").is_err());
+        assert!(JsonPath::parse("$.a[0").is_err());
+        assert!(JsonPath::parse("$.").is_err());
+        assert!(JsonRedactor::from_config(&config(&["$.a"], "hide")).is_err());
+        assert!(BodyPipeline::from_config(&config(&[], "mask")).unwrap().is_none());
+    }
+}
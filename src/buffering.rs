@@ -0,0 +1,195 @@
+use bytes::{Bytes, BytesMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::metrics::{BUFFERED_RESPONSE_BYTES, RESPONSE_BUFFERING};
+
+/// Общий лимит памяти под буферизованные тела ответов (global.buffered_body_budget)
+#[derive(Debug)]
+pub struct BufferBudget {
+    limit: usize,
+    used: AtomicUsize,
+}
+
+impl BufferBudget {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// Резервирует `size` байт, если они помещаются в лимит
+    fn try_reserve(&self, size: usize) -> bool {
+        let reserved = self
+            .used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                used.checked_add(size).filter(|total| *total <= self.limit)
+            })
+            .is_ok();
+        if reserved {
+            BUFFERED_RESPONSE_BYTES.add(size as i64);
+        }
+        reserved
+    }
+
+    fn release(&self, size: usize) {
+        self.used.fetch_sub(size, Ordering::AcqRel);
+        BUFFERED_RESPONSE_BYTES.sub(size as i64);
+    }
+
+    /// Занятый буферами объем памяти
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Acquire)
+    }
+}
+
+/// Буфер тела ответа upstream (proxy_buffering on): тело отдается клиенту целиком
+/// после получения от upstream. При превышении proxy_buffers_size или общего лимита
+/// накопленное отправляется, и ответ дальше передается потоком.
+#[derive(Debug)]
+pub struct ResponseBuffer {
+    budget: Arc<BufferBudget>,
+    limit: usize,
+    data: BytesMut,
+    /// Превышен лимит - тело передается потоком
+    spilled: bool,
+}
+
+impl ResponseBuffer {
+    pub fn new(budget: Arc<BufferBudget>, limit: usize) -> Self {
+        Self {
+            budget,
+            limit,
+            data: BytesMut::new(),
+            spilled: false,
+        }
+    }
+
+    /// Идет ли еще буферизация (ответ не начал передаваться клиенту)
+    pub fn is_buffering(&self) -> bool {
+        !self.spilled
+    }
+
+    /// Принимает часть тела от upstream и возвращает то, что нужно отправить клиенту
+    pub fn push(&mut self, chunk: Option<Bytes>, end_of_stream: bool) -> Option<Bytes> {
+        if self.spilled {
+            return chunk;
+        }
+
+        if let Some(chunk) = chunk {
+            if self.data.len() + chunk.len() > self.limit || !self.budget.try_reserve(chunk.len()) {
+                RESPONSE_BUFFERING.with_label_values(&["spilled"]).inc();
+                self.spilled = true;
+                let mut out = self.take();
+                out.extend_from_slice(&chunk);
+                return Some(out.freeze());
+            }
+            self.data.extend_from_slice(&chunk);
+        }
+
+        if end_of_stream {
+            RESPONSE_BUFFERING.with_label_values(&["complete"]).inc();
+            self.spilled = true;
+            let out = self.take();
+            return (!out.is_empty()).then(|| out.freeze());
+        }
+        None
+    }
+
+    /// Отбрасывает накопленное тело при ошибке upstream, возвращает его размер
+    pub fn discard(&mut self) -> usize {
+        RESPONSE_BUFFERING.with_label_values(&["failed"]).inc();
+        self.spilled = true;
+        self.take().len()
+    }
+
+    /// Забирает накопленные данные и освобождает их место в общем лимите
+    fn take(&mut self) -> BytesMut {
+        let data = std::mem::take(&mut self.data);
+        self.budget.release(data.len());
+        data
+    }
+}
+
+impl Drop for ResponseBuffer {
+    fn drop(&mut self) {
+        self.take();
+    }
+}
+
+/// Нужно ли буферизовать ответ: SSE, gRPC и upgrade ответы всегда передаются потоком
+pub fn should_buffer(status: u16, content_type: Option<&str>, content_length: Option<usize>, limit: usize) -> bool {
+    let streaming = content_type.is_some_and(|ct| {
+        let ct = ct.trim_start().to_ascii_lowercase();
+        ct.starts_with("text/event-stream") || ct.starts_with("application/grpc")
+    });
+    status != 101 && !streaming && content_length.is_none_or(|len| len <= limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(budget: &Arc<BufferBudget>, limit: usize) -> ResponseBuffer {
+        ResponseBuffer::new(budget.clone(), limit)
+    }
+
+    #[test]
+    fn test_body_sent_only_when_complete() {
+        let budget = Arc::new(BufferBudget::new(1024));
+        let mut buffer = buffer(&budget, 100);
+
+        assert_eq!(buffer.push(Some(Bytes::from_static(b"{\"items\":")), false), None);
+        assert_eq!(buffer.push(Some(Bytes::from_static(b"[1,2]")), false), None);
+        assert_eq!(budget.used(), 14);
+        assert_eq!(buffer.push(None, true), Some(Bytes::from_static(b"{\"items\":[1,2]}")));
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn test_spillover_to_streaming() {
+        let budget = Arc::new(BufferBudget::new(1024));
+        let mut buffer = buffer(&budget, 8);
+
+        assert_eq!(buffer.push(Some(Bytes::from_static(b"12345")), false), None);
+        // Превышен proxy_buffers_size: накопленное уходит вместе с текущей частью
+        assert_eq!(buffer.push(Some(Bytes::from_static(b"67890")), false), Some(Bytes::from_static(b"1234567890")));
+        assert!(!buffer.is_buffering());
+        assert_eq!(budget.used(), 0);
+        assert_eq!(buffer.push(Some(Bytes::from_static(b"abc")), false), Some(Bytes::from_static(b"abc")));
+        assert_eq!(buffer.push(None, true), None);
+
+        // Исчерпан общий лимит памяти
+        let shared = Arc::new(BufferBudget::new(6));
+        let mut first = ResponseBuffer::new(shared.clone(), 100);
+        let mut second = ResponseBuffer::new(shared.clone(), 100);
+        assert_eq!(first.push(Some(Bytes::from_static(b"aaaa")), false), None);
+        assert_eq!(second.push(Some(Bytes::from_static(b"bbbb")), false), Some(Bytes::from_static(b"bbbb")));
+        assert!(first.is_buffering());
+        drop(first);
+        assert_eq!(shared.used(), 0);
+    }
+
+    #[test]
+    fn test_upstream_failure_discards_partial_body() {
+        let budget = Arc::new(BufferBudget::new(1024));
+        let mut buffer = buffer(&budget, 100);
+
+        assert_eq!(buffer.push(Some(Bytes::from_static(b"{\"partial\":")), false), None);
+        assert_eq!(buffer.discard(), 11);
+        assert_eq!(budget.used(), 0);
+        // Ничего из частичного тела не отправляется клиенту
+        assert_eq!(buffer.push(None, true), None);
+    }
+
+    #[test]
+    fn test_streaming_responses_not_buffered() {
+        assert!(should_buffer(200, Some("application/json"), Some(512), 1024));
+        assert!(should_buffer(200, None, None, 1024));
+        assert!(!should_buffer(200, Some("text/event-stream; charset=utf-8"), None, 1024));
+        assert!(!should_buffer(200, Some("application/grpc"), None, 1024));
+        assert!(!should_buffer(101, None, None, 1024));
+        assert!(!should_buffer(200, Some("application/json"), Some(4096), 1024));
+    }
+}
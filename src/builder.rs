@@ -0,0 +1,1244 @@
+use async_trait::async_trait;
+use log::{info, warn};
+use pingora_core::apps::HttpServerOptions;
+use pingora_core::listeners::TcpSocketOptions;
+use pingora_core::server::configuration::Opt;
+use pingora_core::server::{Server, ShutdownWatch};
+use pingora_core::services::background::{background_service, BackgroundService};
+use pingora_core::services::listening::Service as ListeningService;
+use pingora_core::services::Service;
+use pingora_load_balancing::discovery::Static;
+use pingora_load_balancing::health_check::TcpHealthCheck;
+use pingora_load_balancing::selection::RoundRobin;
+use pingora_load_balancing::{Backend, Backends, LoadBalancer};
+use pingora_proxy::http_proxy;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use crate::body_transform::BodyPipeline;
+use crate::cache::CacheManager;
+use crate::challenge::Challenge;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::compression::Compression;
+use crate::config::{parse_size, Config, IpFilterConfig, ResolverDirective, UpstreamBlock, CONFIG_VERSION};
+use crate::config_check::Report;
+use crate::dns::{DnsDiscovery, DnsResolver};
+use crate::drain::DrainTracker;
+use crate::error::ProxyError;
+use crate::error_messages::{self, ErrorMessages};
+use crate::experiments::Experiments;
+use crate::fallback::FallbackResponses;
+use crate::filter::{HeaderRules, IPFilter, UaFilter};
+use crate::flight_recorder::FlightRecorder;
+use crate::health_events::{HealthEvents, HealthObserver};
+use crate::keepalive::{KeepaliveApp, KeepaliveTracker};
+use crate::logging::{self, init_logging, LogReopenService, LogScrubber, LoggingMiddleware};
+use crate::metrics::{self, init_metrics, MetricsApp};
+use crate::preflight;
+use crate::process_metrics::ProcessMetricsService;
+use crate::proxy::{AdQuestProxy, ProxyComponents};
+use crate::proxy_protocol::ProxyProtocolApp;
+use crate::reload::{ConfigReloader, ReloadableProxy};
+use crate::scheme::SchemeResolver;
+use crate::schedules::{ScheduleService, Schedules};
+use crate::smoke_test::{self, SmokeTarget};
+use crate::status::{HealthChecks, RecordingHealthCheck, StatusSource};
+use crate::warmup::WarmupService;
+
+/// Сборка прокси из конфигурации: балансировщики upstream, кеш, circuit breaker,
+/// IP фильтр, логирование и фоновые сервисы. `build` отдает компоненты приложениям,
+/// встраивающим прокси, и тестам; `into_server` собирает из них Server, как при запуске
+/// adq-pingora. Компоненты можно заменить готовыми до сборки
+pub struct ProxyBuilder {
+    config: Arc<Config>,
+    /// Файл конфигурации и каталоги фрагментов, из которых она прочитана (для reload)
+    source: Option<(String, Vec<PathBuf>)>,
+    smoke_test: bool,
+    upstreams: HashMap<String, Arc<LoadBalancer<RoundRobin>>>,
+    cache_manager: Option<Arc<CacheManager>>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    ip_filter: Option<Arc<IPFilter>>,
+    logging_middleware: Option<Arc<LoggingMiddleware>>,
+    challenge: Option<Arc<Challenge>>,
+    drain_tracker: Option<Arc<DrainTracker>>,
+    keepalive_tracker: Option<Arc<KeepaliveTracker>>,
+    fallbacks: Option<Arc<FallbackResponses>>,
+    schedules: Option<Arc<Schedules>>,
+    flight_recorder: Option<Arc<FlightRecorder>>,
+}
+
+/// Собранный прокси: `proxy` передается в `http_proxy_service`, `background_services` -
+/// в `Server::add_services`. Остальные компоненты нужны admin API и метрикам
+pub struct BuiltProxy {
+    pub proxy: AdQuestProxy,
+    pub config: Arc<Config>,
+    /// Балансировщики по имени upstream; бэкенды заполняет фоновый сервис health check
+    pub upstreams: HashMap<String, Arc<LoadBalancer<RoundRobin>>>,
+    pub background_services: Vec<Box<dyn Service>>,
+    pub logging_middleware: Arc<LoggingMiddleware>,
+    pub health_events: Arc<HealthEvents>,
+    pub health_checks: Arc<HealthChecks>,
+    pub drain_tracker: Arc<DrainTracker>,
+    pub keepalive_tracker: Arc<KeepaliveTracker>,
+    pub circuit_breaker: Option<Arc<CircuitBreaker>>,
+    pub challenge: Arc<Challenge>,
+    pub flight_recorder: Option<Arc<FlightRecorder>>,
+    pub schedules: Arc<Schedules>,
+    reload: ReloadState,
+}
+
+impl ProxyBuilder {
+    pub fn new(config: impl Into<Arc<Config>>) -> Self {
+        Self {
+            config: config.into(),
+            source: None,
+            smoke_test: false,
+            upstreams: HashMap::new(),
+            cache_manager: None,
+            circuit_breaker: None,
+            ip_filter: None,
+            logging_middleware: None,
+            challenge: None,
+            drain_tracker: None,
+            keepalive_tracker: None,
+            fallbacks: None,
+            schedules: None,
+            flight_recorder: None,
+        }
+    }
+
+    /// Прокси из конфигурации без замены компонентов
+    pub fn from_config(config: impl Into<Arc<Config>>) -> Result<BuiltProxy, ProxyError> {
+        Self::new(config).build()
+    }
+
+    /// Конфигурация из файла с conf.d и каталогами `--config-dir`. Нечитаемый файл
+    /// заменяется конфигурацией по умолчанию; конфигурация новее бинарника или
+    /// с опечатками при strict: true - ошибка
+    pub fn load(config_path: &str, config_dirs: &[PathBuf]) -> Result<Self, ProxyError> {
+        let config = match Config::load_with_fragments(config_path, config_dirs) {
+            Ok(config) => config,
+            Err(e @ ProxyError::Schema(_)) => return Err(e),
+            Err(e) => {
+                eprintln!("Failed to load config from {}: {}", config_path, e);
+                eprintln!("Using default configuration");
+                Config::default()
+            }
+        };
+        let mut builder = Self::new(config);
+        builder.source = Some((config_path.to_string(), config_dirs.to_vec()));
+        Ok(builder)
+    }
+
+    /// Режим --smoke-test: listener на свободном порту loopback вместо listen адресов,
+    /// без порта метрик; после запуска маршруты проверяются запросами и процесс завершается
+    pub fn smoke_test(mut self, enabled: bool) -> Self {
+        self.smoke_test = enabled;
+        self
+    }
+
+    /// Готовый балансировщик вместо upstream `name` из конфигурации; для него
+    /// health check не запускается
+    pub fn upstream(mut self, name: &str, lb: Arc<LoadBalancer<RoundRobin>>) -> Self {
+        self.upstreams.insert(name.to_string(), lb);
+        self
+    }
+
+    /// Кеш вместо создаваемого по секции cache (используется и при cache.enabled: false)
+    pub fn cache_manager(mut self, cache_manager: Arc<CacheManager>) -> Self {
+        self.cache_manager = Some(cache_manager);
+        self
+    }
+
+    pub fn circuit_breaker(mut self, circuit_breaker: Arc<CircuitBreaker>) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
+    /// IP фильтр вместо создаваемого по секции ip_filter
+    pub fn ip_filter(mut self, ip_filter: Arc<IPFilter>) -> Self {
+        self.ip_filter = Some(ip_filter);
+        self
+    }
+
+    pub fn logging(mut self, logging_middleware: Arc<LoggingMiddleware>) -> Self {
+        self.logging_middleware = Some(logging_middleware);
+        self
+    }
+
+    /// Проверка клиентов challenge_mode, общая с admin API
+    pub fn challenge(mut self, challenge: Arc<Challenge>) -> Self {
+        self.challenge = Some(challenge);
+        self
+    }
+
+    /// Учет in-flight запросов; по умолчанию с global.drain_timeout
+    pub fn drain_tracker(mut self, drain_tracker: Arc<DrainTracker>) -> Self {
+        self.drain_tracker = Some(drain_tracker);
+        self
+    }
+
+    /// Лимит запросов в keep-alive соединении; по умолчанию global.keepalive_requests
+    pub fn keepalive_tracker(mut self, keepalive_tracker: Arc<KeepaliveTracker>) -> Self {
+        self.keepalive_tracker = Some(keepalive_tracker);
+        self
+    }
+
+    /// Заглушки fallback_response вместо загружаемых из файлов конфигурации
+    pub fn fallbacks(mut self, fallbacks: Arc<FallbackResponses>) -> Self {
+        self.fallbacks = Some(fallbacks);
+        self
+    }
+
+    /// Расписания вместо создаваемых по секции schedules; фоновая задача пересчета
+    /// для них не запускается
+    pub fn schedules(mut self, schedules: Arc<Schedules>) -> Self {
+        self.schedules = Some(schedules);
+        self
+    }
+
+    /// Запись запросов с 5xx и ошибками соединения вместо создаваемой по flight_recorder.capacity
+    pub fn flight_recorder(mut self, flight_recorder: Arc<FlightRecorder>) -> Self {
+        self.flight_recorder = Some(flight_recorder);
+        self
+    }
+
+    pub fn build(self) -> Result<BuiltProxy, ProxyError> {
+        let config = self.config;
+        validate(&config)?;
+
+        // Заглушки fallback_response читаются при старте и при reload
+        let fallbacks = match &self.fallbacks {
+            Some(fallbacks) => fallbacks.clone(),
+            None => Arc::new(load_fallbacks(&config)?),
+        };
+        // Окна расписаний; набор действующих окон пересчитывается фоновой задачей
+        let schedules = match &self.schedules {
+            Some(schedules) => schedules.clone(),
+            None => Arc::new(load_schedules(&config)?),
+        };
+
+        let cache_manager = self.cache_manager.or_else(|| cache_manager(&config));
+        let logging_middleware = self
+            .logging_middleware
+            .unwrap_or_else(|| Arc::new(LoggingMiddleware::new(config.logging.clone())));
+
+        // Журнал событий up/down бэкендов (health checks и circuit breaker)
+        let (health_events, health_webhook) =
+            HealthEvents::from_config(&config.health_events, &config.http_client, logging_middleware.clone());
+
+        let circuit_breaker = self.circuit_breaker.or_else(|| {
+            if !config.circuit_breaker.enabled {
+                info!("Circuit breaker is disabled");
+                return None;
+            }
+            info!("Circuit breaker initialized with failure threshold: {}", config.circuit_breaker.failure_threshold);
+            Some(Arc::new(
+                CircuitBreaker::new(config.circuit_breaker.clone()).with_health_events(health_events.clone()),
+            ))
+        });
+
+        let ip_filter = self.ip_filter.clone().or_else(|| {
+            if !config.ip_filter.enabled {
+                info!("IP filtering is disabled");
+                return None;
+            }
+            let filter = load_ip_filter(&config.ip_filter);
+            info!("IP filter initialized");
+            Some(filter)
+        });
+
+        // Балансировщики upstream из nginx-style конфигурации; health checks выполняют фоновые сервисы
+        let health_checks = Arc::new(HealthChecks::default());
+        let mut background_services: Vec<Box<dyn Service>> = Vec::new();
+        let mut upstreams = HashMap::new();
+        let mut reload = ReloadState {
+            resolver: None,
+            upstreams: HashMap::new(),
+            injected_upstreams: self.upstreams.clone(),
+            cache_manager: cache_manager.clone(),
+            ip_filter: self.ip_filter,
+            fallbacks: self.fallbacks,
+            schedules: self.schedules,
+            schedules_refresh: None,
+        };
+        if let Some(nginx_config) = &config.nginx_config {
+            // Общий DNS резолвер для периодического переразрешения имен upstream серверов
+            let resolver = match &nginx_config.resolver {
+                Some(directive) => {
+                    let resolver =
+                        DnsResolver::new(Some(directive)).map_err(|e| format!("Failed to create DNS resolver: {}", e))?;
+                    info!("DNS resolver configured: {:?}", directive.nameservers);
+                    Some(Arc::new(resolver))
+                }
+                None => None,
+            };
+
+            for (upstream_name, upstream_block) in &nginx_config.upstreams {
+                if self.upstreams.contains_key(upstream_name) {
+                    continue;
+                }
+                let lb = Arc::new(load_balancer(
+                    upstream_name,
+                    upstream_block,
+                    resolver.as_ref(),
+                    &health_events,
+                    &health_checks,
+                    config.global.health_check_interval,
+                )?);
+                let (health_check, stop) = stoppable(lb.clone());
+                background_services
+                    .push(Box::new(background_service(&format!("{} health check", upstream_name), health_check)));
+                upstreams.insert(upstream_name.clone(), lb.clone());
+                reload.upstreams.insert(
+                    upstream_name.clone(),
+                    ConfigUpstream { lb, servers: upstream_servers(upstream_block), health_check: stop },
+                );
+            }
+            reload.resolver = nginx_config.resolver.clone().zip(resolver);
+        } else {
+            warn!("No nginx configuration found in sites-enabled/");
+            info!("Please create configuration files in sites-available/ and link them to sites-enabled/");
+        }
+        upstreams.extend(self.upstreams);
+
+        // Без upstream обслуживаются только локальные маршруты (статика, preflight)
+        if upstreams.is_empty() {
+            warn!("No upstreams configured, only local routes will be served");
+        }
+
+        // Ограничение количества запросов в keep-alive соединениях клиентов;
+        // in-flight запросы учитываются с global.drain_timeout
+        let keepalive_tracker = self
+            .keepalive_tracker
+            .unwrap_or_else(|| Arc::new(KeepaliveTracker::new(config.global.keepalive_requests)));
+        let drain_tracker = self
+            .drain_tracker
+            .unwrap_or_else(|| Arc::new(DrainTracker::new(Duration::from_secs(config.global.drain_timeout))));
+
+        // Проверка клиентов challenge_mode; режимы location переключаются через /_admin/challenge
+        let challenge = match self.challenge {
+            Some(challenge) => challenge,
+            None => {
+                let challenge =
+                    Challenge::from_config(&config).map_err(|e| format!("Invalid challenge configuration: {}", e))?;
+                if config.challenge.secret.is_none() && Challenge::is_configured(&config) {
+                    warn!("challenge.secret is not set, challenge cookies become invalid on restart");
+                }
+                Arc::new(challenge)
+            }
+        };
+        // Flight recorder: те же правила скрытия данных, что и в логах
+        let flight_recorder = self.flight_recorder.or_else(|| {
+            (config.flight_recorder.capacity > 0).then(|| {
+                Arc::new(FlightRecorder::new(config.flight_recorder.capacity, LogScrubber::new(&config.logging.scrub)))
+            })
+        });
+
+        let proxy = AdQuestProxy::from_components(ProxyComponents {
+            config: config.clone(),
+            upstreams: upstreams.clone(),
+            cache_manager,
+            circuit_breaker: circuit_breaker.clone(),
+            logging_middleware: logging_middleware.clone(),
+            ip_filter,
+            challenge: challenge.clone(),
+            drain_tracker: drain_tracker.clone(),
+            keepalive_tracker: keepalive_tracker.clone(),
+            fallbacks,
+            schedules: schedules.clone(),
+            flight_recorder: flight_recorder.clone(),
+        });
+
+        // Прогрев соединений с бэкендами после первого раунда health checks
+        let warmup_connections = config.global.upstream_warmup_connections;
+        if warmup_connections > 0 && !upstreams.is_empty() {
+            let targets = upstreams.iter().map(|(name, lb)| (name.clone(), lb.clone())).collect();
+            background_services.push(Box::new(background_service(
+                "upstream warmup",
+                WarmupService::new(targets, warmup_connections),
+            )));
+            info!("Upstream warmup enabled: {} connection(s) per backend", warmup_connections);
+        }
+        if reload.schedules.is_none() && !schedules.is_empty() {
+            let (refresh, stop) = stoppable(Arc::new(ScheduleService::new(schedules.clone())));
+            background_services.push(Box::new(background_service("schedules", refresh)));
+            reload.schedules_refresh = Some(stop);
+        }
+        // Отправка событий health_events в webhook
+        if let Some(webhook) = health_webhook {
+            background_services.push(Box::new(background_service("health event webhook", webhook)));
+        }
+        // Переоткрытие файлов логов по SIGUSR1 после ротации
+        background_services.push(Box::new(background_service(
+            "log reopen",
+            LogReopenService::new(logging_middleware.clone()),
+        )));
+
+        Ok(BuiltProxy {
+            proxy,
+            config,
+            upstreams,
+            background_services,
+            logging_middleware,
+            health_events,
+            health_checks,
+            drain_tracker,
+            keepalive_tracker,
+            circuit_breaker,
+            challenge,
+            flight_recorder,
+            schedules,
+            reload,
+        })
+    }
+}
+
+impl ProxyBuilder {
+    /// Server Pingora с прокси, метриками и фоновыми сервисами, как при запуске adq-pingora:
+    /// логирование, проверка listen адресов до bootstrap, шаблоны ошибок и метрики.
+    /// `opt` - аргументы Pingora (None - настройки по умолчанию)
+    pub fn into_server(self, opt: Option<Opt>) -> Result<ProxyServer, ProxyError> {
+        let started = Instant::now();
+        let config = self.config.clone();
+        let source = self.source.clone();
+        let smoke_mode = self.smoke_test;
+        let upgrade = opt.as_ref().is_some_and(|opt| opt.upgrade);
+        let mut server = Server::new(opt).map_err(|e| format!("Failed to create server: {}", e))?;
+
+        // Структурированное логирование
+        if let Err(e) = init_logging(&config.logging) {
+            eprintln!("Failed to initialize logging: {}, falling back to env_logger", e);
+            let _ = env_logger::try_init();
+        }
+        logging::scrub::install(LogScrubber::new(&config.logging.scrub));
+
+        // Адреса listen проверяются до bootstrap: занятый порт - понятная ошибка и отдельный код
+        // завершения, а не panic Pingora. При upgrade сокеты передает старый процесс
+        if !smoke_mode && !upgrade {
+            if let Ok(addrs) = config.listen_addrs() {
+                let conflicts = preflight::check_listeners(&addrs, &preflight::inherited_addrs());
+                for conflict in &conflicts {
+                    log::error!("{}", conflict);
+                }
+                if !conflicts.is_empty() {
+                    return Err(ProxyError::PortConflict(conflicts.len()));
+                }
+            }
+        }
+        server.bootstrap();
+
+        info!("Starting ADQ Pingora v1.0.0...");
+        log_config_notes(&config, source.as_ref().map_or("configuration", |(path, _)| path.as_str()));
+
+        let messages = ErrorMessages::from_config(&config.error_messages)
+            .map_err(|e| format!("Invalid error_messages configuration: {}", e))?;
+        if config.error_messages.templates_dir.is_some() {
+            info!("Error message templates loaded: {:?}", messages.languages());
+        }
+        error_messages::install(messages);
+        // Prometheus метрики (префикс задается до регистрации)
+        if let Some(namespace) = &config.logging.metrics.namespace {
+            metrics::set_namespace(namespace).map_err(|e| format!("Invalid metrics configuration: {}", e))?;
+        }
+        init_metrics();
+
+        let mut built = self.build()?;
+        // Health checks, прогрев, расписания, webhook health_events и переоткрытие логов
+        let mut services = std::mem::take(&mut built.background_services);
+        let health_events = built.health_events.clone();
+        let status = StatusSource::new(
+            HashMap::new(),
+            built.health_checks.clone(),
+            built.drain_tracker.clone(),
+            built.circuit_breaker.clone(),
+            started,
+        );
+        let challenge = built.challenge.clone();
+        let flight_recorder = built.flight_recorder.clone();
+        let keepalive_tracker = built.keepalive_tracker.clone();
+
+        // POST /_admin/reload: проверки как у -t, новая конфигурация применяется к работающему
+        // прокси. Без bearer_token endpoint не открывается
+        let metrics_enabled = config.logging.metrics.enabled && !smoke_mode;
+        let has_token = config.logging.metrics.bearer_token.as_deref().is_some_and(|token| !token.is_empty());
+        let reloadable = source.as_ref().filter(|_| metrics_enabled);
+        if reloadable.is_some() && !has_token {
+            warn!("POST /_admin/reload is disabled: logging.metrics.bearer_token is not set");
+        }
+        let (proxy, reloader) = match reloadable.filter(|_| has_token) {
+            Some((config_path, config_dirs)) => {
+                let (proxy, reloader) = built.into_reloadable(config_path, config_dirs);
+                (proxy, Some(reloader))
+            }
+            None => (ReloadableProxy::new(built.proxy), None),
+        };
+
+        // Адрес проверки --smoke-test вместо listen адресов
+        let smoke_addr = smoke_mode
+            .then(smoke_test::ephemeral_addr)
+            .transpose()
+            .map_err(|e| format!("Failed to find a free port for smoke test: {}", e))?;
+        services.push(proxy_service(&server, proxy.clone(), keepalive_tracker, &config, smoke_addr)?);
+        check_ssl_files(&config);
+        server.add_services(services);
+
+        // Prometheus метрики и /_admin (в --smoke-test порт метрик не занимается)
+        if metrics_enabled {
+            let metrics_config = &config.logging.metrics;
+            let listen_addr = metrics_config
+                .listen_addr()
+                .map_err(|e| format!("Invalid metrics configuration: {}", e))?;
+            if metrics_config.process_interval == 0 {
+                return Err("Invalid metrics configuration: process_interval must be greater than 0".into());
+            }
+            if !listen_addr.ip().is_loopback() && metrics_config.bearer_token.is_none() {
+                warn!("Metrics are exposed on {} without bearer_token", listen_addr);
+            }
+
+            let mut metrics_app = MetricsApp::new(metrics_config)
+                .with_health_events(health_events)
+                .with_status(Arc::new(status.with_proxy(proxy)))
+                .with_challenge(challenge);
+            if let Some(flight_recorder) = flight_recorder {
+                metrics_app = metrics_app.with_flight_recorder(flight_recorder);
+            }
+            if let Some(reloader) = reloader {
+                metrics_app = metrics_app.with_reloader(Arc::new(reloader));
+            }
+            let mut prometheus_service = ListeningService::new("Prometheus metrics".to_string(), metrics_app);
+            prometheus_service.add_tcp(&listen_addr.to_string());
+            server.add_service(prometheus_service);
+
+            // Память, дескрипторы и uptime процесса
+            server.add_service(background_service(
+                "process metrics",
+                ProcessMetricsService::new(
+                    started,
+                    Duration::from_secs(metrics_config.process_interval),
+                    server.configuration.threads,
+                ),
+            ));
+            info!("Prometheus metrics service started on {}", listen_addr);
+        }
+
+        info!("ADQ Pingora started successfully!");
+        log_servers(&config);
+
+        let smoke_test = smoke_addr.map(|addr| (addr, smoke_test::smoke_targets(&config)));
+        Ok(ProxyServer { server, smoke_test })
+    }
+}
+
+/// Server, собранный ProxyBuilder::into_server
+pub struct ProxyServer {
+    server: Server,
+    /// Адрес listener и маршруты проверки --smoke-test
+    smoke_test: Option<(SocketAddr, Vec<SmokeTarget>)>,
+}
+
+impl ProxyServer {
+    /// Запускает сервер. В режиме --smoke-test процесс завершается с результатом проверки
+    pub fn run_forever(self) {
+        if let Some((addr, targets)) = self.smoke_test {
+            std::thread::spawn(move || {
+                let passed = smoke_test::run_smoke_test(addr, &targets, Duration::from_secs(10));
+                std::process::exit(if passed { 0 } else { 1 });
+            });
+        }
+        self.server.run_forever();
+    }
+}
+
+/// Сервис прокси: PROXY protocol на портах `listen ... proxy_protocol`, лимит keep-alive
+/// запросов, h2c на портах `listen ... http2` и TCP listeners из конфигурации
+fn proxy_service(
+    server: &Server,
+    proxy: ReloadableProxy,
+    keepalive_tracker: Arc<KeepaliveTracker>,
+    config: &Config,
+    smoke_addr: Option<SocketAddr>,
+) -> Result<Box<dyn Service>, ProxyError> {
+    let listens = config
+        .nginx_config
+        .iter()
+        .flat_map(|nginx_config| &nginx_config.servers)
+        .flat_map(|server_config| &server_config.listen_ports);
+    let proxy_protocol_ports = proxy_protocol_ports(config);
+    if !proxy_protocol_ports.is_empty() {
+        info!("PROXY protocol enabled on ports: {:?}", proxy_protocol_ports);
+    }
+
+    // h2c с prior knowledge: Pingora определяет HTTP/2 по preface соединения,
+    // HTTP/1.1 клиенты на том же порту обслуживаются как раньше
+    let mut proxy_app = http_proxy(&server.configuration, proxy);
+    let h2c_ports = config.h2c_ports();
+    if !h2c_ports.is_empty() {
+        proxy_app.server_options = Some(HttpServerOptions { h2c: true, ..Default::default() });
+        info!("HTTP/2 cleartext (h2c) enabled, listen http2 ports: {:?}", h2c_ports);
+    }
+    let mut service = ListeningService::new(
+        "Pingora HTTP Proxy Service".to_string(),
+        ProxyProtocolApp::new(KeepaliveApp::new(proxy_app, keepalive_tracker), proxy_protocol_ports),
+    );
+
+    if let Some(addr) = smoke_addr {
+        service.add_tcp(&addr.to_string());
+        info!("Smoke test listener on {}", addr);
+        return Ok(Box::new(service));
+    }
+    let listen_addrs = config.listen_addrs().map_err(|e| format!("Invalid listen configuration: {}", e))?;
+    for listen in listen_addrs {
+        let mut options = TcpSocketOptions::default();
+        if listen.addr.is_ipv6() {
+            options.ipv6_only = Some(listen.ipv6_only);
+        }
+        service.add_tcp_with_settings(&listen.addr.to_string(), options);
+        if listen.addr.is_ipv6() && !listen.ipv6_only {
+            info!("Added TCP listener on {} (dual-stack)", listen.addr);
+        } else {
+            info!("Added TCP listener on {}", listen.addr);
+        }
+    }
+    if listens.count() == 0 {
+        info!("No listen directives found, using default ports {} and {}",
+              config.global.default_http_port, config.global.default_https_port);
+    }
+    Ok(Box::new(service))
+}
+
+/// Порты `listen ... proxy_protocol`
+fn proxy_protocol_ports(config: &Config) -> HashSet<u16> {
+    config
+        .nginx_config
+        .iter()
+        .flat_map(|nginx_config| &nginx_config.servers)
+        .flat_map(|server_config| &server_config.listen_ports)
+        .filter(|listen| listen.proxy_protocol)
+        .map(|listen| listen.port)
+        .collect()
+}
+
+/// Примененные фрагменты, миграции схемы, неизвестные поля и близость к config_limits
+fn log_config_notes(config: &Config, config_path: &str) {
+    for fragment in &config.schema.fragments {
+        info!("Configuration fragment {} applied", fragment);
+    }
+    for migration in &config.schema.migrations {
+        warn!("Configuration {} migrated to version {}: {}", config_path, CONFIG_VERSION, migration);
+    }
+    for field in &config.schema.unknown_fields {
+        warn!("Unknown configuration field '{}' ignored (set strict: true to reject)", field);
+    }
+    if let Some(Ok(warnings)) = config.nginx_config.as_ref().map(|nginx| config.config_limits.check(nginx)) {
+        for warning in warnings {
+            warn!("{}", warning);
+        }
+    }
+}
+
+/// Предупреждает о server блоках, файлы сертификатов которых не найдены
+fn check_ssl_files(config: &Config) {
+    let servers = config.nginx_config.iter().flat_map(|nginx_config| &nginx_config.servers);
+    for server in servers {
+        if let (Some(cert_path), Some(key_path)) = (&server.ssl_certificate, &server.ssl_certificate_key) {
+            if std::path::Path::new(cert_path).exists() && std::path::Path::new(key_path).exists() {
+                info!("Configuring SSL for server '{}' with cert: {}", server.server_names.join(", "), cert_path);
+            } else {
+                warn!("SSL certificates not found for server '{}': cert={}, key={}",
+                      server.server_names.join(", "), cert_path, key_path);
+            }
+        }
+    }
+}
+
+/// Сводка server блоков и location в логе запуска
+fn log_servers(config: &Config) {
+    let Some(nginx_config) = &config.nginx_config else {
+        info!("No server configurations loaded from sites-enabled/");
+        return;
+    };
+    info!("Configuration loaded: {} servers, {} upstreams", nginx_config.servers.len(), nginx_config.upstreams.len());
+    for server in &nginx_config.servers {
+        let ports: Vec<String> = server
+            .listen_ports
+            .iter()
+            .map(|p| format!("{}{}", p.port, if p.ssl { " (SSL)" } else { "" }))
+            .collect();
+        info!("Server '{}' listening on ports: {}", server.server_names.join(", "), ports.join(", "));
+        for location in &server.locations {
+            let rate_info = match &location.rate_limit {
+                Some(rate) => format!(" (Rate limit: {} req/s, burst: {})", rate.requests_per_second, rate.burst),
+                None => String::new(),
+            };
+            info!("  {} -> {}{}", location.path, location.proxy_pass.as_deref().unwrap_or("no upstream"), rate_info);
+        }
+    }
+}
+
+impl BuiltProxy {
+    /// Заполняет бэкенды балансировщиков без фоновых сервисов: для прокси, запущенного
+    /// вне Server (тесты, встраивание). Health checks при этом не выполняются
+    pub async fn update_upstreams(&self) -> Result<(), ProxyError> {
+        for (name, lb) in &self.upstreams {
+            lb.update().await.map_err(|e| ProxyError::InvalidUpstream {
+                name: name.clone(),
+                message: format!("failed to update backends: {}", e),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Прокси для `http_proxy` и ConfigReloader для POST /_admin/reload: конфигурация из
+    /// `config_path`, прошедшая проверки `-t`, применяется к прокси без перезапуска (ProxyReload).
+    /// Фоновые сервисы нужно забрать до вызова. `reload` выполняется в потоке tokio runtime
+    /// вне async контекста (spawn_blocking): новые балансировщики заполняются через block_on
+    pub fn into_reloadable(self, config_path: &str, config_dirs: &[PathBuf]) -> (ReloadableProxy, ConfigReloader) {
+        let proxy = ReloadableProxy::new(self.proxy);
+        let reload = ProxyReload {
+            proxy: proxy.clone(),
+            logging_middleware: self.logging_middleware,
+            health_events: self.health_events,
+            health_checks: self.health_checks,
+            drain_tracker: self.drain_tracker,
+            keepalive_tracker: self.keepalive_tracker,
+            circuit_breaker: self.circuit_breaker,
+            challenge: self.challenge,
+            flight_recorder: self.flight_recorder,
+            state: Mutex::new(self.reload),
+            shutdown: watch::channel(false).0,
+        };
+        let reloader = ConfigReloader::new(config_path, config_dirs, self.config)
+            .on_apply(move |old, config, report| reload.apply(old, config, report));
+        (proxy, reloader)
+    }
+}
+
+/// Состояние сборки, которое reload переносит в следующую
+struct ReloadState {
+    /// Директива resolver и созданный по ней резолвер
+    resolver: Option<(ResolverDirective, Arc<DnsResolver>)>,
+    /// Балансировщики upstream из конфигурации
+    upstreams: HashMap<String, ConfigUpstream>,
+    /// Компоненты, переданные в ProxyBuilder: reload их не заменяет
+    injected_upstreams: HashMap<String, Arc<LoadBalancer<RoundRobin>>>,
+    ip_filter: Option<Arc<IPFilter>>,
+    fallbacks: Option<Arc<FallbackResponses>>,
+    schedules: Option<Arc<Schedules>>,
+    cache_manager: Option<Arc<CacheManager>>,
+    /// Пересчет окон расписаний текущей сборки
+    schedules_refresh: Option<StopHandle>,
+}
+
+/// Балансировщик upstream из конфигурации и серверы (адрес, вес), из которых он создан
+struct ConfigUpstream {
+    lb: Arc<LoadBalancer<RoundRobin>>,
+    servers: Vec<(String, u32)>,
+    health_check: StopHandle,
+}
+
+/// Применение новой конфигурации к работающему прокси. Стадии, маршруты, лимиты, IP фильтр,
+/// заглушки и расписания собираются заново. Учет in-flight запросов и keep-alive, circuit
+/// breaker, кеш, challenge, flight recorder и логирование переходят в новую сборку как есть:
+/// изменения их секций вступают в силу после перезапуска. Балансировщик upstream с прежними
+/// серверами и resolver сохраняется вместе с состоянием health checks; измененный создается
+/// заново, а health checks прежнего работают, пока удаленные бэкенды дообслуживают запросы
+struct ProxyReload {
+    proxy: ReloadableProxy,
+    logging_middleware: Arc<LoggingMiddleware>,
+    health_events: Arc<HealthEvents>,
+    health_checks: Arc<HealthChecks>,
+    drain_tracker: Arc<DrainTracker>,
+    keepalive_tracker: Arc<KeepaliveTracker>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    challenge: Arc<Challenge>,
+    flight_recorder: Option<Arc<FlightRecorder>>,
+    state: Mutex<ReloadState>,
+    /// ShutdownWatch задач, запущенных reload; они завершаются вместе с runtime
+    shutdown: watch::Sender<bool>,
+}
+
+impl ProxyReload {
+    fn apply(&self, old: &Config, config: &Arc<Config>, report: &mut Report) -> Result<(), String> {
+        let runtime = tokio::runtime::Handle::try_current().map_err(|e| e.to_string())?;
+        validate(config).map_err(|e| e.to_string())?;
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        let directive = config.nginx_config.as_ref().and_then(|nginx_config| nginx_config.resolver.as_ref());
+        let resolver_changed = directive != state.resolver.as_ref().map(|(current, _)| current);
+        let resolver = match (directive, &state.resolver) {
+            (Some(_), Some((_, resolver))) if !resolver_changed => Some(resolver.clone()),
+            (Some(directive), _) => Some(Arc::new(
+                DnsResolver::new(Some(directive)).map_err(|e| format!("Failed to create DNS resolver: {}", e))?,
+            )),
+            (None, _) => None,
+        };
+
+        // Новые и измененные балансировщики заполняются до замены сборки: первые запросы
+        // к ним не получают 502 из-за пустого списка бэкендов
+        let mut upstreams = state.injected_upstreams.clone();
+        let mut created = Vec::new();
+        for (name, upstream) in config.nginx_config.iter().flat_map(|nginx_config| &nginx_config.upstreams) {
+            if upstreams.contains_key(name) {
+                continue;
+            }
+            let servers = upstream_servers(upstream);
+            let current = state.upstreams.get(name).filter(|current| current.servers == servers && !resolver_changed);
+            if let Some(current) = current {
+                upstreams.insert(name.clone(), current.lb.clone());
+                continue;
+            }
+            let lb = Arc::new(
+                load_balancer(
+                    name,
+                    upstream,
+                    resolver.as_ref(),
+                    &self.health_events,
+                    &self.health_checks,
+                    config.global.health_check_interval,
+                )
+                .map_err(|e| e.to_string())?,
+            );
+            runtime
+                .block_on(lb.update())
+                .map_err(|e| format!("upstream '{}': failed to update backends: {}", name, e))?;
+            upstreams.insert(name.clone(), lb.clone());
+            created.push((name.clone(), lb, servers));
+        }
+
+        let fallbacks = match &state.fallbacks {
+            Some(fallbacks) => fallbacks.clone(),
+            None => Arc::new(load_fallbacks(config).map_err(|e| e.to_string())?),
+        };
+        let schedules = match &state.schedules {
+            Some(schedules) => schedules.clone(),
+            None => Arc::new(load_schedules(config).map_err(|e| e.to_string())?),
+        };
+        let ip_filter = state.ip_filter.clone().or_else(|| {
+            // blocklist_file перечитывается
+            config.ip_filter.enabled.then(|| load_ip_filter(&config.ip_filter))
+        });
+        let proxy = AdQuestProxy::from_components(ProxyComponents {
+            config: config.clone(),
+            upstreams: upstreams.clone(),
+            cache_manager: state.cache_manager.clone(),
+            circuit_breaker: self.circuit_breaker.clone(),
+            logging_middleware: self.logging_middleware.clone(),
+            ip_filter,
+            challenge: self.challenge.clone(),
+            drain_tracker: self.drain_tracker.clone(),
+            keepalive_tracker: self.keepalive_tracker.clone(),
+            fallbacks,
+            schedules: schedules.clone(),
+            flight_recorder: self.flight_recorder.clone(),
+        });
+
+        // Дальше ошибок нет: фоновые задачи переключаются на новую сборку.
+        // Бэкенды, удаленные из upstream, дообслуживают in-flight запросы (global.drain_timeout)
+        if let (Some(old_nginx), Some(new_nginx)) = (&old.nginx_config, &config.nginx_config) {
+            self.drain_tracker.apply_config_reload(old_nginx, new_nginx);
+        }
+        let mut replaced = Vec::new();
+        for (name, lb, servers) in created {
+            let (health_check, stop) = stoppable(lb.clone());
+            let shutdown = self.shutdown.subscribe();
+            runtime.spawn(async move { health_check.start(shutdown).await });
+            if let Some(upstream) = state.upstreams.insert(name.clone(), ConfigUpstream { lb, servers, health_check: stop }) {
+                replaced.push((name, upstream));
+            }
+        }
+        let removed: Vec<String> = state.upstreams.keys().filter(|name| !upstreams.contains_key(*name)).cloned().collect();
+        for name in removed {
+            if let Some(upstream) = state.upstreams.remove(&name) {
+                replaced.push((name, upstream));
+            }
+        }
+        for (name, upstream) in replaced {
+            self.stop_after_drain(&runtime, name, upstream.health_check);
+        }
+        state.resolver = directive.cloned().zip(resolver);
+        if let Some(refresh) = state.schedules_refresh.take() {
+            refresh.stop();
+        }
+        if state.schedules.is_none() && !schedules.is_empty() {
+            let (refresh, stop) = stoppable(Arc::new(ScheduleService::new(schedules)));
+            let shutdown = self.shutdown.subscribe();
+            runtime.spawn(async move { refresh.start(shutdown).await });
+            state.schedules_refresh = Some(stop);
+        }
+
+        for section in restart_sections(old, config) {
+            report.warn(format!("{} changed, the change takes effect after restart", section));
+        }
+        self.proxy.replace(proxy);
+        Ok(())
+    }
+
+    /// Останавливает health checks замененного балансировщика, когда у upstream не останется
+    /// draining бэкендов или истечет drain_timeout: повторы запросов прежней сборки
+    /// выбирают бэкенд по актуальному состоянию
+    fn stop_after_drain(&self, runtime: &tokio::runtime::Handle, upstream: String, health_check: StopHandle) {
+        const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+        let drain_tracker = self.drain_tracker.clone();
+        runtime.spawn(async move {
+            let deadline = tokio::time::Instant::now() + drain_tracker.drain_timeout();
+            while drain_tracker.draining_count(&upstream) > 0 && tokio::time::Instant::now() < deadline {
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+            health_check.stop();
+        });
+    }
+}
+
+/// Изменения, которые reload не применяет: listeners и компоненты, переходящие в новую сборку
+fn restart_sections(old: &Config, new: &Config) -> Vec<&'static str> {
+    const SECTIONS: [&str; 11] = [
+        "logging",
+        "cache",
+        "circuit_breaker",
+        "challenge",
+        "flight_recorder",
+        "error_messages",
+        "health_events",
+        "http_client",
+        "global.keepalive_requests",
+        "global.drain_timeout",
+        "global.health_check_interval",
+    ];
+    let (old_json, new_json) = (serde_json::to_value(old).unwrap_or_default(), serde_json::to_value(new).unwrap_or_default());
+    let mut changed = Vec::new();
+    if old.listen_addrs() != new.listen_addrs()
+        || old.h2c_ports() != new.h2c_ports()
+        || proxy_protocol_ports(old) != proxy_protocol_ports(new)
+    {
+        changed.push("listen");
+    }
+    for section in SECTIONS {
+        let pointer = format!("/{}", section.replace('.', "/"));
+        if old_json.pointer(&pointer) != new_json.pointer(&pointer) {
+            changed.push(section);
+        }
+    }
+    changed
+}
+
+/// Фоновый сервис, который можно остановить раньше сервера: health checks балансировщика
+/// или пересчет расписаний сборки, замененной reload
+struct Stoppable<S> {
+    inner: Arc<S>,
+    stop: watch::Receiver<bool>,
+}
+
+/// Остановка Stoppable; без вызова `stop` сервис работает до завершения сервера
+struct StopHandle(watch::Sender<bool>);
+
+impl StopHandle {
+    fn stop(&self) {
+        let _ = self.0.send(true);
+    }
+}
+
+fn stoppable<S>(inner: Arc<S>) -> (Stoppable<S>, StopHandle) {
+    let (sender, stop) = watch::channel(false);
+    (Stoppable { inner, stop }, StopHandle(sender))
+}
+
+#[async_trait]
+impl<S: BackgroundService + Send + Sync> BackgroundService for Stoppable<S> {
+    async fn start(&self, shutdown: ShutdownWatch) {
+        let mut stop = self.stop.clone();
+        tokio::select! {
+            _ = self.inner.start(shutdown) => {}
+            // Закрытый канал (StopHandle удален без stop) сервис не останавливает
+            true = async { stop.wait_for(|stopped| *stopped).await.is_ok() } => {}
+        }
+    }
+}
+
+/// Балансировщик upstream с TCP health check, результаты которого записываются в
+/// `health_checks` и журнал событий. С resolver имена серверов периодически переразрешаются
+fn load_balancer(
+    name: &str,
+    upstream: &UpstreamBlock,
+    resolver: Option<&Arc<DnsResolver>>,
+    health_events: &Arc<HealthEvents>,
+    health_checks: &Arc<HealthChecks>,
+    health_check_interval: u64,
+) -> Result<LoadBalancer<RoundRobin>, ProxyError> {
+    info!("Creating load balancer for upstream: {}", name);
+    let mut lb = match resolver {
+        Some(resolver) => {
+            let discovery = DnsDiscovery::new(name, upstream.servers.clone(), resolver.clone());
+            let mut lb = LoadBalancer::from_backends(Backends::new(Box::new(discovery)));
+            lb.update_frequency = Some(resolver.refresh_interval());
+            lb
+        }
+        None => {
+            // Без resolver имена разрешаются один раз системным резолвером,
+            // бэкенды получают веса из `server ... weight=N`
+            let backends = static_backends(upstream).map_err(|e| ProxyError::InvalidUpstream {
+                name: name.to_string(),
+                message: format!("failed to create load balancer: {}", e),
+            })?;
+            LoadBalancer::from_backends(Backends::new(Static::new(backends)))
+        }
+    };
+
+    // Настраиваем health checks (по умолчанию TCP)
+    let mut hc = TcpHealthCheck::new();
+    hc.health_changed_callback = Some(Box::new(HealthObserver::new(name, health_events.clone())));
+    lb.set_health_check(Box::new(RecordingHealthCheck::new(name, hc, health_checks.clone())));
+    lb.health_check_frequency = Some(Duration::from_secs(health_check_interval));
+    info!("TCP health check configured for '{}'", name);
+    Ok(lb)
+}
+
+fn upstream_servers(upstream: &UpstreamBlock) -> Vec<(String, u32)> {
+    upstream.servers.iter().map(|server| (server.address.clone(), server.weight)).collect()
+}
+
+fn load_fallbacks(config: &Config) -> Result<FallbackResponses, ProxyError> {
+    FallbackResponses::load(config).map_err(|e| format!("Invalid fallback_response configuration: {}", e).into())
+}
+
+fn load_schedules(config: &Config) -> Result<Schedules, ProxyError> {
+    Schedules::from_config(&config.schedules)
+        .and_then(|schedules| schedules.check_references(config).map(|_| schedules))
+        .map_err(|e| format!("Invalid schedules configuration: {}", e).into())
+}
+
+/// Проверки секций конфигурации, без которых прокси не собирается
+fn validate(config: &Config) -> Result<(), ProxyError> {
+    let invalid = |section: &'static str| move |e: String| format!("Invalid {} configuration: {}", section, e);
+
+    config.validate_pipeline().map_err(invalid("pipeline"))?;
+    config.proxy_headers.validate().map_err(invalid("proxy_headers"))?;
+    config.response_headers.validate().map_err(invalid("response_headers"))?;
+    SchemeResolver::from_config(&config.proxy_headers).map_err(invalid("proxy_headers"))?;
+    if config.ua_filter.enabled {
+        UaFilter::from_config(&config.ua_filter).map_err(invalid("ua_filter"))?;
+    }
+    HeaderRules::from_config(&config.header_rules).map_err(invalid("header_rules"))?;
+    config.validate_backend_profiles().map_err(invalid("backend_profiles"))?;
+    config.idempotency.validate().map_err(invalid("idempotency"))?;
+    if let Some(keepalive) = &config.global.tcp_keepalive {
+        keepalive.validate().map_err(invalid("global.tcp_keepalive"))?;
+    }
+    config.wasm_filters.validate().map_err(invalid("wasm_filters"))?;
+    config.challenge.validate().map_err(invalid("challenge"))?;
+    config.health_events.validate().map_err(invalid("health_events"))?;
+    config.http_client.validate().map_err(invalid("http_client"))?;
+    BodyPipeline::from_config(&config.redaction).map_err(invalid("redaction"))?;
+    Compression::from_config(&config.compression).map_err(invalid("compression"))?;
+    Experiments::from_config(&config.experiments).map_err(invalid("experiments"))?;
+    config.config_limits.validate().map_err(invalid("config_limits"))?;
+    config.default_page.validate().map_err(invalid("default_page"))?;
+    config.ip_filter.validate().map_err(invalid("IP filter"))?;
+    config
+        .logging
+        .validate_rotation()
+        .and_then(|_| config.logging.validate_sampling())
+        .map_err(invalid("logging"))?;
+    if parse_size(&config.global.buffered_body_budget).is_none() {
+        return Err(format!("Invalid global.buffered_body_budget: {}", config.global.buffered_body_budget).into());
+    }
+    if parse_size(&config.global.max_header_size).is_none() {
+        return Err(format!("Invalid global.max_header_size: {}", config.global.max_header_size).into());
+    }
+    Ok(())
+}
+
+fn cache_manager(config: &Config) -> Option<Arc<CacheManager>> {
+    if !config.cache.enabled {
+        info!("Caching is disabled");
+        return None;
+    }
+    match CacheManager::new(config.cache.clone()) {
+        Ok(manager) => {
+            info!("Cache manager initialized with {} rules", config.cache.rules.len());
+            Some(Arc::new(manager))
+        }
+        Err(e) => {
+            log::error!("Failed to initialize cache manager: {}", e);
+            None
+        }
+    }
+}
+
+/// IP фильтр с allowlist и blocklist_file. Списки загружаются в отдельном потоке:
+/// build может вызываться внутри tokio runtime (тесты, встраивание)
+fn load_ip_filter(config: &IpFilterConfig) -> Arc<IPFilter> {
+    let filter = Arc::new(IPFilter::new());
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            runtime.block_on(async {
+                if let Some(allowlist) = &config.allowlist {
+                    for ip_str in allowlist {
+                        if let Ok(ip) = ip_str.parse() {
+                            filter.add_to_whitelist(ip).await;
+                        }
+                    }
+                }
+                if let Some(blocklist_file) = &config.blocklist_file {
+                    if let Err(e) = filter.load_blacklist_from_file(blocklist_file).await {
+                        warn!("Failed to load blocklist file '{}': {}", blocklist_file, e);
+                    }
+                }
+            });
+        });
+    });
+    filter
+}
+
+/// Бэкенды upstream без resolver: адреса разрешаются системным резолвером, веса из конфигурации
+fn static_backends(upstream: &UpstreamBlock) -> std::io::Result<BTreeSet<Backend>> {
+    use std::net::ToSocketAddrs;
+
+    let mut backends = BTreeSet::new();
+    for server in &upstream.servers {
+        for addr in server.address.to_socket_addrs()? {
+            let backend = Backend::new_with_weight(&addr.to_string(), server.weight as usize)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+            backends.insert(backend);
+        }
+    }
+    Ok(backends)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NginxConfig;
+    use crate::reload::ReloadOutcome;
+    use crate::testing::{MockUpstream, TestProxy};
+
+    fn sites_config(sites: &str) -> crate::error::Result<Config> {
+        let mut config = Config::default();
+        config.nginx_config = Some(NginxConfig::parse_config_content(sites)?);
+        Ok(config)
+    }
+
+    fn site(servers: &[SocketAddr], locations: &str) -> String {
+        let servers: String = servers.iter().map(|addr| format!("server {}; ", addr)).collect();
+        format!(
+            "upstream api {{ {}}} server {{ listen 80; server_name api.example.com; location / {{ proxy_pass api; }} {} }}",
+            servers, locations
+        )
+    }
+
+    /// Reload вне async контекста, как в MetricsApp
+    async fn reload(reloader: &Arc<ConfigReloader>) -> ReloadOutcome {
+        let reloader = reloader.clone();
+        tokio::task::spawn_blocking(move || reloader.reload()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reload_changes_proxy_behavior() {
+        let (first, second) = (MockUpstream::start().await.unwrap(), MockUpstream::start().await.unwrap());
+        let sites = Arc::new(Mutex::new(site(&[first.addr()], "")));
+        let built = ProxyBuilder::new(sites_config(&sites.lock().unwrap()).unwrap()).build().unwrap();
+        built.update_upstreams().await.unwrap();
+        let (proxy, reloader) = built.into_reloadable("proxy.yaml", &[]);
+        let loaded = sites.clone();
+        let reloader = Arc::new(reloader.with_loader(move |_, _| sites_config(&loaded.lock().unwrap())));
+        let test_proxy = TestProxy::serve_reloadable(proxy).await.unwrap();
+        let client = reqwest::Client::new();
+        let get = |path: &str| client.get(test_proxy.url(path)).header("Host", "api.example.com").send();
+
+        let response = get("/maintenance/").await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["x-mock-upstream"], first.addr().to_string());
+
+        // Новый сервер upstream и location с return: процесс продолжает работать
+        *sites.lock().unwrap() = site(&[second.addr()], "location /maintenance/ { return 503; }");
+        let outcome = reload(&reloader).await;
+        assert!(outcome.applied, "{}", outcome.to_json());
+        let response = get("/orders").await.unwrap();
+        assert_eq!(response.headers()["x-mock-upstream"], second.addr().to_string());
+        assert_eq!(get("/maintenance/").await.unwrap().status(), 503);
+        assert_eq!(first.requests(), 1);
+
+        // Конфигурация с ошибкой не применяется: маршруты прежние
+        *sites.lock().unwrap() =
+            "server { listen 80; server_name api.example.com; location / { proxy_pass missing_api; } }".to_string();
+        assert!(!reload(&reloader).await.applied);
+        let response = get("/orders").await.unwrap();
+        assert_eq!(response.headers()["x-mock-upstream"], second.addr().to_string());
+        assert_eq!(get("/maintenance/").await.unwrap().status(), 503);
+    }
+
+    #[tokio::test]
+    async fn test_reload_drains_removed_backend() {
+        let removed = MockUpstream::start_with_delay(Duration::from_millis(800)).await.unwrap();
+        let kept = MockUpstream::start().await.unwrap();
+        let sites = Arc::new(Mutex::new(site(&[removed.addr()], "")));
+        let drain_tracker = Arc::new(DrainTracker::new(Duration::from_secs(30)));
+        let built = ProxyBuilder::new(sites_config(&sites.lock().unwrap()).unwrap())
+            .drain_tracker(drain_tracker.clone())
+            .build()
+            .unwrap();
+        built.update_upstreams().await.unwrap();
+        let (proxy, reloader) = built.into_reloadable("proxy.yaml", &[]);
+        let loaded = sites.clone();
+        let reloader = Arc::new(reloader.with_loader(move |_, _| sites_config(&loaded.lock().unwrap())));
+        let test_proxy = TestProxy::serve_reloadable(proxy).await.unwrap();
+        let client = reqwest::Client::new();
+        let (removed_addr, kept_addr) = (removed.addr().to_string(), kept.addr().to_string());
+
+        // Медленный запрос к бэкенду, который reload удалит из upstream
+        let slow = tokio::spawn(client.get(test_proxy.url("/slow")).header("Host", "api.example.com").send());
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while drain_tracker.in_flight("api", &removed_addr) == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("slow request did not reach the upstream");
+
+        *sites.lock().unwrap() = site(&[kept.addr()], "");
+        assert!(reload(&reloader).await.applied);
+        assert!(drain_tracker.is_draining("api", &removed_addr));
+        assert!(!drain_tracker.is_draining("api", &kept_addr));
+        assert_eq!(drain_tracker.draining_count("api"), 1);
+
+        for _ in 0..4 {
+            let response = client.get(test_proxy.url("/")).header("Host", "api.example.com").send().await.unwrap();
+            assert_eq!(response.headers()["x-mock-upstream"], kept_addr);
+        }
+
+        // Запрос, начатый до reload, завершается на удаленном бэкенде, и draining заканчивается
+        let response = slow.await.unwrap().unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["x-mock-upstream"], removed_addr);
+        assert_eq!(response.text().await.unwrap(), "GET /slow");
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while drain_tracker.draining_count("api") > 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("removed backend is still draining");
+        assert_eq!(drain_tracker.in_flight("api", &removed_addr), 0);
+        assert_eq!(removed.requests(), 1);
+    }
+
+    #[test]
+    fn test_restart_sections() {
+        let old = Config::default();
+        let mut new = Config::default();
+        new.global.drain_timeout += 1;
+        new.cache.enabled = !old.cache.enabled;
+        new.ip_filter.enabled = !old.ip_filter.enabled;
+        assert_eq!(restart_sections(&old, &new), vec!["cache", "global.drain_timeout"]);
+
+        new = Config::default();
+        new.nginx_config = Some(NginxConfig::parse_config_content("server { listen 8081; server_name a.example.com; }").unwrap());
+        assert_eq!(restart_sections(&old, &new), vec!["listen"]);
+    }
+}
@@ -0,0 +1,173 @@
+use chrono::{DateTime, FixedOffset};
+use pingora::http::{RequestHeader, ResponseHeader};
+use pingora::prelude::*;
+
+use crate::local_response::http_date_now;
+
+/// Заголовки, которые 304 сохраняет из полного ответа (RFC 7232 4.1)
+const NOT_MODIFIED_HEADERS: [&str; 6] = ["cache-control", "content-location", "date", "etag", "expires", "vary"];
+
+/// ETag без признака слабости W/
+fn opaque_tag(etag: &str) -> &str {
+    let etag = etag.trim();
+    etag.strip_prefix("W/").unwrap_or(etag)
+}
+
+/// Слабое сравнение ETag (RFC 7232 2.3.2): W/"1" совпадает с "1" и с W/"1"
+pub fn weak_match(a: &str, b: &str) -> bool {
+    opaque_tag(a) == opaque_tag(b)
+}
+
+/// ETag из списка If-None-Match; запятая внутри кавычек не разделяет значения
+fn entity_tags(value: &str) -> Vec<&str> {
+    let mut tags = Vec::new();
+    let mut rest = value;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        if rest.is_empty() {
+            break;
+        }
+        let quote = if rest.starts_with("W/\"") { 2 } else if rest.starts_with('"') { 0 } else {
+            // `*` или значение без кавычек
+            let end = rest.find(',').unwrap_or(rest.len());
+            tags.push(rest[..end].trim());
+            rest = &rest[end..];
+            continue;
+        };
+        let Some(close) = rest[quote + 1..].find('"') else {
+            break;
+        };
+        let end = quote + close + 2;
+        tags.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    tags
+}
+
+fn http_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc2822(value.trim()).ok()
+}
+
+/// Валидаторы клиента совпадают с ответом 200 и клиенту достаточно 304 (RFC 7232 6).
+/// If-None-Match сравнивается слабо, If-Modified-Since учитывается только без If-None-Match
+pub fn not_modified(req: &RequestHeader, resp: &ResponseHeader) -> bool {
+    if (req.method != "GET" && req.method != "HEAD") || resp.status.as_u16() != 200 {
+        return false;
+    }
+
+    let if_none_match: Vec<&str> = req
+        .headers
+        .get_all("if-none-match")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    if !if_none_match.is_empty() {
+        let etag = resp.headers.get("etag").and_then(|v| v.to_str().ok());
+        return if_none_match
+            .into_iter()
+            .flat_map(entity_tags)
+            .any(|tag| tag == "*" || etag.is_some_and(|etag| weak_match(tag, etag)));
+    }
+
+    let since = req.headers.get("if-modified-since").and_then(|v| v.to_str().ok()).and_then(http_date);
+    let modified = resp.headers.get("last-modified").and_then(|v| v.to_str().ok()).and_then(http_date);
+    matches!((since, modified), (Some(since), Some(modified)) if modified <= since)
+}
+
+/// 304 без тела с валидаторами и заголовками кеширования полного ответа
+pub fn not_modified_response(resp: &ResponseHeader) -> Result<ResponseHeader> {
+    let mut not_modified = ResponseHeader::build(304, None)?;
+    for name in NOT_MODIFIED_HEADERS {
+        for value in resp.headers.get_all(name) {
+            not_modified.append_header(name, value.clone())?;
+        }
+    }
+    if let Some(last_modified) = resp.headers.get("last-modified") {
+        not_modified.insert_header("Last-Modified", last_modified.clone())?;
+    }
+    if !not_modified.headers.contains_key("date") {
+        not_modified.insert_header("Date", http_date_now())?;
+    }
+    Ok(not_modified)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &[(&str, &str)]) -> RequestHeader {
+        let mut req = RequestHeader::build("GET", b"/assets/app.js", None).unwrap();
+        for (name, value) in headers {
+            req.append_header(name.to_string(), *value).unwrap();
+        }
+        req
+    }
+
+    fn cached(etag: &str) -> ResponseHeader {
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("ETag", etag).unwrap();
+        resp.insert_header("Last-Modified", "Tue, 13 Oct 2026 08:00:00 GMT").unwrap();
+        resp.insert_header("Cache-Control", "public, max-age=600").unwrap();
+        resp.insert_header("Content-Length", "1024").unwrap();
+        resp.insert_header("Content-Type", "application/javascript").unwrap();
+        resp
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let resp = cached("\"5f1-400\"");
+        assert!(not_modified(&request(&[("If-None-Match", "\"5f1-400\"")]), &resp));
+        assert!(!not_modified(&request(&[("If-None-Match", "\"5f1-401\"")]), &resp));
+        // Список и несколько заголовков; запятая внутри кавычек - часть ETag
+        assert!(not_modified(&request(&[("If-None-Match", "\"a,b\", \"5f1-400\"")]), &resp));
+        assert!(not_modified(&request(&[("If-None-Match", "\"old\""), ("If-None-Match", "\"5f1-400\"")]), &resp));
+        assert!(!not_modified(&request(&[("If-None-Match", "\"a,b\"")]), &cached("\"a\"")));
+        assert!(not_modified(&request(&[("If-None-Match", "*")]), &resp));
+
+        // Только GET и HEAD, только полный ответ
+        let mut post = request(&[("If-None-Match", "\"5f1-400\"")]);
+        post.set_method(http::Method::POST);
+        assert!(!not_modified(&post, &resp));
+        let mut partial = resp.clone();
+        partial.set_status(206).unwrap();
+        assert!(!not_modified(&request(&[("If-None-Match", "\"5f1-400\"")]), &partial));
+    }
+
+    #[test]
+    fn test_weak_comparison() {
+        // Сжатый прокси ответ отдается со слабым ETag, запись кеша хранит сильный
+        assert!(not_modified(&request(&[("If-None-Match", "W/\"5f1-400\"")]), &cached("\"5f1-400\"")));
+        assert!(not_modified(&request(&[("If-None-Match", "\"5f1-400\"")]), &cached("W/\"5f1-400\"")));
+        assert!(not_modified(&request(&[("If-None-Match", "W/\"v1\"")]), &cached("W/\"v1\"")));
+        assert!(!not_modified(&request(&[("If-None-Match", "W/\"v1\"")]), &cached("W/\"v2\"")));
+        assert!(weak_match("W/\"1\"", "\"1\""));
+        assert!(!weak_match("\"1\"", "\"01\""));
+    }
+
+    #[test]
+    fn test_if_modified_since() {
+        let resp = cached("\"v1\"");
+        assert!(not_modified(&request(&[("If-Modified-Since", "Tue, 13 Oct 2026 08:00:00 GMT")]), &resp));
+        assert!(not_modified(&request(&[("If-Modified-Since", "Wed, 14 Oct 2026 00:00:00 GMT")]), &resp));
+        assert!(!not_modified(&request(&[("If-Modified-Since", "Mon, 12 Oct 2026 23:59:59 GMT")]), &resp));
+        assert!(!not_modified(&request(&[("If-Modified-Since", "yesterday")]), &resp));
+
+        // If-None-Match важнее If-Modified-Since
+        let both = request(&[("If-None-Match", "\"v0\""), ("If-Modified-Since", "Wed, 14 Oct 2026 00:00:00 GMT")]);
+        assert!(!not_modified(&both, &resp));
+    }
+
+    #[test]
+    fn test_not_modified_response_headers() {
+        let mut resp = cached("\"v1\"");
+        resp.insert_header("Vary", "Accept-Encoding").unwrap();
+        let not_modified = not_modified_response(&resp).unwrap();
+        assert_eq!(not_modified.status.as_u16(), 304);
+        assert_eq!(not_modified.headers.get("etag").unwrap(), "\"v1\"");
+        assert_eq!(not_modified.headers.get("cache-control").unwrap(), "public, max-age=600");
+        assert_eq!(not_modified.headers.get("vary").unwrap(), "Accept-Encoding");
+        assert!(not_modified.headers.get("date").is_some());
+        assert!(not_modified.headers.get("content-length").is_none());
+        assert!(not_modified.headers.get("content-type").is_none());
+    }
+}
@@ -1,16 +1,87 @@
-use pingora_cache::{CacheKey, RespCacheable, CacheMeta};
+use pingora_cache::{CacheKey, CacheMeta, CachePhase, MemCache, NoCacheReason, RespCacheable};
 use pingora_core::Result;
 use pingora_proxy::Session;
 use pingora::http::{RequestHeader, ResponseHeader};
+use once_cell::sync::Lazy;
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 use regex::Regex;
 use log::{info, debug};
-use crate::config::{CacheConfig, CacheRule};
+use crate::clock::{Clock, SystemClock};
+use crate::config::{CacheConfig, CacheRule, LocationBlock, MultiRangeMode};
+use crate::metrics::CACHE_REVALIDATIONS;
+
+pub mod conditional;
+pub mod range;
+
+/// Хранилище закешированных ответов
+static CACHE_STORAGE: Lazy<MemCache> = Lazy::new(MemCache::new);
+
+/// Условные заголовки запроса; при повторной проверке записи заменяются валидаторами из кеша
+const CONDITIONAL_HEADERS: [&str; 5] = ["if-none-match", "if-modified-since", "if-match", "if-unmodified-since", "if-range"];
+
+/// Исход повторной проверки устаревшей записи у upstream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Revalidation {
+    /// 304: запись продлевается на TTL и отдается из кеша
+    NotModified,
+    /// Полный ответ заменяет запись
+    Modified,
+    /// Ошибка соединения или 5xx
+    Error,
+}
+
+impl Revalidation {
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            304 => Revalidation::NotModified,
+            500.. => Revalidation::Error,
+            _ => Revalidation::Modified,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Revalidation::NotModified => "not_modified",
+            Revalidation::Modified => "modified",
+            Revalidation::Error => "error",
+        }
+    }
+
+    pub fn record(self) {
+        CACHE_REVALIDATIONS.with_label_values(&[self.as_str()]).inc();
+    }
+}
+
+/// Запрос к upstream проверяет истекшую запись кеша
+pub fn is_revalidating(session: &Session) -> bool {
+    session.cache.enabled() && matches!(session.cache.phase(), CachePhase::Expired)
+}
+
+/// Делает запрос к upstream условным по валидаторам закешированного ответа:
+/// If-None-Match из ETag, If-Modified-Since из Last-Modified. Условия клиента убираются,
+/// ответ предназначен для записи кеша. false - валидаторов нет, запрос безусловный
+pub fn add_revalidation_headers(req: &mut RequestHeader, cached: &ResponseHeader) -> Result<bool> {
+    for name in CONDITIONAL_HEADERS {
+        req.remove_header(name);
+    }
+    let mut conditional = false;
+    if let Some(etag) = cached.headers.get("etag") {
+        req.insert_header("If-None-Match", etag.clone())?;
+        conditional = true;
+    }
+    if let Some(last_modified) = cached.headers.get("last-modified") {
+        req.insert_header("If-Modified-Since", last_modified.clone())?;
+        conditional = true;
+    }
+    Ok(conditional)
+}
 
 /// Менеджер кеширования
 pub struct CacheManager {
     config: CacheConfig,
     path_regexes: Vec<(Regex, u64)>, // (regex, ttl)
+    clock: Arc<dyn Clock>,
 }
 
 impl CacheManager {
@@ -37,19 +108,47 @@ impl CacheManager {
         Ok(Self {
             config,
             path_regexes,
+            clock: Arc::new(SystemClock),
         })
     }
 
-    /// Создает ключ кеша для запроса
-    pub fn create_cache_key(&self, session: &Session) -> Option<CacheKey> {
-        if !self.config.enabled {
-            return None;
+    /// Источник времени для сроков свежести записей
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Включает кеш для запроса. Multi-range запросы в режиме passthrough и запросы
+    /// в location с `proxy_cache off` идут в upstream.
+    pub fn enable(&self, session: &mut Session, location: Option<&LocationBlock>) {
+        if location.is_some_and(|location| !location.proxy_cache) {
+            debug!("Cache bypassed by proxy_cache off for {}", session.req_header().uri.path());
+            return;
         }
+        if self.is_request_cacheable(session.req_header()) {
+            session.cache.enable(&*CACHE_STORAGE, None, None, None, None);
+        }
+    }
 
-        let req = session.req_header();
-        
-        // Кешируем только GET запросы
-        if req.method != "GET" {
+    /// Запросы GET и HEAD обслуживаются из кеша; HEAD использует запись GET
+    pub fn is_request_cacheable(&self, req: &RequestHeader) -> bool {
+        self.config.enabled
+            && (req.method == "GET" || req.method == "HEAD")
+            && !(self.config.multi_range == MultiRangeMode::Passthrough && range::is_multi_range(req))
+    }
+
+    /// Создает ключ кеша для запроса; `encoding` - кодировка, согласованная секцией compression
+    pub fn create_cache_key(&self, session: &Session, encoding: Option<&str>) -> Option<CacheKey> {
+        let cache_key = self.cache_key_string(session.req_header(), encoding)?;
+        debug!("Created cache key: {}", crate::logging::scrub::global().text(&cache_key));
+
+        Some(CacheKey::new("adquest", cache_key, ""))
+    }
+
+    /// Ключ кеша без метода: HEAD и GET одного URL используют общую запись.
+    /// С согласованной кодировкой вариант определяется ею, а не исходным Accept-Encoding
+    fn cache_key_string(&self, req: &RequestHeader, encoding: Option<&str>) -> Option<String> {
+        if !self.is_request_cacheable(req) {
             return None;
         }
 
@@ -70,37 +169,59 @@ impl CacheManager {
         }
 
         // Добавляем Accept-Encoding для правильного кеширования сжатых ответов
-        if let Some(encoding) = req.headers.get("accept-encoding") {
+        if let Some(encoding) = encoding {
+            key_parts.push(format!("ae:{}", encoding));
+        } else if let Some(encoding) = req.headers.get("accept-encoding") {
             if let Ok(encoding_str) = encoding.to_str() {
                 key_parts.push(format!("ae:{}", encoding_str));
             }
         }
 
-        let cache_key = key_parts.join("|");
-        debug!("Created cache key: {}", cache_key);
-        
-        Some(CacheKey::new("adquest", cache_key, ""))
+        Some(key_parts.join("|"))
     }
 
-    /// Определяет, можно ли кешировать ответ
+    /// Определяет, можно ли кешировать ответ. `no_store` - сработал `proxy_no_cache` location
     pub fn is_response_cacheable(&self, 
         session: &Session, 
-        resp: &ResponseHeader
-    ) -> Option<RespCacheable> {
+        resp: &ResponseHeader,
+        location: Option<&LocationBlock>,
+        no_store: bool,
+    ) -> RespCacheable {
+        match self.response_ttl(session.req_header(), resp, location, no_store) {
+            Some(ttl) => {
+                let now = SystemTime::from(self.clock.utc());
+                let fresh_until = now + Duration::from_secs(ttl);
+                RespCacheable::Cacheable(CacheMeta::new(fresh_until, now, 0, 0, resp.clone()))
+            }
+            None => RespCacheable::Uncacheable(NoCacheReason::OriginNotCache),
+        }
+    }
+
+    /// TTL ответа upstream или None, если ответ не кешируется
+    fn response_ttl(
+        &self,
+        req: &RequestHeader,
+        resp: &ResponseHeader,
+        location: Option<&LocationBlock>,
+        no_store: bool,
+    ) -> Option<u64> {
         if !self.config.enabled {
             return None;
         }
 
-        let req = session.req_header();
-        
-        // Кешируем только GET запросы
+        // Кешируем только полные ответы на GET: ответ на HEAD не содержит тела
         if req.method != "GET" {
             return None;
         }
 
-        // Не кешируем ошибки (кроме 404)
+        // Частичный ответ не является полным объектом
         let status = resp.status.as_u16();
-        if status >= 400 && status != 404 {
+        if status == 206 || resp.headers.contains_key("content-range") {
+            return None;
+        }
+
+        if no_store {
+            debug!("Response not cacheable due to proxy_no_cache for {}", req.uri.path());
             return None;
         }
 
@@ -114,14 +235,24 @@ impl CacheManager {
             }
         }
 
-        // Определяем TTL на основе правил
+        // proxy_cache_valid location заменяет глобальные правила: кешируются только указанные статусы
         let path = req.uri.path();
+        if let Some(location) = location.filter(|location| !location.proxy_cache_valid.is_empty()) {
+            let ttl = location.proxy_cache_valid.iter().find(|valid| valid.matches(status))?.ttl.as_secs();
+            info!("Caching response for path '{}' with location TTL {} seconds", path, ttl);
+            return Some(ttl);
+        }
+
+        // Не кешируем ошибки (кроме 404)
+        if status >= 400 && status != 404 {
+            return None;
+        }
+
+        // Определяем TTL на основе правил
         let ttl = self.get_ttl_for_path(path);
         
         info!("Caching response for path '{}' with TTL {} seconds", path, ttl);
-
-        // Временно возвращаем None пока не разберемся с API
-        None
+        Some(ttl)
     }
 
     /// Получает TTL для пути на основе правил
@@ -196,6 +327,7 @@ mod tests {
                 CacheRule { path: "*.css".to_string(), ttl: 86400 },
                 CacheRule { path: "*.js".to_string(), ttl: 86400 },
             ],
+            multi_range: MultiRangeMode::Full,
         };
 
         let cache_manager = CacheManager::new(config).unwrap();
@@ -205,4 +337,191 @@ mod tests {
         assert_eq!(cache_manager.get_ttl_for_path("/scripts/app.js"), 86400);
         assert_eq!(cache_manager.get_ttl_for_path("/api/users"), 300); // default
     }
-}
\ No newline at end of file
+
+    fn request(method: &str, path: &str, headers: &[(&str, &str)]) -> RequestHeader {
+        let mut req = RequestHeader::build(method, path.as_bytes(), None).unwrap();
+        for (name, value) in headers {
+            req.insert_header(name.to_string(), *value).unwrap();
+        }
+        req
+    }
+
+    fn manager(multi_range: MultiRangeMode) -> CacheManager {
+        CacheManager::new(CacheConfig {
+            enabled: true,
+            default_ttl: 300,
+            max_size: "1GB".to_string(),
+            rules: Vec::new(),
+            multi_range,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_head_served_from_get_entry() {
+        let cache = manager(MultiRangeMode::Full);
+        let get = request("GET", "/video.mp4", &[("Host", "cdn.example.com")]);
+        let head = request("HEAD", "/video.mp4", &[("Host", "cdn.example.com")]);
+        assert_eq!(cache.cache_key_string(&head, None), cache.cache_key_string(&get, None));
+        assert!(cache.cache_key_string(&request("POST", "/video.mp4", &[]), None).is_none());
+
+        // Ответ на HEAD без тела не сохраняется вместо полного объекта
+        let ok = ResponseHeader::build(200, None).unwrap();
+        assert_eq!(cache.response_ttl(&get, &ok, None, false), Some(300));
+        assert_eq!(cache.response_ttl(&head, &ok, None, false), None);
+    }
+
+    #[test]
+    fn test_partial_response_not_cached() {
+        let cache = manager(MultiRangeMode::Full);
+        let get = request("GET", "/video.mp4", &[("Range", "bytes=0-99")]);
+        let mut partial = ResponseHeader::build(206, None).unwrap();
+        partial.insert_header("Content-Range", "bytes 0-99/1000").unwrap();
+        assert_eq!(cache.response_ttl(&get, &partial, None, false), None);
+
+        // Multi-range: полный ответ из кеша или запрос в upstream
+        let multi = request("GET", "/video.mp4", &[("Range", "bytes=0-10,20-30")]);
+        assert!(cache.is_request_cacheable(&multi));
+        assert!(!manager(MultiRangeMode::Passthrough).is_request_cacheable(&multi));
+        assert!(manager(MultiRangeMode::Passthrough).is_request_cacheable(&get));
+    }
+
+    #[test]
+    fn test_encoding_variants_cached_separately() {
+        use crate::compression::negotiate;
+        use crate::config::CompressionAlgorithm::{Brotli, Gzip, Zstd};
+
+        let cache = manager(MultiRangeMode::Full);
+        let key = |accept_encoding: &str| {
+            let req = request("GET", "/api/report", &[("Host", "api.example.com"), ("Accept-Encoding", accept_encoding)]);
+            let negotiated = crate::compression::Negotiated {
+                algorithm: negotiate(Some(accept_encoding), &[Zstd, Brotli, Gzip]),
+            };
+            cache.cache_key_string(&req, Some(negotiated.variant())).unwrap()
+        };
+
+        // Разный порядок и q-values с одной согласованной кодировкой - одна запись
+        assert_eq!(key("zstd, br, gzip"), key("gzip, br;q=0.9, zstd"));
+        // zstd-вариант не отдается клиенту без zstd
+        assert_ne!(key("zstd, gzip"), key("gzip"));
+        assert_ne!(key("zstd"), key("identity"));
+        assert!(key("gzip").ends_with("ae:gzip"));
+        assert!(key("deflate").ends_with("ae:identity"));
+    }
+
+    /// Upstream с текущим ETag: 304 на совпавший If-None-Match, иначе полный ответ
+    fn mock_upstream(req: &RequestHeader, etag: &str) -> ResponseHeader {
+        let not_modified = req.headers.get("if-none-match").is_some_and(|value| value == etag);
+        let mut resp = ResponseHeader::build(if not_modified { 304 } else { 200 }, None).unwrap();
+        resp.insert_header("ETag", etag).unwrap();
+        if !not_modified {
+            resp.insert_header("Content-Length", "18").unwrap();
+        }
+        resp
+    }
+
+    #[test]
+    fn test_expired_entry_revalidated_with_validators() {
+        let cache = manager(MultiRangeMode::Full);
+        let mut cached = ResponseHeader::build(200, None).unwrap();
+        cached.insert_header("ETag", "\"v1\"").unwrap();
+        cached.insert_header("Last-Modified", "Tue, 13 Oct 2026 08:00:00 GMT").unwrap();
+
+        // Условия клиента заменяются валидаторами записи
+        let mut upstream_req = request("GET", "/api/catalog", &[("If-None-Match", "\"client\""), ("If-Range", "\"client\"")]);
+        assert!(add_revalidation_headers(&mut upstream_req, &cached).unwrap());
+        assert_eq!(upstream_req.headers.get("if-none-match").unwrap(), "\"v1\"");
+        assert_eq!(upstream_req.headers.get("if-modified-since").unwrap(), "Tue, 13 Oct 2026 08:00:00 GMT");
+        assert!(upstream_req.headers.get("if-range").is_none());
+
+        // Объект не изменился: 304, запись продлевается на TTL
+        let before = CACHE_REVALIDATIONS.with_label_values(&["not_modified"]).get();
+        let resp = mock_upstream(&upstream_req, "\"v1\"");
+        let outcome = Revalidation::from_status(resp.status.as_u16());
+        assert_eq!(outcome, Revalidation::NotModified);
+        outcome.record();
+        assert_eq!(CACHE_REVALIDATIONS.with_label_values(&["not_modified"]).get(), before + 1);
+        assert_eq!(cache.response_ttl(&upstream_req, &cached, None, false), Some(300));
+
+        // Объект изменился: полный ответ заменяет запись
+        let resp = mock_upstream(&upstream_req, "\"v2\"");
+        assert_eq!(Revalidation::from_status(resp.status.as_u16()), Revalidation::Modified);
+        assert_eq!(cache.response_ttl(&upstream_req, &resp, None, false), Some(300));
+        assert_eq!(Revalidation::from_status(502), Revalidation::Error);
+
+        // Без валидаторов запрос безусловный
+        let mut plain_req = request("GET", "/api/catalog", &[("If-Modified-Since", "Mon, 12 Oct 2026 08:00:00 GMT")]);
+        assert!(!add_revalidation_headers(&mut plain_req, &ResponseHeader::build(200, None).unwrap()).unwrap());
+        assert!(plain_req.headers.get("if-modified-since").is_none());
+        assert_eq!(mock_upstream(&plain_req, "\"v1\"").status.as_u16(), 200);
+    }
+
+    fn location(directives: &str) -> LocationBlock {
+        let nginx = crate::config::NginxConfig::parse_config_content(&format!(
+            "server {{ listen 80; server_name cdn.example.com; location /assets/ {{ proxy_pass cdn; {} }} }}",
+            directives
+        ))
+        .unwrap();
+        nginx.servers[0].locations[0].clone()
+    }
+
+    #[test]
+    fn test_location_cache_valid_overrides_rules() {
+        let cache = CacheManager::new(CacheConfig {
+            enabled: true,
+            default_ttl: 300,
+            max_size: "1GB".to_string(),
+            rules: vec![CacheRule { path: "/assets/*".to_string(), ttl: 86400 }],
+            multi_range: MultiRangeMode::Full,
+        })
+        .unwrap();
+        let get = request("GET", "/assets/app.js", &[]);
+        let ok = ResponseHeader::build(200, None).unwrap();
+        let not_found = ResponseHeader::build(404, None).unwrap();
+        let unavailable = ResponseHeader::build(503, None).unwrap();
+        assert_eq!(cache.response_ttl(&get, &ok, None, false), Some(86400));
+
+        let assets = location("proxy_cache_valid 200 10m; proxy_cache_valid 503 5s;");
+        assert_eq!(cache.response_ttl(&get, &ok, Some(&assets), false), Some(600));
+        // Статусы вне proxy_cache_valid location не кешируются, указанные ошибки - кешируются
+        assert_eq!(cache.response_ttl(&get, &not_found, Some(&assets), false), None);
+        assert_eq!(cache.response_ttl(&get, &unavailable, Some(&assets), false), Some(5));
+        // Location без proxy_cache_valid использует глобальные правила
+        assert_eq!(cache.response_ttl(&get, &ok, Some(&location("")), false), Some(86400));
+
+        // proxy_no_cache и Cache-Control сильнее proxy_cache_valid
+        assert_eq!(cache.response_ttl(&get, &ok, Some(&assets), true), None);
+        let mut private = ResponseHeader::build(200, None).unwrap();
+        private.insert_header("Cache-Control", "private").unwrap();
+        assert_eq!(cache.response_ttl(&get, &private, Some(&assets), false), None);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_cache_off_bypasses_cache() {
+        let cache = manager(MultiRangeMode::Full);
+        let raw = "GET /assets/app.js HTTP/1.1\r\nHost: cdn.example.com\r\n\r\n";
+
+        let mut session = crate::stages::test_session(raw).await;
+        cache.enable(&mut session, Some(&location("proxy_cache_valid 10m;")));
+        assert!(session.cache.enabled());
+
+        let mut session = crate::stages::test_session(raw).await;
+        cache.enable(&mut session, Some(&location("proxy_cache off;")));
+        assert!(!session.cache.enabled());
+    }
+
+    #[tokio::test]
+    async fn test_fresh_until_follows_clock() {
+        use crate::clock::MockClock;
+
+        let start = chrono::DateTime::parse_from_rfc3339("2026-10-18T02:00:00Z").unwrap().with_timezone(&chrono::Utc);
+        let cache = manager(MultiRangeMode::Full).with_clock(Arc::new(MockClock::at(start)));
+        let session = crate::stages::test_session("GET /api/users HTTP/1.1\r\nHost: api.example.com\r\n\r\n").await;
+        let meta = match cache.is_response_cacheable(&session, &ResponseHeader::build(200, None).unwrap(), None, false) {
+            RespCacheable::Cacheable(meta) => meta,
+            RespCacheable::Uncacheable(reason) => panic!("uncacheable: {:?}", reason),
+        };
+        assert_eq!(meta.created(), SystemTime::from(start));
+        assert_eq!(meta.fresh_until(), SystemTime::from(start) + Duration::from_secs(300));
+    }
+}
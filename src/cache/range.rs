@@ -0,0 +1,204 @@
+use pingora::http::{RequestHeader, ResponseHeader};
+use pingora_proxy::RangeType;
+
+/// Ответ на Range запрос к полному телу объекта
+#[derive(Debug, Clone, PartialEq)]
+pub enum RangePlan {
+    /// Полный ответ 200 (Range отсутствует, некорректен, multi-range или не прошел If-Range)
+    Full,
+    /// 206 Partial Content, end включительно
+    Partial { start: u64, end: u64, len: u64 },
+    /// 416 Range Not Satisfiable
+    Unsatisfiable { len: u64 },
+}
+
+/// Запрос нескольких диапазонов (bytes=0-10,20-30)
+pub fn is_multi_range(req: &RequestHeader) -> bool {
+    req.headers
+        .get("range")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().strip_prefix("bytes="))
+        .is_some_and(|ranges| ranges.contains(','))
+}
+
+/// Разбирает одиночный диапазон `bytes=a-b`, `bytes=a-` или `bytes=-n` для тела длиной len.
+/// None - заголовок игнорируется (синтаксическая ошибка или несколько диапазонов).
+pub fn parse_byte_range(value: &str, len: u64) -> Option<RangePlan> {
+    let spec = value.trim().strip_prefix("bytes=")?.trim();
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    if start.is_empty() {
+        // Последние n байт
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(RangePlan::Unsatisfiable { len });
+        }
+        return Some(RangePlan::Partial { start: len.saturating_sub(suffix), end: len - 1, len });
+    }
+
+    let start: u64 = start.parse().ok()?;
+    let end = match end {
+        "" => None,
+        end => Some(end.parse::<u64>().ok()?),
+    };
+    if end.is_some_and(|end| end < start) {
+        return None;
+    }
+    if start >= len {
+        return Some(RangePlan::Unsatisfiable { len });
+    }
+    let end = end.map_or(len - 1, |end| end.min(len - 1));
+    Some(RangePlan::Partial { start, end, len })
+}
+
+/// If-Range совпадает с объектом: сильный ETag или точная дата Last-Modified
+pub fn if_range_matches(if_range: &str, resp: &ResponseHeader) -> bool {
+    let if_range = if_range.trim();
+    if if_range.starts_with("W/") {
+        return false;
+    }
+    let header = if if_range.starts_with('"') { "etag" } else { "last-modified" };
+    resp.headers
+        .get(header)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|value| !value.starts_with("W/") && value.trim() == if_range)
+}
+
+/// Как ответить на запрос из полного тела ответа 200 с известной длиной
+pub fn plan_range(req: &RequestHeader, resp: &ResponseHeader) -> RangePlan {
+    // Range применяется только к GET, HEAD отдает заголовки полного объекта
+    if req.method != "GET" || resp.status.as_u16() != 200 {
+        return RangePlan::Full;
+    }
+    let Some(len) = resp
+        .headers
+        .get("content-length")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+    else {
+        return RangePlan::Full;
+    };
+    let Some(range) = req.headers.get("range").and_then(|v| v.to_str().ok()) else {
+        return RangePlan::Full;
+    };
+    if let Some(if_range) = req.headers.get("if-range") {
+        if !if_range.to_str().is_ok_and(|v| if_range_matches(v, resp)) {
+            return RangePlan::Full;
+        }
+    }
+    parse_byte_range(range, len).unwrap_or(RangePlan::Full)
+}
+
+/// Применяет Range запроса к ответу из кеша: 206 с Content-Range или 416.
+/// Тело обрезается Pingora по возвращенному диапазону.
+pub fn range_header_filter(req: &RequestHeader, resp: &mut ResponseHeader) -> RangeType {
+    match plan_range(req, resp) {
+        RangePlan::Full => {
+            if resp.status.as_u16() == 200 && resp.headers.contains_key("content-length") {
+                let _ = resp.insert_header("Accept-Ranges", "bytes");
+            }
+            RangeType::None
+        }
+        RangePlan::Partial { start, end, len } => {
+            let _ = resp.set_status(206);
+            let _ = resp.insert_header("Content-Range", format!("bytes {}-{}/{}", start, end, len));
+            let _ = resp.insert_header("Content-Length", (end - start + 1).to_string());
+            let _ = resp.insert_header("Accept-Ranges", "bytes");
+            RangeType::Single(start as usize..end as usize + 1)
+        }
+        RangePlan::Unsatisfiable { len } => {
+            let _ = resp.set_status(416);
+            let _ = resp.insert_header("Content-Range", format!("bytes */{}", len));
+            let _ = resp.insert_header("Content-Length", "0");
+            RangeType::Invalid
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, headers: &[(&str, &str)]) -> RequestHeader {
+        let mut req = RequestHeader::build(method, b"/video.mp4", None).unwrap();
+        for (name, value) in headers {
+            req.insert_header(name.to_string(), *value).unwrap();
+        }
+        req
+    }
+
+    /// Закешированный полный ответ длиной 1000 байт
+    fn cached() -> ResponseHeader {
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("Content-Length", "1000").unwrap();
+        resp.insert_header("ETag", "\"v1\"").unwrap();
+        resp.insert_header("Last-Modified", "Wed, 21 Oct 2026 07:28:00 GMT").unwrap();
+        resp
+    }
+
+    #[test]
+    fn test_single_range_within_bounds() {
+        let mut resp = cached();
+        let range = range_header_filter(&request("GET", &[("Range", "bytes=0-99")]), &mut resp);
+        assert!(matches!(range, RangeType::Single(ref r) if *r == (0..100)));
+        assert_eq!(resp.status.as_u16(), 206);
+        assert_eq!(resp.headers.get("content-range").unwrap(), "bytes 0-99/1000");
+        assert_eq!(resp.headers.get("content-length").unwrap(), "100");
+
+        assert_eq!(parse_byte_range("bytes=900-", 1000), Some(RangePlan::Partial { start: 900, end: 999, len: 1000 }));
+        assert_eq!(parse_byte_range("bytes=-100", 1000), Some(RangePlan::Partial { start: 900, end: 999, len: 1000 }));
+        assert_eq!(parse_byte_range("bytes=-5000", 1000), Some(RangePlan::Partial { start: 0, end: 999, len: 1000 }));
+        // Конец за пределами тела ограничивается его длиной
+        assert_eq!(parse_byte_range("bytes=900-5000", 1000), Some(RangePlan::Partial { start: 900, end: 999, len: 1000 }));
+    }
+
+    #[test]
+    fn test_single_range_beyond_bounds() {
+        let mut resp = cached();
+        let range = range_header_filter(&request("GET", &[("Range", "bytes=1000-")]), &mut resp);
+        assert!(matches!(range, RangeType::Invalid));
+        assert_eq!(resp.status.as_u16(), 416);
+        assert_eq!(resp.headers.get("content-range").unwrap(), "bytes */1000");
+        assert_eq!(parse_byte_range("bytes=-0", 1000), Some(RangePlan::Unsatisfiable { len: 1000 }));
+
+        // Некорректный заголовок и multi-range дают полный ответ
+        for value in ["bytes=50-10", "items=0-10", "bytes=abc", "bytes=0-10,20-30"] {
+            let mut resp = cached();
+            assert!(matches!(range_header_filter(&request("GET", &[("Range", value)]), &mut resp), RangeType::None));
+            assert_eq!(resp.status.as_u16(), 200);
+        }
+        assert!(is_multi_range(&request("GET", &[("Range", "bytes=0-10,20-30")])));
+        assert!(!is_multi_range(&request("GET", &[("Range", "bytes=0-10")])));
+    }
+
+    #[test]
+    fn test_if_range() {
+        let partial = |if_range: &str| {
+            plan_range(&request("GET", &[("Range", "bytes=10-19"), ("If-Range", if_range)]), &cached())
+        };
+        assert_eq!(partial("\"v1\""), RangePlan::Partial { start: 10, end: 19, len: 1000 });
+        assert_eq!(partial("Wed, 21 Oct 2026 07:28:00 GMT"), RangePlan::Partial { start: 10, end: 19, len: 1000 });
+        // Объект изменился: клиент получает полный ответ
+        assert_eq!(partial("\"v0\""), RangePlan::Full);
+        assert_eq!(partial("W/\"v1\""), RangePlan::Full);
+        assert_eq!(partial("Tue, 20 Oct 2026 07:28:00 GMT"), RangePlan::Full);
+    }
+
+    #[test]
+    fn test_head_and_upstream_partial_not_ranged() {
+        // HEAD получает заголовки полного объекта
+        let mut resp = cached();
+        assert!(matches!(range_header_filter(&request("HEAD", &[("Range", "bytes=0-99")]), &mut resp), RangeType::None));
+        assert_eq!(resp.status.as_u16(), 200);
+        assert_eq!(resp.headers.get("accept-ranges").unwrap(), "bytes");
+
+        // 206 от upstream (объект не в кеше) передается клиенту как есть
+        let mut upstream = ResponseHeader::build(206, None).unwrap();
+        upstream.insert_header("Content-Range", "bytes 0-99/1000").unwrap();
+        assert_eq!(plan_range(&request("GET", &[("Range", "bytes=0-99")]), &upstream), RangePlan::Full);
+    }
+}
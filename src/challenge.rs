@@ -0,0 +1,377 @@
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+use crate::clock::{Clock, SystemClock};
+use crate::config::{ChallengeConfig, Config, LocationBlock};
+use crate::scheme::Cidr;
+
+/// Результат проверки cookie клиента
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Valid,
+    /// Cookie нет: клиент еще не проходил проверку
+    Missing,
+    Expired,
+    /// Неверный формат, подпись (в том числе для другого префикса адреса) или решение
+    Invalid,
+}
+
+/// Proof-of-work проверка клиентов для location с `challenge_mode on`. Клиент без cookie
+/// получает страницу со скриптом, который подбирает nonce к подписанному токену
+/// `<expiry>.<hmac>` и сохраняет cookie `<expiry>.<hmac>.<nonce>`. Подпись HMAC-SHA256
+/// покрывает срок действия и префикс адреса клиента, поэтому cookie не переносится в другую сеть
+pub struct Challenge {
+    secret: PKey<openssl::pkey::Private>,
+    ttl: u64,
+    ipv4_prefix: u8,
+    ipv6_prefix: u8,
+    difficulty: u32,
+    cookie_name: String,
+    allow_ips: Vec<Cidr>,
+    allow_user_agents: Vec<String>,
+    /// Пути location с директивой challenge_mode из конфигурации
+    locations: BTreeMap<String, bool>,
+    /// Режимы location, измененные через admin API (поверх challenge_mode)
+    overrides: RwLock<HashMap<String, bool>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Challenge {
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let challenge = &config.challenge;
+        challenge.validate()?;
+        let secret = match &challenge.secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            // Cookie, выданные до перезапуска, перестают действовать
+            None => rand::random::<[u8; 32]>().to_vec(),
+        };
+        let mut locations = BTreeMap::new();
+        for location in config.nginx_config.iter().flat_map(|nginx| &nginx.servers).flat_map(|s| &s.locations) {
+            *locations.entry(location.path.clone()).or_insert(false) |= location.challenge_mode;
+        }
+        Ok(Self {
+            secret: PKey::hmac(&secret).map_err(|e| format!("challenge.secret: {}", e))?,
+            ttl: challenge.ttl,
+            ipv4_prefix: challenge.ipv4_prefix,
+            ipv6_prefix: challenge.ipv6_prefix,
+            difficulty: challenge.difficulty,
+            cookie_name: challenge.cookie_name.clone(),
+            allow_ips: challenge.allow_ips.iter().filter_map(|cidr| Cidr::parse(cidr).ok()).collect(),
+            allow_user_agents: challenge.allow_user_agents.iter().map(|s| s.to_lowercase()).collect(),
+            locations,
+            overrides: RwLock::new(HashMap::new()),
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    /// Источник времени для срока действия cookie
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Нужна ли проверка хотя бы в одной location конфигурации
+    pub fn is_configured(config: &Config) -> bool {
+        config.nginx_config.as_ref().is_some_and(|nginx| {
+            nginx.servers.iter().flat_map(|s| &s.locations).any(|l| l.challenge_mode)
+        })
+    }
+
+    pub fn cookie_name(&self) -> &str {
+        &self.cookie_name
+    }
+
+    /// Включена ли проверка для location с учетом переключения через admin API
+    pub fn is_enabled(&self, location: &LocationBlock) -> bool {
+        let overrides = self.overrides.read().unwrap_or_else(|e| e.into_inner());
+        overrides.get(&location.path).copied().unwrap_or(location.challenge_mode)
+    }
+
+    /// Меняет режим location; None возвращает значение из конфигурации.
+    /// Неизвестный путь location - ошибка
+    pub fn set_mode(&self, path: &str, enabled: Option<bool>) -> Result<(), String> {
+        if !self.locations.contains_key(path) {
+            return Err(format!("unknown location '{}'", path));
+        }
+        let mut overrides = self.overrides.write().unwrap_or_else(|e| e.into_inner());
+        match enabled {
+            Some(enabled) => overrides.insert(path.to_string(), enabled),
+            None => overrides.remove(path),
+        };
+        Ok(())
+    }
+
+    /// Режимы всех location для `GET /_admin/challenge`
+    pub fn to_json(&self) -> String {
+        let overrides = self.overrides.read().unwrap_or_else(|e| e.into_inner());
+        let locations: Vec<serde_json::Value> = self
+            .locations
+            .iter()
+            .map(|(path, configured)| {
+                serde_json::json!({
+                    "location": path,
+                    "configured": configured,
+                    "override": overrides.get(path),
+                    "enabled": overrides.get(path).copied().unwrap_or(*configured),
+                })
+            })
+            .collect();
+        serde_json::json!({ "locations": locations }).to_string()
+    }
+
+    /// Клиенты из allow_ips и с User-Agent из allow_user_agents проходят без проверки
+    pub fn is_exempt(&self, ip: Option<IpAddr>, user_agent: Option<&str>) -> bool {
+        if ip.is_some_and(|ip| self.allow_ips.iter().any(|cidr| cidr.contains(ip))) {
+            return true;
+        }
+        let user_agent = user_agent.unwrap_or_default().to_lowercase();
+        !user_agent.is_empty() && self.allow_user_agents.iter().any(|allowed| user_agent.contains(allowed.as_str()))
+    }
+
+    /// Подписанный токен `<expiry>.<hmac>` для клиента с адресом `ip`
+    pub fn issue(&self, ip: Option<IpAddr>) -> String {
+        let expiry = self.unix_now() + self.ttl;
+        format!("{}.{}", expiry, self.sign(expiry, ip))
+    }
+
+    /// Проверяет значение cookie `<expiry>.<hmac>.<nonce>` для клиента с адресом `ip`
+    pub fn verify(&self, cookie: Option<&str>, ip: Option<IpAddr>) -> Verdict {
+        let Some(cookie) = cookie.filter(|cookie| !cookie.is_empty()) else {
+            return Verdict::Missing;
+        };
+        let mut parts = cookie.splitn(3, '.');
+        let (Some(expiry), Some(signature), Some(nonce)) = (parts.next(), parts.next(), parts.next()) else {
+            return Verdict::Invalid;
+        };
+        let Ok(expiry_secs) = expiry.parse::<u64>() else {
+            return Verdict::Invalid;
+        };
+        let expected = self.sign(expiry_secs, ip);
+        if signature.len() != expected.len() || !openssl::memcmp::eq(signature.as_bytes(), expected.as_bytes()) {
+            return Verdict::Invalid;
+        }
+        if !solves(&format!("{}.{}", expiry, signature), nonce, self.difficulty) {
+            return Verdict::Invalid;
+        }
+        if expiry_secs <= self.unix_now() {
+            return Verdict::Expired;
+        }
+        Verdict::Valid
+    }
+
+    /// Страница 503 со скриптом, который решает задачу и повторяет запрос
+    pub fn page(&self, token: &str) -> String {
+        CHALLENGE_PAGE
+            .replace("{token}", token)
+            .replace("{difficulty}", &self.difficulty.to_string())
+            .replace("{cookie}", &self.cookie_name)
+            .replace("{ttl}", &self.ttl.to_string())
+    }
+
+    /// Cookie с решением задачи, как ее находит скрипт страницы
+    #[cfg(test)]
+    pub(crate) fn solve(&self, token: &str) -> String {
+        let nonce = (0u32..).find(|nonce| solves(token, &nonce.to_string(), self.difficulty)).unwrap();
+        format!("{}.{}", token, nonce)
+    }
+
+    fn unix_now(&self) -> u64 {
+        self.clock.utc().timestamp().max(0) as u64
+    }
+
+    /// Префикс адреса клиента, к которому привязывается cookie
+    fn network(&self, ip: Option<IpAddr>) -> String {
+        match ip.map(IpAddr::to_canonical) {
+            Some(IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.ipv4_prefix as u32).unwrap_or(0);
+                format!("{}/{}", std::net::Ipv4Addr::from(u32::from(ip) & mask), self.ipv4_prefix)
+            }
+            Some(IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.ipv6_prefix as u32).unwrap_or(0);
+                format!("{}/{}", std::net::Ipv6Addr::from(u128::from(ip) & mask), self.ipv6_prefix)
+            }
+            None => "unknown".to_string(),
+        }
+    }
+
+    fn sign(&self, expiry: u64, ip: Option<IpAddr>) -> String {
+        let data = format!("{}|{}", expiry, self.network(ip));
+        let signature = Signer::new(MessageDigest::sha256(), &self.secret)
+            .and_then(|mut signer| signer.sign_oneshot_to_vec(data.as_bytes()))
+            .unwrap_or_default();
+        signature.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// FNV-1a 32 бит; та же функция в скрипте страницы
+fn fnv1a32(data: &[u8]) -> u32 {
+    data.iter()
+        .fold(0x811c_9dc5, |hash: u32, byte| (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193))
+}
+
+/// Решение задачи: хеш `<token>.<nonce>` начинается с `difficulty` нулевых бит
+fn solves(token: &str, nonce: &str, difficulty: u32) -> bool {
+    !nonce.is_empty()
+        && nonce.len() <= 16
+        && nonce.bytes().all(|byte| byte.is_ascii_digit())
+        && fnv1a32(format!("{}.{}", token, nonce).as_bytes()).leading_zeros() >= difficulty
+}
+
+const CHALLENGE_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>Checking your browser</title></head>
+<body>
+<p>Checking your browser before accessing the site. This takes a moment.</p>
+<noscript><p>Please enable JavaScript to continue.</p></noscript>
+<script>
+(function () {
+  var token = "{token}", difficulty = {difficulty};
+  function fnv(s) {
+    var h = 0x811c9dc5;
+    for (var i = 0; i < s.length; i++) {
+      h ^= s.charCodeAt(i);
+      h = Math.imul(h, 0x01000193) >>> 0;
+    }
+    return h;
+  }
+  var nonce = 0;
+  while (Math.clz32(fnv(token + "." + nonce)) < difficulty) nonce++;
+  document.cookie = "{cookie}=" + token + "." + nonce + "; path=/; max-age={ttl}; SameSite=Lax";
+  location.reload();
+})();
+</script>
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use std::time::Duration;
+
+    fn challenge(clock: Arc<MockClock>) -> Challenge {
+        let mut config = Config::default();
+        config.challenge.secret = Some("0123456789abcdef0123".to_string());
+        config.challenge.difficulty = 8;
+        config.challenge.allow_ips = vec!["10.1.0.0/16".to_string()];
+        config.challenge.allow_user_agents = vec!["UptimeRobot".to_string()];
+        Challenge::from_config(&config).unwrap().with_clock(clock)
+    }
+
+    fn ip(value: &str) -> Option<IpAddr> {
+        value.parse().ok()
+    }
+
+    #[test]
+    fn test_cookie_validation() {
+        let challenge = challenge(Arc::new(MockClock::default()));
+        let client = ip("203.0.113.10");
+        let token = challenge.issue(client);
+        let cookie = challenge.solve(&token);
+        assert_eq!(challenge.verify(Some(&cookie), client), Verdict::Valid);
+        assert_eq!(challenge.verify(None, client), Verdict::Missing);
+
+        // Токен без решения, подделанная подпись и продленный срок не принимаются
+        assert_eq!(challenge.verify(Some(&token), client), Verdict::Invalid);
+        assert_eq!(challenge.verify(Some(&format!("{}.x", token)), client), Verdict::Invalid);
+        let (expiry, rest) = cookie.split_once('.').unwrap();
+        let extended = format!("{}.{}", expiry.parse::<u64>().unwrap() + 3600, rest);
+        assert_eq!(challenge.verify(Some(&extended), client), Verdict::Invalid);
+        let mut forged = cookie.clone().into_bytes();
+        forged[expiry.len() + 1] = if forged[expiry.len() + 1] == b'0' { b'1' } else { b'0' };
+        assert_eq!(challenge.verify(Some(std::str::from_utf8(&forged).unwrap()), client), Verdict::Invalid);
+
+        // Подпись другим ключом не подходит
+        let mut other = Config::default();
+        other.challenge.secret = Some("another-secret-0123456789".to_string());
+        other.challenge.difficulty = 8;
+        assert_eq!(Challenge::from_config(&other).unwrap().verify(Some(&cookie), client), Verdict::Invalid);
+    }
+
+    #[test]
+    fn test_cookie_expiry() {
+        let clock = Arc::new(MockClock::default());
+        let challenge = challenge(clock.clone());
+        let client = ip("203.0.113.10");
+        let cookie = challenge.solve(&challenge.issue(client));
+
+        clock.advance(Duration::from_secs(3599));
+        assert_eq!(challenge.verify(Some(&cookie), client), Verdict::Valid);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(challenge.verify(Some(&cookie), client), Verdict::Expired);
+    }
+
+    #[test]
+    fn test_cookie_bound_to_ip_prefix() {
+        let challenge = challenge(Arc::new(MockClock::default()));
+        let cookie = challenge.solve(&challenge.issue(ip("203.0.113.10")));
+        // Тот же /24 - другой адрес принимается
+        assert_eq!(challenge.verify(Some(&cookie), ip("203.0.113.200")), Verdict::Valid);
+        assert_eq!(challenge.verify(Some(&cookie), ip("::ffff:203.0.113.77")), Verdict::Valid);
+        assert_eq!(challenge.verify(Some(&cookie), ip("203.0.114.10")), Verdict::Invalid);
+        assert_eq!(challenge.verify(Some(&cookie), None), Verdict::Invalid);
+
+        let cookie = challenge.solve(&challenge.issue(ip("2001:db8:1:2::10")));
+        assert_eq!(challenge.verify(Some(&cookie), ip("2001:db8:1:2:ffff::1")), Verdict::Valid);
+        assert_eq!(challenge.verify(Some(&cookie), ip("2001:db8:1:3::10")), Verdict::Invalid);
+    }
+
+    #[test]
+    fn test_exemptions_and_modes() {
+        let mut config = Config::default();
+        config.nginx_config = Some(
+            crate::config::NginxConfig::parse_config_content(
+                "server { listen 80; server_name shop.example.com; \
+                 location /catalog/ { proxy_pass api; challenge_mode on; } location /api/ { proxy_pass api; } }",
+            )
+            .unwrap(),
+        );
+        config.challenge.allow_ips = vec!["10.1.0.0/16".to_string()];
+        config.challenge.allow_user_agents = vec!["UptimeRobot".to_string()];
+        let challenge = Challenge::from_config(&config).unwrap();
+        assert!(Challenge::is_configured(&config));
+
+        assert!(challenge.is_exempt(ip("10.1.20.30"), None));
+        assert!(challenge.is_exempt(ip("198.51.100.1"), Some("Mozilla/5.0 (compatible; UptimeRobot/2.0)")));
+        assert!(!challenge.is_exempt(ip("198.51.100.1"), Some("Mozilla/5.0")));
+        assert!(!challenge.is_exempt(None, None));
+
+        let server = config.find_server("shop.example.com").unwrap();
+        let catalog = config.find_location(server, "/catalog/items").unwrap();
+        let api = config.find_location(server, "/api/orders").unwrap();
+        assert!(challenge.is_enabled(catalog));
+        assert!(!challenge.is_enabled(api));
+
+        challenge.set_mode("/api/", Some(true)).unwrap();
+        challenge.set_mode("/catalog/", Some(false)).unwrap();
+        assert!(challenge.is_enabled(api));
+        assert!(!challenge.is_enabled(catalog));
+        challenge.set_mode("/catalog/", None).unwrap();
+        assert!(challenge.is_enabled(catalog));
+        assert!(challenge.set_mode("/missing/", Some(true)).is_err());
+
+        let json: serde_json::Value = serde_json::from_str(&challenge.to_json()).unwrap();
+        assert_eq!(json["locations"][0]["location"], "/api/");
+        assert_eq!(json["locations"][0]["configured"], false);
+        assert_eq!(json["locations"][0]["override"], true);
+        assert_eq!(json["locations"][0]["enabled"], true);
+        assert_eq!(json["locations"][1]["override"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_page_embeds_token() {
+        let challenge = challenge(Arc::new(MockClock::default()));
+        let token = challenge.issue(ip("203.0.113.10"));
+        let page = challenge.page(&token);
+        assert!(page.contains(&format!("var token = \"{}\", difficulty = 8;", token)));
+        assert!(page.contains("\"adq_challenge=\" + token"));
+        assert!(page.contains("max-age=3600"));
+        // Значение FNV-1a совпадает с эталонным
+        assert_eq!(fnv1a32(b"a"), 0xe40c292c);
+    }
+}
@@ -0,0 +1,166 @@
+use bytes::Bytes;
+use pingora::prelude::*;
+use pingora::http::ResponseHeader;
+use log::warn;
+
+use super::CircuitState;
+use crate::config::CircuitFallbackConfig;
+use crate::cors::{add_cors_headers_for_request, add_security_headers};
+use crate::error_response::{ErrorCode, ErrorResponse};
+use crate::metrics::CIRCUIT_BREAKER_FALLBACKS;
+use crate::types::RequestContext;
+
+/// Тип fallback ответа для метрики
+fn fallback_type(fallback: Option<&CircuitFallbackConfig>) -> &'static str {
+    match fallback {
+        Some(CircuitFallbackConfig::Json { .. }) => "json",
+        Some(CircuitFallbackConfig::File { .. }) => "file",
+        Some(CircuitFallbackConfig::Redirect { .. }) => "redirect",
+        None => "default",
+    }
+}
+
+/// Заголовок с состоянием circuit в ответах, отклоненных circuit breaker
+pub const CIRCUIT_HEADER: &str = "X-ADQ-Circuit";
+
+/// Формирует fallback ответ при открытом circuit: 503 (или 307 для редиректа)
+/// с Retry-After до следующей попытки восстановления и состоянием circuit
+pub fn build_fallback(
+    fallback: Option<&CircuitFallbackConfig>,
+    retry_after: u64,
+    state: &CircuitState,
+    ctx: &RequestContext,
+) -> Result<(ResponseHeader, Bytes)> {
+    let default_body = || {
+        ErrorResponse::new(ErrorCode::CircuitOpen)
+            .retry_after(retry_after)
+            .upstream(ctx.upstream_label())
+            .body(ctx)
+    };
+
+    let (status, content_type, body) = match fallback {
+        Some(CircuitFallbackConfig::Json { body }) => {
+            (503, "application/json".to_string(), Bytes::from(body.clone()))
+        }
+        Some(CircuitFallbackConfig::File { path }) => match std::fs::read(path) {
+            Ok(content) => {
+                let content_type = mime_guess::from_path(path)
+                    .first_or_octet_stream()
+                    .to_string();
+                (503, content_type, Bytes::from(content))
+            }
+            Err(e) => {
+                warn!("Failed to read circuit breaker fallback '{}': {}", path, e);
+                (503, "application/json".to_string(), Bytes::from(default_body()))
+            }
+        },
+        Some(CircuitFallbackConfig::Redirect { .. }) => (307, String::new(), Bytes::new()),
+        None => (503, "application/json".to_string(), Bytes::from(default_body())),
+    };
+
+    let mut response = ResponseHeader::build(status, None)?;
+    if let Some(CircuitFallbackConfig::Redirect { location }) = fallback {
+        response.insert_header("Location", location.as_str())?;
+    } else {
+        response.insert_header("Content-Type", content_type)?;
+    }
+    response.insert_header("Content-Length", body.len().to_string())?;
+    response.insert_header("Cache-Control", "no-store")?;
+    response.insert_header("Retry-After", retry_after.to_string())?;
+    response.insert_header(CIRCUIT_HEADER, state.as_str())?;
+
+    Ok((response, body))
+}
+
+/// Отправляет fallback ответ клиенту
+pub async fn send_fallback(
+    session: &mut Session,
+    ctx: &RequestContext,
+    fallback: Option<&CircuitFallbackConfig>,
+    retry_after: u64,
+    state: &CircuitState,
+) -> Result<()> {
+    CIRCUIT_BREAKER_FALLBACKS
+        .with_label_values(&[ctx.upstream_label(), fallback_type(fallback)])
+        .inc();
+
+    // Без настроенной заглушки - стандартная JSON ошибка на языке клиента
+    if fallback.is_none() {
+        return ErrorResponse::new(ErrorCode::CircuitOpen)
+            .retry_after(retry_after)
+            .upstream(ctx.upstream_label())
+            .header(CIRCUIT_HEADER, state.as_str())
+            .send(session, ctx)
+            .await;
+    }
+
+    let (mut response, body) = build_fallback(fallback, retry_after, state, ctx)?;
+    add_security_headers(&mut response)?;
+    add_cors_headers_for_request(session, &mut response)?;
+
+    session.set_keepalive(None);
+    session.write_response_header(Box::new(response), false).await?;
+    session.write_response_body(Some(body), true).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit_breaker::CircuitBreaker;
+    use crate::config::CircuitBreakerConfig;
+    use serde_json::Value;
+
+    fn open_breaker_config() -> CircuitBreakerConfig {
+        CircuitBreakerConfig {
+            enabled: true,
+            failure_threshold: 1,
+            recovery_timeout: 30,
+            success_threshold: 1,
+            failure_rate: None,
+            half_open_max_requests: 1,
+            failure_status_codes: vec![500, 502, 503, 504],
+            fallback: None,
+        }
+    }
+
+    fn header<'a>(response: &'a ResponseHeader, name: &str) -> &'a str {
+        response.headers.get(name).unwrap().to_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_open_circuit_serves_fallback_with_retry_after() {
+        let cb = CircuitBreaker::new(open_breaker_config());
+        let mut ctx = RequestContext::new();
+        ctx.upstream_name = Some("core_api".to_string());
+
+        cb.record_failure("core_api").await;
+        assert!(!cb.can_execute("core_api").await);
+
+        let retry_after = cb.retry_after("core_api").await;
+        assert!((29..=30).contains(&retry_after));
+
+        let (response, body) = build_fallback(None, retry_after, &CircuitState::Open, &ctx).unwrap();
+        assert_eq!(response.status.as_u16(), 503);
+        assert_eq!(header(&response, "retry-after"), retry_after.to_string());
+        assert_eq!(header(&response, "x-adq-circuit"), "open");
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "UPSTREAM_CIRCUIT_OPEN");
+        assert_eq!(body["error"]["retry_after"], retry_after);
+        assert_eq!(body["error"]["upstream"], "core_api");
+
+        let json = CircuitFallbackConfig::Json { body: r#"{"items":[]}"#.to_string() };
+        let (response, body) = build_fallback(Some(&json), retry_after, &CircuitState::Open, &ctx).unwrap();
+        assert_eq!(response.status.as_u16(), 503);
+        assert_eq!(header(&response, "content-type"), "application/json");
+        assert_eq!(&body[..], br#"{"items":[]}"#);
+
+        let redirect = CircuitFallbackConfig::Redirect { location: "https://status.ad-quest.ru/".to_string() };
+        let (response, body) = build_fallback(Some(&redirect), retry_after, &CircuitState::HalfOpen, &ctx).unwrap();
+        assert_eq!(response.status.as_u16(), 307);
+        assert_eq!(header(&response, "location"), "https://status.ad-quest.ru/");
+        assert_eq!(header(&response, "retry-after"), retry_after.to_string());
+        assert_eq!(header(&response, "x-adq-circuit"), "half-open");
+        assert!(body.is_empty());
+    }
+}
@@ -3,7 +3,12 @@ use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use std::collections::HashMap;
 use log::{info, warn, debug};
+use crate::clock::{Clock, SystemClock};
 use crate::config::CircuitBreakerConfig;
+use crate::health_events::{HealthEvent, HealthEvents};
+
+pub mod fallback;
+pub use fallback::send_fallback;
 
 /// Состояния Circuit Breaker
 #[derive(Debug, Clone, PartialEq)]
@@ -13,6 +18,98 @@ pub enum CircuitState {
     HalfOpen,  // Тестируем восстановление
 }
 
+impl CircuitState {
+    /// Состояние для заголовка X-ADQ-Circuit
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half-open",
+        }
+    }
+}
+
+/// Retry-After для запросов сверх лимита пробных запросов в HalfOpen:
+/// результат проб ожидается скоро
+const HALF_OPEN_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Слот пробного запроса в HalfOpen. Освобождается записью результата запроса, а если
+/// результата не было (запрос отклонен следующей стадией, клиент отключился) - release_probe
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeToken {
+    upstream: String,
+    /// Номер перехода в HalfOpen: слот прежнего HalfOpen не освобождает слоты нового
+    generation: u64,
+}
+
+/// Решение о пропуске запроса к upstream
+#[derive(Debug, Clone, PartialEq)]
+pub enum Admission {
+    Allowed,
+    /// Пробный запрос в HalfOpen
+    Probe(ProbeToken),
+    Rejected,
+}
+
+/// Итоги запросов за одну секунду окна failure_rate
+#[derive(Debug, Clone, Copy, Default)]
+struct OutcomeBucket {
+    total: u32,
+    failed: u32,
+}
+
+/// Скользящее окно failure_rate: секундные слоты по кругу и итоги по всему окну.
+/// Запись результата и проверка доли ошибок не зависят от числа запросов в окне
+#[derive(Debug, Clone, Default)]
+struct OutcomeWindow {
+    /// Слот секунды `n` от начала отсчета - buckets[n % len]
+    buckets: Vec<OutcomeBucket>,
+    /// Начало отсчета секунд (первый результат после очистки)
+    origin: Option<Instant>,
+    /// Последняя секунда, в которую записан результат
+    second: u64,
+    total: u32,
+    failed: u32,
+}
+
+impl OutcomeWindow {
+    fn record(&mut self, now: Instant, window: u64, failed: bool) {
+        let origin = *self.origin.get_or_insert(now);
+        if self.buckets.is_empty() {
+            self.buckets = vec![OutcomeBucket::default(); window.max(1) as usize];
+        }
+        self.advance(now.saturating_duration_since(origin).as_secs());
+
+        let len = self.buckets.len() as u64;
+        let bucket = &mut self.buckets[(self.second % len) as usize];
+        bucket.total += 1;
+        self.total += 1;
+        if failed {
+            bucket.failed += 1;
+            self.failed += 1;
+        }
+    }
+
+    /// Освобождает слоты секунд, вышедших из окна
+    fn advance(&mut self, second: u64) {
+        if second <= self.second {
+            return;
+        }
+        let len = self.buckets.len() as u64;
+        for offset in 1..=(second - self.second).min(len) {
+            let bucket = &mut self.buckets[((self.second + offset) % len) as usize];
+            self.total -= bucket.total;
+            self.failed -= bucket.failed;
+            *bucket = OutcomeBucket::default();
+        }
+        self.second = second;
+    }
+
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
 /// Статистика для Circuit Breaker
 #[derive(Debug, Clone)]
 struct CircuitStats {
@@ -21,6 +118,12 @@ struct CircuitStats {
     last_failure_time: Option<Instant>,
     state: CircuitState,
     next_attempt: Option<Instant>,
+    /// Результаты запросов в скользящем окне failure_rate
+    outcomes: OutcomeWindow,
+    /// Количество пробных запросов в HalfOpen, ожидающих результата
+    half_open_in_flight: u32,
+    /// Счетчик переходов в HalfOpen
+    generation: u64,
 }
 
 impl Default for CircuitStats {
@@ -31,6 +134,9 @@ impl Default for CircuitStats {
             last_failure_time: None,
             state: CircuitState::Closed,
             next_attempt: None,
+            outcomes: OutcomeWindow::default(),
+            half_open_in_flight: 0,
+            generation: 0,
         }
     }
 }
@@ -39,6 +145,9 @@ impl Default for CircuitStats {
 pub struct CircuitBreaker {
     config: CircuitBreakerConfig,
     circuits: Arc<RwLock<HashMap<String, CircuitStats>>>,
+    /// Журнал событий: открытие и закрытие circuit - переходы upstream в down и up
+    health_events: Option<Arc<HealthEvents>>,
+    clock: Arc<dyn Clock>,
 }
 
 impl CircuitBreaker {
@@ -46,48 +155,119 @@ impl CircuitBreaker {
         Self {
             config,
             circuits: Arc::new(RwLock::new(HashMap::new())),
+            health_events: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Источник времени для recovery_timeout и окна failure_rate
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_health_events(mut self, health_events: Arc<HealthEvents>) -> Self {
+        self.health_events = Some(health_events);
+        self
+    }
+
+    async fn record_health_event(&self, upstream_name: &str, healthy: bool) {
+        if let Some(events) = &self.health_events {
+            events.record(HealthEvent::new(upstream_name, None, healthy, "circuit_breaker")).await;
         }
     }
 
-    /// Проверяет, можно ли выполнить запрос к upstream
+    /// Сохраняет результат запроса в скользящем окне (только в режиме failure_rate)
+    fn record_outcome(&self, stats: &mut CircuitStats, now: Instant, failed: bool) {
+        if let Some(rate) = &self.config.failure_rate {
+            stats.outcomes.record(now, rate.window, failed);
+        }
+    }
+
+    /// Проверяет условие открытия circuit: доля ошибок в окне или счетчик ошибок
+    fn should_trip(&self, stats: &CircuitStats) -> bool {
+        match &self.config.failure_rate {
+            Some(rate) => {
+                let total = stats.outcomes.total;
+                if total == 0 || total < rate.min_requests {
+                    return false;
+                }
+                stats.outcomes.failed as f64 / total as f64 > rate.threshold
+            }
+            None => stats.failure_count >= self.config.failure_threshold,
+        }
+    }
+
+    /// Проверяет, можно ли выполнить запрос к upstream. Слот пробного запроса
+    /// освобождается только результатом запроса; см. admit
     pub async fn can_execute(&self, upstream_name: &str) -> bool {
+        self.admit(upstream_name).await != Admission::Rejected
+    }
+
+    /// Пропускает запрос к upstream; в HalfOpen занимает слот пробного запроса
+    pub async fn admit(&self, upstream_name: &str) -> Admission {
         if !self.config.enabled {
-            return true;
+            return Admission::Allowed;
         }
 
         let mut circuits = self.circuits.write().await;
         let stats = circuits.entry(upstream_name.to_string()).or_default();
 
-        let now = Instant::now();
+        let now = self.clock.now();
+        let probe = |stats: &CircuitStats| {
+            Admission::Probe(ProbeToken { upstream: upstream_name.to_string(), generation: stats.generation })
+        };
 
         match stats.state {
             CircuitState::Closed => {
                 // Нормальная работа - разрешаем запрос
-                true
+                Admission::Allowed
             }
             CircuitState::Open => {
                 // Проверяем, не пора ли перейти в HalfOpen
-                if let Some(next_attempt) = stats.next_attempt {
-                    if now >= next_attempt {
+                match stats.next_attempt {
+                    Some(next_attempt) if now >= next_attempt => {
                         info!("Circuit breaker for '{}' transitioning to HalfOpen", upstream_name);
                         stats.state = CircuitState::HalfOpen;
                         stats.success_count = 0;
-                        true
-                    } else {
+                        stats.half_open_in_flight = 1;
+                        stats.generation += 1;
+                        probe(stats)
+                    }
+                    Some(_) => {
                         debug!("Circuit breaker for '{}' is Open, blocking request", upstream_name);
-                        false
+                        Admission::Rejected
                     }
-                } else {
-                    false
+                    None => Admission::Rejected,
                 }
             }
             CircuitState::HalfOpen => {
                 // В состоянии тестирования - разрешаем ограниченное количество запросов
-                true
+                if stats.half_open_in_flight < self.config.half_open_max_requests {
+                    stats.half_open_in_flight += 1;
+                    probe(stats)
+                } else {
+                    debug!("Circuit breaker for '{}' is HalfOpen with {} probe(s) in flight, blocking request",
+                           upstream_name, stats.half_open_in_flight);
+                    Admission::Rejected
+                }
             }
         }
     }
 
+    /// Освобождает слот пробного запроса, завершившегося без результата для circuit breaker
+    pub async fn release_probe(&self, token: ProbeToken) {
+        let mut circuits = self.circuits.write().await;
+        let Some(stats) = circuits.get_mut(&token.upstream) else {
+            return;
+        };
+        if stats.state == CircuitState::HalfOpen && stats.generation == token.generation {
+            stats.half_open_in_flight = stats.half_open_in_flight.saturating_sub(1);
+            debug!("Circuit breaker for '{}': probe ended without an outcome, {} probe(s) in flight",
+                   token.upstream, stats.half_open_in_flight);
+        }
+    }
+
     /// Регистрирует успешный запрос
     pub async fn record_success(&self, upstream_name: &str) {
         if !self.config.enabled {
@@ -96,15 +276,18 @@ impl CircuitBreaker {
 
         let mut circuits = self.circuits.write().await;
         let stats = circuits.entry(upstream_name.to_string()).or_default();
+        let mut closed = false;
 
         match stats.state {
             CircuitState::Closed => {
                 // Сбрасываем счетчик ошибок при успехе
                 stats.failure_count = 0;
+                self.record_outcome(stats, self.clock.now(), false);
                 debug!("Circuit breaker for '{}': success recorded, failure count reset", upstream_name);
             }
             CircuitState::HalfOpen => {
                 stats.success_count += 1;
+                stats.half_open_in_flight = stats.half_open_in_flight.saturating_sub(1);
                 debug!("Circuit breaker for '{}': success in HalfOpen state ({}/{})", 
                        upstream_name, stats.success_count, self.config.success_threshold);
 
@@ -116,6 +299,9 @@ impl CircuitBreaker {
                     stats.failure_count = 0;
                     stats.success_count = 0;
                     stats.next_attempt = None;
+                    stats.half_open_in_flight = 0;
+                    stats.outcomes.clear();
+                    closed = true;
                 }
             }
             CircuitState::Open => {
@@ -123,6 +309,11 @@ impl CircuitBreaker {
                 warn!("Unexpected success recorded for open circuit breaker '{}'", upstream_name);
             }
         }
+        drop(circuits);
+
+        if closed {
+            self.record_health_event(upstream_name, true).await;
+        }
     }
 
     /// Регистрирует неудачный запрос
@@ -134,21 +325,25 @@ impl CircuitBreaker {
         let mut circuits = self.circuits.write().await;
         let stats = circuits.entry(upstream_name.to_string()).or_default();
 
-        let now = Instant::now();
+        let now = self.clock.now();
         stats.failure_count += 1;
         stats.last_failure_time = Some(now);
+        let mut opened = false;
 
         match stats.state {
             CircuitState::Closed => {
                 debug!("Circuit breaker for '{}': failure recorded ({}/{})", 
                        upstream_name, stats.failure_count, self.config.failure_threshold);
+                self.record_outcome(stats, now, true);
 
                 // Проверяем, не достигли ли порога ошибок
-                if stats.failure_count >= self.config.failure_threshold {
+                if self.should_trip(stats) {
                     warn!("Circuit breaker for '{}' transitioning to Open after {} failures", 
                           upstream_name, stats.failure_count);
                     stats.state = CircuitState::Open;
                     stats.next_attempt = Some(now + Duration::from_secs(self.config.recovery_timeout));
+                    stats.outcomes.clear();
+                    opened = true;
                 }
             }
             CircuitState::HalfOpen => {
@@ -157,6 +352,7 @@ impl CircuitBreaker {
                       upstream_name);
                 stats.state = CircuitState::Open;
                 stats.success_count = 0;
+                stats.half_open_in_flight = 0;
                 stats.next_attempt = Some(now + Duration::from_secs(self.config.recovery_timeout));
             }
             CircuitState::Open => {
@@ -166,6 +362,25 @@ impl CircuitBreaker {
                        upstream_name, stats.next_attempt);
             }
         }
+        drop(circuits);
+
+        if opened {
+            self.record_health_event(upstream_name, false).await;
+        }
+    }
+
+    /// Проверяет, считается ли код ответа upstream ошибкой
+    pub fn is_failure_status(&self, status: u16) -> bool {
+        self.config.failure_status_codes.contains(&status)
+    }
+
+    /// Регистрирует результат запроса по коду ответа upstream
+    pub async fn record_status(&self, upstream_name: &str, status: u16) {
+        if self.is_failure_status(status) {
+            self.record_failure(upstream_name).await;
+        } else {
+            self.record_success(upstream_name).await;
+        }
     }
 
     /// Получает текущее состояние circuit breaker
@@ -180,6 +395,32 @@ impl CircuitBreaker {
             .unwrap_or(CircuitState::Closed)
     }
 
+    /// Оставшееся время до повторной попытки: до next_attempt в Open,
+    /// короткая пауза в HalfOpen, None - circuit закрыт
+    pub async fn remaining_cooldown(&self, upstream_name: &str) -> Option<Duration> {
+        let circuits = self.circuits.read().await;
+        let stats = circuits.get(upstream_name)?;
+        match stats.state {
+            CircuitState::Closed => None,
+            CircuitState::Open => Some(
+                stats
+                    .next_attempt
+                    .map(|next_attempt| next_attempt.saturating_duration_since(self.clock.now()))
+                    .unwrap_or_default(),
+            ),
+            CircuitState::HalfOpen => Some(HALF_OPEN_RETRY_AFTER),
+        }
+    }
+
+    /// Время до следующей попытки восстановления в секундах (для Retry-After), минимум 1
+    pub async fn retry_after(&self, upstream_name: &str) -> u64 {
+        self.remaining_cooldown(upstream_name)
+            .await
+            .map(|remaining| remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0))
+            .unwrap_or(1)
+            .max(1)
+    }
+
     /// Получает статистику всех circuit breakers
     pub async fn get_all_stats(&self) -> HashMap<String, (CircuitState, u32, u32)> {
         let circuits = self.circuits.read().await;
@@ -200,6 +441,8 @@ impl CircuitBreaker {
             stats.success_count = 0;
             stats.next_attempt = None;
             stats.last_failure_time = None;
+            stats.half_open_in_flight = 0;
+            stats.outcomes.clear();
         }
     }
 
@@ -210,25 +453,30 @@ impl CircuitBreaker {
         
         info!("Manually opening circuit breaker for '{}'", upstream_name);
         stats.state = CircuitState::Open;
-        stats.next_attempt = Some(Instant::now() + Duration::from_secs(self.config.recovery_timeout));
+        stats.next_attempt = Some(self.clock.now() + Duration::from_secs(self.config.recovery_timeout));
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tokio::time::{sleep, Duration};
+    use crate::clock::MockClock;
 
-    #[test]
+    #[tokio::test]
     async fn test_circuit_breaker_transitions() {
         let config = CircuitBreakerConfig {
             enabled: true,
             failure_threshold: 3,
-            recovery_timeout: 1, // 1 секунда для быстрого тестирования
+            recovery_timeout: 30,
             success_threshold: 2,
+            failure_rate: None,
+            half_open_max_requests: 2,
+            failure_status_codes: vec![500, 502, 503, 504],
+            fallback: None,
         };
 
-        let cb = CircuitBreaker::new(config);
+        let clock = Arc::new(MockClock::default());
+        let cb = CircuitBreaker::new(config).with_clock(clock.clone());
         let upstream = "test_upstream";
 
         // Начальное состояние - Closed
@@ -247,10 +495,13 @@ mod tests {
         assert_eq!(cb.get_state(upstream).await, CircuitState::Open);
         assert!(!cb.can_execute(upstream).await);
 
-        // Ждем время восстановления
-        sleep(Duration::from_secs(2)).await;
-        
+        // До истечения recovery_timeout circuit остается открытым
+        clock.advance(Duration::from_secs(29));
+        assert!(!cb.can_execute(upstream).await);
+        assert_eq!(cb.retry_after(upstream).await, 1);
+
         // Должен перейти в HalfOpen при следующей проверке
+        clock.advance(Duration::from_secs(1));
         assert!(cb.can_execute(upstream).await);
         assert_eq!(cb.get_state(upstream).await, CircuitState::HalfOpen);
 
@@ -263,13 +514,17 @@ mod tests {
         assert_eq!(cb.get_state(upstream).await, CircuitState::Closed);
     }
 
-    #[test]
+    #[tokio::test]
     async fn test_circuit_breaker_disabled() {
         let config = CircuitBreakerConfig {
             enabled: false,
             failure_threshold: 1,
             recovery_timeout: 1,
             success_threshold: 1,
+            failure_rate: None,
+            half_open_max_requests: 1,
+            failure_status_codes: vec![500, 502, 503, 504],
+            fallback: None,
         };
 
         let cb = CircuitBreaker::new(config);
@@ -283,4 +538,223 @@ mod tests {
         assert_eq!(cb.get_state(upstream).await, CircuitState::Closed);
         assert!(cb.can_execute(upstream).await);
     }
-}
\ No newline at end of file
+
+    fn rate_config() -> CircuitBreakerConfig {
+        CircuitBreakerConfig {
+            enabled: true,
+            failure_threshold: 1,
+            recovery_timeout: 30,
+            success_threshold: 1,
+            failure_rate: Some(crate::config::FailureRateConfig {
+                threshold: 0.5,
+                min_requests: 10,
+                window: 10,
+            }),
+            half_open_max_requests: 1,
+            failure_status_codes: vec![500, 502, 503, 504],
+            fallback: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failure_rate_below_threshold_does_not_trip() {
+        let cb = CircuitBreaker::new(rate_config());
+        let upstream = "test_upstream";
+
+        // Всплеск из 4 ошибок среди 10 запросов - 40%, ниже порога
+        for _ in 0..6 {
+            cb.record_success(upstream).await;
+        }
+        for _ in 0..4 {
+            cb.record_failure(upstream).await;
+        }
+        assert_eq!(cb.get_state(upstream).await, CircuitState::Closed);
+
+        // Ошибки при недостаточном количестве запросов в окне также не открывают circuit
+        let other = "other_upstream";
+        for _ in 0..5 {
+            cb.record_failure(other).await;
+        }
+        assert_eq!(cb.get_state(other).await, CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_failure_rate_sustained_trips() {
+        let cb = CircuitBreaker::new(rate_config());
+        let upstream = "test_upstream";
+
+        for _ in 0..4 {
+            cb.record_success(upstream).await;
+        }
+        for _ in 0..6 {
+            cb.record_failure(upstream).await;
+        }
+
+        // 6 ошибок из 10 запросов - 60%, выше порога
+        assert_eq!(cb.get_state(upstream).await, CircuitState::Open);
+        assert!(!cb.can_execute(upstream).await);
+    }
+
+    #[tokio::test]
+    async fn test_failure_rate_window_expires_old_outcomes() {
+        let clock = Arc::new(MockClock::default());
+        let cb = CircuitBreaker::new(rate_config()).with_clock(clock.clone());
+        let upstream = "test_upstream";
+
+        for _ in 0..6 {
+            cb.record_failure(upstream).await;
+        }
+        // Ошибки старше окна (10s) не учитываются
+        clock.advance(Duration::from_secs(11));
+        for _ in 0..4 {
+            cb.record_success(upstream).await;
+        }
+        for _ in 0..4 {
+            cb.record_failure(upstream).await;
+        }
+        assert_eq!(cb.get_state(upstream).await, CircuitState::Closed);
+
+        // Внутри окна результаты разных секунд суммируются: 6 ошибок из 10
+        clock.advance(Duration::from_secs(5));
+        for _ in 0..2 {
+            cb.record_failure(upstream).await;
+        }
+        assert_eq!(cb.get_state(upstream).await, CircuitState::Open);
+    }
+
+    #[tokio::test]
+    async fn test_half_open_limits_concurrent_probes() {
+        let config = CircuitBreakerConfig {
+            enabled: true,
+            failure_threshold: 1,
+            recovery_timeout: 0, // HalfOpen сразу после открытия
+            success_threshold: 3,
+            failure_rate: None,
+            half_open_max_requests: 2,
+            failure_status_codes: vec![500, 502, 503, 504],
+            fallback: None,
+        };
+
+        let cb = CircuitBreaker::new(config);
+        let upstream = "test_upstream";
+
+        cb.record_failure(upstream).await;
+        assert_eq!(cb.get_state(upstream).await, CircuitState::Open);
+
+        // Пропускаются только 2 пробных запроса
+        assert!(cb.can_execute(upstream).await);
+        assert_eq!(cb.get_state(upstream).await, CircuitState::HalfOpen);
+        assert!(cb.can_execute(upstream).await);
+        assert!(!cb.can_execute(upstream).await);
+
+        // Завершение пробы освобождает слот
+        cb.record_success(upstream).await;
+        assert!(cb.can_execute(upstream).await);
+        assert!(!cb.can_execute(upstream).await);
+
+        // Circuit закрывается только после success_threshold успешных проб
+        cb.record_success(upstream).await;
+        assert_eq!(cb.get_state(upstream).await, CircuitState::HalfOpen);
+        cb.record_success(upstream).await;
+        assert_eq!(cb.get_state(upstream).await, CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_failure_status_classification() {
+        let config = CircuitBreakerConfig {
+            enabled: true,
+            failure_threshold: 5,
+            recovery_timeout: 30,
+            success_threshold: 1,
+            failure_rate: None,
+            half_open_max_requests: 1,
+            failure_status_codes: vec![500, 502, 503, 504],
+            fallback: None,
+        };
+
+        let cb = CircuitBreaker::new(config);
+        let upstream = "test_upstream";
+
+        // 404 - ошибка клиента, не учитывается как сбой upstream
+        cb.record_status(upstream, 404).await;
+        assert_eq!(cb.get_all_stats().await[upstream].1, 0);
+
+        cb.record_status(upstream, 503).await;
+        assert_eq!(cb.get_all_stats().await[upstream].1, 1);
+        assert_eq!(cb.get_state(upstream).await, CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_transitions_recorded_as_health_events() {
+        let config = CircuitBreakerConfig {
+            enabled: true,
+            failure_threshold: 1,
+            recovery_timeout: 0,
+            success_threshold: 1,
+            failure_rate: None,
+            half_open_max_requests: 1,
+            failure_status_codes: vec![500, 502, 503, 504],
+            fallback: None,
+        };
+        let events = Arc::new(HealthEvents::new(10));
+        let cb = CircuitBreaker::new(config).with_health_events(events.clone());
+        let upstream = "test_upstream";
+
+        cb.record_failure(upstream).await;
+        // Повторная ошибка в Open не порождает нового события
+        cb.record_failure(upstream).await;
+        assert!(cb.can_execute(upstream).await);
+        cb.record_success(upstream).await;
+
+        let recorded = events.recent();
+        assert_eq!(recorded.len(), 2);
+        assert_eq!(recorded[0].event_type(), "backend_down");
+        assert_eq!(recorded[0].reason, "circuit_breaker");
+        assert_eq!(recorded[0].backend, None);
+        assert_eq!(recorded[1].event_type(), "backend_up");
+    }
+
+    #[tokio::test]
+    async fn test_remaining_cooldown_by_state() {
+        let config = CircuitBreakerConfig {
+            enabled: true,
+            failure_threshold: 1,
+            recovery_timeout: 30,
+            success_threshold: 1,
+            failure_rate: None,
+            half_open_max_requests: 1,
+            failure_status_codes: vec![500, 502, 503, 504],
+            fallback: None,
+        };
+        let clock = Arc::new(MockClock::default());
+        let cb = CircuitBreaker::new(config.clone()).with_clock(clock.clone());
+        let upstream = "test_upstream";
+
+        // Closed: ожидать нечего
+        assert_eq!(cb.remaining_cooldown(upstream).await, None);
+        assert_eq!(cb.retry_after(upstream).await, 1);
+
+        // Open: до next_attempt, Retry-After округляется вверх
+        cb.record_failure(upstream).await;
+        assert_eq!(cb.remaining_cooldown(upstream).await, Some(Duration::from_secs(30)));
+        assert_eq!(cb.retry_after(upstream).await, 30);
+        clock.advance(Duration::from_millis(10_500));
+        assert_eq!(cb.remaining_cooldown(upstream).await, Some(Duration::from_millis(19_500)));
+        assert_eq!(cb.retry_after(upstream).await, 20);
+
+        // HalfOpen сверх лимита проб: короткая пауза
+        let cb = CircuitBreaker::new(CircuitBreakerConfig { recovery_timeout: 0, ..config });
+        cb.record_failure(upstream).await;
+        assert_eq!(cb.remaining_cooldown(upstream).await, Some(Duration::ZERO));
+        assert_eq!(cb.retry_after(upstream).await, 1);
+        assert!(cb.can_execute(upstream).await);
+        assert!(!cb.can_execute(upstream).await);
+        assert_eq!(cb.get_state(upstream).await, CircuitState::HalfOpen);
+        assert_eq!(cb.remaining_cooldown(upstream).await, Some(Duration::from_secs(1)));
+        assert_eq!(cb.retry_after(upstream).await, 1);
+
+        // Закрытие после успешной пробы
+        cb.record_success(upstream).await;
+        assert_eq!(cb.remaining_cooldown(upstream).await, None);
+    }
+}
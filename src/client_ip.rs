@@ -0,0 +1,28 @@
+use pingora_core::protocols::http::ServerSession;
+use std::net::IpAddr;
+
+/// IP адрес клиента соединения (без порта). IPv4 клиенты dual-stack слушателя приходят
+/// как ::ffff:a.b.c.d и приводятся к IPv4, чтобы совпадать с allowlist, ключами rate limit и логами
+pub fn client_ip(session: &ServerSession) -> Option<IpAddr> {
+    session.client_addr()?.as_inet().map(|addr| canonical_ip(addr.ip()))
+}
+
+/// IPv4-mapped IPv6 адрес как IPv4, остальные без изменений
+pub fn canonical_ip(ip: IpAddr) -> IpAddr {
+    ip.to_canonical()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mapped_ipv4_canonicalized() {
+        let mapped: IpAddr = "::ffff:192.0.2.10".parse().unwrap();
+        assert_eq!(canonical_ip(mapped), "192.0.2.10".parse::<IpAddr>().unwrap());
+
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+        assert_eq!(canonical_ip(v6), v6);
+        assert_eq!(canonical_ip("::1".parse().unwrap()), "::1".parse::<IpAddr>().unwrap());
+    }
+}
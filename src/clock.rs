@@ -0,0 +1,92 @@
+use chrono::{DateTime, Utc};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Источник времени компонентов (circuit breaker, расписания, TTL кеша);
+/// в тестах подменяется MockClock, который переводится вручную вместо sleep
+pub trait Clock: Send + Sync {
+    /// Монотонное время: интервалы, таймауты, окна
+    fn now(&self) -> Instant;
+    /// Календарное время: расписания, сроки свежести кеша
+    fn utc(&self) -> DateTime<Utc>;
+}
+
+/// Системное время
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn utc(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Часы, которые идут только по advance и set; монотонное и календарное время сдвигаются вместе
+#[derive(Debug)]
+pub struct MockClock {
+    instant: Instant,
+    utc: DateTime<Utc>,
+    elapsed: Mutex<Duration>,
+}
+
+impl MockClock {
+    /// Часы, показывающие календарное время `utc`
+    pub fn at(utc: DateTime<Utc>) -> Self {
+        Self { instant: Instant::now(), utc, elapsed: Mutex::new(Duration::ZERO) }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+
+    /// Переводит часы вперед на календарное время `utc`
+    pub fn set(&self, utc: DateTime<Utc>) {
+        let elapsed = (utc - self.utc).to_std().expect("MockClock cannot go back in time");
+        *self.elapsed.lock().unwrap() = elapsed;
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::at(Utc::now())
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.instant + self.elapsed()
+    }
+
+    fn utc(&self) -> DateTime<Utc> {
+        self.utc + chrono::Duration::from_std(self.elapsed()).expect("MockClock elapsed time out of range")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock_moves_only_when_advanced() {
+        let start = DateTime::parse_from_rfc3339("2026-10-18T02:00:00Z").unwrap().with_timezone(&Utc);
+        let clock = MockClock::at(start);
+        let instant = clock.now();
+        assert_eq!(clock.now(), instant);
+        assert_eq!(clock.utc(), start);
+
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(clock.now() - instant, Duration::from_secs(90));
+        assert_eq!(clock.utc().to_rfc3339(), "2026-10-18T02:01:30+00:00");
+
+        clock.set(DateTime::parse_from_rfc3339("2026-10-18T03:00:00Z").unwrap().with_timezone(&Utc));
+        assert_eq!(clock.now() - instant, Duration::from_secs(3600));
+    }
+}
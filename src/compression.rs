@@ -0,0 +1,404 @@
+use bytes::Bytes;
+use flate2::write::GzEncoder;
+use log::warn;
+use pingora::http::{RequestHeader, ResponseHeader};
+use pingora::prelude::*;
+use std::io::Write;
+
+use crate::config::{parse_size, CompressionAlgorithm, CompressionConfig, LocationBlock};
+use crate::metrics::COMPRESSED_RESPONSES;
+use crate::static_files::encoding_quality;
+
+/// Уровни по умолчанию: быстрые режимы для сжатия на лету
+const DEFAULT_GZIP_LEVEL: u32 = 6;
+const DEFAULT_BROTLI_LEVEL: u32 = 4;
+const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// Кодировка ответа, согласованная для запроса (None - без сжатия)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Negotiated {
+    pub algorithm: Option<CompressionAlgorithm>,
+}
+
+impl Negotiated {
+    /// Вариант для ключа кеша и Accept-Encoding запроса к upstream
+    pub fn variant(&self) -> &'static str {
+        self.algorithm.map_or("identity", |algorithm| algorithm.as_str())
+    }
+}
+
+/// Выбирает кодировку с наибольшим q-value; при равных - первую из `preference`
+pub fn negotiate(accept_encoding: Option<&str>, preference: &[CompressionAlgorithm]) -> Option<CompressionAlgorithm> {
+    let accept_encoding = accept_encoding?;
+    let mut best: Option<(CompressionAlgorithm, f32)> = None;
+    for algorithm in preference {
+        let quality = encoding_quality(accept_encoding, algorithm.as_str());
+        if quality > 0.0 && best.map_or(true, |(_, best_quality)| quality > best_quality) {
+            best = Some((*algorithm, quality));
+        }
+    }
+    best.map(|(algorithm, _)| algorithm)
+}
+
+/// Правило уровней сжатия с разобранным размером
+#[derive(Debug)]
+struct LevelRule {
+    content_type: Option<String>,
+    min_size: Option<usize>,
+    gzip: Option<u32>,
+    br: Option<u32>,
+    zstd: Option<i32>,
+}
+
+/// Настройки сжатия ответов (секция compression)
+#[derive(Debug)]
+pub struct Compression {
+    algorithms: Vec<CompressionAlgorithm>,
+    min_length: usize,
+    types: Vec<String>,
+    levels: Vec<LevelRule>,
+}
+
+impl Compression {
+    /// Проверяет секцию compression; None - сжатие выключено
+    pub fn from_config(config: &CompressionConfig) -> Result<Option<Self>, String> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        if config.algorithms.is_empty() {
+            return Err("compression.algorithms must not be empty".to_string());
+        }
+        let min_length = parse_size(&config.min_length)
+            .ok_or_else(|| format!("invalid compression.min_length '{}'", config.min_length))?;
+
+        let mut levels = Vec::new();
+        for (i, rule) in config.levels.iter().enumerate() {
+            let min_size = match &rule.min_size {
+                Some(size) => Some(parse_size(size).ok_or_else(|| format!("invalid compression.levels[{}].min_size '{}'", i, size))?),
+                None => None,
+            };
+            if rule.gzip.is_some_and(|level| !(1..=9).contains(&level)) {
+                return Err(format!("compression.levels[{}].gzip must be between 1 and 9", i));
+            }
+            if rule.br.is_some_and(|level| level > 11) {
+                return Err(format!("compression.levels[{}].br must be between 0 and 11", i));
+            }
+            if rule.zstd.is_some_and(|level| !(1..=22).contains(&level)) {
+                return Err(format!("compression.levels[{}].zstd must be between 1 and 22", i));
+            }
+            levels.push(LevelRule {
+                content_type: rule.content_type.as_ref().map(|t| t.to_ascii_lowercase()),
+                min_size,
+                gzip: rule.gzip,
+                br: rule.br,
+                zstd: rule.zstd,
+            });
+        }
+
+        Ok(Some(Self {
+            algorithms: config.algorithms.clone(),
+            min_length,
+            types: config.types.iter().map(|t| t.to_ascii_lowercase()).collect(),
+            levels,
+        }))
+    }
+
+    /// Согласует кодировку для запроса; None - location выключает сжатие (compression_algorithms off)
+    pub fn negotiate(&self, req: &RequestHeader, location: Option<&LocationBlock>) -> Option<Negotiated> {
+        let allowed: Vec<CompressionAlgorithm> = match location.and_then(|l| l.compression_algorithms.as_ref()) {
+            Some(allowed) if allowed.is_empty() => return None,
+            Some(allowed) => self.algorithms.iter().filter(|a| allowed.contains(a)).copied().collect(),
+            None => self.algorithms.clone(),
+        };
+        let accept_encoding = req.headers.get("accept-encoding").and_then(|v| v.to_str().ok());
+        Some(Negotiated { algorithm: negotiate(accept_encoding, &allowed) })
+    }
+
+    fn type_matches(pattern: &str, content_type: &str) -> bool {
+        match pattern.strip_suffix("/*") {
+            Some(prefix) => content_type.split('/').next() == Some(prefix),
+            None => pattern == content_type,
+        }
+    }
+
+    fn media_type(resp: &ResponseHeader) -> Option<String> {
+        let content_type = resp.headers.get("content-type")?.to_str().ok()?;
+        Some(content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase())
+    }
+
+    /// Ответ подходит для сжатия по статусу, типу, размеру и отсутствию Content-Encoding
+    fn compressible(&self, resp: &ResponseHeader) -> bool {
+        let status = resp.status.as_u16();
+        if status < 200 || matches!(status, 204 | 206 | 304) || resp.headers.contains_key("content-range") {
+            return false;
+        }
+        if resp.headers.get("content-encoding").is_some_and(|v| v.as_bytes() != b"identity") {
+            return false;
+        }
+        let content_length = resp.headers.get("content-length").and_then(|v| v.to_str().ok()?.parse::<usize>().ok());
+        if content_length.is_some_and(|length| length < self.min_length) {
+            return false;
+        }
+        Self::media_type(resp).is_some_and(|media_type| self.types.iter().any(|t| Self::type_matches(t, &media_type)))
+    }
+
+    /// Уровень сжатия по первому подходящему правилу levels
+    fn level(&self, algorithm: CompressionAlgorithm, resp: &ResponseHeader) -> i32 {
+        let media_type = Self::media_type(resp).unwrap_or_default();
+        let content_length = resp.headers.get("content-length").and_then(|v| v.to_str().ok()?.parse::<usize>().ok());
+        let rule = self.levels.iter().find(|rule| {
+            rule.content_type.as_deref().map_or(true, |t| Self::type_matches(t, &media_type))
+                && rule.min_size.map_or(true, |min| content_length.is_some_and(|length| length >= min))
+        });
+        match algorithm {
+            CompressionAlgorithm::Gzip => rule.and_then(|r| r.gzip).unwrap_or(DEFAULT_GZIP_LEVEL) as i32,
+            CompressionAlgorithm::Brotli => rule.and_then(|r| r.br).unwrap_or(DEFAULT_BROTLI_LEVEL) as i32,
+            CompressionAlgorithm::Zstd => rule.and_then(|r| r.zstd).unwrap_or(DEFAULT_ZSTD_LEVEL),
+        }
+    }
+
+    /// Готовит заголовки сжимаемого ответа и возвращает кодировщик тела.
+    /// Vary: Accept-Encoding добавляется ко всем сжимаемым ответам, в том числе несжатым
+    pub fn start(&self, resp: &mut ResponseHeader, negotiated: Negotiated) -> Result<Option<ResponseCompressor>> {
+        if !self.compressible(resp) {
+            return Ok(None);
+        }
+        add_vary_accept_encoding(resp)?;
+        let Some(algorithm) = negotiated.algorithm else {
+            return Ok(None);
+        };
+
+        let compressor = match ResponseCompressor::new(algorithm, self.level(algorithm, resp)) {
+            Ok(compressor) => compressor,
+            Err(e) => {
+                warn!("Failed to start {} compression, response sent uncompressed: {}", algorithm.as_str(), e);
+                return Ok(None);
+            }
+        };
+        resp.insert_header("Content-Encoding", algorithm.as_str())?;
+        resp.remove_header("Content-Length");
+        resp.insert_header("Transfer-Encoding", "chunked")?;
+        // Тело изменилось: сильный ETag становится слабым
+        let weak_etag = resp
+            .headers
+            .get("etag")
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.starts_with("W/"))
+            .map(|etag| format!("W/{}", etag));
+        if let Some(etag) = weak_etag {
+            resp.insert_header("ETag", etag)?;
+        }
+        COMPRESSED_RESPONSES.with_label_values(&[algorithm.as_str()]).inc();
+        Ok(Some(compressor))
+    }
+}
+
+/// Добавляет Accept-Encoding в Vary, если его там нет
+fn add_vary_accept_encoding(resp: &mut ResponseHeader) -> Result<()> {
+    let vary = resp.headers.get_all("vary").iter().filter_map(|v| v.to_str().ok()).collect::<Vec<_>>().join(", ");
+    if vary.split(',').any(|v| v.trim() == "*" || v.trim().eq_ignore_ascii_case("accept-encoding")) {
+        return Ok(());
+    }
+    let value = if vary.is_empty() { "Accept-Encoding".to_string() } else { format!("{}, Accept-Encoding", vary) };
+    resp.insert_header("Vary", value)?;
+    Ok(())
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+/// Потоковое сжатие тела ответа
+pub struct ResponseCompressor {
+    encoder: Option<Encoder>,
+}
+
+impl std::fmt::Debug for ResponseCompressor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResponseCompressor").field("finished", &self.encoder.is_none()).finish()
+    }
+}
+
+impl ResponseCompressor {
+    pub fn new(algorithm: CompressionAlgorithm, level: i32) -> std::io::Result<Self> {
+        let encoder = match algorithm {
+            CompressionAlgorithm::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), flate2::Compression::new(level as u32))),
+            CompressionAlgorithm::Brotli => {
+                Encoder::Brotli(Box::new(brotli::CompressorWriter::new(Vec::new(), 4096, level as u32, 22)))
+            }
+            CompressionAlgorithm::Zstd => Encoder::Zstd(zstd::stream::write::Encoder::new(Vec::new(), level)?),
+        };
+        Ok(Self { encoder: Some(encoder) })
+    }
+
+    /// Сжимает очередной фрагмент; в конце потока отдает остаток кодировщика
+    pub fn push(&mut self, chunk: Option<Bytes>, end_of_stream: bool) -> Option<Bytes> {
+        match self.encode(chunk.as_deref().unwrap_or_default(), end_of_stream) {
+            Ok(output) if output.is_empty() => None,
+            Ok(output) => Some(Bytes::from(output)),
+            Err(e) => {
+                warn!("Response compression failed: {}", e);
+                self.encoder = None;
+                None
+            }
+        }
+    }
+
+    fn encode(&mut self, data: &[u8], end_of_stream: bool) -> std::io::Result<Vec<u8>> {
+        let Some(encoder) = self.encoder.as_mut() else {
+            return Ok(Vec::new());
+        };
+        match encoder {
+            Encoder::Gzip(e) => e.write_all(data)?,
+            Encoder::Brotli(e) => e.write_all(data)?,
+            Encoder::Zstd(e) => e.write_all(data)?,
+        }
+        if end_of_stream {
+            return match self.encoder.take() {
+                Some(Encoder::Gzip(e)) => e.finish(),
+                Some(Encoder::Brotli(e)) => Ok(e.into_inner()),
+                Some(Encoder::Zstd(e)) => e.finish(),
+                None => Ok(Vec::new()),
+            };
+        }
+        // Уже сжатые данные отдаются сразу, остальное остается в кодировщике
+        Ok(std::mem::take(match encoder {
+            Encoder::Gzip(e) => e.get_mut(),
+            Encoder::Brotli(e) => e.get_mut(),
+            Encoder::Zstd(e) => e.get_mut(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use CompressionAlgorithm::{Brotli, Gzip, Zstd};
+
+    const ALL: &[CompressionAlgorithm] = &[Zstd, Brotli, Gzip];
+
+    #[test]
+    fn test_negotiation_matrix() {
+        let cases: &[(Option<&str>, &[CompressionAlgorithm], Option<CompressionAlgorithm>)] = &[
+            (Some("zstd, br, gzip"), ALL, Some(Zstd)),
+            (Some("gzip, br, zstd"), ALL, Some(Zstd)),
+            (Some("gzip, deflate, br"), ALL, Some(Brotli)),
+            (Some("gzip"), ALL, Some(Gzip)),
+            (Some("zstd;q=0.5, gzip"), ALL, Some(Gzip)),
+            (Some("zstd;q=0, br;q=0.8, gzip;q=0.8"), ALL, Some(Brotli)),
+            (Some("*"), ALL, Some(Zstd)),
+            (Some("*, zstd;q=0"), ALL, Some(Brotli)),
+            (Some("identity"), ALL, None),
+            (Some("deflate"), ALL, None),
+            (None, ALL, None),
+            // compression_algorithms gzip; в location
+            (Some("zstd, br, gzip"), &[Gzip], Some(Gzip)),
+            (Some("zstd, br"), &[Gzip], None),
+            // Предпочтение сервера задается порядком algorithms
+            (Some("zstd, br, gzip"), &[Gzip, Zstd], Some(Gzip)),
+        ];
+        for (accept_encoding, preference, expected) in cases {
+            assert_eq!(negotiate(*accept_encoding, preference), *expected, "Accept-Encoding: {:?}, allowed {:?}", accept_encoding, preference);
+        }
+    }
+
+    fn compression(levels: Vec<crate::config::CompressionLevels>) -> Compression {
+        let config = CompressionConfig { enabled: true, levels, ..CompressionConfig::default() };
+        Compression::from_config(&config).unwrap().unwrap()
+    }
+
+    fn response(content_type: &str, length: usize) -> ResponseHeader {
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("Content-Type", content_type).unwrap();
+        resp.insert_header("Content-Length", length.to_string()).unwrap();
+        resp.insert_header("ETag", "\"v1\"").unwrap();
+        resp
+    }
+
+    #[test]
+    fn test_compressed_response_headers_and_body() {
+        let compression = compression(Vec::new());
+        let body: Vec<u8> = serde_json::to_vec(&vec![serde_json::json!({"id": 1, "name": "campaign"}); 200]).unwrap();
+
+        for algorithm in ALL {
+            let mut resp = response("application/json; charset=utf-8", body.len());
+            let mut compressor = compression
+                .start(&mut resp, Negotiated { algorithm: Some(*algorithm) })
+                .unwrap()
+                .unwrap();
+            assert_eq!(resp.headers.get("content-encoding").unwrap(), algorithm.as_str());
+            assert_eq!(resp.headers.get("vary").unwrap(), "Accept-Encoding");
+            assert_eq!(resp.headers.get("etag").unwrap(), "W/\"v1\"");
+            assert!(resp.headers.get("content-length").is_none());
+
+            let mut encoded = Vec::new();
+            for chunk in body.chunks(1000) {
+                encoded.extend(compressor.push(Some(Bytes::copy_from_slice(chunk)), false).unwrap_or_default());
+            }
+            encoded.extend(compressor.push(None, true).unwrap_or_default());
+            assert!(encoded.len() < body.len());
+
+            let mut decoded = Vec::new();
+            match algorithm {
+                Gzip => flate2::read::GzDecoder::new(&encoded[..]).read_to_end(&mut decoded).unwrap(),
+                Brotli => brotli::Decompressor::new(&encoded[..], 4096).read_to_end(&mut decoded).unwrap(),
+                Zstd => zstd::stream::read::Decoder::new(&encoded[..]).unwrap().read_to_end(&mut decoded).unwrap(),
+            };
+            assert_eq!(decoded, body);
+        }
+
+        // Без подходящей кодировки ответ не сжимается, но Vary добавляется
+        let mut resp = response("application/json", 5000);
+        assert!(compression.start(&mut resp, Negotiated { algorithm: None }).unwrap().is_none());
+        assert_eq!(resp.headers.get("vary").unwrap(), "Accept-Encoding");
+
+        // Маленькие, уже сжатые и несжимаемые типы не трогаются
+        let zstd = Negotiated { algorithm: Some(Zstd) };
+        assert!(compression.start(&mut response("application/json", 100), zstd).unwrap().is_none());
+        assert!(compression.start(&mut response("image/png", 5000), zstd).unwrap().is_none());
+        let mut encoded = response("text/html", 5000);
+        encoded.insert_header("Content-Encoding", "gzip").unwrap();
+        assert!(compression.start(&mut encoded, zstd).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_levels_by_type_and_size() {
+        let compression = compression(vec![
+            crate::config::CompressionLevels {
+                content_type: Some("application/json".to_string()),
+                min_size: Some("1m".to_string()),
+                gzip: None,
+                br: None,
+                zstd: Some(12),
+            },
+            crate::config::CompressionLevels {
+                content_type: Some("text/*".to_string()),
+                min_size: None,
+                gzip: Some(9),
+                br: None,
+                zstd: None,
+            },
+        ]);
+        assert_eq!(compression.level(Zstd, &response("application/json", 2 * 1024 * 1024)), 12);
+        assert_eq!(compression.level(Zstd, &response("application/json", 10_000)), DEFAULT_ZSTD_LEVEL);
+        assert_eq!(compression.level(Gzip, &response("text/css", 10_000)), 9);
+        assert_eq!(compression.level(Brotli, &response("text/css", 10_000)), DEFAULT_BROTLI_LEVEL as i32);
+
+        let invalid = CompressionConfig {
+            enabled: true,
+            levels: vec![crate::config::CompressionLevels {
+                content_type: None,
+                min_size: None,
+                gzip: None,
+                br: None,
+                zstd: Some(30),
+            }],
+            ..CompressionConfig::default()
+        };
+        assert!(Compression::from_config(&invalid).is_err());
+        assert!(Compression::from_config(&CompressionConfig::default()).unwrap().is_none());
+    }
+}
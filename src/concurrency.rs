@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::{Config, UpstreamBlock};
+use crate::metrics::{UPSTREAM_CONCURRENCY_REJECTIONS, UPSTREAM_QUEUE_LENGTH, UPSTREAM_QUEUE_WAIT};
+
+/// Слот upstream, занятый запросом; освобождается при drop (конец запроса)
+#[derive(Debug)]
+pub struct UpstreamPermit {
+    _permit: OwnedSemaphorePermit,
+    waited: Duration,
+}
+
+impl UpstreamPermit {
+    /// Сколько запрос простоял в очереди (ноль, если слот был свободен)
+    pub fn waited(&self) -> Duration {
+        self.waited
+    }
+}
+
+/// Причина отказа в слоте upstream (503 UPSTREAM_BUSY)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rejection {
+    /// Очередь заполнена или не настроена
+    QueueFull,
+    /// Слот не освободился за queue timeout
+    Timeout,
+}
+
+impl Rejection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rejection::QueueFull => "queue_full",
+            Rejection::Timeout => "timeout",
+        }
+    }
+}
+
+/// Ограничение одновременных запросов к одному upstream с очередью ожидания.
+/// Семафор tokio справедливый: освободившийся слот получает самый давний из ожидающих
+#[derive(Debug)]
+pub struct UpstreamLimiter {
+    upstream: String,
+    semaphore: Arc<Semaphore>,
+    queue_size: usize,
+    queue_timeout: Duration,
+    waiting: AtomicUsize,
+}
+
+/// Место в очереди; освобождается и при отмене ожидания (клиент отключился)
+struct QueueSlot<'a>(&'a UpstreamLimiter);
+
+impl Drop for QueueSlot<'_> {
+    fn drop(&mut self) {
+        let waiting = self.0.waiting.fetch_sub(1, Ordering::SeqCst) - 1;
+        UPSTREAM_QUEUE_LENGTH.with_label_values(&[&self.0.upstream]).set(waiting as i64);
+    }
+}
+
+impl UpstreamLimiter {
+    pub fn new(upstream: &str, max_conns: usize, queue_size: usize, queue_timeout: Duration) -> Self {
+        Self {
+            upstream: upstream.to_string(),
+            semaphore: Arc::new(Semaphore::new(max_conns)),
+            queue_size,
+            queue_timeout,
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    fn from_block(block: &UpstreamBlock) -> Option<Self> {
+        let max_conns = block.max_conns?;
+        let (queue_size, queue_timeout) = block
+            .queue
+            .map(|queue| (queue.size, queue.timeout))
+            .unwrap_or((0, Duration::ZERO));
+        Some(Self::new(&block.name, max_conns, queue_size, queue_timeout))
+    }
+
+    /// Занимает слот: сразу, если есть свободный, иначе ждет в очереди не дольше queue timeout
+    pub async fn acquire(&self) -> Result<UpstreamPermit, Rejection> {
+        let result = self.wait().await;
+        if let Err(rejection) = result {
+            UPSTREAM_CONCURRENCY_REJECTIONS
+                .with_label_values(&[&self.upstream, rejection.as_str()])
+                .inc();
+        }
+        result
+    }
+
+    async fn wait(&self) -> Result<UpstreamPermit, Rejection> {
+        if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+            return Ok(UpstreamPermit { _permit: permit, waited: Duration::ZERO });
+        }
+
+        let waiting = self.waiting.fetch_add(1, Ordering::SeqCst) + 1;
+        let _slot = QueueSlot(self);
+        if waiting > self.queue_size {
+            return Err(Rejection::QueueFull);
+        }
+        UPSTREAM_QUEUE_LENGTH.with_label_values(&[&self.upstream]).set(waiting as i64);
+
+        let start = Instant::now();
+        let result = tokio::time::timeout(self.queue_timeout, self.semaphore.clone().acquire_owned()).await;
+        let waited = start.elapsed();
+        UPSTREAM_QUEUE_WAIT.with_label_values(&[&self.upstream]).observe(waited.as_secs_f64());
+        match result {
+            Ok(Ok(permit)) => Ok(UpstreamPermit { _permit: permit, waited }),
+            _ => Err(Rejection::Timeout),
+        }
+    }
+
+    /// Retry-After для отказа: время ожидания в очереди, округленное вверх до секунд
+    pub fn retry_after(&self) -> u64 {
+        self.queue_timeout.as_secs_f64().ceil().max(1.0) as u64
+    }
+
+    /// Запросов в очереди
+    pub fn waiting(&self) -> usize {
+        self.waiting.load(Ordering::SeqCst)
+    }
+}
+
+/// Лимиты upstream блоков с max_conns
+#[derive(Debug, Default)]
+pub struct UpstreamLimits {
+    limiters: HashMap<String, UpstreamLimiter>,
+}
+
+impl UpstreamLimits {
+    pub fn from_config(config: &Config) -> Self {
+        let limiters = config
+            .nginx_config
+            .iter()
+            .flat_map(|nginx| nginx.upstreams.values())
+            .filter_map(|block| UpstreamLimiter::from_block(block).map(|limiter| (block.name.clone(), limiter)))
+            .collect();
+        Self { limiters }
+    }
+
+    pub fn get(&self, upstream: &str) -> Option<&UpstreamLimiter> {
+        self.limiters.get(upstream)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.limiters.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_response::ErrorCode;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn test_request_over_limit_waits_then_times_out() {
+        let limiter = UpstreamLimiter::new("legacy", 2, 1, Duration::from_millis(100));
+        let first = limiter.acquire().await.unwrap();
+        let _second = limiter.acquire().await.unwrap();
+
+        // Третий запрос ждет в очереди и получает отказ по таймауту
+        let start = Instant::now();
+        assert_eq!(limiter.acquire().await.unwrap_err(), Rejection::Timeout);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(limiter.waiting(), 0);
+        assert_eq!(ErrorCode::UpstreamBusy.status(), 503);
+
+        // Освободившийся слот достается ожидающему запросу
+        let waiter = async { limiter.acquire().await };
+        let release = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            drop(first);
+        };
+        let (permit, _) = tokio::join!(waiter, release);
+        assert!(permit.unwrap().waited() >= Duration::from_millis(20));
+        assert_eq!(limiter.retry_after(), 1);
+
+        let rejected = UPSTREAM_CONCURRENCY_REJECTIONS.with_label_values(&["legacy", "timeout"]).get();
+        assert!(rejected >= 1);
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_rejects_immediately() {
+        let limiter = Arc::new(UpstreamLimiter::new("fragile", 1, 1, Duration::from_secs(5)));
+        let _busy = limiter.acquire().await.unwrap();
+
+        let queued = {
+            let limiter = limiter.clone();
+            tokio::spawn(async move { limiter.acquire().await.is_ok() })
+        };
+        while limiter.waiting() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let start = Instant::now();
+        assert_eq!(limiter.acquire().await.unwrap_err(), Rejection::QueueFull);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        queued.abort();
+        let _ = queued.await;
+        assert_eq!(limiter.waiting(), 0);
+
+        // Без очереди отказ сразу
+        let no_queue = UpstreamLimiter::new("strict", 1, 0, Duration::ZERO);
+        let _busy = no_queue.acquire().await.unwrap();
+        assert_eq!(no_queue.acquire().await.unwrap_err(), Rejection::QueueFull);
+    }
+
+    #[tokio::test]
+    async fn test_queued_requests_served_in_arrival_order() {
+        let limiter = Arc::new(UpstreamLimiter::new("slow", 1, 3, Duration::from_secs(5)));
+        let busy = limiter.acquire().await.unwrap();
+        let served = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let mut waiters = Vec::new();
+        for id in 1..=3 {
+            let (limiter_ref, served) = (limiter.clone(), served.clone());
+            waiters.push(tokio::spawn(async move {
+                let permit = limiter_ref.acquire().await.unwrap();
+                served.lock().unwrap().push(id);
+                // Медленный бэкенд держит слот
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                drop(permit);
+            }));
+            while limiter.waiting() < id {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        // Запрос, пришедший после очереди, не обгоняет ожидающих
+        assert_eq!(limiter.acquire().await.unwrap_err(), Rejection::QueueFull);
+
+        drop(busy);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(*served.lock().unwrap(), vec![1, 2, 3]);
+        assert_eq!(limiter.waiting(), 0);
+        assert!(UPSTREAM_QUEUE_WAIT.with_label_values(&["slow"]).get_sample_count() >= 3);
+    }
+}
@@ -0,0 +1,125 @@
+use serde_yaml::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{self, ProxyError};
+
+/// Каталог фрагментов рядом с основным файлом (/etc/adq-pingora/conf.d)
+pub const FRAGMENTS_DIR: &str = "conf.d";
+
+/// Файлы `*.yaml` и `*.yml` каталога в порядке имен; каталога нет - фрагментов нет
+pub fn fragment_files(dir: &Path) -> error::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(ProxyError::io(dir)(e)),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(ProxyError::io(dir))?.path();
+        let yaml = path.extension().is_some_and(|ext| ext == "yaml" || ext == "yml");
+        if yaml && path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Накладывает фрагмент на конфигурацию: секции (mapping) объединяются по ключам
+/// рекурсивно, скаляры и списки фрагмента заменяют прежние значения целиком
+pub fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Основной файл с наложенными фрагментами: сначала `conf.d` рядом с основным файлом,
+/// затем каталоги `dirs` в указанном порядке. Возвращает YAML и пути примененных фрагментов
+pub fn load_merged(path: &Path, dirs: &[PathBuf]) -> error::Result<(Value, Vec<String>)> {
+    let content = fs::read_to_string(path).map_err(ProxyError::io(path))?;
+    let mut merged: Value = serde_yaml::from_str(&content)?;
+
+    let default_dir = path.parent().unwrap_or(Path::new(".")).join(FRAGMENTS_DIR);
+    let mut applied = Vec::new();
+    for dir in std::iter::once(&default_dir).chain(dirs) {
+        for file in fragment_files(dir)? {
+            let content = fs::read_to_string(&file).map_err(ProxyError::io(&file))?;
+            let fragment: Value = serde_yaml::from_str(&content)
+                .map_err(|e| format!("{}: invalid YAML: {}", file.display(), e))?;
+            match fragment {
+                // Пустой файл или только комментарии
+                Value::Null => {}
+                Value::Mapping(_) => merge(&mut merged, fragment),
+                _ => return Err(format!("{}: fragment must be a YAML mapping", file.display()).into()),
+            }
+            applied.push(file.display().to_string());
+        }
+    }
+    Ok((merged, applied))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{version, Config};
+
+    #[test]
+    fn test_later_fragment_overrides_field() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("proxy.yaml");
+        fs::write(&main, serde_yaml::to_string(&Config::default()).unwrap()).unwrap();
+        fs::create_dir(dir.path().join(FRAGMENTS_DIR)).unwrap();
+        fs::write(
+            dir.path().join("conf.d/10-cache.yaml"),
+            "cache:\n  default_ttl: 600\n  rules:\n    - path: \"/static/*\"\n      ttl: 3600\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("conf.d/20-cache.yaml"), "cache:\n  default_ttl: 900\n").unwrap();
+        fs::write(dir.path().join("conf.d/README"), "not a fragment").unwrap();
+
+        let extra = tempfile::tempdir().unwrap();
+        fs::write(extra.path().join("00-global.yml"), "global:\n  max_retries: 5\n").unwrap();
+
+        let (merged, applied) = load_merged(&main, &[extra.path().to_path_buf()]).unwrap();
+        let names: Vec<&str> = applied.iter().map(|path| path.rsplit('/').next().unwrap()).collect();
+        assert_eq!(names, vec!["10-cache.yaml", "20-cache.yaml", "00-global.yml"]);
+
+        let config = version::parse_config_value(merged).unwrap();
+        // 20-cache.yaml переопределяет default_ttl, остальные поля секции сохраняются
+        assert_eq!(config.cache.default_ttl, 900);
+        assert_eq!(config.cache.rules.len(), 1);
+        assert_eq!(config.cache.enabled, Config::default().cache.enabled);
+        assert_eq!(config.global.max_retries, 5);
+        assert_eq!(config.global.default_timeout, Config::default().global.default_timeout);
+    }
+
+    #[test]
+    fn test_merged_result_is_validated() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("proxy.yaml");
+        let strict = serde_yaml::to_string(&Config::default()).unwrap().replacen("strict: false", "strict: true", 1);
+        fs::write(&main, strict).unwrap();
+        fs::create_dir(dir.path().join(FRAGMENTS_DIR)).unwrap();
+
+        // Опечатка во фрагменте отклоняется так же, как в основном файле
+        fs::write(dir.path().join("conf.d/cache.yaml"), "cache:\n  default_tll: 900\n").unwrap();
+        let (merged, _) = load_merged(&main, &[]).unwrap();
+        let e = version::parse_config_value(merged).unwrap_err();
+        assert!(e.to_string().contains("cache.default_tll"), "{}", e);
+
+        fs::write(dir.path().join("conf.d/cache.yaml"), "- cache\n").unwrap();
+        let e = load_merged(&main, &[]).unwrap_err();
+        assert!(e.to_string().ends_with("cache.yaml: fragment must be a YAML mapping"), "{}", e);
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use super::nginx_parser::{LocationBlock, ServerBlock};
+
+/// Индекс server блоков по server_name, строится при загрузке конфигурации и в `NginxConfig::reindex`.
+/// Точные имена - HashMap (при повторе имени выигрывает первый server, как при
+/// линейном поиске), `*.example.com` - по суффиксу `.example.com`, самый длинный суффикс первым
+#[derive(Debug, Clone, Default)]
+pub struct ServerIndex {
+    exact: HashMap<String, usize>,
+    wildcard: HashMap<String, usize>,
+}
+
+impl ServerIndex {
+    pub fn build(servers: &[ServerBlock]) -> Self {
+        let mut index = Self::default();
+        for (position, server) in servers.iter().enumerate() {
+            for name in &server.server_names {
+                let names = match name.strip_prefix('*').filter(|suffix| suffix.starts_with('.')) {
+                    Some(suffix) => index.wildcard.entry(suffix.to_string()),
+                    None => index.exact.entry(name.clone()),
+                };
+                names.or_insert(position);
+            }
+        }
+        index
+    }
+
+    /// Позиция server блока для host без порта
+    pub fn find(&self, host: &str) -> Option<usize> {
+        if let Some(position) = self.exact.get(host) {
+            return Some(*position);
+        }
+        // От первой точки к последней: сначала самый длинный суффикс
+        host.match_indices('.')
+            .find_map(|(dot, _)| self.wildcard.get(&host[dot..]))
+            .copied()
+    }
+}
+
+/// Индекс location блоков server: точные пути и префиксы location с `/` на конце.
+/// Префиксы проверяются от самого длинного, поиск не зависит от числа location
+#[derive(Debug, Clone, Default)]
+pub struct LocationIndex {
+    exact: HashMap<String, usize>,
+    prefixes: HashMap<String, usize>,
+    /// Длины префиксов без повторов, по убыванию
+    prefix_lengths: Vec<usize>,
+}
+
+impl LocationIndex {
+    pub fn build(locations: &[LocationBlock]) -> Self {
+        let mut index = Self::default();
+        for (position, location) in locations.iter().enumerate() {
+            index.exact.entry(location.path.clone()).or_insert(position);
+            // Префикс `/` (пустой) никогда не длиннее найденного совпадения
+            let prefix = location.path.trim_end_matches('/');
+            if location.path.ends_with('/') && !prefix.is_empty() {
+                index.prefixes.entry(prefix.to_string()).or_insert(position);
+            }
+        }
+        index.prefix_lengths = index.prefixes.keys().map(String::len).collect();
+        index.prefix_lengths.sort_unstable_by(|a, b| b.cmp(a));
+        index.prefix_lengths.dedup();
+        index
+    }
+
+    /// Позиция location: точное совпадение пути, иначе самый длинный префикс
+    pub fn find(&self, path: &str) -> Option<usize> {
+        if let Some(position) = self.exact.get(path) {
+            return Some(*position);
+        }
+        self.prefix_lengths
+            .iter()
+            .find_map(|len| path.get(..*len).and_then(|prefix| self.prefixes.get(prefix)))
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NginxConfig;
+    use std::time::Instant;
+
+    fn config() -> NginxConfig {
+        NginxConfig::parse_config_content(r#"
+            server {
+                listen 80;
+                server_name api.example.com www.example.com;
+                location /api/v1/ { proxy_pass v1; }
+                location /api/ { proxy_pass api; }
+                location /health { proxy_pass health; }
+                location / { proxy_pass web; }
+            }
+            server {
+                listen 80;
+                server_name *.example.com;
+                location /tenant/ { proxy_pass tenants; }
+            }
+            server {
+                listen 80;
+                server_name *.eu.example.com api.example.com;
+                location /eu/ { proxy_pass eu; }
+            }
+        "#).unwrap()
+    }
+
+    #[test]
+    fn test_find_server() {
+        let config = config();
+        let name = |host: &str| config.find_server(host).map(|server| server.server_names[0].as_str());
+
+        assert_eq!(name("api.example.com"), Some("api.example.com"));
+        assert_eq!(name("www.example.com:8080"), Some("api.example.com"));
+        assert_eq!(name("acme.example.com"), Some("*.example.com"));
+        assert_eq!(name("acme.eu.example.com"), Some("*.eu.example.com"));
+        assert_eq!(name("example.com"), None);
+        assert_eq!(name("example.org"), None);
+    }
+
+    #[test]
+    fn test_find_location() {
+        let config = config();
+        let server = config.find_server("api.example.com").unwrap();
+        let upstream = |path: &str| {
+            config.find_location(server, path).and_then(|location| location.proxy_pass.as_deref())
+        };
+
+        assert_eq!(upstream("/api/v1/users"), Some("v1"));
+        assert_eq!(upstream("/api/users"), Some("api"));
+        // Префикс без завершающего `/`, как раньше при линейном поиске
+        assert_eq!(upstream("/apix"), Some("api"));
+        assert_eq!(upstream("/health"), Some("health"));
+        assert_eq!(upstream("/health/live"), None);
+        assert_eq!(upstream("/"), Some("web"));
+        assert_eq!(upstream("/about"), None);
+        assert_eq!(upstream("/api/v1/ü"), Some("v1"));
+    }
+
+    #[test]
+    fn test_reindex_after_changes() {
+        let mut config = config();
+        // Замена имен без изменения числа server блоков
+        config.servers[1].server_names = vec!["late.example.org".to_string()];
+        let mut extra = config.servers[0].clone();
+        extra.server_names = vec!["extra.example.org".to_string()];
+        let mut location = extra.locations[0].clone();
+        location.path = "/late/".to_string();
+        extra.locations[3] = location;
+        config.servers.push(extra);
+        config.reindex();
+
+        assert_eq!(config.find_server("late.example.org").unwrap().server_names[0], "late.example.org");
+        assert!(config.find_server("acme.example.com").is_none());
+        let server = config.find_server("extra.example.org").unwrap();
+        assert_eq!(config.find_location(server, "/late/x").unwrap().path, "/late/");
+        assert!(config.find_location(server, "/").is_none());
+    }
+
+    #[test]
+    fn test_lookup_with_5k_servers() {
+        let template = config().servers[0].clone();
+        let servers: Vec<ServerBlock> = (0..5000)
+            .map(|tenant| {
+                let mut server = template.clone();
+                server.server_names = vec![format!("tenant-{}.example.com", tenant), format!("*.tenant-{}.example.net", tenant)];
+                server
+            })
+            .collect();
+        let index = ServerIndex::build(&servers);
+        let locations = LocationIndex::build(&template.locations);
+
+        let hosts: Vec<String> = (0..5000).map(|tenant| format!("tenant-{}.example.com", (tenant * 7919) % 5000)).collect();
+        let rounds = 20;
+        let start = Instant::now();
+        for _ in 0..rounds {
+            for host in &hosts {
+                let server = index.find(host).unwrap();
+                assert!(locations.find("/api/v1/campaigns/42").is_some());
+                std::hint::black_box(server);
+            }
+        }
+        let per_lookup = start.elapsed() / (rounds * hosts.len()) as u32;
+        assert_eq!(index.find("app.tenant-4999.example.net"), Some(4999));
+
+        // Без оптимизаций сборки (debug) порог мягче
+        let limit = if cfg!(debug_assertions) { 20_000 } else { 1_000 };
+        assert!(per_lookup.as_nanos() < limit, "server + location lookup took {:?}", per_lookup);
+    }
+}
@@ -1,23 +1,110 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use crate::error::{self, ProxyError};
+
+pub mod fragments;
+pub mod lookup;
 pub mod nginx_parser;
+pub mod proxy_pass;
+pub mod version;
 pub use nginx_parser::*;
+pub use proxy_pass::{ProxyPassAddr, ProxyPassUrl};
+pub use version::{SchemaError, SchemaInfo, CONFIG_VERSION};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
+    /// Версия схемы; старые версии мигрируются при загрузке (config/version.rs)
     pub version: u32,
+    /// Неизвестные поля - ошибка загрузки, а не предупреждение
+    #[serde(default)]
+    pub strict: bool,
     pub global: GlobalConfig,
     pub security: SecurityConfig,
     pub cache: CacheConfig,
     pub logging: LoggingConfig,
     pub ip_filter: IpFilterConfig,
+    /// Блокировка клиентов по User-Agent
+    #[serde(default)]
+    pub ua_filter: UaFilterConfig,
+    /// Ответы по заголовкам запроса (например, 426 для устаревших версий приложения)
+    #[serde(default)]
+    pub header_rules: Vec<HeaderRuleConfig>,
     pub circuit_breaker: CircuitBreakerConfig,
+    /// Адреса сервисов с прямой маршрутизацией
+    #[serde(default)]
+    pub services: ServicesConfig,
+    /// Прежние встроенные правила маршрутизации
+    #[serde(default)]
+    pub compat: CompatConfig,
+    /// Дополнительные заголовки ответа (Server-Timing, Alt-Svc)
+    #[serde(default)]
+    pub response_headers: ResponseHeadersConfig,
+    /// Стадии обработки запроса в request_filter
+    #[serde(default)]
+    pub pipeline: PipelineConfig,
+    /// Имена заголовков идентификатора запроса и проксирования
+    #[serde(default)]
+    pub proxy_headers: ProxyHeadersConfig,
+    /// Определение gRPC-Web запросов для активации gRPC-Web bridge
+    #[serde(default)]
+    pub grpc_web: GrpcWebConfig,
+    /// Особенности бэкендов (X-Forwarded-Proto, CORS, gRPC-Web) по имени профиля
+    #[serde(default = "default_backend_profiles")]
+    pub backend_profiles: HashMap<String, BackendProfileConfig>,
+    /// Дедупликация запросов по Idempotency-Key (включается директивой idempotency в location)
+    #[serde(default)]
+    pub idempotency: IdempotencyConfig,
+    /// Ограничения WASM фильтров запросов (директива wasm_filter в location)
+    #[serde(default)]
+    pub wasm_filters: WasmFiltersConfig,
+    /// Проверка клиентов proof-of-work страницей (директива challenge_mode в location)
+    #[serde(default)]
+    pub challenge: ChallengeConfig,
+    /// События смены состояния бэкендов (журнал и webhook)
+    #[serde(default)]
+    pub health_events: HealthEventsConfig,
+    /// Последние запросы с 5xx и ошибками соединения для /_admin/flight_recorder
+    #[serde(default)]
+    pub flight_recorder: FlightRecorderConfig,
+    /// Таймауты и повторы запросов прокси к внешним сервисам (webhook событий)
+    #[serde(default)]
+    pub http_client: HttpClientConfig,
+    /// Маскирование полей JSON тел в location с `redact on`
+    #[serde(default)]
+    pub redaction: RedactionConfig,
+    /// Сжатие ответов на лету (gzip, br, zstd)
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    /// Переводы сообщений JSON ошибок по Accept-Language
+    #[serde(default)]
+    pub error_messages: ErrorMessagesConfig,
+    /// Страница для запросов, не направленных ни в один сервис
+    #[serde(default)]
+    pub default_page: DefaultPageConfig,
+    /// Пороги числа server, location и upstream блоков nginx конфигурации
+    #[serde(default)]
+    pub config_limits: ConfigLimitsConfig,
+    /// Эксперименты: группа запроса передается upstream в X-Experiment-<имя>
+    #[serde(default)]
+    pub experiments: Vec<ExperimentConfig>,
+    /// Именованные окна времени (день недели и интервал), на которые ссылаются
+    /// правила заголовков, maintenance и rate_limit ... schedule=<имя>
+    #[serde(default)]
+    pub schedules: Vec<ScheduleConfig>,
+    /// Ответ 503 о техническом обслуживании в окна расписаний
+    #[serde(default)]
+    pub maintenance: Vec<MaintenanceConfig>,
     // Nginx-style конфигурация загружается отдельно
     #[serde(skip)]
     pub nginx_config: Option<NginxConfig>,
+    /// Исходная версия схемы и выполненные миграции
+    #[serde(skip)]
+    pub schema: SchemaInfo,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -25,6 +112,133 @@ pub struct GlobalConfig {
     pub default_timeout: u64,
     pub max_retries: u32,
     pub health_check_interval: u64,
+    /// Таймаут соединения с upstream по умолчанию (секунды)
+    #[serde(default)]
+    pub connect_timeout: Option<u64>,
+    /// Таймаут ожидания первого байта ответа upstream по умолчанию (секунды)
+    #[serde(default)]
+    pub first_byte_timeout: Option<u64>,
+    /// Общее время обращения к upstream по умолчанию (секунды), после него клиент получает 504
+    #[serde(default)]
+    pub request_timeout: Option<u64>,
+    /// Таймаут записи запроса в upstream по умолчанию (секунды)
+    #[serde(default)]
+    pub write_timeout: Option<u64>,
+    /// TCP keepalive соединений с upstream (so_keepalive)
+    #[serde(default)]
+    pub tcp_keepalive: Option<TcpKeepaliveConfig>,
+    /// Максимальное время draining удаленных при reload бэкендов (секунды)
+    #[serde(default = "default_drain_timeout")]
+    pub drain_timeout: u64,
+    /// Время ожидания следующего запроса в keep-alive соединении клиента (секунды)
+    #[serde(default = "default_keepalive_timeout")]
+    pub keepalive_timeout: u64,
+    /// Максимум запросов в одном соединении клиента (0 - без ограничения)
+    #[serde(default = "default_keepalive_requests")]
+    pub keepalive_requests: u32,
+    /// Общий лимит памяти под буферизованные тела ответов (proxy_buffering), формат nginx: 256m
+    #[serde(default = "default_buffered_body_budget")]
+    pub buffered_body_budget: String,
+    /// Максимальный размер одного заголовка запроса (имя и значение), формат nginx: 8k
+    #[serde(default = "default_max_header_size")]
+    pub max_header_size: String,
+    /// HTTP порт, если в nginx конфигурации нет директив listen
+    #[serde(default = "default_http_port")]
+    pub default_http_port: u16,
+    /// HTTPS порт, если в nginx конфигурации нет директив listen
+    #[serde(default = "default_https_port")]
+    pub default_https_port: u16,
+    /// Принимать IPv4 клиентов на слушателях без адреса (listen 80 и порты по умолчанию)
+    /// и на явных IPv4 адресах
+    #[serde(default = "default_true")]
+    pub listen_ipv4: bool,
+    /// Принимать IPv6 клиентов; вместе с listen_ipv4 слушатели без адреса становятся dual-stack ([::], ipv6only=off)
+    #[serde(default = "default_true")]
+    pub listen_ipv6: bool,
+    /// Upstream для запросов, чей upstream отсутствует среди балансировщиков
+    /// (None - такие запросы получают 502)
+    #[serde(default)]
+    pub default_upstream: Option<String>,
+    /// Соединений с каждым здоровым бэкендом, открываемых при старте (0 - без прогрева)
+    #[serde(default)]
+    pub upstream_warmup_connections: usize,
+    /// Отвечать 426 Upgrade Required на запросы HTTP/1.0
+    #[serde(default)]
+    pub reject_http10: bool,
+}
+
+fn default_drain_timeout() -> u64 {
+    30
+}
+
+fn default_keepalive_timeout() -> u64 {
+    75
+}
+
+fn default_keepalive_requests() -> u32 {
+    1000
+}
+
+fn default_buffered_body_budget() -> String {
+    "256m".to_string()
+}
+
+fn default_max_header_size() -> String {
+    "8k".to_string()
+}
+
+fn default_http_port() -> u16 {
+    9080
+}
+
+fn default_https_port() -> u16 {
+    9443
+}
+
+/// Адрес TCP listener прокси
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ListenAddr {
+    pub addr: SocketAddr,
+    /// IPV6_V6ONLY для IPv6 адреса; false у [::] - dual-stack сокет, принимающий и IPv4
+    pub ipv6_only: bool,
+}
+
+/// Параметры TCP keepalive соединений с upstream, как `so_keepalive=idle:interval:count` в nginx
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TcpKeepaliveConfig {
+    /// Простой соединения до первой проверки (секунды)
+    pub idle: u64,
+    /// Интервал между проверками (секунды)
+    pub interval: u64,
+    /// Число неотвеченных проверок до закрытия соединения
+    pub count: usize,
+}
+
+impl TcpKeepaliveConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.idle == 0 || self.interval == 0 || self.count == 0 {
+            return Err("global.tcp_keepalive idle, interval and count must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Итоговые таймауты upstream для конкретного запроса
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpstreamTimeouts {
+    pub connect: Option<Duration>,
+    pub read: Duration,
+    pub first_byte: Duration,
+    pub write: Option<Duration>,
+    /// Предельное время обращения к upstream с учетом повторов (соединение, запрос и ответ)
+    pub request: Option<Duration>,
+}
+
+impl UpstreamTimeouts {
+    /// Момент истечения request timeout для обращения к upstream, начатого в `start`
+    pub fn deadline(&self, start: std::time::Instant) -> Option<std::time::Instant> {
+        self.request.map(|request| start + request)
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -66,12 +280,955 @@ pub struct SecurityHeaders {
     pub server: String,
 }
 
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ResponseHeadersConfig {
+    /// Добавлять Server-Timing с длительностью запроса к upstream и общей длительностью
+    #[serde(default)]
+    pub server_timing: bool,
+    /// Значение Alt-Svc (например, `h3=":443"; ma=86400`)
+    #[serde(default)]
+    pub alt_svc: Option<String>,
+    /// Имя заголовка с адресом бэкенда, обработавшего запрос (например, X-Upstream-Addr)
+    #[serde(default)]
+    pub upstream_addr: Option<String>,
+}
+
+impl ResponseHeadersConfig {
+    /// Проверяет имя заголовка upstream_addr
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(name) = &self.upstream_addr {
+            if http::HeaderName::from_bytes(name.as_bytes()).is_err() {
+                return Err(format!("invalid header name '{}' in response_headers.upstream_addr", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Имена заголовков идентификатора запроса и X-Forwarded-* заголовков
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProxyHeadersConfig {
+    /// Идентификатор запроса: принимается от клиента, передается upstream и в ответе
+    #[serde(default = "default_request_id_header")]
+    pub request_id: String,
+    #[serde(default = "default_real_ip_header")]
+    pub real_ip: String,
+    #[serde(default = "default_forwarded_for_header")]
+    pub forwarded_for: String,
+    #[serde(default = "default_forwarded_proto_header")]
+    pub forwarded_proto: String,
+    #[serde(default = "default_forwarded_host_header")]
+    pub forwarded_host: String,
+    #[serde(default = "default_forwarded_port_header")]
+    pub forwarded_port: String,
+    /// Подсети прокси и балансировщиков, которым доверяется X-Forwarded-Proto (CIDR)
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    /// Заголовки с адресом клиента от доверенных прокси, по приоритету; пусто - адрес соединения
+    #[serde(default)]
+    pub real_ip_from: Vec<RealIpSource>,
+    /// Узел `by` в элементе Forwarded прокси: IP, `unknown` или `_идентификатор`
+    #[serde(default)]
+    pub forwarded_by: Option<String>,
+}
+
+/// Источник адреса клиента за доверенными прокси
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RealIpSource {
+    /// `for` элементов Forwarded (RFC 7239)
+    Forwarded,
+    /// Заголовок proxy_headers.forwarded_for
+    XForwardedFor,
+}
+
+fn default_request_id_header() -> String {
+    "X-Request-Id".to_string()
+}
+
+fn default_real_ip_header() -> String {
+    "X-Real-IP".to_string()
+}
+
+fn default_forwarded_for_header() -> String {
+    "X-Forwarded-For".to_string()
+}
+
+fn default_forwarded_proto_header() -> String {
+    "X-Forwarded-Proto".to_string()
+}
+
+fn default_forwarded_host_header() -> String {
+    "X-Forwarded-Host".to_string()
+}
+
+fn default_forwarded_port_header() -> String {
+    "X-Forwarded-Port".to_string()
+}
+
+impl Default for ProxyHeadersConfig {
+    fn default() -> Self {
+        Self {
+            request_id: default_request_id_header(),
+            real_ip: default_real_ip_header(),
+            forwarded_for: default_forwarded_for_header(),
+            forwarded_proto: default_forwarded_proto_header(),
+            forwarded_host: default_forwarded_host_header(),
+            forwarded_port: default_forwarded_port_header(),
+            trusted_proxies: Vec::new(),
+            real_ip_from: Vec::new(),
+            forwarded_by: None,
+        }
+    }
+}
+
+impl ProxyHeadersConfig {
+    /// Проверяет, что все имена - корректные имена HTTP заголовков
+    pub fn validate(&self) -> Result<(), String> {
+        let names = [
+            &self.request_id,
+            &self.real_ip,
+            &self.forwarded_for,
+            &self.forwarded_proto,
+            &self.forwarded_host,
+            &self.forwarded_port,
+        ];
+        for name in names {
+            if http::HeaderName::from_bytes(name.as_bytes()).is_err() {
+                return Err(format!("invalid header name '{}' in proxy_headers", name));
+            }
+        }
+        if let Some(by) = &self.forwarded_by {
+            if !crate::forwarded::is_valid_node(by) {
+                return Err(format!("invalid proxy_headers.forwarded_by '{}': expected an IP address, unknown or _identifier", by));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Условие активации gRPC-Web bridge: Content-Type запроса и, при необходимости, путь
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GrpcWebConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Префиксы Content-Type gRPC-Web запросов (без учета регистра)
+    #[serde(default = "default_grpc_web_content_types")]
+    pub content_types: Vec<String>,
+    /// Префиксы путей, для которых включается bridge (пусто - для всех)
+    #[serde(default)]
+    pub paths: Vec<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_grpc_web_content_types() -> Vec<String> {
+    vec!["application/grpc-web".to_string()]
+}
+
+impl Default for GrpcWebConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            content_types: default_grpc_web_content_types(),
+            paths: Vec::new(),
+        }
+    }
+}
+
+/// Профиль бэкенда: как прокси передает запросы upstream и обрабатывает его ответы.
+/// Привязывается к location директивой `backend_profile` или к upstream через `upstreams`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct BackendProfileConfig {
+    /// Имена upstream (proxy_pass или тип сервиса), к которым применяется профиль
+    #[serde(default)]
+    pub upstreams: Vec<String>,
+    /// Всегда передавать X-Forwarded-Proto: https (бэкенд работает за HTTPS прокси)
+    #[serde(default)]
+    pub force_https_proto: bool,
+    /// Передавать X-Forwarded-Host и X-Forwarded-Port для генерации URL бэкендом
+    #[serde(default)]
+    pub forwarded_host: bool,
+    /// Бэкенд сам выставляет CORS заголовки, прокси их не добавляет
+    #[serde(default)]
+    pub manages_own_cors: bool,
+    /// Разрешен ли gRPC-Web bridge для запросов к бэкенду
+    #[serde(default = "default_true")]
+    pub grpc_web: bool,
+}
+
+/// Профиль Zitadel, который раньше был зашит в код прокси
+fn default_backend_profiles() -> HashMap<String, BackendProfileConfig> {
+    let zitadel = BackendProfileConfig {
+        upstreams: vec!["zitadel_auth".to_string()],
+        force_https_proto: true,
+        forwarded_host: true,
+        manages_own_cors: true,
+        grpc_web: true,
+    };
+    HashMap::from([("zitadel".to_string(), zitadel)])
+}
+
+/// Общие настройки дедупликации запросов по ключу идемпотентности
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct IdempotencyConfig {
+    /// Заголовок с ключом идемпотентности
+    #[serde(default = "default_idempotency_header")]
+    pub header: String,
+    /// Общий лимит памяти под сохраненные ответы, формат nginx: 64m
+    #[serde(default = "default_idempotency_budget")]
+    pub budget: String,
+    /// Что делать с повтором, пока исходный запрос выполняется: reject (409) или wait
+    #[serde(default = "default_idempotency_in_flight")]
+    pub in_flight: String,
+    /// Максимальное ожидание исходного запроса в режиме wait (секунды)
+    #[serde(default = "default_idempotency_wait_timeout")]
+    pub wait_timeout: u64,
+}
+
+fn default_idempotency_header() -> String {
+    "Idempotency-Key".to_string()
+}
+
+fn default_idempotency_budget() -> String {
+    "64m".to_string()
+}
+
+fn default_idempotency_in_flight() -> String {
+    "reject".to_string()
+}
+
+fn default_idempotency_wait_timeout() -> u64 {
+    10
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            header: default_idempotency_header(),
+            budget: default_idempotency_budget(),
+            in_flight: default_idempotency_in_flight(),
+            wait_timeout: default_idempotency_wait_timeout(),
+        }
+    }
+}
+
+impl IdempotencyConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if http::HeaderName::from_bytes(self.header.as_bytes()).is_err() {
+            return Err(format!("invalid idempotency header name '{}'", self.header));
+        }
+        if parse_size(&self.budget).is_none() {
+            return Err(format!("invalid idempotency budget '{}'", self.budget));
+        }
+        if self.in_flight != "reject" && self.in_flight != "wait" {
+            return Err(format!("invalid idempotency in_flight mode '{}', expected reject or wait", self.in_flight));
+        }
+        Ok(())
+    }
+}
+
+/// Ограничения выполнения WASM фильтров запросов
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WasmFiltersConfig {
+    /// Максимальное время работы фильтра на один запрос (миллисекунды)
+    #[serde(default = "default_wasm_filters_timeout")]
+    pub timeout_ms: u64,
+    /// Максимальный размер линейной памяти экземпляра фильтра, формат nginx: 16m
+    #[serde(default = "default_wasm_filters_memory_limit")]
+    pub memory_limit: String,
+}
+
+fn default_wasm_filters_timeout() -> u64 {
+    5
+}
+
+fn default_wasm_filters_memory_limit() -> String {
+    "16m".to_string()
+}
+
+impl Default for WasmFiltersConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: default_wasm_filters_timeout(),
+            memory_limit: default_wasm_filters_memory_limit(),
+        }
+    }
+}
+
+impl WasmFiltersConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.timeout_ms == 0 {
+            return Err("wasm_filters.timeout_ms must be greater than 0".to_string());
+        }
+        if parse_size(&self.memory_limit).is_none() {
+            return Err(format!("invalid wasm_filters.memory_limit '{}'", self.memory_limit));
+        }
+        Ok(())
+    }
+}
+
+/// Подписанная cookie проверки клиента для location с `challenge_mode on`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChallengeConfig {
+    /// Ключ HMAC подписи cookie; без него генерируется случайный при старте процесса
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Время жизни пройденной проверки (секунды)
+    #[serde(default = "default_challenge_ttl")]
+    pub ttl: u64,
+    /// Длина префикса адреса клиента, к которому привязана cookie
+    #[serde(default = "default_challenge_ipv4_prefix")]
+    pub ipv4_prefix: u8,
+    #[serde(default = "default_challenge_ipv6_prefix")]
+    pub ipv6_prefix: u8,
+    /// Число нулевых старших бит хеша, которое должен найти скрипт страницы
+    #[serde(default = "default_challenge_difficulty")]
+    pub difficulty: u32,
+    #[serde(default = "default_challenge_cookie_name")]
+    pub cookie_name: String,
+    /// Адреса и подсети клиентов без проверки
+    #[serde(default)]
+    pub allow_ips: Vec<String>,
+    /// Подстроки User-Agent клиентов без проверки (без учета регистра)
+    #[serde(default)]
+    pub allow_user_agents: Vec<String>,
+}
+
+fn default_challenge_ttl() -> u64 {
+    3600
+}
+
+fn default_challenge_ipv4_prefix() -> u8 {
+    24
+}
+
+fn default_challenge_ipv6_prefix() -> u8 {
+    64
+}
+
+fn default_challenge_difficulty() -> u32 {
+    16
+}
+
+fn default_challenge_cookie_name() -> String {
+    "adq_challenge".to_string()
+}
+
+impl Default for ChallengeConfig {
+    fn default() -> Self {
+        Self {
+            secret: None,
+            ttl: default_challenge_ttl(),
+            ipv4_prefix: default_challenge_ipv4_prefix(),
+            ipv6_prefix: default_challenge_ipv6_prefix(),
+            difficulty: default_challenge_difficulty(),
+            cookie_name: default_challenge_cookie_name(),
+            allow_ips: Vec::new(),
+            allow_user_agents: Vec::new(),
+        }
+    }
+}
+
+impl ChallengeConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.secret.as_ref().is_some_and(|secret| secret.len() < 16) {
+            return Err("challenge.secret must be at least 16 characters".to_string());
+        }
+        if self.ttl == 0 {
+            return Err("challenge.ttl must be greater than 0".to_string());
+        }
+        if self.ipv4_prefix > 32 {
+            return Err(format!("challenge.ipv4_prefix {} is greater than 32", self.ipv4_prefix));
+        }
+        if self.ipv6_prefix > 128 {
+            return Err(format!("challenge.ipv6_prefix {} is greater than 128", self.ipv6_prefix));
+        }
+        if self.difficulty > 24 {
+            return Err(format!("challenge.difficulty {} is greater than 24", self.difficulty));
+        }
+        if self.cookie_name.is_empty() || !self.cookie_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(format!("invalid challenge.cookie_name '{}'", self.cookie_name));
+        }
+        for cidr in &self.allow_ips {
+            crate::scheme::Cidr::parse(cidr).map_err(|e| format!("challenge.allow_ips: {}", e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Журнал событий up/down бэкендов и их отправка во внешний webhook
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HealthEventsConfig {
+    /// Сколько последних событий хранится в памяти для /_admin/health/events
+    #[serde(default = "default_health_events_capacity")]
+    pub capacity: usize,
+    #[serde(default)]
+    pub webhook: Option<HealthWebhookConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HealthWebhookConfig {
+    /// URL, на который отправляется POST с JSON событием
+    pub url: String,
+    /// Таймаут одной попытки отправки (секунды)
+    #[serde(default = "default_health_webhook_timeout")]
+    pub timeout: u64,
+    /// Число неудачных отправок подряд, после которого webhook отключается на `cooldown`
+    #[serde(default = "default_health_webhook_failure_threshold")]
+    pub failure_threshold: u32,
+    /// Время (секунды), на которое отключается недоступный webhook
+    #[serde(default = "default_health_webhook_cooldown")]
+    pub cooldown: u64,
+}
+
+fn default_health_events_capacity() -> usize {
+    100
+}
+
+fn default_health_webhook_timeout() -> u64 {
+    5
+}
+
+fn default_health_webhook_failure_threshold() -> u32 {
+    3
+}
+
+fn default_health_webhook_cooldown() -> u64 {
+    60
+}
+
+impl Default for HealthEventsConfig {
+    fn default() -> Self {
+        Self {
+            capacity: default_health_events_capacity(),
+            webhook: None,
+        }
+    }
+}
+
+/// Flight recorder: заголовки и тайминги последних неудачных запросов в памяти
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FlightRecorderConfig {
+    /// Сколько последних записей хранится (0 - выключен)
+    #[serde(default = "default_flight_recorder_capacity")]
+    pub capacity: usize,
+}
+
+fn default_flight_recorder_capacity() -> usize {
+    100
+}
+
+impl Default for FlightRecorderConfig {
+    fn default() -> Self {
+        Self {
+            capacity: default_flight_recorder_capacity(),
+        }
+    }
+}
+
+/// Внутренний HTTP клиент для обращений прокси к внешним сервисам
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HttpClientConfig {
+    /// Таймаут установки соединения (секунды)
+    #[serde(default = "default_http_client_connect_timeout")]
+    pub connect_timeout: u64,
+    /// Таймаут одной попытки целиком, включая чтение ответа (секунды)
+    #[serde(default = "default_http_client_timeout")]
+    pub timeout: u64,
+    /// Повторы после ошибки соединения, таймаута или ответа 5xx
+    #[serde(default = "default_http_client_retries")]
+    pub retries: u32,
+    /// Пауза перед повтором (миллисекунды), удваивается с каждой попыткой
+    #[serde(default = "default_http_client_retry_backoff")]
+    pub retry_backoff_ms: u64,
+}
+
+fn default_http_client_connect_timeout() -> u64 {
+    2
+}
+
+fn default_http_client_timeout() -> u64 {
+    5
+}
+
+fn default_http_client_retries() -> u32 {
+    1
+}
+
+fn default_http_client_retry_backoff() -> u64 {
+    100
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: default_http_client_connect_timeout(),
+            timeout: default_http_client_timeout(),
+            retries: default_http_client_retries(),
+            retry_backoff_ms: default_http_client_retry_backoff(),
+        }
+    }
+}
+
+impl HttpClientConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.connect_timeout == 0 || self.timeout == 0 {
+            return Err("http_client.connect_timeout and http_client.timeout must be greater than 0".to_string());
+        }
+        if self.retries > 5 {
+            return Err(format!("http_client.retries {} is too large, at most 5 retries are allowed", self.retries));
+        }
+        Ok(())
+    }
+}
+
+impl HealthEventsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.capacity == 0 {
+            return Err("health_events.capacity must be greater than 0".to_string());
+        }
+        if let Some(webhook) = &self.webhook {
+            if !webhook.url.starts_with("http://") && !webhook.url.starts_with("https://") {
+                return Err(format!("invalid health_events.webhook.url '{}', expected http:// or https://", webhook.url));
+            }
+            if webhook.timeout == 0 {
+                return Err("health_events.webhook.timeout must be greater than 0".to_string());
+            }
+            if webhook.failure_threshold == 0 {
+                return Err("health_events.webhook.failure_threshold must be greater than 0".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Алгоритм сжатия ответов
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionAlgorithm {
+    Zstd,
+    #[serde(rename = "br")]
+    Brotli,
+    Gzip,
+}
+
+impl CompressionAlgorithm {
+    /// Значение Content-Encoding
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompressionAlgorithm::Zstd => "zstd",
+            CompressionAlgorithm::Brotli => "br",
+            CompressionAlgorithm::Gzip => "gzip",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "zstd" => Some(CompressionAlgorithm::Zstd),
+            "br" => Some(CompressionAlgorithm::Brotli),
+            "gzip" => Some(CompressionAlgorithm::Gzip),
+            _ => None,
+        }
+    }
+}
+
+/// Сжатие ответов upstream по Accept-Encoding клиента
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CompressionConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Порядок предпочтения при равных q-values
+    #[serde(default = "default_compression_algorithms")]
+    pub algorithms: Vec<CompressionAlgorithm>,
+    /// Ответы с Content-Length меньше порога не сжимаются, формат nginx: 1k
+    #[serde(default = "default_compression_min_length")]
+    pub min_length: String,
+    /// Сжимаемые Content-Type (`text/*` - все подтипы)
+    #[serde(default = "default_compression_types")]
+    pub types: Vec<String>,
+    /// Уровни по типу и размеру ответа; применяется первое подходящее правило
+    #[serde(default)]
+    pub levels: Vec<CompressionLevels>,
+}
+
+/// Уровни сжатия для класса ответов; не указанные алгоритмы - с уровнем по умолчанию
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CompressionLevels {
+    /// Content-Type ответа (`application/json`, `text/*`); не указан - любой
+    #[serde(default)]
+    pub content_type: Option<String>,
+    /// Content-Length не меньше этого размера (1m); не указан - любой
+    #[serde(default)]
+    pub min_size: Option<String>,
+    #[serde(default)]
+    pub gzip: Option<u32>,
+    #[serde(default)]
+    pub br: Option<u32>,
+    #[serde(default)]
+    pub zstd: Option<i32>,
+}
+
+fn default_compression_algorithms() -> Vec<CompressionAlgorithm> {
+    vec![CompressionAlgorithm::Zstd, CompressionAlgorithm::Brotli, CompressionAlgorithm::Gzip]
+}
+
+fn default_compression_min_length() -> String {
+    "1k".to_string()
+}
+
+fn default_compression_types() -> Vec<String> {
+    ["application/json", "application/javascript", "application/xml", "image/svg+xml", "text/*"]
+        .iter()
+        .map(|content_type| content_type.to_string())
+        .collect()
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            algorithms: default_compression_algorithms(),
+            min_length: default_compression_min_length(),
+            types: default_compression_types(),
+            levels: Vec::new(),
+        }
+    }
+}
+
+/// Шаблоны сообщений об ошибках: `<templates_dir>/<язык>.yaml` с сообщениями по коду ошибки
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ErrorMessagesConfig {
+    /// Каталог шаблонов (None - сообщения на английском)
+    #[serde(default)]
+    pub templates_dir: Option<String>,
+    /// Язык, если Accept-Language не совпал ни с одним шаблоном
+    #[serde(default = "default_error_language")]
+    pub default_language: String,
+}
+
+fn default_error_language() -> String {
+    "en".to_string()
+}
+
+impl Default for ErrorMessagesConfig {
+    fn default() -> Self {
+        Self {
+            templates_dir: None,
+            default_language: default_error_language(),
+        }
+    }
+}
+
+/// Страница для запросов без сервиса: файл, текст из конфигурации или встроенная страница
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DefaultPageConfig {
+    /// Файл страницы; Content-Type по расширению
+    #[serde(default)]
+    pub file: Option<String>,
+    /// HTML страницы, если файл не задан или не читается
+    #[serde(default)]
+    pub inline: Option<String>,
+    /// Статус ответа: 200 или 404
+    #[serde(default = "default_page_status")]
+    pub status: u16,
+}
+
+fn default_page_status() -> u16 {
+    200
+}
+
+impl Default for DefaultPageConfig {
+    fn default() -> Self {
+        Self {
+            file: None,
+            inline: None,
+            status: default_page_status(),
+        }
+    }
+}
+
+impl DefaultPageConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !matches!(self.status, 200 | 404) {
+            return Err(format!("default_page.status must be 200 or 404, got {}", self.status));
+        }
+        Ok(())
+    }
+}
+
+/// Порог числа блоков: выше soft - предупреждение, выше hard - ошибка загрузки
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct CountLimit {
+    pub soft: usize,
+    pub hard: usize,
+}
+
+impl CountLimit {
+    fn check(&self, kind: &str, count: usize) -> Result<Option<String>, String> {
+        if count > self.hard {
+            return Err(format!(
+                "sites-enabled has {} {}, above the hard limit of {} (config_limits.{}.hard)",
+                count, kind, self.hard, kind
+            ));
+        }
+        Ok((count > self.soft).then(|| {
+            format!("sites-enabled has {} {}, above the soft limit of {} (config_limits.{}.soft)", count, kind, self.soft, kind)
+        }))
+    }
+}
+
+/// Защита от патологически больших сгенерированных конфигураций
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfigLimitsConfig {
+    #[serde(default = "default_servers_limit")]
+    pub servers: CountLimit,
+    #[serde(default = "default_locations_limit")]
+    pub locations: CountLimit,
+    #[serde(default = "default_upstreams_limit")]
+    pub upstreams: CountLimit,
+}
+
+fn default_servers_limit() -> CountLimit {
+    CountLimit { soft: 1000, hard: 10000 }
+}
+
+fn default_locations_limit() -> CountLimit {
+    CountLimit { soft: 10000, hard: 100000 }
+}
+
+fn default_upstreams_limit() -> CountLimit {
+    CountLimit { soft: 1000, hard: 10000 }
+}
+
+impl Default for ConfigLimitsConfig {
+    fn default() -> Self {
+        Self {
+            servers: default_servers_limit(),
+            locations: default_locations_limit(),
+            upstreams: default_upstreams_limit(),
+        }
+    }
+}
+
+impl ConfigLimitsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for (kind, limit) in [("servers", self.servers), ("locations", self.locations), ("upstreams", self.upstreams)] {
+            if limit.soft > limit.hard {
+                return Err(format!(
+                    "config_limits.{}.soft ({}) must not exceed config_limits.{}.hard ({})",
+                    kind, limit.soft, kind, limit.hard
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Предупреждения о превышении soft порогов; превышение hard порога - ошибка
+    pub fn check(&self, nginx: &NginxConfig) -> Result<Vec<String>, String> {
+        let locations = nginx.servers.iter().map(|server| server.locations.len()).sum();
+        let counts = [
+            ("servers", self.servers, nginx.servers.len()),
+            ("locations", self.locations, locations),
+            ("upstreams", self.upstreams, nginx.upstreams.len()),
+        ];
+        let mut warnings = Vec::new();
+        for (kind, limit, count) in counts {
+            warnings.extend(limit.check(kind, count)?);
+        }
+        Ok(warnings)
+    }
+}
+
+/// Эксперимент с детерминированным распределением клиентов по группам
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ExperimentConfig {
+    /// Имя эксперимента, часть заголовка X-Experiment-<имя>
+    pub name: String,
+    /// Ключ распределения: `ip` или `cookie:<имя>` (без cookie - IP клиента)
+    #[serde(default = "default_experiment_key")]
+    pub key: String,
+    pub buckets: Vec<ExperimentBucketConfig>,
+    /// Cookie с назначенной группой для закрепления клиента (None - без Set-Cookie)
+    #[serde(default)]
+    pub cookie: Option<String>,
+    /// Max-Age cookie в секундах
+    #[serde(default = "default_experiment_cookie_max_age")]
+    pub cookie_max_age: u64,
+}
+
+/// Группа эксперимента и ее доля
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ExperimentBucketConfig {
+    pub name: String,
+    #[serde(default = "default_experiment_weight")]
+    pub weight: u32,
+}
+
+fn default_experiment_key() -> String {
+    "ip".to_string()
+}
+
+fn default_experiment_cookie_max_age() -> u64 {
+    30 * 24 * 3600
+}
+
+fn default_experiment_weight() -> u32 {
+    1
+}
+
+/// Окно расписания: дни недели и интервал времени в часовом поясе
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ScheduleConfig {
+    pub name: String,
+    /// Дни начала окна: mon..sun или диапазоны mon-fri (пусто - каждый день)
+    #[serde(default)]
+    pub days: Vec<String>,
+    /// Начало и конец окна, HH:MM; конец раньше начала - окно через полночь
+    pub start: String,
+    pub end: String,
+    /// UTC или имя часового пояса IANA (Europe/Moscow)
+    #[serde(default = "default_schedule_timezone")]
+    pub timezone: String,
+}
+
+fn default_schedule_timezone() -> String {
+    "UTC".to_string()
+}
+
+/// Режим обслуживания: в окна расписания запросы получают 503 с Retry-After до конца окна
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MaintenanceConfig {
+    pub name: String,
+    pub schedule: String,
+    /// Server names (пусто - все)
+    #[serde(default)]
+    pub servers: Vec<String>,
+    /// Префиксы путей location (пусто - все)
+    #[serde(default)]
+    pub locations: Vec<String>,
+    #[serde(default = "default_maintenance_message")]
+    pub message: String,
+}
+
+fn default_maintenance_message() -> String {
+    "Service is under maintenance".to_string()
+}
+
+/// Пути JSON, скрываемые в телах запросов и ответов location с `redact on`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RedactionConfig {
+    /// Пути вида $.password, $.user.token, $.items[*].card, $..secret
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// mask - заменить значение на `mask`, remove - удалить поле
+    #[serde(default = "default_redaction_action")]
+    pub action: String,
+    #[serde(default = "default_redaction_mask")]
+    pub mask: String,
+    /// Тела больше этого размера передаются без изменений, формат nginx: 1m
+    #[serde(default = "default_redaction_max_body_size")]
+    pub max_body_size: String,
+}
+
+fn default_redaction_action() -> String {
+    "mask".to_string()
+}
+
+fn default_redaction_mask() -> String {
+    "***".to_string()
+}
+
+fn default_redaction_max_body_size() -> String {
+    "1m".to_string()
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        Self {
+            paths: Vec::new(),
+            action: default_redaction_action(),
+            mask: default_redaction_mask(),
+            max_body_size: default_redaction_max_body_size(),
+        }
+    }
+}
+
+/// Стадии обработки запроса, которые поддерживает прокси (в порядке по умолчанию)
+pub const REQUEST_STAGES: &[&str] = &[
+    "ip_filter",
+    "ua_filter",
+    "rate_limit",
+    "request_log",
+    "cors",
+    "redirect",
+    "maintenance",
+    "header_rules",
+    "wasm_filter",
+    "challenge",
+    "routing",
+    "static",
+    "circuit_breaker",
+    "idempotency",
+    "concurrency",
+];
+
+/// Состав и порядок стадий обработки запроса
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PipelineConfig {
+    #[serde(default = "default_pipeline_stages")]
+    pub stages: Vec<String>,
+}
+
+fn default_pipeline_stages() -> Vec<String> {
+    REQUEST_STAGES.iter().map(|stage| stage.to_string()).collect()
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            stages: default_pipeline_stages(),
+        }
+    }
+}
+
+impl PipelineConfig {
+    /// Проверяет, что все стадии известны и не повторяются
+    pub fn validate(&self) -> Result<(), String> {
+        for (i, stage) in self.stages.iter().enumerate() {
+            if !REQUEST_STAGES.contains(&stage.as_str()) {
+                return Err(format!("unknown pipeline stage '{}'", stage));
+            }
+            if self.stages[..i].contains(stage) {
+                return Err(format!("duplicate pipeline stage '{}'", stage));
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct CacheConfig {
     pub enabled: bool,
     pub default_ttl: u64,
     pub max_size: String,
     pub rules: Vec<CacheRule>,
+    /// Ответ на запрос нескольких диапазонов (Range: bytes=0-10,20-30)
+    #[serde(default)]
+    pub multi_range: MultiRangeMode,
+}
+
+/// Обработка multi-range запросов к кешируемым объектам
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MultiRangeMode {
+    /// Полный ответ 200 из кеша
+    #[default]
+    Full,
+    /// Запрос без кеша передается в upstream
+    Passthrough,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -87,6 +1244,63 @@ pub struct LoggingConfig {
     pub access_log: LogConfig,
     pub error_log: LogConfig,
     pub metrics: MetricsConfig,
+    /// Скрытие чувствительных данных (токены в query, заголовки) в логах
+    #[serde(default)]
+    pub scrub: LogScrubConfig,
+    /// Выборочный лог решений балансировщика (отладка распределения нагрузки)
+    #[serde(default)]
+    pub decisions_log: Option<DecisionsLogConfig>,
+}
+
+impl LoggingConfig {
+    /// Проверяет секции rotate access_log, error_log и decisions_log
+    pub fn validate_rotation(&self) -> Result<(), String> {
+        for (name, log) in [("access_log", &self.access_log), ("error_log", &self.error_log)] {
+            if let Some(rotate) = &log.rotate {
+                rotate.validate().map_err(|e| format!("logging.{}.{}", name, e))?;
+            }
+        }
+        if let Some(rotate) = self.decisions_log.as_ref().and_then(|log| log.rotate.as_ref()) {
+            rotate.validate().map_err(|e| format!("logging.decisions_log.{}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Проверяет выборку access_log (в error_log не поддерживается)
+    pub fn validate_sampling(&self) -> Result<(), String> {
+        if let Some(sampling) = &self.access_log.sampling {
+            sampling.validate().map_err(|e| format!("logging.access_log.{}", e))?;
+        }
+        if self.error_log.sampling.is_some() {
+            return Err("logging.error_log.sampling is not supported, errors are always logged".to_string());
+        }
+        if let Some(decisions_log) = &self.decisions_log {
+            if !(0.0..=1.0).contains(&decisions_log.sample_rate) {
+                return Err(format!(
+                    "logging.decisions_log.sample_rate must be between 0 and 1, got {}",
+                    decisions_log.sample_rate
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Лог решений балансировщика: одна JSON запись на выбранный выборкой запрос
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DecisionsLogConfig {
+    /// Файл лога или `stdout`
+    pub path: String,
+    /// Доля записываемых запросов (0.0 - 1.0)
+    #[serde(default = "default_decisions_sample_rate")]
+    pub sample_rate: f64,
+    /// Ротация файла по размеру
+    #[serde(default)]
+    pub rotate: Option<LogRotateConfig>,
+}
+
+fn default_decisions_sample_rate() -> f64 {
+    0.01
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -94,6 +1308,109 @@ pub struct LogConfig {
     pub enabled: bool,
     pub path: String,
     pub format: String,
+    /// Ротация файла по размеру самим прокси (без внешнего logrotate)
+    #[serde(default)]
+    pub rotate: Option<LogRotateConfig>,
+    /// Выборочная запись успешных запросов (только access_log)
+    #[serde(default)]
+    pub sampling: Option<LogSamplingConfig>,
+}
+
+/// Что скрывается в access/error log
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LogScrubConfig {
+    /// Query-параметры, значения которых заменяются на [REDACTED]
+    #[serde(default = "default_scrub_query_params")]
+    pub query_params: Vec<String>,
+    /// Заголовки, значения которых никогда не пишутся в лог
+    #[serde(default = "default_scrub_headers")]
+    pub headers: Vec<String>,
+    /// Оставлять от Authorization только схему (Bearer, Basic)
+    #[serde(default = "default_true")]
+    pub mask_authorization: bool,
+}
+
+fn default_scrub_query_params() -> Vec<String> {
+    ["token", "access_token", "refresh_token", "id_token", "api_key", "apikey", "code", "password", "secret", "signature"]
+        .iter()
+        .map(|name| name.to_string())
+        .collect()
+}
+
+fn default_scrub_headers() -> Vec<String> {
+    ["cookie", "set-cookie", "proxy-authorization", "x-api-key"]
+        .iter()
+        .map(|name| name.to_string())
+        .collect()
+}
+
+impl Default for LogScrubConfig {
+    fn default() -> Self {
+        Self {
+            query_params: default_scrub_query_params(),
+            headers: default_scrub_headers(),
+            mask_authorization: true,
+        }
+    }
+}
+
+/// Выборка access log для нагруженных инсталляций
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LogSamplingConfig {
+    /// Доля записываемых успешных запросов (0.0 - 1.0)
+    #[serde(default = "default_sample_rate")]
+    pub rate: f64,
+    /// Запросы не быстрее порога (мс) записываются всегда
+    #[serde(default)]
+    pub slow_ms: Option<u64>,
+}
+
+fn default_sample_rate() -> f64 {
+    1.0
+}
+
+impl LogSamplingConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.rate) {
+            return Err(format!("sampling.rate must be between 0 and 1, got {}", self.rate));
+        }
+        Ok(())
+    }
+}
+
+/// Ротация лога: file -> file.1 -> ... -> file.{keep}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LogRotateConfig {
+    /// Размер, после которого файл ротируется (100MB)
+    pub max_size: String,
+    /// Количество хранимых ротированных файлов, сжатых или нет (max_files)
+    #[serde(default = "default_rotate_keep", alias = "max_files")]
+    pub keep: usize,
+    /// Сжимать ротированные файлы gzip в фоне
+    #[serde(default)]
+    pub compress: bool,
+}
+
+fn default_rotate_keep() -> usize {
+    5
+}
+
+impl LogRotateConfig {
+    /// Размер файла для ротации в байтах
+    pub fn max_size_bytes(&self) -> Result<u64, String> {
+        parse_size(&self.max_size)
+            .filter(|size| *size > 0)
+            .map(|size| size as u64)
+            .ok_or_else(|| format!("invalid rotate.max_size '{}'", self.max_size))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.max_size_bytes()?;
+        if self.keep == 0 {
+            return Err("rotate.keep must be greater than 0".to_string());
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -101,14 +1418,135 @@ pub struct MetricsConfig {
     pub enabled: bool,
     pub endpoint: String,
     pub port: u16,
+    /// Префикс имен метрик (например, "adq" -> adq_http_requests_total)
+    #[serde(default)]
+    pub namespace: Option<String>,
+    /// Адрес, на котором слушает сервис метрик
+    #[serde(default = "default_metrics_bind")]
+    pub bind: String,
+    /// Bearer токен для доступа к метрикам (Authorization: Bearer <token>)
+    #[serde(default)]
+    pub bearer_token: Option<String>,
+    /// Интервал обновления метрик процесса (память, дескрипторы, uptime), в секундах
+    #[serde(default = "default_process_interval")]
+    pub process_interval: u64,
+}
+
+fn default_metrics_bind() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_process_interval() -> u64 {
+    15
+}
+
+impl MetricsConfig {
+    /// Адрес сервиса метрик (bind и port)
+    pub fn listen_addr(&self) -> Result<SocketAddr, String> {
+        let ip = self
+            .bind
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<std::net::IpAddr>()
+            .map_err(|_| format!("invalid metrics bind address '{}'", self.bind))?;
+        Ok(SocketAddr::new(ip, self.port))
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct IpFilterConfig {
     pub enabled: bool,
-    pub blacklist_file: Option<String>,
-    pub whitelist: Option<Vec<String>>,
+    /// Файл заблокированных адресов (до v2 - blacklist_file)
+    pub blocklist_file: Option<String>,
+    /// Разрешенные адреса (до v2 - whitelist)
+    pub allowlist: Option<Vec<String>>,
     pub max_connections_per_ip: Option<usize>,
+    /// Ответ заблокированному клиенту: 403 или 444 (закрыть соединение без ответа)
+    #[serde(default = "default_ip_deny_status")]
+    pub deny_status: u16,
+}
+
+fn default_ip_deny_status() -> u16 {
+    403
+}
+
+impl IpFilterConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !matches!(self.deny_status, 403 | 444) {
+            return Err(format!("ip_filter.deny_status must be 403 or 444, got {}", self.deny_status));
+        }
+        Ok(())
+    }
+}
+
+/// Фильтрация клиентов по User-Agent
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct UaFilterConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Блокировать запросы без User-Agent или с пустым значением
+    #[serde(default)]
+    pub block_empty: bool,
+    /// Подстроки User-Agent, которые никогда не блокируются (наши мониторинговые боты)
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub rules: Vec<UaRuleConfig>,
+}
+
+/// Правило блокировки User-Agent
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UaRuleConfig {
+    /// Имя правила для метрики ua_blocked_total{rule}
+    pub name: String,
+    /// Подстроки User-Agent (без учета регистра)
+    #[serde(default)]
+    pub contains: Vec<String>,
+    /// Регулярные выражения для User-Agent
+    #[serde(default)]
+    pub regex: Vec<String>,
+    /// Server names, для которых действует правило (пусто - для всех)
+    #[serde(default)]
+    pub servers: Vec<String>,
+    /// Префиксы путей location, для которых действует правило (пусто - для всех)
+    #[serde(default)]
+    pub locations: Vec<String>,
+}
+
+/// Правило по заголовку запроса, отвечающее заданным статусом и JSON телом
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HeaderRuleConfig {
+    /// Имя правила для метрики header_rule_matches_total{rule}
+    pub name: String,
+    /// Имя заголовка запроса (например, X-App-Version)
+    pub header: String,
+    /// Точное совпадение значения
+    #[serde(default)]
+    pub equals: Option<String>,
+    /// Срабатывает для semver версий ниже указанной
+    #[serde(default)]
+    pub version_below: Option<String>,
+    /// Срабатывать для запросов без заголовка (по умолчанию они пропускаются)
+    #[serde(default)]
+    pub match_missing: bool,
+    /// Server names, для которых действует правило (пусто - для всех)
+    #[serde(default)]
+    pub servers: Vec<String>,
+    /// Префиксы путей location, для которых действует правило (пусто - для всех)
+    #[serde(default)]
+    pub locations: Vec<String>,
+    #[serde(default = "default_header_rule_status")]
+    pub status: u16,
+    /// JSON тело ответа (не нужно для 444 - закрыть соединение без ответа)
+    #[serde(default)]
+    pub body: serde_json::Value,
+    /// Расписание из schedules: правило действует только в его окна
+    #[serde(default)]
+    pub schedule: Option<String>,
+}
+
+fn default_header_rule_status() -> u16 {
+    426
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -117,44 +1555,175 @@ pub struct CircuitBreakerConfig {
     pub failure_threshold: u32,
     pub recovery_timeout: u64,
     pub success_threshold: u32,
+    /// Открытие по доле ошибок в скользящем окне вместо счетчика ошибок
+    #[serde(default)]
+    pub failure_rate: Option<FailureRateConfig>,
+    /// Максимальное количество одновременных пробных запросов в HalfOpen
+    #[serde(default = "default_half_open_max_requests")]
+    pub half_open_max_requests: u32,
+    /// Коды ответа upstream, которые считаются ошибкой (4xx по умолчанию не учитываются)
+    #[serde(default = "default_failure_status_codes")]
+    pub failure_status_codes: Vec<u16>,
+    /// Ответ клиенту при открытом circuit (по умолчанию JSON ошибка UPSTREAM_CIRCUIT_OPEN)
+    #[serde(default)]
+    pub fallback: Option<CircuitFallbackConfig>,
+}
+
+/// Fallback ответ при открытом circuit breaker
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CircuitFallbackConfig {
+    /// Статический JSON
+    Json { body: String },
+    /// Содержимое файла (например, заранее сохраненный ответ)
+    File { path: String },
+    /// Редирект на другую страницу
+    Redirect { location: String },
+}
+
+fn default_failure_status_codes() -> Vec<u16> {
+    vec![500, 502, 503, 504]
+}
+
+fn default_half_open_max_requests() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FailureRateConfig {
+    /// Доля ошибок (0.0 - 1.0), при превышении которой circuit открывается
+    pub threshold: f64,
+    /// Минимальное количество запросов в окне для оценки доли ошибок
+    pub min_requests: u32,
+    /// Размер скользящего окна в секундах
+    pub window: u64,
+}
+
+impl Default for FailureRateConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0.5,
+            min_requests: 20,
+            window: 10,
+        }
+    }
+}
+
+/// Адреса сервисов, к которым запросы проксируются напрямую (без балансировщика)
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ServicesConfig {
+    #[serde(default = "default_challenge_api_addr")]
+    pub challenge_api: SocketAddr,
+    #[serde(default = "default_billing_api_addr")]
+    pub billing_api: SocketAddr,
+    #[serde(default = "default_erir_api_addr")]
+    pub erir_api: SocketAddr,
+    #[serde(default = "default_shared_api_addr")]
+    pub shared_api: SocketAddr,
+}
+
+fn default_challenge_api_addr() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 8080))
+}
+
+fn default_billing_api_addr() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 8081))
+}
+
+fn default_erir_api_addr() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 8082))
+}
+
+fn default_shared_api_addr() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 8083))
+}
+
+impl Default for ServicesConfig {
+    fn default() -> Self {
+        Self {
+            challenge_api: default_challenge_api_addr(),
+            billing_api: default_billing_api_addr(),
+            erir_api: default_erir_api_addr(),
+            shared_api: default_shared_api_addr(),
+        }
+    }
+}
+
+/// Совместимость с прежним поведением прокси
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CompatConfig {
+    /// Встроенные правила для localhost/127.0.0.1: порты 8085/8091 и префиксы
+    /// /ui/, /oauth/, /.well-known/ идут в zitadel_auth, остальное - статическая страница.
+    /// Без флага localhost маршрутизируется по nginx конфигурации
+    #[serde(default)]
+    pub adquest_legacy_routes: bool,
 }
 
 impl Config {
-    /// Загружает основную конфигурацию из YAML файла
-    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
-        let content = fs::read_to_string(path)?;
-        let mut config: Config = serde_yaml::from_str(&content)?;
+    /// Загружает основную конфигурацию из YAML файла и фрагментов conf.d рядом с ним
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> error::Result<Self> {
+        Self::load_with_fragments(path, &[])
+    }
+
+    /// Загружает основной файл, фрагменты conf.d рядом с ним и фрагменты каталогов `dirs`.
+    /// Более поздний фрагмент переопределяет поля более раннего (fragments::merge)
+    pub fn load_with_fragments<P: AsRef<Path>>(path: P, dirs: &[PathBuf]) -> error::Result<Self> {
+        let (value, applied) = fragments::load_merged(path.as_ref(), dirs)?;
+        // Старые версии схемы мигрируются, более новые - ошибка (SchemaError)
+        let mut config = version::parse_config_value(value)?;
+        config.schema.fragments = applied;
         
         // Загружаем nginx-style конфигурацию из sites-enabled
-        config.nginx_config = Some(NginxConfig::load_from_sites_enabled("/etc/adq-pingora/sites-enabled")?);
+        let nginx_config = NginxConfig::load_from_sites_enabled("/etc/adq-pingora/sites-enabled")?;
+        // Выше hard порога конфигурация не загружается; soft пороги выводятся при старте и в -t
+        config.config_limits.check(&nginx_config).map_err(ProxyError::ConfigParse)?;
+        config.nginx_config = Some(nginx_config);
         
         Ok(config)
     }
 
     /// Загружает только nginx-style конфигурацию
-    pub fn load_nginx_config() -> Result<NginxConfig, Box<dyn std::error::Error>> {
+    pub fn load_nginx_config() -> error::Result<NginxConfig> {
         NginxConfig::load_from_sites_enabled("/etc/adq-pingora/sites-enabled")
     }
 
     /// Сохраняет конфигурацию в YAML файл
-    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> error::Result<()> {
         // Не сохраняем nginx_config в YAML, так как он загружается из sites-enabled
         let mut config_to_save = self.clone();
         config_to_save.nginx_config = None;
         
         let content = serde_yaml::to_string(&config_to_save)?;
-        fs::write(path, content)?;
+        fs::write(&path, content).map_err(ProxyError::io(&path))?;
         Ok(())
     }
 
     /// Создает конфигурацию по умолчанию
     pub fn default() -> Self {
         Self {
-            version: 1,
+            version: CONFIG_VERSION,
+            strict: false,
             global: GlobalConfig {
                 default_timeout: 30,
                 max_retries: 3,
                 health_check_interval: 5,
+                connect_timeout: None,
+                first_byte_timeout: None,
+                request_timeout: None,
+                write_timeout: None,
+                tcp_keepalive: None,
+                drain_timeout: default_drain_timeout(),
+                keepalive_timeout: default_keepalive_timeout(),
+                keepalive_requests: default_keepalive_requests(),
+                buffered_body_budget: default_buffered_body_budget(),
+                max_header_size: default_max_header_size(),
+                default_http_port: default_http_port(),
+                default_https_port: default_https_port(),
+                listen_ipv4: true,
+                listen_ipv6: true,
+                default_upstream: None,
+                upstream_warmup_connections: 0,
+                reject_http10: false,
             },
             security: SecurityConfig {
                 headers: SecurityHeaders {
@@ -171,6 +1740,7 @@ impl Config {
                 default_ttl: 300,
                 max_size: "1GB".to_string(),
                 rules: Vec::new(),
+                multi_range: MultiRangeMode::Full,
             },
             logging: LoggingConfig {
                 format: "json".to_string(),
@@ -179,32 +1749,162 @@ impl Config {
                     enabled: true,
                     path: "/var/log/pingora-proxy/access.log".to_string(),
                     format: "json".to_string(),
+                    rotate: None,
+                    sampling: None,
                 },
                 error_log: LogConfig {
                     enabled: true,
                     path: "/var/log/pingora-proxy/error.log".to_string(),
                     format: "json".to_string(),
+                    rotate: None,
+                    sampling: None,
                 },
                 metrics: MetricsConfig {
                     enabled: true,
                     endpoint: "/metrics".to_string(),
                     port: 9090,
+                    namespace: None,
+                    bind: default_metrics_bind(),
+                    bearer_token: None,
+                    process_interval: default_process_interval(),
                 },
+                scrub: LogScrubConfig::default(),
+                decisions_log: None,
             },
             ip_filter: IpFilterConfig {
                 enabled: false,
-                blacklist_file: None,
-                whitelist: None,
+                blocklist_file: None,
+                allowlist: None,
                 max_connections_per_ip: None,
+                deny_status: default_ip_deny_status(),
             },
+            ua_filter: UaFilterConfig::default(),
+            header_rules: Vec::new(),
             circuit_breaker: CircuitBreakerConfig {
                 enabled: false,
                 failure_threshold: 5,
                 recovery_timeout: 30,
                 success_threshold: 3,
+                failure_rate: None,
+                half_open_max_requests: default_half_open_max_requests(),
+                failure_status_codes: default_failure_status_codes(),
+                fallback: None,
             },
+            services: ServicesConfig::default(),
+            compat: CompatConfig::default(),
+            response_headers: ResponseHeadersConfig::default(),
+            pipeline: PipelineConfig::default(),
+            proxy_headers: ProxyHeadersConfig::default(),
+            grpc_web: GrpcWebConfig::default(),
+            backend_profiles: default_backend_profiles(),
+            idempotency: IdempotencyConfig::default(),
+            wasm_filters: WasmFiltersConfig::default(),
+            challenge: ChallengeConfig::default(),
+            health_events: HealthEventsConfig::default(),
+            flight_recorder: FlightRecorderConfig::default(),
+            http_client: HttpClientConfig::default(),
+            redaction: RedactionConfig::default(),
+            compression: CompressionConfig::default(),
+            error_messages: ErrorMessagesConfig::default(),
+            default_page: DefaultPageConfig::default(),
+            config_limits: ConfigLimitsConfig::default(),
+            experiments: Vec::new(),
+            schedules: Vec::new(),
+            maintenance: Vec::new(),
             nginx_config: None,
+            schema: SchemaInfo::default(),
+        }
+    }
+
+    /// Порты для TCP listeners: директивы listen nginx конфигурации без повторов,
+    /// а без них - global.default_http_port и global.default_https_port
+    pub fn listen_ports(&self) -> Vec<u16> {
+        let mut ports = Vec::new();
+        let listens = self
+            .nginx_config
+            .iter()
+            .flat_map(|nginx| &nginx.servers)
+            .flat_map(|server| &server.listen_ports);
+        for listen in listens {
+            if !ports.contains(&listen.port) {
+                ports.push(listen.port);
+            }
+        }
+        if ports.is_empty() {
+            ports.push(self.global.default_http_port);
+            if self.global.default_https_port != self.global.default_http_port {
+                ports.push(self.global.default_https_port);
+            }
         }
+        ports
+    }
+
+    /// Адреса TCP listeners прокси с учетом global.listen_ipv4 / global.listen_ipv6.
+    /// Явные адреса (listen 127.0.0.1:80, listen [::]:80) привязываются как указано; слушатели без адреса
+    /// и порты по умолчанию - одним dual-stack сокетом [::], если порт не занят явным [::] или 0.0.0.0
+    pub fn listen_addrs(&self) -> Result<Vec<ListenAddr>, String> {
+        let (ipv4, ipv6) = (self.global.listen_ipv4, self.global.listen_ipv6);
+        if !ipv4 && !ipv6 {
+            return Err("global.listen_ipv4 and global.listen_ipv6 cannot both be disabled".to_string());
+        }
+
+        let listens: Vec<&ListenDirective> = self
+            .nginx_config
+            .iter()
+            .flat_map(|nginx| &nginx.servers)
+            .flat_map(|server| &server.listen_ports)
+            .collect();
+        let mut addrs: Vec<ListenAddr> = Vec::new();
+        for listen in &listens {
+            let Some(address) = listen.address else { continue };
+            let addr = ListenAddr {
+                addr: SocketAddr::new(address, listen.port),
+                ipv6_only: address.is_ipv6() && listen.ipv6only,
+            };
+            let enabled = if address.is_ipv6() { ipv6 } else { ipv4 };
+            if enabled && !addrs.iter().any(|existing| existing.addr == addr.addr) {
+                addrs.push(addr);
+            }
+        }
+
+        let mut wildcard_ports: Vec<u16> = Vec::new();
+        if listens.is_empty() {
+            wildcard_ports = self.listen_ports();
+        }
+        for listen in listens.iter().filter(|listen| listen.address.is_none()) {
+            if !wildcard_ports.contains(&listen.port) {
+                wildcard_ports.push(listen.port);
+            }
+        }
+        for port in wildcard_ports {
+            let v6_any = SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port);
+            let v4_any = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port);
+            let v6_bound = addrs.iter().find(|existing| existing.addr == v6_any).map(|existing| existing.ipv6_only);
+            let v4_bound = addrs.iter().any(|existing| existing.addr == v4_any) || v6_bound == Some(false);
+            if ipv6 && v6_bound.is_none() {
+                addrs.push(ListenAddr { addr: v6_any, ipv6_only: !ipv4 || v4_bound });
+            } else if ipv4 && !v4_bound {
+                addrs.push(ListenAddr { addr: v4_any, ipv6_only: false });
+            }
+        }
+        Ok(addrs)
+    }
+
+    /// Порты с HTTP/2 без TLS (listen 8080 http2): на них принимается h2c с prior knowledge
+    pub fn h2c_ports(&self) -> Vec<u16> {
+        let mut ports = Vec::new();
+        let listens = self
+            .nginx_config
+            .iter()
+            .flat_map(|nginx| &nginx.servers)
+            .flat_map(|server| &server.listen_ports)
+            .filter(|listen| listen.http2 && !listen.ssl);
+        for listen in listens {
+            if !ports.contains(&listen.port) {
+                ports.push(listen.port);
+            }
+        }
+        ports
     }
 
     /// Находит server блок по хосту (из nginx конфигурации)
@@ -212,6 +1912,11 @@ impl Config {
         self.nginx_config.as_ref()?.find_server(host)
     }
 
+    /// Server блок для запросов без Host
+    pub fn default_server(&self) -> Option<&ServerBlock> {
+        self.nginx_config.as_ref()?.default_server()
+    }
+
     /// Находит location в server блоке по пути
     pub fn find_location<'a>(&self, server: &'a ServerBlock, path: &str) -> Option<&'a LocationBlock> {
         self.nginx_config.as_ref()?.find_location(server, path)
@@ -222,6 +1927,113 @@ impl Config {
         self.nginx_config.as_ref()?.get_upstream(name)
     }
 
+    /// Вычисляет таймауты upstream: значения location переопределяют глобальные.
+    /// Overall read timeout берется из `default_timeout`, first-byte timeout
+    /// по умолчанию равен read timeout.
+    pub fn resolve_upstream_timeouts(&self, location: Option<&LocationBlock>) -> UpstreamTimeouts {
+        let read = location
+            .and_then(|l| l.proxy_read_timeout)
+            .unwrap_or(Duration::from_secs(self.global.default_timeout));
+        let first_byte = location
+            .and_then(|l| l.proxy_first_byte_timeout)
+            .or(self.global.first_byte_timeout.map(Duration::from_secs))
+            .unwrap_or(read);
+        let connect = location
+            .and_then(|l| l.proxy_connect_timeout)
+            .or(self.global.connect_timeout.map(Duration::from_secs));
+        let write = location
+            .and_then(|l| l.proxy_send_timeout)
+            .or(self.global.write_timeout.map(Duration::from_secs));
+        let request = location
+            .and_then(|l| l.proxy_request_timeout)
+            .or(self.global.request_timeout.map(Duration::from_secs));
+
+        UpstreamTimeouts { connect, read, first_byte, write, request }
+    }
+
+    /// Профиль бэкенда для запроса: `backend_profile` location, иначе профиль,
+    /// в `upstreams` которого указан upstream запроса
+    pub fn backend_profile(&self, location: Option<&LocationBlock>, upstream: &str) -> Option<(&str, &BackendProfileConfig)> {
+        if let Some(name) = location.and_then(|l| l.backend_profile.as_deref()) {
+            return self.backend_profiles.get_key_value(name).map(|(k, v)| (k.as_str(), v));
+        }
+        self.backend_profiles
+            .iter()
+            .find(|(_, profile)| profile.upstreams.iter().any(|u| u == upstream))
+            .map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// Проверяет, что upstream привязан не более чем к одному профилю, а location
+    /// ссылаются на существующие профили
+    pub fn validate_backend_profiles(&self) -> Result<(), String> {
+        let mut owners: HashMap<&str, &str> = HashMap::new();
+        for (name, profile) in &self.backend_profiles {
+            for upstream in &profile.upstreams {
+                if let Some(other) = owners.insert(upstream, name) {
+                    return Err(format!(
+                        "upstream '{}' is listed in backend profiles '{}' and '{}'",
+                        upstream, other, name
+                    ));
+                }
+            }
+        }
+
+        let Some(nginx_config) = &self.nginx_config else {
+            return Ok(());
+        };
+        for server in &nginx_config.servers {
+            for location in &server.locations {
+                if let Some(name) = &location.backend_profile {
+                    if !self.backend_profiles.contains_key(name) {
+                        return Err(format!("unknown backend_profile '{}' in location {}", name, location.path));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Проверяет pipeline.stages: имена стадий известны, а стадии включенных в конфигурации
+    /// компонентов не пропущены (иначе их проверки молча не выполняются)
+    pub fn validate_pipeline(&self) -> Result<(), String> {
+        self.pipeline.validate()?;
+
+        let locations: Vec<&LocationBlock> = self
+            .nginx_config
+            .iter()
+            .flat_map(|nginx| &nginx.servers)
+            .flat_map(|server| &server.locations)
+            .collect();
+        let required = [
+            ("ip_filter", self.ip_filter.enabled, "ip_filter is enabled"),
+            ("ua_filter", self.ua_filter.enabled, "ua_filter is enabled"),
+            ("maintenance", !self.maintenance.is_empty(), "maintenance windows are configured"),
+            ("header_rules", !self.header_rules.is_empty(), "header_rules are configured"),
+            ("wasm_filter", locations.iter().any(|l| l.wasm_filter.is_some()), "a location sets wasm_filter"),
+            ("circuit_breaker", self.circuit_breaker.enabled, "circuit_breaker is enabled"),
+            (
+                "rate_limit",
+                locations
+                    .iter()
+                    .any(|l| l.rate_limit.is_some() || !l.rate_limit_schedules.is_empty() || !l.limit_req.is_empty() || !l.limit_conn.is_empty()),
+                "a location uses rate_limit, limit_req or limit_conn",
+            ),
+            ("idempotency", locations.iter().any(|l| l.idempotency.is_some()), "a location sets idempotency"),
+            ("challenge", locations.iter().any(|l| l.challenge_mode), "a location sets challenge_mode on"),
+            (
+                "concurrency",
+                self.nginx_config.iter().flat_map(|nginx| nginx.upstreams.values()).any(|u| u.max_conns.is_some()),
+                "an upstream sets max_conns",
+            ),
+        ];
+        for (stage, enabled, reason) in required {
+            if enabled && !self.pipeline.stages.iter().any(|s| s == stage) {
+                return Err(format!("{} but pipeline.stages has no {} stage", reason, stage));
+            }
+        }
+        Ok(())
+    }
+
     /// Получает все upstreams
     pub fn get_all_upstreams(&self) -> HashMap<String, &UpstreamBlock> {
         if let Some(nginx_config) = &self.nginx_config {
@@ -264,4 +2076,39 @@ mod tests {
         assert!(config.find_route("unknown.com", "/api/users").is_none());
         assert!(config.find_route("api.example.com", "/unknown").is_none());
     }
+
+    #[test]
+    fn test_config_limits_soft_and_hard() {
+        let sites = |locations: usize| {
+            let locations: String = (0..locations).map(|i| format!("location /l{}/ {{ proxy_pass api; }} ", i)).collect();
+            NginxConfig::parse_config_content(&format!(
+                "upstream api {{ server 127.0.0.1:8080; }} server {{ listen 80; server_name shop.example.com; {} }}",
+                locations
+            ))
+            .unwrap()
+        };
+        let limits = ConfigLimitsConfig {
+            locations: CountLimit { soft: 3, hard: 5 },
+            ..Default::default()
+        };
+        assert!(limits.validate().is_ok());
+
+        assert_eq!(limits.check(&sites(3)).unwrap(), Vec::<String>::new());
+        let warnings = limits.check(&sites(5)).unwrap();
+        assert_eq!(warnings, vec!["sites-enabled has 5 locations, above the soft limit of 3 (config_limits.locations.soft)"]);
+        let error = limits.check(&sites(6)).unwrap_err();
+        assert_eq!(error, "sites-enabled has 6 locations, above the hard limit of 5 (config_limits.locations.hard)");
+
+        let upstreams = ConfigLimitsConfig {
+            upstreams: CountLimit { soft: 0, hard: 0 },
+            ..Default::default()
+        };
+        assert!(upstreams.check(&sites(1)).unwrap_err().contains("1 upstreams"));
+
+        let inverted = ConfigLimitsConfig {
+            servers: CountLimit { soft: 10, hard: 5 },
+            ..Default::default()
+        };
+        assert!(inverted.validate().is_err());
+    }
 }
\ No newline at end of file
@@ -1,13 +1,111 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::fs;
+use std::net::{IpAddr, SocketAddr};
 use std::path::Path;
+use std::time::Duration;
 use regex::Regex;
 use log::{info, warn, error};
 
+use super::lookup::{LocationIndex, ServerIndex};
+use super::proxy_pass::{ProxyPassAddr, ProxyPassUrl};
+use super::CompressionAlgorithm;
+use crate::error::ProxyError;
+
 #[derive(Debug, Clone)]
 pub struct NginxConfig {
     pub servers: Vec<ServerBlock>,
     pub upstreams: HashMap<String, UpstreamBlock>,
+    /// DNS резолвер для upstream имен (resolver 10.0.0.2 valid=30s timeout=2s)
+    pub resolver: Option<ResolverDirective>,
+    /// Зоны limit_req_zone по имени
+    pub limit_req_zones: HashMap<String, LimitReqZone>,
+    /// Зоны limit_conn_zone по имени
+    pub limit_conn_zones: HashMap<String, LimitConnZone>,
+    /// Блоки map по имени определяемой переменной (без `$`)
+    pub maps: HashMap<String, MapBlock>,
+    /// Поиск server блока по имени без перебора всех servers.
+    /// После изменения servers или их locations индексы обновляет `reindex`
+    server_index: ServerIndex,
+    /// Блоки и файлы, пропущенные из-за ошибок разбора (выводятся в `adq-pingora -t`)
+    pub issues: Vec<ParseIssue>,
+}
+
+/// Место в nginx-конфиге: файл (None - конфиг из строки), строка и колонка с 1;
+/// line 0 - блок создан не парсером
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourcePos {
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for SourcePos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}:{}", file, self.line, self.column),
+            None => write!(f, "line {}, column {}", self.line, self.column),
+        }
+    }
+}
+
+/// Ошибка разбора, из-за которой пропущен блок или весь файл
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseIssue {
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl ParseIssue {
+    fn at(pos: SourcePos, message: String) -> Self {
+        Self { file: pos.file, line: Some(pos.line), column: Some(pos.column), message }
+    }
+
+    /// Ошибка файла целиком; место берется из ProxyError::Located, если оно есть
+    fn from_error(file: &Path, e: &ProxyError) -> Self {
+        match e {
+            ProxyError::Located { pos, source } => Self::at(pos.clone(), source.to_string()),
+            e => Self { file: Some(file.display().to_string()), line: None, column: None, message: e.to_string() },
+        }
+    }
+}
+
+/// Текст конфига без комментариев (строки и колонки совпадают с файлом) и путь файла
+struct Source<'a> {
+    text: &'a str,
+    file: Option<&'a str>,
+}
+
+impl Source<'_> {
+    fn pos(&self, offset: usize) -> SourcePos {
+        let before = &self.text[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        SourcePos {
+            file: self.file.map(str::to_string),
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+
+    fn located(&self, offset: usize) -> impl FnOnce(ProxyError) -> ProxyError + '_ {
+        move |e| ProxyError::Located { pos: self.pos(offset), source: Box::new(e) }
+    }
+}
+
+/// Смещение в `body` директивы, после которой блок перестает разбираться:
+/// директивы добавляются по одной, пока `parse` не вернет ошибку
+fn failing_directive<T>(body: &str, parse: impl Fn(&str) -> Result<T, ProxyError>) -> Option<usize> {
+    let mut end = 0;
+    while let Some(next) = find_unquoted(&body[end..], ';') {
+        let start = end + (body[end..].len() - body[end..].trim_start().len());
+        end += next + 1;
+        if parse(&body[..end]).is_err() {
+            return Some(start);
+        }
+    }
+    None
 }
 
 #[derive(Debug, Clone)]
@@ -17,21 +115,394 @@ pub struct ServerBlock {
     pub ssl_certificate: Option<String>,
     pub ssl_certificate_key: Option<String>,
     pub locations: Vec<LocationBlock>,
+    /// Отдельный access log сервера (access_log path [format])
+    pub access_log: Option<AccessLogDirective>,
+    /// Поиск location по пути без перебора всех locations (обновляет `reindex`)
+    location_index: LocationIndex,
+    /// Начало блока `server` в файле
+    pub pos: SourcePos,
+}
+
+/// Директива access_log в server или location блоке
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessLogDirective {
+    /// Запись отключена (access_log off)
+    Off,
+    /// Файл и формат (json или text; без формата - как в глобальном access_log)
+    File { path: String, format: Option<String> },
 }
 
 #[derive(Debug, Clone)]
 pub struct ListenDirective {
+    /// Адрес привязки (listen 127.0.0.1:80, listen [::]:80); None - все адреса (listen 80, listen *:80)
+    pub address: Option<IpAddr>,
     pub port: u16,
     pub ssl: bool,
     pub http2: bool,
+    /// Ожидать PROXY protocol заголовок от L4 балансировщика (listen ... proxy_protocol)
+    pub proxy_protocol: bool,
+    /// Для IPv6 адреса: принимать только IPv6 (ipv6only=on, по умолчанию как в nginx)
+    /// или и IPv4 через тот же сокет (ipv6only=off)
+    pub ipv6only: bool,
+    /// Server для запросов без Host (listen ... default_server)
+    pub default_server: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct LocationBlock {
     pub path: String,
     pub proxy_pass: Option<String>,
+    /// proxy_pass в форме URL (`https://unix:/run/app.sock:/api/`); None - имя upstream блока
+    pub proxy_pass_url: Option<ProxyPassUrl>,
     pub rate_limit: Option<RateLimit>,
+    /// Лимиты на время окон расписаний (`rate_limit 10 20 schedule=billing_night;`),
+    /// заменяют rate_limit, пока окно действует
+    pub rate_limit_schedules: Vec<ScheduledRateLimit>,
     pub cors_enable: bool,
+    /// Таймаут установки соединения с upstream (proxy_connect_timeout)
+    pub proxy_connect_timeout: Option<Duration>,
+    /// Таймаут чтения ответа upstream (proxy_read_timeout)
+    pub proxy_read_timeout: Option<Duration>,
+    /// Таймаут записи запроса в upstream (proxy_send_timeout)
+    pub proxy_send_timeout: Option<Duration>,
+    /// Таймаут ожидания первого байта ответа upstream (proxy_first_byte_timeout)
+    pub proxy_first_byte_timeout: Option<Duration>,
+    /// Общее время обращения к upstream, после которого клиент получает 504 (proxy_request_timeout)
+    pub proxy_request_timeout: Option<Duration>,
+    /// Перехват ошибок upstream (proxy_intercept_errors on)
+    pub intercept_errors: bool,
+    /// Страницы ошибок: статус -> путь к файлу (error_page)
+    pub error_pages: HashMap<u16, String>,
+    /// Замена статусов перехваченных ответов (status_map 500=502)
+    pub status_map: HashMap<u16, u16>,
+    /// Переписывание Location/Refresh в ответах upstream (proxy_redirect)
+    pub proxy_redirect: ProxyRedirect,
+    /// Замены домена в Set-Cookie ответов upstream (proxy_cookie_domain from to)
+    pub proxy_cookie_domain: Vec<(String, String)>,
+    /// Замены префикса пути в Set-Cookie ответов upstream (proxy_cookie_path from to)
+    pub proxy_cookie_path: Vec<(String, String)>,
+    /// Буферизация ответа upstream целиком перед отправкой клиенту (proxy_buffering on)
+    pub proxy_buffering: bool,
+    /// Максимальный размер буферизуемого тела ответа (proxy_buffers_size)
+    pub proxy_buffers_size: usize,
+    /// Отдельный access log location (перекрывает access_log сервера)
+    pub access_log: Option<AccessLogDirective>,
+    /// Профиль бэкенда из backend_profiles (backend_profile zitadel)
+    pub backend_profile: Option<String>,
+    /// Время хранения ответов по Idempotency-Key (idempotency 24h; off - выключено)
+    pub idempotency: Option<Duration>,
+    /// Максимальный размер сохраняемого тела ответа (idempotency_max_body)
+    pub idempotency_max_body: usize,
+    /// Заглушка при недоступности upstream (fallback_response file=... when=...)
+    pub fallback_response: Option<FallbackResponse>,
+    /// Условия перехода к следующему бэкенду (proxy_next_upstream error timeout http_502)
+    pub proxy_next_upstream: NextUpstream,
+    /// Максимум попыток обращения к upstream, включая первую (proxy_next_upstream_tries; 0 - как global.max_retries)
+    pub proxy_next_upstream_tries: Option<u32>,
+    /// Каталог статических файлов location (root)
+    pub root: Option<String>,
+    /// Отдавать file.gz вместо file при Accept-Encoding: gzip (gzip_static on)
+    pub gzip_static: bool,
+    /// Отдавать file.br вместо file при Accept-Encoding: br (brotli_static on)
+    pub brotli_static: bool,
+    /// Маскирование путей redaction.paths в JSON телах запросов и ответов (redact on)
+    pub redact: bool,
+    /// Алгоритмы сжатия ответов location (compression_algorithms gzip br; off - без сжатия)
+    pub compression_algorithms: Option<Vec<CompressionAlgorithm>>,
+    /// Ограничения частоты запросов по зонам limit_req_zone (limit_req zone=api burst=20)
+    pub limit_req: Vec<LimitReq>,
+    /// Ограничения одновременных запросов по зонам limit_conn_zone (limit_conn addr 10)
+    pub limit_conn: Vec<LimitConn>,
+    /// Заголовки ответа клиенту; значения могут содержать переменные (add_header)
+    pub add_header: Vec<AddHeader>,
+    /// Заголовки запроса к upstream; пустое значение удаляет заголовок (proxy_set_header)
+    pub proxy_set_header: Vec<(String, String)>,
+    /// Ответ прокси вместо проксирования (return 403; return 301 https://$host$uri;)
+    pub return_directive: Option<ReturnDirective>,
+    /// Заголовки о клиенте для upstream (forwarded_headers x_forwarded | forwarded | both)
+    pub forwarded_headers: ForwardedHeaders,
+    /// WASM фильтр запросов (wasm_filter /etc/adq-pingora/filters/tenant.wasm on_failure=open)
+    pub wasm_filter: Option<WasmFilterDirective>,
+    /// Проверка клиентов страницей с proof-of-work перед проксированием (challenge_mode on)
+    pub challenge_mode: bool,
+    /// Кеширование ответов location; `proxy_cache off` отключает его
+    pub proxy_cache: bool,
+    /// Время кеширования по статусам ответа; вместо правил cache.rules (proxy_cache_valid)
+    pub proxy_cache_valid: Vec<CacheValid>,
+    /// Ответ не сохраняется в кеш, если значение не пустое и не "0" (proxy_no_cache $arg_nocache)
+    pub proxy_no_cache: Vec<String>,
+    /// Начало блока `location` в файле
+    pub pos: SourcePos,
+}
+
+/// Какие заголовки о клиенте получает upstream
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ForwardedHeaders {
+    /// X-Forwarded-For, X-Forwarded-Proto и др. (proxy_headers)
+    #[default]
+    XForwarded,
+    /// Forwarded по RFC 7239
+    Forwarded,
+    Both,
+}
+
+impl ForwardedHeaders {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim() {
+            "x_forwarded" => Ok(Self::XForwarded),
+            "forwarded" => Ok(Self::Forwarded),
+            "both" => Ok(Self::Both),
+            other => Err(format!("invalid forwarded_headers value: {}", other)),
+        }
+    }
+
+    pub fn x_forwarded(self) -> bool {
+        matches!(self, Self::XForwarded | Self::Both)
+    }
+
+    pub fn forwarded(self) -> bool {
+        matches!(self, Self::Forwarded | Self::Both)
+    }
+}
+
+/// Директива `wasm_filter <path> [on_failure=open|closed];`
+#[derive(Debug, Clone, PartialEq)]
+pub struct WasmFilterDirective {
+    /// Путь к модулю (.wasm или текстовый .wat)
+    pub path: String,
+    /// Пропускать запрос при ошибке фильтра (on_failure=open); по умолчанию - 500
+    pub fail_open: bool,
+}
+
+impl WasmFilterDirective {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let invalid = || format!("invalid wasm_filter: {}", args.join(" "));
+        let (path, options) = args.split_first().ok_or_else(invalid)?;
+        let mut fail_open = false;
+        for option in options {
+            fail_open = match option.as_str() {
+                "on_failure=open" => true,
+                "on_failure=closed" => false,
+                _ => return Err(invalid()),
+            };
+        }
+        Ok(Self { path: path.clone(), fail_open })
+    }
+}
+
+/// Директива `proxy_cache_valid [code ...|any] time;`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheValid {
+    /// Статусы ответа; пусто - любой статус (any)
+    pub statuses: Vec<u16>,
+    pub ttl: Duration,
+}
+
+impl CacheValid {
+    /// Без кодов, как в nginx, время действует для 200, 301 и 302
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let invalid = || format!("invalid proxy_cache_valid: {}", args.join(" "));
+        let (time, codes) = args.split_last().ok_or_else(invalid)?;
+        let ttl = parse_duration(time).ok_or_else(invalid)?;
+        let statuses = if codes.is_empty() {
+            vec![200, 301, 302]
+        } else if codes == ["any"] {
+            Vec::new()
+        } else {
+            codes
+                .iter()
+                .map(|code| code.parse::<u16>().ok().filter(|code| (100..600).contains(code)).ok_or_else(invalid))
+                .collect::<Result<_, _>>()?
+        };
+        Ok(Self { statuses, ttl })
+    }
+
+    pub fn matches(&self, status: u16) -> bool {
+        self.statuses.is_empty() || self.statuses.contains(&status)
+    }
+}
+
+/// Директива `add_header Name value [always];`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddHeader {
+    pub name: String,
+    pub value: String,
+    /// Добавлять и к ответам с ошибкой (без always - только 2xx и 3xx, как в nginx)
+    pub always: bool,
+}
+
+/// Директива `return code [text|URL];` или `return URL;` (302)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReturnDirective {
+    pub status: u16,
+    /// Location для редиректов (301, 302, 303, 307, 308), тело для остальных статусов
+    pub value: Option<String>,
+}
+
+impl ReturnDirective {
+    pub fn is_redirect(&self) -> bool {
+        matches!(self.status, 301 | 302 | 303 | 307 | 308)
+    }
+}
+
+/// Блок `map $source $variable { default ""; value result; ~regex result; }`.
+/// Точные значения проверяются первыми, затем регулярные выражения по порядку;
+/// результат может содержать переменные и группы регулярного выражения ($1, $name)
+#[derive(Debug, Clone)]
+pub struct MapBlock {
+    /// Исходная переменная без `$` (http_origin, host)
+    pub source: String,
+    /// Определяемая переменная без `$`
+    pub variable: String,
+    pub default: String,
+    pub exact: HashMap<String, String>,
+    pub patterns: Vec<(Regex, String)>,
+    /// Начало блока `map` в файле
+    pub pos: SourcePos,
+}
+
+impl MapBlock {
+    /// Результат для значения исходной переменной и совпадение регулярного выражения
+    pub fn find<'a, 'v>(&'a self, value: &'v str) -> (&'a str, Option<regex::Captures<'v>>) {
+        if let Some(result) = self.exact.get(value) {
+            return (result, None);
+        }
+        for (pattern, result) in &self.patterns {
+            if let Some(captures) = pattern.captures(value) {
+                return (result, Some(captures));
+            }
+        }
+        (&self.default, None)
+    }
+}
+
+/// Размер буфера ответа по умолчанию (proxy_buffers_size)
+pub const DEFAULT_PROXY_BUFFERS_SIZE: usize = 1024 * 1024;
+
+/// Максимальный размер сохраняемого тела ответа по умолчанию (idempotency_max_body)
+pub const DEFAULT_IDEMPOTENCY_MAX_BODY: usize = 64 * 1024;
+
+/// Директива `proxy_next_upstream error timeout http_502 http_504 non_idempotent;`
+#[derive(Debug, Clone, PartialEq)]
+pub struct NextUpstream {
+    /// Ошибка соединения с бэкендом или чтения его ответа
+    pub error: bool,
+    /// Таймаут соединения или ожидания ответа
+    pub timeout: bool,
+    /// Статусы ответа бэкенда (http_502 -> 502)
+    pub statuses: Vec<u16>,
+    /// Повторять и неидемпотентные запросы (POST, PATCH) после отправки бэкенду
+    pub non_idempotent: bool,
+}
+
+/// Статусы, допустимые в proxy_next_upstream (как в nginx)
+const NEXT_UPSTREAM_STATUSES: [u16; 7] = [500, 502, 503, 504, 403, 404, 429];
+
+impl Default for NextUpstream {
+    /// Как в nginx: error timeout
+    fn default() -> Self {
+        Self {
+            error: true,
+            timeout: true,
+            statuses: Vec::new(),
+            non_idempotent: false,
+        }
+    }
+}
+
+impl NextUpstream {
+    /// proxy_next_upstream off
+    pub fn off() -> Self {
+        Self {
+            error: false,
+            timeout: false,
+            statuses: Vec::new(),
+            non_idempotent: false,
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        if value == "off" {
+            return Ok(Self::off());
+        }
+        let mut next = Self::off();
+        for token in value.split_whitespace() {
+            match token {
+                "error" => next.error = true,
+                "timeout" => next.timeout = true,
+                "non_idempotent" => next.non_idempotent = true,
+                _ => {
+                    let status = token
+                        .strip_prefix("http_")
+                        .and_then(|code| code.parse::<u16>().ok())
+                        .filter(|code| NEXT_UPSTREAM_STATUSES.contains(code))
+                        .ok_or_else(|| format!("invalid proxy_next_upstream value: {}", token))?;
+                    next.statuses.push(status);
+                }
+            }
+        }
+        Ok(next)
+    }
+
+    /// Переходить ли к следующему бэкенду после ответа с этим статусом
+    pub fn on_status(&self, status: u16) -> bool {
+        self.statuses.contains(&status)
+    }
+}
+
+/// Условие отдачи заглушки fallback_response
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FallbackCondition {
+    /// Статус ответа upstream или ошибки прокси (502, 503, 504)
+    Status(u16),
+    /// Не удалось установить соединение с upstream
+    ConnectError,
+    /// Истек таймаут ожидания ответа upstream
+    Timeout,
+    /// Circuit breaker upstream открыт
+    CircuitOpen,
+}
+
+/// Директива `fallback_response file=/path status=200 content_type=application/json when=502,503,504,connect_error;`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FallbackResponse {
+    pub file: String,
+    pub status: u16,
+    /// Content-Type заглушки (по умолчанию по расширению файла)
+    pub content_type: Option<String>,
+    pub when: Vec<FallbackCondition>,
+}
+
+impl FallbackResponse {
+    /// Условия по умолчанию, если when не указан
+    pub const DEFAULT_WHEN: &'static [FallbackCondition] = &[
+        FallbackCondition::Status(502),
+        FallbackCondition::Status(503),
+        FallbackCondition::Status(504),
+        FallbackCondition::ConnectError,
+        FallbackCondition::Timeout,
+        FallbackCondition::CircuitOpen,
+    ];
+
+    /// Подходит ли заглушка для условия. Открытый circuit breaker отвечает 503,
+    /// поэтому он покрывается и условием 503.
+    pub fn matches(&self, condition: FallbackCondition) -> bool {
+        self.when.contains(&condition)
+            || (condition == FallbackCondition::CircuitOpen && self.when.contains(&FallbackCondition::Status(503)))
+    }
+}
+
+/// Режим переписывания редиректов upstream
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum ProxyRedirect {
+    /// Адрес upstream заменяется на публичные scheme://host запроса
+    #[default]
+    Default,
+    /// Переписывание отключено (proxy_redirect off)
+    Off,
+    /// Явные замены префикса URL (proxy_redirect from to)
+    Replace(Vec<(String, String)>),
 }
 
 #[derive(Debug, Clone)]
@@ -40,10 +511,146 @@ pub struct RateLimit {
     pub burst: u32,
 }
 
+/// rate_limit, действующий в окна расписания из секции schedules
+#[derive(Debug, Clone)]
+pub struct ScheduledRateLimit {
+    pub schedule: String,
+    pub limit: RateLimit,
+}
+
+/// Ключ зоны limit_req_zone / limit_conn_zone
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitKey {
+    /// Адрес клиента ($binary_remote_addr, $remote_addr)
+    RemoteAddr,
+    /// Значение заголовка запроса ($http_x_api_key -> x-api-key)
+    Header(String),
+    /// Host запроса ($host, $server_name)
+    Host,
+}
+
+impl LimitKey {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "$binary_remote_addr" | "$remote_addr" => Ok(LimitKey::RemoteAddr),
+            "$host" | "$server_name" => Ok(LimitKey::Host),
+            _ => value
+                .strip_prefix("$http_")
+                .filter(|name| !name.is_empty())
+                .map(|name| LimitKey::Header(name.replace('_', "-")))
+                .ok_or_else(|| {
+                    format!(
+                        "unsupported limit zone key: {} (expected $binary_remote_addr, $remote_addr, $http_<name>, $host or $server_name)",
+                        value
+                    )
+                }),
+        }
+    }
+
+    /// Тип ключа для метрик
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LimitKey::RemoteAddr => "ip",
+            LimitKey::Header(_) => "header",
+            LimitKey::Host => "host",
+        }
+    }
+}
+
+/// Директива `limit_req_zone $binary_remote_addr zone=api:10m rate=10r/s;`.
+/// Размер зоны принимается для совместимости, счетчики хранятся в памяти процесса
+#[derive(Debug, Clone, PartialEq)]
+pub struct LimitReqZone {
+    pub name: String,
+    pub key: LimitKey,
+    /// Запросов за period
+    pub rate: u32,
+    /// Окно счетчика: секунда (r/s) или минута (r/m)
+    pub period: Duration,
+}
+
+/// Директива `limit_conn_zone $binary_remote_addr zone=addr:10m;`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LimitConnZone {
+    pub name: String,
+    pub key: LimitKey,
+}
+
+/// Директива `limit_req zone=api [burst=20] [nodelay];`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LimitReq {
+    pub zone: String,
+    /// Запросы сверх rate, принимаемые в том же окне (без задержки, как с nodelay)
+    pub burst: u32,
+    /// Указан `nodelay`; без него nginx задерживает запросы в пределах burst, а мы нет
+    pub nodelay: bool,
+}
+
+/// Директива `limit_conn addr 10;`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LimitConn {
+    pub zone: String,
+    pub max: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolverDirective {
+    /// DNS серверы (порт по умолчанию 53)
+    pub nameservers: Vec<SocketAddr>,
+    /// Время кэширования ответов вместо TTL записей (valid=)
+    pub valid: Option<Duration>,
+    /// Таймаут одного разрешения имени (timeout=)
+    pub timeout: Option<Duration>,
+}
+
 #[derive(Debug, Clone)]
 pub struct UpstreamBlock {
     pub name: String,
     pub servers: Vec<UpstreamServer>,
+    /// Выбор бэкенда: round robin, least_requests (power of two choices) или hash
+    pub balancing: Balancing,
+    /// Ключ консистентного хеширования с переменными (hash $request_uri;)
+    pub hash_key: Option<String>,
+    /// Максимум одновременных запросов к upstream (max_conns)
+    pub max_conns: Option<usize>,
+    /// Очередь запросов сверх max_conns (queue N timeout=T или queue depth=N timeout=T)
+    pub queue: Option<UpstreamQueue>,
+    /// Неявный upstream из `proxy_pass http://host:port` без блока upstream
+    pub implicit: bool,
+    /// Начало блока `upstream` в файле (для неявного - location с proxy_pass)
+    pub pos: SourcePos,
+}
+
+/// Очередь ожидания свободного слота upstream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpstreamQueue {
+    pub size: usize,
+    pub timeout: Duration,
+}
+
+/// Ожидание в очереди по умолчанию, как в nginx
+const DEFAULT_QUEUE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Алгоритм выбора бэкенда upstream
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Balancing {
+    /// Взвешенный round robin (по умолчанию)
+    #[default]
+    RoundRobin,
+    /// Из двух случайных бэкендов (с учетом весов) - с меньшим числом запросов в обработке (least_requests)
+    LeastRequests,
+    /// Консистентное хеширование по ключу запроса (hash $key)
+    Hash,
+}
+
+impl Balancing {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Balancing::RoundRobin => "round_robin",
+            Balancing::LeastRequests => "least_requests",
+            Balancing::Hash => "hash",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -54,14 +661,20 @@ pub struct UpstreamServer {
 
 impl NginxConfig {
     /// Загружает все конфиги из директории sites-enabled
-    pub fn load_from_sites_enabled<P: AsRef<Path>>(sites_enabled_dir: P) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn load_from_sites_enabled<P: AsRef<Path>>(sites_enabled_dir: P) -> Result<Self, ProxyError> {
         let mut servers = Vec::new();
         let mut upstreams = HashMap::new();
+        let mut resolver = None;
+        let mut limit_req_zones = HashMap::new();
+        let mut limit_conn_zones = HashMap::new();
+        let mut maps = HashMap::new();
+        let mut issues = Vec::new();
 
-        let dir = fs::read_dir(sites_enabled_dir)?;
+        let sites_enabled_dir = sites_enabled_dir.as_ref();
+        let dir = fs::read_dir(sites_enabled_dir).map_err(ProxyError::io(sites_enabled_dir))?;
         
         for entry in dir {
-            let entry = entry?;
+            let entry = entry.map_err(ProxyError::io(sites_enabled_dir))?;
             let path = entry.path();
             
             if path.is_file() {
@@ -70,38 +683,69 @@ impl NginxConfig {
                         info!("Loaded config from: {}", path.display());
                         servers.extend(config.servers);
                         upstreams.extend(config.upstreams);
+                        limit_req_zones.extend(config.limit_req_zones);
+                        limit_conn_zones.extend(config.limit_conn_zones);
+                        maps.extend(config.maps);
+                        issues.extend(config.issues);
+                        if resolver.is_none() {
+                            resolver = config.resolver;
+                        }
                     }
                     Err(e) => {
                         error!("Failed to parse config {}: {}", path.display(), e);
+                        issues.push(ParseIssue::from_error(&path, &e));
                     }
                 }
             }
         }
 
-        Ok(NginxConfig { servers, upstreams })
+        let server_index = ServerIndex::build(&servers);
+        let mut config = NginxConfig { servers, upstreams, resolver, limit_req_zones, limit_conn_zones, maps, server_index, issues };
+        // Блоки upstream могут быть объявлены в других файлах
+        config.add_implicit_upstreams();
+        Ok(config)
     }
 
     /// Парсит один конфигурационный файл
-    pub fn parse_config_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
-        let content = fs::read_to_string(path)?;
-        Self::parse_config_content(&content)
+    pub fn parse_config_file<P: AsRef<Path>>(path: P) -> Result<Self, ProxyError> {
+        let content = fs::read_to_string(&path).map_err(ProxyError::io(&path))?;
+        let file = path.as_ref().display().to_string();
+        Self::parse_source(&content, Some(&file))
     }
 
     /// Парсит содержимое конфига
-    pub fn parse_config_content(content: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn parse_config_content(content: &str) -> Result<Self, ProxyError> {
+        let mut config = Self::parse_source(content, None)?;
+        config.add_implicit_upstreams();
+        Ok(config)
+    }
+
+    /// Парсит конфиг; места блоков и ошибок указываются в файле `file`
+    fn parse_source(content: &str, file: Option<&str>) -> Result<Self, ProxyError> {
         let mut servers = Vec::new();
         let mut upstreams = HashMap::new();
+        let mut issues = Vec::new();
 
         // Удаляем комментарии
         let content = Self::remove_comments(content);
+        let source = Source { text: &content, file };
         
         // Парсим server блоки
         let server_regex = Regex::new(r"server\s*\{([^{}]*(?:\{[^{}]*\}[^{}]*)*)\}")?;
+        // limit_req уровня http (вне server блоков) наследуется серверами без своих limit_req
+        let http_limit_req = Self::parse_limit_req_directives(&server_regex.replace_all(&content, ""))?;
         for cap in server_regex.captures_iter(&content) {
-            if let Some(server_content) = cap.get(1) {
-                match Self::parse_server_block(server_content.as_str()) {
-                    Ok(server) => servers.push(server),
-                    Err(e) => warn!("Failed to parse server block: {}", e),
+            if let (Some(whole), Some(server_content)) = (cap.get(0), cap.get(1)) {
+                match Self::parse_server_block(&source, server_content, &http_limit_req, &mut issues) {
+                    Ok(mut server) => {
+                        server.pos = source.pos(whole.start());
+                        servers.push(server);
+                    }
+                    Err(e) => {
+                        let pos = source.pos(whole.start());
+                        warn!("Failed to parse server block at {}: {}", pos, e);
+                        issues.push(ParseIssue::at(pos, format!("server block skipped: {}", e)));
+                    }
                 }
             }
         }
@@ -109,31 +753,168 @@ impl NginxConfig {
         // Парсим upstream блоки
         let upstream_regex = Regex::new(r"upstream\s+(\w+)\s*\{([^{}]*)\}")?;
         for cap in upstream_regex.captures_iter(&content) {
-            if let (Some(name), Some(upstream_content)) = (cap.get(1), cap.get(2)) {
-                match Self::parse_upstream_block(name.as_str(), upstream_content.as_str()) {
-                    Ok(upstream) => {
+            if let (Some(whole), Some(name), Some(upstream_content)) = (cap.get(0), cap.get(1), cap.get(2)) {
+                let body = upstream_content.as_str();
+                match Self::parse_upstream_block(name.as_str(), body) {
+                    Ok(mut upstream) => {
+                        upstream.pos = source.pos(whole.start());
                         upstreams.insert(upstream.name.clone(), upstream);
                     }
-                    Err(e) => warn!("Failed to parse upstream block {}: {}", name.as_str(), e),
+                    Err(e) => {
+                        let offset = failing_directive(body, |prefix| Self::parse_upstream_block(name.as_str(), prefix))
+                            .map_or(whole.start(), |offset| upstream_content.start() + offset);
+                        let pos = source.pos(offset);
+                        warn!("Failed to parse upstream block at {}: {}", pos, e);
+                        issues.push(ParseIssue::at(pos, format!("upstream block skipped: {}", e)));
+                    }
                 }
             }
         }
 
-        Ok(NginxConfig { servers, upstreams })
+        // Парсим директиву resolver
+        let resolver_regex = Regex::new(r"(?:^|\s)resolver\s+([^;]+);")?;
+        let resolver = match resolver_regex.captures(&content).and_then(|cap| cap.get(1)) {
+            Some(value) => Some(Self::parse_resolver_directive(value.as_str()).map_err(source.located(value.start()))?),
+            None => None,
+        };
+
+        // Зоны ограничений запросов и одновременных запросов
+        let mut limit_req_zones = HashMap::new();
+        let limit_req_zone_regex = Regex::new(r"(?:^|\s)limit_req_zone\s+([^;]+);")?;
+        for cap in limit_req_zone_regex.captures_iter(&content) {
+            if let Some(args) = cap.get(1) {
+                let zone = Self::parse_limit_req_zone(args.as_str()).map_err(source.located(args.start()))?;
+                limit_req_zones.insert(zone.name.clone(), zone);
+            }
+        }
+        let mut limit_conn_zones = HashMap::new();
+        let limit_conn_zone_regex = Regex::new(r"(?:^|\s)limit_conn_zone\s+([^;]+);")?;
+        for cap in limit_conn_zone_regex.captures_iter(&content) {
+            if let Some(args) = cap.get(1) {
+                let (name, key) = Self::parse_limit_zone(args.as_str()).map_err(source.located(args.start()))?;
+                limit_conn_zones.insert(name.clone(), LimitConnZone { name, key });
+            }
+        }
+
+        let maps = Self::parse_maps(&source)?;
+
+        let server_index = ServerIndex::build(&servers);
+        Ok(NginxConfig { servers, upstreams, resolver, limit_req_zones, limit_conn_zones, maps, server_index, issues })
+    }
+
+    /// Блоки map уровня http. Тело блока ищется с учетом кавычек:
+    /// регулярные выражения в кавычках могут содержать `{` и `}`
+    fn parse_maps(source: &Source) -> Result<HashMap<String, MapBlock>, ProxyError> {
+        let content = source.text;
+        let mut maps = HashMap::new();
+        let map_regex = Regex::new(r"(?:^|[\s;}])map\s+\$(\w+)\s+\$(\w+)\s*\{")?;
+        for cap in map_regex.captures_iter(content) {
+            let (Some(source_variable), Some(variable), Some(whole)) = (cap.get(1), cap.get(2), cap.get(0)) else {
+                continue;
+            };
+            // Совпадение начинается с разделителя перед `map`
+            let start = whole.start() + whole.as_str().find("map").unwrap_or(0);
+            let body = &content[whole.end()..];
+            let end = find_unquoted(body, '}').ok_or_else(|| {
+                source.located(start)(format!("unterminated map block for ${}", variable.as_str()).into())
+            })?;
+            let mut map = Self::parse_map_block(source_variable.as_str(), variable.as_str(), &body[..end])
+                .map_err(source.located(start))?;
+            map.pos = source.pos(start);
+            if maps.insert(map.variable.clone(), map).is_some() {
+                return Err(source.located(start)(format!("duplicate map for ${}", variable.as_str()).into()));
+            }
+        }
+        Ok(maps)
+    }
+
+    /// Записи map: `default value;`, `value result;`, `~regex result;`, `~*regex result;`
+    fn parse_map_block(source: &str, variable: &str, body: &str) -> Result<MapBlock, ProxyError> {
+        let mut map = MapBlock {
+            source: source.to_string(),
+            variable: variable.to_string(),
+            default: String::new(),
+            exact: HashMap::new(),
+            patterns: Vec::new(),
+            pos: SourcePos::default(),
+        };
+        let mut rest = body;
+        while let Some(end) = find_unquoted(rest, ';') {
+            let entry = &rest[..end];
+            rest = &rest[end + 1..];
+            let parts = directive_args(entry);
+            let [key, value] = parts.as_slice() else {
+                return Err(format!("invalid map entry in ${}: {}", variable, entry.trim()).into());
+            };
+            if key == "default" {
+                map.default = value.clone();
+            } else if let Some(pattern) = key.strip_prefix("~*") {
+                let regex = Regex::new(&format!("(?i){}", pattern))
+                    .map_err(|e| format!("invalid regex in map ${}: {}", variable, e))?;
+                map.patterns.push((regex, value.clone()));
+            } else if let Some(pattern) = key.strip_prefix('~') {
+                let regex = Regex::new(pattern).map_err(|e| format!("invalid regex in map ${}: {}", variable, e))?;
+                map.patterns.push((regex, value.clone()));
+            } else {
+                // `\~value` - точное значение, начинающееся с `~`
+                let key = key.strip_prefix('\\').unwrap_or(key);
+                map.exact.insert(key.to_string(), value.clone());
+            }
+        }
+        if !rest.trim().is_empty() {
+            return Err(format!("invalid map entry in ${}: {}", variable, rest.trim()).into());
+        }
+        Ok(map)
+    }
+
+    /// Ключ и имя зоны: `$binary_remote_addr zone=api:10m ...`
+    fn parse_limit_zone(args: &str) -> Result<(String, LimitKey), ProxyError> {
+        let mut parts = args.split_whitespace();
+        let key = LimitKey::parse(parts.next().unwrap_or_default())?;
+        let name = parts
+            .find_map(|part| part.strip_prefix("zone="))
+            .and_then(|zone| zone.split(':').next())
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| format!("limit zone without name: {}", args))?;
+        Ok((name.to_string(), key))
     }
 
-    /// Удаляет комментарии из конфига
+    /// Парсит `limit_req_zone $binary_remote_addr zone=api:10m rate=10r/s;`
+    fn parse_limit_req_zone(args: &str) -> Result<LimitReqZone, ProxyError> {
+        let (name, key) = Self::parse_limit_zone(args)?;
+        let rate = args
+            .split_whitespace()
+            .find_map(|part| part.strip_prefix("rate="))
+            .ok_or_else(|| format!("limit_req_zone {} without rate", name))?;
+        let invalid = || format!("invalid limit_req_zone rate: {}", rate);
+        let (rate, period) = if let Some(per_second) = rate.strip_suffix("r/s") {
+            (per_second, Duration::from_secs(1))
+        } else if let Some(per_minute) = rate.strip_suffix("r/m") {
+            (per_minute, Duration::from_secs(60))
+        } else {
+            return Err(invalid().into());
+        };
+        let rate = rate.parse::<u32>().ok().filter(|rate| *rate > 0).ok_or_else(invalid)?;
+        Ok(LimitReqZone { name, key, rate, period })
+    }
+
+    /// Удаляет комментарии из конфига; строки и колонки остаются на прежних местах
     fn remove_comments(content: &str) -> String {
         let comment_regex = Regex::new(r"#.*$").unwrap();
         content.lines()
-            .map(|line| comment_regex.replace(line, "").trim().to_string())
-            .filter(|line| !line.is_empty())
+            .map(|line| comment_regex.replace(line, ""))
             .collect::<Vec<_>>()
             .join("\n")
     }
 
-    /// Парсит server блок
-    fn parse_server_block(content: &str) -> Result<ServerBlock, Box<dyn std::error::Error>> {
+    /// Парсит server блок; location с ошибками пропускаются и попадают в `issues`
+    fn parse_server_block(
+        source: &Source,
+        body: regex::Match,
+        http_limit_req: &[LimitReq],
+        issues: &mut Vec<ParseIssue>,
+    ) -> Result<ServerBlock, ProxyError> {
+        let content = body.as_str();
         let mut listen_ports = Vec::new();
         let mut server_names = Vec::new();
         let mut ssl_certificate = None;
@@ -174,38 +955,85 @@ impl NginxConfig {
 
         // Парсим location блоки
         let location_regex = Regex::new(r"location\s+([^\s{]+)\s*\{([^{}]*)\}")?;
+
+        // access_log сервера ищется вне location блоков
+        let server_level = location_regex.replace_all(content, "");
+        let access_log = Self::parse_access_log_directive(&server_level)?;
+        // Как в nginx: limit_req уровня наследуются, только если на нижнем уровне их нет
+        let mut server_limit_req = Self::parse_limit_req_directives(&server_level)?;
+        if server_limit_req.is_empty() {
+            server_limit_req = http_limit_req.to_vec();
+        }
+
         for cap in location_regex.captures_iter(content) {
-            if let (Some(path), Some(location_content)) = (cap.get(1), cap.get(2)) {
-                match Self::parse_location_block(path.as_str(), location_content.as_str()) {
-                    Ok(location) => locations.push(location),
-                    Err(e) => warn!("Failed to parse location block {}: {}", path.as_str(), e),
+            if let (Some(whole), Some(path), Some(location_content)) = (cap.get(0), cap.get(1), cap.get(2)) {
+                let location_body = location_content.as_str();
+                match Self::parse_location_block(path.as_str(), location_body) {
+                    Ok(mut location) => {
+                        location.pos = source.pos(body.start() + whole.start());
+                        if location.limit_req.is_empty() {
+                            location.limit_req = server_limit_req.clone();
+                        }
+                        locations.push(location);
+                    }
+                    Err(e) => {
+                        // Ошибка указывает на директиву, а не на начало блока
+                        let offset = failing_directive(location_body, |prefix| Self::parse_location_block(path.as_str(), prefix))
+                            .map_or(whole.start(), |offset| location_content.start() + offset);
+                        let pos = source.pos(body.start() + offset);
+                        warn!("Failed to parse location block {} at {}: {}", path.as_str(), pos, e);
+                        issues.push(ParseIssue::at(pos, format!("location {} skipped: {}", path.as_str(), e)));
+                    }
                 }
             }
         }
 
+        let location_index = LocationIndex::build(&locations);
         Ok(ServerBlock {
             listen_ports,
             server_names,
             ssl_certificate,
             ssl_certificate_key,
             locations,
+            access_log,
+            location_index,
+            pos: SourcePos::default(),
         })
     }
 
     /// Парсит listen директиву
-    fn parse_listen_directive(listen_str: &str) -> Result<ListenDirective, Box<dyn std::error::Error>> {
+    fn parse_listen_directive(listen_str: &str) -> Result<ListenDirective, ProxyError> {
         let parts: Vec<&str> = listen_str.split_whitespace().collect();
-        let port_str = parts[0];
-        
-        let port = port_str.parse::<u16>()?;
+        let (address, port) = Self::parse_listen_address(parts[0])?;
         let ssl = parts.contains(&"ssl");
         let http2 = parts.contains(&"http2");
+        let proxy_protocol = parts.contains(&"proxy_protocol");
+        let default_server = parts.contains(&"default_server");
+        let ipv6only = match parts.iter().find_map(|part| part.strip_prefix("ipv6only=")) {
+            Some("on") | None => true,
+            Some("off") => false,
+            Some(other) => return Err(format!("invalid ipv6only: {}", other).into()),
+        };
 
-        Ok(ListenDirective { port, ssl, http2 })
+        Ok(ListenDirective { address, port, ssl, http2, proxy_protocol, ipv6only, default_server })
+    }
+
+    /// Адрес listen: `80`, `*:80`, `127.0.0.1:80`, `[::]:80`, `[::1]:80`
+    fn parse_listen_address(value: &str) -> Result<(Option<IpAddr>, u16), ProxyError> {
+        let Some((host, port)) = value.rsplit_once(':') else {
+            return Ok((None, value.parse::<u16>()?));
+        };
+        let port = port.parse::<u16>()?;
+        if host == "*" {
+            return Ok((None, port));
+        }
+        let host = host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host);
+        let address = host.parse::<IpAddr>().map_err(|_| format!("invalid listen address: {}", value))?;
+        Ok((Some(address), port))
     }
 
     /// Парсит location блок
-    fn parse_location_block(path: &str, content: &str) -> Result<LocationBlock, Box<dyn std::error::Error>> {
+    pub(crate) fn parse_location_block(path: &str, content: &str) -> Result<LocationBlock, ProxyError> {
         let mut proxy_pass = None;
         let mut rate_limit = None;
         let mut cors_enable = false;
@@ -213,8 +1041,9 @@ impl NginxConfig {
         // Парсим proxy_pass
         let proxy_pass_regex = Regex::new(r"proxy_pass\s+([^;]+);")?;
         if let Some(cap) = proxy_pass_regex.captures(content) {
-            proxy_pass = cap.get(1).map(|m| m.as_str().to_string());
+            proxy_pass = cap.get(1).map(|m| m.as_str().trim().to_string());
         }
+        let proxy_pass_url = proxy_pass.as_deref().map(ProxyPassUrl::parse).transpose()?.flatten();
 
         // Парсим rate_limit
         let rate_limit_regex = Regex::new(r"rate_limit\s+(\d+)\s+(\d+);")?;
@@ -229,19 +1058,467 @@ impl NginxConfig {
             }
         }
 
+        // rate_limit на время окна расписания
+        let scheduled_rate_limit_regex = Regex::new(r"rate_limit\s+(\d+)\s+(\d+)\s+schedule=([\w-]+)\s*;")?;
+        let mut rate_limit_schedules = Vec::new();
+        for cap in scheduled_rate_limit_regex.captures_iter(content) {
+            rate_limit_schedules.push(ScheduledRateLimit {
+                schedule: cap[3].to_string(),
+                limit: RateLimit {
+                    requests_per_second: cap[1].parse()?,
+                    burst: cap[2].parse()?,
+                },
+            });
+        }
+
         // Проверяем cors_enable
         cors_enable = content.contains("cors_enable");
 
+        // Парсим таймауты upstream
+        let proxy_connect_timeout = Self::parse_timeout_directive(content, "proxy_connect_timeout")?;
+        let proxy_read_timeout = Self::parse_timeout_directive(content, "proxy_read_timeout")?;
+        let proxy_send_timeout = Self::parse_timeout_directive(content, "proxy_send_timeout")?;
+        let proxy_first_byte_timeout = Self::parse_timeout_directive(content, "proxy_first_byte_timeout")?;
+        let proxy_request_timeout = Self::parse_timeout_directive(content, "proxy_request_timeout")?;
+
+        // Парсим перехват ошибок upstream
+        let intercept_regex = Regex::new(r"proxy_intercept_errors\s+(on|off);")?;
+        let intercept_errors = intercept_regex
+            .captures(content)
+            .and_then(|cap| cap.get(1))
+            .is_some_and(|m| m.as_str() == "on");
+
+        let mut error_pages = HashMap::new();
+        let error_page_regex = Regex::new(r"error_page\s+([^;]+);")?;
+        for cap in error_page_regex.captures_iter(content) {
+            if let Some(args) = cap.get(1) {
+                let parts: Vec<&str> = args.as_str().split_whitespace().collect();
+                if let Some((page, codes)) = parts.split_last() {
+                    for code in codes {
+                        let status = code.parse::<u16>()
+                            .map_err(|_| format!("invalid error_page status: {}", code))?;
+                        error_pages.insert(status, page.to_string());
+                    }
+                }
+            }
+        }
+
+        let mut status_map = HashMap::new();
+        let status_map_regex = Regex::new(r"status_map\s+([^;]+);")?;
+        for cap in status_map_regex.captures_iter(content) {
+            if let Some(args) = cap.get(1) {
+                for pair in args.as_str().split_whitespace() {
+                    let (from, to) = pair.split_once('=')
+                        .ok_or_else(|| format!("invalid status_map entry: {}", pair))?;
+                    let from = from.parse::<u16>().map_err(|_| format!("invalid status_map entry: {}", pair))?;
+                    let to = to.parse::<u16>().map_err(|_| format!("invalid status_map entry: {}", pair))?;
+                    status_map.insert(from, to);
+                }
+            }
+        }
+
+        let mut redirect_rules = Vec::new();
+        let mut proxy_redirect = ProxyRedirect::Default;
+        let proxy_redirect_regex = Regex::new(r"proxy_redirect\s+([^;]+);")?;
+        for cap in proxy_redirect_regex.captures_iter(content) {
+            if let Some(args) = cap.get(1) {
+                let parts: Vec<&str> = args.as_str().split_whitespace().collect();
+                match parts.as_slice() {
+                    ["off"] => proxy_redirect = ProxyRedirect::Off,
+                    ["default"] => {}
+                    [from, to] => redirect_rules.push((from.to_string(), to.to_string())),
+                    _ => return Err(format!("invalid proxy_redirect: {}", args.as_str()).into()),
+                }
+            }
+        }
+        if !redirect_rules.is_empty() && proxy_redirect != ProxyRedirect::Off {
+            proxy_redirect = ProxyRedirect::Replace(redirect_rules);
+        }
+
+        let proxy_cookie_domain = Self::parse_replace_directive(content, "proxy_cookie_domain")?;
+        let proxy_cookie_path = Self::parse_replace_directive(content, "proxy_cookie_path")?;
+
+        // Буферизация ответов upstream
+        let buffering_regex = Regex::new(r"(?:^|\s)proxy_buffering\s+([^;]+);")?;
+        let proxy_buffering = match buffering_regex.captures(content).and_then(|cap| cap.get(1)) {
+            Some(value) => match value.as_str().trim() {
+                "on" => true,
+                "off" => false,
+                other => return Err(format!("invalid proxy_buffering value: {}", other).into()),
+            },
+            None => false,
+        };
+        let buffers_size_regex = Regex::new(r"(?:^|\s)proxy_buffers_size\s+([^;]+);")?;
+        let proxy_buffers_size = match buffers_size_regex.captures(content).and_then(|cap| cap.get(1)) {
+            Some(value) => parse_size(value.as_str())
+                .ok_or_else(|| format!("invalid proxy_buffers_size value: {}", value.as_str()))?,
+            None => DEFAULT_PROXY_BUFFERS_SIZE,
+        };
+
+        let access_log = Self::parse_access_log_directive(content)?;
+
+        let profile_regex = Regex::new(r"(?:^|\s)backend_profile\s+([^;\s]+)\s*;")?;
+        let backend_profile = profile_regex
+            .captures(content)
+            .and_then(|cap| cap.get(1))
+            .map(|m| m.as_str().to_string());
+
+        // Дедупликация запросов по Idempotency-Key
+        let idempotency_regex = Regex::new(r"(?:^|\s)idempotency\s+([^;]+);")?;
+        let idempotency = match idempotency_regex.captures(content).and_then(|cap| cap.get(1)) {
+            Some(value) if value.as_str().trim() == "off" => None,
+            Some(value) => Some(
+                parse_duration(value.as_str())
+                    .filter(|ttl| !ttl.is_zero())
+                    .ok_or_else(|| format!("invalid idempotency value: {}", value.as_str()))?,
+            ),
+            None => None,
+        };
+        let max_body_regex = Regex::new(r"(?:^|\s)idempotency_max_body\s+([^;]+);")?;
+        let idempotency_max_body = match max_body_regex.captures(content).and_then(|cap| cap.get(1)) {
+            Some(value) => parse_size(value.as_str())
+                .ok_or_else(|| format!("invalid idempotency_max_body value: {}", value.as_str()))?,
+            None => DEFAULT_IDEMPOTENCY_MAX_BODY,
+        };
+
+        let fallback_response = Self::parse_fallback_response(content)?;
+
+        // Переход к следующему бэкенду при ошибках и статусах ответа
+        let next_upstream_regex = Regex::new(r"(?:^|\s)proxy_next_upstream\s+([^;]+);")?;
+        let proxy_next_upstream = match next_upstream_regex.captures(content).and_then(|cap| cap.get(1)) {
+            Some(value) => NextUpstream::parse(value.as_str())?,
+            None => NextUpstream::default(),
+        };
+        let tries_regex = Regex::new(r"(?:^|\s)proxy_next_upstream_tries\s+([^;]+);")?;
+        let proxy_next_upstream_tries = match tries_regex.captures(content).and_then(|cap| cap.get(1)) {
+            Some(value) => Some(
+                value.as_str().trim().parse::<u32>()
+                    .map_err(|_| format!("invalid proxy_next_upstream_tries value: {}", value.as_str()))?,
+            ),
+            None => None,
+        };
+
+        // Статические файлы и их предварительно сжатые варианты (file.gz, file.br)
+        let root_regex = Regex::new(r"(?:^|\s)root\s+([^;]+);")?;
+        let root = root_regex
+            .captures(content)
+            .and_then(|cap| cap.get(1))
+            .map(|value| value.as_str().trim().to_string());
+        let gzip_static = Self::parse_switch(content, "gzip_static")?.unwrap_or(false);
+        let brotli_static = Self::parse_switch(content, "brotli_static")?.unwrap_or(false);
+        let redact = Self::parse_switch(content, "redact")?.unwrap_or(false);
+        let forwarded_headers_regex = Regex::new(r"(?:^|\s)forwarded_headers\s+([^;]+);")?;
+        let forwarded_headers = match forwarded_headers_regex.captures(content).and_then(|cap| cap.get(1)) {
+            Some(value) => ForwardedHeaders::parse(value.as_str())?,
+            None => ForwardedHeaders::default(),
+        };
+        let wasm_filter = match directive_occurrences(content, "wasm_filter")?.first() {
+            Some(args) => Some(WasmFilterDirective::parse(args)?),
+            None => None,
+        };
+        let challenge_mode_regex = Regex::new(r"(?:^|\s)challenge_mode\s+([^;]+);")?;
+        let challenge_mode = match challenge_mode_regex.captures(content).and_then(|cap| cap.get(1)) {
+            Some(value) => match value.as_str().trim() {
+                "on" => true,
+                "off" => false,
+                other => return Err(format!("invalid challenge_mode value: {}", other).into()),
+            },
+            None => false,
+        };
+
+        let proxy_cache = match directive_occurrences(content, "proxy_cache")?.first().map(Vec::as_slice) {
+            Some([value]) if value == "off" => false,
+            Some(args) => return Err(format!("invalid proxy_cache: {} (only 'off' is supported)", args.join(" ")).into()),
+            None => true,
+        };
+        let proxy_cache_valid = directive_occurrences(content, "proxy_cache_valid")?
+            .iter()
+            .map(|args| CacheValid::parse(args))
+            .collect::<Result<Vec<_>, _>>()?;
+        let proxy_no_cache = directive_occurrences(content, "proxy_no_cache")?.concat();
+
+        let limit_req = Self::parse_limit_req_directives(content)?;
+        let mut limit_conn = Vec::new();
+        let limit_conn_regex = Regex::new(r"(?:^|\s)limit_conn\s+([^;]+);")?;
+        for cap in limit_conn_regex.captures_iter(content) {
+            if let Some(args) = cap.get(1) {
+                let invalid = || format!("invalid limit_conn: {}", args.as_str());
+                let parts: Vec<&str> = args.as_str().split_whitespace().collect();
+                let [zone, max] = parts.as_slice() else {
+                    return Err(invalid().into());
+                };
+                let max = max.parse::<usize>().ok().filter(|max| *max > 0).ok_or_else(invalid)?;
+                limit_conn.push(LimitConn { zone: zone.to_string(), max });
+            }
+        }
+
+        let compression_regex = Regex::new(r"(?:^|\s)compression_algorithms\s+([^;]+);")?;
+        let compression_algorithms = match compression_regex.captures(content).and_then(|cap| cap.get(1)) {
+            Some(value) if value.as_str().trim() == "off" => Some(Vec::new()),
+            Some(value) => Some(
+                value
+                    .as_str()
+                    .split_whitespace()
+                    .map(|name| {
+                        CompressionAlgorithm::from_name(name)
+                            .ok_or_else(|| format!("invalid compression_algorithms value: {}", name))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            None => None,
+        };
+
+        let mut add_header = Vec::new();
+        for args in directive_occurrences(content, "add_header")? {
+            match args.as_slice() {
+                [name, value] => add_header.push(AddHeader { name: name.clone(), value: value.clone(), always: false }),
+                [name, value, always] if always == "always" => {
+                    add_header.push(AddHeader { name: name.clone(), value: value.clone(), always: true })
+                }
+                _ => return Err(format!("invalid add_header: {}", args.join(" ")).into()),
+            }
+        }
+        let mut proxy_set_header = Vec::new();
+        for args in directive_occurrences(content, "proxy_set_header")? {
+            let [name, value] = args.as_slice() else {
+                return Err(format!("invalid proxy_set_header: {}", args.join(" ")).into());
+            };
+            proxy_set_header.push((name.clone(), value.clone()));
+        }
+        let return_directive = match directive_occurrences(content, "return")?.first().map(Vec::as_slice) {
+            Some([code]) if code.parse::<u16>().is_ok() => Some(ReturnDirective { status: code.parse()?, value: None }),
+            Some([url]) if url.starts_with("http://") || url.starts_with("https://") || url.starts_with('$') => {
+                Some(ReturnDirective { status: 302, value: Some(url.clone()) })
+            }
+            Some([code, value]) => {
+                let status = code.parse::<u16>().ok().filter(|s| (200..600).contains(s) || *s == 444)
+                    .ok_or_else(|| format!("invalid return status: {}", code))?;
+                Some(ReturnDirective { status, value: Some(value.clone()) })
+            }
+            Some(args) => return Err(format!("invalid return: {}", args.join(" ")).into()),
+            None => None,
+        };
+        if let Some(ReturnDirective { status, value: None }) = &return_directive {
+            if !(200..600).contains(status) && *status != 444 {
+                return Err(format!("invalid return status: {}", status).into());
+            }
+        }
+
         Ok(LocationBlock {
             path: path.to_string(),
             proxy_pass,
+            proxy_pass_url,
             rate_limit,
+            rate_limit_schedules,
             cors_enable,
+            proxy_connect_timeout,
+            proxy_read_timeout,
+            proxy_send_timeout,
+            proxy_first_byte_timeout,
+            proxy_request_timeout,
+            intercept_errors,
+            error_pages,
+            status_map,
+            proxy_redirect,
+            proxy_cookie_domain,
+            proxy_cookie_path,
+            proxy_buffering,
+            proxy_buffers_size,
+            access_log,
+            backend_profile,
+            idempotency,
+            idempotency_max_body,
+            fallback_response,
+            proxy_next_upstream,
+            proxy_next_upstream_tries,
+            root,
+            gzip_static,
+            brotli_static,
+            redact,
+            compression_algorithms,
+            limit_req,
+            limit_conn,
+            add_header,
+            proxy_set_header,
+            return_directive,
+            forwarded_headers,
+            wasm_filter,
+            challenge_mode,
+            proxy_cache,
+            proxy_cache_valid,
+            proxy_no_cache,
+            pos: SourcePos::default(),
         })
     }
 
+    /// Все директивы limit_req блока (несколько зон применяются одновременно)
+    fn parse_limit_req_directives(content: &str) -> Result<Vec<LimitReq>, ProxyError> {
+        let limit_req_regex = Regex::new(r"(?:^|\s)limit_req\s+([^;]+);")?;
+        limit_req_regex
+            .captures_iter(content)
+            .filter_map(|cap| cap.get(1))
+            .map(|args| Self::parse_limit_req(args.as_str()))
+            .collect()
+    }
+
+    /// Парсит `limit_req zone=api [burst=20] [nodelay];`
+    fn parse_limit_req(args: &str) -> Result<LimitReq, ProxyError> {
+        let invalid = || format!("invalid limit_req: {}", args);
+        let mut zone = None;
+        let mut burst = 0;
+        let mut nodelay = false;
+        for part in args.split_whitespace() {
+            match part.split_once('=') {
+                Some(("zone", name)) if !name.is_empty() => zone = Some(name.to_string()),
+                Some(("burst", value)) => burst = value.parse::<u32>().map_err(|_| invalid())?,
+                None if part == "nodelay" => nodelay = true,
+                // Задержка запросов в пределах burst не поддерживается: burst всегда без задержки
+                Some(("delay", _)) => return Err(format!("unsupported limit_req delay: {} (use nodelay)", args).into()),
+                _ => return Err(invalid().into()),
+            }
+        }
+        Ok(LimitReq { zone: zone.ok_or_else(invalid)?, burst, nodelay })
+    }
+
+    /// Парсит директиву-переключатель `name on|off;` (None - директива не указана)
+    fn parse_switch(content: &str, name: &str) -> Result<Option<bool>, ProxyError> {
+        let regex = Regex::new(&format!(r"(?:^|\s){}\s+([^;]+);", regex::escape(name)))?;
+        match regex.captures(content).and_then(|cap| cap.get(1)) {
+            Some(value) => match value.as_str().trim() {
+                "on" => Ok(Some(true)),
+                "off" => Ok(Some(false)),
+                other => Err(format!("invalid {} value: {}", name, other).into()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Парсит директиву `fallback_response file=... [status=200] [content_type=...] [when=502,connect_error];`
+    fn parse_fallback_response(content: &str) -> Result<Option<FallbackResponse>, ProxyError> {
+        let regex = Regex::new(r"(?:^|\s)fallback_response\s+([^;]+);")?;
+        let Some(args) = regex.captures(content).and_then(|cap| cap.get(1)) else {
+            return Ok(None);
+        };
+
+        let invalid = || format!("invalid fallback_response: {}", args.as_str());
+        let mut file = None;
+        let mut status = 200;
+        let mut content_type = None;
+        let mut when = FallbackResponse::DEFAULT_WHEN.to_vec();
+        for part in args.as_str().split_whitespace() {
+            let (key, value) = part.split_once('=').ok_or_else(invalid)?;
+            match key {
+                "file" => file = Some(value.to_string()),
+                "status" => {
+                    status = value
+                        .parse::<u16>()
+                        .ok()
+                        .filter(|s| (200..600).contains(s))
+                        .ok_or_else(invalid)?
+                }
+                "content_type" => content_type = Some(value.to_string()),
+                "when" => {
+                    when = value
+                        .split(',')
+                        .map(|condition| match condition {
+                            "connect_error" => Ok(FallbackCondition::ConnectError),
+                            "timeout" => Ok(FallbackCondition::Timeout),
+                            "circuit_open" => Ok(FallbackCondition::CircuitOpen),
+                            code => code
+                                .parse::<u16>()
+                                .ok()
+                                .filter(|s| (500..600).contains(s))
+                                .map(FallbackCondition::Status)
+                                .ok_or_else(invalid),
+                        })
+                        .collect::<Result<_, _>>()?
+                }
+                _ => return Err(invalid().into()),
+            }
+        }
+
+        let file = file.ok_or_else(invalid)?;
+        Ok(Some(FallbackResponse { file, status, content_type, when }))
+    }
+
+    /// Парсит директиву `access_log /var/log/adq/api.access.log json;` или `access_log off;`
+    fn parse_access_log_directive(content: &str) -> Result<Option<AccessLogDirective>, ProxyError> {
+        let regex = Regex::new(r"(?:^|\s)access_log\s+([^;]+);")?;
+        let Some(args) = regex.captures(content).and_then(|cap| cap.get(1)) else {
+            return Ok(None);
+        };
+        let parts: Vec<&str> = args.as_str().split_whitespace().collect();
+        match parts.as_slice() {
+            ["off"] => Ok(Some(AccessLogDirective::Off)),
+            [path] => Ok(Some(AccessLogDirective::File { path: path.to_string(), format: None })),
+            [path, format @ ("json" | "text")] => Ok(Some(AccessLogDirective::File {
+                path: path.to_string(),
+                format: Some(format.to_string()),
+            })),
+            _ => Err(format!("invalid access_log: {}", args.as_str()).into()),
+        }
+    }
+
+    /// Парсит повторяемую директиву замены вида `name from to;` (`name off;` - без замен)
+    fn parse_replace_directive(content: &str, name: &str) -> Result<Vec<(String, String)>, ProxyError> {
+        let mut rules = Vec::new();
+        let regex = Regex::new(&format!(r"(?:^|\s){}\s+([^;]+);", name))?;
+        for cap in regex.captures_iter(content) {
+            if let Some(args) = cap.get(1) {
+                let parts: Vec<&str> = args.as_str().split_whitespace().collect();
+                match parts.as_slice() {
+                    ["off"] => rules.clear(),
+                    [from, to] => rules.push((from.to_string(), to.to_string())),
+                    _ => return Err(format!("invalid {}: {}", name, args.as_str()).into()),
+                }
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Парсит директиву таймаута вида `name 30s;`
+    fn parse_timeout_directive(content: &str, name: &str) -> Result<Option<Duration>, ProxyError> {
+        let regex = Regex::new(&format!(r"(?:^|\s){}\s+([^;]+);", name))?;
+        match regex.captures(content).and_then(|cap| cap.get(1)) {
+            Some(value) => match parse_duration(value.as_str()) {
+                Some(duration) => Ok(Some(duration)),
+                None => Err(format!("invalid {} value: {}", name, value.as_str()).into()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Парсит директиву `resolver 10.0.0.2 10.0.0.3:5353 valid=30s timeout=2s;`
+    fn parse_resolver_directive(value: &str) -> Result<ResolverDirective, ProxyError> {
+        let mut resolver = ResolverDirective {
+            nameservers: Vec::new(),
+            valid: None,
+            timeout: None,
+        };
+
+        for part in value.split_whitespace() {
+            if let Some(valid) = part.strip_prefix("valid=") {
+                resolver.valid = Some(parse_duration(valid).ok_or_else(|| format!("invalid resolver valid value: {}", valid))?);
+            } else if let Some(timeout) = part.strip_prefix("timeout=") {
+                resolver.timeout = Some(parse_duration(timeout).ok_or_else(|| format!("invalid resolver timeout value: {}", timeout))?);
+            } else if let Ok(addr) = part.parse::<SocketAddr>() {
+                resolver.nameservers.push(addr);
+            } else if let Ok(ip) = part.parse::<IpAddr>() {
+                resolver.nameservers.push(SocketAddr::new(ip, 53));
+            } else {
+                return Err(format!("invalid resolver address: {}", part).into());
+            }
+        }
+
+        if resolver.nameservers.is_empty() {
+            return Err("resolver requires at least one address".into());
+        }
+        Ok(resolver)
+    }
+
     /// Парсит upstream блок
-    fn parse_upstream_block(name: &str, content: &str) -> Result<UpstreamBlock, Box<dyn std::error::Error>> {
+    pub(crate) fn parse_upstream_block(name: &str, content: &str) -> Result<UpstreamBlock, ProxyError> {
+        let invalid = |message: String| ProxyError::InvalidUpstream { name: name.to_string(), message };
         let mut servers = Vec::new();
 
         let server_regex = Regex::new(r"server\s+([^;]+);")?;
@@ -249,51 +1526,142 @@ impl NginxConfig {
             if let Some(server_str) = cap.get(1) {
                 let parts: Vec<&str> = server_str.as_str().split_whitespace().collect();
                 let address = parts[0].to_string();
-                let weight = 1; // По умолчанию вес 1, можно расширить парсинг
+                // Вес по умолчанию 1; остальные параметры server игнорируются
+                let mut weight = 1;
+                for part in &parts[1..] {
+                    if let Some(value) = part.strip_prefix("weight=") {
+                        weight = value
+                            .parse::<u32>()
+                            .ok()
+                            .filter(|weight| *weight > 0)
+                            .ok_or_else(|| invalid(format!("invalid server weight: {}", part)))?;
+                    }
+                }
 
                 servers.push(UpstreamServer { address, weight });
             }
         }
 
+        let least_requests_regex = Regex::new(r"(?:^|\s)least_requests\s*;")?;
+        let mut balancing = if least_requests_regex.is_match(content) {
+            Balancing::LeastRequests
+        } else {
+            Balancing::RoundRobin
+        };
+
+        // hash $key [consistent]; - хеширование всегда консистентное, consistent допускается для совместимости
+        let hash_key = match directive_occurrences(content, "hash")?.first().map(Vec::as_slice) {
+            Some([key]) | Some([key, _]) if balancing == Balancing::LeastRequests => {
+                return Err(invalid(format!("hash {} conflicts with least_requests", key)));
+            }
+            Some([key]) => Some(key.clone()),
+            Some([key, consistent]) if consistent == "consistent" => Some(key.clone()),
+            Some(args) => return Err(invalid(format!("invalid hash: {}", args.join(" ")))),
+            None => None,
+        };
+        if hash_key.is_some() {
+            balancing = Balancing::Hash;
+        }
+
+        let max_conns_regex = Regex::new(r"(?:^|\s)max_conns\s+([^;\s]+)\s*;")?;
+        let max_conns = match max_conns_regex.captures(content) {
+            Some(cap) => Some(
+                cap[1]
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|max_conns| *max_conns > 0)
+                    .ok_or_else(|| invalid(format!("invalid max_conns: {}", &cap[1])))?,
+            ),
+            None => None,
+        };
+
+        let queue_regex = Regex::new(r"(?:^|\s)queue\s+(?:depth=)?([^;\s]+)(?:\s+timeout=([^;\s]+))?\s*;")?;
+        let queue = match queue_regex.captures(content) {
+            Some(cap) => {
+                if max_conns.is_none() {
+                    return Err(invalid("queue requires max_conns".to_string()));
+                }
+                let size = cap[1].parse::<usize>().map_err(|_| invalid(format!("invalid queue size: {}", &cap[1])))?;
+                let timeout = match cap.get(2) {
+                    Some(timeout) => parse_duration(timeout.as_str())
+                        .filter(|timeout| !timeout.is_zero())
+                        .ok_or_else(|| invalid(format!("invalid queue timeout: {}", timeout.as_str())))?,
+                    None => DEFAULT_QUEUE_TIMEOUT,
+                };
+                Some(UpstreamQueue { size, timeout })
+            }
+            None => None,
+        };
+
         Ok(UpstreamBlock {
             name: name.to_string(),
             servers,
+            balancing,
+            hash_key,
+            max_conns,
+            queue,
+            implicit: false,
+            pos: SourcePos::default(),
         })
     }
 
-    /// Находит server блок по host
+    /// Upstream для `proxy_pass http[s]://host[:port]`. Имя без порта ссылается на блок
+    /// upstream, как в nginx; иначе создается неявный upstream с одним сервером host:port,
+    /// имя которого разрешается так же, как у серверов upstream блоков (resolver)
+    fn add_implicit_upstreams(&mut self) {
+        for location in self.servers.iter_mut().flat_map(|server| server.locations.iter_mut()) {
+            let Some(url) = location.proxy_pass_url.as_mut() else {
+                continue;
+            };
+            let ProxyPassAddr::Host { host, port } = &url.addr else {
+                continue;
+            };
+            if port.is_none() && self.upstreams.contains_key(host) {
+                url.upstream = Some(host.clone());
+                continue;
+            }
+            let Some(address) = url.server_address() else {
+                continue;
+            };
+            self.upstreams.entry(address.clone()).or_insert_with(|| UpstreamBlock {
+                name: address.clone(),
+                servers: vec![UpstreamServer { address: address.clone(), weight: 1 }],
+                balancing: Balancing::default(),
+                hash_key: None,
+                max_conns: None,
+                queue: None,
+                implicit: true,
+                pos: location.pos.clone(),
+            });
+            url.upstream = Some(address);
+        }
+    }
+
+    /// Находит server блок по host: точное имя, затем `*.example.com` с самым длинным суффиксом
     pub fn find_server(&self, host: &str) -> Option<&ServerBlock> {
         let host_without_port = host.split(':').next().unwrap_or(host);
-        
-        self.servers.iter().find(|server| {
-            server.server_names.iter().any(|name| name == host_without_port)
-        })
+        self.server_index.find(host_without_port).and_then(|position| self.servers.get(position))
     }
 
-    /// Находит location в server блоке по пути
-    pub fn find_location<'a>(&self, server: &'a ServerBlock, path: &str) -> Option<&'a LocationBlock> {
-        // Сначала ищем точное совпадение
-        for location in &server.locations {
-            if location.path == path {
-                return Some(location);
-            }
+    /// Строит индексы server и location заново после изменения `servers`
+    pub fn reindex(&mut self) {
+        for server in &mut self.servers {
+            server.location_index = LocationIndex::build(&server.locations);
         }
+        self.server_index = ServerIndex::build(&self.servers);
+    }
 
-        // Затем ищем по префиксу (самый длинный префикс)
-        let mut best_match: Option<&LocationBlock> = None;
-        let mut best_match_len = 0;
-
-        for location in &server.locations {
-            if location.path.ends_with('/') || location.path == "/" {
-                let prefix = location.path.trim_end_matches('/');
-                if path.starts_with(prefix) && prefix.len() > best_match_len {
-                    best_match = Some(location);
-                    best_match_len = prefix.len();
-                }
-            }
-        }
+    /// Server для запросов без Host: помеченный `default_server`, иначе первый
+    pub fn default_server(&self) -> Option<&ServerBlock> {
+        self.servers
+            .iter()
+            .find(|server| server.listen_ports.iter().any(|listen| listen.default_server))
+            .or_else(|| self.servers.first())
+    }
 
-        best_match
+    /// Находит location в server блоке по пути: точное совпадение, затем самый длинный префикс
+    pub fn find_location<'a>(&self, server: &'a ServerBlock, path: &str) -> Option<&'a LocationBlock> {
+        server.location_index.find(path).and_then(|position| server.locations.get(position))
     }
 
     /// Получает upstream по имени
@@ -302,6 +1670,87 @@ impl NginxConfig {
     }
 }
 
+/// Позиция символа вне кавычек
+fn find_unquoted(value: &str, target: char) -> Option<usize> {
+    let mut quote = None;
+    for (position, c) in value.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == target => return Some(position),
+            None => {}
+        }
+    }
+    None
+}
+
+/// Аргументы директивы через пробел; значения в кавычках могут содержать пробелы и `;`
+fn directive_args(value: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut rest = value.trim_start();
+    while !rest.is_empty() {
+        let (arg, len) = match rest.chars().next() {
+            Some(q @ ('"' | '\'')) => match rest[1..].find(q) {
+                Some(end) => (rest[1..end + 1].to_string(), end + 2),
+                None => (rest[1..].to_string(), rest.len()),
+            },
+            _ => {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                (rest[..end].to_string(), end)
+            }
+        };
+        args.push(arg);
+        rest = rest[len..].trim_start();
+    }
+    args
+}
+
+/// Аргументы всех вхождений директивы `name ...;` (`;` в кавычках не завершает директиву)
+fn directive_occurrences(content: &str, name: &str) -> Result<Vec<Vec<String>>, ProxyError> {
+    let regex = Regex::new(&format!(r#"(?:^|[\s;{{}}]){}\s+((?:"[^"]*"|'[^']*'|[^;"'])+);"#, regex::escape(name)))?;
+    Ok(regex
+        .captures_iter(content)
+        .filter_map(|cap| cap.get(1))
+        .map(|args| directive_args(args.as_str()))
+        .collect())
+}
+
+/// Парсит значение времени в формате nginx: `500ms`, `30s`, `5m`, `1h` или число секунд
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => value.split_at(idx),
+        None => (value, "s"),
+    };
+    let number = number.parse::<u64>().ok()?;
+
+    match unit {
+        "ms" => Some(Duration::from_millis(number)),
+        "s" => Some(Duration::from_secs(number)),
+        "m" => Some(Duration::from_secs(number * 60)),
+        "h" => Some(Duration::from_secs(number * 3600)),
+        _ => None,
+    }
+}
+
+/// Парсит размер в формате nginx: `512`, `64k`, `2m`, `1g` (без учета регистра)
+pub fn parse_size(value: &str) -> Option<usize> {
+    let value = value.trim().to_ascii_lowercase();
+    // Допускается и форма с b: 100mb, 64kb
+    let value = match value.strip_suffix('b') {
+        Some(rest) if rest.ends_with(['k', 'm', 'g']) => rest.to_string(),
+        _ => value,
+    };
+    let (number, multiplier) = match value.chars().last()? {
+        'k' => (&value[..value.len() - 1], 1024),
+        'm' => (&value[..value.len() - 1], 1024 * 1024),
+        'g' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value.as_str(), 1),
+    };
+    number.parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -343,4 +1792,569 @@ mod tests {
         let upstream = config.upstreams.get("backend").unwrap();
         assert_eq!(upstream.servers.len(), 2);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_listen_proxy_protocol() {
+        let listen = NginxConfig::parse_listen_directive("80 proxy_protocol").unwrap();
+        assert_eq!(listen.port, 80);
+        assert!(listen.proxy_protocol);
+
+        let listen = NginxConfig::parse_listen_directive("443 ssl http2").unwrap();
+        assert!(listen.ssl);
+        assert!(!listen.proxy_protocol);
+    }
+
+    #[test]
+    fn test_parse_listen_address() {
+        let listen = NginxConfig::parse_listen_directive("9080").unwrap();
+        assert_eq!((listen.address, listen.port), (None, 9080));
+
+        let listen = NginxConfig::parse_listen_directive("[::]:9443 ssl").unwrap();
+        assert_eq!(listen.address, Some("::".parse().unwrap()));
+        assert_eq!(listen.port, 9443);
+        assert!(listen.ssl && listen.ipv6only);
+
+        let listen = NginxConfig::parse_listen_directive("[::]:443 ssl ipv6only=off").unwrap();
+        assert!(!listen.ipv6only);
+
+        let listen = NginxConfig::parse_listen_directive("127.0.0.1:8080").unwrap();
+        assert_eq!(listen.address, Some("127.0.0.1".parse().unwrap()));
+        assert_eq!(NginxConfig::parse_listen_directive("*:80").unwrap().address, None);
+
+        assert!(NginxConfig::parse_listen_directive("localhost:80").is_err());
+        assert!(NginxConfig::parse_listen_directive("[::]:80 ipv6only=maybe").is_err());
+    }
+
+    #[test]
+    fn test_parse_proxy_redirect() {
+        let location = NginxConfig::parse_location_block("/", "proxy_pass app;").unwrap();
+        assert_eq!(location.proxy_redirect, ProxyRedirect::Default);
+
+        let location = NginxConfig::parse_location_block("/", "proxy_redirect off;").unwrap();
+        assert_eq!(location.proxy_redirect, ProxyRedirect::Off);
+
+        let location = NginxConfig::parse_location_block(
+            "/",
+            "proxy_redirect http://app.internal:8080/ https://example.com/app/;",
+        )
+        .unwrap();
+        assert_eq!(
+            location.proxy_redirect,
+            ProxyRedirect::Replace(vec![(
+                "http://app.internal:8080/".to_string(),
+                "https://example.com/app/".to_string()
+            )])
+        );
+    }
+
+    #[test]
+    fn test_parse_proxy_cookie_directives() {
+        let location = NginxConfig::parse_location_block(
+            "/",
+            "proxy_cookie_domain app.internal $host;\nproxy_cookie_domain .svc.local example.com;\nproxy_cookie_path /app/ /;",
+        )
+        .unwrap();
+        assert_eq!(
+            location.proxy_cookie_domain,
+            vec![
+                ("app.internal".to_string(), "$host".to_string()),
+                (".svc.local".to_string(), "example.com".to_string()),
+            ]
+        );
+        assert_eq!(location.proxy_cookie_path, vec![("/app/".to_string(), "/".to_string())]);
+        assert!(NginxConfig::parse_location_block("/", "proxy_cookie_path /a;").is_err());
+    }
+
+    #[test]
+    fn test_parse_idempotency() {
+        let location = NginxConfig::parse_location_block("/billing", "proxy_pass billing;\nidempotency 24h;\nidempotency_max_body 16k;").unwrap();
+        assert_eq!(location.idempotency, Some(Duration::from_secs(24 * 3600)));
+        assert_eq!(location.idempotency_max_body, 16 * 1024);
+
+        let location = NginxConfig::parse_location_block("/", "idempotency off;").unwrap();
+        assert_eq!(location.idempotency, None);
+        assert_eq!(location.idempotency_max_body, DEFAULT_IDEMPOTENCY_MAX_BODY);
+        assert!(NginxConfig::parse_location_block("/", "idempotency 0s;").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("16k"), Some(16 * 1024));
+        assert_eq!(parse_size("100MB"), Some(100 * 1024 * 1024));
+        assert_eq!(parse_size("1gb"), Some(1024 * 1024 * 1024));
+        assert_eq!(parse_size("10b"), None);
+        assert_eq!(parse_size("mb"), None);
+    }
+
+    #[test]
+    fn test_parse_next_upstream() {
+        let location = NginxConfig::parse_location_block(
+            "/api",
+            "proxy_pass api;\nproxy_next_upstream error timeout http_502 http_504;\nproxy_next_upstream_tries 2;",
+        )
+        .unwrap();
+        assert!(location.proxy_next_upstream.error && location.proxy_next_upstream.timeout);
+        assert!(location.proxy_next_upstream.on_status(502));
+        assert!(!location.proxy_next_upstream.on_status(404));
+        assert!(!location.proxy_next_upstream.non_idempotent);
+        assert_eq!(location.proxy_next_upstream_tries, Some(2));
+
+        // По умолчанию как в nginx: error timeout
+        let location = NginxConfig::parse_location_block("/", "proxy_pass api;").unwrap();
+        assert_eq!(location.proxy_next_upstream, NextUpstream::default());
+        assert_eq!(location.proxy_next_upstream_tries, None);
+
+        assert_eq!(NextUpstream::parse("off").unwrap(), NextUpstream::off());
+        assert!(NextUpstream::parse("http_502 non_idempotent").unwrap().non_idempotent);
+        assert!(NextUpstream::parse("http_418").is_err());
+        assert!(NginxConfig::parse_location_block("/", "proxy_next_upstream_tries many;").is_err());
+    }
+
+    #[test]
+    fn test_parse_static_directives() {
+        let location = NginxConfig::parse_location_block(
+            "/assets/",
+            "root /var/www/adquest;\ngzip_static on;\nbrotli_static on;",
+        )
+        .unwrap();
+        assert_eq!(location.root.as_deref(), Some("/var/www/adquest"));
+        assert!(location.gzip_static && location.brotli_static);
+
+        let location = NginxConfig::parse_location_block("/", "proxy_pass api;").unwrap();
+        assert_eq!(location.root, None);
+        assert!(!location.gzip_static && !location.brotli_static);
+        assert!(NginxConfig::parse_location_block("/", "root /srv;\ngzip_static always;").is_err());
+    }
+
+    #[test]
+    fn test_parse_upstream_balancing() {
+        let upstream = NginxConfig::parse_upstream_block(
+            "core_api",
+            "least_requests;\nserver 10.0.0.1:8080 weight=3;\nserver 10.0.0.2:8080 max_fails=3;",
+        )
+        .unwrap();
+        assert_eq!(upstream.balancing, Balancing::LeastRequests);
+        assert_eq!(upstream.servers[0].weight, 3);
+        assert_eq!(upstream.servers[1].weight, 1);
+
+        let upstream = NginxConfig::parse_upstream_block("api", "server 10.0.0.1:8080;").unwrap();
+        assert_eq!(upstream.balancing, Balancing::RoundRobin);
+        assert!(NginxConfig::parse_upstream_block("api", "server 10.0.0.1:8080 weight=0;").is_err());
+
+        let upstream = NginxConfig::parse_upstream_block("cache", "hash $request_uri consistent;\nserver 10.0.0.1:8080;").unwrap();
+        assert_eq!(upstream.balancing, Balancing::Hash);
+        assert_eq!(upstream.hash_key.as_deref(), Some("$request_uri"));
+        let upstream = NginxConfig::parse_upstream_block("cache", "hash \"$host$arg_user\";\nserver 10.0.0.1:8080;").unwrap();
+        assert_eq!(upstream.hash_key.as_deref(), Some("$host$arg_user"));
+        // ip_hash не путается с hash
+        let upstream = NginxConfig::parse_upstream_block("cache", "ip_hash;\nserver 10.0.0.1:8080;").unwrap();
+        assert_eq!(upstream.hash_key, None);
+        assert!(NginxConfig::parse_upstream_block("cache", "hash $uri ring;\nserver 10.0.0.1:8080;").is_err());
+        assert!(NginxConfig::parse_upstream_block("cache", "least_requests;\nhash $uri;\nserver 10.0.0.1:8080;").is_err());
+    }
+
+    #[test]
+    fn test_proxy_pass_host_implies_upstream() {
+        let config = NginxConfig::parse_config_content(
+            "upstream api { server 10.0.0.1:8080; }
+server { listen 80; server_name shop.example.com;
+  location /app/ { proxy_pass http://127.0.0.1:3000; }
+  location /partner/ { proxy_pass https://partner.example.com/v1/; }
+  location /api/ { proxy_pass http://api; }
+  location /legacy/ { proxy_pass http://127.0.0.1:3000/old/; }
+}",
+        )
+        .unwrap();
+        let upstream_of = |path: &str| {
+            let location = config.servers[0].locations.iter().find(|l| l.path == path).unwrap();
+            location.proxy_pass_url.as_ref().unwrap().upstream.clone().unwrap()
+        };
+
+        assert_eq!(upstream_of("/app/"), "127.0.0.1:3000");
+        assert_eq!(upstream_of("/legacy/"), "127.0.0.1:3000");
+        assert_eq!(upstream_of("/partner/"), "partner.example.com:443");
+        // Имя без порта - блок upstream
+        assert_eq!(upstream_of("/api/"), "api");
+        assert!(!config.upstreams["api"].implicit);
+
+        let implicit = &config.upstreams["127.0.0.1:3000"];
+        assert!(implicit.implicit);
+        assert_eq!(implicit.servers[0].address, "127.0.0.1:3000");
+        assert_eq!(implicit.pos.line, 3);
+        assert_eq!(config.upstreams.len(), 3);
+    }
+
+    #[test]
+    fn test_parse_upstream_concurrency_limit() {
+        let upstream = NginxConfig::parse_upstream_block(
+            "legacy",
+            "server 10.0.0.1:8080;\nmax_conns 20;\nqueue 50 timeout=5s;",
+        )
+        .unwrap();
+        assert_eq!(upstream.max_conns, Some(20));
+        assert_eq!(upstream.queue, Some(UpstreamQueue { size: 50, timeout: Duration::from_secs(5) }));
+
+        let upstream = NginxConfig::parse_upstream_block("legacy", "server 10.0.0.1:8080;\nmax_conns 5;\nqueue 10;").unwrap();
+        assert_eq!(upstream.queue.unwrap().timeout, Duration::from_secs(60));
+
+        let upstream = NginxConfig::parse_upstream_block("api", "server 10.0.0.1:8080 max_conns=3;").unwrap();
+        assert_eq!(upstream.max_conns, None);
+        assert!(upstream.queue.is_none());
+
+        assert!(NginxConfig::parse_upstream_block("api", "server 10.0.0.1:8080;\nmax_conns 0;").is_err());
+        assert!(NginxConfig::parse_upstream_block("api", "server 10.0.0.1:8080;\nqueue 10;").is_err());
+        assert!(NginxConfig::parse_upstream_block("api", "max_conns 5;\nqueue 10 timeout=0s;").is_err());
+
+        let upstream =
+            NginxConfig::parse_upstream_block("api", "server 10.0.0.1:8080;\nmax_conns 8;\nqueue depth=100 timeout=200ms;")
+                .unwrap();
+        assert_eq!(upstream.queue, Some(UpstreamQueue { size: 100, timeout: Duration::from_millis(200) }));
+        assert!(NginxConfig::parse_upstream_block("api", "max_conns 5;\nqueue depth=many;").is_err());
+    }
+
+    #[test]
+    fn test_parse_nginx_limit_req_snippet() {
+        // Фрагмент nginx.conf, перенесенный без изменений
+        let config = NginxConfig::parse_config_content(r#"
+            http {
+                limit_req_zone $binary_remote_addr zone=api:10m rate=10r/s;
+                limit_req_zone $http_x_api_key zone=keys:10m rate=300r/m;
+                limit_req_status 429;
+                limit_req_log_level warn;
+
+                server {
+                    listen 80;
+                    server_name api.example.com;
+                    limit_req zone=api burst=20 nodelay;
+
+                    location /v1/ {
+                        proxy_pass core_api;
+                    }
+                    location /partners/ {
+                        proxy_pass core_api;
+                        limit_req zone=api burst=5;
+                        limit_req zone=keys;
+                    }
+                }
+
+                server {
+                    listen 80;
+                    server_name static.example.com;
+                    location / {
+                        root /var/www;
+                    }
+                }
+            }
+        "#)
+        .unwrap();
+        assert!(config.issues.is_empty(), "{:?}", config.issues);
+        assert_eq!(config.limit_req_zones.len(), 2);
+        assert_eq!(config.limit_req_zones["keys"].period, Duration::from_secs(60));
+
+        // limit_req сервера наследуется location без собственных
+        let api = &config.servers[0];
+        assert_eq!(api.locations[0].limit_req, vec![LimitReq { zone: "api".to_string(), burst: 20, nodelay: true }]);
+        assert_eq!(
+            api.locations[1].limit_req,
+            vec![
+                LimitReq { zone: "api".to_string(), burst: 5, nodelay: false },
+                LimitReq { zone: "keys".to_string(), burst: 0, nodelay: false },
+            ]
+        );
+        assert!(config.servers[1].locations[0].limit_req.is_empty());
+
+        // limit_req уровня http действует в серверах без своих limit_req
+        let config = NginxConfig::parse_config_content(
+            "limit_req_zone $remote_addr zone=one:1m rate=1r/s;\nlimit_req zone=one burst=3;\n\
+             server { listen 80; location / { proxy_pass core_api; } }",
+        )
+        .unwrap();
+        assert_eq!(config.servers[0].locations[0].limit_req, vec![LimitReq { zone: "one".to_string(), burst: 3, nodelay: false }]);
+
+        let e = NginxConfig::parse_config_content("limit_req_zone $cookie_session zone=api:10m rate=10r/s;").unwrap_err();
+        assert!(e.to_string().contains("unsupported limit zone key: $cookie_session"), "{}", e);
+
+        // Задержка в пределах burst не поддерживается
+        let e = NginxConfig::parse_location_block("/api/", "proxy_pass api;\nlimit_req zone=api burst=20 delay=10;").unwrap_err();
+        assert!(e.to_string().contains("unsupported limit_req delay: zone=api burst=20 delay=10"), "{}", e);
+    }
+
+    #[test]
+    fn test_parse_compression_algorithms() {
+        let location = NginxConfig::parse_location_block("/export/", "proxy_pass api;\ncompression_algorithms gzip br;").unwrap();
+        assert_eq!(
+            location.compression_algorithms,
+            Some(vec![CompressionAlgorithm::Gzip, CompressionAlgorithm::Brotli])
+        );
+        let location = NginxConfig::parse_location_block("/stream/", "compression_algorithms off;").unwrap();
+        assert_eq!(location.compression_algorithms, Some(Vec::new()));
+        assert!(NginxConfig::parse_location_block("/", "proxy_pass api;").unwrap().compression_algorithms.is_none());
+        assert!(NginxConfig::parse_location_block("/", "compression_algorithms gzip lz4;").is_err());
+    }
+
+    #[test]
+    fn test_parse_redact() {
+        assert!(NginxConfig::parse_location_block("/api/auth/", "proxy_pass api;\nredact on;").unwrap().redact);
+        assert!(!NginxConfig::parse_location_block("/api/", "proxy_pass api;").unwrap().redact);
+        assert!(NginxConfig::parse_location_block("/", "redact yes;").is_err());
+    }
+
+    #[test]
+    fn test_parse_errors_report_position() {
+        let content = "upstream api {\n    server 10.0.0.1:8080;\n}\n\nserver {\n    listen 80;\n    server_name api.example.com;\n\n    location /api/ {\n        proxy_pass api;\n        # forwarded_headers both;\n        forwarded_headers rfc7239;\n    }\n    location /health {\n        proxy_pass missing;\n    }\n}\n";
+        let config = NginxConfig::parse_config_content(content).unwrap();
+
+        // Сломанная директива - строка 12, после комментария
+        assert_eq!(config.issues.len(), 1);
+        let issue = &config.issues[0];
+        assert_eq!((issue.line, issue.column), (Some(12), Some(9)));
+        assert_eq!(issue.message, "location /api/ skipped: invalid forwarded_headers value: rfc7239");
+
+        let location = &config.servers[0].locations[0];
+        assert_eq!(location.path, "/health");
+        assert_eq!((location.pos.line, location.pos.column), (14, 5));
+        assert_eq!(config.servers[0].pos.line, 5);
+        assert_eq!(config.upstreams["api"].pos.line, 1);
+
+        // Директива upstream, после которой блок не разбирается
+        let config = NginxConfig::parse_config_content("upstream billing {\n  server 10.0.0.1:8080;\n  queue 10;\n}").unwrap();
+        assert!(config.upstreams.is_empty());
+        assert_eq!((config.issues[0].line, config.issues[0].column), (Some(3), Some(3)));
+    }
+
+    #[test]
+    fn test_parse_forwarded_headers() {
+        let location = NginxConfig::parse_location_block("/partner/", "proxy_pass partner;\nforwarded_headers both;").unwrap();
+        assert_eq!(location.forwarded_headers, ForwardedHeaders::Both);
+        assert!(location.forwarded_headers.forwarded() && location.forwarded_headers.x_forwarded());
+        let location = NginxConfig::parse_location_block("/api/", "proxy_pass api;").unwrap();
+        assert_eq!(location.forwarded_headers, ForwardedHeaders::XForwarded);
+        assert!(NginxConfig::parse_location_block("/", "forwarded_headers rfc7239;").is_err());
+    }
+
+    #[test]
+    fn test_parse_wasm_filter() {
+        let location = NginxConfig::parse_location_block(
+            "/tenants/",
+            "proxy_pass api;\nwasm_filter /etc/adq-pingora/filters/tenant.wasm on_failure=open;",
+        )
+        .unwrap();
+        let filter = location.wasm_filter.unwrap();
+        assert_eq!(filter.path, "/etc/adq-pingora/filters/tenant.wasm");
+        assert!(filter.fail_open);
+        let location = NginxConfig::parse_location_block("/", "wasm_filter /tmp/f.wasm;").unwrap();
+        assert!(!location.wasm_filter.unwrap().fail_open);
+        assert!(NginxConfig::parse_location_block("/", "wasm_filter /tmp/f.wasm on_failure=retry;").is_err());
+    }
+
+    #[test]
+    fn test_parse_cache_directives() {
+        let location = NginxConfig::parse_location_block(
+            "/api/catalog/",
+            "proxy_pass api;\nproxy_cache_valid 200 10m;\nproxy_cache_valid 404 any 30s;\nproxy_cache_valid 5m;\nproxy_no_cache $arg_nocache $http_x_no_cache;",
+        );
+        assert!(location.is_err(), "any mixed with codes is rejected");
+
+        let location = NginxConfig::parse_location_block(
+            "/api/catalog/",
+            "proxy_pass api;\nproxy_cache_valid 200 10m;\nproxy_cache_valid any 30s;\nproxy_cache_valid 5m;\nproxy_no_cache $arg_nocache $http_x_no_cache;",
+        )
+        .unwrap();
+        assert!(location.proxy_cache);
+        assert_eq!(location.proxy_cache_valid[0], CacheValid { statuses: vec![200], ttl: Duration::from_secs(600) });
+        assert!(location.proxy_cache_valid[1].matches(503));
+        assert_eq!(location.proxy_cache_valid[2].statuses, vec![200, 301, 302]);
+        assert_eq!(location.proxy_no_cache, vec!["$arg_nocache", "$http_x_no_cache"]);
+
+        let location = NginxConfig::parse_location_block("/admin/", "proxy_pass api;\nproxy_cache off;").unwrap();
+        assert!(!location.proxy_cache);
+        assert!(location.proxy_cache_valid.is_empty());
+        assert!(NginxConfig::parse_location_block("/", "proxy_cache my_zone;").is_err());
+        assert!(NginxConfig::parse_location_block("/", "proxy_cache_valid 200 soon;").is_err());
+        assert!(NginxConfig::parse_location_block("/", "proxy_cache_valid 999 1m;").is_err());
+    }
+
+    #[test]
+    fn test_parse_challenge_mode() {
+        let location = NginxConfig::parse_location_block("/catalog/", "proxy_pass api;\nchallenge_mode on;").unwrap();
+        assert!(location.challenge_mode);
+        assert!(!NginxConfig::parse_location_block("/", "challenge_mode off;").unwrap().challenge_mode);
+        assert!(!NginxConfig::parse_location_block("/", "proxy_pass api;").unwrap().challenge_mode);
+        assert!(NginxConfig::parse_location_block("/", "challenge_mode always;").is_err());
+    }
+
+    #[test]
+    fn test_parse_fallback_response() {
+        let location = NginxConfig::parse_location_block(
+            "/flags",
+            "proxy_pass flags;\nfallback_response file=/etc/adq-pingora/fallbacks/flags.json status=200 content_type=application/json when=502,503,504,connect_error;",
+        )
+        .unwrap();
+        let fallback = location.fallback_response.unwrap();
+        assert_eq!(fallback.file, "/etc/adq-pingora/fallbacks/flags.json");
+        assert_eq!(fallback.status, 200);
+        assert_eq!(fallback.content_type.as_deref(), Some("application/json"));
+        assert!(fallback.matches(FallbackCondition::ConnectError));
+        assert!(fallback.matches(FallbackCondition::CircuitOpen));
+        assert!(!fallback.matches(FallbackCondition::Timeout));
+        assert!(!fallback.matches(FallbackCondition::Status(500)));
+
+        let location = NginxConfig::parse_location_block("/", "fallback_response file=/tmp/stub.html;").unwrap();
+        let fallback = location.fallback_response.unwrap();
+        assert_eq!(fallback.content_type, None);
+        assert_eq!(fallback.when, FallbackResponse::DEFAULT_WHEN);
+
+        assert!(NginxConfig::parse_location_block("/", "fallback_response status=200;").is_err());
+        assert!(NginxConfig::parse_location_block("/", "fallback_response file=/a when=404;").is_err());
+        assert!(NginxConfig::parse_location_block("/", "fallback_response file=/a mode=x;").is_err());
+    }
+
+    #[test]
+    fn test_parse_access_log() {
+        let config = NginxConfig::parse_config_content(r#"
+            server {
+                listen 80;
+                server_name api.example.com;
+                access_log /var/log/adq/api.access.log json;
+
+                location /health {
+                    access_log off;
+                }
+
+                location / {
+                    proxy_pass backend;
+                }
+            }
+        "#).unwrap();
+
+        let server = &config.servers[0];
+        assert_eq!(
+            server.access_log,
+            Some(AccessLogDirective::File {
+                path: "/var/log/adq/api.access.log".to_string(),
+                format: Some("json".to_string()),
+            })
+        );
+        assert_eq!(server.locations[0].access_log, Some(AccessLogDirective::Off));
+        assert_eq!(server.locations[1].access_log, None);
+        assert!(NginxConfig::parse_location_block("/", "access_log /a.log combined;").is_err());
+    }
+
+    #[test]
+    fn test_parse_proxy_buffering() {
+        let location = NginxConfig::parse_location_block("/api/", "proxy_buffering on;\nproxy_buffers_size 2m;").unwrap();
+        assert!(location.proxy_buffering);
+        assert_eq!(location.proxy_buffers_size, 2 * 1024 * 1024);
+
+        let location = NginxConfig::parse_location_block("/events/", "proxy_pass sse;").unwrap();
+        assert!(!location.proxy_buffering);
+        assert_eq!(location.proxy_buffers_size, DEFAULT_PROXY_BUFFERS_SIZE);
+
+        assert!(NginxConfig::parse_location_block("/", "proxy_buffering yes;").is_err());
+        assert!(NginxConfig::parse_location_block("/", "proxy_buffers_size 2x;").is_err());
+        assert_eq!(parse_size("64K"), Some(64 * 1024));
+        assert_eq!(parse_size("512"), Some(512));
+    }
+
+    #[test]
+    fn test_parse_resolver_directive() {
+        let config = NginxConfig::parse_config_content(
+            "resolver 10.0.0.2 10.0.0.3:5353 valid=30s timeout=2s;\nupstream backend {\n    server api.internal:8080;\n}\n",
+        )
+        .unwrap();
+        let resolver = config.resolver.unwrap();
+        assert_eq!(
+            resolver.nameservers,
+            vec!["10.0.0.2:53".parse().unwrap(), "10.0.0.3:5353".parse().unwrap()]
+        );
+        assert_eq!(resolver.valid, Some(Duration::from_secs(30)));
+        assert_eq!(resolver.timeout, Some(Duration::from_secs(2)));
+
+        assert!(NginxConfig::parse_config_content("resolver valid=30s;").is_err());
+        assert!(NginxConfig::parse_config_content("server { listen 80; }").unwrap().resolver.is_none());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("15"), Some(Duration::from_secs(15)));
+        assert_eq!(parse_duration("abc"), None);
+        assert_eq!(parse_duration("10x"), None);
+    }
+
+    #[test]
+    fn test_parse_location_timeouts() {
+        let config_content = r#"
+            server {
+                listen 80;
+                server_name reports.example.com;
+
+                location /reports/ {
+                    proxy_pass reports;
+                    proxy_connect_timeout 2s;
+                    proxy_read_timeout 120s;
+                    proxy_send_timeout 10s;
+                    proxy_first_byte_timeout 90s;
+                    proxy_request_timeout 300s;
+                }
+
+                location / {
+                    proxy_pass backend;
+                }
+            }
+        "#;
+
+        let config = NginxConfig::parse_config_content(config_content).unwrap();
+        let server = &config.servers[0];
+
+        let reports = &server.locations[0];
+        assert_eq!(reports.proxy_connect_timeout, Some(Duration::from_secs(2)));
+        assert_eq!(reports.proxy_read_timeout, Some(Duration::from_secs(120)));
+        assert_eq!(reports.proxy_send_timeout, Some(Duration::from_secs(10)));
+        assert_eq!(reports.proxy_first_byte_timeout, Some(Duration::from_secs(90)));
+        assert_eq!(reports.proxy_request_timeout, Some(Duration::from_secs(300)));
+
+        let root = &server.locations[1];
+        assert_eq!(root.proxy_read_timeout, None);
+        assert_eq!(root.proxy_send_timeout, None);
+        assert_eq!(root.proxy_first_byte_timeout, None);
+        assert_eq!(root.proxy_request_timeout, None);
+    }
+
+    #[test]
+    fn test_parse_intercept_errors() {
+        let config_content = r#"
+            server {
+                listen 80;
+                server_name app.example.com;
+
+                location /app/ {
+                    proxy_pass app;
+                    proxy_intercept_errors on;
+                    error_page 500 502 503 /var/www/errors/50x.html;
+                    status_map 500=502;
+                }
+
+                location /api/ {
+                    proxy_pass api;
+                }
+            }
+        "#;
+
+        let config = NginxConfig::parse_config_content(config_content).unwrap();
+        let server = &config.servers[0];
+
+        let app = &server.locations[0];
+        assert!(app.intercept_errors);
+        assert_eq!(app.error_pages.get(&503), Some(&"/var/www/errors/50x.html".to_string()));
+        assert_eq!(app.status_map.get(&500), Some(&502));
+
+        let api = &server.locations[1];
+        assert!(!api.intercept_errors);
+        assert!(api.error_pages.is_empty());
+    }
+}
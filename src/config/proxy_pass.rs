@@ -0,0 +1,212 @@
+use std::fmt;
+use std::net::IpAddr;
+
+/// Адрес upstream в URL форме proxy_pass
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProxyPassAddr {
+    /// Unix сокет (`unix:/run/app.sock`)
+    Unix(String),
+    /// Адрес или имя хоста; IPv6 адрес хранится в скобках (`[2001:db8::1]`)
+    Host { host: String, port: Option<u16> },
+}
+
+/// proxy_pass в форме URL: `http://127.0.0.1:3000;`, `https://api.example.com/v1/;`
+/// или `https://unix:/run/app.sock:/api/;`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProxyPassUrl {
+    /// Схема https: TLS до upstream
+    pub tls: bool,
+    pub addr: ProxyPassAddr,
+    /// URI после адреса; заменяет совпавший с location префикс пути, как в nginx
+    pub uri: Option<String>,
+    /// Upstream для адреса host[:port]: блок upstream с именем host или неявный upstream
+    /// с одним сервером. Заполняется после загрузки всех файлов sites-enabled
+    pub upstream: Option<String>,
+}
+
+/// Разбирает `host[:port]` или `[ipv6][:port]`
+fn parse_authority(authority: &str) -> Option<(String, Option<u16>)> {
+    let (host, port) = if authority.starts_with('[') {
+        let end = authority.find(']')?;
+        authority[1..end].parse::<std::net::Ipv6Addr>().ok()?;
+        let port = match &authority[end + 1..] {
+            "" => None,
+            rest => Some(rest.strip_prefix(':')?),
+        };
+        (&authority[..=end], port)
+    } else {
+        match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        }
+    };
+    let valid_host = !host.is_empty()
+        && (host.starts_with('[') || host.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_'));
+    if !valid_host {
+        return None;
+    }
+    let port = match port {
+        Some(port) => Some(port.parse::<u16>().ok().filter(|port| *port > 0)?),
+        None => None,
+    };
+    Some((host.to_string(), port))
+}
+
+impl ProxyPassUrl {
+    /// Разбирает значение proxy_pass. Без схемы - имя upstream блока (None)
+    pub fn parse(value: &str) -> Result<Option<Self>, String> {
+        let (tls, rest) = if let Some(rest) = value.strip_prefix("http://") {
+            (false, rest)
+        } else if let Some(rest) = value.strip_prefix("https://") {
+            (true, rest)
+        } else if value.contains("://") {
+            return Err(format!("unsupported proxy_pass scheme: {}", value));
+        } else {
+            return Ok(None);
+        };
+
+        let Some(socket) = rest.strip_prefix("unix:") else {
+            let (authority, uri) = match rest.find('/') {
+                Some(slash) => (&rest[..slash], Some(&rest[slash..])),
+                None => (rest, None),
+            };
+            let (host, port) =
+                parse_authority(authority).ok_or_else(|| format!("invalid proxy_pass address: {}", value))?;
+            return Ok(Some(Self {
+                tls,
+                addr: ProxyPassAddr::Host { host, port },
+                uri: uri.map(str::to_string),
+                upstream: None,
+            }));
+        };
+        // Путь сокета заканчивается на `:`, за которым идет URI
+        let (path, uri) = match socket.split_once(':') {
+            Some((path, uri)) => (path, Some(uri)),
+            None => (socket, None),
+        };
+        if !path.starts_with('/') {
+            return Err(format!("proxy_pass unix socket path must be absolute: {}", value));
+        }
+        if uri.is_some_and(|uri| !uri.starts_with('/')) {
+            return Err(format!("proxy_pass URI must start with '/': {}", value));
+        }
+        Ok(Some(Self {
+            tls,
+            addr: ProxyPassAddr::Unix(path.to_string()),
+            uri: uri.map(str::to_string),
+            upstream: None,
+        }))
+    }
+
+    /// Адрес сервера неявного upstream: порт по умолчанию 80 для http и 443 для https
+    pub fn server_address(&self) -> Option<String> {
+        let ProxyPassAddr::Host { host, port } = &self.addr else {
+            return None;
+        };
+        let port = port.unwrap_or(if self.tls { 443 } else { 80 });
+        Some(format!("{}:{}", host, port))
+    }
+
+    /// SNI для TLS до upstream (None - без TLS). Для IP адреса SNI не передается,
+    /// unix сокету передается localhost
+    pub fn tls_sni(&self) -> Option<String> {
+        if !self.tls {
+            return None;
+        }
+        Some(match &self.addr {
+            ProxyPassAddr::Unix(_) => "localhost".to_string(),
+            ProxyPassAddr::Host { host, .. } if host.starts_with('[') || host.parse::<IpAddr>().is_ok() => String::new(),
+            ProxyPassAddr::Host { host, .. } => host.clone(),
+        })
+    }
+
+    /// URI запроса к upstream: префикс location заменяется URI из proxy_pass,
+    /// без URI в proxy_pass - None (путь передается без изменений)
+    pub fn upstream_uri(&self, location_path: &str, request_uri: &str) -> Option<String> {
+        let uri = self.uri.as_deref()?;
+        let rest = request_uri.strip_prefix(location_path)?;
+        Some(format!("{}{}", uri, rest))
+    }
+}
+
+impl fmt::Display for ProxyPassUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = if self.tls { "https" } else { "http" };
+        match &self.addr {
+            ProxyPassAddr::Unix(path) => {
+                write!(f, "{}://unix:{}", scheme, path)?;
+                if self.uri.is_some() {
+                    f.write_str(":")?;
+                }
+            }
+            ProxyPassAddr::Host { host, port: Some(port) } => write!(f, "{}://{}:{}", scheme, host, port)?,
+            ProxyPassAddr::Host { host, port: None } => write!(f, "{}://{}", scheme, host)?,
+        }
+        f.write_str(self.uri.as_deref().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tls_unix_socket_with_uri() {
+        let url = ProxyPassUrl::parse("https://unix:/run/app.sock:/api/").unwrap().unwrap();
+        assert!(url.tls);
+        assert_eq!(url.addr, ProxyPassAddr::Unix("/run/app.sock".to_string()));
+        assert_eq!(url.uri.as_deref(), Some("/api/"));
+        assert_eq!(url.to_string(), "https://unix:/run/app.sock:/api/");
+
+        let url = ProxyPassUrl::parse("http://unix:/var/run/php/app.sock").unwrap().unwrap();
+        assert!(!url.tls);
+        assert_eq!(url.addr, ProxyPassAddr::Unix("/var/run/php/app.sock".to_string()));
+        assert_eq!(url.uri, None);
+
+        // Имя upstream блока
+        assert_eq!(ProxyPassUrl::parse("core_api").unwrap(), None);
+
+        assert!(ProxyPassUrl::parse("https://unix:run/app.sock").is_err());
+        assert!(ProxyPassUrl::parse("http://unix:/run/app.sock:api/").is_err());
+        assert!(ProxyPassUrl::parse("grpc://unix:/run/app.sock").is_err());
+    }
+
+    #[test]
+    fn test_parse_host_and_port() {
+        let host = |value: &str| ProxyPassUrl::parse(value).unwrap().unwrap();
+
+        let ip = host("http://127.0.0.1:3000");
+        assert_eq!(ip.addr, ProxyPassAddr::Host { host: "127.0.0.1".to_string(), port: Some(3000) });
+        assert_eq!((ip.uri.as_deref(), ip.server_address().as_deref(), ip.tls_sni()), (None, Some("127.0.0.1:3000"), None));
+
+        let name = host("http://backend.internal");
+        assert_eq!(name.addr, ProxyPassAddr::Host { host: "backend.internal".to_string(), port: None });
+        assert_eq!(name.server_address().as_deref(), Some("backend.internal:80"));
+
+        let tls = host("https://api.example.com/v1/");
+        assert_eq!(tls.server_address().as_deref(), Some("api.example.com:443"));
+        assert_eq!(tls.uri.as_deref(), Some("/v1/"));
+        assert_eq!(tls.tls_sni().as_deref(), Some("api.example.com"));
+        assert_eq!(tls.upstream_uri("/partner/", "/partner/orders?id=1").as_deref(), Some("/v1/orders?id=1"));
+        assert_eq!(tls.to_string(), "https://api.example.com/v1/");
+
+        let v6 = host("https://[2001:db8::10]:8443/");
+        assert_eq!(v6.server_address().as_deref(), Some("[2001:db8::10]:8443"));
+        assert_eq!(v6.tls_sni().as_deref(), Some(""));
+        assert_eq!(v6.to_string(), "https://[2001:db8::10]:8443/");
+
+        for invalid in ["http://", "http://:8080", "http://127.0.0.1:0", "http://api:http", "http://[::1", "http://a b"] {
+            assert!(ProxyPassUrl::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_upstream_uri_replaces_location_prefix() {
+        let url = ProxyPassUrl::parse("https://unix:/run/app.sock:/api/").unwrap().unwrap();
+        assert_eq!(url.upstream_uri("/app/", "/app/users?page=2").as_deref(), Some("/api/users?page=2"));
+        assert_eq!(url.upstream_uri("/app/", "/app/").as_deref(), Some("/api/"));
+
+        let url = ProxyPassUrl::parse("http://unix:/run/app.sock").unwrap().unwrap();
+        assert_eq!(url.upstream_uri("/app/", "/app/users"), None);
+    }
+}
@@ -0,0 +1,227 @@
+use serde_yaml::{Mapping, Value};
+use std::fmt;
+
+use super::Config;
+use crate::error::ProxyError;
+
+/// Версия схемы proxy.yaml, которую понимает этот бинарник
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Миграция схемы: MIGRATIONS[i] переводит версию i + 1 в i + 2
+type Migration = fn(&mut Mapping, &mut Vec<String>);
+
+const MIGRATIONS: [Migration; (CONFIG_VERSION - 1) as usize] = [migrate_v1_to_v2];
+
+/// Версия исходного файла, выполненные миграции и проигнорированные поля
+#[derive(Debug, Clone, Default)]
+pub struct SchemaInfo {
+    /// Версия из файла до миграции
+    pub source_version: u32,
+    /// Описание каждого перенесенного поля ("v1 -> v2: ...")
+    pub migrations: Vec<String>,
+    /// Неизвестные поля, пропущенные без strict: true
+    pub unknown_fields: Vec<String>,
+    /// Фрагменты (conf.d), наложенные на основной файл, в порядке применения
+    pub fragments: Vec<String>,
+}
+
+/// Ошибка схемы конфигурации. Запуск с конфигурацией по умолчанию при ней недопустим
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaError {
+    /// Поле version отсутствует или не является числом
+    InvalidVersion(String),
+    /// Конфигурация новее бинарника
+    TooNew { version: u32 },
+    /// Неизвестные поля при strict: true
+    UnknownFields(Vec<String>),
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemaError::InvalidVersion(value) => write!(
+                f,
+                "invalid configuration version '{}', expected a number from 1 to {}",
+                value, CONFIG_VERSION
+            ),
+            SchemaError::TooNew { version } => write!(
+                f,
+                "configuration version {} is newer than supported version {}, upgrade adq-pingora",
+                version, CONFIG_VERSION
+            ),
+            SchemaError::UnknownFields(fields) => {
+                write!(f, "unknown configuration field(s) with strict: true: {}", fields.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+/// v2: ip_filter.blacklist_file -> blocklist_file, ip_filter.whitelist -> allowlist
+fn migrate_v1_to_v2(root: &mut Mapping, changes: &mut Vec<String>) {
+    rename_field(root, "ip_filter", "blacklist_file", "blocklist_file", 2, changes);
+    rename_field(root, "ip_filter", "whitelist", "allowlist", 2, changes);
+}
+
+/// Переименовывает поле секции; новое имя, указанное явно, имеет приоритет
+fn rename_field(root: &mut Mapping, section: &str, from: &str, to: &str, version: u32, changes: &mut Vec<String>) {
+    let Some(Value::Mapping(section_map)) = root.get_mut(section) else {
+        return;
+    };
+    let Some(value) = section_map.remove(from) else {
+        return;
+    };
+    if section_map.contains_key(to) {
+        changes.push(format!(
+            "v{} -> v{}: {}.{} dropped, {}.{} is already set",
+            version - 1, version, section, from, section, to
+        ));
+        return;
+    }
+    section_map.insert(Value::from(to), value);
+    changes.push(format!(
+        "v{} -> v{}: {}.{} renamed to {}.{}",
+        version - 1, version, section, from, section, to
+    ));
+}
+
+/// Приводит YAML к текущей версии схемы
+pub fn migrate(value: &mut Value) -> Result<SchemaInfo, SchemaError> {
+    let Value::Mapping(root) = value else {
+        return Ok(SchemaInfo { source_version: CONFIG_VERSION, ..Default::default() });
+    };
+    let version = match root.get("version") {
+        Some(Value::Number(n)) => n
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .filter(|v| *v >= 1)
+            .ok_or_else(|| SchemaError::InvalidVersion(n.to_string()))?,
+        Some(other) => {
+            let shown = serde_yaml::to_string(other).unwrap_or_default();
+            return Err(SchemaError::InvalidVersion(shown.trim().to_string()));
+        }
+        None => return Err(SchemaError::InvalidVersion("missing".to_string())),
+    };
+    if version > CONFIG_VERSION {
+        return Err(SchemaError::TooNew { version });
+    }
+
+    let mut migrations = Vec::new();
+    for migration in &MIGRATIONS[(version - 1) as usize..] {
+        migration(root, &mut migrations);
+    }
+    root.insert(Value::from("version"), Value::from(CONFIG_VERSION));
+
+    Ok(SchemaInfo { source_version: version, migrations, ..Default::default() })
+}
+
+/// Разбирает proxy.yaml: миграция схемы, затем десериализация.
+/// С strict: true неизвестные поля (опечатки вроде circut_breaker) - ошибка
+pub fn parse_config(content: &str) -> Result<Config, ProxyError> {
+    parse_config_value(serde_yaml::from_str(content)?)
+}
+
+/// Разбор уже прочитанного YAML (основной файл с наложенными фрагментами)
+pub fn parse_config_value(mut value: Value) -> Result<Config, ProxyError> {
+    let mut schema = migrate(&mut value)?;
+    let strict = value.get("strict").and_then(Value::as_bool).unwrap_or(false);
+
+    let mut unknown_fields = Vec::new();
+    let mut config: Config = serde_ignored::deserialize(value, |path| unknown_fields.push(path.to_string()))?;
+    if strict && !unknown_fields.is_empty() {
+        return Err(SchemaError::UnknownFields(unknown_fields).into());
+    }
+    schema.unknown_fields = unknown_fields;
+    config.schema = schema;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Конфигурация по умолчанию в текущей схеме
+    fn current() -> String {
+        let mut config = Config::default();
+        config.ip_filter.blocklist_file = Some("/etc/adq-pingora/blacklist.txt".to_string());
+        config.ip_filter.allowlist = Some(vec!["127.0.0.1".to_string()]);
+        serde_yaml::to_string(&config).unwrap()
+    }
+
+    /// Та же конфигурация в схеме v1
+    fn v1() -> String {
+        current()
+            .replacen(&format!("version: {}", CONFIG_VERSION), "version: 1", 1)
+            .replace("blocklist_file:", "blacklist_file:")
+            .replace("allowlist:", "whitelist:")
+    }
+
+    #[test]
+    fn test_current_version_loads_without_migrations() {
+        let config = parse_config(&current()).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.schema.source_version, CONFIG_VERSION);
+        assert!(config.schema.migrations.is_empty());
+        assert!(config.schema.unknown_fields.is_empty());
+        assert_eq!(config.ip_filter.blocklist_file.as_deref(), Some("/etc/adq-pingora/blacklist.txt"));
+    }
+
+    #[test]
+    fn test_v1_is_migrated_with_warnings() {
+        let config = parse_config(&v1()).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.schema.source_version, 1);
+        assert_eq!(
+            config.schema.migrations,
+            vec![
+                "v1 -> v2: ip_filter.blacklist_file renamed to ip_filter.blocklist_file".to_string(),
+                "v1 -> v2: ip_filter.whitelist renamed to ip_filter.allowlist".to_string(),
+            ]
+        );
+        assert_eq!(config.ip_filter.blocklist_file.as_deref(), Some("/etc/adq-pingora/blacklist.txt"));
+        assert_eq!(config.ip_filter.allowlist, Some(vec!["127.0.0.1".to_string()]));
+
+        // Новое имя уже задано: старое поле отбрасывается
+        let both = v1().replace("  whitelist:", "  allowlist: [\"10.0.0.1\"]\n  whitelist:");
+        let config = parse_config(&both).unwrap();
+        assert_eq!(config.ip_filter.allowlist, Some(vec!["10.0.0.1".to_string()]));
+        assert!(config.schema.migrations[1].contains("ip_filter.whitelist dropped"));
+    }
+
+    #[test]
+    fn test_newer_and_invalid_versions_are_errors() {
+        let newer = v1().replacen("version: 1", &format!("version: {}", CONFIG_VERSION + 1), 1);
+        let e = parse_config(&newer).unwrap_err();
+        assert!(matches!(&e, ProxyError::Schema(SchemaError::TooNew { version }) if *version == CONFIG_VERSION + 1));
+        assert!(e.to_string().contains("upgrade adq-pingora"));
+
+        for version in ["version: 0", "version: two", "version: -1"] {
+            let e = parse_config(&v1().replacen("version: 1", version, 1)).unwrap_err();
+            assert!(matches!(e, ProxyError::Schema(SchemaError::InvalidVersion(_))), "{}", version);
+        }
+        let e = parse_config(&v1().replacen("version: 1\n", "", 1)).unwrap_err();
+        assert!(matches!(e, ProxyError::Schema(SchemaError::InvalidVersion(_))));
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        let typo = current().replacen("circuit_breaker:", "circuit_breaker:\n  failure_treshold: 3", 1);
+
+        // Без strict поле пропускается и попадает в предупреждения
+        let config = parse_config(&typo).unwrap();
+        assert_eq!(config.schema.unknown_fields, vec!["circuit_breaker.failure_treshold".to_string()]);
+
+        let strict = typo.replacen("strict: false", "strict: true", 1);
+        let e = parse_config(&strict).unwrap_err();
+        assert!(matches!(&e, ProxyError::Schema(SchemaError::UnknownFields(fields))
+            if fields == &["circuit_breaker.failure_treshold".to_string()]));
+
+        let strict = format!("circut_breaker:\n  enabled: true\n{}", current().replacen("strict: false", "strict: true", 1));
+        let e = parse_config(&strict).unwrap_err();
+        assert!(e.to_string().contains("circut_breaker"), "{}", e);
+
+        // strict: true с корректной конфигурацией
+        assert!(parse_config(&current().replacen("strict: false", "strict: true", 1)).unwrap().strict);
+    }
+}
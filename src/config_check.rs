@@ -0,0 +1,809 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::body_transform::BodyPipeline;
+use crate::compression::Compression;
+use crate::config::{parse_size, AccessLogDirective, Config, SourcePos, CONFIG_VERSION};
+use crate::default_page;
+use crate::error;
+use crate::error_messages::ErrorMessages;
+use crate::experiments::Experiments;
+use crate::filter::{HeaderRules, UaFilter};
+use crate::metrics;
+use crate::preflight;
+use crate::schedules::Schedules;
+use crate::scheme::SchemeResolver;
+use crate::ssl;
+use crate::variables;
+use crate::wasm_filter;
+
+/// Формат вывода `adq-pingora -t`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Строки в стиле `nginx -t`
+    Text,
+    Json,
+    /// SARIF 2.1.0 для code scanning в CI
+    Sarif,
+}
+
+impl OutputFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "text" => Some(Self::Text),
+            "json" => Some(Self::Json),
+            "sarif" => Some(Self::Sarif),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Ход проверки; выводится только в текстовом формате
+    Info,
+    Warning,
+    Error,
+}
+
+/// Результат одной проверки конфигурации
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Файл, к которому относится проверка (None - проверка окружения)
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+/// Результаты `adq-pingora -t` в порядке проверок
+#[derive(Debug, Clone)]
+pub struct Report {
+    config_path: String,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Report {
+    pub fn new(config_path: &str) -> Self {
+        Self { config_path: config_path.to_string(), diagnostics: Vec::new() }
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn unlocated(&mut self, severity: Severity, file: Option<String>, message: impl Into<String>) {
+        self.push(Diagnostic { severity, file, line: None, column: None, message: message.into() });
+    }
+
+    pub fn info(&mut self, message: impl Into<String>) {
+        self.unlocated(Severity::Info, None, message);
+    }
+
+    /// Предупреждение по основному файлу конфигурации
+    pub fn warn(&mut self, message: impl Into<String>) {
+        self.unlocated(Severity::Warning, Some(self.config_path.clone()), message);
+    }
+
+    /// Ошибка в основном файле конфигурации
+    pub fn error(&mut self, message: impl Into<String>) {
+        self.unlocated(Severity::Error, Some(self.config_path.clone()), message);
+    }
+
+    /// Предупреждение об окружении процесса, без файла
+    pub fn env_warn(&mut self, message: impl Into<String>) {
+        self.unlocated(Severity::Warning, None, message);
+    }
+
+    /// Ошибка в nginx конфигурации из sites-enabled; место блока, если оно известно
+    pub fn nginx_error(&mut self, pos: &SourcePos, message: impl Into<String>) {
+        self.located(Severity::Error, pos, message);
+    }
+
+    pub fn nginx_warn(&mut self, pos: &SourcePos, message: impl Into<String>) {
+        self.located(Severity::Warning, pos, message);
+    }
+
+    fn located(&mut self, severity: Severity, pos: &SourcePos, message: impl Into<String>) {
+        // line 0 - блок создан не парсером, места нет
+        let known = pos.line > 0;
+        self.push(Diagnostic {
+            severity,
+            file: pos.file.clone(),
+            line: known.then_some(pos.line),
+            column: known.then_some(pos.column),
+            message: message.into(),
+        });
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.diagnostics.iter().filter(|d| d.severity == severity).count()
+    }
+
+    pub fn errors(&self) -> usize {
+        self.count(Severity::Error)
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.count(Severity::Warning)
+    }
+
+    fn problems(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|d| d.severity != Severity::Info)
+    }
+
+    /// Вывод как у `nginx -t`: ход проверки, ошибки и итоговая строка
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("adq-pingora: testing configuration file...\n");
+        for diagnostic in &self.diagnostics {
+            let prefix = match diagnostic.severity {
+                Severity::Info => "",
+                Severity::Warning => "[warn] ",
+                Severity::Error => "[error] ",
+            };
+            text.push_str(&format!("adq-pingora: {}{}", prefix, diagnostic.message));
+            // Место как у nginx -t: "in /etc/nginx/sites-enabled/api.conf:12"
+            if let (Some(file), Some(line)) = (&diagnostic.file, diagnostic.line) {
+                text.push_str(&format!(" in {}:{}", file, line));
+            } else if let Some(line) = diagnostic.line {
+                text.push_str(&format!(" at line {}", line));
+            }
+            text.push('\n');
+        }
+        let summary = if self.errors() > 0 {
+            format!("configuration file {} test failed", self.config_path)
+        } else if self.warnings() > 0 {
+            format!("configuration file {} test is successful (with {} warning(s))", self.config_path, self.warnings())
+        } else {
+            format!("configuration file {} test is successful", self.config_path)
+        };
+        text.push_str(&format!("adq-pingora: {}\n", summary));
+        text
+    }
+
+    /// `{"file", "ok", "errors", "warnings", "diagnostics": [...]}` без информационных строк
+    pub fn to_json(&self) -> String {
+        self.to_json_value().to_string()
+    }
+
+    pub fn to_json_value(&self) -> serde_json::Value {
+        let diagnostics: Vec<&Diagnostic> = self.problems().collect();
+        serde_json::json!({
+            "file": self.config_path,
+            "ok": self.errors() == 0,
+            "errors": self.errors(),
+            "warnings": self.warnings(),
+            "diagnostics": diagnostics,
+        })
+    }
+
+    /// Лог SARIF 2.1.0 с одним run
+    pub fn to_sarif(&self) -> String {
+        let results: Vec<serde_json::Value> = self
+            .problems()
+            .map(|diagnostic| {
+                let mut result = serde_json::json!({
+                    "level": if diagnostic.severity == Severity::Error { "error" } else { "warning" },
+                    "message": { "text": diagnostic.message },
+                });
+                if let Some(file) = &diagnostic.file {
+                    let mut location = serde_json::json!({ "artifactLocation": { "uri": file } });
+                    if let (Some(line), Some(column)) = (diagnostic.line, diagnostic.column) {
+                        location["region"] = serde_json::json!({ "startLine": line, "startColumn": column });
+                    }
+                    result["locations"] = serde_json::json!([{ "physicalLocation": location }]);
+                }
+                result
+            })
+            .collect();
+        serde_json::json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": [{
+                "tool": { "driver": { "name": "adq-pingora", "version": env!("CARGO_PKG_VERSION") } },
+                "results": results,
+            }],
+        })
+        .to_string()
+    }
+
+    pub fn render(&self, format: OutputFormat) -> String {
+        match format {
+            OutputFormat::Text => self.to_text(),
+            OutputFormat::Json => self.to_json(),
+            OutputFormat::Sarif => self.to_sarif(),
+        }
+    }
+}
+
+/// Загружает конфигурацию с фрагментами и выполняет все проверки `adq-pingora -t`
+pub fn check_configuration(config_path: &str, config_dirs: &[PathBuf]) -> Report {
+    check_loaded(config_path, &Config::load_with_fragments(config_path, config_dirs))
+}
+
+/// `adq-pingora -t`: печатает отчет в формате `format` и возвращает код завершения.
+/// С `check_ports` ошибками считаются и адреса listen, которые сейчас не удается занять;
+/// если других ошибок нет, код - preflight::EXIT_PORT_CONFLICT
+pub fn run(config_path: &str, config_dirs: &[PathBuf], format: OutputFormat, check_ports: bool) -> i32 {
+    let mut report = check_configuration(config_path, config_dirs);
+    let mut port_conflicts = 0;
+    if check_ports {
+        let loaded = Config::load_with_fragments(config_path, config_dirs).ok();
+        if let Some(addrs) = loaded.and_then(|config| config.listen_addrs().ok()) {
+            for conflict in preflight::check_listeners(&addrs, &preflight::inherited_addrs()) {
+                report.error(conflict.to_string());
+                port_conflicts += 1;
+            }
+        }
+    }
+    print!("{}", report.render(format));
+    if format != OutputFormat::Text {
+        println!();
+    }
+    if port_conflicts > 0 && report.errors() == port_conflicts {
+        return preflight::EXIT_PORT_CONFLICT;
+    }
+    if report.errors() > 0 {
+        return 1;
+    }
+    0
+}
+
+/// Проверки `-t` для результата загрузки конфигурации (используется и при reload)
+pub fn check_loaded(config_path: &str, loaded: &error::Result<Config>) -> Report {
+    let mut report = Report::new(config_path);
+    match loaded {
+        Ok(config) => {
+            report.info(format!("configuration file {} syntax is ok", config_path));
+            check_config(config, &mut report);
+            check_environment(&mut report);
+        }
+        Err(e) => report.error(format!("configuration file {} test failed: {}", config_path, e)),
+    }
+    report
+}
+
+/// Проверки загруженной конфигурации: секции YAML и nginx-style конфигурация
+pub fn check_config(config: &Config, report: &mut Report) {
+    for fragment in &config.schema.fragments {
+        report.info(format!("fragment {} applied", fragment));
+    }
+    if config.schema.source_version == CONFIG_VERSION {
+        report.info(format!("configuration version {}", CONFIG_VERSION));
+    } else {
+        report.info(format!("configuration version {} (migrated to {})", config.schema.source_version, CONFIG_VERSION));
+    }
+    for migration in &config.schema.migrations {
+        report.warn(format!("migrated {}", migration));
+    }
+    for field in &config.schema.unknown_fields {
+        report.warn(format!("unknown field '{}' ignored (strict: true rejects it)", field));
+    }
+
+    let section_checks = [
+        // Имена стадий и стадии включенных компонентов
+        config.validate_pipeline(),
+        config.proxy_headers.validate(),
+        config.response_headers.validate(),
+        SchemeResolver::from_config(&config.proxy_headers).map(|_| ()),
+        // Регулярные выражения правил User-Agent
+        UaFilter::from_config(&config.ua_filter).map(|_| ()),
+        // Правила по заголовкам запроса
+        HeaderRules::from_config(&config.header_rules).map(|_| ()),
+        // Профили бэкендов и ссылки на них из location
+        config.validate_backend_profiles(),
+        config.idempotency.validate(),
+        config.wasm_filters.validate(),
+        config.challenge.validate(),
+        config.health_events.validate(),
+        config.http_client.validate(),
+        BodyPipeline::from_config(&config.redaction).map(|_| ()),
+        Compression::from_config(&config.compression).map(|_| ()),
+        Experiments::from_config(&config.experiments).map(|_| ()),
+        Schedules::from_config(&config.schedules)
+            .and_then(|schedules| schedules.check_references(config)),
+        ErrorMessages::from_config(&config.error_messages).map(|_| ()),
+        config.default_page.validate(),
+        config.config_limits.validate(),
+        config.ip_filter.validate(),
+        config.logging.validate_rotation(),
+        config.logging.validate_sampling(),
+        config.global.tcp_keepalive.as_ref().map_or(Ok(()), |keepalive| keepalive.validate()),
+    ];
+    for e in section_checks.into_iter().filter_map(Result::err) {
+        report.error(e);
+    }
+
+    if let Some(nginx) = &config.nginx_config {
+        match config.config_limits.check(nginx) {
+            Ok(warnings) => warnings.into_iter().for_each(|warning| report.warn(warning)),
+            Err(e) => report.error(e),
+        }
+    }
+
+    // Нечитаемый файл страницы не мешает старту: отдается inline или встроенная страница
+    if let Some(file) = &config.default_page.file {
+        if let Err(e) = default_page::read_file(file) {
+            report.warn(e);
+        }
+    }
+
+    if let Some(namespace) = &config.logging.metrics.namespace {
+        if let Err(e) = metrics::normalize_namespace(namespace) {
+            report.error(e);
+        }
+    }
+
+    if config.logging.metrics.process_interval == 0 {
+        report.error("logging.metrics.process_interval must be greater than 0");
+    }
+
+    if parse_size(&config.global.buffered_body_budget).is_none() {
+        report.error(format!("invalid global.buffered_body_budget: {}", config.global.buffered_body_budget));
+    }
+
+    if parse_size(&config.global.max_header_size).is_none() {
+        report.error(format!("invalid global.max_header_size: {}", config.global.max_header_size));
+    }
+
+    if let Err(e) = config.logging.metrics.listen_addr() {
+        report.error(e);
+    }
+
+    if let Err(e) = config.listen_addrs() {
+        report.error(e);
+    }
+
+    // Проверяем nginx-style конфигурацию
+    let Some(nginx_config) = &config.nginx_config else {
+        report.env_warn("no server configurations found in sites-enabled/");
+        return;
+    };
+    report.info(format!(
+        "found {} server(s) and {} upstream(s)",
+        nginx_config.servers.len(),
+        nginx_config.upstreams.len()
+    ));
+
+    // Блоки и файлы, пропущенные при разборе
+    for issue in &nginx_config.issues {
+        report.push(Diagnostic {
+            severity: Severity::Error,
+            file: issue.file.clone(),
+            line: issue.line,
+            column: issue.column,
+            message: issue.message.clone(),
+        });
+    }
+
+    // Проверяем каждый сервер
+    for (i, server) in nginx_config.servers.iter().enumerate() {
+        report.info(format!("testing server {} ({})", i + 1, server.server_names.join(", ")));
+
+        // Проверяем SSL сертификаты
+        if let (Some(cert), Some(key)) = (&server.ssl_certificate, &server.ssl_certificate_key) {
+            if !Path::new(cert).exists() {
+                report.nginx_warn(&server.pos, format!("SSL certificate not found: {}", cert));
+            }
+            if !Path::new(key).exists() {
+                report.nginx_warn(&server.pos, format!("SSL private key not found: {}", key));
+            }
+            // Файлы на месте - они должны загружаться и составлять пару
+            if Path::new(cert).exists() && Path::new(key).exists() {
+                if let Err(e) = ssl::check_certificate_pair(cert, key) {
+                    report.nginx_error(&server.pos, e);
+                }
+            }
+        }
+
+        // Директории файлов access_log должны существовать
+        let access_logs = std::iter::once(&server.access_log)
+            .chain(server.locations.iter().map(|location| &location.access_log));
+        for access_log in access_logs.flatten() {
+            if let AccessLogDirective::File { path, .. } = access_log {
+                let dir = Path::new(path).parent().filter(|dir| !dir.as_os_str().is_empty());
+                if dir.is_some_and(|dir| !dir.is_dir()) {
+                    report.nginx_error(&server.pos, format!("access_log directory does not exist: {}", path));
+                }
+            }
+        }
+
+        // Проверяем locations
+        for location in &server.locations {
+            // proxy_pass в форме URL не ссылается на upstream блок
+            if let Some(upstream) = location.proxy_pass.as_ref().filter(|_| location.proxy_pass_url.is_none()) {
+                if !nginx_config.upstreams.contains_key(upstream) {
+                    report.nginx_error(&location.pos, format!("upstream '{}' not found for location '{}'", upstream, location.path));
+                }
+            }
+
+            // Зоны limit_req и limit_conn должны быть объявлены
+            for limit in &location.limit_req {
+                if !nginx_config.limit_req_zones.contains_key(&limit.zone) {
+                    report.nginx_error(&location.pos, format!("limit_req zone '{}' not found for location '{}'", limit.zone, location.path));
+                }
+                // nginx задержал бы запросы в пределах burst, а прокси пропускает их сразу
+                if limit.burst > 0 && !limit.nodelay {
+                    report.nginx_error(
+                        &location.pos,
+                        format!("limit_req zone '{}' for location '{}' has burst without nodelay; delaying is not supported", limit.zone, location.path),
+                    );
+                }
+            }
+            for zone in location.limit_conn.iter().map(|limit| &limit.zone) {
+                if !nginx_config.limit_conn_zones.contains_key(zone) {
+                    report.nginx_error(&location.pos, format!("limit_conn zone '{}' not found for location '{}'", zone, location.path));
+                }
+            }
+
+            // Файл заглушки fallback_response должен существовать
+            if let Some(fallback) = &location.fallback_response {
+                if !Path::new(&fallback.file).is_file() {
+                    report.nginx_error(&location.pos, format!(
+                        "fallback_response file not found for location '{}': {}",
+                        location.path, fallback.file
+                    ));
+                }
+            }
+
+            // Переменные в add_header, proxy_set_header, return и proxy_no_cache: встроенные или из map
+            let templates = location
+                .add_header
+                .iter()
+                .map(|header| &header.value)
+                .chain(location.proxy_set_header.iter().map(|(_, value)| value))
+                .chain(location.return_directive.iter().flat_map(|directive| &directive.value))
+                .chain(&location.proxy_no_cache);
+            for variable in templates.flat_map(|value| variables::referenced(value)) {
+                if !variables::is_builtin(variable) && !nginx_config.maps.contains_key(variable) {
+                    report.nginx_error(&location.pos, format!("unknown variable '${}' in location '{}'", variable, location.path));
+                }
+            }
+
+            // Модуль wasm_filter компилируется (стадия в pipeline проверяет validate_pipeline)
+            if let Some(filter) = &location.wasm_filter {
+                if let Err(e) = wasm_filter::check_module(&filter.path, &config.wasm_filters) {
+                    report.nginx_error(&location.pos, format!("wasm_filter for location '{}': {}", location.path, e));
+                }
+            }
+
+            // redact без redaction.paths ничего не скрывает
+            if location.redact && config.redaction.paths.is_empty() {
+                report.nginx_warn(&location.pos, format!("redact is on for location '{}' but redaction.paths is empty", location.path));
+            }
+
+            // first-byte timeout не может превышать read timeout
+            let timeouts = config.resolve_upstream_timeouts(Some(location));
+            if timeouts.first_byte > timeouts.read {
+                report.nginx_error(&location.pos, format!(
+                    "proxy_first_byte_timeout ({:?}) exceeds proxy_read_timeout ({:?}) for location '{}'",
+                    timeouts.first_byte, timeouts.read, location.path
+                ));
+            }
+        }
+    }
+
+    // Проверяем upstreams
+    for (upstream_name, upstream) in &nginx_config.upstreams {
+        if upstream.implicit {
+            report.info(format!("upstream '{}' implied by proxy_pass", upstream_name));
+        } else if upstream.servers.is_empty() {
+            report.nginx_error(&upstream.pos, format!("upstream '{}' has no servers", upstream_name));
+        } else {
+            report.info(format!("upstream '{}' has {} server(s)", upstream_name, upstream.servers.len()));
+        }
+
+        // Переменные ключа hash: встроенные или из map
+        for variable in upstream.hash_key.iter().flat_map(|key| variables::referenced(key)) {
+            if !variables::is_builtin(variable) && !nginx_config.maps.contains_key(variable) {
+                report.nginx_error(&upstream.pos, format!("unknown variable '${}' in hash of upstream '{}'", variable, upstream_name));
+            }
+        }
+    }
+
+    // Источник map - встроенная переменная или другой map
+    for map in nginx_config.maps.values() {
+        if !variables::is_builtin(&map.source) && !nginx_config.maps.contains_key(&map.source) {
+            report.nginx_error(&map.pos, format!("unsupported source variable '${}' in map ${}", map.source, map.variable));
+        }
+    }
+
+    // Резервный upstream должен быть объявлен
+    if let Some(default_upstream) = &config.global.default_upstream {
+        if !nginx_config.upstreams.contains_key(default_upstream) {
+            report.error(format!("global.default_upstream '{}' not found", default_upstream));
+        }
+    }
+}
+
+/// Окружение процесса: каталог sites-enabled и права на привилегированные порты
+fn check_environment(report: &mut Report) {
+    let sites_enabled = "/etc/adq-pingora/sites-enabled";
+    if !Path::new(sites_enabled).exists() {
+        report.env_warn("sites-enabled directory not found");
+    } else {
+        let count = std::fs::read_dir(sites_enabled).map(|entries| entries.count()).unwrap_or(0);
+        report.info(format!("found {} enabled site(s)", count));
+    }
+
+    if std::env::var("USER").unwrap_or_default() != "root" {
+        report.env_warn("not running as root, may not be able to bind to ports 80/443");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NginxConfig;
+
+    fn broken_config() -> Config {
+        let mut config = Config::default();
+        config.logging.metrics.process_interval = 0;
+        config.global.max_header_size = "lots".to_string();
+        config.nginx_config = Some(
+            NginxConfig::parse_config_content(
+                "server { listen 80; server_name api.example.com; location /api/ { proxy_pass missing_api; } }",
+            )
+            .unwrap(),
+        );
+        config
+    }
+
+    #[test]
+    fn test_json_lists_diagnostics() {
+        let mut report = Report::new("/etc/adq-pingora/proxy.yaml");
+        check_config(&broken_config(), &mut report);
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["ok"], false);
+        assert_eq!(json["errors"], 3);
+        let diagnostics = json["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 3, "{}", json);
+        assert!(diagnostics.iter().all(|d| d["severity"] == "error"));
+
+        assert_eq!(diagnostics[0]["message"], "logging.metrics.process_interval must be greater than 0");
+        assert_eq!(diagnostics[0]["file"], "/etc/adq-pingora/proxy.yaml");
+        assert_eq!(diagnostics[1]["message"], "invalid global.max_header_size: lots");
+        assert_eq!(diagnostics[2]["message"], "upstream 'missing_api' not found for location '/api/'");
+        // Место location в nginx конфигурации
+        assert!(diagnostics[2]["file"].is_null());
+        assert_eq!(diagnostics[2]["line"], 1);
+        assert_eq!(diagnostics[2]["column"], 50);
+    }
+
+    #[test]
+    fn test_sarif_and_text_output() {
+        let mut report = Report::new("proxy.yaml");
+        check_config(&broken_config(), &mut report);
+
+        let sarif: serde_json::Value = serde_json::from_str(&report.to_sarif()).unwrap();
+        assert_eq!(sarif["version"], "2.1.0");
+        let results = sarif["runs"][0]["results"].as_array().unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0]["level"], "error");
+        assert_eq!(results[0]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"], "proxy.yaml");
+        assert!(results[2].get("locations").is_none());
+
+        let text = report.to_text();
+        assert!(text.contains("adq-pingora: [error] invalid global.max_header_size: lots\n"));
+        assert!(text.contains("adq-pingora: [error] upstream 'missing_api' not found for location '/api/' at line 1\n"));
+        assert!(text.ends_with("adq-pingora: configuration file proxy.yaml test failed\n"));
+    }
+
+    #[test]
+    fn test_wasm_filter_checked() {
+        let mut config = Config::default();
+        config.pipeline.stages.retain(|stage| stage != "wasm_filter");
+        config.nginx_config = Some(
+            NginxConfig::parse_config_content(
+                "upstream api { server 10.0.0.1:8080; }\nserver { listen 80; server_name api.example.com;\n  location /tenants/ { proxy_pass api; wasm_filter /nonexistent/tenant.wasm; } }",
+            )
+            .unwrap(),
+        );
+        let mut report = Report::new("proxy.yaml");
+        check_config(&config, &mut report);
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        let diagnostics = json["diagnostics"].as_array().unwrap();
+        let error = diagnostics
+            .iter()
+            .find(|d| d["severity"] == "error" && d["message"].as_str().unwrap().starts_with("wasm_filter for location"))
+            .unwrap();
+        assert!(error["message"].as_str().unwrap().starts_with("wasm_filter for location '/tenants/': "), "{}", error);
+        assert_eq!(error["line"], 3);
+        assert!(diagnostics.iter().any(|d| d["severity"] == "error"
+            && d["message"] == "a location sets wasm_filter but pipeline.stages has no wasm_filter stage"));
+    }
+
+    #[test]
+    fn test_pipeline_missing_enabled_stages() {
+        let check = |config: &Config| {
+            let mut report = Report::new("proxy.yaml");
+            check_config(config, &mut report);
+            let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+            json["diagnostics"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter(|d| d["severity"] == "error")
+                .map(|d| d["message"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let mut config = Config::default();
+        config.pipeline.stages = vec!["routing".to_string(), "rewrite".to_string()];
+        assert!(check(&config).iter().any(|e| e == "unknown pipeline stage 'rewrite'"));
+
+        config.pipeline.stages = vec!["routing".to_string()];
+        config.nginx_config = Some(
+            NginxConfig::parse_config_content(
+                "limit_conn_zone $binary_remote_addr zone=addr:10m;\nupstream api { server 10.0.0.1:8080; }\nserver { listen 80; server_name api.example.com;\n  location /api/ { proxy_pass api; limit_conn addr 10; } }",
+            )
+            .unwrap(),
+        );
+        assert!(check(&config)
+            .iter()
+            .any(|e| e == "a location uses rate_limit, limit_req or limit_conn but pipeline.stages has no rate_limit stage"));
+
+        config.pipeline.stages = vec!["rate_limit".to_string(), "routing".to_string()];
+        config.circuit_breaker.enabled = true;
+        assert!(check(&config)
+            .iter()
+            .any(|e| e == "circuit_breaker is enabled but pipeline.stages has no circuit_breaker stage"));
+
+        config.pipeline.stages.push("circuit_breaker".to_string());
+        assert!(!check(&config).iter().any(|e| e.contains("pipeline")));
+    }
+
+    /// Конфигурация по умолчанию без стадии `stage` в pipeline
+    fn config_without_stage(stage: &str) -> Config {
+        let mut config = Config::default();
+        config.pipeline.stages.retain(|s| s != stage);
+        config
+    }
+
+    #[test]
+    fn test_pipeline_missing_maintenance_stage() {
+        let mut config = config_without_stage("maintenance");
+        config.maintenance = serde_yaml::from_str("- name: partner_api\n  schedule: partner_sunday\n").unwrap();
+        assert_eq!(
+            config.validate_pipeline().unwrap_err(),
+            "maintenance windows are configured but pipeline.stages has no maintenance stage"
+        );
+    }
+
+    #[test]
+    fn test_pipeline_missing_header_rules_stage() {
+        let mut config = config_without_stage("header_rules");
+        config.header_rules =
+            serde_yaml::from_str("- name: old_mobile_app\n  header: X-App-Version\n  version_below: \"2.4.0\"\n").unwrap();
+        assert_eq!(
+            config.validate_pipeline().unwrap_err(),
+            "header_rules are configured but pipeline.stages has no header_rules stage"
+        );
+    }
+
+    #[test]
+    fn test_pipeline_missing_wasm_filter_stage() {
+        let mut config = config_without_stage("wasm_filter");
+        config.nginx_config = Some(
+            NginxConfig::parse_config_content(
+                "upstream api { server 10.0.0.1:8080; }\nserver { listen 80; server_name api.example.com;\n  location /tenants/ { proxy_pass api; wasm_filter /etc/adq/tenant.wasm; } }",
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            config.validate_pipeline().unwrap_err(),
+            "a location sets wasm_filter but pipeline.stages has no wasm_filter stage"
+        );
+
+        // Со стадией в pipeline конфигурация проходит
+        config.pipeline = Default::default();
+        assert!(config.validate_pipeline().is_ok());
+    }
+
+    #[test]
+    fn test_limit_req_burst_requires_nodelay() {
+        let mut config = Config::default();
+        config.nginx_config = Some(
+            NginxConfig::parse_config_content(
+                "limit_req_zone $binary_remote_addr zone=api:10m rate=10r/s;\nupstream api { server 10.0.0.1:8080; }\nserver { listen 80; server_name api.example.com;\n  location /api/ { proxy_pass api; limit_req zone=api burst=20 nodelay; }\n  location /login { proxy_pass api; limit_req zone=api burst=5; }\n  location /health { proxy_pass api; limit_req zone=api; } }",
+            )
+            .unwrap(),
+        );
+        let mut report = Report::new("proxy.yaml");
+        check_config(&config, &mut report);
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        let errors: Vec<&str> = json["diagnostics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|d| d["severity"] == "error")
+            .map(|d| d["message"].as_str().unwrap())
+            .collect();
+        // Без burst задерживать нечего: `/health` проходит
+        assert_eq!(
+            errors,
+            vec!["limit_req zone 'api' for location '/login' has burst without nodelay; delaying is not supported"]
+        );
+    }
+
+    #[test]
+    fn test_ssl_certificate_must_match_key() {
+        use openssl::ec::{EcGroup, EcKey};
+        use openssl::nid::Nid;
+        use openssl::pkey::PKey;
+        use openssl::x509::{X509NameBuilder, X509};
+
+        let dir = tempfile::tempdir().unwrap();
+        let new_key = || {
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+            PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+        };
+        let key = new_key();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "shop.example.com").unwrap();
+        let name = name.build();
+        let mut cert = X509::builder().unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.sign(&key, openssl::hash::MessageDigest::sha256()).unwrap();
+
+        let write = |file: &str, pem: Vec<u8>| {
+            let path = dir.path().join(file);
+            std::fs::write(&path, pem).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        let cert_path = write("fullchain.pem", cert.build().to_pem().unwrap());
+        let key_path = write("privkey.pem", key.private_key_to_pem_pkcs8().unwrap());
+        let other_key_path = write("other.pem", new_key().private_key_to_pem_pkcs8().unwrap());
+        let garbage_path = write("garbage.pem", b"not a certificate".to_vec());
+
+        let check = |cert: &str, key: &str| {
+            let mut config = Config::default();
+            config.nginx_config = Some(
+                NginxConfig::parse_config_content(&format!(
+                    "upstream api {{ server 10.0.0.1:8080; }}\nserver {{ listen 443 ssl; server_name shop.example.com;\n  ssl_certificate {};\n  ssl_certificate_key {};\n  location / {{ proxy_pass api; }} }}",
+                    cert, key
+                ))
+                .unwrap(),
+            );
+            let mut report = Report::new("proxy.yaml");
+            check_config(&config, &mut report);
+            let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+            json["diagnostics"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter(|d| d["severity"] == "error")
+                .map(|d| d["message"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert!(check(&cert_path, &key_path).is_empty());
+        let errors = check(&cert_path, &other_key_path);
+        assert_eq!(errors, vec![format!("SSL private key {} does not match certificate {}", other_key_path, cert_path)]);
+        let errors = check(&garbage_path, &key_path);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with(&format!("cannot load SSL certificate {}: ", garbage_path)), "{:?}", errors);
+    }
+
+    #[test]
+    fn test_missing_file_is_error() {
+        let report = check_configuration("/nonexistent/adq-pingora.yaml", &[]);
+        assert_eq!(report.errors(), 1);
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        let message = json["diagnostics"][0]["message"].as_str().unwrap();
+        assert!(message.starts_with("configuration file /nonexistent/adq-pingora.yaml test failed"), "{}", message);
+        assert_eq!(OutputFormat::parse("sarif"), Some(OutputFormat::Sarif));
+        assert_eq!(OutputFormat::parse("xml"), None);
+    }
+}
@@ -1,5 +1,5 @@
 use pingora::prelude::*;
-use pingora::http::ResponseHeader;
+use pingora::http::{RequestHeader, ResponseHeader};
 use log::info;
 
 /// Обрабатывает CORS preflight запросы
@@ -33,6 +33,11 @@ pub async fn handle_cors_preflight(session: &mut Session, uri: &str) -> Result<b
 /// Добавляет CORS заголовки к ответу на основе Origin запроса
 /// Не добавляет заголовки, если они уже есть (например, от Zitadel)
 pub fn add_cors_headers_for_request(session: &Session, response: &mut ResponseHeader) -> Result<()> {
+    add_cors_headers_for_header(session.req_header(), response)
+}
+
+/// Добавляет CORS заголовки к ответу по заголовкам запроса клиента
+pub fn add_cors_headers_for_header(request: &RequestHeader, response: &mut ResponseHeader) -> Result<()> {
     // Проверяем, есть ли уже CORS заголовки от upstream (например, от Zitadel)
     // Если есть, не добавляем свои, чтобы не конфликтовать
     if response.headers.contains_key("access-control-allow-origin") {
@@ -41,8 +46,7 @@ pub fn add_cors_headers_for_request(session: &Session, response: &mut ResponseHe
     }
     
     // Получаем Origin из запроса
-    let origin = session
-        .req_header()
+    let origin = request
         .headers
         .get("origin")
         .and_then(|h| h.to_str().ok())
@@ -112,4 +116,21 @@ pub fn add_security_headers(response: &mut ResponseHeader) -> Result<()> {
     
     response.insert_header("Server", "Pingora/0.6.0")?;
     Ok(())
+}
+
+/// Для HTTPS запросов CSP требует загрузку всех ресурсов страницы по HTTPS
+pub fn add_scheme_csp(response: &mut ResponseHeader, scheme: &str) -> Result<()> {
+    if scheme != "https" {
+        return Ok(());
+    }
+    let csp = response
+        .headers
+        .get("content-security-policy")
+        .and_then(|v| v.to_str().ok())
+        .filter(|csp| !csp.contains("upgrade-insecure-requests"))
+        .map(|csp| format!("{}; upgrade-insecure-requests", csp));
+    if let Some(csp) = csp {
+        response.insert_header("Content-Security-Policy", csp)?;
+    }
+    Ok(())
 }
\ No newline at end of file
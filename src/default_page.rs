@@ -0,0 +1,115 @@
+use bytes::Bytes;
+use log::warn;
+
+use crate::config::DefaultPageConfig;
+
+/// Встроенная страница: отдается, если default_page.file и default_page.inline не заданы
+/// или файл не читается
+const BUILTIN_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>Welcome!</title>
+    <style>
+        body {
+            width: 35em;
+            margin: 0 auto;
+            font-family: Tahoma, Verdana, Arial, sans-serif;
+        }
+    </style>
+</head>
+<body>
+    <h1>Welcome!</h1>
+    <p>If you see this page, the proxy server is successfully installed and
+    working. Further configuration is required.</p>
+</body>
+</html>"#;
+
+/// Страница для запросов, не направленных ни в один сервис; загружается один раз
+/// при старте (reload перезапускает процесс)
+#[derive(Debug, Clone)]
+pub struct DefaultPage {
+    pub status: u16,
+    pub content_type: String,
+    pub body: Bytes,
+}
+
+/// Содержимое файла страницы и Content-Type по расширению (без расширения - text/html)
+pub fn read_file(path: &str) -> Result<(String, Bytes), String> {
+    let content = std::fs::read(path).map_err(|e| format!("failed to read default_page.file '{}': {}", path, e))?;
+    let content_type = mime_guess::from_path(path).first_raw().unwrap_or("text/html");
+    Ok((content_type.to_string(), Bytes::from(content)))
+}
+
+impl DefaultPage {
+    /// Источники по порядку: file, inline, встроенная страница. Нечитаемый файл
+    /// пропускается с предупреждением
+    pub fn load(config: &DefaultPageConfig) -> Self {
+        let (content_type, body) = config
+            .file
+            .as_deref()
+            .and_then(|path| read_file(path).map_err(|e| warn!("{}, using the next default page source", e)).ok())
+            .or_else(|| {
+                let inline = config.inline.as_ref()?;
+                Some(("text/html".to_string(), Bytes::from(inline.clone())))
+            })
+            .unwrap_or_else(|| ("text/html".to_string(), Bytes::from_static(BUILTIN_PAGE.as_bytes())));
+        Self {
+            status: config.status,
+            content_type,
+            body,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page_config(file: Option<&str>, inline: Option<&str>) -> DefaultPageConfig {
+        DefaultPageConfig {
+            file: file.map(str::to_string),
+            inline: inline.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_file_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.html");
+        std::fs::write(&path, "<h1>shop.example.com</h1>").unwrap();
+        let text = dir.path().join("status.txt");
+        std::fs::write(&text, "not configured").unwrap();
+
+        // Файл важнее inline
+        let page = DefaultPage::load(&page_config(path.to_str(), Some("<h1>inline</h1>")));
+        assert_eq!(page.status, 200);
+        assert_eq!(page.content_type, "text/html");
+        assert_eq!(page.body, "<h1>shop.example.com</h1>");
+
+        let page = DefaultPage::load(&DefaultPageConfig { status: 404, ..page_config(text.to_str(), None) });
+        assert_eq!(page.status, 404);
+        assert_eq!(page.content_type, "text/plain");
+        assert_eq!(page.body, "not configured");
+    }
+
+    #[test]
+    fn test_inline_and_builtin_fallback() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.html");
+        assert!(read_file(missing.to_str().unwrap()).unwrap_err().contains("missing.html"));
+
+        // Нечитаемый файл - inline, без inline - встроенная страница
+        let page = DefaultPage::load(&page_config(missing.to_str(), Some("<h1>inline</h1>")));
+        assert_eq!(page.body, "<h1>inline</h1>");
+        assert_eq!(page.content_type, "text/html");
+
+        let page = DefaultPage::load(&page_config(missing.to_str(), None));
+        assert_eq!(page.body, BUILTIN_PAGE);
+        let page = DefaultPage::load(&DefaultPageConfig::default());
+        assert_eq!(page.status, 200);
+        assert_eq!(page.body, BUILTIN_PAGE);
+        let builtin = String::from_utf8_lossy(&page.body).to_lowercase();
+        assert!(!builtin.contains("adquest") && !builtin.contains("cloudflare"));
+    }
+}
@@ -0,0 +1,76 @@
+use async_trait::async_trait;
+use log::trace;
+use pingora::prelude::*;
+use pingora_load_balancing::discovery::ServiceDiscovery;
+use pingora_load_balancing::Backend;
+use std::collections::{BTreeSet, HashMap};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use super::DnsResolver;
+use crate::config::UpstreamServer;
+
+/// Порт upstream сервера по умолчанию, если в адресе он не указан
+const DEFAULT_PORT: u16 = 80;
+
+/// Service discovery для upstream: имена серверов периодически переразрешаются
+/// через общий резолвер, каждый полученный адрес становится отдельным бэкендом
+pub struct DnsDiscovery {
+    upstream: String,
+    servers: Vec<UpstreamServer>,
+    resolver: Arc<DnsResolver>,
+}
+
+impl DnsDiscovery {
+    pub fn new(upstream: &str, servers: Vec<UpstreamServer>, resolver: Arc<DnsResolver>) -> Self {
+        Self {
+            upstream: upstream.to_string(),
+            servers,
+            resolver,
+        }
+    }
+
+    async fn resolve_server(&self, address: &str) -> Result<Vec<SocketAddr>> {
+        if let Ok(addr) = address.parse::<SocketAddr>() {
+            return Ok(vec![addr]);
+        }
+
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port.parse::<u16>().map_err(|_| {
+                    Error::explain(ErrorType::InternalError, format!("invalid port in upstream server '{}'", address))
+                })?;
+                (host, port)
+            }
+            None => (address, DEFAULT_PORT),
+        };
+
+        let ips = self
+            .resolver
+            .resolve(host)
+            .await
+            .map_err(|e| Error::explain(ErrorType::InternalError, e))?;
+        Ok(ips.into_iter().map(|ip| SocketAddr::new(ip, port)).collect())
+    }
+}
+
+#[async_trait]
+impl ServiceDiscovery for DnsDiscovery {
+    async fn discover(&self) -> Result<(BTreeSet<Backend>, HashMap<u64, bool>)> {
+        let mut backends = BTreeSet::new();
+
+        // При ошибке разрешения любого имени Pingora сохраняет предыдущий набор бэкендов
+        for server in &self.servers {
+            let addrs = self.resolve_server(&server.address).await?;
+            trace!("Upstream '{}' server {} resolved to {:?}", self.upstream, server.address, addrs);
+
+            for addr in addrs {
+                let mut backend = Backend::new(&addr.to_string())?;
+                backend.weight = server.weight as usize;
+                backends.insert(backend);
+            }
+        }
+
+        Ok((backends, HashMap::new()))
+    }
+}
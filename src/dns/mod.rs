@@ -0,0 +1,247 @@
+use hickory_resolver::config::{NameServerConfig, NameServerConfigGroup, Protocol, ResolverConfig, ResolverOpts};
+use hickory_resolver::system_conf::read_system_conf;
+use hickory_resolver::TokioAsyncResolver;
+use log::{debug, trace, warn};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use crate::config::ResolverDirective;
+use crate::metrics::{DNS_RESOLUTION_DURATION, DNS_RESOLUTION_FAILURES};
+
+pub mod discovery;
+pub use discovery::DnsDiscovery;
+
+/// Время кэширования ошибок разрешения, если valid= не задан
+const DEFAULT_NEGATIVE_TTL: Duration = Duration::from_secs(10);
+/// Период переразрешения upstream серверов, если valid= не задан
+const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+struct CacheEntry {
+    answer: Result<Vec<IpAddr>, String>,
+    expires: Instant,
+}
+
+/// Общий асинхронный DNS резолвер с positive/negative кэшем.
+/// Используется для разрешения имен upstream серверов во время работы прокси.
+pub struct DnsResolver {
+    resolver: TokioAsyncResolver,
+    /// Время кэширования ответов вместо TTL записей
+    valid: Option<Duration>,
+    timeout: Duration,
+    cache: RwLock<HashMap<String, CacheEntry>>,
+}
+
+impl DnsResolver {
+    /// Создает резолвер по директиве `resolver`, без нее используется системная конфигурация
+    pub fn new(directive: Option<&ResolverDirective>) -> Result<Self, String> {
+        let (config, mut opts) = match directive {
+            Some(directive) => {
+                let nameservers: Vec<NameServerConfig> = directive
+                    .nameservers
+                    .iter()
+                    .flat_map(|addr| {
+                        [
+                            NameServerConfig::new(*addr, Protocol::Udp),
+                            NameServerConfig::new(*addr, Protocol::Tcp),
+                        ]
+                    })
+                    .collect();
+                let config = ResolverConfig::from_parts(None, vec![], NameServerConfigGroup::from(nameservers));
+                (config, ResolverOpts::default())
+            }
+            None => read_system_conf().map_err(|e| format!("failed to read system DNS configuration: {}", e))?,
+        };
+
+        let timeout = directive.and_then(|d| d.timeout).unwrap_or(opts.timeout);
+        let valid = directive.and_then(|d| d.valid);
+        opts.timeout = timeout;
+        // Внутренний кэш hickory не должен переживать наш кэш
+        if let Some(valid) = valid {
+            opts.positive_max_ttl = Some(valid);
+            opts.negative_max_ttl = Some(valid);
+        }
+
+        Ok(Self {
+            resolver: TokioAsyncResolver::tokio(config, opts),
+            valid,
+            timeout,
+            cache: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// Период переразрешения имен upstream серверов
+    pub fn refresh_interval(&self) -> Duration {
+        self.valid.unwrap_or(DEFAULT_REFRESH_INTERVAL)
+    }
+
+    /// Разрешает имя в список адресов с учетом кэша
+    pub async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, String> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+
+        let now = Instant::now();
+        if let Some(entry) = self.cache.read().unwrap().get(host) {
+            if entry.expires > now {
+                trace!("DNS cache hit for {}: {:?}", host, entry.answer);
+                return entry.answer.clone();
+            }
+        }
+
+        let start = Instant::now();
+        let result = tokio::time::timeout(self.timeout, self.resolver.lookup_ip(host)).await;
+        DNS_RESOLUTION_DURATION.observe(start.elapsed().as_secs_f64());
+
+        let (answer, expires) = match result {
+            Ok(Ok(lookup)) => {
+                let addrs: Vec<IpAddr> = lookup.iter().collect();
+                let expires = self.valid.map(|valid| now + valid).unwrap_or_else(|| lookup.valid_until());
+                trace!("Resolved {} to {:?}", host, addrs);
+                (Ok(addrs), expires)
+            }
+            Ok(Err(e)) => {
+                DNS_RESOLUTION_FAILURES.with_label_values(&["error"]).inc();
+                warn!("Failed to resolve {}: {}", host, e);
+                (Err(format!("failed to resolve {}: {}", host, e)), now + self.negative_ttl())
+            }
+            Err(_) => {
+                DNS_RESOLUTION_FAILURES.with_label_values(&["timeout"]).inc();
+                warn!("Resolving {} timed out after {:?}", host, self.timeout);
+                (Err(format!("resolving {} timed out", host)), now + self.negative_ttl())
+            }
+        };
+
+        debug!("Caching DNS answer for {} for {:?}", host, expires.saturating_duration_since(now));
+        self.cache.write().unwrap().insert(
+            host.to_string(),
+            CacheEntry { answer: answer.clone(), expires },
+        );
+        answer
+    }
+
+    fn negative_ttl(&self) -> Duration {
+        self.valid.unwrap_or(DEFAULT_NEGATIVE_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UpstreamServer;
+    use pingora_load_balancing::discovery::ServiceDiscovery;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::net::UdpSocket;
+
+    /// Ответ на DNS запрос: вопрос копируется, на A запрос добавляется одна A запись
+    fn dns_response(query: &[u8], ip: Ipv4Addr) -> Vec<u8> {
+        let mut question_end = 12;
+        while query[question_end] != 0 {
+            question_end += query[question_end] as usize + 1;
+        }
+        question_end += 5;
+        let is_a_query = query[question_end - 4..question_end - 2] == [0, 1];
+
+        let mut response = query[0..2].to_vec();
+        response.extend_from_slice(&[0x81, 0x80, 0, 1, 0, is_a_query as u8, 0, 0, 0, 0]);
+        response.extend_from_slice(&query[12..question_end]);
+        if is_a_query {
+            // Указатель на имя из вопроса, A, IN, TTL 300, 4 байта адреса
+            response.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0x01, 0x2c, 0, 4]);
+            response.extend_from_slice(&ip.octets());
+        }
+        response
+    }
+
+    /// Mock DNS сервер; без адреса ответа молчит, имитируя недоступный DNS
+    async fn mock_dns(answer: Option<Ipv4Addr>) -> (SocketAddr, Arc<AtomicUsize>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = queries.clone();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                if let Some(ip) = answer {
+                    let _ = socket.send_to(&dns_response(&buf[..len], ip), peer).await;
+                }
+            }
+        });
+        (addr, queries)
+    }
+
+    fn directive(nameserver: SocketAddr, valid: Option<Duration>, timeout: Duration) -> ResolverDirective {
+        ResolverDirective {
+            nameservers: vec![nameserver],
+            valid,
+            timeout: Some(timeout),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_caches_answers_for_valid_period() {
+        let (addr, queries) = mock_dns(Some(Ipv4Addr::new(10, 1, 2, 3))).await;
+        let resolver = DnsResolver::new(Some(&directive(addr, Some(Duration::from_millis(300)), Duration::from_secs(2)))).unwrap();
+
+        let expected = vec![IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3))];
+        assert_eq!(resolver.resolve("backend.test").await.unwrap(), expected);
+        assert_eq!(resolver.resolve("backend.test").await.unwrap(), expected);
+        assert_eq!(queries.load(Ordering::SeqCst), 1);
+
+        // После истечения valid= имя разрешается заново
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(resolver.resolve("backend.test").await.unwrap(), expected);
+        assert_eq!(queries.load(Ordering::SeqCst), 2);
+
+        // IP адреса не требуют обращения к DNS
+        assert_eq!(resolver.resolve("10.0.0.1").await.unwrap(), vec!["10.0.0.1".parse::<IpAddr>().unwrap()]);
+        assert_eq!(queries.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_timeout_is_negatively_cached() {
+        let (addr, queries) = mock_dns(None).await;
+        let resolver = DnsResolver::new(Some(&directive(addr, None, Duration::from_millis(200)))).unwrap();
+        let timeouts_before = DNS_RESOLUTION_FAILURES.with_label_values(&["timeout"]).get();
+
+        let start = Instant::now();
+        assert!(resolver.resolve("slow.test").await.is_err());
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(DNS_RESOLUTION_FAILURES.with_label_values(&["timeout"]).get() > timeouts_before);
+
+        // Повторный запрос отдается из negative кэша без обращения к DNS
+        let sent = queries.load(Ordering::SeqCst);
+        assert!(sent >= 1);
+        let start = Instant::now();
+        assert!(resolver.resolve("slow.test").await.is_err());
+        assert!(start.elapsed() < Duration::from_millis(50));
+        assert_eq!(queries.load(Ordering::SeqCst), sent);
+    }
+
+    #[tokio::test]
+    async fn test_discovery_resolves_upstream_servers() {
+        let (addr, _queries) = mock_dns(Some(Ipv4Addr::new(10, 1, 2, 3))).await;
+        let resolver = Arc::new(DnsResolver::new(Some(&directive(addr, None, Duration::from_secs(2)))).unwrap());
+        let discovery = DnsDiscovery::new(
+            "backend",
+            vec![
+                UpstreamServer { address: "127.0.0.1:8080".to_string(), weight: 1 },
+                UpstreamServer { address: "api.test:9000".to_string(), weight: 3 },
+            ],
+            resolver,
+        );
+
+        let (backends, _) = discovery.discover().await.unwrap();
+        let mut addrs: Vec<(String, usize)> = backends.iter().map(|b| (b.addr.to_string(), b.weight)).collect();
+        addrs.sort();
+        assert_eq!(
+            addrs,
+            vec![("10.1.2.3:9000".to_string(), 3), ("127.0.0.1:8080".to_string(), 1)]
+        );
+    }
+}
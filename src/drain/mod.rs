@@ -0,0 +1,226 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use log::{info, warn};
+use crate::config::NginxConfig;
+use crate::metrics::{UPSTREAM_BACKENDS_DRAINING, UPSTREAM_BACKEND_IN_FLIGHT};
+
+/// Бэкенд, удаленный из upstream при reload и ожидающий завершения запросов
+#[derive(Debug, Clone)]
+struct DrainingBackend {
+    started: Instant,
+}
+
+#[derive(Debug, Default)]
+struct DrainState {
+    /// Количество запросов в обработке: (upstream, адрес) -> счетчик
+    in_flight: HashMap<(String, String), usize>,
+    /// Бэкенды в режиме draining: (upstream, адрес) -> состояние
+    draining: HashMap<(String, String), DrainingBackend>,
+}
+
+/// Отслеживает in-flight запросы к бэкендам и draining удаленных при reload бэкендов.
+/// Draining бэкенды исключаются из выбора, но остаются в учете до тех пор,
+/// пока их in-flight счетчик не станет нулевым или не истечет `drain_timeout`.
+pub struct DrainTracker {
+    drain_timeout: Duration,
+    state: RwLock<DrainState>,
+}
+
+impl DrainTracker {
+    pub fn new(drain_timeout: Duration) -> Self {
+        Self {
+            drain_timeout,
+            state: RwLock::new(DrainState::default()),
+        }
+    }
+
+    /// Наибольшее время draining бэкенда (global.drain_timeout)
+    pub fn drain_timeout(&self) -> Duration {
+        self.drain_timeout
+    }
+
+    /// Сравнивает наборы бэкендов до и после reload и переводит удаленные в draining.
+    /// Возвращает список удаленных адресов.
+    pub fn apply_reload(&self, upstream: &str, old: &[String], new: &[String]) -> Vec<String> {
+        let new_set: HashSet<&String> = new.iter().collect();
+        let removed: Vec<String> = old
+            .iter()
+            .filter(|addr| !new_set.contains(addr))
+            .cloned()
+            .collect();
+
+        let mut state = self.state.write().unwrap();
+
+        // Бэкенд вернулся в upstream - прекращаем draining
+        for addr in new {
+            state.draining.remove(&(upstream.to_string(), addr.clone()));
+        }
+
+        let now = Instant::now();
+        for addr in &removed {
+            let key = (upstream.to_string(), addr.clone());
+            let in_flight = state.in_flight.get(&key).copied().unwrap_or(0);
+            if in_flight == 0 {
+                info!("Backend {} removed from upstream '{}' with no in-flight requests", addr, upstream);
+                continue;
+            }
+            info!("Backend {} removed from upstream '{}', draining {} in-flight request(s)",
+                  addr, upstream, in_flight);
+            state.draining.entry(key).or_insert(DrainingBackend { started: now });
+        }
+
+        Self::sweep_locked(&mut state, self.drain_timeout, now);
+        Self::update_gauge(&state, upstream);
+        removed
+    }
+
+    /// Применяет reload для всех upstream из старой и новой nginx конфигурации
+    pub fn apply_config_reload(&self, old: &NginxConfig, new: &NginxConfig) {
+        for (name, old_upstream) in &old.upstreams {
+            let old_addrs: Vec<String> = old_upstream.servers.iter().map(|s| s.address.clone()).collect();
+            let new_addrs: Vec<String> = new
+                .upstreams
+                .get(name)
+                .map(|u| u.servers.iter().map(|s| s.address.clone()).collect())
+                .unwrap_or_default();
+            self.apply_reload(name, &old_addrs, &new_addrs);
+        }
+    }
+
+    /// Проверяет, находится ли бэкенд в режиме draining (не должен получать новые запросы)
+    pub fn is_draining(&self, upstream: &str, addr: &str) -> bool {
+        let state = self.state.read().unwrap();
+        state
+            .draining
+            .get(&(upstream.to_string(), addr.to_string()))
+            .is_some_and(|backend| backend.started.elapsed() < self.drain_timeout)
+    }
+
+    /// Регистрирует начало запроса к бэкенду
+    pub fn begin_request(&self, upstream: &str, addr: &str) {
+        let mut state = self.state.write().unwrap();
+        let count = state
+            .in_flight
+            .entry((upstream.to_string(), addr.to_string()))
+            .or_insert(0);
+        *count += 1;
+        UPSTREAM_BACKEND_IN_FLIGHT
+            .with_label_values(&[upstream, addr])
+            .set(*count as i64);
+    }
+
+    /// Количество запросов в обработке у бэкенда
+    pub fn in_flight(&self, upstream: &str, addr: &str) -> usize {
+        let state = self.state.read().unwrap();
+        state
+            .in_flight
+            .get(&(upstream.to_string(), addr.to_string()))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Регистрирует завершение запроса к бэкенду и завершает draining, если запросов не осталось
+    pub fn end_request(&self, upstream: &str, addr: &str) {
+        let mut state = self.state.write().unwrap();
+        let key = (upstream.to_string(), addr.to_string());
+
+        let remaining = match state.in_flight.get_mut(&key) {
+            Some(count) => {
+                *count = count.saturating_sub(1);
+                *count
+            }
+            None => 0,
+        };
+        UPSTREAM_BACKEND_IN_FLIGHT
+            .with_label_values(&[upstream, addr])
+            .set(remaining as i64);
+        if remaining == 0 {
+            state.in_flight.remove(&key);
+            if let Some(backend) = state.draining.remove(&key) {
+                info!("Backend {} of upstream '{}' drained in {:?}",
+                      addr, upstream, backend.started.elapsed());
+            }
+        }
+
+        Self::sweep_locked(&mut state, self.drain_timeout, Instant::now());
+        Self::update_gauge(&state, upstream);
+    }
+
+    /// Количество draining бэкендов в upstream
+    pub fn draining_count(&self, upstream: &str) -> usize {
+        let state = self.state.read().unwrap();
+        state.draining.keys().filter(|(name, _)| name == upstream).count()
+    }
+
+    /// Удаляет draining бэкенды, у которых истек drain_timeout
+    fn sweep_locked(state: &mut DrainState, drain_timeout: Duration, now: Instant) {
+        let expired: Vec<(String, String)> = state
+            .draining
+            .iter()
+            .filter(|(_, backend)| now.duration_since(backend.started) >= drain_timeout)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in expired {
+            let in_flight = state.in_flight.remove(&key).unwrap_or(0);
+            state.draining.remove(&key);
+            UPSTREAM_BACKEND_IN_FLIGHT
+                .with_label_values(&[&key.0, &key.1])
+                .set(0);
+            warn!("Backend {} of upstream '{}' drain timeout exceeded with {} in-flight request(s), dropping",
+                  key.1, key.0, in_flight);
+        }
+    }
+
+    fn update_gauge(state: &DrainState, upstream: &str) {
+        let count = state.draining.keys().filter(|(name, _)| name == upstream).count();
+        UPSTREAM_BACKENDS_DRAINING
+            .with_label_values(&[upstream])
+            .set(count as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_reload_drains_backend_with_in_flight_request() {
+        let tracker = DrainTracker::new(Duration::from_secs(30));
+        let old = addrs(&["127.0.0.1:8080", "127.0.0.1:8081"]);
+        let new = addrs(&["127.0.0.1:8080"]);
+
+        // Медленный запрос к бэкенду, который будет удален
+        tracker.begin_request("core_api", "127.0.0.1:8081");
+
+        let removed = tracker.apply_reload("core_api", &old, &new);
+        assert_eq!(removed, addrs(&["127.0.0.1:8081"]));
+        assert!(tracker.is_draining("core_api", "127.0.0.1:8081"));
+        assert!(!tracker.is_draining("core_api", "127.0.0.1:8080"));
+        assert_eq!(tracker.draining_count("core_api"), 1);
+
+        // Запрос завершился - draining закончен
+        tracker.end_request("core_api", "127.0.0.1:8081");
+        assert!(!tracker.is_draining("core_api", "127.0.0.1:8081"));
+        assert_eq!(tracker.draining_count("core_api"), 0);
+    }
+
+    #[test]
+    fn test_reload_drops_idle_backend_and_honours_timeout() {
+        let tracker = DrainTracker::new(Duration::from_millis(0));
+        let old = addrs(&["127.0.0.1:8080", "127.0.0.1:8081"]);
+        let new = addrs(&["127.0.0.1:8080"]);
+
+        tracker.begin_request("core_api", "127.0.0.1:8081");
+        tracker.apply_reload("core_api", &old, &new);
+
+        // drain_timeout истек сразу - бэкенд удален несмотря на in-flight запрос
+        assert!(!tracker.is_draining("core_api", "127.0.0.1:8081"));
+        assert_eq!(tracker.draining_count("core_api"), 0);
+    }
+}
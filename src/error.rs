@@ -0,0 +1,136 @@
+use std::path::Path;
+use thiserror::Error;
+
+use crate::config::{SchemaError, SourcePos};
+
+/// Ошибки загрузки конфигурации, nginx-конфигов, списков IP, TLS и запуска сервера
+#[derive(Debug, Error)]
+pub enum ProxyError {
+    /// Файл не читается или не записывается
+    #[error("{path}: {source}")]
+    Io {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    /// proxy.yaml не разбирается или не соответствует структуре конфигурации
+    #[error("invalid YAML: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    /// Версия схемы новее бинарника, некорректна или неизвестные поля при strict: true
+    #[error(transparent)]
+    Schema(#[from] SchemaError),
+    /// Некорректная директива nginx-конфига
+    #[error("{0}")]
+    ConfigParse(String),
+    /// Некорректный upstream блок
+    #[error("upstream '{name}': {message}")]
+    InvalidUpstream { name: String, message: String },
+    /// Ошибка nginx-конфига с местом в файле
+    #[error("{source} at {pos}")]
+    Located {
+        pos: SourcePos,
+        #[source]
+        source: Box<ProxyError>,
+    },
+    /// Сертификат или ключ TLS не загружается
+    #[error("TLS: {0}")]
+    Tls(String),
+    /// Адреса listen заняты другими процессами
+    #[error("{0} listen address(es) unavailable, not starting")]
+    PortConflict(usize),
+}
+
+pub type Result<T, E = ProxyError> = std::result::Result<T, E>;
+
+impl ProxyError {
+    /// Ошибка ввода-вывода с путем файла
+    pub fn io(path: impl AsRef<Path>) -> impl FnOnce(std::io::Error) -> Self {
+        let path = path.as_ref().display().to_string();
+        move |source| ProxyError::Io { path, source }
+    }
+
+    /// Код завершения процесса при ошибке запуска
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ProxyError::PortConflict(_) => crate::preflight::EXIT_PORT_CONFLICT,
+            _ => 1,
+        }
+    }
+}
+
+impl From<String> for ProxyError {
+    fn from(message: String) -> Self {
+        ProxyError::ConfigParse(message)
+    }
+}
+
+impl From<&str> for ProxyError {
+    fn from(message: &str) -> Self {
+        ProxyError::ConfigParse(message.to_string())
+    }
+}
+
+impl From<std::num::ParseIntError> for ProxyError {
+    fn from(e: std::num::ParseIntError) -> Self {
+        ProxyError::ConfigParse(format!("invalid number: {}", e))
+    }
+}
+
+impl From<regex::Error> for ProxyError {
+    fn from(e: regex::Error) -> Self {
+        ProxyError::ConfigParse(format!("invalid pattern: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, NginxConfig};
+    use crate::filter::IPFilter;
+
+    #[test]
+    fn test_config_errors() {
+        let e = Config::load_from_file("/nonexistent/adq-pingora/proxy.yaml").unwrap_err();
+        assert!(matches!(&e, ProxyError::Io { path, source }
+            if path == "/nonexistent/adq-pingora/proxy.yaml" && source.kind() == std::io::ErrorKind::NotFound));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy.yaml");
+        std::fs::write(&path, "version: [1\n").unwrap();
+        assert!(matches!(Config::load_from_file(&path), Err(ProxyError::Yaml(_))));
+
+        std::fs::write(&path, "version: 999\n").unwrap();
+        assert!(matches!(Config::load_from_file(&path), Err(ProxyError::Schema(SchemaError::TooNew { version: 999 }))));
+    }
+
+    #[test]
+    fn test_nginx_errors() {
+        let e = NginxConfig::parse_config_content("map $host $x { default a; }\nmap $uri $x { default b; }").unwrap_err();
+        assert!(matches!(&e, ProxyError::Located { pos, source }
+            if pos.line == 2 && matches!(source.as_ref(), ProxyError::ConfigParse(message) if message == "duplicate map for $x")));
+        assert_eq!(e.to_string(), "duplicate map for $x at line 2, column 1");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("site.conf");
+        std::fs::write(&path, "# DNS\nresolver valid=30s;\n").unwrap();
+        let e = NginxConfig::parse_config_file(&path).unwrap_err();
+        assert!(e.to_string().ends_with(&format!(" at {}:2:10", path.display())), "{}", e);
+        assert!(matches!(NginxConfig::parse_config_file("/nonexistent/site.conf"), Err(ProxyError::Io { .. })));
+
+        let e = NginxConfig::parse_upstream_block("billing", "server 10.0.0.1:8080;\nqueue 10;").unwrap_err();
+        assert!(matches!(&e, ProxyError::InvalidUpstream { name, .. } if name == "billing"), "{}", e);
+        assert_eq!(e.to_string(), "upstream 'billing': queue requires max_conns");
+        assert!(matches!(
+            NginxConfig::parse_upstream_block("billing", "server 10.0.0.1:8080 weight=0;"),
+            Err(ProxyError::InvalidUpstream { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_blacklist_file_errors() {
+        let filter = IPFilter::new();
+        let e = filter.load_blacklist_from_file("/nonexistent/blacklist.txt").await.unwrap_err();
+        assert!(matches!(e, ProxyError::Io { .. }));
+        assert_eq!(e.to_string().split(':').next(), Some("/nonexistent/blacklist.txt"));
+    }
+}
@@ -0,0 +1,154 @@
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use std::fs;
+
+use crate::config::ErrorMessagesConfig;
+use crate::error_response::ErrorCode;
+
+/// Переводы сообщений JSON ошибок: язык -> код ошибки -> шаблон.
+/// В шаблоне подставляются `{status}`, `{retry_after}` и `{upstream}`
+#[derive(Debug, Clone, Default)]
+pub struct ErrorMessages {
+    default_language: String,
+    languages: HashMap<String, HashMap<String, String>>,
+}
+
+static GLOBAL: OnceCell<ErrorMessages> = OnceCell::new();
+
+/// Устанавливает переводы для ErrorResponse
+pub fn install(messages: ErrorMessages) {
+    let _ = GLOBAL.set(messages);
+}
+
+/// Установленные переводы (None - сообщения по умолчанию на английском)
+pub fn global() -> Option<&'static ErrorMessages> {
+    GLOBAL.get()
+}
+
+/// Языковые диапазоны Accept-Language в нижнем регистре по убыванию q (q=0 исключаются)
+fn language_ranges(accept_language: &str) -> Vec<String> {
+    let mut ranges: Vec<(String, f32)> = accept_language
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let range = parts.next()?.trim().to_ascii_lowercase();
+            let quality = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (!range.is_empty() && quality > 0.0).then_some((range, quality))
+        })
+        .collect();
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranges.into_iter().map(|(range, _)| range).collect()
+}
+
+impl ErrorMessages {
+    /// Загружает `<templates_dir>/<язык>.yaml`; ключи - коды ошибок (`RATE_LIMITED: "..."`)
+    pub fn from_config(config: &ErrorMessagesConfig) -> Result<Self, String> {
+        let mut languages = HashMap::new();
+        if let Some(dir) = &config.templates_dir {
+            let entries = fs::read_dir(dir)
+                .map_err(|e| format!("failed to read error_messages.templates_dir {}: {}", dir, e))?;
+            for entry in entries {
+                let path = entry.map_err(|e| format!("failed to read {}: {}", dir, e))?.path();
+                let is_yaml = path.extension().is_some_and(|ext| ext == "yaml" || ext == "yml");
+                let Some(language) = path.file_stem().and_then(|stem| stem.to_str()).filter(|_| is_yaml) else {
+                    continue;
+                };
+                let content = fs::read_to_string(&path)
+                    .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+                let templates: HashMap<String, String> = serde_yaml::from_str(&content)
+                    .map_err(|e| format!("invalid error message templates {}: {}", path.display(), e))?;
+                if let Some(code) = templates.keys().find(|code| !ErrorCode::ALL.iter().any(|known| known.as_str() == *code)) {
+                    return Err(format!("unknown error code '{}' in {}", code, path.display()));
+                }
+                languages.insert(language.to_ascii_lowercase(), templates);
+            }
+        }
+        Ok(Self {
+            default_language: config.default_language.to_ascii_lowercase(),
+            languages,
+        })
+    }
+
+    /// Загруженные языки
+    pub fn languages(&self) -> Vec<&str> {
+        let mut languages: Vec<&str> = self.languages.keys().map(String::as_str).collect();
+        languages.sort_unstable();
+        languages
+    }
+
+    /// Языки шаблонов в порядке предпочтения клиента: точное совпадение (`pt-br`),
+    /// затем основной подтег (`pt`); в конце язык по умолчанию
+    fn candidates(&self, accept_language: Option<&str>) -> Vec<&str> {
+        let mut candidates = Vec::new();
+        for range in accept_language.map(language_ranges).unwrap_or_default() {
+            let primary = range.split('-').next().unwrap_or_default().to_string();
+            for tag in [range, primary] {
+                if let Some((language, _)) = self.languages.get_key_value(&tag) {
+                    if !candidates.contains(&language.as_str()) {
+                        candidates.push(language.as_str());
+                    }
+                }
+            }
+        }
+        if let Some((language, _)) = self.languages.get_key_value(&self.default_language) {
+            candidates.push(language.as_str());
+        }
+        candidates
+    }
+
+    /// Язык и шаблон сообщения для кода ошибки
+    pub fn template(&self, code: ErrorCode, accept_language: Option<&str>) -> Option<(&str, &str)> {
+        self.candidates(accept_language).into_iter().find_map(|language| {
+            let template = self.languages.get(language)?.get(code.as_str())?;
+            Some((language, template.as_str()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(files: &[(&str, &str)]) -> (tempfile::TempDir, Result<ErrorMessages, String>) {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            fs::write(dir.path().join(name), content).unwrap();
+        }
+        let config = ErrorMessagesConfig {
+            templates_dir: Some(dir.path().to_string_lossy().to_string()),
+            default_language: "en".to_string(),
+        };
+        let messages = ErrorMessages::from_config(&config);
+        (dir, messages)
+    }
+
+    #[test]
+    fn test_template_by_accept_language() {
+        let (_dir, messages) = messages(&[
+            ("ru.yaml", "RATE_LIMITED: \"Слишком много запросов, повторите через {retry_after} с\"\nNOT_FOUND: \"Не найдено\"\n"),
+            ("en.yaml", "RATE_LIMITED: \"Too many requests, retry in {retry_after}s\"\nIP_BLOCKED: \"Access denied\"\n"),
+            ("README.txt", "not a template"),
+        ]);
+        let messages = messages.unwrap();
+        assert_eq!(messages.languages(), vec!["en", "ru"]);
+
+        let ru = messages.template(ErrorCode::RateLimited, Some("ru-RU,ru;q=0.9,en;q=0.8")).unwrap();
+        assert_eq!(ru, ("ru", "Слишком много запросов, повторите через {retry_after} с"));
+        // q-values важнее порядка
+        assert_eq!(messages.template(ErrorCode::NotFound, Some("en;q=0.5, ru")).unwrap().0, "ru");
+        // Нет перевода кода на выбранном языке - язык по умолчанию
+        assert_eq!(messages.template(ErrorCode::IpBlocked, Some("ru")).unwrap().0, "en");
+        assert_eq!(messages.template(ErrorCode::RateLimited, Some("de, ru;q=0")).unwrap().0, "en");
+        assert_eq!(messages.template(ErrorCode::RateLimited, None).unwrap().0, "en");
+        assert!(messages.template(ErrorCode::UpstreamTimeout, Some("ru")).is_none());
+    }
+
+    #[test]
+    fn test_unknown_error_code_rejected() {
+        let (_dir, messages) = messages(&[("ru.yaml", "RATE_LIMIT: \"Слишком много запросов\"\n")]);
+        assert!(messages.unwrap_err().contains("unknown error code 'RATE_LIMIT'"));
+    }
+}
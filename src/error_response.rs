@@ -0,0 +1,403 @@
+use bytes::Bytes;
+use pingora::prelude::*;
+use pingora::http::ResponseHeader;
+use serde_json::json;
+
+use crate::error_messages::{self, ErrorMessages};
+use crate::local_response::ResponseBuilder;
+use crate::types::RequestContext;
+
+/// Стабильные машиночитаемые коды ошибок, которые генерирует прокси
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    IpBlocked,
+    UserAgentBlocked,
+    NotFound,
+    PayloadTooLarge,
+    IdempotencyConflict,
+    RateLimited,
+    InternalError,
+    UpstreamUnavailable,
+    CircuitOpen,
+    UpstreamBusy,
+    ServiceUnavailable,
+    UpstreamTimeout,
+    UpgradeRequired,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 16] = [
+        ErrorCode::BadRequest,
+        ErrorCode::Unauthorized,
+        ErrorCode::Forbidden,
+        ErrorCode::IpBlocked,
+        ErrorCode::UserAgentBlocked,
+        ErrorCode::NotFound,
+        ErrorCode::PayloadTooLarge,
+        ErrorCode::IdempotencyConflict,
+        ErrorCode::RateLimited,
+        ErrorCode::InternalError,
+        ErrorCode::UpstreamUnavailable,
+        ErrorCode::CircuitOpen,
+        ErrorCode::UpstreamBusy,
+        ErrorCode::ServiceUnavailable,
+        ErrorCode::UpstreamTimeout,
+        ErrorCode::UpgradeRequired,
+    ];
+
+    /// Код ошибки для JSON ответа
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "BAD_REQUEST",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::Forbidden => "FORBIDDEN",
+            ErrorCode::IpBlocked => "IP_BLOCKED",
+            ErrorCode::UserAgentBlocked => "USER_AGENT_BLOCKED",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::PayloadTooLarge => "PAYLOAD_TOO_LARGE",
+            ErrorCode::IdempotencyConflict => "IDEMPOTENCY_CONFLICT",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::InternalError => "INTERNAL_ERROR",
+            ErrorCode::UpstreamUnavailable => "UPSTREAM_UNAVAILABLE",
+            ErrorCode::CircuitOpen => "UPSTREAM_CIRCUIT_OPEN",
+            ErrorCode::UpstreamBusy => "UPSTREAM_BUSY",
+            ErrorCode::ServiceUnavailable => "SERVICE_UNAVAILABLE",
+            ErrorCode::UpstreamTimeout => "UPSTREAM_TIMEOUT",
+            ErrorCode::UpgradeRequired => "UPGRADE_REQUIRED",
+        }
+    }
+
+    /// HTTP статус для кода ошибки
+    pub fn status(&self) -> u16 {
+        match self {
+            ErrorCode::BadRequest => 400,
+            ErrorCode::Unauthorized => 401,
+            ErrorCode::Forbidden | ErrorCode::IpBlocked | ErrorCode::UserAgentBlocked => 403,
+            ErrorCode::NotFound => 404,
+            ErrorCode::PayloadTooLarge => 413,
+            ErrorCode::UpgradeRequired => 426,
+            ErrorCode::IdempotencyConflict => 409,
+            ErrorCode::RateLimited => 429,
+            ErrorCode::InternalError => 500,
+            ErrorCode::UpstreamUnavailable => 502,
+            ErrorCode::CircuitOpen | ErrorCode::UpstreamBusy | ErrorCode::ServiceUnavailable => 503,
+            ErrorCode::UpstreamTimeout => 504,
+        }
+    }
+
+    /// Сообщение по умолчанию
+    pub fn default_message(&self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "Bad request",
+            ErrorCode::Unauthorized => "Authentication required",
+            ErrorCode::Forbidden | ErrorCode::IpBlocked | ErrorCode::UserAgentBlocked => "Access denied",
+            ErrorCode::NotFound => "Not found",
+            ErrorCode::PayloadTooLarge => "Request body too large",
+            ErrorCode::IdempotencyConflict => "A request with this idempotency key is in progress",
+            ErrorCode::RateLimited => "Rate limit exceeded",
+            ErrorCode::InternalError => "Internal proxy error",
+            ErrorCode::UpstreamUnavailable => "Upstream is unavailable",
+            ErrorCode::CircuitOpen => "Upstream is temporarily unavailable",
+            ErrorCode::UpstreamBusy => "Upstream is at its concurrency limit",
+            ErrorCode::ServiceUnavailable => "Service unavailable",
+            ErrorCode::UpstreamTimeout => "Upstream did not respond in time",
+            ErrorCode::UpgradeRequired => "HTTP/1.1 or newer is required",
+        }
+    }
+
+    /// Подбирает код ошибки для HTTP статуса (для ошибок Pingora и ответов upstream).
+    /// Причину 403 статус не сообщает: IP_BLOCKED задает только стадия ip_filter
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            401 => ErrorCode::Unauthorized,
+            403 => ErrorCode::Forbidden,
+            404 => ErrorCode::NotFound,
+            413 => ErrorCode::PayloadTooLarge,
+            429 => ErrorCode::RateLimited,
+            502 => ErrorCode::UpstreamUnavailable,
+            503 => ErrorCode::ServiceUnavailable,
+            504 => ErrorCode::UpstreamTimeout,
+            400..=499 => ErrorCode::BadRequest,
+            _ => ErrorCode::InternalError,
+        }
+    }
+}
+
+/// Построитель JSON ответов об ошибках в едином формате:
+/// `{"error": {"code": "...", "status": 429, "message": "...", "request_id": "...", "service": "...", "retry_after": 1}}`
+#[derive(Debug, Clone)]
+pub struct ErrorResponse {
+    code: ErrorCode,
+    message: String,
+    retry_after: Option<u64>,
+    upstream: Option<String>,
+    headers: Vec<(&'static str, String)>,
+}
+
+impl ErrorResponse {
+    pub fn new(code: ErrorCode) -> Self {
+        Self {
+            code,
+            message: code.default_message().to_string(),
+            retry_after: None,
+            upstream: None,
+            headers: Vec::new(),
+        }
+    }
+
+    /// Переопределяет сообщение об ошибке
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.message = message.into();
+        self
+    }
+
+    /// Добавляет Retry-After (секунды) в заголовки и тело ответа
+    pub fn retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+
+    /// Указывает upstream, вызвавший ошибку
+    pub fn upstream(mut self, upstream: impl Into<String>) -> Self {
+        self.upstream = Some(upstream.into());
+        self
+    }
+
+    /// Добавляет дополнительный заголовок ответа
+    pub fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    /// Сообщение на языке клиента из шаблонов error_messages и язык шаблона;
+    /// без подходящего шаблона - message на английском
+    pub fn localized_message(&self, messages: Option<&ErrorMessages>, accept_language: Option<&str>) -> (String, Option<String>) {
+        let Some((language, template)) = messages.and_then(|messages| messages.template(self.code, accept_language)) else {
+            return (self.message.clone(), None);
+        };
+        let message = template
+            .replace("{status}", &self.code.status().to_string())
+            .replace("{retry_after}", &self.retry_after.map(|seconds| seconds.to_string()).unwrap_or_default())
+            .replace("{upstream}", self.upstream.as_deref().unwrap_or_default());
+        (message, Some(language.to_string()))
+    }
+
+    /// Формирует JSON тело ответа
+    pub fn body(&self, ctx: &RequestContext) -> String {
+        self.body_with_message(ctx, &self.message)
+    }
+
+    fn body_with_message(&self, ctx: &RequestContext, message: &str) -> String {
+        let mut error = json!({
+            "code": self.code.as_str(),
+            "status": self.code.status(),
+            "message": message,
+            "request_id": ctx.request_id,
+            "service": ctx.service_type.as_str(),
+        });
+        if let Some(retry_after) = self.retry_after {
+            error["retry_after"] = json!(retry_after);
+        }
+        if let Some(upstream) = &self.upstream {
+            error["upstream"] = json!(upstream);
+        }
+
+        json!({ "error": error }).to_string()
+    }
+
+    /// Заголовки ответа: JSON с charset, security и CORS заголовки,
+    /// Content-Language для переведенного сообщения
+    fn response_builder(&self, language: Option<&str>) -> ResponseBuilder {
+        let mut builder = ResponseBuilder::json(self.code.status()).cors();
+        if let Some(retry_after) = self.retry_after {
+            builder = builder.header("Retry-After", retry_after.to_string());
+        }
+        if let Some(language) = language {
+            builder = builder.header("Content-Language", language);
+        }
+        for (name, value) in &self.headers {
+            builder = builder.header(*name, value.as_str());
+        }
+        builder
+    }
+
+    /// Формирует тело и заголовки ответа на языке из Accept-Language запроса
+    pub fn build(&self, session: &Session, ctx: &RequestContext, messages: Option<&ErrorMessages>) -> Result<(ResponseHeader, String)> {
+        let accept_language = session.req_header().headers.get("accept-language").and_then(|v| v.to_str().ok());
+        let (message, language) = self.localized_message(messages, accept_language);
+        let body = self.body_with_message(ctx, &message);
+        let response = self.response_builder(language.as_deref()).build(session, body.len())?;
+        Ok((response, body))
+    }
+
+    /// Отправляет ответ клиенту
+    pub async fn send(&self, session: &mut Session, ctx: &RequestContext) -> Result<()> {
+        let (response, body) = self.build(session, ctx, error_messages::global())?;
+
+        session.set_keepalive(None);
+        session.write_response_header(Box::new(response), false).await?;
+        session.write_response_body(Some(Bytes::from(body)), true).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ErrorMessagesConfig;
+    use crate::stages::test_session;
+    use crate::types::ServiceType;
+    use serde_json::Value;
+
+    fn test_ctx() -> RequestContext {
+        let mut ctx = RequestContext::new();
+        ctx.request_id = "test-request-id".to_string();
+        ctx.service_type = ServiceType::CoreApi;
+        ctx
+    }
+
+    fn parse(body: &str) -> Value {
+        serde_json::from_str(body).unwrap()
+    }
+
+    #[test]
+    fn test_error_bodies() {
+        let ctx = test_ctx();
+
+        assert_eq!(
+            parse(&ErrorResponse::new(ErrorCode::IpBlocked).body(&ctx)),
+            json!({"error": {"code": "IP_BLOCKED", "status": 403, "message": "Access denied",
+                             "request_id": "test-request-id", "service": "core_api"}})
+        );
+        assert_eq!(
+            parse(&ErrorResponse::new(ErrorCode::RateLimited).retry_after(1).body(&ctx)),
+            json!({"error": {"code": "RATE_LIMITED", "status": 429, "message": "Rate limit exceeded",
+                             "request_id": "test-request-id", "service": "core_api", "retry_after": 1}})
+        );
+        assert_eq!(
+            parse(&ErrorResponse::new(ErrorCode::CircuitOpen).retry_after(30).body(&ctx)),
+            json!({"error": {"code": "UPSTREAM_CIRCUIT_OPEN", "status": 503, "message": "Upstream is temporarily unavailable",
+                             "request_id": "test-request-id", "service": "core_api", "retry_after": 30}})
+        );
+        assert_eq!(
+            parse(&ErrorResponse::new(ErrorCode::PayloadTooLarge).body(&ctx)),
+            json!({"error": {"code": "PAYLOAD_TOO_LARGE", "status": 413, "message": "Request body too large",
+                             "request_id": "test-request-id", "service": "core_api"}})
+        );
+        assert_eq!(
+            parse(&ErrorResponse::new(ErrorCode::Unauthorized).body(&ctx)),
+            json!({"error": {"code": "UNAUTHORIZED", "status": 401, "message": "Authentication required",
+                             "request_id": "test-request-id", "service": "core_api"}})
+        );
+        assert_eq!(
+            parse(&ErrorResponse::new(ErrorCode::UpstreamUnavailable).body(&ctx)),
+            json!({"error": {"code": "UPSTREAM_UNAVAILABLE", "status": 502, "message": "Upstream is unavailable",
+                             "request_id": "test-request-id", "service": "core_api"}})
+        );
+        assert_eq!(
+            parse(&ErrorResponse::new(ErrorCode::UpstreamTimeout).upstream("reports").body(&ctx)),
+            json!({"error": {"code": "UPSTREAM_TIMEOUT", "status": 504, "message": "Upstream did not respond in time",
+                             "request_id": "test-request-id", "service": "core_api", "upstream": "reports"}})
+        );
+    }
+
+    /// Имена заголовков ответа по алфавиту (значения Date меняются)
+    fn header_names(response: &ResponseHeader) -> Vec<String> {
+        let mut names: Vec<String> = response.headers.keys().map(|name| name.to_string()).collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn test_error_response_headers() {
+        let ctx = test_ctx();
+        let session = test_session("GET /api/users HTTP/1.1\r\nHost: api.ad-quest.ru\r\nOrigin: https://api.ad-quest.ru\r\n\r\n").await;
+        let common = [
+            "access-control-allow-credentials",
+            "access-control-allow-headers",
+            "access-control-allow-methods",
+            "access-control-allow-origin",
+            "access-control-expose-headers",
+            "cache-control",
+            "content-length",
+            "content-security-policy",
+            "content-type",
+            "date",
+            "referrer-policy",
+            "server",
+            "vary",
+            "x-content-type-options",
+            "x-frame-options",
+            "x-xss-protection",
+        ];
+
+        for (error, status, retry_after) in [
+            (ErrorResponse::new(ErrorCode::IpBlocked), 403, None),
+            (ErrorResponse::new(ErrorCode::NotFound).message("File not found"), 404, None),
+            (ErrorResponse::new(ErrorCode::PayloadTooLarge), 413, None),
+            (ErrorResponse::new(ErrorCode::RateLimited).retry_after(1), 429, Some("1")),
+            (ErrorResponse::new(ErrorCode::UpstreamBusy).retry_after(2), 503, Some("2")),
+        ] {
+            let (response, body) = error.build(&session, &ctx, None).unwrap();
+            let mut expected: Vec<String> = common.iter().map(|name| name.to_string()).collect();
+            if retry_after.is_some() {
+                expected.push("retry-after".to_string());
+                expected.sort();
+            }
+            assert_eq!(header_names(&response), expected, "{}", status);
+            assert_eq!(response.status.as_u16(), status);
+            assert_eq!(response.headers.get("content-type").unwrap(), "application/json; charset=utf-8");
+            assert_eq!(response.headers.get("content-length").unwrap(), body.len().to_string().as_str());
+            assert_eq!(response.headers.get("cache-control").unwrap(), "no-store");
+            assert_eq!(response.headers.get("access-control-allow-origin").unwrap(), "https://api.ad-quest.ru");
+            assert_eq!(response.headers.get("retry-after").map(|v| v.to_str().unwrap()), retry_after);
+            assert_eq!(parse(&body)["error"]["status"], status);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_localized_error_message() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("ru.yaml"),
+            "RATE_LIMITED: \"Слишком много запросов, повторите через {retry_after} с\"\n",
+        )
+        .unwrap();
+        let messages = ErrorMessages::from_config(&ErrorMessagesConfig {
+            templates_dir: Some(dir.path().to_string_lossy().to_string()),
+            default_language: "en".to_string(),
+        })
+        .unwrap();
+        let ctx = test_ctx();
+        let error = ErrorResponse::new(ErrorCode::RateLimited).retry_after(3);
+
+        let session = test_session("GET / HTTP/1.1\r\nHost: api.ad-quest.ru\r\nAccept-Language: ru-RU,ru;q=0.9,en;q=0.8\r\n\r\n").await;
+        let (response, body) = error.build(&session, &ctx, Some(&messages)).unwrap();
+        assert_eq!(parse(&body)["error"]["message"], "Слишком много запросов, повторите через 3 с");
+        assert_eq!(response.headers.get("content-language").unwrap(), "ru");
+        assert_eq!(response.headers.get("content-length").unwrap(), body.len().to_string().as_str());
+
+        // Нет шаблона для языка клиента - сообщение по умолчанию
+        let session = test_session("GET / HTTP/1.1\r\nHost: api.ad-quest.ru\r\nAccept-Language: de\r\n\r\n").await;
+        let (response, body) = error.build(&session, &ctx, Some(&messages)).unwrap();
+        assert_eq!(parse(&body)["error"]["message"], "Rate limit exceeded");
+        assert!(response.headers.get("content-language").is_none());
+    }
+
+    #[test]
+    fn test_error_code_from_status() {
+        assert_eq!(ErrorCode::from_status(502), ErrorCode::UpstreamUnavailable);
+        assert_eq!(ErrorCode::from_status(504), ErrorCode::UpstreamTimeout);
+        assert_eq!(ErrorCode::from_status(400), ErrorCode::BadRequest);
+        assert_eq!(ErrorCode::from_status(418), ErrorCode::BadRequest);
+        assert_eq!(ErrorCode::from_status(500), ErrorCode::InternalError);
+        assert_eq!(ErrorCode::from_status(403), ErrorCode::Forbidden);
+    }
+}
@@ -0,0 +1,258 @@
+use pingora::http::RequestHeader;
+use std::net::IpAddr;
+
+use crate::config::ExperimentConfig;
+
+/// Ключ, по которому клиент попадает в группу эксперимента
+#[derive(Debug, Clone, PartialEq)]
+enum ExperimentKey {
+    ClientIp,
+    /// Значение cookie; без cookie - IP клиента
+    Cookie(String),
+}
+
+/// Эксперимент с группами и накопленными весами
+#[derive(Debug)]
+struct Experiment {
+    name: String,
+    header: String,
+    key: ExperimentKey,
+    /// Группы с верхней границей накопленного веса
+    buckets: Vec<(String, u64)>,
+    cookie: Option<String>,
+    cookie_max_age: u64,
+}
+
+/// Группа, назначенная запросу
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    /// Заголовок запроса к upstream (X-Experiment-<имя>)
+    pub header: String,
+    pub bucket: String,
+    /// Cookie для закрепления группы: имя и Max-Age (None - cookie уже есть или не настроена)
+    cookie: Option<(String, u64)>,
+}
+
+impl Assignment {
+    /// Значение Set-Cookie для новой группы клиента
+    pub fn set_cookie(&self, secure: bool) -> Option<String> {
+        let (name, max_age) = self.cookie.as_ref()?;
+        let mut cookie = format!("{}={}; Path=/; Max-Age={}; SameSite=Lax", name, self.bucket, max_age);
+        if secure {
+            cookie.push_str("; Secure");
+        }
+        Some(cookie)
+    }
+}
+
+/// Значение cookie запроса по имени
+pub fn request_cookie<'a>(req: &'a RequestHeader, name: &str) -> Option<&'a str> {
+    req.headers
+        .get_all("cookie")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| {
+            let (cookie, value) = pair.trim().split_once('=')?;
+            (cookie == name).then_some(value.trim())
+        })
+}
+
+/// FNV-1a: стабилен между версиями и перезапусками, в отличие от DefaultHasher
+fn stable_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn valid_token(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Эксперименты секции experiments
+#[derive(Debug)]
+pub struct Experiments {
+    experiments: Vec<Experiment>,
+}
+
+impl Experiments {
+    /// Проверяет секцию experiments; None - экспериментов нет
+    pub fn from_config(configs: &[ExperimentConfig]) -> Result<Option<Self>, String> {
+        let mut experiments: Vec<Experiment> = Vec::new();
+        for config in configs {
+            if !valid_token(&config.name) {
+                return Err(format!("invalid experiment name '{}': letters, digits, '-' and '_' only", config.name));
+            }
+            if experiments.iter().any(|e| e.name.eq_ignore_ascii_case(&config.name)) {
+                return Err(format!("duplicate experiment '{}'", config.name));
+            }
+            let key = match config.key.as_str() {
+                "ip" => ExperimentKey::ClientIp,
+                key => match key.strip_prefix("cookie:").filter(|name| valid_token(name)) {
+                    Some(name) => ExperimentKey::Cookie(name.to_string()),
+                    None => return Err(format!("invalid key '{}' in experiment '{}': expected ip or cookie:<name>", key, config.name)),
+                },
+            };
+            if let Some(cookie) = config.cookie.as_deref().filter(|cookie| !valid_token(cookie)) {
+                return Err(format!("invalid cookie name '{}' in experiment '{}'", cookie, config.name));
+            }
+
+            let mut buckets = Vec::new();
+            let mut total = 0u64;
+            for bucket in &config.buckets {
+                if !valid_token(&bucket.name) {
+                    return Err(format!("invalid bucket name '{}' in experiment '{}'", bucket.name, config.name));
+                }
+                if bucket.weight == 0 {
+                    continue;
+                }
+                total += u64::from(bucket.weight);
+                buckets.push((bucket.name.clone(), total));
+            }
+            if buckets.is_empty() {
+                return Err(format!("experiment '{}' has no buckets with a positive weight", config.name));
+            }
+
+            experiments.push(Experiment {
+                name: config.name.clone(),
+                header: format!("X-Experiment-{}", config.name),
+                key,
+                buckets,
+                cookie: config.cookie.clone(),
+                cookie_max_age: config.cookie_max_age,
+            });
+        }
+        Ok((!experiments.is_empty()).then_some(Self { experiments }))
+    }
+
+    /// Назначает группы всех экспериментов. Группа из cookie закрепления сохраняется,
+    /// иначе выбирается по хешу ключа: один и тот же ключ всегда попадает в одну группу
+    pub fn assign(&self, req: &RequestHeader, client_ip: Option<IpAddr>) -> Vec<Assignment> {
+        self.experiments
+            .iter()
+            .filter_map(|experiment| {
+                let pinned = experiment
+                    .cookie
+                    .as_deref()
+                    .and_then(|cookie| request_cookie(req, cookie))
+                    .filter(|bucket| experiment.buckets.iter().any(|(name, _)| name == bucket));
+                if let Some(bucket) = pinned {
+                    return Some(Assignment { header: experiment.header.clone(), bucket: bucket.to_string(), cookie: None });
+                }
+
+                let key = match &experiment.key {
+                    ExperimentKey::Cookie(name) => request_cookie(req, name).map(str::to_string),
+                    ExperimentKey::ClientIp => None,
+                }
+                .or_else(|| client_ip.map(|ip| ip.to_string()))?;
+                let bucket = experiment.bucket(&key);
+                Some(Assignment {
+                    header: experiment.header.clone(),
+                    bucket: bucket.to_string(),
+                    cookie: experiment.cookie.clone().map(|cookie| (cookie, experiment.cookie_max_age)),
+                })
+            })
+            .collect()
+    }
+}
+
+impl Experiment {
+    /// Группа для ключа; хеш включает имя эксперимента, чтобы группы разных экспериментов не коррелировали
+    fn bucket(&self, key: &str) -> &str {
+        let total = self.buckets.last().map_or(1, |(_, bound)| *bound);
+        let point = stable_hash(format!("{}:{}", self.name, key).as_bytes()) % total;
+        self.buckets
+            .iter()
+            .find(|(_, bound)| point < *bound)
+            .map_or("", |(name, _)| name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ExperimentBucketConfig;
+
+    fn experiment(key: &str, cookie: Option<&str>, buckets: &[(&str, u32)]) -> ExperimentConfig {
+        ExperimentConfig {
+            name: "checkout".to_string(),
+            key: key.to_string(),
+            buckets: buckets
+                .iter()
+                .map(|(name, weight)| ExperimentBucketConfig { name: name.to_string(), weight: *weight })
+                .collect(),
+            cookie: cookie.map(str::to_string),
+            cookie_max_age: 3600,
+        }
+    }
+
+    fn request(cookie: Option<&str>) -> RequestHeader {
+        let mut req = RequestHeader::build("GET", b"/api/cart", None).unwrap();
+        if let Some(cookie) = cookie {
+            req.insert_header("Cookie", cookie).unwrap();
+        }
+        req
+    }
+
+    #[test]
+    fn test_stable_bucketing() {
+        let experiments = Experiments::from_config(&[experiment("cookie:uid", None, &[("control", 50), ("new_flow", 50)])])
+            .unwrap()
+            .unwrap();
+        let ip: IpAddr = "198.51.100.7".parse().unwrap();
+
+        let first = experiments.assign(&request(Some("lang=ru; uid=u-42")), Some(ip));
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].header, "X-Experiment-checkout");
+        // Тот же ключ с другого адреса - та же группа
+        for _ in 0..10 {
+            assert_eq!(experiments.assign(&request(Some("uid=u-42")), Some("203.0.113.9".parse().unwrap())), first);
+        }
+        // Без cookie ключом служит IP клиента
+        let by_ip = experiments.assign(&request(None), Some(ip));
+        assert_eq!(experiments.assign(&request(None), Some(ip)), by_ip);
+        assert!(experiments.assign(&request(None), None).is_empty());
+
+        // Распределение близко к весам
+        let new_flow = (0..10_000)
+            .filter(|user| experiments.assign(&request(Some(&format!("uid=u-{}", user))), None)[0].bucket == "new_flow")
+            .count();
+        assert!((4_500..5_500).contains(&new_flow), "new_flow: {}", new_flow);
+    }
+
+    #[test]
+    fn test_sticky_cookie() {
+        let experiments = Experiments::from_config(&[experiment("ip", Some("adq_exp_checkout"), &[("control", 1), ("new_flow", 1)])])
+            .unwrap()
+            .unwrap();
+        let ip: IpAddr = "198.51.100.7".parse().unwrap();
+
+        let assigned = experiments.assign(&request(None), Some(ip)).remove(0);
+        assert_eq!(
+            assigned.set_cookie(true).unwrap(),
+            format!("adq_exp_checkout={}; Path=/; Max-Age=3600; SameSite=Lax; Secure", assigned.bucket)
+        );
+
+        // Группа из cookie сохраняется и не выставляется повторно
+        let pinned = experiments.assign(&request(Some("adq_exp_checkout=control")), Some(ip)).remove(0);
+        assert_eq!(pinned.bucket, "control");
+        assert_eq!(pinned.set_cookie(true), None);
+        // Неизвестная группа в cookie (эксперимент изменен) назначается заново
+        let reassigned = experiments.assign(&request(Some("adq_exp_checkout=legacy")), Some(ip)).remove(0);
+        assert_eq!(reassigned.bucket, assigned.bucket);
+        assert!(reassigned.set_cookie(false).is_some());
+    }
+
+    #[test]
+    fn test_invalid_experiments() {
+        assert!(Experiments::from_config(&[]).unwrap().is_none());
+        assert!(Experiments::from_config(&[experiment("session", None, &[("a", 1)])]).is_err());
+        assert!(Experiments::from_config(&[experiment("ip", None, &[("a", 0)])]).is_err());
+        assert!(Experiments::from_config(&[experiment("ip", Some("bad cookie"), &[("a", 1)])]).is_err());
+        let mut invalid_name = experiment("ip", None, &[("a", 1)]);
+        invalid_name.name = "checkout v2".to_string();
+        assert!(Experiments::from_config(&[invalid_name]).is_err());
+        let duplicate = experiment("ip", None, &[("a", 1)]);
+        assert!(Experiments::from_config(&[duplicate.clone(), duplicate]).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use bytes::Bytes;
+use pingora::http::ResponseHeader;
+use pingora::prelude::*;
+use std::collections::HashMap;
+
+use crate::config::{Config, FallbackCondition, FallbackResponse, LocationBlock};
+use crate::cors::{add_cors_headers_for_request, add_security_headers};
+use crate::metrics::LOCATION_FALLBACKS;
+
+/// Тела заглушек fallback_response, загруженные при старте
+#[derive(Debug, Default)]
+pub struct FallbackResponses {
+    bodies: HashMap<String, Bytes>,
+}
+
+impl FallbackResponses {
+    /// Читает файлы заглушек всех location. Отсутствующий файл - ошибка конфигурации.
+    pub fn load(config: &Config) -> Result<Self, String> {
+        let mut bodies = HashMap::new();
+        let locations = config
+            .nginx_config
+            .iter()
+            .flat_map(|nginx| &nginx.servers)
+            .flat_map(|server| &server.locations);
+        for location in locations {
+            let Some(fallback) = &location.fallback_response else {
+                continue;
+            };
+            if bodies.contains_key(&fallback.file) {
+                continue;
+            }
+            let content = std::fs::read(&fallback.file).map_err(|e| {
+                format!(
+                    "failed to read fallback_response file '{}' for location '{}': {}",
+                    fallback.file, location.path, e
+                )
+            })?;
+            bodies.insert(fallback.file.clone(), Bytes::from(content));
+        }
+        Ok(Self { bodies })
+    }
+
+    /// Заглушка location для условия сбоя
+    pub fn find<'a>(
+        &self,
+        location: &'a LocationBlock,
+        conditions: &[FallbackCondition],
+    ) -> Option<(&'a FallbackResponse, FallbackCondition, Bytes)> {
+        let fallback = location.fallback_response.as_ref()?;
+        let condition = conditions.iter().copied().find(|c| fallback.matches(*c))?;
+        let body = self.bodies.get(&fallback.file)?.clone();
+        Some((fallback, condition, body))
+    }
+}
+
+/// Условия сбоя для ошибки проксирования: ошибка соединения, таймаут и статус,
+/// которым прокси ответил бы клиенту
+pub fn proxy_failure_conditions(e: &Error, upstream_response_received: bool) -> Vec<FallbackCondition> {
+    if upstream_response_received || !matches!(e.esource(), ErrorSource::Upstream) {
+        return Vec::new();
+    }
+    match e.etype() {
+        ErrorType::ConnectTimedout => vec![
+            FallbackCondition::ConnectError,
+            FallbackCondition::Timeout,
+            FallbackCondition::Status(504),
+        ],
+        ErrorType::ConnectRefused
+        | ErrorType::ConnectNoRoute
+        | ErrorType::ConnectError
+        | ErrorType::ConnectProxyFailure
+        | ErrorType::TLSHandshakeFailure => vec![FallbackCondition::ConnectError, FallbackCondition::Status(502)],
+        ErrorType::ReadTimedout => vec![FallbackCondition::Timeout, FallbackCondition::Status(504)],
+        ErrorType::HTTPStatus(code) => vec![FallbackCondition::Status(*code)],
+        _ => vec![FallbackCondition::Status(502)],
+    }
+}
+
+/// Формирует заголовки заглушки с X-Fallback: true
+pub fn build_fallback_header(fallback: &FallbackResponse, body_len: usize) -> Result<ResponseHeader> {
+    let content_type = match &fallback.content_type {
+        Some(content_type) => content_type.clone(),
+        None => mime_guess::from_path(&fallback.file).first_or_octet_stream().to_string(),
+    };
+
+    let mut response = ResponseHeader::build(fallback.status, None)?;
+    response.insert_header("Content-Type", content_type)?;
+    response.insert_header("Content-Length", body_len.to_string())?;
+    response.insert_header("Cache-Control", "no-store")?;
+    response.insert_header("X-Fallback", "true")?;
+    Ok(response)
+}
+
+/// Учитывает отдачу заглушки в метрике location
+pub fn record_fallback(location: &LocationBlock, condition: FallbackCondition) {
+    let condition = match condition {
+        FallbackCondition::Status(code) => code.to_string(),
+        FallbackCondition::ConnectError => "connect_error".to_string(),
+        FallbackCondition::Timeout => "timeout".to_string(),
+        FallbackCondition::CircuitOpen => "circuit_open".to_string(),
+    };
+    LOCATION_FALLBACKS.with_label_values(&[&location.path, &condition]).inc();
+}
+
+/// Отправляет заглушку клиенту вместо ответа об ошибке
+pub async fn send_fallback_response(session: &mut Session, fallback: &FallbackResponse, body: Bytes) -> Result<()> {
+    let mut response = build_fallback_header(fallback, body.len())?;
+    add_security_headers(&mut response)?;
+    add_cors_headers_for_request(session, &mut response)?;
+
+    session.set_keepalive(None);
+    session.write_response_header(Box::new(response), false).await?;
+    session.write_response_body(Some(body), true).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NginxConfig;
+    use std::io::Write;
+
+    fn config(file: &str) -> Config {
+        let mut config = Config::default();
+        config.nginx_config = Some(
+            NginxConfig::parse_config_content(&format!(
+                r#"
+                server {{
+                    listen 80;
+                    server_name api.ad-quest.ru;
+                    location /flags {{
+                        proxy_pass flags;
+                        fallback_response file={} status=200 content_type=application/json when=502,503,504,connect_error;
+                    }}
+                }}
+                "#,
+                file
+            ))
+            .unwrap(),
+        );
+        config
+    }
+
+    #[test]
+    fn test_dead_upstream_serves_stub() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(br#"{"flags":{"new_checkout":false}}"#).unwrap();
+        let config = config(file.path().to_str().unwrap());
+        let fallbacks = FallbackResponses::load(&config).unwrap();
+        let location = &config.nginx_config.as_ref().unwrap().servers[0].locations[0];
+
+        // Upstream не принимает соединения
+        let refused = Error::new(ErrorType::ConnectRefused).into_up();
+        let conditions = proxy_failure_conditions(&refused, false);
+        let (fallback, condition, body) = fallbacks.find(location, &conditions).unwrap();
+        assert_eq!(condition, FallbackCondition::ConnectError);
+        assert_eq!(&body[..], br#"{"flags":{"new_checkout":false}}"#);
+
+        let response = build_fallback_header(fallback, body.len()).unwrap();
+        assert_eq!(response.status.as_u16(), 200);
+        assert_eq!(response.headers.get("x-fallback").unwrap(), "true");
+        assert_eq!(response.headers.get("content-type").unwrap(), "application/json");
+        assert_eq!(response.headers.get("content-length").unwrap(), body.len().to_string().as_str());
+
+        record_fallback(location, condition);
+        assert!(LOCATION_FALLBACKS.with_label_values(&["/flags", "connect_error"]).get() >= 1);
+
+        // Ответ upstream 503 и открытый circuit breaker тоже обслуживаются заглушкой
+        assert!(fallbacks.find(location, &[FallbackCondition::Status(503)]).is_some());
+        assert!(fallbacks.find(location, &[FallbackCondition::CircuitOpen]).is_some());
+        // Таймаут чтения дает 504, он указан в when
+        let timeout = Error::new(ErrorType::ReadTimedout).into_up();
+        assert!(fallbacks.find(location, &proxy_failure_conditions(&timeout, false)).is_some());
+        // Ответ уже начат, а 500 не указан в when
+        assert!(proxy_failure_conditions(&timeout, true).is_empty());
+        assert!(fallbacks.find(location, &[FallbackCondition::Status(500)]).is_none());
+    }
+
+    #[test]
+    fn test_missing_file_fails_load() {
+        let config = config("/nonexistent/adq-pingora/flags.json");
+        let err = FallbackResponses::load(&config).unwrap_err();
+        assert!(err.contains("/nonexistent/adq-pingora/flags.json"));
+        assert!(FallbackResponses::load(&Config::default()).unwrap().bodies.is_empty());
+    }
+}
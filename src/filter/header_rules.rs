@@ -0,0 +1,354 @@
+use bytes::Bytes;
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use crate::config::HeaderRuleConfig;
+use crate::metrics::HEADER_RULE_MATCHES;
+use crate::schedules::Schedules;
+
+/// Идентификатор pre-release части версии (1.2.0-beta.1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PreRelease {
+    Numeric(u64),
+    Alpha(String),
+}
+
+impl Ord for PreRelease {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (PreRelease::Numeric(a), PreRelease::Numeric(b)) => a.cmp(b),
+            (PreRelease::Alpha(a), PreRelease::Alpha(b)) => a.cmp(b),
+            // Числовые идентификаторы младше буквенных
+            (PreRelease::Numeric(_), PreRelease::Alpha(_)) => Ordering::Less,
+            (PreRelease::Alpha(_), PreRelease::Numeric(_)) => Ordering::Greater,
+        }
+    }
+}
+
+impl PartialOrd for PreRelease {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Версия приложения в формате semver. Допускаются префикс "v" и сокращенные
+/// версии ("2", "2.3"), build metadata (+build.5) при сравнении не учитывается.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    major: u64,
+    minor: u64,
+    patch: u64,
+    pre: Vec<PreRelease>,
+}
+
+impl Version {
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let value = value.strip_prefix(['v', 'V']).unwrap_or(value);
+        let value = value.split('+').next()?;
+        let (core, pre) = match value.split_once('-') {
+            Some((core, pre)) => (core, Some(pre)),
+            None => (value, None),
+        };
+
+        let mut numbers = [0u64; 3];
+        let parts: Vec<&str> = core.split('.').collect();
+        if parts.len() > 3 {
+            return None;
+        }
+        for (number, part) in numbers.iter_mut().zip(&parts) {
+            if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            *number = part.parse().ok()?;
+        }
+
+        let pre = match pre {
+            Some(pre) => pre
+                .split('.')
+                .map(|id| {
+                    if id.is_empty() || !id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-') {
+                        None
+                    } else if id.bytes().all(|b| b.is_ascii_digit()) {
+                        id.parse().ok().map(PreRelease::Numeric)
+                    } else {
+                        Some(PreRelease::Alpha(id.to_string()))
+                    }
+                })
+                .collect::<Option<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+
+        Some(Self {
+            major: numbers[0],
+            minor: numbers[1],
+            patch: numbers[2],
+            pre,
+        })
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.pre.is_empty(), other.pre.is_empty()) {
+                // Pre-release младше релиза той же версии
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => self.pre.cmp(&other.pre),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Условие на значение заголовка
+#[derive(Debug)]
+enum HeaderCondition {
+    Equals(String),
+    VersionBelow(Version),
+}
+
+/// Скомпилированное правило с готовым ответом
+#[derive(Debug)]
+pub struct HeaderRule {
+    pub name: String,
+    header: String,
+    condition: HeaderCondition,
+    match_missing: bool,
+    servers: Vec<String>,
+    locations: Vec<String>,
+    schedule: Option<String>,
+    pub status: u16,
+    pub body: Bytes,
+}
+
+impl HeaderRule {
+    fn compile(config: &HeaderRuleConfig) -> Result<Self, String> {
+        if http::HeaderName::from_bytes(config.header.as_bytes()).is_err() {
+            return Err(format!("invalid header name '{}' in header rule '{}'", config.header, config.name));
+        }
+        if http::StatusCode::from_u16(config.status).is_err() {
+            return Err(format!("invalid status {} in header rule '{}'", config.status, config.name));
+        }
+
+        let condition = match (&config.equals, &config.version_below) {
+            (Some(value), None) => HeaderCondition::Equals(value.clone()),
+            (None, Some(version)) => HeaderCondition::VersionBelow(Version::parse(version).ok_or_else(|| {
+                format!("invalid version '{}' in header rule '{}'", version, config.name)
+            })?),
+            _ => {
+                return Err(format!(
+                    "header rule '{}' must set exactly one of 'equals' or 'version_below'",
+                    config.name
+                ))
+            }
+        };
+
+        let body = serde_json::to_vec(&config.body)
+            .map_err(|e| format!("invalid body in header rule '{}': {}", config.name, e))?;
+
+        Ok(Self {
+            name: config.name.clone(),
+            header: config.header.clone(),
+            condition,
+            match_missing: config.match_missing,
+            servers: config.servers.clone(),
+            locations: config.locations.clone(),
+            schedule: config.schedule.clone(),
+            status: config.status,
+            body: Bytes::from(body),
+        })
+    }
+
+    fn applies_to(&self, host: &str, path: &str) -> bool {
+        (self.servers.is_empty() || self.servers.iter().any(|server| server == host))
+            && (self.locations.is_empty() || self.locations.iter().any(|prefix| path.starts_with(prefix.as_str())))
+    }
+
+    /// Нераспознанная версия считается отсутствующим заголовком
+    fn matches(&self, value: Option<&str>) -> bool {
+        let Some(value) = value.map(str::trim).filter(|v| !v.is_empty()) else {
+            return self.match_missing;
+        };
+        match &self.condition {
+            HeaderCondition::Equals(expected) => value == expected,
+            HeaderCondition::VersionBelow(threshold) => match Version::parse(value) {
+                Some(version) => version < *threshold,
+                None => self.match_missing,
+            },
+        }
+    }
+}
+
+/// Правила по заголовкам запроса, отвечающие заданным статусом и JSON
+/// (например, 426 для устаревших версий мобильного приложения)
+#[derive(Debug, Default)]
+pub struct HeaderRules {
+    rules: Vec<HeaderRule>,
+    /// Действующие окна для правил с schedule
+    schedules: Arc<Schedules>,
+}
+
+impl HeaderRules {
+    pub fn from_config(rules: &[HeaderRuleConfig]) -> Result<Self, String> {
+        Ok(Self {
+            rules: rules.iter().map(HeaderRule::compile).collect::<Result<Vec<_>, _>>()?,
+            schedules: Arc::default(),
+        })
+    }
+
+    /// Расписания для правил с schedule; без них такие правила не срабатывают
+    pub fn with_schedules(mut self, schedules: Arc<Schedules>) -> Self {
+        self.schedules = schedules;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Возвращает первое сработавшее правило; значение заголовка берется через `header_value`
+    pub fn check<'a, F>(&self, header_value: F, host: &str, path: &str) -> Option<&HeaderRule>
+    where
+        F: Fn(&str) -> Option<&'a str>,
+    {
+        self.rules
+            .iter()
+            .find(|rule| {
+                rule.applies_to(host, path)
+                    && rule.schedule.as_ref().is_none_or(|schedule| self.schedules.is_active(schedule))
+                    && rule.matches(header_value(&rule.header))
+            })
+    }
+
+    /// Проверяет запрос и учитывает срабатывание в header_rule_matches_total
+    pub fn evaluate<'a, F>(&self, header_value: F, host: &str, path: &str) -> Option<&HeaderRule>
+    where
+        F: Fn(&str) -> Option<&'a str>,
+    {
+        let rule = self.check(header_value, host, path)?;
+        HEADER_RULE_MATCHES.with_label_values(&[&rule.name]).inc();
+        Some(rule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn version(value: &str) -> Version {
+        Version::parse(value).unwrap()
+    }
+
+    fn upgrade_rule(match_missing: bool) -> HeaderRuleConfig {
+        HeaderRuleConfig {
+            name: "old_app".to_string(),
+            header: "X-App-Version".to_string(),
+            equals: None,
+            version_below: Some("1.2.10".to_string()),
+            match_missing,
+            servers: Vec::new(),
+            locations: vec!["/api/".to_string()],
+            status: 426,
+            body: json!({"error": "upgrade_required", "upgrade_url": "https://ad-quest.ru/app"}),
+            schedule: None,
+        }
+    }
+
+    #[test]
+    fn test_version_ordering() {
+        assert!(version("1.2.10") > version("1.2.9"));
+        assert!(version("1.10.0") > version("1.9.99"));
+        assert!(version("2") > version("1.99.99"));
+        assert_eq!(version("v2.3"), version("2.3.0"));
+        assert_eq!(version("1.2.3+build.7"), version("1.2.3"));
+
+        // Pre-release младше релиза, числовые идентификаторы младше буквенных
+        assert!(version("1.2.3-beta") < version("1.2.3"));
+        assert!(version("1.2.3-alpha") < version("1.2.3-alpha.1"));
+        assert!(version("1.2.3-alpha.2") < version("1.2.3-alpha.10"));
+        assert!(version("1.2.3-1") < version("1.2.3-alpha"));
+
+        assert!(Version::parse("1.2.x").is_none());
+        assert!(Version::parse("1..2").is_none());
+        assert!(Version::parse("1.2.3.4").is_none());
+        assert!(Version::parse("").is_none());
+    }
+
+    #[test]
+    fn test_version_below_rule() {
+        let rules = HeaderRules::from_config(&[upgrade_rule(false)]).unwrap();
+        let check = |value: Option<&'static str>, path: &str| {
+            rules.check(|_| value, "api.ad-quest.ru", path).map(|rule| rule.name.clone())
+        };
+
+        assert_eq!(check(Some("1.2.9"), "/api/orders"), Some("old_app".to_string()));
+        assert_eq!(check(Some("1.2.10-rc.1"), "/api/orders"), Some("old_app".to_string()));
+        assert_eq!(check(Some("1.2.10"), "/api/orders"), None);
+        assert_eq!(check(Some("1.3"), "/api/orders"), None);
+        // Правило действует только в своих location
+        assert_eq!(check(Some("1.0.0"), "/static/app.js"), None);
+
+        let rule = rules.check(|_| Some("1.0"), "h", "/api/").unwrap();
+        assert_eq!(rule.status, 426);
+        let body: serde_json::Value = serde_json::from_slice(&rule.body).unwrap();
+        assert_eq!(body["upgrade_url"], "https://ad-quest.ru/app");
+    }
+
+    #[test]
+    fn test_missing_header_passthrough() {
+        let passthrough = HeaderRules::from_config(&[upgrade_rule(false)]).unwrap();
+        assert!(passthrough.check(|_| None, "h", "/api/").is_none());
+        assert!(passthrough.check(|_| Some("garbage"), "h", "/api/").is_none());
+
+        let strict = HeaderRules::from_config(&[upgrade_rule(true)]).unwrap();
+        assert!(strict.check(|_| None, "h", "/api/").is_some());
+        assert!(strict.check(|_| Some("garbage"), "h", "/api/").is_some());
+    }
+
+    #[test]
+    fn test_scheduled_rule() {
+        let mut config = upgrade_rule(false);
+        config.schedule = Some("always".to_string());
+        let schedules = Schedules::from_config(&[crate::config::ScheduleConfig {
+            name: "always".to_string(),
+            days: Vec::new(),
+            start: "00:00".to_string(),
+            end: "24:00".to_string(),
+            timezone: "UTC".to_string(),
+        }])
+        .unwrap();
+        let rules = HeaderRules::from_config(&[config.clone()]).unwrap().with_schedules(Arc::new(schedules));
+        assert!(rules.check(|_| Some("1.0"), "h", "/api/").is_some());
+
+        // Правило с недействующим (здесь - необъявленным) расписанием не срабатывает
+        config.schedule = Some("billing_night".to_string());
+        let rules = HeaderRules::from_config(&[config]).unwrap();
+        assert!(rules.check(|_| Some("1.0"), "h", "/api/").is_none());
+    }
+
+    #[test]
+    fn test_equals_rule_and_validation() {
+        let mut config = upgrade_rule(false);
+        config.version_below = None;
+        config.equals = Some("kill-switch".to_string());
+        let rules = HeaderRules::from_config(&[config.clone()]).unwrap();
+        assert!(rules.check(|_| Some("kill-switch"), "h", "/api/").is_some());
+        assert!(rules.check(|_| Some("Kill-Switch"), "h", "/api/").is_none());
+
+        config.version_below = Some("1.0".to_string());
+        assert!(HeaderRules::from_config(&[config.clone()]).unwrap_err().contains("old_app"));
+
+        config.equals = None;
+        config.version_below = Some("1.x".to_string());
+        assert!(HeaderRules::from_config(&[config]).is_err());
+    }
+}
@@ -0,0 +1,63 @@
+use pingora::http::RequestHeader;
+
+/// Допустимый байт значения заголовка (RFC 9110: VCHAR, SP, HTAB и obs-text)
+fn is_valid_value_byte(byte: u8) -> bool {
+    byte == b'\t' || (0x20..0x7f).contains(&byte) || byte >= 0x80
+}
+
+/// Проверяет заголовки запроса клиента перед проксированием: управляющие символы
+/// (в том числе CR/LF) в значениях и размер каждого заголовка
+pub fn validate_request_headers(request: &RequestHeader, max_header_size: usize) -> Result<(), String> {
+    for (name, value) in request.headers.iter() {
+        let bytes = value.as_bytes();
+        if let Some(byte) = bytes.iter().find(|b| !is_valid_value_byte(**b)) {
+            return Err(format!("illegal byte 0x{:02x} in header {}", byte, name));
+        }
+        if name.as_str().len() + bytes.len() > max_header_size {
+            return Err(format!("header {} exceeds {} bytes", name, max_header_size));
+        }
+    }
+
+    // Host передается upstream как есть, поэтому допускается только видимый ASCII
+    if let Some(host) = request.headers.get("host") {
+        if host.is_empty() || host.as_bytes().iter().any(|b| !b.is_ascii_graphic()) {
+            return Err("invalid Host header".to_string());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http::HeaderValue;
+
+    fn request(name: &str, value: &'static [u8]) -> RequestHeader {
+        let mut request = RequestHeader::build("GET", b"/", None).unwrap();
+        request.insert_header("Host", "api.ad-quest.ru").unwrap();
+        // Значение без проверок http, как если бы оно пришло от парсера без валидации
+        let value = unsafe { HeaderValue::from_maybe_shared_unchecked(Bytes::from_static(value)) };
+        request.insert_header(name.to_string(), value).unwrap();
+        request
+    }
+
+    #[test]
+    fn test_crlf_header_value_rejected() {
+        let injected = request("X-Forwarded-User", b"alice\r\nX-Admin: true");
+        assert!(validate_request_headers(&injected, 8192).is_err());
+
+        let nul = request("X-Trace", b"abc\x00def");
+        assert!(validate_request_headers(&nul, 8192).is_err());
+
+        let host = request("Host", b"api.ad-quest.ru\r\nX-Evil: 1");
+        assert!(validate_request_headers(&host, 8192).is_err());
+    }
+
+    #[test]
+    fn test_valid_headers_and_size_limit() {
+        let valid = request("User-Agent", b"Mozilla/5.0 (X11;\tLinux) \xd0\xb0");
+        assert!(validate_request_headers(&valid, 8192).is_ok());
+        assert!(validate_request_headers(&valid, 16).is_err());
+    }
+}
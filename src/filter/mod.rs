@@ -1,9 +1,60 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::net::IpAddr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use log::info;
 
+use crate::error::ProxyError;
+
+pub mod header_rules;
+pub mod headers;
+pub mod user_agent;
+pub use header_rules::HeaderRules;
+pub use headers::validate_request_headers;
+pub use user_agent::UaFilter;
+
+/// Сколько живет закешированное решение "IP разрешен списками"
+const DECISION_CACHE_TTL: Duration = Duration::from_secs(5);
+/// Предел записей в кеше решений; при переполнении кеш очищается
+const DECISION_CACHE_CAPACITY: usize = 65_536;
+
+/// Кеш разрешающих решений по whitelist/blacklist. Запись действительна, пока не истек TTL
+/// и не сменилось поколение списков (любое изменение whitelist/blacklist)
+#[derive(Debug, Default)]
+struct DecisionCache {
+    generation: AtomicU64,
+    allowed: Mutex<HashMap<IpAddr, (Instant, u64)>>,
+}
+
+impl DecisionCache {
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    fn is_allowed(&self, ip: IpAddr, now: Instant) -> bool {
+        let generation = self.generation();
+        let allowed = self.allowed.lock().unwrap_or_else(|e| e.into_inner());
+        matches!(allowed.get(&ip), Some((expires, cached)) if *cached == generation && *expires > now)
+    }
+
+    /// Запоминает решение, принятое по спискам поколения `generation`
+    fn allow(&self, ip: IpAddr, generation: u64, now: Instant) {
+        let mut allowed = self.allowed.lock().unwrap_or_else(|e| e.into_inner());
+        if allowed.len() >= DECISION_CACHE_CAPACITY {
+            allowed.clear();
+        }
+        allowed.insert(ip, (now + DECISION_CACHE_TTL, generation));
+    }
+
+    /// Вызывается после изменения списков
+    fn invalidate(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.allowed.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
 /// Фильтр соединений для блокировки/разрешения IP адресов
 #[derive(Debug, Clone)]
 pub struct IPFilter {
@@ -15,6 +66,8 @@ pub struct IPFilter {
     max_connections_per_ip: Option<usize>,
     /// Счетчик активных соединений по IP
     connection_counts: Arc<RwLock<std::collections::HashMap<IpAddr, usize>>>,
+    /// Кеш решений по спискам для повторных запросов с того же IP
+    decisions: Arc<DecisionCache>,
 }
 
 impl IPFilter {
@@ -25,6 +78,7 @@ impl IPFilter {
             whitelist: None,
             max_connections_per_ip: None,
             connection_counts: Arc::new(RwLock::new(std::collections::HashMap::new())),
+            decisions: Arc::new(DecisionCache::default()),
         }
     }
 
@@ -35,18 +89,21 @@ impl IPFilter {
             whitelist: Some(Arc::new(RwLock::new(whitelist))),
             max_connections_per_ip: None,
             connection_counts: Arc::new(RwLock::new(std::collections::HashMap::new())),
+            decisions: Arc::new(DecisionCache::default()),
         }
     }
 
     /// Добавляет IP в blacklist
     pub async fn add_to_blacklist(&self, ip: IpAddr) {
         self.blacklist.write().await.insert(ip);
+        self.decisions.invalidate();
         info!("Added {} to blacklist", ip);
     }
 
     /// Удаляет IP из blacklist
     pub async fn remove_from_blacklist(&self, ip: IpAddr) {
         if self.blacklist.write().await.remove(&ip) {
+            self.decisions.invalidate();
             info!("Removed {} from blacklist", ip);
         }
     }
@@ -55,13 +112,14 @@ impl IPFilter {
     pub async fn add_to_whitelist(&self, ip: IpAddr) {
         if let Some(whitelist) = &self.whitelist {
             whitelist.write().await.insert(ip);
+            self.decisions.invalidate();
             info!("Added {} to whitelist", ip);
         }
     }
 
     /// Загружает blacklist из файла (по одному IP на строку)
-    pub async fn load_blacklist_from_file(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let content = std::fs::read_to_string(path)?;
+    pub async fn load_blacklist_from_file(&self, path: &str) -> Result<(), ProxyError> {
+        let content = std::fs::read_to_string(path).map_err(ProxyError::io(path))?;
         let mut blacklist = self.blacklist.write().await;
         
         for line in content.lines() {
@@ -83,6 +141,7 @@ impl IPFilter {
             }
         }
         
+        self.decisions.invalidate();
         info!("Loaded {} IPs from blacklist file: {}", blacklist.len(), path);
         Ok(())
     }
@@ -130,19 +189,9 @@ impl IPFilter {
     /// Проверяет, должен ли IP быть заблокирован
     /// Используется в request_filter для фильтрации запросов
     pub async fn should_block_ip(&self, ip: IpAddr) -> bool {
-
-        // Проверяем whitelist (если установлен, разрешены только эти IP)
-        if let Some(whitelist) = &self.whitelist {
-            if !whitelist.read().await.contains(&ip) {
-                info!("Blocking request from {} (not in whitelist)", ip);
-                return true; // Блокируем
-            }
-        }
-
-        // Проверяем blacklist
-        if self.blacklist.read().await.contains(&ip) {
-            info!("Blocking request from {} (in blacklist)", ip);
-            return true; // Блокируем
+        let now = Instant::now();
+        if !self.decisions.is_allowed(ip, now) && self.blocked_by_lists(ip).await {
+            return true;
         }
 
         // Проверяем лимит соединений с одного IP
@@ -161,6 +210,29 @@ impl IPFilter {
 
         false // Не блокируем
     }
+
+    /// Проверка по whitelist/blacklist; разрешающее решение кешируется
+    async fn blocked_by_lists(&self, ip: IpAddr) -> bool {
+        // Поколение берется до чтения списков: изменение во время проверки сделает запись недействительной
+        let generation = self.decisions.generation();
+
+        // Проверяем whitelist (если установлен, разрешены только эти IP)
+        if let Some(whitelist) = &self.whitelist {
+            if !whitelist.read().await.contains(&ip) {
+                info!("Blocking request from {} (not in whitelist)", ip);
+                return true; // Блокируем
+            }
+        }
+
+        // Проверяем blacklist
+        if self.blacklist.read().await.contains(&ip) {
+            info!("Blocking request from {} (in blacklist)", ip);
+            return true; // Блокируем
+        }
+
+        self.decisions.allow(ip, generation, Instant::now());
+        false
+    }
 }
 
 impl Default for IPFilter {
@@ -231,4 +303,29 @@ mod tests {
         filter.decrement_connection_count(ip).await;
         assert!(!filter.should_block_ip(ip).await); // count=1 < max=2, разрешаем
     }
+
+    #[tokio::test]
+    async fn test_cached_allow_invalidated_on_blacklist_update() {
+        let filter = IPFilter::new();
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+
+        assert!(!filter.should_block_ip(ip).await);
+        assert!(filter.decisions.is_allowed(ip, Instant::now()));
+
+        // Пока решение в кеше, списки не читаются: занятая блокировка blacklist не мешает проверке
+        {
+            let _guard = filter.blacklist.write().await;
+            let check = tokio::time::timeout(Duration::from_millis(100), filter.should_block_ip(ip));
+            assert_eq!(check.await, Ok(false));
+        }
+
+        filter.add_to_blacklist(ip).await;
+        assert!(!filter.decisions.is_allowed(ip, Instant::now()));
+        assert!(filter.should_block_ip(ip).await);
+
+        filter.remove_from_blacklist(ip).await;
+        assert!(!filter.should_block_ip(ip).await);
+        // Истекшее решение не используется
+        assert!(!filter.decisions.is_allowed(ip, Instant::now() + DECISION_CACHE_TTL));
+    }
 }
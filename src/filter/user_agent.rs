@@ -0,0 +1,169 @@
+use regex::Regex;
+
+use crate::config::{UaFilterConfig, UaRuleConfig};
+use crate::metrics::UA_BLOCKED;
+
+/// Имя правила для запросов без User-Agent
+pub const EMPTY_USER_AGENT_RULE: &str = "empty_user_agent";
+
+/// Скомпилированное правило блокировки User-Agent
+#[derive(Debug)]
+struct UaRule {
+    name: String,
+    contains: Vec<String>,
+    regex: Vec<Regex>,
+    servers: Vec<String>,
+    locations: Vec<String>,
+}
+
+impl UaRule {
+    fn compile(config: &UaRuleConfig) -> Result<Self, String> {
+        let regex = config
+            .regex
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern)
+                    .map_err(|e| format!("invalid regex '{}' in ua_filter rule '{}': {}", pattern, config.name, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            name: config.name.clone(),
+            contains: config.contains.iter().map(|s| s.to_lowercase()).collect(),
+            regex,
+            servers: config.servers.clone(),
+            locations: config.locations.clone(),
+        })
+    }
+
+    fn applies_to(&self, host: &str, path: &str) -> bool {
+        (self.servers.is_empty() || self.servers.iter().any(|server| server == host))
+            && (self.locations.is_empty() || self.locations.iter().any(|prefix| path.starts_with(prefix.as_str())))
+    }
+
+    fn matches(&self, user_agent: &str, user_agent_lower: &str) -> bool {
+        self.contains.iter().any(|needle| user_agent_lower.contains(needle.as_str()))
+            || self.regex.iter().any(|regex| regex.is_match(user_agent))
+    }
+}
+
+/// Фильтр запросов по User-Agent: deny правила, блокировка пустого User-Agent
+/// и allowlist, который имеет приоритет над правилами
+#[derive(Debug)]
+pub struct UaFilter {
+    block_empty: bool,
+    allow: Vec<String>,
+    rules: Vec<UaRule>,
+}
+
+impl UaFilter {
+    /// Компилирует правила из конфигурации; ошибки регулярных выражений возвращаются с именем правила
+    pub fn from_config(config: &UaFilterConfig) -> Result<Self, String> {
+        Ok(Self {
+            block_empty: config.block_empty,
+            allow: config.allow.iter().map(|s| s.to_lowercase()).collect(),
+            rules: config.rules.iter().map(UaRule::compile).collect::<Result<Vec<_>, _>>()?,
+        })
+    }
+
+    /// Возвращает имя сработавшего правила, если запрос нужно заблокировать
+    pub fn check(&self, user_agent: Option<&str>, host: &str, path: &str) -> Option<&str> {
+        let user_agent = user_agent.map(str::trim).unwrap_or("");
+        if user_agent.is_empty() {
+            return self.block_empty.then_some(EMPTY_USER_AGENT_RULE);
+        }
+
+        let user_agent_lower = user_agent.to_lowercase();
+        if self.allow.iter().any(|allowed| user_agent_lower.contains(allowed.as_str())) {
+            return None;
+        }
+
+        self.rules
+            .iter()
+            .find(|rule| rule.applies_to(host, path) && rule.matches(user_agent, &user_agent_lower))
+            .map(|rule| rule.name.as_str())
+    }
+
+    /// Проверяет запрос и учитывает блокировку в ua_blocked_total
+    pub fn should_block(&self, user_agent: Option<&str>, host: &str, path: &str) -> Option<&str> {
+        let rule = self.check(user_agent, host, path)?;
+        UA_BLOCKED.with_label_values(&[rule]).inc();
+        Some(rule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(name: &str, contains: &[&str], regex: &[&str]) -> UaRuleConfig {
+        UaRuleConfig {
+            name: name.to_string(),
+            contains: contains.iter().map(|s| s.to_string()).collect(),
+            regex: regex.iter().map(|s| s.to_string()).collect(),
+            servers: Vec::new(),
+            locations: Vec::new(),
+        }
+    }
+
+    fn filter(block_empty: bool, allow: &[&str], rules: Vec<UaRuleConfig>) -> UaFilter {
+        UaFilter::from_config(&UaFilterConfig {
+            enabled: true,
+            block_empty,
+            allow: allow.iter().map(|s| s.to_string()).collect(),
+            rules,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_substring_rule() {
+        let filter = filter(false, &[], vec![rule("scrapers", &["python-requests"], &[])]);
+        assert_eq!(filter.check(Some("Python-Requests/2.31"), "api.ad-quest.ru", "/"), Some("scrapers"));
+        assert_eq!(filter.check(Some("Mozilla/5.0"), "api.ad-quest.ru", "/"), None);
+    }
+
+    #[test]
+    fn test_regex_rule_and_invalid_regex() {
+        let filter = filter(false, &[], vec![rule("bots", &[], &[r"^(Scrapy|Go-http-client)/\d"])]);
+        assert_eq!(filter.check(Some("Scrapy/2.11 (+https://scrapy.org)"), "h", "/"), Some("bots"));
+        assert_eq!(filter.check(Some("Mozilla/5.0 Scrapy/2.11"), "h", "/"), None);
+
+        let error = UaFilter::from_config(&UaFilterConfig {
+            enabled: true,
+            rules: vec![rule("broken", &[], &["(unclosed"])],
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(error.contains("broken"));
+    }
+
+    #[test]
+    fn test_empty_user_agent() {
+        let blocking = filter(true, &[], Vec::new());
+        assert_eq!(blocking.check(None, "h", "/"), Some(EMPTY_USER_AGENT_RULE));
+        assert_eq!(blocking.check(Some("  "), "h", "/"), Some(EMPTY_USER_AGENT_RULE));
+
+        let permissive = filter(false, &[], Vec::new());
+        assert_eq!(permissive.check(None, "h", "/"), None);
+    }
+
+    #[test]
+    fn test_allowlist_overrides_rules() {
+        let filter = filter(false, &["AdQuestMonitor"], vec![rule("bots", &["bot"], &[])]);
+        assert_eq!(filter.check(Some("AdQuestMonitor-bot/1.0"), "h", "/"), None);
+        assert_eq!(filter.check(Some("SomeBot/1.0"), "h", "/"), Some("bots"));
+    }
+
+    #[test]
+    fn test_rule_scoped_to_server_and_location() {
+        let mut scoped = rule("api_scrapers", &["curl"], &[]);
+        scoped.servers = vec!["api.ad-quest.ru".to_string()];
+        scoped.locations = vec!["/api/".to_string()];
+        let filter = filter(false, &[], vec![scoped]);
+
+        assert_eq!(filter.check(Some("curl/8.0"), "api.ad-quest.ru", "/api/users"), Some("api_scrapers"));
+        assert_eq!(filter.check(Some("curl/8.0"), "api.ad-quest.ru", "/health"), None);
+        assert_eq!(filter.check(Some("curl/8.0"), "auth.ad-quest.ru", "/api/users"), None);
+    }
+}
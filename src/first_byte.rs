@@ -0,0 +1,106 @@
+use std::net::{Shutdown, TcpStream};
+use std::os::fd::{BorrowedFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+
+/// Срок ожидания заголовков ответа upstream (proxy_first_byte_timeout).
+///
+/// Pingora ограничивает каждое чтение из upstream одним read_timeout, который нельзя
+/// поменять после отправки запроса. Таймер закрывает соединение, если заголовки не пришли
+/// в срок: ожидание ответа прерывается ошибкой, а таймаут между чанками тела остается read.
+#[derive(Debug)]
+pub struct FirstByteTimer {
+    socket: Arc<TcpStream>,
+    timeout: Duration,
+    expired: Arc<AtomicBool>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl FirstByteTimer {
+    /// Таймер для соединения с дескриптором `fd`; отсчет начинается с `start`
+    pub fn new(fd: RawFd, timeout: Duration) -> std::io::Result<Self> {
+        // Соединением владеет Pingora: таймер работает с собственной копией дескриптора
+        let socket = unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()?;
+        Ok(Self {
+            socket: Arc::new(TcpStream::from(socket)),
+            timeout,
+            expired: Arc::new(AtomicBool::new(false)),
+            task: None,
+        })
+    }
+
+    /// Запускает отсчет (запрос полностью отправлен в upstream). Повторный вызов ничего не меняет
+    pub fn start(&mut self) {
+        if self.task.is_some() {
+            return;
+        }
+        let socket = self.socket.clone();
+        let expired = self.expired.clone();
+        let timeout = self.timeout;
+        self.task = Some(tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            expired.store(true, Ordering::Release);
+            // Ожидающее заголовков чтение Pingora завершается ошибкой
+            let _ = socket.shutdown(Shutdown::Both);
+        }));
+    }
+
+    /// Истек ли срок до получения заголовков
+    pub fn expired(&self) -> bool {
+        self.expired.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for FirstByteTimer {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::os::fd::AsRawFd;
+    use std::time::Instant;
+
+    fn connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, server)
+    }
+
+    #[tokio::test]
+    async fn test_expired_timer_closes_connection() {
+        let (client, mut server) = connection();
+        let mut timer = FirstByteTimer::new(client.as_raw_fd(), Duration::from_millis(100)).unwrap();
+        timer.start();
+
+        let started = Instant::now();
+        let read = tokio::task::spawn_blocking(move || server.read(&mut [0u8; 16]).unwrap());
+        assert_eq!(read.await.unwrap(), 0);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(timer.expired());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_timer_keeps_connection() {
+        let (client, server) = connection();
+        let mut timer = FirstByteTimer::new(client.as_raw_fd(), Duration::from_millis(50)).unwrap();
+        timer.start();
+        drop(timer);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        server.set_nonblocking(true).unwrap();
+        let mut buf = [0u8; 16];
+        let err = (&server).read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+    }
+}
@@ -0,0 +1,204 @@
+use pingora::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::logging::LogScrubber;
+use crate::types::{HandledBy, RequestContext};
+
+/// Запрос, завершившийся статусом 5xx или ошибкой соединения с upstream
+#[derive(Debug, Clone, Serialize)]
+pub struct FlightRecord {
+    /// Время завершения запроса, RFC 3339
+    pub timestamp: String,
+    pub request_id: String,
+    pub method: String,
+    pub uri: String,
+    /// Заголовки запроса после скрытия чувствительных значений; повторы через ", "
+    pub request_headers: BTreeMap<String, String>,
+    /// Статус ответа клиенту (0 - ответ не отправлен)
+    pub status: u16,
+    pub upstream: Option<String>,
+    /// Адрес бэкенда последней попытки
+    pub backend: Option<String>,
+    pub retries: u32,
+    pub duration_ms: u64,
+    /// Время последней попытки обращения к upstream
+    pub upstream_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// Ошибка установки соединения с upstream
+fn is_connect_error(e: &Error) -> bool {
+    matches!(
+        e.etype(),
+        ErrorType::ConnectTimedout
+            | ErrorType::ConnectRefused
+            | ErrorType::ConnectNoRoute
+            | ErrorType::ConnectError
+            | ErrorType::ConnectProxyFailure
+            | ErrorType::TLSHandshakeFailure
+    )
+}
+
+/// Попадает ли запрос в flight recorder
+pub fn should_record(status: u16, e: Option<&Error>) -> bool {
+    (500..600).contains(&status) || e.is_some_and(is_connect_error)
+}
+
+/// Последние N неудачных запросов в памяти для разбора инцидентов (/_admin/flight_recorder).
+/// Кольцевой буфер: запись занимает слот по атомарному счетчику и блокирует только его,
+/// поэтому параллельные запросы не ждут друг друга
+pub struct FlightRecorder {
+    slots: Box<[Mutex<Option<(u64, FlightRecord)>>]>,
+    next: AtomicU64,
+    scrubber: LogScrubber,
+}
+
+impl FlightRecorder {
+    pub fn new(capacity: usize, scrubber: LogScrubber) -> Self {
+        Self {
+            slots: (0..capacity.max(1)).map(|_| Mutex::new(None)).collect(),
+            next: AtomicU64::new(0),
+            scrubber,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Сохраняет запись, вытесняя самую старую
+    pub fn push(&self, record: FlightRecord) {
+        let sequence = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut slot = self.slots[(sequence % self.slots.len() as u64) as usize].lock().unwrap();
+        // Более поздняя запись в тот же слот не затирается отставшим потоком
+        if slot.as_ref().is_none_or(|(stored, _)| *stored < sequence) {
+            *slot = Some((sequence, record));
+        }
+    }
+
+    /// Записывает завершенный запрос; URI, заголовки и текст ошибки скрываются, как в логах
+    pub fn record(&self, session: &Session, ctx: &RequestContext, status: u16, e: Option<&Error>) {
+        let request = session.req_header();
+        let mut request_headers: BTreeMap<String, String> = BTreeMap::new();
+        for (name, value) in request.headers.iter() {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            let value = self.scrubber.header(name.as_str(), &value).into_owned();
+            request_headers
+                .entry(name.as_str().to_string())
+                .and_modify(|existing| {
+                    existing.push_str(", ");
+                    existing.push_str(&value);
+                })
+                .or_insert(value);
+        }
+        let upstream = ctx.handled_by == HandledBy::Upstream;
+
+        self.push(FlightRecord {
+            timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            request_id: ctx.request_id.clone(),
+            method: request.method.to_string(),
+            uri: self.scrubber.text(&request.uri.to_string()).into_owned(),
+            request_headers,
+            status,
+            upstream: upstream.then(|| ctx.upstream_label().to_string()),
+            backend: ctx.upstream_addr.clone(),
+            retries: ctx.retries,
+            duration_ms: ctx.start_time.elapsed().as_millis() as u64,
+            upstream_ms: ctx.upstream_start.map(|start| start.elapsed().as_millis() as u64),
+            error: e.map(|e| self.scrubber.text(&e.to_string()).into_owned()),
+        });
+    }
+
+    /// Записи от самой новой к самой старой
+    pub fn recent(&self) -> Vec<FlightRecord> {
+        let mut records: Vec<(u64, FlightRecord)> = self
+            .slots
+            .iter()
+            .filter_map(|slot| slot.lock().unwrap().clone())
+            .collect();
+        records.sort_by(|a, b| b.0.cmp(&a.0));
+        records.into_iter().map(|(_, record)| record).collect()
+    }
+
+    /// Удаляет все записи, возвращает их число
+    pub fn clear(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.lock().unwrap().take().is_some()).count()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::json!({ "capacity": self.capacity(), "records": self.recent() }).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LogScrubConfig;
+    use crate::stages::test_session;
+
+    fn recorder(capacity: usize) -> FlightRecorder {
+        FlightRecorder::new(capacity, LogScrubber::new(&LogScrubConfig::default()))
+    }
+
+    #[tokio::test]
+    async fn test_oldest_records_evicted() {
+        let recorder = recorder(3);
+        let session = test_session("GET /api/orders HTTP/1.1\r\nHost: api.example.com\r\n\r\n").await;
+        for i in 0..5 {
+            let mut ctx = RequestContext::new();
+            ctx.request_id = format!("req-{}", i);
+            recorder.record(&session, &ctx, 502, None);
+        }
+
+        let ids: Vec<String> = recorder.recent().into_iter().map(|record| record.request_id).collect();
+        assert_eq!(ids, vec!["req-4", "req-3", "req-2"]);
+
+        assert_eq!(recorder.clear(), 3);
+        assert!(recorder.recent().is_empty());
+        // После очистки буфер заполняется заново
+        recorder.record(&session, &RequestContext::new(), 503, None);
+        assert_eq!(recorder.recent().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_records_are_scrubbed() {
+        let recorder = recorder(10);
+        let session = test_session(
+            "POST /reset?token=s3cr3t&lang=en HTTP/1.1\r\nHost: api.example.com\r\n\
+             Authorization: Bearer eyJhbGciOi\r\nCookie: session=abc\r\nAccept: a\r\nAccept: b\r\n\r\n",
+        )
+        .await;
+        let mut ctx = RequestContext::new();
+        ctx.upstream_name = Some("core_api".to_string());
+        ctx.upstream_addr = Some("10.0.0.7:8080".to_string());
+        let e = *Error::explain(ErrorType::ConnectRefused, "connect to /reset?token=s3cr3t failed");
+        assert!(should_record(0, Some(&e)));
+        recorder.record(&session, &ctx, 502, Some(&e));
+
+        let record = &recorder.recent()[0];
+        assert_eq!(record.uri, "/reset?token=[REDACTED]&lang=en");
+        assert_eq!(record.request_headers["authorization"], "Bearer [REDACTED]");
+        assert_eq!(record.request_headers["cookie"], "[REDACTED]");
+        assert_eq!(record.request_headers["accept"], "a, b");
+        assert_eq!(record.upstream.as_deref(), Some("core_api"));
+        assert_eq!(record.backend.as_deref(), Some("10.0.0.7:8080"));
+        let error = record.error.as_deref().unwrap();
+        assert!(error.contains("token=[REDACTED]") && !error.contains("s3cr3t"), "{}", error);
+
+        let json: serde_json::Value = serde_json::from_str(&recorder.to_json()).unwrap();
+        assert_eq!(json["capacity"], 10);
+        assert_eq!(json["records"][0]["status"], 502);
+    }
+
+    #[test]
+    fn test_recorded_outcomes() {
+        assert!(should_record(500, None));
+        assert!(should_record(504, None));
+        assert!(!should_record(404, None));
+        assert!(!should_record(0, Some(&*Error::new(ErrorType::ReadError))));
+        assert!(should_record(0, Some(&*Error::new(ErrorType::ConnectTimedout))));
+    }
+}
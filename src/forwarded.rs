@@ -0,0 +1,271 @@
+use pingora::http::RequestHeader;
+use std::fmt;
+use std::net::IpAddr;
+
+/// Элемент заголовка Forwarded (RFC 7239): пары `for=...;proto=...;host=...;by=...`.
+/// Имена параметров приводятся к нижнему регистру, значения хранятся без кавычек
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ForwardedElement {
+    pairs: Vec<(String, String)>,
+}
+
+impl ForwardedElement {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.pairs.push((name.to_ascii_lowercase(), value.into()));
+        self
+    }
+
+    /// Значение параметра; имя без учета регистра
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Элемент, который прокси добавляет для клиента запроса
+    pub fn for_client(client: Option<IpAddr>, proto: &str, host: Option<&str>) -> Self {
+        let node = client.map(node).unwrap_or_else(|| "unknown".to_string());
+        let element = Self::new().with("for", node).with("proto", proto);
+        match host {
+            Some(host) => element.with("host", host),
+            None => element,
+        }
+    }
+}
+
+impl fmt::Display for ForwardedElement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, value)) in self.pairs.iter().enumerate() {
+            if i > 0 {
+                f.write_str(";")?;
+            }
+            write!(f, "{}={}", name, quote(value))?;
+        }
+        Ok(())
+    }
+}
+
+/// tchar из RFC 7230
+fn is_tchar(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+/// Значение как token или, если в нем есть другие символы, как quoted-string
+fn quote(value: &str) -> String {
+    if !value.is_empty() && value.chars().all(is_tchar) {
+        return value.to_string();
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Узел для `for`/`by`: IPv4 как есть, IPv6 в квадратных скобках (в заголовке - в кавычках)
+pub fn node(ip: IpAddr) -> String {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("[{}]", ip),
+    }
+}
+
+/// IP адрес узла `for`; порт, скрытые (`_hidden`) и `unknown` узлы - None
+pub fn node_ip(node: &str) -> Option<IpAddr> {
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    let addr = node.split_once(':').map_or(node, |(addr, _)| addr);
+    addr.parse().ok()
+}
+
+/// Допустимый узел `for`/`by`: IP адрес (IPv6 в скобках, с портом или без), `unknown`
+/// или скрытый идентификатор `_[A-Za-z0-9._-]+`
+pub fn is_valid_node(node: &str) -> bool {
+    if node == "unknown" || node_ip(node).is_some() {
+        return true;
+    }
+    node.strip_prefix('_')
+        .is_some_and(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c)))
+}
+
+/// Разбирает значение заголовка Forwarded; несколько строк заголовка передаются через ", "
+pub fn parse(value: &str) -> Result<Vec<ForwardedElement>, String> {
+    let invalid = |reason: &str| format!("invalid Forwarded header ({}): {}", reason, value);
+    let mut elements = Vec::new();
+    let mut element = ForwardedElement::new();
+    let mut chars = value.chars().peekable();
+    loop {
+        while chars.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+        match chars.peek() {
+            None => break,
+            // Пустые пары и элементы допускаются грамматикой (`;;`, `,,`)
+            Some(';') => {
+                chars.next();
+                continue;
+            }
+            Some(',') => {
+                chars.next();
+                elements.push(std::mem::take(&mut element));
+                continue;
+            }
+            Some(_) => {}
+        }
+
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(|c| is_tchar(*c)) {
+            name.push(c);
+        }
+        if name.is_empty() || chars.next() != Some('=') {
+            return Err(invalid("expected name=value"));
+        }
+
+        let mut pair_value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => pair_value.push(chars.next().ok_or_else(|| invalid("unterminated quoted string"))?),
+                    Some(c) => pair_value.push(c),
+                    None => return Err(invalid("unterminated quoted string")),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| is_tchar(*c)) {
+                pair_value.push(c);
+            }
+            if pair_value.is_empty() {
+                return Err(invalid("empty value"));
+            }
+        }
+        element = element.with(&name, pair_value);
+
+        while chars.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+        if !matches!(chars.peek(), None | Some(';') | Some(',')) {
+            return Err(invalid("unexpected character"));
+        }
+    }
+    elements.push(element);
+    elements.retain(|element| !element.pairs.is_empty());
+    Ok(elements)
+}
+
+/// Элементы всех заголовков Forwarded запроса по порядку; некорректное значение - пустой список
+pub fn from_request(request: &RequestHeader) -> Vec<ForwardedElement> {
+    let values: Vec<&str> = request
+        .headers
+        .get_all("forwarded")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    if values.is_empty() {
+        return Vec::new();
+    }
+    parse(&values.join(", ")).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc_examples() {
+        let elements = parse("for=\"_gazonk\"").unwrap();
+        assert_eq!(elements[0].get("for"), Some("_gazonk"));
+
+        let elements = parse("For=\"[2001:db8:cafe::17]:4711\"").unwrap();
+        assert_eq!(elements[0].get("for"), Some("[2001:db8:cafe::17]:4711"));
+        assert_eq!(node_ip(elements[0].get("for").unwrap()), "2001:db8:cafe::17".parse().ok());
+
+        let elements = parse("for=192.0.2.60;proto=http;by=203.0.113.43").unwrap();
+        assert_eq!(elements.len(), 1);
+        assert_eq!(elements[0].get("proto"), Some("http"));
+        assert_eq!(elements[0].get("BY"), Some("203.0.113.43"));
+
+        let elements = parse("for=192.0.2.43, for=198.51.100.17").unwrap();
+        let nodes: Vec<&str> = elements.iter().filter_map(|e| e.get("for")).collect();
+        assert_eq!(nodes, vec!["192.0.2.43", "198.51.100.17"]);
+
+        // Скрытые и неизвестные узлы не дают IP адреса
+        let elements = parse("for=_hidden, for=_SEVKISEK, for=unknown").unwrap();
+        assert_eq!(elements.len(), 3);
+        assert!(elements.iter().all(|e| node_ip(e.get("for").unwrap()).is_none()));
+    }
+
+    #[test]
+    fn test_quoted_separators_and_errors() {
+        let elements = parse("for=\"a,b;c\";host=\"ex\\\"ample\" , ;for=\"192.0.2.1:8080\"").unwrap();
+        assert_eq!(elements.len(), 2);
+        assert_eq!(elements[0].get("for"), Some("a,b;c"));
+        assert_eq!(elements[0].get("host"), Some("ex\"ample"));
+        assert_eq!(node_ip(elements[1].get("for").unwrap()), "192.0.2.1".parse().ok());
+
+        assert!(parse("for=\"[2001:db8::1]").is_err());
+        assert!(parse("for").is_err());
+        assert!(parse("for=[2001:db8::1]").is_err());
+        // Порт без кавычек - не token
+        assert!(parse("for=192.0.2.1:8080").is_err());
+        assert!(parse("for=1.2.3.4 proto=http").is_err());
+        assert!(parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_serialize_quotes_ipv6() {
+        let v4 = ForwardedElement::for_client("192.0.2.60".parse().ok(), "https", Some("api.example.com"));
+        assert_eq!(v4.to_string(), "for=192.0.2.60;proto=https;host=api.example.com");
+
+        let v6 = ForwardedElement::for_client("2001:db8:cafe::17".parse().ok(), "http", Some("api.example.com:8443"));
+        assert_eq!(v6.to_string(), "for=\"[2001:db8:cafe::17]\";proto=http;host=\"api.example.com:8443\"");
+
+        let mapped = ForwardedElement::for_client("::ffff:192.0.2.1".parse().ok(), "http", None);
+        assert_eq!(mapped.to_string(), "for=192.0.2.1;proto=http");
+        assert_eq!(ForwardedElement::for_client(None, "http", None).to_string(), "for=unknown;proto=http");
+
+        let mut request = RequestHeader::build("GET", b"/", None).unwrap();
+        request.append_header("Forwarded", "for=192.0.2.43").unwrap();
+        request.append_header("Forwarded", "for=\"[2001:db8:cafe::17]\";proto=https").unwrap();
+        assert_eq!(from_request(&request).len(), 2);
+        request.append_header("Forwarded", "for=\"unterminated").unwrap();
+        assert!(from_request(&request).is_empty());
+
+        // Сериализованное значение разбирается обратно без потерь
+        let header = format!("{}, {}", v4, v6);
+        assert_eq!(parse(&header).unwrap(), vec![v4, v6]);
+    }
+
+    #[test]
+    fn test_extends_parsed_chain() {
+        let mut chain = parse("for=192.0.2.43;proto=https;by=_cdn, for=\"[2001:db8:cafe::17]\"").unwrap();
+        assert_eq!(chain.len(), 2);
+
+        let hop = ForwardedElement::for_client("198.51.100.7".parse().ok(), "https", Some("shop.example.com"))
+            .with("by", "_edge");
+        chain.push(hop);
+        let value: Vec<String> = chain.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            value.join(", "),
+            "for=192.0.2.43;proto=https;by=_cdn, for=\"[2001:db8:cafe::17]\", \
+             for=198.51.100.7;proto=https;host=shop.example.com;by=_edge"
+        );
+    }
+
+    #[test]
+    fn test_valid_nodes() {
+        for node in ["unknown", "_edge", "_edge-1.dc", "192.0.2.1", "[2001:db8::1]", "[2001:db8::1]:443"] {
+            assert!(is_valid_node(node), "{}", node);
+        }
+        for node in ["", "_", "edge", "_edge 1", "2001:db8::1x"] {
+            assert!(!is_valid_node(node), "{}", node);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use pingora::http::RequestHeader;
+
+use crate::config::GrpcWebConfig;
+
+/// Является ли запрос gRPC-Web запросом: определяется по Content-Type
+/// (и префиксу пути, если он задан), а не по хосту или подстрокам URI
+pub fn is_grpc_web_request(request: &RequestHeader, config: &GrpcWebConfig) -> bool {
+    if !config.enabled {
+        return false;
+    }
+
+    let content_type = request
+        .headers
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim_start().to_ascii_lowercase());
+    let Some(content_type) = content_type else {
+        return false;
+    };
+
+    let path = request.uri.path();
+    config
+        .content_types
+        .iter()
+        .any(|prefix| content_type.starts_with(&prefix.to_ascii_lowercase()))
+        && (config.paths.is_empty() || config.paths.iter().any(|prefix| path.starts_with(prefix.as_str())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(path: &str, content_type: Option<&str>) -> RequestHeader {
+        let mut request = RequestHeader::build("POST", path.as_bytes(), None).unwrap();
+        request.insert_header("Host", "auth.ad-quest.ru").unwrap();
+        if let Some(content_type) = content_type {
+            request.insert_header("Content-Type", content_type).unwrap();
+        }
+        request
+    }
+
+    #[test]
+    fn test_detection_by_content_type() {
+        let config = GrpcWebConfig::default();
+
+        assert!(is_grpc_web_request(&request("/api/Login", Some("application/grpc-web+proto")), &config));
+        assert!(is_grpc_web_request(&request("/x", Some("Application/GRPC-Web-Text")), &config));
+
+        // Подстроки URI, по которым раньше угадывался gRPC-Web, больше не важны
+        let zitadel_path = "/zitadel.admin.v1.AdminService/Healthz";
+        assert!(!is_grpc_web_request(&request(zitadel_path, Some("application/json")), &config));
+        assert!(!is_grpc_web_request(&request(zitadel_path, None), &config));
+        assert!(!is_grpc_web_request(&request("/api.v2.Users", Some("text/plain")), &config));
+    }
+
+    #[test]
+    fn test_configurable_predicate() {
+        let config = GrpcWebConfig {
+            enabled: true,
+            content_types: vec!["application/grpc-web".to_string(), "application/grpc".to_string()],
+            paths: vec!["/zitadel.".to_string()],
+        };
+        assert!(is_grpc_web_request(&request("/zitadel.auth.v1.AuthService/GetMyUser", Some("application/grpc")), &config));
+        assert!(!is_grpc_web_request(&request("/other.Service/Call", Some("application/grpc")), &config));
+
+        let disabled = GrpcWebConfig { enabled: false, ..GrpcWebConfig::default() };
+        assert!(!is_grpc_web_request(&request("/x", Some("application/grpc-web")), &disabled));
+    }
+}
@@ -0,0 +1,117 @@
+use pingora_load_balancing::selection::RoundRobin;
+use pingora_load_balancing::{Backend, LoadBalancer};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Оценка бэкенда для ключа (weighted rendezvous hashing): у каждого ключа свой порядок
+/// бэкендов, поэтому при удалении бэкенда на другие переезжают только его ключи,
+/// а доля ключей бэкенда пропорциональна весу
+fn score(key: &str, backend: &Backend) -> f64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    backend.addr.to_string().hash(&mut hasher);
+    // Равномерное значение в (0, 1)
+    let unit = ((hasher.finish() >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+    -(backend.weight.max(1) as f64) / unit.ln()
+}
+
+/// Бэкенд с наибольшей оценкой для ключа
+pub fn choose<'a>(candidates: &'a [Backend], key: &str) -> Option<&'a Backend> {
+    candidates
+        .iter()
+        .map(|backend| (score(key, backend), backend))
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, backend)| backend)
+}
+
+/// Выбирает бэкенд upstream с `hash` по значению ключа запроса. Запросы с одинаковым ключом
+/// попадают на один бэкенд, пока он доступен; при повторе - на следующий для ключа непробованный
+pub fn select_by_hash<F>(lb: &LoadBalancer<RoundRobin>, key: &str, tried: &[String], accept: F) -> Option<Backend>
+where
+    F: Fn(&Backend, bool) -> bool,
+{
+    let backends = lb.backends();
+    let eligible: Vec<Backend> = backends
+        .get_backend()
+        .iter()
+        .filter(|backend| accept(backend, backends.ready(backend)))
+        .cloned()
+        .collect();
+    let untried: Vec<Backend> = eligible
+        .iter()
+        .filter(|backend| !tried.contains(&backend.addr.to_string()))
+        .cloned()
+        .collect();
+    let candidates = if untried.is_empty() { eligible } else { untried };
+    choose(&candidates, key).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn backends(weights: &[usize]) -> Vec<Backend> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| {
+                let mut backend = Backend::new(&format!("10.0.0.{}:8080", i + 1)).unwrap();
+                backend.weight = *weight;
+                backend
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_same_key_same_backend() {
+        let lb = LoadBalancer::<RoundRobin>::try_from_iter(["10.0.0.1:8080", "10.0.0.2:8080", "10.0.0.3:8080"]).unwrap();
+        let mut chosen = HashMap::new();
+        for i in 0..100 {
+            let key = format!("/api/campaigns/{}", i % 10);
+            let backend = select_by_hash(&lb, &key, &[], |_, healthy| healthy).unwrap();
+            assert_eq!(*chosen.entry(key).or_insert_with(|| backend.addr.clone()), backend.addr);
+        }
+        // Ключи распределяются по нескольким бэкендам
+        let used: std::collections::HashSet<_> = chosen.values().collect();
+        assert!(used.len() > 1, "{:?}", chosen);
+
+        // Повтор идет на другой бэкенд, затем снова на исходный для ключа
+        let first = select_by_hash(&lb, "/api/campaigns/1", &[], |_, healthy| healthy).unwrap();
+        let tried = vec![first.addr.to_string()];
+        let retry = select_by_hash(&lb, "/api/campaigns/1", &tried, |_, healthy| healthy).unwrap();
+        assert_ne!(retry.addr, first.addr);
+        assert_eq!(select_by_hash(&lb, "/api/campaigns/1", &[], |_, healthy| healthy).unwrap().addr, first.addr);
+    }
+
+    #[test]
+    fn test_removed_backend_moves_only_its_keys() {
+        let all = backends(&[1, 1, 1, 1]);
+        let without_last = &all[..3];
+        let keys: Vec<String> = (0..1000).map(|i| format!("user-{}", i)).collect();
+
+        let mut moved = 0;
+        for key in &keys {
+            let before = choose(&all, key).unwrap();
+            let after = choose(without_last, key).unwrap();
+            if before.addr != all[3].addr {
+                assert_eq!(before.addr, after.addr, "key {} moved", key);
+            } else {
+                moved += 1;
+            }
+        }
+        // Около четверти ключей было на удаленном бэкенде
+        assert!((150..350).contains(&moved), "{} keys moved", moved);
+    }
+
+    #[test]
+    fn test_weights_share_keys() {
+        let candidates = backends(&[3, 1]);
+        let heavy = (0..4000)
+            .filter(|i| choose(&candidates, &format!("key-{}", i)).unwrap().weight == 3)
+            .count();
+        // Ожидается 3/4 ключей
+        assert!((2800..3200).contains(&heavy), "heavy backend got {} keys", heavy);
+        assert!(choose(&[], "key").is_none());
+    }
+}
@@ -0,0 +1,432 @@
+use async_trait::async_trait;
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+use pingora_core::server::ShutdownWatch;
+use pingora_core::services::background::BackgroundService;
+use pingora_load_balancing::health_check::HealthObserve;
+use pingora_load_balancing::Backend;
+
+use crate::config::{HealthEventsConfig, HealthWebhookConfig, HttpClientConfig};
+use crate::http_client::HttpClient;
+use crate::logging::LoggingMiddleware;
+use crate::metrics::{HEALTH_EVENTS, HEALTH_WEBHOOK_DELIVERIES};
+
+/// Состояние бэкенда с точки зрения прокси
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendState {
+    Up,
+    Down,
+}
+
+/// Смена состояния бэкенда
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthEvent {
+    pub upstream: String,
+    /// Адрес бэкенда; None - событие относится ко всему upstream (circuit breaker)
+    pub backend: Option<String>,
+    pub old_state: BackendState,
+    pub new_state: BackendState,
+    /// Источник события: health_check или circuit_breaker
+    pub reason: String,
+    /// Время события, RFC 3339
+    pub timestamp: String,
+}
+
+impl HealthEvent {
+    pub fn new(upstream: &str, backend: Option<String>, healthy: bool, reason: &str) -> Self {
+        let (old_state, new_state) = if healthy {
+            (BackendState::Down, BackendState::Up)
+        } else {
+            (BackendState::Up, BackendState::Down)
+        };
+        Self {
+            upstream: upstream.to_string(),
+            backend,
+            old_state,
+            new_state,
+            reason: reason.to_string(),
+            timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        }
+    }
+
+    /// Тип события для метрики health_events_total
+    pub fn event_type(&self) -> &'static str {
+        match self.new_state {
+            BackendState::Up => "backend_up",
+            BackendState::Down => "backend_down",
+        }
+    }
+}
+
+/// Журнал событий смены состояния бэкендов: error log, последние N событий в памяти
+/// и очередь на отправку в webhook
+pub struct HealthEvents {
+    capacity: usize,
+    recent: Mutex<VecDeque<HealthEvent>>,
+    logging: Option<Arc<LoggingMiddleware>>,
+    webhook: Option<mpsc::Sender<HealthEvent>>,
+}
+
+impl HealthEvents {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            recent: Mutex::new(VecDeque::with_capacity(capacity)),
+            logging: None,
+            webhook: None,
+        }
+    }
+
+    /// Создает журнал по конфигурации; при настроенном webhook возвращает сервис отправки,
+    /// который нужно запустить в фоне
+    pub fn from_config(
+        config: &HealthEventsConfig,
+        http_client: &HttpClientConfig,
+        logging: Arc<LoggingMiddleware>,
+    ) -> (Arc<Self>, Option<WebhookService>) {
+        let mut events = Self::new(config.capacity);
+        events.logging = Some(logging);
+        let service = config.webhook.as_ref().map(|webhook| {
+            // Очередь ограничена: при недоступном webhook новые события не копятся в памяти
+            let (sender, receiver) = mpsc::channel(config.capacity);
+            events.webhook = Some(sender);
+            WebhookService::new(WebhookNotifier::new(webhook, http_client), receiver)
+        });
+        (Arc::new(events), service)
+    }
+
+    /// Регистрирует событие. Не ждет отправки в webhook
+    pub async fn record(&self, event: HealthEvent) {
+        HEALTH_EVENTS.with_label_values(&[event.event_type()]).inc();
+
+        let backend = event.backend.as_deref().unwrap_or("*");
+        let message = format!(
+            "Backend {} of upstream '{}' is {} ({})",
+            backend,
+            event.upstream,
+            if event.new_state == BackendState::Up { "up" } else { "down" },
+            event.reason
+        );
+        match event.new_state {
+            BackendState::Up => info!("{}", message),
+            BackendState::Down => warn!("{}", message),
+        }
+
+        if let Some(logging) = &self.logging {
+            let level = if event.new_state == BackendState::Up { "INFO" } else { "WARN" };
+            let mut fields = serde_json::to_value(&event).unwrap_or_default();
+            fields["event_type"] = event.event_type().into();
+            logging.error_logger().log_event(level, "health_event", &message, fields).await;
+        }
+
+        if let Some(webhook) = &self.webhook {
+            if webhook.try_send(event.clone()).is_err() {
+                HEALTH_WEBHOOK_DELIVERIES.with_label_values(&["dropped"]).inc();
+                debug!("Health event webhook queue is full, event dropped");
+            }
+        }
+
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == self.capacity {
+            recent.pop_front();
+        }
+        recent.push_back(event);
+    }
+
+    /// Последние события, от старых к новым
+    pub fn recent(&self) -> Vec<HealthEvent> {
+        self.recent.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Тело ответа GET /_admin/health/events
+    pub fn to_json(&self) -> String {
+        serde_json::json!({ "events": self.recent() }).to_string()
+    }
+}
+
+/// Подписка на результаты активных health check одного upstream
+pub struct HealthObserver {
+    upstream: String,
+    events: Arc<HealthEvents>,
+}
+
+impl HealthObserver {
+    pub fn new(upstream: &str, events: Arc<HealthEvents>) -> Self {
+        Self {
+            upstream: upstream.to_string(),
+            events,
+        }
+    }
+}
+
+#[async_trait]
+impl HealthObserve for HealthObserver {
+    async fn observe(&self, target: &Backend, healthy: bool) {
+        let event = HealthEvent::new(&self.upstream, Some(target.addr.to_string()), healthy, "health_check");
+        self.events.record(event).await;
+    }
+}
+
+/// Результат отправки события в webhook
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delivery {
+    Delivered,
+    Failed,
+    /// Webhook отключен после серии ошибок, отправка не выполнялась
+    Skipped,
+}
+
+impl Delivery {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Delivery::Delivered => "delivered",
+            Delivery::Failed => "failed",
+            Delivery::Skipped => "skipped",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct WebhookCircuit {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Отправляет события POST запросом с JSON телом: таймаут на попытку, повторы http_client
+/// и отключение webhook на `cooldown` после `failure_threshold` неудач подряд
+pub struct WebhookNotifier {
+    url: String,
+    client: HttpClient,
+    failure_threshold: u32,
+    cooldown: Duration,
+    circuit: Mutex<WebhookCircuit>,
+}
+
+impl WebhookNotifier {
+    pub fn new(config: &HealthWebhookConfig, http_client: &HttpClientConfig) -> Self {
+        let client = HttpClient::from_config(http_client).with_timeout(Duration::from_secs(config.timeout));
+        Self {
+            url: config.url.clone(),
+            client,
+            failure_threshold: config.failure_threshold,
+            cooldown: Duration::from_secs(config.cooldown),
+            circuit: Mutex::new(WebhookCircuit::default()),
+        }
+    }
+
+    pub async fn deliver(&self, event: &HealthEvent) -> Delivery {
+        if self.circuit_open() {
+            return Delivery::Skipped;
+        }
+        match self.client.post_json(&self.url, event).await {
+            Ok(()) => {
+                *self.circuit.lock().unwrap() = WebhookCircuit::default();
+                return Delivery::Delivered;
+            }
+            Err(e) => warn!("Health event webhook {} failed: {}", self.url, e),
+        }
+
+        let mut circuit = self.circuit.lock().unwrap();
+        circuit.failures += 1;
+        if circuit.failures >= self.failure_threshold {
+            warn!("Health event webhook {} disabled for {:?} after {} failed deliveries",
+                  self.url, self.cooldown, circuit.failures);
+            circuit.failures = 0;
+            circuit.open_until = Some(Instant::now() + self.cooldown);
+        }
+        Delivery::Failed
+    }
+
+    fn circuit_open(&self) -> bool {
+        let mut circuit = self.circuit.lock().unwrap();
+        match circuit.open_until {
+            Some(until) if Instant::now() < until => true,
+            Some(_) => {
+                circuit.open_until = None;
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+/// Фоновая отправка событий из очереди в webhook
+pub struct WebhookService {
+    notifier: WebhookNotifier,
+    queue: tokio::sync::Mutex<mpsc::Receiver<HealthEvent>>,
+}
+
+impl WebhookService {
+    pub fn new(notifier: WebhookNotifier, queue: mpsc::Receiver<HealthEvent>) -> Self {
+        Self {
+            notifier,
+            queue: tokio::sync::Mutex::new(queue),
+        }
+    }
+}
+
+#[async_trait]
+impl BackgroundService for WebhookService {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        let mut queue = self.queue.lock().await;
+        loop {
+            tokio::select! {
+                event = queue.recv() => {
+                    let Some(event) = event else { break };
+                    let delivery = self.notifier.deliver(&event).await;
+                    HEALTH_WEBHOOK_DELIVERIES.with_label_values(&[delivery.as_str()]).inc();
+                }
+                _ = shutdown.changed() => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Mock webhook: отвечает статусами по очереди (последний повторяется) и сохраняет тела запросов
+    async fn mock_webhook(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hooks/health", listener.local_addr().unwrap());
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let received = bodies.clone();
+        tokio::spawn(async move {
+            let mut served = 0;
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                loop {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                            .unwrap_or(0);
+                        if body.len() >= length {
+                            received.lock().unwrap().push(body.to_string());
+                            break;
+                        }
+                    }
+                    if n == 0 {
+                        break;
+                    }
+                }
+                let status = statuses[served.min(statuses.len() - 1)];
+                served += 1;
+                let response = format!("HTTP/1.1 {} Test\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, bodies)
+    }
+
+    fn webhook_config(url: &str, failure_threshold: u32) -> HealthWebhookConfig {
+        HealthWebhookConfig {
+            url: url.to_string(),
+            timeout: 2,
+            failure_threshold,
+            cooldown: 60,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transitions_recorded() {
+        let events = Arc::new(HealthEvents::new(2));
+        let observer = HealthObserver::new("core_api", events.clone());
+        let backend = Backend::new("10.0.0.1:8080").unwrap();
+        let down_before = HEALTH_EVENTS.with_label_values(&["backend_down"]).get();
+
+        observer.observe(&backend, false).await;
+        observer.observe(&backend, true).await;
+        events.record(HealthEvent::new("billing", None, false, "circuit_breaker")).await;
+
+        assert!(HEALTH_EVENTS.with_label_values(&["backend_down"]).get() >= down_before + 2);
+        // Хранятся только последние capacity событий
+        let recent = events.recent();
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].backend.as_deref(), Some("10.0.0.1:8080"));
+        assert_eq!(recent[0].old_state, BackendState::Down);
+        assert_eq!(recent[0].new_state, BackendState::Up);
+        assert_eq!(recent[0].reason, "health_check");
+        assert_eq!(recent[1].upstream, "billing");
+
+        let json: serde_json::Value = serde_json::from_str(&events.to_json()).unwrap();
+        assert_eq!(json["events"][0]["new_state"], "up");
+        assert_eq!(json["events"][1]["backend"], serde_json::Value::Null);
+        assert_eq!(json["events"][1]["reason"], "circuit_breaker");
+    }
+
+    #[tokio::test]
+    async fn test_webhook_retry_once() {
+        let (url, bodies) = mock_webhook(vec![500, 200]).await;
+        let notifier = WebhookNotifier::new(&webhook_config(&url, 3), &HttpClientConfig::default());
+        let event = HealthEvent::new("core_api", Some("10.0.0.2:8080".to_string()), false, "health_check");
+
+        assert_eq!(notifier.deliver(&event).await, Delivery::Delivered);
+        let bodies = bodies.lock().unwrap();
+        assert_eq!(bodies.len(), 2);
+        let payload: serde_json::Value = serde_json::from_str(&bodies[1]).unwrap();
+        assert_eq!(payload["upstream"], "core_api");
+        assert_eq!(payload["backend"], "10.0.0.2:8080");
+        assert_eq!(payload["old_state"], "up");
+        assert_eq!(payload["new_state"], "down");
+    }
+
+    #[tokio::test]
+    async fn test_dead_webhook_circuit() {
+        let (url, bodies) = mock_webhook(vec![503]).await;
+        let notifier = WebhookNotifier::new(&webhook_config(&url, 1), &HttpClientConfig::default());
+        let event = HealthEvent::new("core_api", Some("10.0.0.3:8080".to_string()), false, "health_check");
+
+        assert_eq!(notifier.deliver(&event).await, Delivery::Failed);
+        assert_eq!(bodies.lock().unwrap().len(), 2);
+        // После failure_threshold неудач webhook не вызывается до истечения cooldown
+        assert_eq!(notifier.deliver(&event).await, Delivery::Skipped);
+        assert_eq!(bodies.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_error_log_and_full_queue() {
+        let dir = tempfile::tempdir().unwrap();
+        let error_log = dir.path().join("error.log");
+        let mut logging_config = crate::config::Config::default().logging;
+        logging_config.access_log.enabled = false;
+        logging_config.error_log.enabled = true;
+        logging_config.error_log.format = "json".to_string();
+        logging_config.error_log.path = error_log.to_string_lossy().to_string();
+        let logging = Arc::new(LoggingMiddleware::new(logging_config));
+        let config = HealthEventsConfig {
+            capacity: 1,
+            webhook: Some(webhook_config("http://127.0.0.1:9/", 1)),
+        };
+        // Сервис отправки не запущен: очередь заполняется, record не ждет
+        let (events, service) = HealthEvents::from_config(&config, &HttpClientConfig::default(), logging);
+        assert!(service.is_some());
+        let dropped = HEALTH_WEBHOOK_DELIVERIES.with_label_values(&["dropped"]).get();
+        for healthy in [false, true, false] {
+            events.record(HealthEvent::new("core_api", Some("10.0.0.4:8080".to_string()), healthy, "health_check")).await;
+        }
+        assert!(HEALTH_WEBHOOK_DELIVERIES.with_label_values(&["dropped"]).get() >= dropped + 2);
+        assert_eq!(events.recent().len(), 1);
+
+        let log = std::fs::read_to_string(&error_log).unwrap();
+        let lines: Vec<serde_json::Value> = log.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["level"], "WARN");
+        assert_eq!(lines[0]["fields"]["event_type"], "backend_down");
+        assert_eq!(lines[1]["fields"]["new_state"], "up");
+        assert_eq!(lines[2]["fields"]["backend"], "10.0.0.4:8080");
+    }
+}
@@ -0,0 +1,180 @@
+use http::{HeaderMap, HeaderValue};
+use pingora::http::{RequestHeader, ResponseHeader};
+use pingora::prelude::*;
+
+/// Заголовки, относящиеся только к одному соединению (RFC 7230 6.1, RFC 2616 13.5.1).
+/// Transfer-Encoding не удаляется: по нему Pingora заново кодирует тело на каждом соединении
+const HOP_BY_HOP: [&str; 7] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "upgrade",
+];
+
+/// Заголовки кадрирования сообщения не удаляются, даже если перечислены в Connection
+const PROTECTED: [&str; 3] = ["host", "content-length", "transfer-encoding"];
+
+/// Имена из Connection в нижнем регистре
+fn connection_tokens(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all("connection")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|token| token.trim().to_ascii_lowercase())
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+fn lists(value: Option<&HeaderValue>, token: &str) -> bool {
+    value
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|item| item.trim().eq_ignore_ascii_case(token)))
+}
+
+/// Имена заголовков для удаления: стандартные hop-by-hop и перечисленные в Connection
+fn hop_by_hop_names(headers: &HeaderMap) -> Vec<String> {
+    let mut names: Vec<String> = HOP_BY_HOP.iter().map(|name| name.to_string()).collect();
+    for token in connection_tokens(headers) {
+        if !PROTECTED.contains(&token.as_str()) && !names.contains(&token) {
+            names.push(token);
+        }
+    }
+    names
+}
+
+/// Протокол запроса на смену протокола (Upgrade и токен upgrade в Connection).
+/// h2c через Upgrade не поддерживается: HTTP/2 без TLS принимается только с prior knowledge
+fn requested_upgrade(headers: &HeaderMap) -> Option<HeaderValue> {
+    if !connection_tokens(headers).iter().any(|token| token == "upgrade") {
+        return None;
+    }
+    let upgrade = headers.get("upgrade")?;
+    (!lists(Some(upgrade), "h2c")).then(|| upgrade.clone())
+}
+
+/// Убирает hop-by-hop заголовки клиента из запроса к upstream. Upgrade (WebSocket)
+/// передается с `Connection: upgrade`, `TE: trailers` сохраняется (нужен gRPC)
+pub fn strip_request(req: &mut RequestHeader) -> Result<()> {
+    let upgrade = requested_upgrade(&req.headers);
+    let te_trailers = lists(req.headers.get("te"), "trailers");
+    for name in hop_by_hop_names(&req.headers) {
+        req.remove_header(name.as_str());
+    }
+    if let Some(upgrade) = upgrade {
+        req.insert_header("Upgrade", upgrade)?;
+        req.insert_header("Connection", "upgrade")?;
+    }
+    if te_trailers {
+        req.insert_header("TE", "trailers")?;
+    }
+    Ok(())
+}
+
+/// Убирает hop-by-hop заголовки upstream из ответа клиенту; 101 Switching Protocols
+/// сохраняет Upgrade с `Connection: upgrade`
+pub fn strip_response(resp: &mut ResponseHeader) -> Result<()> {
+    let upgrade = (resp.status.as_u16() == 101).then(|| resp.headers.get("upgrade").cloned()).flatten();
+    for name in hop_by_hop_names(&resp.headers) {
+        resp.remove_header(name.as_str());
+    }
+    if let Some(upgrade) = upgrade {
+        resp.insert_header("Upgrade", upgrade)?;
+        resp.insert_header("Connection", "upgrade")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &[(&str, &str)]) -> RequestHeader {
+        let mut req = RequestHeader::build("GET", b"/api/events", None).unwrap();
+        for (name, value) in headers {
+            req.append_header(name.to_string(), *value).unwrap();
+        }
+        req
+    }
+
+    #[test]
+    fn test_hop_by_hop_request_headers_stripped() {
+        let mut req = request(&[
+            ("Host", "api.example.com"),
+            ("Connection", "keep-alive, X-Debug-Token"),
+            ("Connection", "Content-Length"),
+            ("Keep-Alive", "timeout=5"),
+            ("Proxy-Connection", "keep-alive"),
+            ("Proxy-Authorization", "Basic dXNlcjpwYXNz"),
+            ("X-Debug-Token", "abc"),
+            ("TE", "gzip, trailers"),
+            ("Content-Length", "0"),
+            ("Authorization", "Bearer token"),
+            ("Accept", "application/json"),
+            ("Upgrade", "websocket"),
+        ]);
+        strip_request(&mut req).unwrap();
+
+        for name in ["connection", "keep-alive", "proxy-connection", "proxy-authorization", "x-debug-token", "upgrade"] {
+            assert!(req.headers.get(name).is_none(), "{} must be stripped", name);
+        }
+        // Без upgrade в Connection смены протокола нет; TE сужается до trailers
+        assert_eq!(req.headers.get("te").unwrap(), "trailers");
+        // Сквозные заголовки и кадрирование не трогаются
+        assert_eq!(req.headers.get("host").unwrap(), "api.example.com");
+        assert_eq!(req.headers.get("content-length").unwrap(), "0");
+        assert_eq!(req.headers.get("authorization").unwrap(), "Bearer token");
+        assert_eq!(req.headers.get("accept").unwrap(), "application/json");
+    }
+
+    #[test]
+    fn test_websocket_upgrade_preserved() {
+        let mut req = request(&[
+            ("Connection", "keep-alive, Upgrade"),
+            ("Upgrade", "websocket"),
+            ("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="),
+            ("Sec-WebSocket-Version", "13"),
+        ]);
+        strip_request(&mut req).unwrap();
+        assert_eq!(req.headers.get("upgrade").unwrap(), "websocket");
+        assert_eq!(req.headers.get("connection").unwrap(), "upgrade");
+        assert_eq!(req.headers.get("sec-websocket-key").unwrap(), "dGhlIHNhbXBsZSBub25jZQ==");
+
+        // h2c Upgrade не передается
+        let mut req = request(&[("Connection", "Upgrade, HTTP2-Settings"), ("Upgrade", "h2c"), ("HTTP2-Settings", "AAMAAABkAAQAAP__")]);
+        strip_request(&mut req).unwrap();
+        assert!(req.headers.get("upgrade").is_none());
+        assert!(req.headers.get("http2-settings").is_none());
+
+        let mut switching = ResponseHeader::build(101, None).unwrap();
+        switching.insert_header("Connection", "Upgrade").unwrap();
+        switching.insert_header("Upgrade", "websocket").unwrap();
+        switching.insert_header("Sec-WebSocket-Accept", "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=").unwrap();
+        strip_response(&mut switching).unwrap();
+        assert_eq!(switching.headers.get("upgrade").unwrap(), "websocket");
+        assert_eq!(switching.headers.get("connection").unwrap(), "upgrade");
+        assert!(switching.headers.get("sec-websocket-accept").is_some());
+    }
+
+    #[test]
+    fn test_hop_by_hop_response_headers_stripped() {
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("Connection", "close, X-Backend-Node").unwrap();
+        resp.insert_header("Keep-Alive", "timeout=5, max=100").unwrap();
+        resp.insert_header("X-Backend-Node", "app-3").unwrap();
+        resp.insert_header("Upgrade", "h2").unwrap();
+        resp.insert_header("Proxy-Authenticate", "Basic realm=\"upstream\"").unwrap();
+        resp.insert_header("Transfer-Encoding", "chunked").unwrap();
+        resp.insert_header("Cache-Control", "max-age=60").unwrap();
+        strip_response(&mut resp).unwrap();
+
+        for name in ["connection", "keep-alive", "x-backend-node", "upgrade", "proxy-authenticate"] {
+            assert!(resp.headers.get(name).is_none(), "{} must be stripped", name);
+        }
+        assert_eq!(resp.headers.get("transfer-encoding").unwrap(), "chunked");
+        assert_eq!(resp.headers.get("cache-control").unwrap(), "max-age=60");
+    }
+}
@@ -0,0 +1,115 @@
+use http::Version;
+use pingora::http::{RequestHeader, ResponseHeader};
+use pingora::prelude::*;
+
+use crate::config::Config;
+
+/// Запрос клиента HTTP/1.0 (или HTTP/0.9)
+pub fn is_http10(req: &RequestHeader) -> bool {
+    req.version < Version::HTTP_11
+}
+
+fn connection_lists(req: &RequestHeader, token: &str) -> bool {
+    req.headers
+        .get_all("connection")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|item| item.trim().eq_ignore_ascii_case(token))
+}
+
+/// Готов ли клиент держать соединение после ответа: в HTTP/1.0 только с явным
+/// `Connection: keep-alive`, в HTTP/1.1 - пока нет `Connection: close`
+pub fn client_keepalive(req: &RequestHeader) -> bool {
+    if is_http10(req) {
+        connection_lists(req, "keep-alive")
+    } else {
+        !connection_lists(req, "close")
+    }
+}
+
+/// Host для запроса HTTP/1.0 без Host: первое имя default server
+/// (`listen ... default_server`, иначе первый server блок)
+pub fn missing_host(req: &RequestHeader, config: &Config) -> Option<String> {
+    if !is_http10(req) || req.uri.authority().is_some() || req.headers.contains_key("host") {
+        return None;
+    }
+    config.default_server()?.server_names.first().cloned()
+}
+
+/// Кадрирование ответа клиенту HTTP/1.0, который не понимает chunked: Transfer-Encoding
+/// убирается, тело без Content-Length передается до закрытия соединения.
+/// Возвращает, остается ли соединение открытым после ответа
+pub fn frame_response(resp: &mut ResponseHeader, head_request: bool, keepalive: bool) -> Result<bool> {
+    let status = resp.status.as_u16();
+    let has_body = !head_request && !matches!(status, 100..=199 | 204 | 304);
+    let chunked = resp.headers.contains_key("transfer-encoding");
+    resp.remove_header("Transfer-Encoding");
+    if chunked {
+        resp.remove_header("Content-Length");
+    }
+    let keepalive = keepalive && !(has_body && !resp.headers.contains_key("content-length"));
+    resp.insert_header("Connection", if keepalive { "keep-alive" } else { "close" })?;
+    Ok(keepalive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NginxConfig;
+    use crate::stages::test_session;
+
+    async fn request(raw: &str) -> RequestHeader {
+        test_session(raw).await.req_header().clone()
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_opt_in() {
+        assert!(!client_keepalive(&request("GET / HTTP/1.0\r\n\r\n").await));
+        assert!(client_keepalive(&request("GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n").await));
+        assert!(client_keepalive(&request("GET / HTTP/1.1\r\nHost: a\r\n\r\n").await));
+        assert!(!client_keepalive(&request("GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n").await));
+    }
+
+    #[tokio::test]
+    async fn test_missing_host_uses_default_server() {
+        let mut config = Config::default();
+        config.nginx_config = Some(
+            NginxConfig::parse_config_content(
+                "server { listen 80; server_name api.example.com; location / { proxy_pass api; } }
+                 server { listen 80 default_server; server_name status.example.com; location / { proxy_pass api; } }",
+            )
+            .unwrap(),
+        );
+
+        let req = request("GET /health HTTP/1.0\r\n\r\n").await;
+        assert_eq!(missing_host(&req, &config).as_deref(), Some("status.example.com"));
+        // Host клиента и HTTP/1.1 не меняются
+        assert_eq!(missing_host(&request("GET / HTTP/1.0\r\nHost: api.example.com\r\n\r\n").await, &config), None);
+        assert_eq!(missing_host(&request("GET / HTTP/1.1\r\nHost: a\r\n\r\n").await, &config), None);
+    }
+
+    #[test]
+    fn test_chunked_response_close_delimited() {
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("Transfer-Encoding", "chunked").unwrap();
+        assert!(!frame_response(&mut resp, false, true).unwrap());
+        assert!(resp.headers.get("transfer-encoding").is_none());
+        assert_eq!(resp.headers.get("connection").unwrap(), "close");
+
+        // Content-Length сохраняет keep-alive, если клиент его запросил
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("Content-Length", "12").unwrap();
+        assert!(frame_response(&mut resp, false, true).unwrap());
+        assert_eq!(resp.headers.get("connection").unwrap(), "keep-alive");
+        assert!(!frame_response(&mut resp, false, false).unwrap());
+        assert_eq!(resp.headers.get("connection").unwrap(), "close");
+
+        // Ответ без тела не требует закрытия соединения
+        let mut resp = ResponseHeader::build(304, None).unwrap();
+        assert!(frame_response(&mut resp, false, true).unwrap());
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("Transfer-Encoding", "chunked").unwrap();
+        assert!(frame_response(&mut resp, true, true).unwrap());
+    }
+}
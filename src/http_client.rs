@@ -0,0 +1,151 @@
+use log::warn;
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::config::HttpClientConfig;
+
+/// HTTP клиент для обращений прокси к внешним сервисам (webhook событий и т.п.):
+/// ограниченные таймауты соединения и попытки, несколько повторов с паузой.
+/// Медленный внешний сервис не задерживает фоновые задачи дольше (retries + 1) * timeout
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    timeout: Duration,
+    retries: u32,
+    retry_backoff: Duration,
+}
+
+impl HttpClient {
+    pub fn new(connect_timeout: Duration, timeout: Duration, retries: u32, retry_backoff: Duration) -> Self {
+        let client = reqwest::Client::builder()
+            .connect_timeout(connect_timeout)
+            .build()
+            .unwrap_or_default();
+        Self { client, timeout, retries, retry_backoff }
+    }
+
+    pub fn from_config(config: &HttpClientConfig) -> Self {
+        Self::new(
+            Duration::from_secs(config.connect_timeout),
+            Duration::from_secs(config.timeout),
+            config.retries,
+            Duration::from_millis(config.retry_backoff_ms),
+        )
+    }
+
+    /// Таймаут попытки для конкретного сервиса (например, health_events.webhook.timeout)
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// GET с повторами; тело успешного (2xx) ответа
+    pub async fn get(&self, url: &str) -> Result<bytes::Bytes, String> {
+        let response = self.execute(url, |client| client.get(url)).await?;
+        response.bytes().await.map_err(|e| e.to_string())
+    }
+
+    /// POST с JSON телом и повторами; Ok - ответ 2xx
+    pub async fn post_json<T: Serialize + ?Sized>(&self, url: &str, body: &T) -> Result<(), String> {
+        self.execute(url, |client| client.post(url).json(body)).await.map(|_| ())
+    }
+
+    /// Выполняет запрос, повторяя его после ошибок соединения, таймаутов и ответов 5xx.
+    /// Ответы 4xx не повторяются
+    async fn execute<F>(&self, url: &str, request: F) -> Result<reqwest::Response, String>
+    where
+        F: Fn(&reqwest::Client) -> reqwest::RequestBuilder,
+    {
+        let mut backoff = self.retry_backoff;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let error = match request(&self.client).timeout(self.timeout).send().await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) if !response.status().is_server_error() => {
+                    return Err(format!("status {}", response.status()));
+                }
+                Ok(response) => format!("status {}", response.status()),
+                Err(e) if e.is_timeout() => format!("timed out after {:?}", self.timeout),
+                Err(e) => e.to_string(),
+            };
+            if attempt > self.retries {
+                return Err(error);
+            }
+            warn!("Request to {} failed (attempt {}): {}, retrying", url, attempt, error);
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Instant;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Сервер отвечает статусами по очереди (последний повторяется); None - принимает
+    /// соединение и не отвечает
+    async fn mock_server(statuses: Vec<Option<u16>>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/jwks.json", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let served = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let index = served.fetch_add(1, Ordering::SeqCst);
+                let status = statuses[index.min(statuses.len() - 1)];
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 4096];
+                    let _ = stream.read(&mut buf).await;
+                    match status {
+                        Some(status) => {
+                            let response = format!("HTTP/1.1 {} Test\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok", status);
+                            let _ = stream.write_all(response.as_bytes()).await;
+                        }
+                        // Зависший сервис: соединение открыто, ответа нет
+                        None => tokio::time::sleep(Duration::from_secs(60)).await,
+                    }
+                });
+            }
+        });
+        (url, requests)
+    }
+
+    fn client(retries: u32) -> HttpClient {
+        HttpClient::new(Duration::from_secs(1), Duration::from_millis(200), retries, Duration::from_millis(10))
+    }
+
+    #[tokio::test]
+    async fn test_hung_remote_abandoned_after_timeout() {
+        let (url, requests) = mock_server(vec![None]).await;
+
+        let start = Instant::now();
+        let error = client(1).get(&url).await.unwrap_err();
+        let elapsed = start.elapsed();
+
+        assert!(error.contains("timed out"), "{}", error);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        // Две попытки по 200ms и пауза, а не 60 секунд ожидания ответа
+        assert!(elapsed >= Duration::from_millis(400) && elapsed < Duration::from_secs(2), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn test_retries_server_errors_only() {
+        let (url, requests) = mock_server(vec![Some(503), Some(200)]).await;
+        assert_eq!(&client(1).get(&url).await.unwrap()[..], b"ok");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        let (url, requests) = mock_server(vec![Some(404)]).await;
+        assert_eq!(client(3).get(&url).await.unwrap_err(), "status 404 Not Found");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let (url, requests) = mock_server(vec![Some(500)]).await;
+        assert!(client(0).post_json(&url, &serde_json::json!({"event": "test"})).await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,401 @@
+use bytes::{Bytes, BytesMut};
+use pingora::http::ResponseHeader;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use crate::metrics::{IDEMPOTENCY_REQUESTS, IDEMPOTENCY_STORED_BYTES};
+
+/// Заголовки, которые не сохраняются: длина и способ передачи тела задаются при повторе
+const SKIPPED_HEADERS: &[&str] = &["connection", "transfer-encoding", "content-length", "keep-alive"];
+
+/// Сохраненный ответ на запрос с ключом идемпотентности
+#[derive(Debug)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub headers: Vec<(String, Vec<u8>)>,
+    /// None - tombstone: запрос выполнен, но тело не сохранено (5xx, больше
+    /// idempotency_max_body, нет места в бюджете); повтор получает только статус и заголовки
+    pub body: Option<Bytes>,
+}
+
+impl StoredResponse {
+    /// Размер ответа для учета в лимите памяти
+    fn size(&self) -> usize {
+        self.body.as_ref().map_or(0, |body| body.len())
+            + self.headers.iter().map(|(name, value)| name.len() + value.len()).sum::<usize>()
+    }
+}
+
+#[derive(Debug)]
+enum Entry {
+    /// Исходный запрос выполняется; получатель закрывается, когда запрос завершится
+    InFlight(watch::Receiver<()>),
+    Completed {
+        response: Arc<StoredResponse>,
+        expires_at: Instant,
+    },
+}
+
+/// Результат поиска ключа
+#[derive(Debug)]
+pub enum Lookup {
+    /// Ключ новый: запрос идет к upstream, ответ сохраняется через guard
+    Proceed(IdempotencyGuard),
+    /// Исходный запрос еще выполняется
+    InFlight(watch::Receiver<()>),
+    /// Есть сохраненный ответ или tombstone для повтора
+    Replay(Arc<StoredResponse>),
+}
+
+#[derive(Debug, Default)]
+struct StoreState {
+    entries: HashMap<String, Entry>,
+    used: usize,
+}
+
+/// Хранилище ключей идемпотентности в памяти с общим лимитом размера ответов
+#[derive(Debug)]
+pub struct IdempotencyStore {
+    budget: usize,
+    state: Mutex<StoreState>,
+}
+
+impl IdempotencyStore {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            state: Mutex::new(StoreState::default()),
+        }
+    }
+
+    /// Ключ хранилища: ключ идемпотентности, метод и путь запроса
+    pub fn key(idempotency_key: &str, method: &str, host: &str, path: &str) -> String {
+        format!("{} {}{} {}", method, host, path, idempotency_key)
+    }
+
+    /// Регистрирует запрос или возвращает состояние уже известного ключа
+    pub fn begin(self: &Arc<Self>, key: String, ttl: Duration, max_body: usize) -> Lookup {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        let expired = match state.entries.get(&key) {
+            Some(Entry::InFlight(done)) => return Lookup::InFlight(done.clone()),
+            Some(Entry::Completed { response, expires_at }) => {
+                if *expires_at > now {
+                    return Lookup::Replay(response.clone());
+                }
+                true
+            }
+            None => false,
+        };
+        if expired {
+            Self::remove_locked(&mut state, &key);
+        }
+
+        let (done, receiver) = watch::channel(());
+        state.entries.insert(key.clone(), Entry::InFlight(receiver));
+        Lookup::Proceed(IdempotencyGuard {
+            store: self.clone(),
+            key,
+            ttl,
+            max_body,
+            status: None,
+            headers: Vec::new(),
+            body: BytesMut::new(),
+            body_skipped: false,
+            finished: false,
+            _done: done,
+        })
+    }
+
+    /// Сохраняет ответ до истечения TTL. Если он не помещается в лимит (после удаления
+    /// просроченных), остается tombstone без тела, а при нехватке места и без заголовков.
+    /// Возвращает, сохранено ли тело
+    fn complete(&self, key: &str, mut response: StoredResponse, ttl: Duration) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let size = response.size();
+
+        if state.used + size > self.budget {
+            let now = Instant::now();
+            let expired: Vec<String> = state
+                .entries
+                .iter()
+                .filter(|(_, entry)| matches!(entry, Entry::Completed { expires_at, .. } if *expires_at <= now))
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in expired {
+                Self::remove_locked(&mut state, &key);
+            }
+        }
+
+        if state.used + response.size() > self.budget {
+            response.body = None;
+        }
+        if state.used + response.size() > self.budget {
+            response.headers.clear();
+        }
+
+        let stored = response.body.is_some();
+        let size = response.size();
+        state.used += size;
+        IDEMPOTENCY_STORED_BYTES.add(size as i64);
+        state.entries.insert(
+            key.to_string(),
+            Entry::Completed {
+                response: Arc::new(response),
+                expires_at: Instant::now() + ttl,
+            },
+        );
+        stored
+    }
+
+    /// Удаляет запись запроса, не получившего ответ upstream (ошибка upstream, обрыв соединения)
+    fn abandon(&self, key: &str) {
+        let mut state = self.state.lock().unwrap();
+        if matches!(state.entries.get(key), Some(Entry::InFlight(_))) {
+            state.entries.remove(key);
+        }
+    }
+
+    fn remove_locked(state: &mut StoreState, key: &str) {
+        if let Some(Entry::Completed { response, .. }) = state.entries.remove(key) {
+            let size = response.size();
+            state.used -= size;
+            IDEMPOTENCY_STORED_BYTES.sub(size as i64);
+        }
+    }
+
+    /// Объем памяти, занятый сохраненными ответами
+    pub fn used(&self) -> usize {
+        self.state.lock().unwrap().used
+    }
+}
+
+/// Запрос с новым ключом идемпотентности: собирает ответ upstream и сохраняет его.
+/// Если ответ не сохранен до удаления guard, ключ освобождается.
+#[derive(Debug)]
+pub struct IdempotencyGuard {
+    store: Arc<IdempotencyStore>,
+    key: String,
+    ttl: Duration,
+    max_body: usize,
+    status: Option<u16>,
+    headers: Vec<(String, Vec<u8>)>,
+    body: BytesMut,
+    /// Тело не сохраняется (больше idempotency_max_body или заменено прокси)
+    body_skipped: bool,
+    finished: bool,
+    /// Закрывается вместе с guard и будит ожидающие повторы
+    _done: watch::Sender<()>,
+}
+
+impl IdempotencyGuard {
+    /// Запоминает статус и заголовки ответа
+    pub fn set_response(&mut self, response: &ResponseHeader) {
+        self.status = Some(response.status.as_u16());
+        self.headers = response
+            .headers
+            .iter()
+            .filter(|(name, _)| !SKIPPED_HEADERS.contains(&name.as_str()))
+            .map(|(name, value)| (name.as_str().to_string(), value.as_bytes().to_vec()))
+            .collect();
+    }
+
+    /// Добавляет часть тела ответа
+    pub fn push_body(&mut self, chunk: &[u8]) {
+        if self.body_skipped {
+            return;
+        }
+        if self.body.len() + chunk.len() > self.max_body {
+            self.skip_body();
+            return;
+        }
+        self.body.extend_from_slice(chunk);
+    }
+
+    /// Тело ответа не сохраняется: повтор получит tombstone со статусом и заголовками
+    pub fn skip_body(&mut self) {
+        self.body_skipped = true;
+        self.body = BytesMut::new();
+    }
+
+    /// Завершает запрос. Ответ upstream хранится до истечения TTL, чтобы повтор не дошел
+    /// до upstream второй раз; тело 5xx ответов не сохраняется
+    pub fn finish(&mut self) {
+        self.finished = true;
+        let stored = match self.status {
+            Some(status) => {
+                let body = std::mem::take(&mut self.body).freeze();
+                self.store.complete(
+                    &self.key,
+                    StoredResponse {
+                        status,
+                        headers: std::mem::take(&mut self.headers),
+                        body: (status < 500 && !self.body_skipped).then_some(body),
+                    },
+                    self.ttl,
+                )
+            }
+            None => {
+                self.store.abandon(&self.key);
+                false
+            }
+        };
+        let result = if stored { "stored" } else { "not_stored" };
+        IDEMPOTENCY_REQUESTS.with_label_values(&[result]).inc();
+    }
+}
+
+impl Drop for IdempotencyGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.store.abandon(&self.key);
+        }
+    }
+}
+
+/// Ожидает завершения исходного запроса не дольше `timeout`
+pub async fn wait_for_completion(mut done: watch::Receiver<()>, timeout: Duration) -> bool {
+    // Канал закрывается при удалении guard исходного запроса
+    tokio::time::timeout(timeout, async { while done.changed().await.is_ok() {} })
+        .await
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn response(status: u16) -> ResponseHeader {
+        let mut response = ResponseHeader::build(status, None).unwrap();
+        response.insert_header("Content-Type", "application/json").unwrap();
+        response.insert_header("Transfer-Encoding", "chunked").unwrap();
+        response
+    }
+
+    fn proceed(store: &Arc<IdempotencyStore>, key: &str) -> IdempotencyGuard {
+        match store.begin(key.to_string(), TTL, 1024) {
+            Lookup::Proceed(guard) => guard,
+            other => panic!("expected Proceed, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_duplicate_in_flight() {
+        let store = Arc::new(IdempotencyStore::new(1024 * 1024));
+        let key = IdempotencyStore::key("charge-1", "POST", "api.ad-quest.ru", "/billing/charge");
+        let mut original = proceed(&store, &key);
+
+        // Повтор, пока исходный запрос выполняется
+        let Lookup::InFlight(done) = store.begin(key.clone(), TTL, 1024) else {
+            panic!("duplicate must see the in-flight original");
+        };
+        assert!(!wait_for_completion(done.clone(), Duration::from_millis(10)).await);
+
+        let waiter = tokio::spawn(wait_for_completion(done, Duration::from_secs(5)));
+        original.set_response(&response(201));
+        original.push_body(b"{\"id\":1}");
+        original.finish();
+        drop(original);
+        assert!(waiter.await.unwrap());
+
+        assert!(matches!(store.begin(key, TTL, 1024), Lookup::Replay(_)));
+    }
+
+    #[test]
+    fn test_replay_after_complete() {
+        let store = Arc::new(IdempotencyStore::new(1024 * 1024));
+        let key = IdempotencyStore::key("charge-2", "POST", "api.ad-quest.ru", "/billing/charge");
+        let mut original = proceed(&store, &key);
+        original.set_response(&response(201));
+        original.push_body(b"{\"id\":");
+        original.push_body(b"2}");
+        original.finish();
+        drop(original);
+
+        let Lookup::Replay(stored) = store.begin(key.clone(), TTL, 1024) else {
+            panic!("expected replay");
+        };
+        assert_eq!(stored.status, 201);
+        assert_eq!(stored.body, Some(Bytes::from_static(b"{\"id\":2}")));
+        assert!(stored.headers.iter().any(|(name, _)| name == "content-type"));
+        assert!(!stored.headers.iter().any(|(name, _)| name == "transfer-encoding"));
+        assert!(store.used() > 0);
+
+        // Другой метод или путь - другой ключ
+        let other = IdempotencyStore::key("charge-2", "POST", "api.ad-quest.ru", "/billing/refund");
+        assert!(matches!(store.begin(other, TTL, 1024), Lookup::Proceed(_)));
+    }
+
+    fn tombstone(store: &Arc<IdempotencyStore>, key: &str) -> Arc<StoredResponse> {
+        match store.begin(key.to_string(), TTL, 1024) {
+            Lookup::Replay(stored) if stored.body.is_none() => stored,
+            other => panic!("expected tombstone, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_failed_and_oversized_responses_keep_tombstone() {
+        let store = Arc::new(IdempotencyStore::new(1024 * 1024));
+
+        // Ошибка upstream (guard удален без ответа) освобождает ключ для повтора
+        drop(proceed(&store, "a"));
+        let mut retry = proceed(&store, "a");
+        retry.set_response(&response(502));
+        retry.push_body(b"bad gateway");
+        retry.finish();
+        drop(retry);
+        // Ответ 5xx получен от upstream: повтор не отправляется второй раз
+        assert_eq!(tombstone(&store, "a").status, 502);
+
+        let mut large = match store.begin("b".to_string(), TTL, 4) {
+            Lookup::Proceed(guard) => guard,
+            other => panic!("expected Proceed, got {:?}", other),
+        };
+        large.set_response(&response(200));
+        large.push_body(b"too large");
+        large.finish();
+        drop(large);
+        let stored = tombstone(&store, "b");
+        assert_eq!(stored.status, 200);
+        assert!(stored.headers.iter().any(|(name, _)| name == "content-type"));
+        assert_eq!(store.used(), 2 * stored.size());
+
+        // Tombstone истекает вместе с TTL
+        let mut short = match store.begin("c".to_string(), Duration::ZERO, 4) {
+            Lookup::Proceed(guard) => guard,
+            other => panic!("expected Proceed, got {:?}", other),
+        };
+        short.set_response(&response(200));
+        short.push_body(b"too large");
+        short.finish();
+        drop(short);
+        assert!(matches!(store.begin("c".to_string(), TTL, 1024), Lookup::Proceed(_)));
+    }
+
+    #[test]
+    fn test_budget_limits_stored_responses() {
+        let store = Arc::new(IdempotencyStore::new(64));
+        let mut first = proceed(&store, "first");
+        first.set_response(&ResponseHeader::build(200, None).unwrap());
+        first.push_body(&[b'x'; 48]);
+        first.finish();
+        drop(first);
+
+        let mut second = proceed(&store, "second");
+        second.set_response(&ResponseHeader::build(200, None).unwrap());
+        second.push_body(&[b'y'; 48]);
+        second.finish();
+        drop(second);
+
+        // Ответ не поместился в бюджет: остается tombstone без тела и заголовков
+        assert_eq!(store.used(), 48);
+        let stored = tombstone(&store, "second");
+        assert_eq!(stored.status, 200);
+        assert_eq!(stored.size(), 0);
+    }
+}
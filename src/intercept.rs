@@ -0,0 +1,177 @@
+use bytes::Bytes;
+use pingora::prelude::*;
+use pingora::http::ResponseHeader;
+use log::warn;
+
+use crate::config::LocationBlock;
+use crate::error_response::{ErrorCode, ErrorResponse};
+use crate::types::RequestContext;
+
+/// Заголовки upstream, которые сохраняются при перехвате ошибки
+const PRESERVED_HEADERS: [&str; 1] = ["retry-after"];
+
+/// Определяет, нужно ли перехватить ответ upstream (proxy_intercept_errors).
+/// Перехватываются только статусы >= 400, для которых настроен error_page или status_map,
+/// чтобы API locations с собственными JSON 4xx ответами не затрагивались.
+/// Возвращает статус, который будет отправлен клиенту.
+pub fn intercept_status(location: &LocationBlock, status: u16) -> Option<u16> {
+    if !location.intercept_errors || status < 400 {
+        return None;
+    }
+    if !location.error_pages.contains_key(&status) && !location.status_map.contains_key(&status) {
+        return None;
+    }
+
+    Some(location.status_map.get(&status).copied().unwrap_or(status))
+}
+
+/// Загружает тело страницы ошибки для статуса upstream и ее Content-Type.
+/// Если error_page не настроен или не читается, используется JSON ответ об ошибке
+/// с итоговым статусом ответа.
+pub fn load_error_page(
+    location: &LocationBlock,
+    upstream_status: u16,
+    status: u16,
+    ctx: &RequestContext,
+) -> (Bytes, String) {
+    if let Some(path) = location.error_pages.get(&upstream_status) {
+        match std::fs::read(path) {
+            Ok(content) => {
+                let content_type = mime_guess::from_path(path)
+                    .first_or_octet_stream()
+                    .to_string();
+                return (Bytes::from(content), content_type);
+            }
+            Err(e) => warn!("Failed to read error page '{}': {}", path, e),
+        }
+    }
+
+    let body = ErrorResponse::new(ErrorCode::from_status(status)).body(ctx);
+    (Bytes::from(body), "application/json".to_string())
+}
+
+/// Формирует заголовки перехваченного ответа, сохраняя Retry-After от upstream
+pub fn build_intercepted_header(
+    upstream_response: &ResponseHeader,
+    status: u16,
+    content_type: &str,
+    body_len: usize,
+) -> Result<ResponseHeader> {
+    let mut response = ResponseHeader::build(status, None)?;
+    response.insert_header("Content-Type", content_type)?;
+    response.insert_header("Content-Length", body_len.to_string())?;
+    response.insert_header("Cache-Control", "no-store")?;
+
+    for name in PRESERVED_HEADERS {
+        if let Some(value) = upstream_response.headers.get(name) {
+            response.insert_header(name, value.clone())?;
+        }
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn location(intercept_errors: bool) -> LocationBlock {
+        LocationBlock {
+            path: "/app/".to_string(),
+            proxy_pass: Some("app".to_string()),
+            proxy_pass_url: None,
+            rate_limit: None,
+            rate_limit_schedules: Vec::new(),
+            cors_enable: false,
+            proxy_connect_timeout: None,
+            proxy_send_timeout: None,
+            proxy_read_timeout: None,
+            proxy_first_byte_timeout: None,
+            proxy_request_timeout: None,
+            intercept_errors,
+            error_pages: HashMap::from([
+                (500, "/var/www/errors/50x.html".to_string()),
+                (503, "/var/www/errors/50x.html".to_string()),
+            ]),
+            status_map: HashMap::from([(500, 502)]),
+            proxy_redirect: Default::default(),
+            proxy_cookie_domain: Vec::new(),
+            proxy_cookie_path: Vec::new(),
+            proxy_buffering: false,
+            proxy_buffers_size: crate::config::DEFAULT_PROXY_BUFFERS_SIZE,
+            access_log: None,
+            backend_profile: None,
+            idempotency: None,
+            idempotency_max_body: crate::config::DEFAULT_IDEMPOTENCY_MAX_BODY,
+            fallback_response: None,
+            proxy_next_upstream: Default::default(),
+            proxy_next_upstream_tries: None,
+            root: None,
+            gzip_static: false,
+            brotli_static: false,
+            redact: false,
+            compression_algorithms: None,
+            limit_req: Vec::new(),
+            limit_conn: Vec::new(),
+            add_header: Vec::new(),
+            proxy_set_header: Vec::new(),
+            return_directive: None,
+            forwarded_headers: Default::default(),
+            wasm_filter: None,
+            challenge_mode: false,
+            proxy_cache: true,
+            proxy_cache_valid: Vec::new(),
+            proxy_no_cache: Vec::new(),
+            pos: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_intercept_disabled() {
+        let location = location(false);
+        assert_eq!(intercept_status(&location, 500), None);
+        assert_eq!(intercept_status(&location, 503), None);
+    }
+
+    #[test]
+    fn test_intercept_enabled_with_status_map() {
+        let location = location(true);
+        assert_eq!(intercept_status(&location, 500), Some(502));
+        assert_eq!(intercept_status(&location, 503), Some(503));
+        // Статусы без error_page (например JSON 4xx от API) не перехватываются
+        assert_eq!(intercept_status(&location, 404), None);
+        assert_eq!(intercept_status(&location, 200), None);
+    }
+
+    #[test]
+    fn test_intercepted_header_preserves_retry_after() {
+        let mut upstream = ResponseHeader::build(503, None).unwrap();
+        upstream.insert_header("Retry-After", "120").unwrap();
+        upstream.insert_header("X-Debug-Trace", "secret").unwrap();
+
+        let response = build_intercepted_header(&upstream, 503, "text/html", 42).unwrap();
+        assert_eq!(response.status.as_u16(), 503);
+        assert_eq!(response.headers.get("retry-after").unwrap(), "120");
+        assert_eq!(response.headers.get("content-length").unwrap(), "42");
+        assert!(response.headers.get("x-debug-trace").is_none());
+    }
+
+    #[test]
+    fn test_missing_error_page_falls_back_to_json() {
+        let mut location = location(true);
+        location.error_pages.insert(502, "/nonexistent/error.html".to_string());
+        let ctx = RequestContext::new();
+
+        let (body, content_type) = load_error_page(&location, 502, 502, &ctx);
+        assert_eq!(content_type, "application/json");
+        assert!(String::from_utf8_lossy(&body).contains("UPSTREAM_UNAVAILABLE"));
+
+        // 403 от upstream - не блокировка IP прокси
+        location.error_pages.insert(403, "/nonexistent/error.html".to_string());
+        let (body, _) = load_error_page(&location, 403, 403, &ctx);
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("\"FORBIDDEN\""), "{}", body);
+        assert!(!body.contains("IP_BLOCKED"), "{}", body);
+    }
+}
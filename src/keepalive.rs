@@ -0,0 +1,173 @@
+use async_trait::async_trait;
+use log::debug;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use pingora::prelude::*;
+use pingora_core::apps::ServerApp;
+use pingora_core::protocols::{GetSocketDigest, Stream};
+use pingora_core::server::ShutdownWatch;
+
+/// Учет количества запросов в downstream keep-alive соединениях
+#[derive(Debug, Default)]
+pub struct KeepaliveTracker {
+    /// Максимум запросов на соединение (0 - без ограничения)
+    max_requests: u32,
+    /// Количество запросов по соединениям ("клиент-сервер" адреса)
+    connections: Mutex<HashMap<String, u32>>,
+}
+
+impl KeepaliveTracker {
+    pub fn new(max_requests: u32) -> Self {
+        Self {
+            max_requests,
+            connections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn limit_reached(&self, count: u32) -> bool {
+        self.max_requests > 0 && count >= self.max_requests
+    }
+
+    /// Учитывает новый запрос в соединении, возвращает его номер
+    fn begin_request(&self, key: &str) -> u32 {
+        let mut connections = self.connections.lock().unwrap();
+        let count = connections.entry(key.to_string()).or_insert(0);
+        *count += 1;
+        *count
+    }
+
+    fn end_connection(&self, key: &str) {
+        self.connections.lock().unwrap().remove(key);
+    }
+
+    /// Является ли текущий запрос последним разрешенным в соединении
+    pub fn is_last_request(&self, key: &str) -> bool {
+        let connections = self.connections.lock().unwrap();
+        connections.get(key).is_some_and(|count| self.limit_reached(*count))
+    }
+}
+
+fn connection_key(client: impl std::fmt::Display, server: impl std::fmt::Display) -> String {
+    format!("{}-{}", client, server)
+}
+
+/// Ключ соединения для запроса
+pub fn session_connection_key(session: &Session) -> Option<String> {
+    Some(connection_key(session.client_addr()?, session.server_addr()?))
+}
+
+fn stream_connection_key(stream: &Stream) -> Option<String> {
+    let digest = stream.get_socket_digest()?;
+    Some(connection_key(digest.peer_addr()?, digest.local_addr()?))
+}
+
+/// Обертка над приложением Pingora, закрывающая keep-alive соединение
+/// после `keepalive_requests` запросов
+pub struct KeepaliveApp<A> {
+    inner: Arc<A>,
+    tracker: Arc<KeepaliveTracker>,
+}
+
+impl<A> KeepaliveApp<A> {
+    pub fn new(inner: A, tracker: Arc<KeepaliveTracker>) -> Self {
+        Self { inner: Arc::new(inner), tracker }
+    }
+}
+
+#[async_trait]
+impl<A> ServerApp for KeepaliveApp<A>
+where
+    A: ServerApp + Send + Sync + 'static,
+{
+    async fn process_new(self: &Arc<Self>, stream: Stream, shutdown: &ShutdownWatch) -> Option<Stream> {
+        let Some(key) = stream_connection_key(&stream) else {
+            return self.inner.process_new(stream, shutdown).await;
+        };
+
+        // Pingora вызывает process_new для каждого запроса в keep-alive соединении
+        let count = self.tracker.begin_request(&key);
+        match self.inner.process_new(stream, shutdown).await {
+            Some(stream) if !self.tracker.limit_reached(count) => Some(stream),
+            Some(mut stream) => {
+                debug!("Closing connection {} after {} requests", key, count);
+                self.tracker.end_connection(&key);
+                stream.shutdown().await;
+                None
+            }
+            None => {
+                self.tracker.end_connection(&key);
+                None
+            }
+        }
+    }
+
+    async fn cleanup(&self) {
+        self.inner.cleanup().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingora_core::protocols::digest::SocketDigest;
+    use pingora_core::protocols::l4::stream::Stream as L4Stream;
+    use std::os::unix::io::AsRawFd;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Приложение, которое всегда оставляет соединение открытым
+    struct ReuseApp {
+        requests: AtomicU32,
+    }
+
+    #[async_trait]
+    impl ServerApp for ReuseApp {
+        async fn process_new(self: &Arc<Self>, stream: Stream, _shutdown: &ShutdownWatch) -> Option<Stream> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            Some(stream)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connection_reused_up_to_max_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (tcp, _) = listener.accept().await.unwrap();
+
+        let fd = tcp.as_raw_fd();
+        let mut l4 = L4Stream::from(tcp);
+        l4.set_socket_digest(SocketDigest::from_raw_fd(fd));
+        let stream: Stream = Box::new(l4);
+        let key = stream_connection_key(&stream).unwrap();
+
+        let tracker = Arc::new(KeepaliveTracker::new(3));
+        let app = Arc::new(KeepaliveApp::new(ReuseApp { requests: AtomicU32::new(0) }, tracker.clone()));
+        let (_tx, shutdown) = tokio::sync::watch::channel(false);
+
+        // Цикл повторного использования соединения, как в listening сервисе Pingora
+        let mut reused = app.process_new(stream, &shutdown).await;
+        while let Some(stream) = reused {
+            reused = app.process_new(stream, &shutdown).await;
+        }
+
+        assert_eq!(app.inner.requests.load(Ordering::SeqCst), 3);
+        assert!(!tracker.is_last_request(&key));
+        assert!(tracker.connections.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_last_request_detection() {
+        let tracker = KeepaliveTracker::new(2);
+        assert_eq!(tracker.begin_request("c"), 1);
+        assert!(!tracker.is_last_request("c"));
+        assert_eq!(tracker.begin_request("c"), 2);
+        assert!(tracker.is_last_request("c"));
+
+        let unlimited = KeepaliveTracker::new(0);
+        for _ in 0..100 {
+            unlimited.begin_request("c");
+        }
+        assert!(!unlimited.is_last_request("c"));
+    }
+}
@@ -0,0 +1,157 @@
+use pingora_load_balancing::selection::RoundRobin;
+use pingora_load_balancing::{Backend, LoadBalancer};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+
+/// Количество повторных выборок второго кандидата, прежде чем взять соседний
+const SECOND_CANDIDATE_ATTEMPTS: usize = 16;
+
+/// "Power of two choices": два разных случайных кандидата (вероятность выбора пропорциональна весу),
+/// из них - с меньшим числом запросов в обработке. При равенстве - случайный из двух
+pub fn choose<'a, I, R>(candidates: &'a [Backend], in_flight: I, rng: &mut R) -> Option<&'a Backend>
+where
+    I: Fn(&Backend) -> usize,
+    R: Rng,
+{
+    match candidates.len() {
+        0 => return None,
+        1 => return candidates.first(),
+        _ => {}
+    }
+
+    let weights = WeightedIndex::new(candidates.iter().map(|backend| backend.weight.max(1))).ok()?;
+    let first = weights.sample(rng);
+    let second = (0..SECOND_CANDIDATE_ATTEMPTS)
+        .map(|_| weights.sample(rng))
+        .find(|index| *index != first)
+        .unwrap_or((first + 1) % candidates.len());
+
+    let (a, b) = (&candidates[first], &candidates[second]);
+    let (load_a, load_b) = (in_flight(a), in_flight(b));
+    Some(match load_a.cmp(&load_b) {
+        std::cmp::Ordering::Less => a,
+        std::cmp::Ordering::Greater => b,
+        std::cmp::Ordering::Equal if rng.gen_bool(0.5) => a,
+        std::cmp::Ordering::Equal => b,
+    })
+}
+
+/// Выбирает бэкенд upstream с least_requests. Бэкенды, к которым запрос уже отправлялся,
+/// выбираются, только если других подходящих нет
+pub fn select_least_requests<F, I>(lb: &LoadBalancer<RoundRobin>, tried: &[String], accept: F, in_flight: I) -> Option<Backend>
+where
+    F: Fn(&Backend, bool) -> bool,
+    I: Fn(&Backend) -> usize,
+{
+    let backends = lb.backends();
+    let eligible: Vec<Backend> = backends
+        .get_backend()
+        .iter()
+        .filter(|backend| accept(backend, backends.ready(backend)))
+        .cloned()
+        .collect();
+    let untried: Vec<Backend> = eligible
+        .iter()
+        .filter(|backend| !tried.contains(&backend.addr.to_string()))
+        .cloned()
+        .collect();
+    let candidates = if untried.is_empty() { eligible } else { untried };
+    choose(&candidates, in_flight, &mut rand::thread_rng()).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::collections::HashMap;
+
+    fn backends(weights: &[usize]) -> Vec<Backend> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| {
+                let mut backend = Backend::new(&format!("10.0.0.{}:8080", i + 1)).unwrap();
+                backend.weight = *weight;
+                backend
+            })
+            .collect()
+    }
+
+    /// Моделирует поток запросов: каждый тик приходит `rate` запросов, запрос к бэкенду
+    /// обрабатывается `latency[i]` тиков. Возвращает число запросов на каждый бэкенд
+    fn simulate(candidates: &[Backend], latency: &[u32], rate: usize, ticks: u32) -> Vec<usize> {
+        let mut rng = StdRng::seed_from_u64(7);
+        let index: HashMap<String, usize> = candidates.iter().enumerate().map(|(i, b)| (b.addr.to_string(), i)).collect();
+        let mut in_flight = vec![0usize; candidates.len()];
+        let mut finishing: Vec<(u32, usize)> = Vec::new();
+        let mut served = vec![0usize; candidates.len()];
+
+        for tick in 0..ticks {
+            finishing.retain(|(at, i)| {
+                if *at == tick {
+                    in_flight[*i] -= 1;
+                }
+                *at != tick
+            });
+            for _ in 0..rate {
+                let backend = choose(candidates, |b| in_flight[index[&b.addr.to_string()]], &mut rng).unwrap();
+                let i = index[&backend.addr.to_string()];
+                in_flight[i] += 1;
+                served[i] += 1;
+                finishing.push((tick + latency[i], i));
+            }
+        }
+        served
+    }
+
+    #[test]
+    fn test_slow_backend_gets_fewer_requests() {
+        let candidates = backends(&[1, 1, 1]);
+        // Третий бэкенд в 10 раз медленнее остальных
+        let served = simulate(&candidates, &[1, 1, 10], 3, 1000);
+        let total: usize = served.iter().sum();
+        let round_robin_share = total / candidates.len();
+
+        assert!(served[2] * 2 < round_robin_share, "slow backend served {:?}", served);
+        assert!(served[0] > round_robin_share && served[1] > round_robin_share);
+    }
+
+    #[test]
+    fn test_weights_and_ties() {
+        let mut rng = StdRng::seed_from_u64(42);
+        // Все бэкенды свободны: выбор определяется весами и случайным разрешением равенства
+        let candidates = backends(&[5, 1, 1, 1]);
+        let mut heavy = 0;
+        for _ in 0..4000 {
+            if choose(&candidates, |_| 0, &mut rng).unwrap().weight == 5 {
+                heavy += 1;
+            }
+        }
+        assert!(heavy > 1400, "heavy backend chosen {} times", heavy);
+
+        let single = backends(&[1]);
+        assert_eq!(choose(&single, |_| 100, &mut rng).unwrap().addr, single[0].addr);
+        assert!(choose(&[], |_| 0, &mut rng).is_none());
+
+        // Из двух - всегда менее загруженный
+        let pair = backends(&[1, 1]);
+        for _ in 0..100 {
+            let chosen = choose(&pair, |b| if b.addr == pair[0].addr { 5 } else { 1 }, &mut rng).unwrap();
+            assert_eq!(chosen.addr, pair[1].addr);
+        }
+    }
+
+    #[test]
+    fn test_select_skips_tried_backends() {
+        let lb = LoadBalancer::<RoundRobin>::try_from_iter(["10.0.0.1:8080", "10.0.0.2:8080"]).unwrap();
+        let tried = vec!["10.0.0.1:8080".to_string()];
+        for _ in 0..20 {
+            let backend = select_least_requests(&lb, &tried, |_, healthy| healthy, |_| 0).unwrap();
+            assert_eq!(backend.addr.to_string(), "10.0.0.2:8080");
+        }
+        // Все пробовались - выбирается любой
+        let all = vec!["10.0.0.1:8080".to_string(), "10.0.0.2:8080".to_string()];
+        assert!(select_least_requests(&lb, &all, |_, healthy| healthy, |_| 0).is_some());
+    }
+}
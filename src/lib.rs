@@ -7,9 +7,62 @@ pub mod rate_limit;
 pub mod metrics;
 pub mod filter;
 pub mod config;
+pub mod config_check;
 pub mod cache;
+pub mod challenge;
 pub mod circuit_breaker;
+pub mod client_ip;
+pub mod clock;
+pub mod compression;
+pub mod concurrency;
+pub mod hop_by_hop;
+pub mod http10;
 pub mod logging;
+pub mod drain;
+pub mod error;
+pub mod error_messages;
+pub mod error_response;
+pub mod experiments;
+pub mod intercept;
+pub mod keepalive;
+pub mod local_response;
+pub mod proxy_protocol;
+pub mod timing;
+pub mod trailers;
+pub mod preflight;
+pub mod default_page;
+pub mod dns;
+pub mod rewrite;
+pub mod stages;
+pub mod request_id;
+pub mod buffering;
+pub mod grpc_web;
+pub mod process_metrics;
+pub mod backend_profile;
+pub mod idempotency;
+pub mod fallback;
+pub mod first_byte;
+pub mod forwarded;
+pub mod wasm_filter;
+pub mod next_upstream;
+pub mod scheme;
+pub mod static_files;
+pub mod variables;
+pub mod warmup;
+pub mod schedules;
+pub mod flight_recorder;
+pub mod health_events;
+pub mod http_client;
+pub mod body_transform;
+pub mod least_requests;
+pub mod hash_balancing;
+pub mod reload;
+pub mod smoke_test;
+pub mod status;
+pub mod testing;
+pub mod builder;
 
+pub use builder::{BuiltProxy, ProxyBuilder, ProxyServer};
 pub use proxy::AdQuestProxy;
-pub use types::{RequestContext, ServiceType};
\ No newline at end of file
+pub use reload::ReloadableProxy;
+pub use types::{HandledBy, RequestContext, ServiceType, UpstreamTarget};
\ No newline at end of file
@@ -0,0 +1,130 @@
+use bytes::Bytes;
+use pingora::http::ResponseHeader;
+use pingora::prelude::*;
+
+use crate::cors::{add_cors_headers_for_request, add_security_headers};
+
+/// Текущее время в формате HTTP-date (RFC 7231 7.1.1.1)
+pub fn http_date_now() -> String {
+    chrono::Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Content-Type с `charset=utf-8` для текстовых типов, если charset не указан
+pub fn with_charset(content_type: &str) -> String {
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    let textual = mime.starts_with("text/")
+        || mime == "application/json"
+        || mime == "application/javascript"
+        || mime.ends_with("+json");
+    if textual && !content_type.to_ascii_lowercase().contains("charset=") {
+        format!("{}; charset=utf-8", content_type)
+    } else {
+        content_type.to_string()
+    }
+}
+
+/// Заголовки ответов, которые формирует сам прокси (страница приветствия, JSON ошибки,
+/// ответы header_rules): Content-Type с charset, Content-Length, Date и security заголовки
+#[derive(Debug, Clone)]
+pub struct ResponseBuilder {
+    status: u16,
+    content_type: String,
+    headers: Vec<(&'static str, String)>,
+    cors: bool,
+}
+
+impl ResponseBuilder {
+    pub fn new(status: u16, content_type: &str) -> Self {
+        Self {
+            status,
+            content_type: with_charset(content_type),
+            headers: Vec::new(),
+            cors: false,
+        }
+    }
+
+    /// JSON ответ, не сохраняемый в кешах
+    pub fn json(status: u16) -> Self {
+        Self::new(status, "application/json").header("Cache-Control", "no-store")
+    }
+
+    pub fn html(status: u16) -> Self {
+        Self::new(status, "text/html")
+    }
+
+    /// Добавляет заголовок; повторное имя заменяет значение
+    pub fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    /// CORS заголовки по Origin запроса
+    pub fn cors(mut self) -> Self {
+        self.cors = true;
+        self
+    }
+
+    /// Формирует заголовки ответа для тела длиной body_len
+    pub fn build(&self, session: &Session, body_len: usize) -> Result<ResponseHeader> {
+        let mut response = ResponseHeader::build(self.status, None)?;
+        response.insert_header("Content-Type", self.content_type.as_str())?;
+        response.insert_header("Content-Length", body_len.to_string())?;
+        response.insert_header("Date", http_date_now())?;
+        for (name, value) in &self.headers {
+            response.insert_header(*name, value.as_str())?;
+        }
+
+        add_security_headers(&mut response)?;
+        if self.cors {
+            add_cors_headers_for_request(session, &mut response)?;
+        }
+        Ok(response)
+    }
+
+    /// Отправляет ответ с телом клиенту; keepalive выключается, если keep_alive = false
+    pub async fn send(&self, session: &mut Session, body: Bytes, keep_alive: bool) -> Result<()> {
+        let response = self.build(session, body.len())?;
+        if !keep_alive {
+            session.set_keepalive(None);
+        }
+        session.write_response_header(Box::new(response), false).await?;
+        session.write_response_body(Some(body), true).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stages::test_session;
+
+    #[test]
+    fn test_with_charset() {
+        assert_eq!(with_charset("application/json"), "application/json; charset=utf-8");
+        assert_eq!(with_charset("text/html"), "text/html; charset=utf-8");
+        assert_eq!(with_charset("application/problem+json"), "application/problem+json; charset=utf-8");
+        assert_eq!(with_charset("text/html; charset=windows-1251"), "text/html; charset=windows-1251");
+        assert_eq!(with_charset("image/png"), "image/png");
+    }
+
+    #[tokio::test]
+    async fn test_builder_headers() {
+        let session = test_session("GET / HTTP/1.1\r\nHost: example.com\r\nOrigin: https://app.ad-quest.ru\r\n\r\n").await;
+
+        let html = ResponseBuilder::html(200).build(&session, 42).unwrap();
+        assert_eq!(html.headers.get("content-type").unwrap(), "text/html; charset=utf-8");
+        assert_eq!(html.headers.get("content-length").unwrap(), "42");
+        assert!(html.headers.get("date").is_some());
+        assert_eq!(html.headers.get("x-content-type-options").unwrap(), "nosniff");
+        assert!(html.headers.get("cache-control").is_none());
+        assert!(html.headers.get("access-control-allow-origin").is_none());
+
+        let json = ResponseBuilder::json(429).header("Retry-After", "1").cors().build(&session, 10).unwrap();
+        assert_eq!(json.status.as_u16(), 429);
+        assert_eq!(json.headers.get("content-type").unwrap(), "application/json; charset=utf-8");
+        assert_eq!(json.headers.get("cache-control").unwrap(), "no-store");
+        assert_eq!(json.headers.get("retry-after").unwrap(), "1");
+        assert!(json.headers.get("access-control-allow-origin").is_some());
+    }
+}
@@ -0,0 +1,174 @@
+use chrono::{SecondsFormat, Utc};
+use pingora_proxy::Session;
+use serde::Serialize;
+use std::io::Write;
+use tracing::error;
+
+use super::writer::{LogWriter, RotationPolicy};
+use crate::config::DecisionsLogConfig;
+use crate::routing::request_host;
+use crate::types::RequestContext;
+
+/// Выбор бэкенда балансировщиком; сохраняется только для запросов из выборки
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpstreamDecision {
+    pub upstream: String,
+    pub backend: String,
+    /// Алгоритм upstream: round_robin или least_requests
+    pub algorithm: &'static str,
+    /// Запросов в обработке у бэкенда в момент выбора
+    pub in_flight: usize,
+}
+
+/// Запись decisions log. Имена и порядок полей - формат лога
+#[derive(Debug, Serialize)]
+pub struct DecisionRecord {
+    pub timestamp: String,
+    pub request_id: String,
+    pub host: String,
+    pub path: String,
+    pub matched_location: Option<String>,
+    pub upstream: String,
+    pub backend: String,
+    pub selection_algo: &'static str,
+    pub in_flight_at_selection: usize,
+    pub retries: u32,
+}
+
+impl DecisionRecord {
+    /// Собирает запись по завершении запроса: решение последней попытки и число повторов
+    pub fn from_session(
+        session: &Session,
+        ctx: &RequestContext,
+        decision: UpstreamDecision,
+        matched_location: Option<&str>,
+    ) -> Self {
+        Self {
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            request_id: ctx.request_id.clone(),
+            host: request_host(session).to_string(),
+            // Только путь: query может содержать токены
+            path: session.req_header().uri.path().to_string(),
+            matched_location: matched_location.map(str::to_string),
+            upstream: decision.upstream,
+            backend: decision.backend,
+            selection_algo: decision.algorithm,
+            in_flight_at_selection: decision.in_flight,
+            retries: ctx.retries,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Output {
+    Stdout,
+    File(LogWriter),
+}
+
+/// Выборочный лог решений балансировщика (logging.decisions_log)
+#[derive(Debug)]
+pub struct DecisionsLog {
+    output: Output,
+    sample_rate: f64,
+}
+
+impl DecisionsLog {
+    pub fn new(config: &DecisionsLogConfig) -> Self {
+        let output = if config.path == "stdout" {
+            Output::Stdout
+        } else {
+            Output::File(LogWriter::with_rotation(
+                &config.path,
+                RotationPolicy::from_rotate(&config.path, config.rotate.as_ref()),
+            ))
+        };
+        Self { output, sample_rate: config.sample_rate.clamp(0.0, 1.0) }
+    }
+
+    /// Попадает ли запрос в выборку. Решается при первом выборе бэкенда,
+    /// до сбора и форматирования записи.
+    /// Случайная выборка не совпадает с периодом round robin, в отличие от каждого N-го запроса
+    pub fn sample(&self) -> bool {
+        self.sample_rate >= 1.0 || (self.sample_rate > 0.0 && rand::random::<f64>() < self.sample_rate)
+    }
+
+    /// Записывает одну JSON строку
+    pub fn log(&self, record: &DecisionRecord) {
+        let line = match serde_json::to_string(record) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Failed to format decisions log record: {}", e);
+                return;
+            }
+        };
+        let result = match &self.output {
+            Output::Stdout => writeln!(std::io::stdout().lock(), "{}", line),
+            Output::File(writer) => writer.write_line(&line),
+        };
+        if let Err(e) = result {
+            error!("Failed to write decisions log: {}", e);
+        }
+    }
+
+    /// Переоткрывает файл лога после ротации
+    pub fn reopen(&self) {
+        if let Output::File(writer) = &self.output {
+            writer.reopen();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(path: &str, sample_rate: f64) -> DecisionsLog {
+        DecisionsLog::new(&DecisionsLogConfig { path: path.to_string(), sample_rate, rotate: None })
+    }
+
+    #[test]
+    fn test_sampling_rate() {
+        let sampled = |rate: f64| {
+            let decisions = log("stdout", rate);
+            (0..20_000).filter(|_| decisions.sample()).count()
+        };
+        // 1% от 20000 - 200 записей; границы с запасом в несколько сигм
+        let one_percent = sampled(0.01);
+        assert!((120..=280).contains(&one_percent), "{}", one_percent);
+        let quarter = sampled(0.25);
+        assert!((4_700..=5_300).contains(&quarter), "{}", quarter);
+        assert_eq!(sampled(0.0), 0);
+        assert_eq!(sampled(1.0), 20_000);
+    }
+
+    #[tokio::test]
+    async fn test_record_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("decisions.log");
+        let decisions = log(&path.to_string_lossy(), 1.0);
+
+        let session =
+            crate::stages::test_session("GET /api/campaigns?token=secret HTTP/1.1\r\nHost: api.ad-quest.ru\r\n\r\n").await;
+        let mut ctx = RequestContext::new();
+        ctx.request_id = "req-1".to_string();
+        ctx.retries = 1;
+        let decision = UpstreamDecision {
+            upstream: "core_api".to_string(),
+            backend: "10.0.0.7:8080".to_string(),
+            algorithm: "least_requests",
+            in_flight: 3,
+        };
+        decisions.log(&DecisionRecord::from_session(&session, &ctx, decision, Some("/api/")));
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let line = content.lines().next().unwrap();
+        assert!(line.starts_with(r#"{"timestamp":""#), "{}", line);
+        let (_, fields) = line.split_once(r#"Z","#).unwrap();
+        assert_eq!(
+            fields,
+            r#""request_id":"req-1","host":"api.ad-quest.ru","path":"/api/campaigns","matched_location":"/api/","upstream":"core_api","backend":"10.0.0.7:8080","selection_algo":"least_requests","in_flight_at_selection":3,"retries":1}"#
+        );
+        let record: serde_json::Value = serde_json::from_str(line).unwrap();
+        assert!(chrono::DateTime::parse_from_rfc3339(record["timestamp"].as_str().unwrap()).is_ok());
+    }
+}
@@ -7,10 +7,18 @@ use tracing_subscriber::{
 };
 use serde_json::json;
 use std::time::{SystemTime, UNIX_EPOCH};
-use std::fs::OpenOptions;
-use std::io::Write;
 use pingora_proxy::Session;
-use crate::config::LoggingConfig;
+use crate::config::{AccessLogDirective, LoggingConfig};
+use crate::types::RequestContext;
+
+pub mod decisions;
+pub mod sampling;
+pub mod scrub;
+pub mod writer;
+pub use decisions::{DecisionRecord, DecisionsLog, UpstreamDecision};
+pub use sampling::AccessLogSampler;
+pub use scrub::LogScrubber;
+pub use writer::{LogReopenService, LogWriter, LogWriters, RotationPolicy};
 
 /// Инициализирует систему логирования
 pub fn init_logging(config: &LoggingConfig) -> Result<(), Box<dyn std::error::Error>> {
@@ -57,121 +65,266 @@ pub fn init_logging(config: &LoggingConfig) -> Result<(), Box<dyn std::error::Er
     Ok(())
 }
 
+/// Поля записи access log
+#[derive(Debug, Clone, Default)]
+pub struct AccessLogEntry {
+    pub client_ip: String,
+    pub method: String,
+    pub uri: String,
+    pub version: String,
+    pub host: String,
+    pub user_agent: String,
+    pub referer: String,
+    pub x_forwarded_for: String,
+    pub x_real_ip: String,
+    pub status: u16,
+    pub response_size: u64,
+    pub duration_ms: u64,
+    /// Метка сервиса (`local` для ответов, сформированных прокси)
+    pub service: String,
+    /// Кто сформировал ответ: upstream или proxy_local
+    pub handled_by: String,
+    /// Upstream запроса (имя балансировщика, адрес сервиса или `-`)
+    pub upstream: String,
+    /// Схема запроса клиента ($scheme) с учетом доверенных прокси
+    pub scheme: String,
+    /// Клиент закрыл соединение до окончания ответа (response_size - отправлено до отключения)
+    pub client_aborted: bool,
+    /// Адрес бэкенда, обработавшего запрос (`-` без обращения к upstream)
+    pub upstream_addr: String,
+}
+
+impl AccessLogEntry {
+    /// Собирает запись из сессии и контекста запроса
+    pub fn from_session(session: &Session, ctx: &RequestContext, status: u16, response_size: u64, duration_ms: u64) -> Self {
+        let req = session.req_header();
+        let header = |name: &str| {
+            req.headers.get(name)
+                .and_then(|h| h.to_str().ok())
+                .unwrap_or("-")
+                .to_string()
+        };
+
+        Self {
+            client_ip: ctx.client_ip
+                .map(|ip| ip.to_string())
+                .unwrap_or_else(|| "unknown".to_string()),
+            method: req.method.as_str().to_string(),
+            uri: req.uri.to_string(),
+            version: format!("{:?}", req.version),
+            host: header("host"),
+            user_agent: header("user-agent"),
+            referer: header("referer"),
+            x_forwarded_for: header("x-forwarded-for"),
+            x_real_ip: header("x-real-ip"),
+            status,
+            response_size,
+            duration_ms,
+            service: ctx.service_label().to_string(),
+            handled_by: ctx.handled_by.as_str().to_string(),
+            upstream: ctx.upstream_target.to_string(),
+            scheme: ctx.scheme.to_string(),
+            client_aborted: ctx.client_aborted,
+            upstream_addr: ctx.upstream_addr.clone().unwrap_or_else(|| "-".to_string()),
+        }
+    }
+}
+
 /// Структура для логирования HTTP запросов
 #[derive(Debug)]
 pub struct AccessLogger {
     config: LoggingConfig,
+    /// Открытые файлы access log (глобальный и access_log server/location блоков)
+    writers: LogWriters,
+    /// Выборка записей (logging.access_log.sampling)
+    sampler: AccessLogSampler,
+    /// Скрытие токенов и заголовков (logging.scrub)
+    scrubber: LogScrubber,
 }
 
 impl AccessLogger {
     pub fn new(config: LoggingConfig) -> Self {
-        Self { config }
+        Self {
+            writers: LogWriters::with_rotation(RotationPolicy::from_config(&config.access_log)),
+            sampler: AccessLogSampler::new(config.access_log.sampling.as_ref()),
+            scrubber: LogScrubber::new(&config.scrub),
+            config,
+        }
     }
 
     /// Логирует HTTP запрос
-    pub async fn log_request(&self, session: &Session, response_status: u16, response_size: u64, duration_ms: u64) {
-        if !self.config.access_log.enabled {
+    pub async fn log_request(&self, session: &Session, ctx: &RequestContext, response_status: u16, response_size: u64, duration_ms: u64) {
+        self.log_request_to(session, ctx, response_status, response_size, duration_ms, None).await;
+    }
+
+    /// Логирует HTTP запрос в access_log server/location блока (None - в глобальный)
+    pub async fn log_request_to(
+        &self,
+        session: &Session,
+        ctx: &RequestContext,
+        response_status: u16,
+        response_size: u64,
+        duration_ms: u64,
+        target: Option<&AccessLogDirective>,
+    ) {
+        let entry = AccessLogEntry::from_session(session, ctx, response_status, response_size, duration_ms);
+        self.log_entry_to(&entry, target).await;
+    }
+
+    /// Записывает подготовленную запись в глобальный access log
+    pub async fn log_entry(&self, entry: &AccessLogEntry) {
+        self.log_entry_to(entry, None).await;
+    }
+
+    /// Записывает подготовленную запись в access log server/location блока или в глобальный
+    pub async fn log_entry_to(&self, entry: &AccessLogEntry, target: Option<&AccessLogDirective>) {
+        // Выборка применяется ко всем access log, ошибки записываются всегда
+        if !self.sampler.should_log(entry) {
             return;
         }
+        let entry = &self.scrubber.entry(entry);
+
+        let result = match target {
+            Some(AccessLogDirective::Off) => return,
+            Some(AccessLogDirective::File { path, format }) => {
+                let format = format.as_deref().unwrap_or(&self.config.access_log.format);
+                self.writers.get(path).write_line(&self.format_entry_as(entry, format))
+            }
+            None if !self.config.access_log.enabled => return,
+            None => self.write_to_file(&self.format_entry(entry)).await,
+        };
 
-        let req = session.req_header();
-        let client_addr = session.client_addr()
-            .map(|addr| addr.to_string())
-            .unwrap_or_else(|| "unknown".to_string());
+        if let Err(e) = result {
+            error!("Failed to write access log: {}", e);
+        }
 
+        // Также логируем через tracing для консоли
+        info!(
+            client_ip = %entry.client_ip,
+            method = %entry.method,
+            uri = %entry.uri,
+            status = entry.status,
+            duration_ms = entry.duration_ms,
+            handled_by = %entry.handled_by,
+            "HTTP Request"
+        );
+    }
+
+    /// Форматирует запись в формате глобального access log
+    fn format_entry(&self, entry: &AccessLogEntry) -> String {
+        self.format_entry_as(entry, &self.config.access_log.format)
+    }
+
+    /// Форматирует запись в JSON или nginx-like формате
+    fn format_entry_as(&self, entry: &AccessLogEntry, format: &str) -> String {
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs();
 
-        let log_entry = if self.config.access_log.format == "json" {
+        if format == "json" {
             // JSON формат
             json!({
                 "timestamp": timestamp,
                 "level": "INFO",
                 "message": "HTTP Request",
                 "fields": {
-                    "client_ip": client_addr,
-                    "method": req.method.as_str(),
-                    "uri": req.uri.to_string(),
-                    "version": format!("{:?}", req.version),
-                    "status": response_status,
-                    "response_size": response_size,
-                    "duration_ms": duration_ms,
-                    "user_agent": req.headers.get("user-agent")
-                        .and_then(|h| h.to_str().ok())
-                        .unwrap_or("-"),
-                    "referer": req.headers.get("referer")
-                        .and_then(|h| h.to_str().ok())
-                        .unwrap_or("-"),
-                    "host": req.headers.get("host")
-                        .and_then(|h| h.to_str().ok())
-                        .unwrap_or("-"),
-                    "x_forwarded_for": req.headers.get("x-forwarded-for")
-                        .and_then(|h| h.to_str().ok())
-                        .unwrap_or("-"),
-                    "x_real_ip": req.headers.get("x-real-ip")
-                        .and_then(|h| h.to_str().ok())
-                        .unwrap_or("-")
+                    "client_ip": entry.client_ip,
+                    "method": entry.method,
+                    "uri": entry.uri,
+                    "version": entry.version,
+                    "status": entry.status,
+                    "response_size": entry.response_size,
+                    "duration_ms": entry.duration_ms,
+                    "user_agent": entry.user_agent,
+                    "referer": entry.referer,
+                    "host": entry.host,
+                    "x_forwarded_for": entry.x_forwarded_for,
+                    "x_real_ip": entry.x_real_ip,
+                    "service": entry.service,
+                    "handled_by": entry.handled_by,
+                    "upstream": entry.upstream,
+                    "scheme": entry.scheme,
+                    "client_aborted": entry.client_aborted,
+                    "upstream_addr": entry.upstream_addr
                 }
             }).to_string()
         } else {
             // Nginx-like формат
             format!(
-                "{} - - [{}] \"{} {} {:?}\" {} {} \"{}\" \"{}\"",
-                client_addr,
+                "{} - - [{}] \"{} {} {}\" {} {} \"{}\" \"{}\" {} {} {} {} {} {}",
+                entry.client_ip,
                 format_timestamp(timestamp),
-                req.method.as_str(),
-                req.uri,
-                req.version,
-                response_status,
-                response_size,
-                req.headers.get("referer")
-                    .and_then(|h| h.to_str().ok())
-                    .unwrap_or("-"),
-                req.headers.get("user-agent")
-                    .and_then(|h| h.to_str().ok())
-                    .unwrap_or("-")
+                entry.method,
+                entry.uri,
+                entry.version,
+                entry.status,
+                entry.response_size,
+                entry.referer,
+                entry.user_agent,
+                entry.service,
+                entry.handled_by,
+                entry.upstream,
+                entry.scheme,
+                if entry.client_aborted { "client_aborted" } else { "-" },
+                entry.upstream_addr
             )
+        }
+    }
+
+    /// Логирует структурированное событие (например, смену состояния бэкенда)
+    pub async fn log_event(&self, level: &str, event_type: &str, message: &str, fields: serde_json::Value) {
+        if !self.config.error_log.enabled {
+            return;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let message = self.scrubber.text(message);
+
+        let log_entry = if self.config.error_log.format == "json" {
+            json!({
+                "timestamp": timestamp,
+                "level": level,
+                "message": message,
+                "fields": fields,
+            }).to_string()
+        } else {
+            format!("[{}] [{}] {} {}", format_timestamp(timestamp), event_type, message, fields)
         };
 
-        // Записываем в файл
         if let Err(e) = self.write_to_file(&log_entry).await {
-            error!("Failed to write access log: {}", e);
+            error!("Failed to write error log: {}", e);
         }
-
-        // Также логируем через tracing для консоли
-        info!(
-            client_ip = %client_addr,
-            method = %req.method,
-            uri = %req.uri,
-            status = response_status,
-            duration_ms = duration_ms,
-            "HTTP Request"
-        );
     }
 
     /// Записывает лог в файл
     async fn write_to_file(&self, log_entry: &str) -> Result<(), std::io::Error> {
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.config.access_log.path)?;
-        
-        writeln!(file, "{}", log_entry)?;
-        file.flush()?;
-        Ok(())
+        self.writers.get(&self.config.access_log.path).write_line(log_entry)
+    }
+
+    /// Переоткрывает файлы access log после ротации
+    pub fn reopen(&self) {
+        self.writers.reopen_all();
     }
 }
 
 /// Структура для логирования ошибок
 pub struct ErrorLogger {
+    writer: LogWriter,
+    scrubber: LogScrubber,
     config: LoggingConfig,
 }
 
 impl ErrorLogger {
     pub fn new(config: LoggingConfig) -> Self {
-        Self { config }
+        Self {
+            writer: LogWriter::with_rotation(&config.error_log.path, RotationPolicy::from_config(&config.error_log)),
+            scrubber: LogScrubber::new(&config.scrub),
+            config,
+        }
     }
 
     /// Логирует ошибку
@@ -186,6 +339,12 @@ impl ErrorLogger {
             return;
         }
 
+        let message = self.scrubber.text(message);
+        let details = details.map(|details| self.scrubber.text(details));
+        let details = details.as_deref();
+        let uri = uri.map(|uri| self.scrubber.text(uri));
+        let uri = uri.as_deref();
+
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
@@ -232,14 +391,12 @@ impl ErrorLogger {
 
     /// Записывает лог в файл
     async fn write_to_file(&self, log_entry: &str) -> Result<(), std::io::Error> {
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.config.error_log.path)?;
-        
-        writeln!(file, "{}", log_entry)?;
-        file.flush()?;
-        Ok(())
+        self.writer.write_line(log_entry)
+    }
+
+    /// Переоткрывает файл error log после ротации
+    pub fn reopen(&self) {
+        self.writer.reopen();
     }
 }
 
@@ -252,8 +409,8 @@ fn format_timestamp(timestamp: u64) -> String {
 /// Макросы для удобного логирования
 #[macro_export]
 macro_rules! log_request {
-    ($logger:expr, $session:expr, $status:expr, $size:expr, $duration:expr) => {
-        $logger.log_request($session, $status, $size, $duration).await
+    ($logger:expr, $session:expr, $ctx:expr, $status:expr, $size:expr, $duration:expr) => {
+        $logger.log_request($session, $ctx, $status, $size, $duration).await
     };
 }
 
@@ -274,12 +431,15 @@ macro_rules! log_error {
 pub struct LoggingMiddleware {
     access_logger: AccessLogger,
     error_logger: ErrorLogger,
+    /// Лог решений балансировщика (None - logging.decisions_log не задан)
+    decisions_log: Option<DecisionsLog>,
 }
 
 impl LoggingMiddleware {
     pub fn new(config: LoggingConfig) -> Self {
         Self {
             access_logger: AccessLogger::new(config.clone()),
+            decisions_log: config.decisions_log.as_ref().map(DecisionsLog::new),
             error_logger: ErrorLogger::new(config),
         }
     }
@@ -291,6 +451,19 @@ impl LoggingMiddleware {
     pub fn error_logger(&self) -> &ErrorLogger {
         &self.error_logger
     }
+
+    pub fn decisions_log(&self) -> Option<&DecisionsLog> {
+        self.decisions_log.as_ref()
+    }
+
+    /// Переоткрывает все файлы логов (SIGUSR1 после ротации)
+    pub fn reopen_logs(&self) {
+        self.access_logger.reopen();
+        self.error_logger.reopen();
+        if let Some(decisions_log) = &self.decisions_log {
+            decisions_log.reopen();
+        }
+    }
 }
 
 #[cfg(test)]
@@ -312,17 +485,27 @@ mod tests {
                 enabled: true,
                 path: log_path.to_string_lossy().to_string(),
                 format: "json".to_string(),
+                rotate: None,
+                sampling: None,
             },
             error_log: LogConfig {
                 enabled: false,
                 path: "".to_string(),
                 format: "text".to_string(),
+                rotate: None,
+                sampling: None,
             },
             metrics: MetricsConfig {
                 enabled: false,
                 endpoint: "/metrics".to_string(),
                 port: 9090,
+                namespace: None,
+                bind: "127.0.0.1".to_string(),
+                bearer_token: None,
+                process_interval: 15,
             },
+            scrub: Default::default(),
+            decisions_log: None,
         };
 
         let logger = AccessLogger::new(config);
@@ -335,4 +518,205 @@ mod tests {
         let content = fs::read_to_string(&log_path).unwrap();
         assert!(content.contains("Test"));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_local_preflight_access_log_line() {
+        let temp_dir = tempdir().unwrap();
+        let log_path = temp_dir.path().join("access.log");
+
+        let config = LoggingConfig {
+            format: "json".to_string(),
+            level: "info".to_string(),
+            access_log: LogConfig {
+                enabled: true,
+                path: log_path.to_string_lossy().to_string(),
+                format: "json".to_string(),
+                rotate: None,
+                sampling: None,
+            },
+            error_log: LogConfig {
+                enabled: false,
+                path: "".to_string(),
+                format: "text".to_string(),
+                rotate: None,
+                sampling: None,
+            },
+            metrics: MetricsConfig {
+                enabled: false,
+                endpoint: "/metrics".to_string(),
+                port: 9090,
+                namespace: None,
+                bind: "127.0.0.1".to_string(),
+                bearer_token: None,
+                process_interval: 15,
+            },
+            scrub: Default::default(),
+            decisions_log: None,
+        };
+
+        let mut ctx = RequestContext::new();
+        ctx.handle_locally("cors_preflight");
+
+        let entry = AccessLogEntry {
+            method: "OPTIONS".to_string(),
+            uri: "/api/test".to_string(),
+            status: 200,
+            service: ctx.service_label().to_string(),
+            handled_by: ctx.handled_by.as_str().to_string(),
+            ..Default::default()
+        };
+
+        let logger = AccessLogger::new(config);
+        logger.log_entry(&entry).await;
+
+        let content = fs::read_to_string(&log_path).unwrap();
+        let line: serde_json::Value = serde_json::from_str(content.lines().next().unwrap()).unwrap();
+        assert_eq!(line["fields"]["method"], "OPTIONS");
+        assert_eq!(line["fields"]["service"], "local");
+        assert_eq!(line["fields"]["handled_by"], "proxy_local");
+    }
+
+    #[tokio::test]
+    async fn test_per_server_access_logs() {
+        let temp_dir = tempdir().unwrap();
+        let global_path = temp_dir.path().join("access.log");
+        let api_path = temp_dir.path().join("api.access.log");
+        let admin_path = temp_dir.path().join("admin.access.log");
+
+        let mut config = crate::config::Config::default().logging;
+        config.access_log.path = global_path.to_string_lossy().to_string();
+        config.access_log.format = "json".to_string();
+        let logger = AccessLogger::new(config);
+
+        let api = AccessLogDirective::File { path: api_path.to_string_lossy().to_string(), format: None };
+        let admin = AccessLogDirective::File {
+            path: admin_path.to_string_lossy().to_string(),
+            format: Some("text".to_string()),
+        };
+        let entry = |host: &str| AccessLogEntry {
+            host: host.to_string(),
+            method: "GET".to_string(),
+            uri: "/".to_string(),
+            status: 200,
+            ..Default::default()
+        };
+
+        logger.log_entry_to(&entry("api.example.com"), Some(&api)).await;
+        logger.log_entry_to(&entry("admin.example.com"), Some(&admin)).await;
+        logger.log_entry_to(&entry("api.example.com"), Some(&api)).await;
+        logger.log_entry_to(&entry("other.example.com"), None).await;
+        logger.log_entry_to(&entry("api.example.com"), Some(&AccessLogDirective::Off)).await;
+
+        let api_log = fs::read_to_string(&api_path).unwrap();
+        assert_eq!(api_log.lines().count(), 2);
+        for line in api_log.lines() {
+            let line: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(line["fields"]["host"], "api.example.com");
+        }
+
+        let admin_log = fs::read_to_string(&admin_path).unwrap();
+        assert_eq!(admin_log.lines().count(), 1);
+        assert!(admin_log.contains("\"GET / "));
+
+        let global_log = fs::read_to_string(&global_path).unwrap();
+        assert_eq!(global_log.lines().count(), 1);
+        assert!(global_log.contains("other.example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_access_log_sampling() {
+        let temp_dir = tempdir().unwrap();
+        let entry = |status: u16| AccessLogEntry { status, uri: format!("/{}", status), ..Default::default() };
+
+        for (rate, expected) in [(0.0, vec!["/404", "/502"]), (1.0, vec!["/200", "/301", "/404", "/502"])] {
+            let log_path = temp_dir.path().join(format!("access-{}.log", rate));
+            let mut config = crate::config::Config::default().logging;
+            config.access_log.path = log_path.to_string_lossy().to_string();
+            config.access_log.format = "json".to_string();
+            config.access_log.sampling = Some(crate::config::LogSamplingConfig { rate, slow_ms: None });
+            let logger = AccessLogger::new(config);
+
+            for status in [200, 301, 404, 502] {
+                logger.log_entry(&entry(status)).await;
+            }
+
+            let content = fs::read_to_string(&log_path).unwrap_or_default();
+            let uris: Vec<String> = content
+                .lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["fields"]["uri"].as_str().unwrap().to_string())
+                .collect();
+            assert_eq!(uris, expected, "sampling rate {}", rate);
+        }
+    }
+
+    #[test]
+    fn test_access_log_upstream_field() {
+        use crate::types::UpstreamTarget;
+
+        let logger = AccessLogger::new(crate::config::Config::default().logging);
+        let mut ctx = RequestContext::new();
+        ctx.upstream_target = UpstreamTarget::Direct("127.0.0.1:8081".parse().unwrap());
+
+        let entry = AccessLogEntry {
+            upstream: ctx.upstream_target.to_string(),
+            ..Default::default()
+        };
+        let line: serde_json::Value = serde_json::from_str(&logger.format_entry(&entry)).unwrap();
+        assert_eq!(line["fields"]["upstream"], "127.0.0.1:8081");
+
+        ctx.scheme = "https";
+        let entry = AccessLogEntry {
+            scheme: ctx.scheme.to_string(),
+            ..Default::default()
+        };
+        let line: serde_json::Value = serde_json::from_str(&logger.format_entry(&entry)).unwrap();
+        assert_eq!(line["fields"]["scheme"], "https");
+
+        ctx.upstream_target = UpstreamTarget::Named("core_api".to_string());
+        assert_eq!(ctx.upstream_target.to_string(), "core_api");
+        assert_eq!(UpstreamTarget::None.to_string(), "-");
+    }
+
+    #[tokio::test]
+    async fn test_sensitive_values_never_logged() {
+        let temp_dir = tempdir().unwrap();
+        let entry = AccessLogEntry {
+            method: "GET".to_string(),
+            uri: "/password/reset?token=s3cr3t-reset&lang=en".to_string(),
+            referer: "https://app.example.com/login?api_key=k3y-value".to_string(),
+            user_agent: "curl/8.0".to_string(),
+            status: 200,
+            ..Default::default()
+        };
+
+        for format in ["json", "text"] {
+            let mut config = crate::config::Config::default().logging;
+            config.access_log.path = temp_dir.path().join(format!("access.{}.log", format)).to_string_lossy().to_string();
+            config.access_log.format = format.to_string();
+            config.error_log.path = temp_dir.path().join(format!("error.{}.log", format)).to_string_lossy().to_string();
+            config.error_log.format = format.to_string();
+
+            AccessLogger::new(config.clone()).log_entry(&entry).await;
+            ErrorLogger::new(config.clone())
+                .log_error(
+                    "upstream_timeout",
+                    "Request /password/reset?token=s3cr3t-reset timed out",
+                    Some("GET /login?code=oauth-c0de"),
+                    Some("10.0.0.1"),
+                    Some("/password/reset?token=s3cr3t-reset"),
+                )
+                .await;
+
+            let access = fs::read_to_string(&config.access_log.path).unwrap();
+            let error = fs::read_to_string(&config.error_log.path).unwrap();
+            for content in [&access, &error] {
+                for secret in ["s3cr3t-reset", "k3y-value", "oauth-c0de"] {
+                    assert!(!content.contains(secret), "{} leaked in {} log: {}", secret, format, content);
+                }
+            }
+            assert!(access.contains("token=[REDACTED]&lang=en"));
+            assert!(access.contains("curl/8.0"));
+            assert!(error.contains("10.0.0.1"));
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::AccessLogEntry;
+use crate::config::LogSamplingConfig;
+
+/// Выборка записей access log (logging.access_log.sampling).
+/// Ошибки (4xx/5xx), прерванные клиентом и медленные запросы записываются всегда
+#[derive(Debug)]
+pub struct AccessLogSampler {
+    rate: f64,
+    slow_ms: Option<u64>,
+    /// Счетчик успешных запросов для равномерной выборки без генератора случайных чисел
+    seen: AtomicU64,
+}
+
+impl AccessLogSampler {
+    pub fn new(config: Option<&LogSamplingConfig>) -> Self {
+        Self {
+            rate: config.map_or(1.0, |c| c.rate.clamp(0.0, 1.0)),
+            slow_ms: config.and_then(|c| c.slow_ms),
+            seen: AtomicU64::new(0),
+        }
+    }
+
+    /// Записывать ли запрос в access log
+    pub fn should_log(&self, entry: &AccessLogEntry) -> bool {
+        if entry.status >= 400 || entry.client_aborted {
+            return true;
+        }
+        if self.slow_ms.is_some_and(|slow_ms| entry.duration_ms >= slow_ms) {
+            return true;
+        }
+        if self.rate >= 1.0 {
+            return true;
+        }
+        if self.rate <= 0.0 {
+            return false;
+        }
+        // Запрос n записывается, когда n * rate переходит к следующему целому
+        let n = self.seen.fetch_add(1, Ordering::Relaxed);
+        ((n + 1) as f64 * self.rate).floor() > (n as f64 * self.rate).floor()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(status: u16, duration_ms: u64) -> AccessLogEntry {
+        AccessLogEntry { status, duration_ms, ..Default::default() }
+    }
+
+    #[test]
+    fn test_fraction_and_slow_requests() {
+        let sampler = AccessLogSampler::new(Some(&LogSamplingConfig { rate: 0.25, slow_ms: Some(500) }));
+        let logged = (0..1000).filter(|_| sampler.should_log(&entry(200, 10))).count();
+        assert_eq!(logged, 250);
+
+        // Медленные, ошибочные и прерванные клиентом запросы не участвуют в выборке
+        assert!((0..10).all(|_| sampler.should_log(&entry(200, 500))));
+        assert!((0..10).all(|_| sampler.should_log(&entry(503, 1))));
+        let aborted = AccessLogEntry { client_aborted: true, ..entry(200, 1) };
+        assert!((0..10).all(|_| sampler.should_log(&aborted)));
+
+        // Без настройки записывается все
+        let sampler = AccessLogSampler::new(None);
+        assert!((0..10).all(|_| sampler.should_log(&entry(200, 1))));
+    }
+}
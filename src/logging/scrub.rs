@@ -0,0 +1,119 @@
+use once_cell::sync::{Lazy, OnceCell};
+use regex::Regex;
+use std::borrow::Cow;
+
+use crate::config::LogScrubConfig;
+use super::AccessLogEntry;
+
+/// Замена скрытого значения
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Скрывает чувствительные данные перед записью в access/error log и отладочный вывод
+#[derive(Debug, Clone)]
+pub struct LogScrubber {
+    /// `name=value` для скрываемых query-параметров, в том числе в ключе кеша через `|` (None - список пуст)
+    query: Option<Regex>,
+    headers: Vec<String>,
+    mask_authorization: bool,
+}
+
+static GLOBAL: OnceCell<LogScrubber> = OnceCell::new();
+static DEFAULT: Lazy<LogScrubber> = Lazy::new(|| LogScrubber::new(&LogScrubConfig::default()));
+
+/// Устанавливает настройки для кода без доступа к логгерам (ключи кеша и т.п.)
+pub fn install(scrubber: LogScrubber) {
+    let _ = GLOBAL.set(scrubber);
+}
+
+/// Общий экземпляр: установленный через install или с настройками по умолчанию
+pub fn global() -> &'static LogScrubber {
+    GLOBAL.get().unwrap_or(&DEFAULT)
+}
+
+impl LogScrubber {
+    pub fn new(config: &LogScrubConfig) -> Self {
+        let query = (!config.query_params.is_empty()).then(|| {
+            let names: Vec<String> = config.query_params.iter().map(|name| regex::escape(name)).collect();
+            Regex::new(&format!(r#"(?i)((?:^|[?&;|])(?:{})=)[^&;#|\s"]*"#, names.join("|")))
+                .expect("escaped parameter names form a valid regex")
+        });
+        Self {
+            query,
+            headers: config.headers.iter().map(|name| name.to_ascii_lowercase()).collect(),
+            mask_authorization: config.mask_authorization,
+        }
+    }
+
+    /// Заменяет значения скрываемых query-параметров в URI или произвольном тексте
+    pub fn text<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match &self.query {
+            Some(query) => query.replace_all(text, format!("${{1}}{}", REDACTED).as_str()),
+            None => Cow::Borrowed(text),
+        }
+    }
+
+    /// Значение заголовка для лога
+    pub fn header<'a>(&self, name: &str, value: &'a str) -> Cow<'a, str> {
+        if self.headers.iter().any(|header| header.eq_ignore_ascii_case(name)) {
+            return Cow::Borrowed(REDACTED);
+        }
+        if self.mask_authorization && name.eq_ignore_ascii_case("authorization") {
+            return match value.split_whitespace().next() {
+                Some(scheme) if scheme.len() < value.trim().len() => Cow::Owned(format!("{} {}", scheme, REDACTED)),
+                _ => Cow::Borrowed(REDACTED),
+            };
+        }
+        // В Referer часто попадает URL страницы сброса пароля с токеном
+        if name.eq_ignore_ascii_case("referer") {
+            return self.text(value);
+        }
+        Cow::Borrowed(value)
+    }
+
+    /// Копия записи access log со скрытыми URI и заголовками
+    pub fn entry(&self, entry: &AccessLogEntry) -> AccessLogEntry {
+        AccessLogEntry {
+            uri: self.text(&entry.uri).into_owned(),
+            host: self.header("host", &entry.host).into_owned(),
+            user_agent: self.header("user-agent", &entry.user_agent).into_owned(),
+            referer: self.header("referer", &entry.referer).into_owned(),
+            x_forwarded_for: self.header("x-forwarded-for", &entry.x_forwarded_for).into_owned(),
+            x_real_ip: self.header("x-real-ip", &entry.x_real_ip).into_owned(),
+            ..entry.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_and_header_scrubbing() {
+        let scrubber = LogScrubber::new(&LogScrubConfig::default());
+
+        assert_eq!(
+            scrubber.text("/reset?token=s3cr3t&lang=en&API_KEY=abc123"),
+            "/reset?token=[REDACTED]&lang=en&API_KEY=[REDACTED]"
+        );
+        assert_eq!(scrubber.text("/oauth/callback?state=x&code=4/0Ad"), "/oauth/callback?state=x&code=[REDACTED]");
+        // Параметр с похожим именем не трогается
+        assert_eq!(scrubber.text("/search?zipcode=12345"), "/search?zipcode=12345");
+        assert!(matches!(scrubber.text("/plain/path"), Cow::Borrowed(_)));
+
+        assert_eq!(scrubber.header("Authorization", "Bearer eyJhbGciOi"), "Bearer [REDACTED]");
+        assert_eq!(scrubber.header("authorization", "opaque"), REDACTED);
+        assert_eq!(scrubber.header("Cookie", "session=abc"), REDACTED);
+        assert_eq!(scrubber.header("referer", "https://a.example/reset?token=s3cr3t"), "https://a.example/reset?token=[REDACTED]");
+        assert_eq!(scrubber.header("user-agent", "curl/8.0"), "curl/8.0");
+
+        let custom = LogScrubber::new(&LogScrubConfig {
+            query_params: vec!["session".to_string()],
+            headers: vec!["User-Agent".to_string()],
+            mask_authorization: false,
+        });
+        assert_eq!(custom.text("/a?token=t&session=s"), "/a?token=t&session=[REDACTED]");
+        assert_eq!(custom.header("user-agent", "curl/8.0"), REDACTED);
+        assert_eq!(custom.header("authorization", "Bearer abc"), "Bearer abc");
+    }
+}
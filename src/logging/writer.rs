@@ -0,0 +1,401 @@
+use async_trait::async_trait;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+use pingora_core::server::ShutdownWatch;
+use pingora_core::services::background::BackgroundService;
+
+use super::LoggingMiddleware;
+use crate::config::{LogConfig, LogRotateConfig};
+
+/// Параметры ротации файла лога по размеру
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RotationPolicy {
+    pub max_size: u64,
+    pub keep: usize,
+    pub compress: bool,
+}
+
+impl RotationPolicy {
+    /// Ротация из `rotate` секции лога; некорректная секция отключает ротацию
+    pub fn from_config(config: &LogConfig) -> Option<Self> {
+        Self::from_rotate(&config.path, config.rotate.as_ref())
+    }
+
+    /// Ротация файла `path` по секции rotate
+    pub fn from_rotate(path: &str, rotate: Option<&LogRotateConfig>) -> Option<Self> {
+        let rotate = rotate?;
+        match rotate.validate().and_then(|_| rotate.max_size_bytes()) {
+            Ok(max_size) => Some(Self {
+                max_size,
+                keep: rotate.keep,
+                compress: rotate.compress,
+            }),
+            Err(e) => {
+                warn!("Log rotation for {} disabled: {}", path, e);
+                None
+            }
+        }
+    }
+}
+
+/// Открытый файл и его текущий размер
+#[derive(Debug)]
+struct OpenFile {
+    writer: BufWriter<File>,
+    size: u64,
+}
+
+/// Файл лога с открытым дескриптором. Файл открывается при первой записи
+/// и заново после `reopen` (ротация логов) или ротации по размеру.
+#[derive(Debug)]
+pub struct LogWriter {
+    path: String,
+    file: Mutex<Option<OpenFile>>,
+    rotation: Option<RotationPolicy>,
+    /// Сдвиг ротированных файлов и их сжатие не выполняются одновременно
+    rotation_lock: Arc<Mutex<()>>,
+}
+
+impl LogWriter {
+    pub fn new(path: &str) -> Self {
+        Self::with_rotation(path, None)
+    }
+
+    pub fn with_rotation(path: &str, rotation: Option<RotationPolicy>) -> Self {
+        Self {
+            path: path.to_string(),
+            file: Mutex::new(None),
+            rotation,
+            rotation_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Записывает строку и сбрасывает буфер на диск. Ротация выполняется под той же
+    /// блокировкой, поэтому строки не теряются и не перемешиваются.
+    pub fn write_line(&self, line: &str) -> std::io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        if file.is_none() {
+            let handle = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            let size = handle.metadata().map(|m| m.len()).unwrap_or(0);
+            *file = Some(OpenFile { writer: BufWriter::new(handle), size });
+        }
+
+        let open = file.as_mut().unwrap();
+        let result = writeln!(open.writer, "{}", line).and_then(|_| open.writer.flush());
+        if result.is_err() {
+            // При ошибке файл будет открыт заново при следующей записи
+            *file = None;
+            return result;
+        }
+
+        open.size += line.len() as u64 + 1;
+        if let Some(policy) = self.rotation.filter(|policy| open.size >= policy.max_size) {
+            match self.rotate(policy) {
+                // Следующая запись откроет новый файл
+                Ok(()) => *file = None,
+                Err(e) => {
+                    // Запись продолжается в текущий файл, повтор после следующих max_size байт
+                    eprintln!("adq-pingora: failed to rotate log {}: {}", self.path, e);
+                    open.size = 0;
+                }
+            }
+        }
+        result
+    }
+
+    /// Сдвигает ротированные файлы (file.1 -> file.2, ...) и переименовывает текущий в file.1
+    fn rotate(&self, policy: RotationPolicy) -> std::io::Result<()> {
+        {
+            let _guard = self.rotation_lock.lock().unwrap();
+            for gz in [false, true] {
+                let oldest = rotated_path(&self.path, policy.keep, gz);
+                if oldest.exists() {
+                    fs::remove_file(oldest)?;
+                }
+            }
+            for index in (1..policy.keep).rev() {
+                for gz in [false, true] {
+                    let from = rotated_path(&self.path, index, gz);
+                    if from.exists() {
+                        fs::rename(from, rotated_path(&self.path, index + 1, gz))?;
+                    }
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1, false))?;
+        }
+
+        if policy.compress {
+            let path = self.path.clone();
+            let lock = self.rotation_lock.clone();
+            std::thread::spawn(move || {
+                let _guard = lock.lock().unwrap();
+                compress_rotated(&path, policy.keep);
+            });
+        }
+        Ok(())
+    }
+
+    /// Закрывает дескриптор: следующая запись откроет файл по пути заново
+    pub fn reopen(&self) {
+        self.file.lock().unwrap().take();
+    }
+}
+
+/// Путь ротированного файла: access.log.1 или access.log.1.gz
+fn rotated_path(path: &str, index: usize, gz: bool) -> PathBuf {
+    PathBuf::from(format!("{}.{}{}", path, index, if gz { ".gz" } else { "" }))
+}
+
+/// Сжимает ротированные файлы, у которых еще нет .gz версии
+fn compress_rotated(path: &str, keep: usize) {
+    for index in 1..=keep {
+        let plain = rotated_path(path, index, false);
+        if !plain.exists() {
+            continue;
+        }
+        let gz = rotated_path(path, index, true);
+        let tmp = PathBuf::from(format!("{}.tmp", gz.display()));
+        let result = (|| -> std::io::Result<()> {
+            let mut encoder = GzEncoder::new(File::create(&tmp)?, Compression::default());
+            std::io::copy(&mut File::open(&plain)?, &mut encoder)?;
+            encoder.finish()?.sync_all()?;
+            fs::rename(&tmp, &gz)?;
+            fs::remove_file(&plain)
+        })();
+        if let Err(e) = result {
+            eprintln!("adq-pingora: failed to compress rotated log {}: {}", plain.display(), e);
+            let _ = fs::remove_file(&tmp);
+        }
+    }
+}
+
+/// Реестр файлов логов по пути: запросы разных server блоков с одним
+/// access_log пишут через общий дескриптор
+#[derive(Debug, Default)]
+pub struct LogWriters {
+    writers: Mutex<HashMap<String, Arc<LogWriter>>>,
+    /// Ротация для всех файлов реестра
+    rotation: Option<RotationPolicy>,
+}
+
+impl LogWriters {
+    pub fn with_rotation(rotation: Option<RotationPolicy>) -> Self {
+        Self {
+            writers: Mutex::new(HashMap::new()),
+            rotation,
+        }
+    }
+
+    pub fn get(&self, path: &str) -> Arc<LogWriter> {
+        self.writers
+            .lock()
+            .unwrap()
+            .entry(path.to_string())
+            .or_insert_with(|| Arc::new(LogWriter::with_rotation(path, self.rotation)))
+            .clone()
+    }
+
+    /// Переоткрывает все файлы (после ротации)
+    pub fn reopen_all(&self) {
+        for writer in self.writers.lock().unwrap().values() {
+            writer.reopen();
+        }
+    }
+}
+
+/// Переоткрывает файлы логов по сигналу SIGUSR1, как `nginx -s reopen`
+pub struct LogReopenService {
+    logging: Arc<LoggingMiddleware>,
+}
+
+impl LogReopenService {
+    pub fn new(logging: Arc<LoggingMiddleware>) -> Self {
+        Self { logging }
+    }
+}
+
+#[async_trait]
+impl BackgroundService for LogReopenService {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut reopen = match signal(SignalKind::user_defined1()) {
+            Ok(reopen) => reopen,
+            Err(e) => {
+                warn!("Failed to listen for SIGUSR1, log reopening disabled: {}", e);
+                return;
+            }
+        };
+
+        loop {
+            tokio::select! {
+                _ = reopen.recv() => {
+                    info!("SIGUSR1 received, reopening log files");
+                    self.logging.reopen_logs();
+                }
+                _ = shutdown.changed() => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_reopen_after_rotation() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("access.log");
+        let rotated = temp_dir.path().join("access.log.1");
+
+        let writers = LogWriters::default();
+        let writer = writers.get(path.to_str().unwrap());
+        writer.write_line("first").unwrap();
+
+        // logrotate переименовывает файл, запись продолжается в старый дескриптор до reopen
+        fs::rename(&path, &rotated).unwrap();
+        writer.write_line("second").unwrap();
+        writers.reopen_all();
+        writer.write_line("third").unwrap();
+
+        assert_eq!(fs::read_to_string(&rotated).unwrap(), "first\nsecond\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third\n");
+        assert!(Arc::ptr_eq(&writer, &writers.get(path.to_str().unwrap())));
+    }
+
+    /// Строки лога и его ротированных файлов (включая сжатые), от старых к новым
+    fn read_lines(path: &str, keep: usize) -> Vec<String> {
+        use std::io::Read;
+
+        let mut files: Vec<String> = (1..=keep)
+            .rev()
+            .filter_map(|index| {
+                if let Ok(content) = fs::read_to_string(rotated_path(path, index, false)) {
+                    return Some(content);
+                }
+                let gz = File::open(rotated_path(path, index, true)).ok()?;
+                let mut content = String::new();
+                flate2::read::GzDecoder::new(gz).read_to_string(&mut content).unwrap();
+                Some(content)
+            })
+            .collect();
+        files.push(fs::read_to_string(path).unwrap_or_default());
+        files.iter().flat_map(|content| content.lines().map(str::to_string)).collect()
+    }
+
+    #[test]
+    fn test_rotation_by_size() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("access.log");
+        let path = path.to_str().unwrap();
+        let policy = RotationPolicy { max_size: 100, keep: 3, compress: false };
+        let writer = LogWriter::with_rotation(path, Some(policy));
+
+        // Строки по 10 байт: ротация после каждых 10 строк
+        for i in 0..25 {
+            writer.write_line(&format!("line-{:04}", i)).unwrap();
+        }
+
+        assert!(rotated_path(path, 1, false).exists());
+        assert!(rotated_path(path, 2, false).exists());
+        assert!(!rotated_path(path, 3, false).exists());
+        assert_eq!(fs::read_to_string(rotated_path(path, 2, false)).unwrap().lines().count(), 10);
+        assert_eq!(fs::read_to_string(rotated_path(path, 1, false)).unwrap().lines().count(), 10);
+        let lines = read_lines(path, policy.keep);
+        let expected: Vec<String> = (0..25).map(|i| format!("line-{:04}", i)).collect();
+        assert_eq!(lines, expected);
+
+        // Старше keep файлы удаляются
+        for i in 25..60 {
+            writer.write_line(&format!("line-{:04}", i)).unwrap();
+        }
+        assert!(rotated_path(path, 3, false).exists());
+        assert!(!rotated_path(path, 4, false).exists());
+        let expected: Vec<String> = (30..60).map(|i| format!("line-{:04}", i)).collect();
+        assert_eq!(read_lines(path, policy.keep), expected);
+    }
+
+    #[test]
+    fn test_rotation_with_compression() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("error.log");
+        let path = path.to_str().unwrap();
+        let policy = RotationPolicy { max_size: 100, keep: 5, compress: true };
+        let writers = LogWriters::with_rotation(Some(policy));
+        let writer = writers.get(path);
+
+        for i in 0..35 {
+            writer.write_line(&format!("line-{:04}", i)).unwrap();
+        }
+
+        // Сжатие выполняется в фоне
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while (1..=3).any(|index| !rotated_path(path, index, true).exists())
+            || (1..=3).any(|index| rotated_path(path, index, false).exists())
+        {
+            assert!(std::time::Instant::now() < deadline, "rotated logs were not compressed");
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+
+        let expected: Vec<String> = (0..35).map(|i| format!("line-{:04}", i)).collect();
+        assert_eq!(read_lines(path, policy.keep), expected);
+    }
+
+    #[test]
+    fn test_compressed_archives_bounded_by_keep() {
+        use std::io::Read;
+
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("access.log");
+        let path = path.to_str().unwrap();
+        let config: LogRotateConfig = serde_yaml::from_str("max_size: 100\nmax_files: 2\ncompress: true").unwrap();
+        let policy = RotationPolicy::from_rotate(path, Some(&config)).unwrap();
+        assert_eq!(policy, RotationPolicy { max_size: 100, keep: 2, compress: true });
+        let writer = LogWriter::with_rotation(path, Some(policy));
+
+        // Пять ротаций по 10 строк
+        for i in 0..50 {
+            writer.write_line(&format!("line-{:04}", i)).unwrap();
+        }
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while (1..=2).any(|index| rotated_path(path, index, false).exists() || !rotated_path(path, index, true).exists()) {
+            assert!(std::time::Instant::now() < deadline, "rotated logs were not compressed");
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        assert!(!rotated_path(path, 3, true).exists());
+        assert!(!rotated_path(path, 3, false).exists());
+
+        let mut newest = String::new();
+        flate2::read::GzDecoder::new(File::open(rotated_path(path, 1, true)).unwrap())
+            .read_to_string(&mut newest)
+            .unwrap();
+        let expected: String = (40..50).map(|i| format!("line-{:04}\n", i)).collect();
+        assert_eq!(newest, expected);
+    }
+
+    #[test]
+    fn test_rotation_failure_keeps_writing() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("access.log");
+        let path = path.to_str().unwrap();
+        // Каталог на месте file.1 не дает переименовать текущий файл
+        fs::create_dir(rotated_path(path, 1, false)).unwrap();
+        let writer = LogWriter::with_rotation(path, Some(RotationPolicy { max_size: 20, keep: 1, compress: false }));
+
+        for i in 0..5 {
+            writer.write_line(&format!("line-{:04}", i)).unwrap();
+        }
+        assert_eq!(fs::read_to_string(path).unwrap().lines().count(), 5);
+    }
+}
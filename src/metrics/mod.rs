@@ -1,16 +1,67 @@
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use prometheus::{
-    register_int_counter, register_int_counter_vec, register_histogram, register_gauge,
-    IntCounter, IntCounterVec, Histogram, Gauge,
+    register_int_counter, register_int_counter_vec, register_histogram, register_histogram_vec,
+    register_gauge, register_int_gauge, register_int_gauge_vec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Histogram, HistogramVec, Gauge, HistogramOpts, Opts,
 };
 use log::info;
+use crate::rate_limit::RateLimitDecision;
+use crate::types::RequestContext;
+
+pub mod server;
+pub use server::MetricsApp;
+
+/// Префикс имен метрик (metrics.namespace), задается до первой регистрации
+static NAMESPACE: OnceCell<String> = OnceCell::new();
+
+/// Проверяет префикс метрик и убирает завершающие "_" ("adq_" -> "adq").
+/// Префикс должен быть допустимым идентификатором Prometheus без ":".
+pub fn normalize_namespace(namespace: &str) -> Result<String, String> {
+    let namespace = namespace.trim().trim_end_matches('_');
+    let mut chars = namespace.chars();
+    let valid = match chars.next() {
+        None => true,
+        Some(first) => {
+            (first.is_ascii_alphabetic() || first == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+    };
+    if !valid {
+        return Err(format!("invalid metrics namespace '{}': expected [a-zA-Z_][a-zA-Z0-9_]*", namespace));
+    }
+    Ok(namespace.to_string())
+}
+
+/// Устанавливает префикс имен метрик. Вызывается до init_metrics: метрики,
+/// зарегистрированные раньше, остаются без префикса.
+pub fn set_namespace(namespace: &str) -> Result<(), String> {
+    let namespace = normalize_namespace(namespace)?;
+    NAMESPACE
+        .set(namespace)
+        .map_err(|_| "metrics namespace is already set".to_string())
+}
+
+fn namespaced_opts(namespace: Option<&str>, name: &str, help: &str) -> Opts {
+    let opts = Opts::new(name, help);
+    match namespace {
+        Some(namespace) if !namespace.is_empty() => opts.namespace(namespace),
+        _ => opts,
+    }
+}
+
+pub(crate) fn opts(name: &str, help: &str) -> Opts {
+    namespaced_opts(NAMESPACE.get().map(String::as_str), name, help)
+}
+
+pub(crate) fn histogram_opts(name: &str, help: &str) -> HistogramOpts {
+    HistogramOpts::from(opts(name, help))
+}
 
 /// Общее количество HTTP запросов
 pub static HTTP_REQUESTS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
     register_int_counter_vec!(
-        "http_requests_total",
-        "Total HTTP requests",
-        &["method", "status", "service"]
+        opts("http_requests_total", "Total HTTP requests"),
+        &["method", "status", "service", "handled_by"]
     )
     .expect("Failed to register http_requests_total metric")
 });
@@ -18,8 +69,7 @@ pub static HTTP_REQUESTS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
 /// Длительность обработки HTTP запросов
 pub static HTTP_REQUEST_DURATION: Lazy<Histogram> = Lazy::new(|| {
     register_histogram!(
-        "http_request_duration_seconds",
-        "HTTP request duration in seconds"
+        histogram_opts("http_request_duration_seconds", "HTTP request duration in seconds")
     )
     .expect("Failed to register http_request_duration_seconds metric")
 });
@@ -27,51 +77,411 @@ pub static HTTP_REQUEST_DURATION: Lazy<Histogram> = Lazy::new(|| {
 /// Количество соединений к upstream серверам
 pub static UPSTREAM_CONNECTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
     register_int_counter_vec!(
-        "upstream_connections_total",
-        "Total upstream connections",
+        opts("upstream_connections_total", "Total upstream connections"),
         &["upstream", "status"]
     )
     .expect("Failed to register upstream_connections_total metric")
 });
 
-/// Количество срабатываний rate limit
+/// Количество срабатываний rate limit.
+/// Устарела: оставлена на один релиз, используйте rate_limit_decisions_total{decision="limited"}
 pub static RATE_LIMIT_HITS: Lazy<IntCounter> = Lazy::new(|| {
     register_int_counter!(
-        "rate_limit_hits_total",
-        "Total rate limit hits"
+        opts("rate_limit_hits_total", "Total rate limit hits (deprecated, use rate_limit_decisions_total)")
     )
     .expect("Failed to register rate_limit_hits_total metric")
 });
 
+/// Решения rate limiter по location и типу ключа
+pub static RATE_LIMIT_DECISIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("rate_limit_decisions_total", "Total rate limiting decisions"),
+        &["location", "key_type", "decision"]
+    )
+    .expect("Failed to register rate_limit_decisions_total metric")
+});
+
+/// Наблюдаемая частота запросов ключа (req/s) в момент принятия решения
+pub static RATE_LIMIT_OBSERVED_RATE: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        histogram_opts("rate_limit_observed_rate", "Observed request rate per rate limiting key at decision time")
+            .buckets(vec![1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0]),
+        &["location"]
+    )
+    .expect("Failed to register rate_limit_observed_rate metric")
+});
+
+/// Количество запросов, заблокированных по User-Agent, по имени правила
+pub static UA_BLOCKED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("ua_blocked_total", "Total requests blocked by User-Agent rules"),
+        &["rule"]
+    )
+    .expect("Failed to register ua_blocked_total metric")
+});
+
+/// Количество срабатываний правил по заголовкам запроса, по имени правила
+pub static HEADER_RULE_MATCHES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("header_rule_matches_total", "Total requests answered by header rules"),
+        &["rule"]
+    )
+    .expect("Failed to register header_rule_matches_total metric")
+});
+
 /// Количество retry попыток
 pub static RETRY_ATTEMPTS: Lazy<IntCounterVec> = Lazy::new(|| {
     register_int_counter_vec!(
-        "retry_attempts_total",
-        "Total retry attempts",
+        opts("retry_attempts_total", "Total retry attempts"),
         &["service", "result"]
     )
     .expect("Failed to register retry_attempts_total metric")
 });
 
+/// Количество таймаутов upstream по типу (connect, first_byte, read)
+pub static UPSTREAM_TIMEOUTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("upstream_timeouts_total", "Total upstream timeouts"),
+        &["kind"]
+    )
+    .expect("Failed to register upstream_timeouts_total metric")
+});
+
+/// Количество бэкендов в режиме draining после reload
+pub static UPSTREAM_BACKENDS_DRAINING: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        opts("upstream_backends_draining", "Number of backends draining after removal on reload"),
+        &["upstream"]
+    )
+    .expect("Failed to register upstream_backends_draining metric")
+});
+
+/// Количество fallback ответов при открытом circuit breaker
+pub static CIRCUIT_BREAKER_FALLBACKS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("circuit_breaker_fallbacks_total", "Total fallback responses served while the circuit breaker is open"),
+        &["upstream", "type"]
+    )
+    .expect("Failed to register circuit_breaker_fallbacks_total metric")
+});
+
+/// Заглушки fallback_response, отданные при сбоях upstream, по location и условию
+pub static LOCATION_FALLBACKS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("location_fallbacks_total", "Total fallback_response stubs served instead of upstream failures"),
+        &["location", "condition"]
+    )
+    .expect("Failed to register location_fallbacks_total metric")
+});
+
+/// Результаты буферизации ответов upstream (complete, spilled, failed)
+pub static RESPONSE_BUFFERING: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("response_buffering_total", "Total buffered upstream responses by result"),
+        &["result"]
+    )
+    .expect("Failed to register response_buffering_total metric")
+});
+
+/// Объем памяти, занятый буферизованными телами ответов
+pub static BUFFERED_RESPONSE_BYTES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(opts("buffered_response_bytes", "Bytes of upstream response bodies held in buffers"))
+        .expect("Failed to register buffered_response_bytes metric")
+});
+
+/// Запросы с Idempotency-Key по результату (stored, replayed, conflict, not_stored)
+pub static IDEMPOTENCY_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("idempotency_requests_total", "Requests with an idempotency key by result"),
+        &["result"]
+    )
+    .expect("Failed to register idempotency_requests_total metric")
+});
+
+/// Объем памяти, занятый сохраненными ответами идемпотентных запросов
+pub static IDEMPOTENCY_STORED_BYTES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(opts("idempotency_stored_bytes", "Bytes of responses stored for idempotency keys"))
+        .expect("Failed to register idempotency_stored_bytes metric")
+});
+
+/// Вызовы WASM фильтров запросов по результату (continue, respond, error)
+pub static WASM_FILTER_RESULTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("wasm_filter_results_total", "WASM request filter invocations by result"),
+        &["result"]
+    )
+    .expect("Failed to register wasm_filter_results_total metric")
+});
+
+/// Запросы, прерванные клиентом (закрыл соединение до окончания ответа)
+pub static CLIENT_ABORTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("client_aborts_total", "Requests aborted by the client disconnecting"),
+        &["service"]
+    )
+    .expect("Failed to register client_aborts_total metric")
+});
+
+/// Байты тела ответа, отправленные клиентам до их отключения
+pub static CLIENT_ABORTED_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("client_aborted_response_bytes_total", "Response body bytes sent before the client disconnected"),
+        &["service"]
+    )
+    .expect("Failed to register client_aborted_response_bytes_total metric")
+});
+
+/// Запросы в обработке по бэкендам (по ним выбирает least_requests)
+pub static UPSTREAM_BACKEND_IN_FLIGHT: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        opts("upstream_backend_in_flight", "Requests in flight per upstream backend"),
+        &["upstream", "backend"]
+    )
+    .expect("Failed to register upstream_backend_in_flight metric")
+});
+
+/// Отказы upstream с max_conns по причине (queue_full, timeout)
+pub static UPSTREAM_CONCURRENCY_REJECTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("upstream_concurrency_rejections_total", "Requests rejected by the upstream concurrency limit"),
+        &["upstream", "reason"]
+    )
+    .expect("Failed to register upstream_concurrency_rejections_total metric")
+});
+
+/// Запросы, ожидающие свободного слота upstream (queue)
+pub static UPSTREAM_QUEUE_LENGTH: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        opts("upstream_queue_length", "Requests waiting for a free upstream slot"),
+        &["upstream"]
+    )
+    .expect("Failed to register upstream_queue_length metric")
+});
+
+/// Время ожидания свободного слота upstream в очереди
+pub static UPSTREAM_QUEUE_WAIT: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        histogram_opts("upstream_queue_wait_seconds", "Time requests spent waiting for a free upstream slot")
+            .buckets(vec![0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]),
+        &["upstream"]
+    )
+    .expect("Failed to register upstream_queue_wait_seconds metric")
+});
+
+/// Повторные проверки устаревших записей кеша условным запросом к upstream
+pub static CACHE_REVALIDATIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("cache_revalidations_total", "Expired cache entries revalidated with the upstream by outcome"),
+        &["result"]
+    )
+    .expect("Failed to register cache_revalidations_total metric")
+});
+
+/// Ответы, сжатые прокси, по алгоритму (gzip, br, zstd)
+pub static COMPRESSED_RESPONSES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("compressed_responses_total", "Responses compressed by the proxy"),
+        &["algorithm"]
+    )
+    .expect("Failed to register compressed_responses_total metric")
+});
+
+/// События смены состояния бэкендов по типу (backend_up, backend_down)
+pub static HEALTH_EVENTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("health_events_total", "Backend health state transitions"),
+        &["type"]
+    )
+    .expect("Failed to register health_events_total metric")
+});
+
+/// Отправки событий в webhook по результату (delivered, failed, skipped, dropped)
+pub static HEALTH_WEBHOOK_DELIVERIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("health_webhook_deliveries_total", "Health event webhook deliveries"),
+        &["result"]
+    )
+    .expect("Failed to register health_webhook_deliveries_total metric")
+});
+
+/// Соединения прогрева upstream при старте по результату (success, failure)
+pub static UPSTREAM_WARMUP_CONNECTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("upstream_warmup_connections_total", "Upstream connections opened by startup warmup"),
+        &["upstream", "result"]
+    )
+    .expect("Failed to register upstream_warmup_connections_total metric")
+});
+
+/// Длительность стадий обработки запроса в request_filter
+pub static REQUEST_STAGE_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        histogram_opts("request_stage_duration_seconds", "Request pipeline stage duration in seconds")
+            .buckets(vec![0.00001, 0.00005, 0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1]),
+        &["stage"]
+    )
+    .expect("Failed to register request_stage_duration_seconds metric")
+});
+
+/// Длительность разрешения DNS имен
+pub static DNS_RESOLUTION_DURATION: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        histogram_opts("dns_resolution_duration_seconds", "DNS resolution duration in seconds").buckets(vec![0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0])
+    )
+    .expect("Failed to register dns_resolution_duration_seconds metric")
+});
+
+/// Количество ошибок разрешения DNS имен по причине (timeout, error)
+pub static DNS_RESOLUTION_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("dns_resolution_failures_total", "Total DNS resolution failures"),
+        &["reason"]
+    )
+    .expect("Failed to register dns_resolution_failures_total metric")
+});
+
+/// Проверки клиентов challenge_mode по результату (issued, passed, failed)
+pub static CHALLENGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("challenges_total", "Total proof-of-work challenges by result"),
+        &["result"]
+    )
+    .expect("Failed to register challenges_total metric")
+});
+
+/// Трейлеры ответов upstream по результату (forwarded - клиенту HTTP/2, dropped - клиенту HTTP/1.x)
+pub static RESPONSE_TRAILERS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("response_trailers_total", "Total upstream response trailers by result"),
+        &["result"]
+    )
+    .expect("Failed to register response_trailers_total metric")
+});
+
+/// Запросы reload конфигурации через admin API по результату (applied, rejected)
+pub static CONFIG_RELOADS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        opts("config_reloads_total", "Total configuration reloads requested via the admin API"),
+        &["result"]
+    )
+    .expect("Failed to register config_reloads_total metric")
+});
+
 /// Активные соединения
 pub static ACTIVE_CONNECTIONS: Lazy<Gauge> = Lazy::new(|| {
     register_gauge!(
-        "active_connections",
-        "Number of active connections"
+        opts("active_connections", "Number of active connections")
     )
     .expect("Failed to register active_connections metric")
 });
 
-/// Инициализация метрик
+/// Учитывает завершенный HTTP запрос в http_requests_total и http_request_duration_seconds
+pub fn record_http_request(method: &str, status: u16, ctx: &RequestContext) {
+    HTTP_REQUESTS_TOTAL
+        .with_label_values(&[method, &status.to_string(), ctx.service_label(), ctx.handled_by.as_str()])
+        .inc();
+    HTTP_REQUEST_DURATION.observe(ctx.start_time.elapsed().as_secs_f64());
+}
+
+/// Инициализация метрик: регистрирует все метрики с текущим префиксом
 pub fn init_metrics() {
+    Lazy::force(&HTTP_REQUESTS_TOTAL);
+    Lazy::force(&HTTP_REQUEST_DURATION);
+    Lazy::force(&UPSTREAM_CONNECTIONS);
+    Lazy::force(&RATE_LIMIT_HITS);
+    Lazy::force(&RATE_LIMIT_DECISIONS);
+    Lazy::force(&RATE_LIMIT_OBSERVED_RATE);
+    Lazy::force(&UA_BLOCKED);
+    Lazy::force(&HEADER_RULE_MATCHES);
+    Lazy::force(&RETRY_ATTEMPTS);
+    Lazy::force(&UPSTREAM_TIMEOUTS);
+    Lazy::force(&UPSTREAM_BACKENDS_DRAINING);
+    Lazy::force(&CIRCUIT_BREAKER_FALLBACKS);
+    Lazy::force(&LOCATION_FALLBACKS);
+    Lazy::force(&RESPONSE_BUFFERING);
+    Lazy::force(&BUFFERED_RESPONSE_BYTES);
+    Lazy::force(&IDEMPOTENCY_REQUESTS);
+    Lazy::force(&IDEMPOTENCY_STORED_BYTES);
+    Lazy::force(&WASM_FILTER_RESULTS);
+    Lazy::force(&CLIENT_ABORTS);
+    Lazy::force(&CLIENT_ABORTED_BYTES);
+    Lazy::force(&UPSTREAM_BACKEND_IN_FLIGHT);
+    Lazy::force(&UPSTREAM_CONCURRENCY_REJECTIONS);
+    Lazy::force(&UPSTREAM_QUEUE_LENGTH);
+    Lazy::force(&UPSTREAM_QUEUE_WAIT);
+    Lazy::force(&CACHE_REVALIDATIONS);
+    Lazy::force(&COMPRESSED_RESPONSES);
+    Lazy::force(&HEALTH_EVENTS);
+    Lazy::force(&HEALTH_WEBHOOK_DELIVERIES);
+    Lazy::force(&UPSTREAM_WARMUP_CONNECTIONS);
+    Lazy::force(&REQUEST_STAGE_DURATION);
+    Lazy::force(&DNS_RESOLUTION_DURATION);
+    Lazy::force(&DNS_RESOLUTION_FAILURES);
+    Lazy::force(&CHALLENGES);
+    Lazy::force(&CONFIG_RELOADS);
+    Lazy::force(&RESPONSE_TRAILERS);
+    Lazy::force(&ACTIVE_CONNECTIONS);
+    crate::process_metrics::register();
+
     info!("Prometheus metrics initialized");
+    if let Some(namespace) = NAMESPACE.get().filter(|ns| !ns.is_empty()) {
+        info!("Metric names are prefixed with '{}_'", namespace);
+    }
     info!("Available metrics:");
     info!("  - http_requests_total");
     info!("  - http_request_duration_seconds");
     info!("  - upstream_connections_total");
-    info!("  - rate_limit_hits_total");
+    info!("  - rate_limit_hits_total (deprecated)");
+    info!("  - rate_limit_decisions_total");
+    info!("  - rate_limit_observed_rate");
+    info!("  - ua_blocked_total");
+    info!("  - header_rule_matches_total");
     info!("  - retry_attempts_total");
+    info!("  - upstream_timeouts_total");
+    info!("  - upstream_backends_draining");
+    info!("  - circuit_breaker_fallbacks_total");
+    info!("  - location_fallbacks_total");
+    info!("  - response_buffering_total");
+    info!("  - buffered_response_bytes");
+    info!("  - idempotency_requests_total");
+    info!("  - idempotency_stored_bytes");
+    info!("  - client_aborts_total");
+    info!("  - client_aborted_response_bytes_total");
+    info!("  - upstream_backend_in_flight");
+    info!("  - upstream_concurrency_rejections_total");
+    info!("  - upstream_queue_length");
+    info!("  - upstream_queue_wait_seconds");
+    info!("  - cache_revalidations_total");
+    info!("  - compressed_responses_total");
+    info!("  - response_trailers_total");
+    info!("  - health_events_total");
+    info!("  - health_webhook_deliveries_total");
+    info!("  - request_stage_duration_seconds");
+    info!("  - dns_resolution_duration_seconds");
+    info!("  - dns_resolution_failures_total");
     info!("  - active_connections");
+    info!("  - proxy_uptime_seconds, proxy_worker_threads");
+    info!("  - process_resident_memory_bytes, process_open_fds, process_threads");
+}
+
+/// Учитывает решение rate limiter и наблюдаемую частоту запросов ключа
+pub fn record_rate_limit_decision(
+    location: &str,
+    key_type: &str,
+    decision: RateLimitDecision,
+    observed_rate: Option<isize>,
+) {
+    RATE_LIMIT_DECISIONS
+        .with_label_values(&[location, key_type, decision.as_str()])
+        .inc();
+    if let Some(rate) = observed_rate {
+        RATE_LIMIT_OBSERVED_RATE
+            .with_label_values(&[location])
+            .observe(rate as f64);
+    }
+    if decision == RateLimitDecision::Limited {
+        RATE_LIMIT_HITS.inc();
+    }
 }
 
 #[cfg(test)]
@@ -81,8 +491,56 @@ mod tests {
     #[test]
     fn test_metrics_initialization() {
         // Просто проверяем, что метрики создаются без ошибок
-        let _ = HTTP_REQUESTS_TOTAL.with_label_values(&["GET", "200", "core_api"]);
+        let _ = HTTP_REQUESTS_TOTAL.with_label_values(&["GET", "200", "core_api", "upstream"]);
         let _ = HTTP_REQUEST_DURATION.observe(0.1);
         let _ = RATE_LIMIT_HITS.inc();
     }
+
+    #[test]
+    fn test_local_preflight_counted_under_local_service() {
+        let mut ctx = RequestContext::new();
+        ctx.handle_locally("cors_preflight");
+
+        let counter = HTTP_REQUESTS_TOTAL.with_label_values(&["OPTIONS", "200", "local", "proxy_local"]);
+        let before = counter.get();
+        record_http_request("OPTIONS", 200, &ctx);
+        assert_eq!(counter.get(), before + 1);
+    }
+
+    #[test]
+    fn test_namespace_prefixes_registered_names() {
+        let registry = prometheus::Registry::new();
+        let counter = IntCounterVec::new(
+            namespaced_opts(Some("adq"), "http_requests_total", "Total HTTP requests"),
+            &["method"],
+        )
+        .unwrap();
+        let histogram = Histogram::with_opts(HistogramOpts::from(namespaced_opts(
+            Some("adq"),
+            "http_request_duration_seconds",
+            "HTTP request duration in seconds",
+        )))
+        .unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+        registry.register(Box::new(histogram.clone())).unwrap();
+        counter.with_label_values(&["GET"]).inc();
+        histogram.observe(0.1);
+
+        let names: Vec<String> = registry.gather().iter().map(|family| family.get_name().to_string()).collect();
+        assert_eq!(names, vec!["adq_http_request_duration_seconds", "adq_http_requests_total"]);
+
+        // Без префикса имена не меняются
+        assert_eq!(namespaced_opts(None, "active_connections", "h").fq_name(), "active_connections");
+        assert_eq!(namespaced_opts(Some(""), "active_connections", "h").fq_name(), "active_connections");
+    }
+
+    #[test]
+    fn test_namespace_validation() {
+        assert_eq!(normalize_namespace("adq_").unwrap(), "adq");
+        assert_eq!(normalize_namespace("adq_proxy").unwrap(), "adq_proxy");
+        assert_eq!(normalize_namespace("").unwrap(), "");
+        assert!(normalize_namespace("1adq").is_err());
+        assert!(normalize_namespace("adq-proxy").is_err());
+        assert!(normalize_namespace("adq:proxy").is_err());
+    }
 }
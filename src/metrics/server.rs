@@ -0,0 +1,484 @@
+use async_trait::async_trait;
+use http::{Response, StatusCode};
+use prometheus::{Encoder, TextEncoder};
+use std::sync::Arc;
+
+use pingora_core::apps::http_app::ServeHttp;
+use pingora_core::protocols::http::ServerSession;
+
+use crate::challenge::Challenge;
+use crate::config::MetricsConfig;
+use crate::flight_recorder::FlightRecorder;
+use crate::health_events::HealthEvents;
+use crate::reload::ConfigReloader;
+use crate::status::{render_html, StatusSource};
+
+/// Журнал последних событий смены состояния бэкендов
+pub const HEALTH_EVENTS_PATH: &str = "/_admin/health/events";
+
+/// HTML страница состояния upstream и бэкендов
+pub const HEALTH_PAGE_PATH: &str = "/_admin/health";
+
+/// Последние запросы с 5xx и ошибками соединения: GET - записи, DELETE - очистка
+pub const FLIGHT_RECORDER_PATH: &str = "/_admin/flight_recorder";
+
+/// Режимы challenge_mode location: GET - список, POST ?location=/path/&mode=on|off|config - переключение
+pub const CHALLENGE_PATH: &str = "/_admin/challenge";
+
+/// POST - перечитать и проверить конфигурацию, применить ее при отсутствии ошибок.
+/// Отдается только при заданном bearer_token
+pub const RELOAD_PATH: &str = "/_admin/reload";
+
+/// HTTP приложение для отдачи метрик Prometheus и служебных /_admin эндпоинтов
+/// с необязательной авторизацией по bearer токену
+pub struct MetricsApp {
+    endpoint: String,
+    bearer_token: Option<String>,
+    health_events: Option<Arc<HealthEvents>>,
+    status: Option<Arc<StatusSource>>,
+    flight_recorder: Option<Arc<FlightRecorder>>,
+    reloader: Option<Arc<ConfigReloader>>,
+    challenge: Option<Arc<Challenge>>,
+}
+
+impl MetricsApp {
+    pub fn new(config: &MetricsConfig) -> Self {
+        Self {
+            endpoint: config.endpoint.clone(),
+            bearer_token: config.bearer_token.clone().filter(|token| !token.is_empty()),
+            health_events: None,
+            status: None,
+            flight_recorder: None,
+            reloader: None,
+            challenge: None,
+        }
+    }
+
+    pub fn with_health_events(mut self, health_events: Arc<HealthEvents>) -> Self {
+        self.health_events = Some(health_events);
+        self
+    }
+
+    pub fn with_status(mut self, status: Arc<StatusSource>) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn with_flight_recorder(mut self, flight_recorder: Arc<FlightRecorder>) -> Self {
+        self.flight_recorder = Some(flight_recorder);
+        self
+    }
+
+    /// Reloader для POST /_admin/reload; без bearer_token endpoint не отдается
+    pub fn with_reloader(mut self, reloader: Arc<ConfigReloader>) -> Self {
+        self.reloader = Some(reloader);
+        self
+    }
+
+    pub fn with_challenge(mut self, challenge: Arc<Challenge>) -> Self {
+        self.challenge = Some(challenge);
+        self
+    }
+
+    /// Проверяет заголовок Authorization, если токен настроен
+    fn authorized(&self, authorization: Option<&str>) -> bool {
+        let Some(token) = &self.bearer_token else {
+            return true;
+        };
+        authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|provided| constant_time_eq(provided.trim().as_bytes(), token.as_bytes()))
+    }
+}
+
+/// Значение параметра upstream строки запроса
+fn upstream_filter(query: Option<&str>) -> Option<&str> {
+    query_param(query, "upstream")
+}
+
+/// Непустое значение параметра строки запроса (без декодирования)
+fn query_param<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+        .filter(|value| !value.is_empty())
+}
+
+/// Сравнение без раннего выхода, чтобы время ответа не раскрывало токен
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn json_response(body: String) -> Response<Vec<u8>> {
+    json_status_response(StatusCode::OK, body)
+}
+
+fn json_status_response(status: StatusCode, body: String) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .header("Content-Length", body.len())
+        .body(body.into_bytes())
+        .unwrap()
+}
+
+fn text_response(status: StatusCode, body: &str) -> Response<Vec<u8>> {
+    let mut builder = Response::builder()
+        .status(status)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .header("Content-Length", body.len());
+    if status == StatusCode::UNAUTHORIZED {
+        builder = builder.header("WWW-Authenticate", "Bearer realm=\"metrics\"");
+    }
+    builder.body(body.as_bytes().to_vec()).unwrap()
+}
+
+#[async_trait]
+impl ServeHttp for MetricsApp {
+    async fn response(&self, http_session: &mut ServerSession) -> Response<Vec<u8>> {
+        let request = http_session.req_header();
+        let health_events = self
+            .health_events
+            .as_ref()
+            .filter(|_| request.uri.path() == HEALTH_EVENTS_PATH);
+        let status = self.status.as_ref().filter(|_| request.uri.path() == HEALTH_PAGE_PATH);
+        let flight_recorder = self
+            .flight_recorder
+            .as_ref()
+            .filter(|_| request.uri.path() == FLIGHT_RECORDER_PATH);
+        // Reload меняет работающий прокси: без токена endpoint не существует
+        let reloader = self
+            .reloader
+            .as_ref()
+            .filter(|_| request.uri.path() == RELOAD_PATH && self.bearer_token.is_some());
+        let challenge = self.challenge.as_ref().filter(|_| request.uri.path() == CHALLENGE_PATH);
+        if request.uri.path() != self.endpoint
+            && health_events.is_none()
+            && status.is_none()
+            && flight_recorder.is_none()
+            && reloader.is_none()
+            && challenge.is_none()
+        {
+            return text_response(StatusCode::NOT_FOUND, "Not Found\n");
+        }
+
+        let authorization = request.headers.get("authorization").and_then(|v| v.to_str().ok());
+        if !self.authorized(authorization) {
+            return text_response(StatusCode::UNAUTHORIZED, "Unauthorized\n");
+        }
+
+        if let Some(status) = status {
+            if request.method != http::Method::GET {
+                return text_response(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed\n");
+            }
+            let upstream = upstream_filter(request.uri.query());
+            let body = render_html(&status.snapshot(upstream).await, upstream);
+            return Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "text/html; charset=utf-8")
+                .header("Cache-Control", "no-store")
+                .header("Content-Length", body.len())
+                .body(body.into_bytes())
+                .unwrap();
+        }
+
+        if let Some(health_events) = health_events {
+            if request.method != http::Method::GET {
+                return text_response(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed\n");
+            }
+            return json_response(health_events.to_json());
+        }
+
+        if let Some(flight_recorder) = flight_recorder {
+            return match request.method {
+                http::Method::GET => json_response(flight_recorder.to_json()),
+                http::Method::DELETE => {
+                    json_response(serde_json::json!({ "cleared": flight_recorder.clear() }).to_string())
+                }
+                _ => text_response(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed\n"),
+            };
+        }
+
+        if let Some(challenge) = challenge {
+            return match request.method {
+                http::Method::GET => json_response(challenge.to_json()),
+                http::Method::POST => {
+                    let query = request.uri.query();
+                    let mode = match query_param(query, "mode") {
+                        Some("on") => Some(true),
+                        Some("off") => Some(false),
+                        Some("config") => None,
+                        _ => return text_response(StatusCode::BAD_REQUEST, "mode must be on, off or config\n"),
+                    };
+                    let Some(location) = query_param(query, "location") else {
+                        return text_response(StatusCode::BAD_REQUEST, "location is required\n");
+                    };
+                    match challenge.set_mode(location, mode) {
+                        Ok(()) => {
+                            log::info!("challenge_mode for location {} set to {:?} via admin API", location, mode);
+                            json_response(challenge.to_json())
+                        }
+                        Err(e) => text_response(StatusCode::NOT_FOUND, &format!("{}\n", e)),
+                    }
+                }
+                _ => text_response(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed\n"),
+            };
+        }
+
+        if let Some(reloader) = reloader {
+            if request.method != http::Method::POST {
+                return text_response(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed\n");
+            }
+            // Чтение и проверка файлов конфигурации - блокирующие операции
+            let reloader = reloader.clone();
+            let outcome = match tokio::task::spawn_blocking(move || reloader.reload()).await {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    log::error!("Configuration reload failed: {}", e);
+                    return text_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error\n");
+                }
+            };
+            let status = if outcome.applied { StatusCode::OK } else { StatusCode::UNPROCESSABLE_ENTITY };
+            return json_status_response(status, outcome.to_json());
+        }
+
+        let encoder = TextEncoder::new();
+        let mut buffer = Vec::new();
+        if let Err(e) = encoder.encode(&prometheus::gather(), &mut buffer) {
+            log::error!("Failed to encode metrics: {}", e);
+            return text_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error\n");
+        }
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", encoder.format_type())
+            .header("Content-Length", buffer.len())
+            .body(buffer)
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(bearer_token: Option<&str>) -> MetricsApp {
+        MetricsApp {
+            endpoint: "/metrics".to_string(),
+            bearer_token: bearer_token.map(str::to_string),
+            health_events: None,
+            status: None,
+            flight_recorder: None,
+            reloader: None,
+            challenge: None,
+        }
+    }
+
+    async fn scrape(app: &MetricsApp, request: &str) -> Response<Vec<u8>> {
+        let stream = tokio_test::io::Builder::new().read(request.as_bytes()).build();
+        let mut session = ServerSession::new_http1(Box::new(stream));
+        session.read_request().await.unwrap();
+        app.response(&mut session).await
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized_scrape_rejected() {
+        let app = app(Some("s3cret"));
+
+        let response = scrape(&app, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().contains_key("www-authenticate"));
+
+        let response = scrape(&app, "GET /metrics HTTP/1.1\r\nAuthorization: Bearer wrong\r\n\r\n").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = scrape(&app, "GET /metrics HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n").await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_open_endpoint_without_token() {
+        let app = app(None);
+        let response = scrape(&app, "GET /metrics HTTP/1.1\r\n\r\n").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = scrape(&app, "GET /other HTTP/1.1\r\n\r\n").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_health_events_endpoint() {
+        use crate::health_events::HealthEvent;
+
+        let response = scrape(&app(None), "GET /_admin/health/events HTTP/1.1\r\n\r\n").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let events = Arc::new(HealthEvents::new(10));
+        events.record(HealthEvent::new("core_api", Some("10.0.0.1:8080".to_string()), false, "health_check")).await;
+        let app = app(Some("s3cret")).with_health_events(events);
+
+        let response = scrape(&app, "GET /_admin/health/events HTTP/1.1\r\n\r\n").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = scrape(&app, "GET /_admin/health/events HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["events"][0]["backend"], "10.0.0.1:8080");
+        assert_eq!(body["events"][0]["new_state"], "down");
+    }
+
+    #[tokio::test]
+    async fn test_flight_recorder_endpoint() {
+        use crate::config::LogScrubConfig;
+        use crate::flight_recorder::FlightRecord;
+        use crate::logging::LogScrubber;
+
+        let recorder = Arc::new(FlightRecorder::new(10, LogScrubber::new(&LogScrubConfig::default())));
+        recorder.push(FlightRecord {
+            timestamp: "2026-10-16T10:00:00.000Z".to_string(),
+            request_id: "req-1".to_string(),
+            method: "GET".to_string(),
+            uri: "/api/orders".to_string(),
+            request_headers: Default::default(),
+            status: 502,
+            upstream: Some("core_api".to_string()),
+            backend: Some("10.0.0.1:8080".to_string()),
+            retries: 1,
+            duration_ms: 12,
+            upstream_ms: Some(10),
+            error: None,
+        });
+        let app = app(Some("s3cret")).with_flight_recorder(recorder.clone());
+
+        let response = scrape(&app, "GET /_admin/flight_recorder HTTP/1.1\r\n\r\n").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let auth = "Authorization: Bearer s3cret\r\n";
+        let response = scrape(&app, &format!("GET /_admin/flight_recorder HTTP/1.1\r\n{}\r\n", auth)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["records"][0]["request_id"], "req-1");
+        assert_eq!(body["records"][0]["status"], 502);
+
+        let response = scrape(&app, &format!("POST /_admin/flight_recorder HTTP/1.1\r\n{}\r\n", auth)).await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        let response = scrape(&app, &format!("DELETE /_admin/flight_recorder HTTP/1.1\r\n{}\r\n", auth)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body().as_slice(), br#"{"cleared":1}"#);
+        assert!(recorder.recent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reload_endpoint_rejects_invalid_config() {
+        let reloader = ConfigReloader::new(
+            "/nonexistent/adq-pingora.yaml",
+            &[],
+            Arc::new(crate::config::Config::default()),
+        );
+        let app = app(Some("s3cret")).with_reloader(Arc::new(reloader));
+
+        let response = scrape(&app, "POST /_admin/reload HTTP/1.1\r\n\r\n").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let auth = "Authorization: Bearer s3cret\r\n";
+        let response = scrape(&app, &format!("GET /_admin/reload HTTP/1.1\r\n{}\r\n", auth)).await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        let response = scrape(&app, &format!("POST /_admin/reload HTTP/1.1\r\n{}\r\n", auth)).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["applied"], false);
+        assert_eq!(body["errors"], 1);
+        let message = body["diagnostics"][0]["message"].as_str().unwrap();
+        assert!(message.starts_with("configuration file /nonexistent/adq-pingora.yaml test failed"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn test_reload_endpoint_requires_token() {
+        let reloader = ConfigReloader::new(
+            "/nonexistent/adq-pingora.yaml",
+            &[],
+            Arc::new(crate::config::Config::default()),
+        );
+        let app = app(None).with_reloader(Arc::new(reloader));
+
+        let response = scrape(&app, "POST /_admin/reload HTTP/1.1\r\n\r\n").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        // Метрики без токена по-прежнему открыты
+        assert_eq!(scrape(&app, "GET /metrics HTTP/1.1\r\n\r\n").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_challenge_toggle_endpoint() {
+        let mut config = crate::config::Config::default();
+        config.nginx_config = Some(
+            crate::config::NginxConfig::parse_config_content(
+                "server { listen 80; server_name shop.example.com; location /catalog/ { proxy_pass api; challenge_mode on; } }",
+            )
+            .unwrap(),
+        );
+        let challenge = Arc::new(Challenge::from_config(&config).unwrap());
+        let app = app(Some("s3cret")).with_challenge(challenge.clone());
+        let auth = "Authorization: Bearer s3cret\r\n";
+
+        let response = scrape(&app, "POST /_admin/challenge?location=/catalog/&mode=off HTTP/1.1\r\n\r\n").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let request = format!("POST /_admin/challenge?location=/catalog/&mode=off HTTP/1.1\r\n{}\r\n", auth);
+        let response = scrape(&app, &request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["locations"][0]["enabled"], false);
+        assert_eq!(body["locations"][0]["configured"], true);
+
+        let request = format!("POST /_admin/challenge?location=/catalog/&mode=maybe HTTP/1.1\r\n{}\r\n", auth);
+        assert_eq!(scrape(&app, &request).await.status(), StatusCode::BAD_REQUEST);
+        let request = format!("POST /_admin/challenge?location=/missing/&mode=on HTTP/1.1\r\n{}\r\n", auth);
+        assert_eq!(scrape(&app, &request).await.status(), StatusCode::NOT_FOUND);
+
+        let request = format!("POST /_admin/challenge?mode=config&location=/catalog/ HTTP/1.1\r\n{}\r\n", auth);
+        assert_eq!(scrape(&app, &request).await.status(), StatusCode::OK);
+        let response = scrape(&app, &format!("GET /_admin/challenge HTTP/1.1\r\n{}\r\n", auth)).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["locations"][0]["enabled"], true);
+        assert_eq!(body["locations"][0]["override"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_health_page_endpoint() {
+        use crate::drain::DrainTracker;
+        use crate::status::HealthChecks;
+        use pingora_load_balancing::{selection::RoundRobin, LoadBalancer};
+        use std::collections::HashMap;
+
+        let upstreams = HashMap::from([
+            ("core_api".to_string(), Arc::new(LoadBalancer::<RoundRobin>::try_from_iter(["10.0.0.1:8080"]).unwrap())),
+            ("billing_api".to_string(), Arc::new(LoadBalancer::<RoundRobin>::try_from_iter(["10.0.0.2:8081"]).unwrap())),
+        ]);
+        let status = StatusSource::new(
+            upstreams,
+            Arc::new(HealthChecks::default()),
+            Arc::new(DrainTracker::new(std::time::Duration::from_secs(30))),
+            None,
+            std::time::Instant::now(),
+        );
+        let app = app(Some("s3cret")).with_status(Arc::new(status));
+
+        let response = scrape(&app, "GET /_admin/health HTTP/1.1\r\n\r\n").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = scrape(&app, "GET /_admin/health HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/html; charset=utf-8");
+        let page = String::from_utf8(response.body().clone()).unwrap();
+        assert!(page.contains("<td>core_api</td><td>10.0.0.1:8080</td>"));
+        assert!(page.contains("<td>billing_api</td><td>10.0.0.2:8081</td>"));
+
+        let request = "GET /_admin/health?upstream=billing_api HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n";
+        let page = String::from_utf8(scrape(&app, request).await.body().clone()).unwrap();
+        assert!(page.contains("10.0.0.2:8081"));
+        assert!(!page.contains("10.0.0.1:8080"));
+    }
+}
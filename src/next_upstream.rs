@@ -0,0 +1,118 @@
+use pingora::prelude::*;
+use pingora_load_balancing::selection::RoundRobin;
+use pingora_load_balancing::Backend;
+
+use crate::config::{LocationBlock, NextUpstream};
+
+/// Методы, которые безопасно повторить на другом бэкенде после отправки запроса
+pub fn is_idempotent(method: &str) -> bool {
+    matches!(method, "GET" | "HEAD" | "OPTIONS" | "TRACE" | "PUT" | "DELETE")
+}
+
+/// Максимум повторов для location: proxy_next_upstream_tries включает первую попытку,
+/// без директивы (или 0) действует global.max_retries
+pub fn max_retries(location: Option<&LocationBlock>, default: u32) -> u32 {
+    match location.and_then(|l| l.proxy_next_upstream_tries) {
+        Some(tries) if tries > 0 => tries - 1,
+        _ => default,
+    }
+}
+
+/// Ошибка соединения или обмена с бэкендом попадает под условия error/timeout
+pub fn error_matches(next: &NextUpstream, e: &Error) -> bool {
+    match e.etype() {
+        ErrorType::ConnectTimedout | ErrorType::ReadTimedout | ErrorType::WriteTimedout => next.timeout,
+        // Статус ответа проверяется отдельно (retry_on_status)
+        ErrorType::HTTPStatus(_) => false,
+        _ => next.error,
+    }
+}
+
+/// Переходить ли к следующему бэкенду после ошибки.
+/// Запрос, уже переданный бэкенду, повторяется только для идемпотентных методов или с non_idempotent
+pub fn retry_on_error(next: &NextUpstream, e: &Error, method: &str, request_sent: bool) -> bool {
+    error_matches(next, e) && (!request_sent || next.non_idempotent || is_idempotent(method))
+}
+
+/// Переходить ли к следующему бэкенду после ответа с этим статусом
+pub fn retry_on_status(next: &NextUpstream, status: u16, method: &str) -> bool {
+    next.on_status(status) && (next.non_idempotent || is_idempotent(method))
+}
+
+/// Выбирает бэкенд, к которому запрос еще не отправлялся.
+/// Если все подходящие бэкенды уже пробовались, выбирается любой из них
+pub fn select_untried<F>(lb: &LoadBalancer<RoundRobin>, tried: &[String], accept: F) -> Option<Backend>
+where
+    F: Fn(&Backend, bool) -> bool,
+{
+    if !tried.is_empty() {
+        let untried = lb.select_with(b"", 256, |backend, healthy| {
+            accept(backend, healthy) && !tried.contains(&backend.addr.to_string())
+        });
+        if untried.is_some() {
+            return untried;
+        }
+    }
+    lb.select_with(b"", 256, accept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NginxConfig;
+
+    fn location(directives: &str) -> LocationBlock {
+        NginxConfig::parse_location_block("/api", &format!("proxy_pass api;\n{}", directives)).unwrap()
+    }
+
+    #[test]
+    fn test_502_tries_next_backend_404_does_not() {
+        let location = location("proxy_next_upstream error timeout http_502 http_504;");
+        let next = &location.proxy_next_upstream;
+        assert!(retry_on_status(next, 502, "GET"));
+        assert!(retry_on_status(next, 504, "GET"));
+        assert!(!retry_on_status(next, 404, "GET"));
+        assert!(!retry_on_status(next, 200, "GET"));
+
+        // Следующая попытка уходит на бэкенд, который еще не отвечал
+        let lb = LoadBalancer::<RoundRobin>::try_from_iter(["127.0.0.1:10001", "127.0.0.1:10002"]).unwrap();
+        let first = select_untried(&lb, &[], |_, healthy| healthy).unwrap();
+        let tried = vec![first.addr.to_string()];
+        for _ in 0..4 {
+            let second = select_untried(&lb, &tried, |_, healthy| healthy).unwrap();
+            assert_ne!(second.addr, first.addr);
+        }
+        // Все бэкенды уже пробовались: повтор идет на любой
+        let all = vec!["127.0.0.1:10001".to_string(), "127.0.0.1:10002".to_string()];
+        assert!(select_untried(&lb, &all, |_, healthy| healthy).is_some());
+    }
+
+    #[test]
+    fn test_non_idempotent_not_retried_after_send() {
+        let location = location("proxy_next_upstream error timeout http_502;");
+        let next = &location.proxy_next_upstream;
+        let read_error = Error::new(ErrorType::ReadError);
+        let connect_error = Error::new(ErrorType::ConnectRefused);
+
+        assert!(!retry_on_status(next, 502, "POST"));
+        assert!(!retry_on_error(next, &read_error, "POST", true));
+        // Бэкенд не получил запрос: повтор безопасен для любого метода
+        assert!(retry_on_error(next, &connect_error, "POST", false));
+        assert!(retry_on_error(next, &read_error, "GET", true));
+
+        let location = self::location("proxy_next_upstream http_502 non_idempotent;");
+        assert!(retry_on_status(&location.proxy_next_upstream, 502, "POST"));
+        // Ошибки не входят в условия
+        assert!(!retry_on_error(&location.proxy_next_upstream, &read_error, "GET", true));
+        assert!(!retry_on_error(&NextUpstream::off(), &Error::new(ErrorType::ConnectTimedout), "GET", false));
+    }
+
+    #[test]
+    fn test_tries_limit() {
+        assert_eq!(max_retries(Some(&location("proxy_next_upstream_tries 2;")), 3), 1);
+        assert_eq!(max_retries(Some(&location("proxy_next_upstream_tries 1;")), 3), 0);
+        assert_eq!(max_retries(Some(&location("proxy_next_upstream_tries 0;")), 3), 3);
+        assert_eq!(max_retries(Some(&location("")), 3), 3);
+        assert_eq!(max_retries(None, 5), 5);
+    }
+}
@@ -0,0 +1,166 @@
+use socket2::{Domain, Protocol, Socket, Type};
+use std::fmt;
+use std::net::SocketAddr;
+use std::os::unix::io::FromRawFd;
+
+use crate::config::ListenAddr;
+
+/// Код завершения при занятом порте (EADDRINUSE), отличный от ошибок конфигурации (1):
+/// его можно указать в RestartPreventExitStatus юнита systemd
+pub const EXIT_PORT_CONFLICT: i32 = 98;
+
+/// Процесс, слушающий порт
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortOwner {
+    pub pid: u32,
+    pub name: String,
+}
+
+/// Адрес listen, который не удалось занять
+#[derive(Debug)]
+pub struct PortConflict {
+    pub addr: SocketAddr,
+    pub error: std::io::Error,
+    pub owner: Option<PortOwner>,
+}
+
+impl fmt::Display for PortConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot listen on {}: {}", self.addr, self.error)?;
+        if let Some(owner) = &self.owner {
+            write!(f, " (in use by {} pid {})", owner.name, owner.pid)?;
+        }
+        Ok(())
+    }
+}
+
+/// Занимает адрес так же, как listener сервиса (SO_REUSEADDR, IPV6_V6ONLY), и сразу освобождает
+fn try_bind(listen: &ListenAddr) -> std::io::Result<()> {
+    let socket = Socket::new(Domain::for_address(listen.addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    if listen.addr.is_ipv6() {
+        socket.set_only_v6(listen.ipv6_only)?;
+    }
+    socket.bind(&listen.addr.into())?;
+    socket.listen(1)
+}
+
+/// Проверяет все адреса listen до запуска сервера и возвращает все конфликты сразу.
+/// Адреса из `inherited` (сокеты socket activation) не проверяются
+pub fn check_listeners(addrs: &[ListenAddr], inherited: &[SocketAddr]) -> Vec<PortConflict> {
+    addrs
+        .iter()
+        .filter(|listen| !inherited.contains(&listen.addr))
+        .filter_map(|listen| {
+            let error = try_bind(listen).err()?;
+            let owner = (error.kind() == std::io::ErrorKind::AddrInUse)
+                .then(|| find_owner(listen.addr.port()))
+                .flatten();
+            Some(PortConflict { addr: listen.addr, error, owner })
+        })
+        .collect()
+}
+
+/// Адреса сокетов, переданных systemd (socket activation: LISTEN_PID, LISTEN_FDS, с fd 3)
+pub fn inherited_addrs() -> Vec<SocketAddr> {
+    let var = |name: &str| std::env::var(name).ok().and_then(|value| value.parse::<u32>().ok());
+    if var("LISTEN_PID") != Some(std::process::id()) {
+        return Vec::new();
+    }
+    let count = var("LISTEN_FDS").unwrap_or(0) as i32;
+    (3..3 + count)
+        .filter_map(|fd| {
+            // Дескриптором владеет процесс: он не закрывается после чтения адреса
+            let socket = std::mem::ManuallyDrop::new(unsafe { std::net::TcpListener::from_raw_fd(fd) });
+            socket.local_addr().ok()
+        })
+        .collect()
+}
+
+/// Inode слушающих сокетов порта из таблицы /proc/net/tcp или /proc/net/tcp6
+fn listening_inodes(table: &str, port: u16) -> Vec<u64> {
+    const LISTEN: &str = "0A";
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let local_port = fields.get(1)?.rsplit(':').next()?;
+            let listening = u16::from_str_radix(local_port, 16).ok()? == port && *fields.get(3)? == LISTEN;
+            listening.then(|| fields.get(9)?.parse().ok()).flatten()
+        })
+        .collect()
+}
+
+/// Процесс, которому принадлежит слушающий сокет порта (Linux, best effort: без прав
+/// на /proc/<pid>/fd чужие процессы не видны)
+fn find_owner(port: u16) -> Option<PortOwner> {
+    let inodes: Vec<u64> = ["/proc/net/tcp", "/proc/net/tcp6"]
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .flat_map(|table| listening_inodes(&table, port))
+        .collect();
+    if inodes.is_empty() {
+        return None;
+    }
+    let sockets: Vec<String> = inodes.iter().map(|inode| format!("socket:[{}]", inode)).collect();
+
+    for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse::<u32>().ok()) else {
+            continue;
+        };
+        let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        let owns = fds
+            .flatten()
+            .filter_map(|fd| std::fs::read_link(fd.path()).ok())
+            .any(|target| sockets.iter().any(|socket| target.as_os_str() == socket.as_str()));
+        if owns {
+            let name = std::fs::read_to_string(entry.path().join("comm")).unwrap_or_default();
+            return Some(PortOwner { pid, name: name.trim().to_string() });
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports_port_in_use() {
+        let busy = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let busy_addr = busy.local_addr().unwrap();
+        let free_addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let addrs = [
+            ListenAddr { addr: busy_addr, ipv6_only: false },
+            ListenAddr { addr: free_addr, ipv6_only: false },
+        ];
+
+        let conflicts = check_listeners(&addrs, &[]);
+        assert_eq!(conflicts.len(), 1, "{:?}", conflicts);
+        assert_eq!(conflicts[0].addr, busy_addr);
+        assert_eq!(conflicts[0].error.kind(), std::io::ErrorKind::AddrInUse);
+        // Владелец определяется через /proc, где он доступен
+        if let Some(owner) = &conflicts[0].owner {
+            assert_eq!(owner.pid, std::process::id());
+        }
+        assert!(conflicts[0].to_string().starts_with(&format!("cannot listen on {}", busy_addr)));
+
+        // Унаследованный сокет не проверяется
+        assert!(check_listeners(&addrs, &[busy_addr]).is_empty());
+    }
+
+    #[test]
+    fn test_listening_inodes() {
+        let table = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 31337 1 0000000000000000 100 0 0 10 0
+   1: 0100007F:1F90 0100007F:A2C4 01 00000000:00000000 00:00000000 00000000     0        0 41414 1 0000000000000000 20 4 30 10 -1
+   2: 00000000:01BB 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 27182 1 0000000000000000 100 0 0 10 0";
+        // Порт 8080: только слушающий сокет, без установленного соединения
+        assert_eq!(listening_inodes(table, 8080), vec![31337]);
+        assert_eq!(listening_inodes(table, 443), vec![27182]);
+        assert!(listening_inodes(table, 80).is_empty());
+    }
+}
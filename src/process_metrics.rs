@@ -0,0 +1,214 @@
+use async_trait::async_trait;
+use log::debug;
+use once_cell::sync::Lazy;
+use prometheus::{register_gauge, register_int_gauge, Gauge, IntGauge};
+use std::time::{Duration, Instant};
+
+use pingora_core::server::ShutdownWatch;
+use pingora_core::services::background::BackgroundService;
+
+use crate::metrics::opts;
+
+/// Время работы прокси
+pub static PROXY_UPTIME: Lazy<Gauge> = Lazy::new(|| {
+    register_gauge!(opts("proxy_uptime_seconds", "Time since the proxy process started"))
+        .expect("Failed to register proxy_uptime_seconds metric")
+});
+
+/// Количество рабочих потоков Pingora на сервис (threads из конфигурации сервера)
+pub static PROXY_WORKER_THREADS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(opts("proxy_worker_threads", "Configured Pingora worker threads per service"))
+        .expect("Failed to register proxy_worker_threads metric")
+});
+
+/// Резидентная память процесса (Linux)
+pub static PROCESS_RESIDENT_MEMORY: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(opts("process_resident_memory_bytes", "Resident memory size in bytes"))
+        .expect("Failed to register process_resident_memory_bytes metric")
+});
+
+/// Открытые файловые дескрипторы процесса (Linux, macOS, BSD)
+pub static PROCESS_OPEN_FDS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(opts("process_open_fds", "Number of open file descriptors"))
+        .expect("Failed to register process_open_fds metric")
+});
+
+/// Потоки ОС процесса (Linux)
+pub static PROCESS_THREADS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(opts("process_threads", "Number of OS threads in the process"))
+        .expect("Failed to register process_threads metric")
+});
+
+/// Рабочие потоки Tokio runtime фонового сервиса
+#[cfg(feature = "runtime-metrics")]
+pub static TOKIO_WORKERS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(opts("tokio_runtime_workers", "Worker threads of the Tokio runtime"))
+        .expect("Failed to register tokio_runtime_workers metric")
+});
+
+/// Активные задачи Tokio runtime фонового сервиса
+#[cfg(feature = "runtime-metrics")]
+pub static TOKIO_ALIVE_TASKS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(opts("tokio_runtime_alive_tasks", "Alive tasks in the Tokio runtime"))
+        .expect("Failed to register tokio_runtime_alive_tasks metric")
+});
+
+/// Показатели процесса из /proc/self
+#[derive(Debug, Default, PartialEq)]
+struct ProcessStats {
+    resident_memory: Option<i64>,
+    open_fds: Option<i64>,
+    threads: Option<i64>,
+}
+
+/// Значение поля `Name:   123 kB` из /proc/self/status
+#[cfg(any(target_os = "linux", test))]
+fn status_field(status: &str, name: &str) -> Option<i64> {
+    let line = status.lines().find(|line| line.starts_with(name))?;
+    line[name.len()..].split_whitespace().next()?.parse().ok()
+}
+
+#[cfg(target_os = "linux")]
+fn read_process_stats() -> ProcessStats {
+    let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+    ProcessStats {
+        resident_memory: status_field(&status, "VmRSS:").map(|kb| kb * 1024),
+        open_fds: std::fs::read_dir("/proc/self/fd").ok().map(|dir| dir.count() as i64),
+        threads: status_field(&status, "Threads:"),
+    }
+}
+
+/// macOS и BSD: /proc нет, доступно только число дескрипторов через /dev/fd
+#[cfg(all(unix, not(target_os = "linux")))]
+fn read_process_stats() -> ProcessStats {
+    ProcessStats {
+        open_fds: std::fs::read_dir("/dev/fd").ok().map(|dir| dir.count() as i64),
+        ..ProcessStats::default()
+    }
+}
+
+#[cfg(not(unix))]
+fn read_process_stats() -> ProcessStats {
+    ProcessStats::default()
+}
+
+/// Регистрирует метрики процесса, чтобы они были видны до первого обновления
+pub fn register() {
+    Lazy::force(&PROXY_UPTIME);
+    Lazy::force(&PROXY_WORKER_THREADS);
+    Lazy::force(&PROCESS_RESIDENT_MEMORY);
+    Lazy::force(&PROCESS_OPEN_FDS);
+    Lazy::force(&PROCESS_THREADS);
+    #[cfg(feature = "runtime-metrics")]
+    {
+        Lazy::force(&TOKIO_WORKERS);
+        Lazy::force(&TOKIO_ALIVE_TASKS);
+    }
+}
+
+/// Обновляет метрики процесса; недоступные на платформе показатели не меняются
+pub fn refresh(started: Instant) {
+    PROXY_UPTIME.set(started.elapsed().as_secs_f64());
+
+    let stats = read_process_stats();
+    if let Some(rss) = stats.resident_memory {
+        PROCESS_RESIDENT_MEMORY.set(rss);
+    }
+    if let Some(fds) = stats.open_fds {
+        PROCESS_OPEN_FDS.set(fds);
+    }
+    if let Some(threads) = stats.threads {
+        PROCESS_THREADS.set(threads);
+    }
+
+    #[cfg(feature = "runtime-metrics")]
+    if let Ok(handle) = tokio::runtime::Handle::try_current() {
+        let metrics = handle.metrics();
+        TOKIO_WORKERS.set(metrics.num_workers() as i64);
+        TOKIO_ALIVE_TASKS.set(metrics.num_alive_tasks() as i64);
+    }
+}
+
+/// Фоновое обновление метрик процесса каждые `interval`
+pub struct ProcessMetricsService {
+    started: Instant,
+    interval: Duration,
+}
+
+impl ProcessMetricsService {
+    pub fn new(started: Instant, interval: Duration, worker_threads: usize) -> Self {
+        PROXY_WORKER_THREADS.set(worker_threads as i64);
+        Self { started, interval }
+    }
+}
+
+#[async_trait]
+impl BackgroundService for ProcessMetricsService {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        let mut ticker = tokio::time::interval(self.interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    refresh(self.started);
+                    debug!("Process metrics refreshed");
+                }
+                _ = shutdown.changed() => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_field_parsing() {
+        let status = "Name:\tadq-pingora\nVmRSS:\t   20480 kB\nThreads:\t9\n";
+        assert_eq!(status_field(status, "VmRSS:"), Some(20480));
+        assert_eq!(status_field(status, "Threads:"), Some(9));
+        assert_eq!(status_field(status, "VmSwap:"), None);
+    }
+
+    #[test]
+    fn test_gauges_register_and_update() {
+        let started = Instant::now() - Duration::from_secs(5);
+        let _service = ProcessMetricsService::new(started, Duration::from_secs(15), 4);
+        refresh(started);
+
+        assert!(PROXY_UPTIME.get() >= 5.0);
+        assert_eq!(PROXY_WORKER_THREADS.get(), 4);
+        if cfg!(target_os = "linux") {
+            assert!(PROCESS_RESIDENT_MEMORY.get() > 0);
+            assert!(PROCESS_OPEN_FDS.get() > 0);
+            assert!(PROCESS_THREADS.get() >= 1);
+        }
+
+        let names: Vec<String> = prometheus::gather().iter().map(|f| f.get_name().to_string()).collect();
+        assert!(names.iter().any(|name| name.ends_with("proxy_uptime_seconds")));
+    }
+
+    #[tokio::test]
+    async fn test_service_refreshes_on_first_tick() {
+        register();
+        let names: Vec<String> = prometheus::gather().iter().map(|f| f.get_name().to_string()).collect();
+        for gauge in ["process_resident_memory_bytes", "process_open_fds", "process_threads"] {
+            assert!(names.iter().any(|name| name.ends_with(gauge)), "{} is not registered", gauge);
+        }
+
+        let started = Instant::now() - Duration::from_secs(60);
+        let service = ProcessMetricsService::new(started, Duration::from_secs(3600), 2);
+        let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+        let task = tokio::spawn(async move { service.start(shutdown_rx).await });
+
+        // Первый тик интервала срабатывает сразу
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(PROXY_UPTIME.get() >= 60.0);
+        if cfg!(unix) {
+            assert!(PROCESS_OPEN_FDS.get() > 0);
+        }
+
+        shutdown_tx.send(true).unwrap();
+        task.await.unwrap();
+    }
+}
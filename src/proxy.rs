@@ -1,6 +1,7 @@
 use async_trait::async_trait;
 use bytes::Bytes;
-use log::info;
+use log::{info, warn};
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use pingora::prelude::*;
@@ -9,78 +10,289 @@ use pingora_core::modules::http::{
     grpc_web::{GrpcWeb, GrpcWebBridge},
     HttpModules,
 };
+use pingora_core::protocols::l4::ext::TcpKeepalive;
+use pingora_core::protocols::{Digest, ALPN};
 use pingora_load_balancing::selection::RoundRobin;
+use pingora_cache::{CacheKey, NoCacheReason, RespCacheable};
+use pingora_proxy::{FailToProxy, RangeType};
 
-use crate::types::{RequestContext, ServiceType};
-use crate::cors::{handle_cors_preflight, add_cors_headers_for_request, add_security_headers};
-use crate::routing::{handle_https_redirect, route_request};
-use crate::rate_limit::check_rate_limit;
+use crate::types::{HandledBy, RequestContext, ServiceType, UpstreamTarget};
+use crate::client_ip::client_ip;
+use crate::hop_by_hop::{strip_request, strip_response};
+use crate::trailers;
+use crate::backend_profile::{add_forwarded_headers, add_response_headers, forwarded_proto};
+use crate::forwarded::{self, ForwardedElement};
+use crate::cors::add_scheme_csp;
+use crate::routing::request_host;
 use crate::metrics::*;
-use crate::filter::IPFilter;
-use crate::config::{Config, ServerBlock, LocationBlock};
-use crate::cache::CacheManager;
+use crate::filter::{validate_request_headers, IPFilter};
+use crate::config::{Balancing, BackendProfileConfig, Config, TcpKeepaliveConfig, FallbackCondition, ServerBlock, LocationBlock, NextUpstream, ProxyRedirect, UpstreamTimeouts};
+use crate::cache::conditional::not_modified;
+use crate::cache::{add_revalidation_headers, is_revalidating, range::range_header_filter, CacheManager, Revalidation};
 use crate::circuit_breaker::CircuitBreaker;
-use crate::logging::LoggingMiddleware;
+use crate::logging::{DecisionRecord, LoggingMiddleware, UpstreamDecision};
+use crate::challenge::Challenge;
+use crate::drain::DrainTracker;
+use crate::flight_recorder::{should_record, FlightRecorder};
+use crate::http10::{client_keepalive, frame_response, is_http10, missing_host};
+use crate::keepalive::{session_connection_key, KeepaliveTracker};
+use crate::error_response::{ErrorCode, ErrorResponse};
+use crate::intercept::{build_intercepted_header, intercept_status, load_error_page};
+use crate::timing::add_timing_headers;
+use crate::rewrite::{rewrite_cookie_headers, rewrite_redirect_headers, upstream_hosts};
+use crate::stages::{build_stages, run_stages, RequestStage};
+use crate::request_id::{add_request_id_header, incoming_request_id, propagate_request_id};
+use crate::buffering::{should_buffer, BufferBudget, ResponseBuffer};
+use crate::body_transform::BodyPipeline;
+use crate::compression::Compression;
+use crate::experiments::Experiments;
+use crate::schedules::Schedules;
+use crate::variables::RequestVariables;
+use crate::grpc_web::is_grpc_web_request;
+use crate::scheme::SchemeResolver;
+use crate::first_byte::FirstByteTimer;
+use crate::fallback::{build_fallback_header, proxy_failure_conditions, record_fallback, send_fallback_response, FallbackResponses};
+use crate::config::parse_size;
+use crate::next_upstream::{error_matches, max_retries, retry_on_error, retry_on_status, select_untried};
+use crate::least_requests::select_least_requests;
+use crate::hash_balancing::select_by_hash;
 use std::time::Duration;
 
 /// Основной прокси для AdQuest
 pub struct AdQuestProxy {
-    core_api_lb: Arc<LoadBalancer<RoundRobin>>,  // RoundRobin поддерживает веса через Backend.weight
-    zitadel_lb: Arc<LoadBalancer<RoundRobin>>,
+    /// Балансировщики по имени upstream (RoundRobin поддерживает веса через Backend.weight)
+    upstreams: HashMap<String, Arc<LoadBalancer<RoundRobin>>>,
     config: Arc<Config>,
     cache_manager: Option<Arc<CacheManager>>,
     circuit_breaker: Option<Arc<CircuitBreaker>>,
     logging_middleware: Arc<LoggingMiddleware>,
-    ip_filter: Option<Arc<IPFilter>>,
+    drain_tracker: Arc<DrainTracker>,
+    keepalive_tracker: Arc<KeepaliveTracker>,
+    /// Стадии request_filter в порядке pipeline.stages
+    stages: Vec<Box<dyn RequestStage>>,
+    /// Общий лимит памяти буферизованных ответов
+    buffer_budget: Arc<BufferBudget>,
+    /// Максимальный размер заголовка запроса (global.max_header_size)
+    max_header_size: usize,
+    /// Заглушки fallback_response, загруженные при старте
+    fallbacks: Arc<FallbackResponses>,
+    /// Определение схемы запроса с учетом доверенных прокси
+    scheme_resolver: SchemeResolver,
+    /// Маскирование JSON тел в location с `redact on` (None - redaction.paths не заданы)
+    body_pipeline: Option<Arc<BodyPipeline>>,
+    /// Сжатие ответов (None - секция compression выключена)
+    compression: Option<Compression>,
+    /// Эксперименты (None - секция experiments пуста)
+    experiments: Option<Experiments>,
+    /// Последние запросы с 5xx и ошибками соединения (None - flight_recorder.capacity: 0)
+    flight_recorder: Option<Arc<FlightRecorder>>,
+}
+
+/// Компоненты прокси, подготовленные ProxyBuilder; производные от конфигурации части
+/// (стадии, лимиты, сжатие) AdQuestProxy собирает сам
+pub(crate) struct ProxyComponents {
+    pub config: Arc<Config>,
+    pub upstreams: HashMap<String, Arc<LoadBalancer<RoundRobin>>>,
+    pub cache_manager: Option<Arc<CacheManager>>,
+    pub circuit_breaker: Option<Arc<CircuitBreaker>>,
+    pub logging_middleware: Arc<LoggingMiddleware>,
+    pub ip_filter: Option<Arc<IPFilter>>,
+    pub challenge: Arc<Challenge>,
+    pub drain_tracker: Arc<DrainTracker>,
+    pub keepalive_tracker: Arc<KeepaliveTracker>,
+    pub fallbacks: Arc<FallbackResponses>,
+    pub schedules: Arc<Schedules>,
+    pub flight_recorder: Option<Arc<FlightRecorder>>,
 }
 
 impl AdQuestProxy {
-    pub fn new(
-        core_api_lb: Arc<LoadBalancer<RoundRobin>>,
-        zitadel_lb: Arc<LoadBalancer<RoundRobin>>,
-        config: Arc<Config>,
-        cache_manager: Option<Arc<CacheManager>>,
-        circuit_breaker: Option<Arc<CircuitBreaker>>,
-        logging_middleware: Arc<LoggingMiddleware>,
-        ip_filter: Option<Arc<IPFilter>>,
-    ) -> Self {
-        Self {
-            core_api_lb,
-            zitadel_lb,
+    pub(crate) fn from_components(components: ProxyComponents) -> Self {
+        let config = components.config;
+        let stages = build_stages(
+            &config,
+            components.ip_filter,
+            Some(components.challenge),
+            components.circuit_breaker.clone(),
+            components.fallbacks.clone(),
+            components.schedules,
+        );
+        let budget = parse_size(&config.global.buffered_body_budget).unwrap_or_else(|| {
+            warn!("Invalid buffered_body_budget '{}', using 256m", config.global.buffered_body_budget);
+            256 * 1024 * 1024
+        });
+        let max_header_size = parse_size(&config.global.max_header_size).unwrap_or_else(|| {
+            warn!("Invalid max_header_size '{}', using 8k", config.global.max_header_size);
+            8 * 1024
+        });
+        let scheme_resolver = SchemeResolver::from_config(&config.proxy_headers).unwrap_or_else(|e| {
+            warn!("Invalid trusted proxies, X-Forwarded-Proto is not trusted: {}", e);
+            SchemeResolver::default()
+        });
+        let body_pipeline = BodyPipeline::from_config(&config.redaction).unwrap_or_else(|e| {
+            warn!("Invalid redaction configuration, bodies are not redacted: {}", e);
+            None
+        });
+        let compression = Compression::from_config(&config.compression).unwrap_or_else(|e| {
+            warn!("Invalid compression configuration, responses are not compressed: {}", e);
+            None
+        });
+        let experiments = Experiments::from_config(&config.experiments).unwrap_or_else(|e| {
+            warn!("Invalid experiments configuration, experiments are disabled: {}", e);
+            None
+        });
+        AdQuestProxy {
+            upstreams: components.upstreams,
             config,
-            cache_manager,
-            circuit_breaker,
-            logging_middleware,
-            ip_filter,
-        }
-    }
-
-    fn get_static_html(&self, _uri: &str, _host: &str) -> String {
-        r#"<!DOCTYPE html>
-<html>
-<head>
-    <title>Welcome to AdQuest Proxy!</title>
-    <style>
-        body {
-            width: 35em;
-            margin: 0 auto;
-            font-family: Tahoma, Verdana, Arial, sans-serif;
-        }
-    </style>
-</head>
-<body>
-    <h1>Welcome to AdQuest Proxy!</h1>
-    <p>If you see this page, the AdQuest proxy server is successfully installed and
-    working. Further configuration is required.</p>
-
-    <p>For online documentation and support please refer to
-    <a href="https://github.com/cloudflare/pingora">Pingora</a>.<br/>
-    Commercial support is available at
-    <a href="https://www.cloudflare.com/">Cloudflare</a>.</p>
-
-    <p><em>Thank you for using AdQuest Proxy powered by Pingora.</em></p>
-</body>
-</html>"#.to_string()
+            cache_manager: components.cache_manager,
+            circuit_breaker: components.circuit_breaker,
+            logging_middleware: components.logging_middleware,
+            drain_tracker: components.drain_tracker,
+            keepalive_tracker: components.keepalive_tracker,
+            stages,
+            buffer_budget: Arc::new(BufferBudget::new(budget)),
+            max_header_size,
+            fallbacks: components.fallbacks,
+            scheme_resolver,
+            body_pipeline: body_pipeline.map(Arc::new),
+            compression,
+            experiments,
+            flight_recorder: components.flight_recorder,
+        }
+    }
+
+    /// Балансировщики по имени upstream
+    pub(crate) fn upstreams(&self) -> &HashMap<String, Arc<LoadBalancer<RoundRobin>>> {
+        &self.upstreams
+    }
+
+    /// Лимит запросов keep-alive соединений; передается и обертке KeepaliveApp
+    pub fn keepalive_tracker(&self) -> Arc<KeepaliveTracker> {
+        self.keepalive_tracker.clone()
+    }
+
+    /// Pipeline преобразования тел, если location включает `redact on`
+    fn redaction_for(&self, location: Option<&LocationBlock>) -> Option<&BodyPipeline> {
+        self.body_pipeline.as_deref().filter(|_| location.is_some_and(|l| l.redact))
+    }
+
+    /// Находит location из nginx конфигурации для запроса
+    fn location_for(&self, session: &Session) -> Option<&LocationBlock> {
+        let server = self.config.find_server(request_host(session))?;
+        self.config.find_location(server, session.req_header().uri.path())
+    }
+
+    /// Переменные запроса для значений директив (add_header, proxy_set_header)
+    fn request_variables<'a>(&'a self, session: &'a Session, ctx: &RequestContext) -> RequestVariables<'a> {
+        let maps = self.config.nginx_config.as_ref().map(|nginx| &nginx.maps);
+        RequestVariables::new(session.req_header(), request_host(session), ctx.client_ip, ctx.scheme, maps)
+    }
+
+    /// Освобождает in-flight слот бэкенда и слот upstream с max_conns.
+    /// Вызывается в конце запроса или раньше, когда тело ответа целиком буферизовано
+    fn release_upstream(&self, ctx: &mut RequestContext) {
+        if let Some(backend) = ctx.selected_backend.take() {
+            self.drain_tracker.end_request(ctx.upstream_label(), &backend);
+        }
+        ctx.upstream_permit = None;
+    }
+
+    /// Профиль бэкенда, выбранный при маршрутизации
+    fn backend_profile(&self, ctx: &RequestContext) -> Option<&BackendProfileConfig> {
+        ctx.backend_profile
+            .as_deref()
+            .and_then(|name| self.config.backend_profiles.get(name))
+    }
+
+    /// Профиль бэкенда по location запроса (до маршрутизации)
+    fn location_profile(&self, session: &Session) -> Option<&BackendProfileConfig> {
+        let location = self.location_for(session)?;
+        let upstream = location.proxy_pass.as_deref().unwrap_or_default();
+        self.config.backend_profile(Some(location), upstream).map(|(_, profile)| profile)
+    }
+
+    /// Балансировщик для upstream из маршрутизации
+    fn load_balancer(&self, name: &str) -> Option<&Arc<LoadBalancer<RoundRobin>>> {
+        self.upstreams.get(name)
+    }
+
+    /// Балансировщик upstream или global.default_upstream, если upstream нет
+    /// (например, после частичного reload). Возвращает имя выбранного upstream
+    fn resolve_load_balancer(&self, name: &str) -> Option<(String, &Arc<LoadBalancer<RoundRobin>>)> {
+        if let Some(lb) = self.load_balancer(name) {
+            return Some((name.to_string(), lb));
+        }
+        let default_upstream = self.config.global.default_upstream.as_deref()?;
+        let lb = self.load_balancer(default_upstream)?;
+        warn!("Upstream '{}' is not configured, using default upstream '{}'", name, default_upstream);
+        Some((default_upstream.to_string(), lb))
+    }
+
+    /// Условия proxy_next_upstream и максимум повторов для location запроса
+    fn next_upstream(&self, session: &Session) -> (NextUpstream, u32) {
+        let location = self.location_for(session);
+        let next = location.map(|l| l.proxy_next_upstream.clone()).unwrap_or_default();
+        (next, max_retries(location, self.config.global.max_retries))
+    }
+
+    /// Выбирает бэкенд из load balancer (round robin, least_requests или hash upstream),
+    /// исключая бэкенды в режиме draining.
+    /// При повторе предпочитается бэкенд, к которому запрос еще не отправлялся
+    fn select_backend(
+        &self,
+        session: &Session,
+        lb: &LoadBalancer<RoundRobin>,
+        ctx: &mut RequestContext,
+    ) -> Option<pingora_load_balancing::Backend> {
+        let upstream = ctx.upstream_label().to_string();
+        let accept = |backend: &pingora_load_balancing::Backend, healthy: bool| {
+            healthy && !self.drain_tracker.is_draining(&upstream, &backend.addr.to_string())
+        };
+        let upstream_block = self
+            .config
+            .nginx_config
+            .as_ref()
+            .and_then(|nginx_config| nginx_config.upstreams.get(&upstream));
+        let balancing = upstream_block.map(|upstream_block| upstream_block.balancing).unwrap_or_default();
+        let backend = match balancing {
+            Balancing::LeastRequests => select_least_requests(lb, &ctx.tried_backends, accept, |backend| {
+                self.drain_tracker.in_flight(&upstream, &backend.addr.to_string())
+            })?,
+            Balancing::Hash => {
+                let template = upstream_block.and_then(|upstream_block| upstream_block.hash_key.as_deref()).unwrap_or_default();
+                let key = self.request_variables(session, ctx).expand(template);
+                // Пустой ключ (нет аргумента или заголовка) - обычный round robin
+                if key.is_empty() {
+                    select_untried(lb, &ctx.tried_backends, accept)?
+                } else {
+                    select_by_hash(lb, &key, &ctx.tried_backends, accept)?
+                }
+            }
+            Balancing::RoundRobin => select_untried(lb, &ctx.tried_backends, accept)?,
+        };
+        let addr = backend.addr.to_string();
+        // Выборка decisions log решается при первой попытке; повторы обновляют решение
+        let sampled = ctx.upstream_decision.is_some()
+            || (ctx.retries == 0 && self.logging_middleware.decisions_log().is_some_and(|log| log.sample()));
+        if sampled {
+            ctx.upstream_decision = Some(UpstreamDecision {
+                upstream: upstream.clone(),
+                backend: addr.clone(),
+                algorithm: balancing.as_str(),
+                in_flight: self.drain_tracker.in_flight(&upstream, &addr),
+            });
+        }
+        self.track_backend(ctx, addr);
+        Some(backend)
+    }
+
+    /// Учитывает in-flight запрос к бэкенду; при retry освобождает предыдущий
+    fn track_backend(&self, ctx: &mut RequestContext, addr: String) {
+        if let Some(previous) = ctx.selected_backend.take() {
+            self.drain_tracker.end_request(ctx.upstream_label(), &previous);
+            ctx.tried_backends.push(previous);
+        }
+        self.drain_tracker.begin_request(ctx.upstream_label(), &addr);
+        ctx.selected_backend = Some(addr);
     }
 }
 
@@ -100,194 +312,166 @@ impl ProxyHttp for AdQuestProxy {
     async fn early_request_filter(
         &self,
         session: &mut Session,
-        _ctx: &mut Self::CTX,
+        ctx: &mut Self::CTX,
     ) -> Result<()> {
-        // Определяем, является ли это запрос к Zitadel
-        let host = session
-            .req_header()
-            .uri
-            .authority()
-            .map(|a| a.as_str())
-            .or_else(|| {
-                session
-                    .req_header()
-                    .headers
-                    .get("host")
-                    .and_then(|h| h.to_str().ok())
-            })
-            .unwrap_or("unknown")
-            .to_string();
-        
-        let host_without_port = host.split(':').next().unwrap_or(&host);
-        
-        // Инициализируем gRPC-Web модуль для всех запросов к Zitadel
-        // Модуль сам определит, является ли запрос gRPC-Web по Content-Type в request_header_filter
-        if host_without_port == "auth.ad-quest.ru" || 
-           (host_without_port == "localhost" && (host.contains(":8085") || host.contains(":8091"))) {
+        // Схема запроса для редиректов, X-Forwarded-Proto upstream, CSP и access log
+        ctx.scheme = self.scheme_resolver.session_scheme(session);
+        // Адрес клиента: от доверенного прокси берется из Forwarded/X-Forwarded-For (real_ip_from)
+        ctx.client_ip = self.scheme_resolver.session_client_ip(session);
+
+        // Идентификатор запроса от клиента или внешнего балансировщика
+        if let Some(request_id) = incoming_request_id(session.req_header(), &self.config.proxy_headers.request_id) {
+            ctx.request_id = request_id;
+        }
+
+        // HTTP/1.0 без Host обслуживается default server
+        if let Some(host) = missing_host(session.req_header(), &self.config) {
+            info!("HTTP/1.0 request without Host, using default server '{}'", host);
+            session.req_header_mut().insert_header("Host", host)?;
+        }
+
+        // Keep-alive клиента: последний разрешенный запрос в соединении получает Connection: close,
+        // клиент HTTP/1.0 держит соединение только с Connection: keep-alive
+        let last_request = session_connection_key(session)
+            .is_some_and(|key| self.keepalive_tracker.is_last_request(&key));
+        ctx.downstream_keepalive = !last_request && client_keepalive(session.req_header());
+        if ctx.downstream_keepalive {
+            session.set_keepalive(Some(self.config.global.keepalive_timeout));
+        } else {
+            session.set_keepalive(None);
+        }
+
+        // gRPC-Web bridge активируется по Content-Type запроса (grpc_web), независимо от хоста.
+        // Маршрутизация еще не выполнена, поэтому профиль берется из location
+        let grpc_web_allowed = self
+            .location_profile(session)
+            .is_none_or(|profile| profile.grpc_web);
+        if grpc_web_allowed && is_grpc_web_request(session.req_header(), &self.config.grpc_web) {
             if let Some(grpc) = session.downstream_modules_ctx.get_mut::<GrpcWebBridge>() {
                 grpc.init();
+                ctx.grpc_web = true;
             }
         }
         Ok(())
     }
 
     async fn request_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<bool> {
-        // IP Filtering - проверяем blacklist/whitelist
-        if let Some(ip_filter) = &self.ip_filter {
-            if let Some(client_addr) = session.client_addr() {
-                let addr_str = client_addr.to_string();
-                if let Some(ip_str) = addr_str.split(':').next() {
-                    if let Ok(ip) = ip_str.parse::<std::net::IpAddr>() {
-                        if ip_filter.should_block_ip(ip).await {
-                            // IP заблокирован, возвращаем 403 Forbidden
-                            // Используем respond_error_with_body как в официальных примерах
-                            let error_body = r#"{"error":"Forbidden","message":"Access denied"}"#;
-                            let _ = session
-                                .respond_error_with_body(403, Bytes::from(error_body))
-                                .await;
-                            
-                            return Ok(true);
-                        }
-                    }
-                }
-            }
+        // Заголовки с управляющими символами или слишком большие не передаются upstream
+        if let Err(e) = validate_request_headers(session.req_header(), self.max_header_size) {
+            warn!("Rejecting request with invalid headers from {:?}: {}", session.client_addr(), e);
+            ctx.handle_locally("invalid_headers");
+            ErrorResponse::new(ErrorCode::BadRequest)
+                .message("Invalid request headers")
+                .send(session, ctx)
+                .await?;
+            return Ok(true);
         }
 
-        // Rate limiting - получаем конфигурацию из nginx config
-        if let Some(nginx_config) = &self.config.nginx_config {
-            let host = session
-                .req_header()
-                .uri
-                .authority()
-                .map(|a| a.as_str())
-                .or_else(|| {
-                    session
-                        .req_header()
-                        .headers
-                        .get("host")
-                        .and_then(|h| h.to_str().ok())
-                })
-                .unwrap_or("unknown");
-
-            let uri = session.req_header().uri.path();
-
-            // Находим соответствующий server и location
-            if let Some(server) = nginx_config.find_server(host) {
-                if let Some(location) = nginx_config.find_location(server, uri) {
-                    if let Some(rate_limit) = &location.rate_limit {
-                        // Создаем временную конфигурацию rate limit
-                        let rate_config = crate::rate_limit::RateLimitConfig {
-                            enabled: true,
-                            max_requests_per_second: rate_limit.requests_per_second as isize,
-                            whitelist: vec!["127.0.0.1".to_string(), "::1".to_string()],
-                            per_api_key_limits: std::collections::HashMap::new(),
-                        };
-
-                        if check_rate_limit(session, &rate_config).await? {
-                            // Запрос был заблокирован (429), увеличиваем метрику
-                            RATE_LIMIT_HITS.inc();
-                            return Ok(true);
-                        }
-                    }
-                }
-            }
+        if self.config.global.reject_http10 && is_http10(session.req_header()) {
+            ctx.handle_locally("http10_rejected");
+            ErrorResponse::new(ErrorCode::UpgradeRequired)
+                .header("Upgrade", "HTTP/1.1")
+                .send(session, ctx)
+                .await?;
+            return Ok(true);
         }
 
-        let uri = session.req_header().uri.path().to_string();
-        
-        // В HTTP/2 используется :authority псевдо-заголовок, в HTTP/1.1 - Host заголовок
-        let host = session
-            .req_header()
-            .uri
-            .authority()
-            .map(|a| a.as_str())
-            .or_else(|| {
-                session
-                    .req_header()
-                    .headers
-                    .get("host")
-                    .and_then(|h| h.to_str().ok())
-            })
-            .unwrap_or("unknown")
-            .to_string();
-
-        let host_without_port = host.split(':').next().unwrap_or(&host);
-        
-        // Логируем все запросы к Zitadel и gRPC-Web запросы для диагностики
-        let is_grpc_web = uri.contains("zitadel.") || uri.contains(".v1.") || uri.contains(".v2.");
-        let is_zitadel = host_without_port == "auth.ad-quest.ru";
-        
-        if is_grpc_web || is_zitadel || (!uri.starts_with("/health") && !uri.starts_with("/api/heartbeat")) {
-            info!("Request: {} {} (Host: {})", session.req_header().method, uri, host);
-            
-            // Для gRPC-Web запросов логируем заголовки
-            if is_grpc_web {
-                if let Some(ct) = session.req_header().headers.get("content-type") {
-                    info!("  Content-Type: {:?}", ct.to_str().unwrap_or("invalid"));
-                }
-                if let Some(origin) = session.req_header().headers.get("origin") {
-                    info!("  Origin: {:?}", origin.to_str().unwrap_or("invalid"));
-                }
-            }
-        }
+        // IP фильтр, rate limiting, CORS, редиректы, маршрутизация, статика и circuit breaker
+        let responded = run_stages(&self.stages, session, ctx).await?;
 
-        // Обработка CORS preflight запросов
-        if handle_cors_preflight(session, &uri).await? {
-            return Ok(true);
+        // Кодировка ответа согласуется до ключа кеша: вариант в кеше зависит от нее
+        if let Some(compression) = &self.compression {
+            ctx.negotiated_encoding = compression.negotiate(session.req_header(), self.location_for(session));
         }
+        Ok(responded)
+    }
 
-        // HTTP -> HTTPS редирект для доменов ad-quest.ru
-        if handle_https_redirect(session, &host, &uri).await? {
-            return Ok(true);
+    fn request_cache_filter(&self, session: &mut Session, _ctx: &mut Self::CTX) -> Result<()> {
+        if let Some(cache_manager) = &self.cache_manager {
+            let location = self.location_for(session);
+            cache_manager.enable(session, location);
         }
+        Ok(())
+    }
 
-        // Определяем маршрутизацию
-        route_request(&host, &uri, ctx);
-
-        // Обработка статических страниц
-        if ctx.service_type == ServiceType::Static {
-            let html_content = self.get_static_html(&uri, &host);
-            
-            let mut response = ResponseHeader::build(200, None)?;
-            response.insert_header("Content-Type", "text/html; charset=utf-8")?;
-            response.insert_header("Content-Length", html_content.len().to_string())?;
-            
-            add_security_headers(&mut response)?;
+    fn cache_key_callback(&self, session: &Session, ctx: &mut Self::CTX) -> Result<CacheKey> {
+        // Кеш включается в request_cache_filter только для запросов с ключом
+        let encoding = ctx.negotiated_encoding.map(|negotiated| negotiated.variant());
+        self.cache_manager
+            .as_ref()
+            .and_then(|cache_manager| cache_manager.create_cache_key(session, encoding))
+            .ok_or_else(|| Error::explain(ErrorType::InternalError, "request is not cacheable"))
+    }
 
-            session.write_response_header(Box::new(response), false).await?;
-            session.write_response_body(Some(Bytes::from(html_content)), true).await?;
+    fn response_cache_filter(
+        &self,
+        session: &Session,
+        resp: &ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<RespCacheable> {
+        Ok(match &self.cache_manager {
+            Some(cache_manager) => {
+                let location = self.location_for(session);
+                // proxy_no_cache: любое непустое значение, кроме "0", запрещает сохранение ответа
+                let no_store = location.is_some_and(|location| {
+                    let mut variables = self.request_variables(session, ctx);
+                    location
+                        .proxy_no_cache
+                        .iter()
+                        .map(|value| variables.expand(value))
+                        .any(|value| !value.is_empty() && value != "0")
+                });
+                cache_manager.is_response_cacheable(session, resp, location, no_store)
+            }
+            None => RespCacheable::Uncacheable(NoCacheReason::NeverEnabled),
+        })
+    }
 
-            return Ok(true);
-        }
+    fn cache_not_modified_filter(
+        &self,
+        session: &Session,
+        resp: &ResponseHeader,
+        _ctx: &mut Self::CTX,
+    ) -> Result<bool> {
+        // 304 из кеша: If-None-Match со слабым сравнением ETag, иначе If-Modified-Since
+        Ok(not_modified(session.req_header(), resp))
+    }
 
-        Ok(false) // Продолжаем с проксированием
+    fn range_header_filter(
+        &self,
+        session: &mut Session,
+        resp: &mut ResponseHeader,
+        _ctx: &mut Self::CTX,
+    ) -> RangeType {
+        // 206/416 из закешированного полного ответа; 206 от upstream передается как есть
+        range_header_filter(session.req_header(), resp)
     }
 
     fn fail_to_connect(
         &self,
-        _session: &mut Session,
+        session: &mut Session,
         _peer: &HttpPeer,
         ctx: &mut Self::CTX,
         e: Box<Error>,
     ) -> Box<Error> {
-        const MAX_RETRIES: u32 = 3;
+        if e.etype() == &ErrorType::ConnectTimedout {
+            UPSTREAM_TIMEOUTS.with_label_values(&["connect"]).inc();
+        }
 
-        if ctx.retries < MAX_RETRIES {
+        // Запрос не дошел до бэкенда: повтор по условиям error/timeout proxy_next_upstream.
+        // После истечения proxy_request_timeout повтор не выполняется
+        let (next, max_retries) = self.next_upstream(session);
+        if !error_matches(&next, &e) {
+            return e;
+        }
+        if ctx.retries < max_retries && !ctx.upstream_deadline_exceeded() {
             ctx.retries += 1;
             
-            let service_name = match ctx.service_type {
-                ServiceType::CoreApi => "core_api",
-                ServiceType::ChallengeApi => "challenge_api",
-                ServiceType::BillingApi => "billing_api",
-                ServiceType::ErirApi => "erir_api",
-                ServiceType::SharedApi => "shared_api",
-                ServiceType::ZitadelAuth => "zitadel_auth",
-                ServiceType::Static => "static",
-            };
+            let service_name = ctx.service_type.as_str();
             
             info!(
                 "Connection failed, retry attempt {}/{} for service: {}",
-                ctx.retries, MAX_RETRIES, service_name
+                ctx.retries, max_retries, service_name
             );
             
             // Метрика retry
@@ -299,19 +483,11 @@ impl ProxyHttp for AdQuestProxy {
             retry_e.set_retry(true);
             retry_e
         } else {
-            let service_name = match ctx.service_type {
-                ServiceType::CoreApi => "core_api",
-                ServiceType::ChallengeApi => "challenge_api",
-                ServiceType::BillingApi => "billing_api",
-                ServiceType::ErirApi => "erir_api",
-                ServiceType::SharedApi => "shared_api",
-                ServiceType::ZitadelAuth => "zitadel_auth",
-                ServiceType::Static => "static",
-            };
+            let service_name = ctx.service_type.as_str();
             
             info!(
                 "Max retries ({}) exceeded for service: {}",
-                MAX_RETRIES, service_name
+                max_retries, service_name
             );
             
             // Метрика failed retry
@@ -323,7 +499,48 @@ impl ProxyHttp for AdQuestProxy {
         }
     }
 
-    async fn upstream_peer(&self, _session: &mut Session, ctx: &mut Self::CTX) -> Result<Box<HttpPeer>> {
+    fn error_while_proxy(
+        &self,
+        peer: &HttpPeer,
+        session: &mut Session,
+        e: Box<Error>,
+        ctx: &mut Self::CTX,
+        client_reused: bool,
+    ) -> Box<Error> {
+        // Соединение закрыл таймер proxy_first_byte_timeout: это таймаут ожидания ответа
+        let e = match ctx.first_byte_timer.take() {
+            Some(timer) if timer.expired() => first_byte_timeout_error(),
+            _ => e,
+        };
+        // Поведение Pingora по умолчанию: повтор на переиспользованном соединении
+        let mut e = e.more_context(format!("Peer: {}", peer));
+        let replayable = !session.retry_buffer_truncated();
+        e.retry.decide_reuse(client_reused && replayable);
+        if e.retry() || !replayable {
+            return e;
+        }
+
+        // Ошибка обмена с бэкендом до получения ответа: proxy_next_upstream error/timeout
+        let (next, max_retries) = self.next_upstream(session);
+        let upstream_error = matches!(e.esource(), ErrorSource::Upstream);
+        if upstream_error
+            && !ctx.upstream_response_received
+            && ctx.retries < max_retries
+            && !ctx.upstream_deadline_exceeded()
+            && retry_on_error(&next, &e, session.req_header().method.as_str(), true)
+        {
+            ctx.retries += 1;
+            info!("Upstream '{}' failed ({}), trying next backend, attempt {}/{}",
+                  ctx.upstream_label(), e.etype().as_str(), ctx.retries, max_retries);
+            RETRY_ATTEMPTS
+                .with_label_values(&[ctx.service_type.as_str(), "attempt"])
+                .inc();
+            e.set_retry(true);
+        }
+        e
+    }
+
+    async fn upstream_peer(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<Box<HttpPeer>> {
         const MAX_SLEEP: Duration = Duration::from_secs(10);
 
         // Exponential backoff перед retry
@@ -337,118 +554,281 @@ impl ProxyHttp for AdQuestProxy {
             info!("Sleeping for {:?} before retry attempt {}", sleep_ms, ctx.retries);
             tokio::time::sleep(sleep_ms).await;
         }
+        let now = std::time::Instant::now();
+        ctx.upstream_start = Some(now);
+        // Таймауты задает стадия routing; без нее (или если она не выполнялась)
+        // используются значения location и global, как для любого другого peer
+        if ctx.upstream_timeouts.is_none() {
+            ctx.upstream_timeouts = Some(self.config.resolve_upstream_timeouts(self.location_for(session)));
+        }
 
-        let upstream = match ctx.service_type {
-            ServiceType::CoreApi => {
-                // Используем select() как в примерах Pingora
-                // Arc автоматически разыменовывается при вызове методов через Deref
-                let backend = self.core_api_lb.select(b"", 256).unwrap();
-                info!("Selected core API backend: {:?}", backend);
-                backend
-            }
-            ServiceType::ZitadelAuth => {
-                let backend = self.zitadel_lb.select(b"", 256).unwrap();
-                info!("Selected Zitadel backend: {:?}", backend);
-                backend
-            }
-            ServiceType::ChallengeApi => {
-                let addr = format!("127.0.0.1:{}", ctx.upstream_port);
-                info!("Direct routing to Challenge API: {}", addr);
-                return Ok(Box::new(HttpPeer::new(addr, false, "".to_string())));
-            }
-            ServiceType::BillingApi => {
-                let addr = format!("127.0.0.1:{}", ctx.upstream_port);
-                info!("Direct routing to Billing API: {}", addr);
-                return Ok(Box::new(HttpPeer::new(addr, false, "".to_string())));
+        // Срок обращения к upstream отсчитывается от первой попытки и включает повторы
+        if ctx.upstream_deadline.is_none() {
+            ctx.upstream_deadline = ctx.upstream_timeouts.and_then(|timeouts| timeouts.deadline(now));
+        }
+        let time_left = ctx.upstream_time_left(now);
+        if time_left.is_some_and(|left| left.is_zero()) {
+            return Err(upstream_deadline_error());
+        }
+
+        let mut peer = match ctx.upstream_target.clone() {
+            UpstreamTarget::Named(name) => {
+                let Some((name, lb)) = self.resolve_load_balancer(&name) else {
+                    let message = format!("Upstream '{}' is not configured", name);
+                    log::error!("{}", message);
+                    let client_ip = ctx.client_ip.map(|ip| ip.to_string());
+                    self.logging_middleware
+                        .error_logger()
+                        .log_error(
+                            "upstream_missing",
+                            &message,
+                            None,
+                            client_ip.as_deref(),
+                            Some(&session.req_header().uri.to_string()),
+                        )
+                        .await;
+                    // Ошибка upstream: клиент получает 502, а не 500
+                    return Err(Error::explain(ErrorType::ConnectNoRoute, format!("unknown upstream '{}'", name)).into_up());
+                };
+                let backend = self.select_backend(session, lb, ctx)
+                    .ok_or_else(|| Error::explain(ErrorType::ConnectNoRoute, format!("no available backend for upstream '{}'", name)))?;
+                info!("Selected {} backend: {:?}", name, backend);
+                ctx.upstream_addr = Some(backend.addr.to_string());
+                match &ctx.upstream_tls {
+                    Some(sni) => {
+                        // Как nginx с proxy_ssl_verify off: сертификат upstream не проверяется
+                        let mut peer = HttpPeer::new(backend, true, sni.clone());
+                        peer.options.verify_cert = false;
+                        peer.options.verify_hostname = false;
+                        Box::new(peer)
+                    }
+                    None => Box::new(HttpPeer::new(backend, false, "".to_string())),
+                }
             }
-            ServiceType::ErirApi => {
-                let addr = format!("127.0.0.1:{}", ctx.upstream_port);
-                info!("Direct routing to ERIR API: {}", addr);
-                return Ok(Box::new(HttpPeer::new(addr, false, "".to_string())));
+            UpstreamTarget::Direct(addr) => {
+                info!("Direct routing to {}: {}", ctx.service_type.as_str(), addr);
+                ctx.upstream_addr = Some(addr.to_string());
+                Box::new(HttpPeer::new(addr, false, "".to_string()))
             }
-            ServiceType::SharedApi => {
-                let addr = format!("127.0.0.1:{}", ctx.upstream_port);
-                info!("Direct routing to Shared API: {}", addr);
-                return Ok(Box::new(HttpPeer::new(addr, false, "".to_string())));
+            UpstreamTarget::Unix { path, tls } => {
+                ctx.upstream_addr = Some(format!("unix:{}", path));
+                // Как nginx с proxy_ssl_verify off: сертификат локального сокета не проверяется
+                let mut peer = HttpPeer::new_uds(&path, tls, "localhost".to_string())?;
+                peer.options.verify_cert = false;
+                peer.options.verify_hostname = false;
+                Box::new(peer)
             }
-            ServiceType::Static => {
-                return Err(Error::new(ErrorType::InternalError));
+            UpstreamTarget::None => {
+                return Err(Error::explain(ErrorType::InternalError, "request has no upstream target"));
             }
         };
 
-        let peer = Box::new(HttpPeer::new(upstream, false, "".to_string()));
+        // Одинаково для балансируемых и direct upstream
+        if let Some(timeouts) = &ctx.upstream_timeouts {
+            apply_upstream_timeouts(&mut peer, timeouts, time_left);
+        }
+        apply_tcp_keepalive(&mut peer, self.config.global.tcp_keepalive.as_ref());
+        // gRPC работает только поверх HTTP/2: без TLS - h2c с prior knowledge
+        if trailers::is_grpc(session.req_header()) {
+            peer.options.set_http_version(2, 2);
+        }
+
         Ok(peer)
     }
 
+    async fn connected_to_upstream(
+        &self,
+        _session: &mut Session,
+        _reused: bool,
+        peer: &HttpPeer,
+        fd: std::os::unix::io::RawFd,
+        _digest: Option<&Digest>,
+        ctx: &mut Self::CTX,
+    ) -> Result<()>
+    where
+        Self::CTX: Send + Sync,
+    {
+        // proxy_first_byte_timeout короче read: ожидание заголовков ограничивает таймер.
+        // HTTP/2 соединение общее для потоков, его не закрыть ради одного запроса
+        ctx.first_byte_timer = None;
+        let first_byte = ctx.upstream_timeouts.map(|timeouts| timeouts.first_byte)
+            .filter(|first_byte| peer.options.read_timeout.is_some_and(|read| *first_byte < read));
+        if let (Some(first_byte), ALPN::H1) = (first_byte, &peer.options.alpn) {
+            match FirstByteTimer::new(fd, first_byte) {
+                Ok(timer) => ctx.first_byte_timer = Some(timer),
+                Err(e) => warn!("First byte timer for upstream '{}' is not set: {}", ctx.upstream_label(), e),
+            }
+        }
+        Ok(())
+    }
+
     async fn upstream_request_filter(
         &self,
         session: &mut Session,
         upstream_request: &mut RequestHeader,
         ctx: &mut Self::CTX,
     ) -> Result<()> {
+        let headers = &self.config.proxy_headers;
+        // Hop-by-hop заголовки клиента не передаются; WebSocket Upgrade сохраняется
+        strip_request(upstream_request)?;
+        // Upstream получает HTTP/1.1 и от клиентов HTTP/1.0: кадрирование ответа меняется в response_filter
+        if is_http10(upstream_request) {
+            upstream_request.set_version(http::Version::HTTP_11);
+        }
+
         // Добавляем стандартные proxy заголовки
-        if let Some(client_ip) = session.client_addr() {
-            upstream_request.insert_header("X-Real-IP", client_ip.to_string())?;
-            upstream_request.insert_header("X-Forwarded-For", client_ip.to_string())?;
+        let forwarded_headers = self.location_for(session).map(|l| l.forwarded_headers).unwrap_or_default();
+        if let Some(client_ip) = ctx.client_ip {
+            upstream_request.insert_header(headers.real_ip.clone(), client_ip.to_string())?;
+            if forwarded_headers.x_forwarded() {
+                upstream_request.insert_header(headers.forwarded_for.clone(), client_ip.to_string())?;
+            }
         }
+        propagate_request_id(upstream_request, &headers.request_id, ctx)?;
 
         // Передаем оригинальный Host заголовок
+        // (значения проверены в request_filter и передаются без преобразований)
         if let Some(host) = session.req_header().headers.get("host") {
-            upstream_request.insert_header("Host", host.to_str().unwrap_or("unknown"))?;
+            upstream_request.insert_header("Host", host.clone())?;
         }
 
-        match ctx.service_type {
-            ServiceType::CoreApi | 
-            ServiceType::ChallengeApi | ServiceType::BillingApi | 
-            ServiceType::ErirApi | ServiceType::SharedApi | ServiceType::ZitadelAuth => {
-                // Определяем протокол для upstream запроса
-                let upstream_proto = if ctx.service_type == ServiceType::ZitadelAuth {
-                    // Для Zitadel используем HTTP для подключения к контейнеру
-                    "http"
-                } else {
-                    if session.req_header().uri.scheme().is_some_and(|s| s == "https") ||
-                       session.req_header().headers.get("x-forwarded-proto").is_some_and(|v| v == "https") {
-                        "https"
-                    } else {
-                        "http"
-                    }
-                };
-                
-                // Определяем протокол для X-Forwarded-Proto заголовка
-                let forwarded_proto = if ctx.service_type == ServiceType::ZitadelAuth {
-                    // Для Zitadel всегда передаем https, так как он работает за HTTPS прокси
-                    "https"
+        // proxy_pass с URI: совпавший префикс location заменяется URI, как в nginx
+        let routed_by_url = match &ctx.upstream_target {
+            UpstreamTarget::Unix { .. } => true,
+            UpstreamTarget::Named(name) => self
+                .location_for(session)
+                .and_then(|location| location.proxy_pass_url.as_ref())
+                .is_some_and(|url| url.upstream.as_ref() == Some(name)),
+            _ => false,
+        };
+        if routed_by_url {
+            if let Some(location) = self.location_for(session) {
+                let request_uri = upstream_request.uri.path_and_query().map_or("/", |uri| uri.as_str());
+                let rewritten = location
+                    .proxy_pass_url
+                    .as_ref()
+                    .and_then(|url| url.upstream_uri(&location.path, request_uri));
+                if let Some(uri) = rewritten {
+                    let uri = uri.parse::<http::Uri>().map_err(|e| {
+                        Error::because(ErrorType::InvalidHTTPHeader, format!("invalid upstream URI {}", uri), e)
+                    })?;
+                    upstream_request.set_uri(uri);
+                }
+            }
+        }
+
+        // proxy_set_header location; пустое значение убирает заголовок
+        if let Some(location) = self.location_for(session).filter(|l| !l.proxy_set_header.is_empty()) {
+            let mut variables = self.request_variables(session, ctx);
+            for (name, value) in &location.proxy_set_header {
+                let value = variables.expand(value);
+                if value.is_empty() {
+                    upstream_request.remove_header(name.as_str());
                 } else {
-                    upstream_proto
-                };
-                
-                upstream_request.insert_header("X-Forwarded-Proto", forwarded_proto)?;
-                
-                // Для Zitadel добавляем дополнительные заголовки для правильной генерации URLs
-                if ctx.service_type == ServiceType::ZitadelAuth {
-                    if let Some(host) = session.req_header().headers.get("host") {
-                        upstream_request.insert_header("X-Forwarded-Host", host.to_str().unwrap_or("auth.ad-quest.ru"))?;
-                    }
-                    
-                    // Добавляем X-Forwarded-Port для HTTPS
-                    if forwarded_proto == "https" {
-                        upstream_request.insert_header("X-Forwarded-Port", "443")?;
+                    upstream_request.insert_header(name.clone(), value)?;
+                }
+            }
+        }
+
+        match ctx.upstream_target {
+            UpstreamTarget::Named(_) | UpstreamTarget::Direct(_) | UpstreamTarget::Unix { .. } => {
+                // X-Forwarded-Proto/Host/Port по профилю бэкенда (backend_profiles)
+                if forwarded_headers.x_forwarded() {
+                    add_forwarded_headers(upstream_request, session.req_header(), headers, ctx.scheme, self.backend_profile(ctx))?;
+                }
+                // Forwarded (RFC 7239): цепочка доверенного прокси продолжается,
+                // значение от остальных клиентов заменяется
+                if forwarded_headers.forwarded() {
+                    let peer = client_ip(session);
+                    let mut chain = if self.scheme_resolver.is_trusted(peer) {
+                        forwarded::from_request(session.req_header())
                     } else {
-                        upstream_request.insert_header("X-Forwarded-Port", "80")?;
+                        Vec::new()
+                    };
+                    let host = session.req_header().headers.get("host").and_then(|v| v.to_str().ok());
+                    let proto = forwarded_proto(ctx.scheme, self.backend_profile(ctx));
+                    let mut element = ForwardedElement::for_client(peer, proto, host);
+                    if let Some(by) = &headers.forwarded_by {
+                        element = element.with("by", by.clone());
                     }
-                }
-                
-                // Поддержка WebSocket
-                if let Some(upgrade) = session.req_header().headers.get("upgrade") {
-                    upstream_request.insert_header("Upgrade", upgrade.to_str().unwrap_or(""))?;
-                    upstream_request.insert_header("Connection", "upgrade")?;
-                } else {
-                    upstream_request.insert_header("Connection", "close")?;
+                    chain.push(element);
+                    let value: Vec<String> = chain.iter().map(ToString::to_string).collect();
+                    upstream_request.insert_header("Forwarded", value.join(", "))?;
                 }
             }
-            ServiceType::Static => {}
+            UpstreamTarget::None => {}
         }
 
+        // Группы экспериментов; значения клиента в X-Experiment-* заменяются
+        if let Some(experiments) = &self.experiments {
+            ctx.experiments = experiments.assign(session.req_header(), ctx.client_ip);
+            for assignment in &ctx.experiments {
+                upstream_request.insert_header(assignment.header.clone(), assignment.bucket.as_str())?;
+            }
+        }
+
+        // Истекшая запись кеша проверяется условным запросом: 304 продлевает ее, полный ответ заменяет
+        if is_revalidating(session) {
+            if let Some(meta) = session.cache.maybe_cache_meta() {
+                add_revalidation_headers(upstream_request, meta.response_header())?;
+            }
+        }
+
+        // Upstream получает только согласованную кодировку: в кеше под ее вариантом
+        // не окажется ответ в кодировке, которую клиент не принимает
+        if let Some(negotiated) = ctx.negotiated_encoding {
+            upstream_request.insert_header("Accept-Encoding", negotiated.variant())?;
+        }
+
+        // JSON тело запроса маскируется целиком, длина заранее неизвестна (redact on)
+        if let Some(pipeline) = self.redaction_for(self.location_for(session)) {
+            let content_type = session.req_header().headers.get("content-type").and_then(|v| v.to_str().ok());
+            ctx.request_transform = pipeline.collector(content_type);
+            if ctx.request_transform.is_some() {
+                upstream_request.remove_header("Content-Length");
+                upstream_request.insert_header("Transfer-Encoding", "chunked")?;
+            }
+            // Сжатый ответ не разобрать: запрашиваем его без Content-Encoding
+            upstream_request.remove_header("Accept-Encoding");
+        }
+
+        Ok(())
+    }
+
+    fn upstream_response_filter(
+        &self,
+        session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        // Таймер истек одновременно с приходом заголовков: соединение уже закрыто
+        if ctx.first_byte_timer.take().is_some_and(|timer| timer.expired()) {
+            return Err(first_byte_timeout_error());
+        }
+        ctx.upstream_status = Some(upstream_response.status.as_u16());
+        if is_revalidating(session) {
+            Revalidation::from_status(upstream_response.status.as_u16()).record();
+        }
+        // До записи в кеш: hop-by-hop заголовки upstream не попадают ни в кеш, ни к клиенту
+        strip_response(upstream_response)
+    }
+
+    async fn request_body_filter(
+        &self,
+        _session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        if let Some(transform) = ctx.request_transform.as_mut() {
+            *body = transform.push(body.take(), end_of_stream);
+        }
+        // proxy_first_byte_timeout отсчитывается от отправки запроса целиком, вместе с телом
+        if end_of_stream {
+            if let Some(timer) = ctx.first_byte_timer.as_mut() {
+                timer.start();
+            }
+        }
         Ok(())
     }
 
@@ -458,89 +838,1025 @@ impl ProxyHttp for AdQuestProxy {
         upstream_response: &mut ResponseHeader,
         ctx: &mut Self::CTX,
     ) -> Result<()> {
-        // Для gRPC-Web запросов проверяем, был ли модуль активирован
-        // Если ответ не gRPC (например, 404 JSON), модуль должен быть отключен
-        if ctx.service_type == ServiceType::ZitadelAuth {
-            if let Some(_grpc) = session.downstream_modules_ctx.get_mut::<GrpcWebBridge>() {
-                // Если модуль был активирован, но ответ не gRPC, отключаем его
-                let content_type = upstream_response
-                    .headers
-                    .get("content-type")
-                    .and_then(|v| v.to_str().ok())
-                    .unwrap_or("");
-                
-                if !content_type.starts_with("application/grpc") && 
-                   !content_type.starts_with("application/grpc-web") {
-                    // Ответ не gRPC, но модуль был активирован - это нормально для ошибок
-                    // Модуль сам отключится в response_header_filter
+        // Заголовки пришли после истечения proxy_request_timeout: клиент получит 504
+        if ctx.upstream_deadline_exceeded() {
+            return Err(upstream_deadline_error());
+        }
+
+        // Учитываем код ответа upstream в circuit breaker. Ответ из кеша не является
+        // результатом обращения к upstream; проба HalfOpen освобождается в logging
+        if let (Some(circuit_breaker), Some(status)) = (&self.circuit_breaker, ctx.upstream_status) {
+            circuit_breaker.record_status(ctx.upstream_label(), status).await;
+            ctx.circuit_probe = None;
+        }
+
+        // Статус из proxy_next_upstream (http_502): запрос повторяется на другом бэкенде,
+        // последний ответ при исчерпании попыток отдается клиенту. Ответ из кеша не повторяется
+        if matches!(ctx.upstream_target, UpstreamTarget::Named(_)) && ctx.selected_backend.is_some() {
+            let status = upstream_response.status.as_u16();
+            let (next, max_retries) = self.next_upstream(session);
+            if ctx.retries < max_retries
+                && !ctx.upstream_deadline_exceeded()
+                && !session.retry_buffer_truncated()
+                && retry_on_status(&next, status, session.req_header().method.as_str())
+            {
+                ctx.retries += 1;
+                info!("Upstream '{}' backend {:?} returned {}, trying next backend, attempt {}/{}",
+                      ctx.upstream_label(), ctx.selected_backend, status, ctx.retries, max_retries);
+                RETRY_ATTEMPTS
+                    .with_label_values(&[ctx.service_type.as_str(), "attempt"])
+                    .inc();
+                let mut e = Error::explain(ErrorType::HTTPStatus(status), "proxy_next_upstream").into_up();
+                e.set_retry(true);
+                return Err(e);
+            }
+        }
+        ctx.upstream_response_received = true;
+
+        // Ответ upstream с ошибкой из fallback_response when заменяется заглушкой,
+        // иначе перехват ошибок (proxy_intercept_errors): тело заменяется страницей ошибки
+        let location = self.location_for(session);
+        if let Some(location) = location {
+            let upstream_status = upstream_response.status.as_u16();
+            let conditions = [FallbackCondition::Status(upstream_status)];
+            if let Some((fallback, condition, body)) = self.fallbacks.find(location, &conditions) {
+                info!("Upstream '{}' returned {}, serving fallback_response for location '{}'",
+                      ctx.upstream_label(), upstream_status, location.path);
+                record_fallback(location, condition);
+                *upstream_response = build_fallback_header(fallback, body.len())?;
+                ctx.intercepted_body = Some(body);
+                // Заглушка не сохраняется: повтор по ключу идемпотентности получит ее статус и заголовки
+                if let Some(idempotency) = ctx.idempotency.as_mut() {
+                    idempotency.skip_body();
+                }
+            } else if let Some(status) = intercept_status(location, upstream_status) {
+                let (body, content_type) = load_error_page(location, upstream_status, status, ctx);
+                info!("Intercepted upstream {} response for '{}', serving error page with status {}",
+                      upstream_status, ctx.upstream_label(), status);
+                *upstream_response = build_intercepted_header(upstream_response, status, &content_type, body.len())?;
+                ctx.intercepted_body = Some(body);
+            }
+        }
+
+        // Преобразования тела завершаются на последнем фрагменте тела, а за телом с трейлерами
+        // следуют трейлеры: такие ответы (gRPC, Trailer) передаются без маскирования,
+        // буферизации, сжатия и сохранения тела для повторов
+        let with_trailers = trailers::may_have_trailers(upstream_response);
+        if with_trailers {
+            // HTTP/1 клиенту трейлеры не дойдут (см. response_trailer_filter): не объявляем их
+            if !session.is_http2() {
+                upstream_response.remove_header("trailer");
+            }
+        }
+
+        // Маскирование полей JSON тела ответа (redact on); тело собирается целиком
+        if let Some(pipeline) = self.redaction_for(location).filter(|_| !with_trailers) {
+            let header_value = |name: &str| upstream_response.headers.get(name).and_then(|v| v.to_str().ok());
+            let encoded = header_value("content-encoding").is_some_and(|e| !e.eq_ignore_ascii_case("identity"));
+            if ctx.intercepted_body.is_none() && session.req_header().method != "HEAD" {
+                match pipeline.collector(header_value("content-type")) {
+                    Some(_) if encoded => {
+                        warn!("Upstream '{}' returned an encoded JSON response, redaction skipped", ctx.upstream_label());
+                    }
+                    Some(collector) => {
+                        ctx.response_transform = Some(collector);
+                        upstream_response.remove_header("Content-Length");
+                        upstream_response.insert_header("Transfer-Encoding", "chunked")?;
+                    }
+                    None => {}
                 }
             }
-            
-            // Zitadel сам управляет CORS заголовками, не добавляем свои
-            // Добавляем только security заголовки
-            add_security_headers(upstream_response)?;
-        } else {
-            // Для других сервисов добавляем и security, и CORS заголовки
-            add_security_headers(upstream_response)?;
-            add_cors_headers_for_request(session, upstream_response)?;
         }
 
+        // Буферизация тела ответа (proxy_buffering on); SSE и потоковые ответы не буферизуются
+        if let Some(location) = location.filter(|l| {
+            l.proxy_buffering && !with_trailers && ctx.intercepted_body.is_none() && ctx.response_transform.is_none()
+        }) {
+            let header_value = |name: &str| upstream_response.headers.get(name).and_then(|v| v.to_str().ok());
+            let content_length = header_value("content-length").and_then(|v| v.parse::<usize>().ok());
+            if should_buffer(
+                upstream_response.status.as_u16(),
+                header_value("content-type"),
+                content_length,
+                location.proxy_buffers_size,
+            ) {
+                ctx.response_buffer = Some(ResponseBuffer::new(self.buffer_budget.clone(), location.proxy_buffers_size));
+            }
+        }
+
+        // Адрес upstream в Location/Refresh заменяется публичным хостом (proxy_redirect)
+        let default_redirect = ProxyRedirect::Default;
+        let redirect = location.map_or(&default_redirect, |l| &l.proxy_redirect);
+        let public_origin = format!("{}://{}", ctx.scheme, request_host(session));
+        rewrite_redirect_headers(upstream_response, redirect, &upstream_hosts(ctx), &public_origin)?;
+
+        // Домен и путь Set-Cookie upstream (proxy_cookie_domain / proxy_cookie_path)
+        if let Some(location) = location {
+            let host = request_host(session);
+            let public_host = host.split(':').next().unwrap_or(host);
+            rewrite_cookie_headers(
+                upstream_response,
+                &location.proxy_cookie_domain,
+                &location.proxy_cookie_path,
+                public_host,
+            )?;
+        }
+
+        // Ответ для повторов с тем же ключом идемпотентности (без заголовков, зависящих от запроса)
+        if let Some(idempotency) = ctx.idempotency.as_mut() {
+            idempotency.set_response(upstream_response);
+            // Ответ с трейлерами хранится без тела (tombstone)
+            if with_trailers {
+                idempotency.skip_body();
+                idempotency.finish();
+                ctx.idempotency = None;
+            }
+        }
+
+        // Сжатие ответа согласованной кодировкой (после сохранения несжатого ответа для повторов)
+        if let (Some(compression), Some(negotiated), false) = (&self.compression, ctx.negotiated_encoding, with_trailers) {
+            ctx.response_compressor = compression.start(upstream_response, negotiated)?;
+        }
+
+        // Security и CORS заголовки; бэкенды с manages_own_cors выставляют CORS сами
+        add_response_headers(session.req_header(), upstream_response, self.backend_profile(ctx))?;
+        add_scheme_csp(upstream_response, ctx.scheme)?;
+
+        // add_header location заменяет одноименные заголовки; пустое значение не добавляется
+        if let Some(location) = location.filter(|l| !l.add_header.is_empty()) {
+            let status = upstream_response.status.as_u16();
+            let mut variables = self.request_variables(session, ctx);
+            for header in location.add_header.iter().filter(|h| h.always || add_header_status(status)) {
+                let value = variables.expand(&header.value);
+                if !value.is_empty() {
+                    upstream_response.insert_header(header.name.clone(), value)?;
+                }
+            }
+        }
+
+        add_timing_headers(upstream_response, &self.config.response_headers, ctx)?;
+        // Закрепление групп экспериментов за клиентом
+        for assignment in &ctx.experiments {
+            if let Some(cookie) = assignment.set_cookie(ctx.scheme == "https") {
+                upstream_response.append_header("Set-Cookie", cookie)?;
+            }
+        }
+        add_request_id_header(upstream_response, &self.config.proxy_headers.request_id, ctx)?;
+
+        // Клиент HTTP/1.0 не понимает chunked: тело без Content-Length передается до закрытия соединения
+        if is_http10(session.req_header()) {
+            let head = session.req_header().method == "HEAD";
+            if !frame_response(upstream_response, head, ctx.downstream_keepalive)? {
+                ctx.downstream_keepalive = false;
+                session.set_keepalive(None);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn response_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> Result<Option<Duration>>
+    where
+        Self::CTX: Send + Sync,
+    {
+        // Тело не успело прийти до истечения proxy_request_timeout: соединение с upstream прерывается
+        if !end_of_stream && ctx.upstream_deadline_exceeded() {
+            return Err(upstream_deadline_error());
+        }
+
+        // HEAD из закешированного GET: только заголовки
+        if session.req_header().method == "HEAD" {
+            *body = None;
+            return Ok(None);
+        }
+
+        // Тело с маскированными полями отдается в конце потока
+        if let Some(transform) = ctx.response_transform.as_mut() {
+            *body = transform.push(body.take(), end_of_stream);
+        }
+
+        // Тело перехваченного ответа upstream отбрасывается и заменяется страницей ошибки
+        if ctx.intercepted_body.is_some() {
+            *body = if end_of_stream { ctx.intercepted_body.take() } else { None };
+        } else if let Some(buffer) = ctx.response_buffer.as_mut() {
+            // Тело отдается клиенту целиком или потоком после превышения лимита
+            let buffering = buffer.is_buffering();
+            *body = buffer.push(body.take(), end_of_stream);
+            if end_of_stream {
+                ctx.response_buffer = None;
+                // Тело целиком в буфере: upstream больше не нужен, пока медленный клиент его читает
+                if buffering {
+                    self.release_upstream(ctx);
+                }
+            }
+        }
+
+        // Тело, отправленное клиенту, сохраняется для повторов по ключу идемпотентности
+        if let Some(idempotency) = ctx.idempotency.as_mut() {
+            if let Some(chunk) = body.as_ref() {
+                idempotency.push_body(chunk);
+            }
+            if end_of_stream {
+                idempotency.finish();
+                ctx.idempotency = None;
+            }
+        }
+
+        if let Some(compressor) = ctx.response_compressor.as_mut() {
+            *body = compressor.push(body.take(), end_of_stream);
+            if end_of_stream {
+                ctx.response_compressor = None;
+            }
+        }
+        Ok(None)
+    }
+
+    fn upstream_response_trailer_filter(
+        &self,
+        _session: &mut Session,
+        upstream_trailers: &mut http::HeaderMap,
+        _ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        trailers::sanitize(upstream_trailers);
         Ok(())
     }
 
+    async fn response_trailer_filter(
+        &self,
+        session: &mut Session,
+        upstream_trailers: &mut http::HeaderMap,
+        ctx: &mut Self::CTX,
+    ) -> Result<Option<Bytes>>
+    where
+        Self::CTX: Send + Sync,
+    {
+        // HTTP/2 клиент получает трейлеры кадром HEADERS. HTTP/1 сервер Pingora не пишет
+        // секцию трейлеров chunked ответа: они теряются. gRPC-Web bridge переносит их в тело сам
+        if ctx.grpc_web {
+            return Ok(None);
+        }
+        if session.is_http2() {
+            RESPONSE_TRAILERS.with_label_values(&["forwarded"]).inc();
+        } else {
+            RESPONSE_TRAILERS.with_label_values(&["dropped"]).inc();
+            log::debug!("Dropped {} upstream trailer field(s) for HTTP/1 client on {}",
+                        upstream_trailers.len(), session.req_header().uri.path());
+        }
+        Ok(None)
+    }
+
+    async fn fail_to_proxy(
+        &self,
+        session: &mut Session,
+        e: &Error,
+        ctx: &mut Self::CTX,
+    ) -> FailToProxy
+    where
+        Self::CTX: Send + Sync,
+    {
+        // Истек proxy_request_timeout (таймауты peer ограничены оставшимся временем),
+        // иначе таймаут чтения до получения заголовков ответа - это first-byte timeout
+        let deadline_exceeded = matches!(
+            e.etype(),
+            ErrorType::ReadTimedout | ErrorType::WriteTimedout | ErrorType::ConnectTimedout
+        ) && ctx.upstream_deadline_exceeded();
+        let timed_out = match e.etype() {
+            _ if deadline_exceeded => {
+                UPSTREAM_TIMEOUTS.with_label_values(&["request"]).inc();
+                true
+            }
+            ErrorType::ReadTimedout if ctx.upstream_response_received => {
+                UPSTREAM_TIMEOUTS.with_label_values(&["read"]).inc();
+                false
+            }
+            ErrorType::ReadTimedout => {
+                UPSTREAM_TIMEOUTS.with_label_values(&["first_byte"]).inc();
+                true
+            }
+            ErrorType::ConnectTimedout => true,
+            _ => false,
+        };
+
+        // Проверка записи кеша не дошла до ответа upstream
+        if is_revalidating(session) && !ctx.upstream_response_received {
+            Revalidation::Error.record();
+        }
+
+        // Частично полученное буферизованное тело не отправляется клиенту
+        if let Some(mut buffer) = ctx.response_buffer.take() {
+            if buffer.is_buffering() {
+                let discarded = buffer.discard();
+                warn!("Upstream '{}' failed before the buffered response completed, discarded {} bytes: {}",
+                      ctx.upstream_label(), discarded, e);
+            }
+        }
+
+        // Ключ идемпотентности освобождается: повтор клиента пойдет к upstream
+        ctx.idempotency = None;
+
+        // Ошибка upstream до получения ответа - сбой для circuit breaker
+        if !ctx.upstream_response_received && matches!(e.esource(), ErrorSource::Upstream) {
+            if let Some(circuit_breaker) = &self.circuit_breaker {
+                circuit_breaker.record_failure(ctx.upstream_label()).await;
+                ctx.circuit_probe = None;
+            }
+        }
+
+        // Заглушка location (fallback_response) вместо ответа об ошибке
+        if session.response_written().is_none() {
+            let conditions = proxy_failure_conditions(e, ctx.upstream_response_received);
+            if let Some(location) = self.location_for(session) {
+                if let Some((fallback, condition, body)) = self.fallbacks.find(location, &conditions) {
+                    info!("Upstream '{}' failed, serving fallback_response for location '{}': {}",
+                          ctx.upstream_label(), location.path, e);
+                    record_fallback(location, condition);
+                    let status = fallback.status;
+                    let _ = send_fallback_response(session, fallback, body).await;
+                    return FailToProxy {
+                        error_code: status,
+                        can_reuse_downstream: false,
+                    };
+                }
+            }
+        }
+
+        if timed_out {
+            let upstream = ctx.upstream_label().to_string();
+            info!("Upstream '{}' timed out: {}", upstream, e);
+
+            if deadline_exceeded {
+                let client_ip = ctx.client_ip.map(|ip| ip.to_string());
+                let timeout = ctx.upstream_timeouts.and_then(|timeouts| timeouts.request).unwrap_or_default();
+                self.logging_middleware
+                    .error_logger()
+                    .log_error(
+                        "upstream_timeout",
+                        &format!("Upstream '{}' exceeded proxy_request_timeout of {:?}", upstream, timeout),
+                        Some(&e.to_string()),
+                        client_ip.as_deref(),
+                        Some(&session.req_header().uri.to_string()),
+                    )
+                    .await;
+            }
+
+            // Заголовки ответа могли быть уже отправлены (таймаут во время тела)
+            if session.response_written().is_none() {
+                let _ = ErrorResponse::new(ErrorCode::UpstreamTimeout)
+                    .upstream(upstream)
+                    .send(session, ctx)
+                    .await;
+            }
+
+            return FailToProxy {
+                error_code: 504,
+                can_reuse_downstream: false,
+            };
+        }
+
+        let code = error_status(e);
+
+        // Ответ в едином JSON формате, если заголовки еще не отправлены клиенту
+        if code > 0 && session.response_written().is_none() {
+            let mut error = ErrorResponse::new(ErrorCode::from_status(code));
+            if code == 502 {
+                error = error.upstream(ctx.upstream_label());
+            }
+            let _ = error.send(session, ctx).await;
+        }
+
+        FailToProxy {
+            error_code: code,
+            can_reuse_downstream: false,
+        }
+    }
+
     async fn logging(
         &self,
         session: &mut Session,
-        _e: Option<&Error>,
+        e: Option<&Error>,
         ctx: &mut Self::CTX,
     ) {
-        let response_code = session
-            .response_written()
-            .map_or(0, |resp| resp.status.as_u16());
-
-        let service_name = match ctx.service_type {
-            ServiceType::CoreApi => "CORE_API",
-            ServiceType::ChallengeApi => "CHALLENGE_API",
-            ServiceType::BillingApi => "BILLING_API",
-            ServiceType::ErirApi => "ERIR_API",
-            ServiceType::SharedApi => "SHARED_API",
-            ServiceType::ZitadelAuth => "ZITADEL_AUTH",
-            ServiceType::Static => "STATIC",
+        // Запрос завершен - освобождаем слоты upstream, если они еще заняты
+        self.release_upstream(ctx);
+        // Проба HalfOpen без результата (отклонена стадией, клиент отключился) не держит слот
+        if let (Some(token), Some(circuit_breaker)) = (ctx.circuit_probe.take(), &self.circuit_breaker) {
+            circuit_breaker.release_probe(token).await;
+        }
+
+        // Клиент отключился: Pingora уже закрыл соединение с upstream
+        if e.is_some_and(is_client_abort) {
+            record_client_abort(ctx, session.body_bytes_sent());
+        }
+
+        // Соединение, закрытое без ответа, учитывается как 444 (как в nginx)
+        let response_code = if ctx.connection_closed {
+            crate::stages::CLOSE_CONNECTION
+        } else {
+            session.response_written().map_or(0, |resp| resp.status.as_u16())
         };
 
-        let service_name_metric = match ctx.service_type {
-            ServiceType::CoreApi => "core_api",
-            ServiceType::ChallengeApi => "challenge_api",
-            ServiceType::BillingApi => "billing_api",
-            ServiceType::ErirApi => "erir_api",
-            ServiceType::SharedApi => "shared_api",
-            ServiceType::ZitadelAuth => "zitadel_auth",
-            ServiceType::Static => "static",
+        let service_name = match ctx.handled_by {
+            HandledBy::ProxyLocal => "LOCAL",
+            HandledBy::Upstream => match ctx.service_type {
+                ServiceType::CoreApi => "CORE_API",
+                ServiceType::ChallengeApi => "CHALLENGE_API",
+                ServiceType::BillingApi => "BILLING_API",
+                ServiceType::ErirApi => "ERIR_API",
+                ServiceType::SharedApi => "SHARED_API",
+                ServiceType::ZitadelAuth => "ZITADEL_AUTH",
+                ServiceType::Static => "STATIC",
+            },
         };
 
         let method = session.req_header().method.as_str();
         let duration = ctx.start_time.elapsed().as_secs_f64();
 
-        // Prometheus метрики
-        HTTP_REQUESTS_TOTAL
-            .with_label_values(&[method, &response_code.to_string(), service_name_metric])
-            .inc();
+        // Prometheus метрики (локальные ответы учитываются под сервисом local)
+        record_http_request(method, response_code, ctx);
+
+        // Access log: access_log location, затем server блока, иначе глобальный
+        let access_log = self.config.find_server(request_host(session)).and_then(|server| {
+            self.config
+                .find_location(server, session.req_header().uri.path())
+                .and_then(|location| location.access_log.as_ref())
+                .or(server.access_log.as_ref())
+        });
+        self.logging_middleware
+            .access_logger()
+            .log_request_to(
+                session,
+                ctx,
+                response_code,
+                session.body_bytes_sent() as u64,
+                ctx.start_time.elapsed().as_millis() as u64,
+                access_log,
+            )
+            .await;
 
-        HTTP_REQUEST_DURATION.observe(duration);
+        // Flight recorder: заголовки и тайминги запросов с 5xx и ошибками соединения
+        if let Some(flight_recorder) = &self.flight_recorder {
+            if should_record(response_code, e) {
+                flight_recorder.record(session, ctx, response_code, e);
+            }
+        }
+
+        // Одна запись decisions log на запрос из выборки: выбор бэкенда и число повторов
+        if let Some(decision) = ctx.upstream_decision.take() {
+            if let Some(decisions_log) = self.logging_middleware.decisions_log() {
+                let location = self.location_for(session).map(|location| location.path.as_str());
+                decisions_log.log(&DecisionRecord::from_session(session, ctx, decision, location));
+            }
+        }
 
         let client_addr = session.client_addr()
             .map(|addr| addr.to_string())
             .unwrap_or_else(|| "unknown".to_string());
 
         info!(
-            "[{}] {} {} -> {}, response: {} (duration: {:.3}s, retries: {})",
+            "[{}] {} {} -> {}, upstream: {}, response: {} (duration: {:.3}s, retries: {})",
             service_name,
             session.req_header().method,
             session.req_header().uri,
             client_addr,
+            ctx.upstream_target,
             response_code,
             duration,
             ctx.retries
         );
     }
-}
\ No newline at end of file
+}
+
+/// Применяет таймауты upstream к peer.
+/// Pingora применяет read_timeout к каждому чтению из upstream, поэтому у peer - read
+/// таймаут, а ожидание заголовков ответа ограничивает FirstByteTimer (connected_to_upstream).
+/// Оставшееся до proxy_request_timeout время ограничивает все таймауты попытки.
+fn apply_upstream_timeouts(peer: &mut HttpPeer, timeouts: &UpstreamTimeouts, time_left: Option<Duration>) {
+    let cap = |timeout: Duration| time_left.map_or(timeout, |left| timeout.min(left));
+    if let Some(connect) = timeouts.connect.or(time_left) {
+        peer.options.connection_timeout = Some(cap(connect));
+    }
+    peer.options.read_timeout = Some(cap(timeouts.read));
+    if let Some(write) = timeouts.write.or(time_left) {
+        peer.options.write_timeout = Some(cap(write));
+    }
+}
+
+/// Включает TCP keepalive соединения с upstream (global.tcp_keepalive)
+fn apply_tcp_keepalive(peer: &mut HttpPeer, keepalive: Option<&TcpKeepaliveConfig>) {
+    peer.options.tcp_keepalive = keepalive.map(|keepalive| TcpKeepalive {
+        idle: Duration::from_secs(keepalive.idle),
+        interval: Duration::from_secs(keepalive.interval),
+        count: keepalive.count,
+        // 0 - системное значение TCP_USER_TIMEOUT
+        #[cfg(target_os = "linux")]
+        user_timeout: Duration::ZERO,
+    });
+}
+
+/// Статусы, к которым add_header без always добавляет заголовки (как в nginx)
+fn add_header_status(status: u16) -> bool {
+    matches!(status, 200 | 201 | 204 | 206 | 301 | 302 | 303 | 304 | 307 | 308)
+}
+
+/// Ошибка downstream соединения: клиент закрыл его до окончания ответа
+fn is_client_abort(e: &Error) -> bool {
+    matches!(e.esource(), ErrorSource::Downstream)
+        && matches!(
+            e.etype(),
+            ErrorType::ReadError | ErrorType::WriteError | ErrorType::ConnectionClosed
+        )
+}
+
+/// Учитывает отключение клиента и освобождает буферизованное состояние запроса
+fn record_client_abort(ctx: &mut RequestContext, bytes_sent: usize) {
+    ctx.client_aborted = true;
+    ctx.release_buffered_state();
+    CLIENT_ABORTS.with_label_values(&[ctx.service_label()]).inc();
+    CLIENT_ABORTED_BYTES
+        .with_label_values(&[ctx.service_label()])
+        .inc_by(bytes_sent as u64);
+    info!("Client aborted request {} to '{}' after {} response bytes",
+          ctx.request_id, ctx.upstream_label(), bytes_sent);
+}
+
+/// Код ответа клиенту для ошибки проксирования, как у Pingora (0 - клиент отключился)
+fn error_status(e: &Error) -> u16 {
+    match e.etype() {
+        ErrorType::HTTPStatus(code) => *code,
+        _ => match e.esource() {
+            ErrorSource::Upstream => 502,
+            ErrorSource::Downstream => match e.etype() {
+                ErrorType::WriteError | ErrorType::ReadError | ErrorType::ConnectionClosed => 0,
+                _ => 400,
+            },
+            ErrorSource::Internal | ErrorSource::Unset => 500,
+        },
+    }
+}
+
+/// Ошибка истечения proxy_first_byte_timeout, приводит к 504 в fail_to_proxy
+fn first_byte_timeout_error() -> Box<Error> {
+    Error::explain(ErrorType::ReadTimedout, "upstream first byte timeout exceeded").into_up()
+}
+
+/// Ошибка истечения proxy_request_timeout, приводит к 504 в fail_to_proxy
+fn upstream_deadline_error() -> Box<Error> {
+    Error::explain(ErrorType::ReadTimedout, "upstream request timeout exceeded").into_up()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::ProxyBuilder;
+    use crate::config::{Config, NginxConfig};
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_request_timeout_caps_peer_timeouts() {
+        let mut config = Config::default();
+        config.global.request_timeout = Some(5);
+        let timeouts = config.resolve_upstream_timeouts(None);
+        assert_eq!(timeouts.request, Some(Duration::from_secs(5)));
+
+        let mut peer = HttpPeer::new("127.0.0.1:9", false, "".to_string());
+        apply_upstream_timeouts(&mut peer, &timeouts, Some(Duration::from_secs(2)));
+        assert_eq!(peer.options.connection_timeout, Some(Duration::from_secs(2)));
+        assert_eq!(peer.options.read_timeout, Some(Duration::from_secs(2)));
+        assert_eq!(peer.options.write_timeout, Some(Duration::from_secs(2)));
+
+        // Без request timeout у peer действует read таймаут
+        let mut peer = HttpPeer::new("127.0.0.1:9", false, "".to_string());
+        apply_upstream_timeouts(&mut peer, &Config::default().resolve_upstream_timeouts(None), None);
+        assert_eq!(peer.options.read_timeout, Some(Duration::from_secs(config.global.default_timeout)));
+        assert_eq!(peer.options.write_timeout, None);
+
+        // Таймаут записи location ограничивается оставшимся временем
+        let mut location = NginxConfig::parse_location_block("/upload", "proxy_pass api;\nproxy_send_timeout 10s;").unwrap();
+        let timeouts = config.resolve_upstream_timeouts(Some(&location));
+        let mut peer = HttpPeer::new("127.0.0.1:9", false, "".to_string());
+        apply_upstream_timeouts(&mut peer, &timeouts, None);
+        assert_eq!(peer.options.write_timeout, Some(Duration::from_secs(10)));
+        apply_upstream_timeouts(&mut peer, &timeouts, Some(Duration::from_secs(2)));
+        assert_eq!(peer.options.write_timeout, Some(Duration::from_secs(2)));
+        location.proxy_send_timeout = None;
+        config.global.write_timeout = Some(7);
+        assert_eq!(config.resolve_upstream_timeouts(Some(&location)).write, Some(Duration::from_secs(7)));
+    }
+
+    /// Локальный listener с заполненной очередью accept: новые SYN отбрасываются ядром,
+    /// подключение висит до connect timeout без обращения к внешней сети
+    fn full_backlog_listener() -> (socket2::Socket, Vec<std::net::TcpStream>) {
+        let listener = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None).unwrap();
+        listener.bind(&"127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap().into()).unwrap();
+        listener.listen(0).unwrap();
+        let addr = listener.local_addr().unwrap().as_socket().unwrap();
+        // Соединения не принимаются: очередь заполнена, когда очередное подключение не проходит
+        let mut queued = Vec::new();
+        while let Ok(stream) = std::net::TcpStream::connect_timeout(&addr, Duration::from_millis(200)) {
+            queued.push(stream);
+            assert!(queued.len() < 64, "accept queue does not fill up");
+        }
+        (listener, queued)
+    }
+
+    #[tokio::test]
+    async fn test_direct_peer_uses_connect_timeout() {
+        let (listener, _queued) = full_backlog_listener();
+        let addr = listener.local_addr().unwrap().as_socket().unwrap();
+        let mut config = Config::default();
+        config.global.connect_timeout = Some(1);
+        config.global.tcp_keepalive = Some(TcpKeepaliveConfig { idle: 60, interval: 10, count: 3 });
+
+        // Как в upstream_peer для UpstreamTarget::Direct без стадии routing
+        let mut peer = HttpPeer::new(addr, false, "".to_string());
+        apply_upstream_timeouts(&mut peer, &config.resolve_upstream_timeouts(None), None);
+        apply_tcp_keepalive(&mut peer, config.global.tcp_keepalive.as_ref());
+        assert_eq!(peer.options.read_timeout, Some(Duration::from_secs(config.global.default_timeout)));
+        assert_eq!(peer.options.tcp_keepalive.as_ref().map(|k| k.count), Some(3));
+
+        let connector = pingora_core::connectors::http::Connector::new(None);
+        let start = std::time::Instant::now();
+        let e = connector.get_http_session(&peer).await.err().unwrap();
+        // Ошибка через connect timeout, а не через таймаут ОС
+        let elapsed = start.elapsed();
+        assert_eq!(e.etype(), &ErrorType::ConnectTimedout, "{}", e);
+        assert!(elapsed >= Duration::from_millis(900) && elapsed < Duration::from_secs(3), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn test_selected_backend_in_access_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("access.log");
+        let mut config = Config::default();
+        config.logging.access_log.path = log_path.to_string_lossy().to_string();
+        config.logging.access_log.format = "json".to_string();
+        let decisions_path = dir.path().join("decisions.log");
+        config.logging.decisions_log = Some(crate::config::DecisionsLogConfig {
+            path: decisions_path.to_string_lossy().to_string(),
+            sample_rate: 1.0,
+            rotate: None,
+        });
+        let proxy = ProxyBuilder::new(config)
+            .upstream("core_api", Arc::new(LoadBalancer::<RoundRobin>::try_from_iter(["10.0.0.7:8080"]).unwrap()))
+            .upstream("zitadel_auth", Arc::new(LoadBalancer::<RoundRobin>::try_from_iter(["10.0.0.9:8080"]).unwrap()))
+            .build()
+            .unwrap()
+            .proxy;
+
+        let mut session = crate::stages::test_session("GET /api/users HTTP/1.1\r\nHost: api.ad-quest.ru\r\n\r\n").await;
+        let mut ctx = RequestContext::new();
+        ctx.upstream_target = UpstreamTarget::Named("core_api".to_string());
+        proxy.upstream_peer(&mut session, &mut ctx).await.unwrap();
+        assert_eq!(ctx.upstream_addr.as_deref(), Some("10.0.0.7:8080"));
+
+        proxy.logging(&mut session, None, &mut ctx).await;
+        let content = std::fs::read_to_string(&log_path).unwrap();
+        let line: serde_json::Value = serde_json::from_str(content.lines().last().unwrap()).unwrap();
+        assert_eq!(line["fields"]["upstream_addr"], "10.0.0.7:8080");
+        assert_eq!(line["fields"]["upstream"], "core_api");
+
+        // Решение из upstream_peer и итог из logging() - одна запись decisions log
+        let decisions = std::fs::read_to_string(&decisions_path).unwrap();
+        assert_eq!(decisions.lines().count(), 1);
+        let record: serde_json::Value = serde_json::from_str(decisions.lines().next().unwrap()).unwrap();
+        assert_eq!(record["backend"], "10.0.0.7:8080");
+        assert_eq!(record["selection_algo"], "round_robin");
+        assert_eq!(record["in_flight_at_selection"], 0);
+        assert_eq!(record["request_id"], ctx.request_id);
+    }
+
+    #[tokio::test]
+    async fn test_minimal_proxy_from_builder() {
+        let proxy = ProxyBuilder::new(Config::default())
+            .upstream("billing_api", Arc::new(LoadBalancer::<RoundRobin>::try_from_iter(["10.0.0.11:8080"]).unwrap()))
+            .build()
+            .unwrap()
+            .proxy;
+
+        let mut session = crate::stages::test_session("GET /billing/invoices HTTP/1.1\r\nHost: api.ad-quest.ru\r\n\r\n").await;
+        let mut ctx = RequestContext::new();
+        ctx.upstream_target = UpstreamTarget::Named("billing_api".to_string());
+        ctx.upstream_name = Some("billing_api".to_string());
+        proxy.upstream_peer(&mut session, &mut ctx).await.unwrap();
+        assert_eq!(ctx.upstream_addr.as_deref(), Some("10.0.0.11:8080"));
+        assert_eq!(proxy.drain_tracker.in_flight("billing_api", "10.0.0.11:8080"), 1);
+
+        // Upstream, не переданный builder, не настроен
+        let mut ctx = RequestContext::new();
+        ctx.upstream_target = UpstreamTarget::Named("core_api".to_string());
+        let e = proxy.upstream_peer(&mut session, &mut ctx).await.unwrap_err();
+        assert_eq!(error_status(&e), 502);
+    }
+
+    #[tokio::test]
+    async fn test_half_open_probe_without_outcome_is_released() {
+        use crate::circuit_breaker::{Admission, CircuitState};
+        use crate::config::CircuitBreakerConfig;
+        use crate::stages::{CircuitBreakerStage, StageResult};
+
+        let circuit_breaker = Arc::new(CircuitBreaker::new(CircuitBreakerConfig {
+            enabled: true,
+            failure_threshold: 1,
+            recovery_timeout: 0, // HalfOpen сразу после открытия
+            success_threshold: 1,
+            failure_rate: None,
+            half_open_max_requests: 1,
+            failure_status_codes: vec![502],
+            fallback: None,
+        }));
+        let mut config = Config::default();
+        config.logging.access_log.enabled = false;
+        let config = Arc::new(config);
+        let proxy = ProxyBuilder::new(config.clone())
+            .circuit_breaker(circuit_breaker.clone())
+            .build()
+            .unwrap()
+            .proxy;
+        let stage = CircuitBreakerStage::new(circuit_breaker.clone(), config, Arc::default());
+        circuit_breaker.record_failure("billing_api").await;
+
+        // Проба пропущена стадией, но запрос завершился без ответа upstream
+        // (отклонен следующей стадией или клиент отключился)
+        let mut session = crate::stages::test_session("GET /billing/invoices HTTP/1.1\r\nHost: api.ad-quest.ru\r\n\r\n").await;
+        let mut ctx = RequestContext::new();
+        ctx.upstream_name = Some("billing_api".to_string());
+        assert!(matches!(stage.handle(&mut session, &mut ctx).await.unwrap(), StageResult::Continue));
+        assert!(ctx.circuit_probe.is_some());
+        assert!(!circuit_breaker.can_execute("billing_api").await);
+
+        proxy.logging(&mut session, None, &mut ctx).await;
+        assert!(ctx.circuit_probe.is_none());
+        assert_eq!(circuit_breaker.get_state("billing_api").await, CircuitState::HalfOpen);
+        // Слот свободен: следующая проба проходит
+        assert!(matches!(circuit_breaker.admit("billing_api").await, Admission::Probe(_)));
+    }
+
+    #[tokio::test]
+    async fn test_cache_hit_not_recorded_by_circuit_breaker() {
+        use crate::circuit_breaker::CircuitState;
+        use crate::config::CircuitBreakerConfig;
+
+        let circuit_breaker = Arc::new(CircuitBreaker::new(CircuitBreakerConfig {
+            enabled: true,
+            failure_threshold: 1,
+            recovery_timeout: 60,
+            success_threshold: 1,
+            failure_rate: None,
+            half_open_max_requests: 1,
+            failure_status_codes: vec![502],
+            fallback: None,
+        }));
+        let proxy = ProxyBuilder::new(Config::default())
+            .circuit_breaker(circuit_breaker.clone())
+            .build()
+            .unwrap()
+            .proxy;
+        let mut session = crate::stages::test_session("GET /billing/invoices HTTP/1.1\r\nHost: api.ad-quest.ru\r\n\r\n").await;
+
+        // Закешированный 502 отдается без обращения к upstream
+        let mut ctx = RequestContext::new();
+        ctx.upstream_name = Some("billing_api".to_string());
+        let mut cached = ResponseHeader::build(502, None).unwrap();
+        proxy.response_filter(&mut session, &mut cached, &mut ctx).await.unwrap();
+        assert_eq!(circuit_breaker.get_state("billing_api").await, CircuitState::Closed);
+
+        // Тот же статус от upstream - сбой
+        let mut ctx = RequestContext::new();
+        ctx.upstream_name = Some("billing_api".to_string());
+        let mut response = ResponseHeader::build(502, None).unwrap();
+        proxy.upstream_response_filter(&mut session, &mut response, &mut ctx).unwrap();
+        proxy.response_filter(&mut session, &mut response, &mut ctx).await.unwrap();
+        assert_eq!(circuit_breaker.get_state("billing_api").await, CircuitState::Open);
+    }
+
+    #[tokio::test]
+    async fn test_dangling_upstream_yields_502() {
+        let dir = tempfile::tempdir().unwrap();
+        let build = |default_upstream: Option<&str>| {
+            let mut config = Config::default();
+            config.logging.error_log.path = dir.path().join("error.log").to_string_lossy().to_string();
+            config.global.default_upstream = default_upstream.map(str::to_string);
+            ProxyBuilder::new(config)
+                .upstream("core_api", Arc::new(LoadBalancer::<RoundRobin>::try_from_iter(["10.0.0.7:8080"]).unwrap()))
+                .upstream("zitadel_auth", Arc::new(LoadBalancer::<RoundRobin>::try_from_iter(["10.0.0.9:8080"]).unwrap()))
+                .build()
+                .unwrap()
+                .proxy
+        };
+
+        // proxy_pass на upstream, которого нет среди балансировщиков (частичный reload)
+        let proxy = build(None);
+        let mut session = crate::stages::test_session("GET /api/users HTTP/1.1\r\nHost: api.ad-quest.ru\r\n\r\n").await;
+        let mut ctx = RequestContext::new();
+        ctx.upstream_target = UpstreamTarget::Named("billing_v2".to_string());
+        let e = proxy.upstream_peer(&mut session, &mut ctx).await.unwrap_err();
+        assert_eq!(e.etype(), &ErrorType::ConnectNoRoute);
+        assert_eq!(error_status(&e), 502);
+        assert!(ctx.upstream_addr.is_none());
+
+        // С global.default_upstream запрос уходит на резервный upstream
+        let proxy = build(Some("core_api"));
+        let mut ctx = RequestContext::new();
+        ctx.upstream_target = UpstreamTarget::Named("billing_v2".to_string());
+        proxy.upstream_peer(&mut session, &mut ctx).await.unwrap();
+        assert_eq!(ctx.upstream_addr.as_deref(), Some("10.0.0.7:8080"));
+
+        // Резервный upstream тоже не настроен
+        let proxy = build(Some("billing_v3"));
+        let mut ctx = RequestContext::new();
+        ctx.upstream_target = UpstreamTarget::Named("billing_v2".to_string());
+        let e = proxy.upstream_peer(&mut session, &mut ctx).await.unwrap_err();
+        assert_eq!(error_status(&e), 502);
+    }
+
+    #[tokio::test]
+    async fn test_client_abort_during_slow_response() {
+        use pingora_core::protocols::http::ServerSession;
+        use pingora_core::protocols::l4::stream::Stream as L4Stream;
+        use tokio::io::AsyncWriteExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Клиент отправляет заголовки и закрывает соединение, не дождавшись ответа
+        let client = tokio::spawn(async move {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            stream.write_all(b"GET /export HTTP/1.1\r\nHost: api.example.com\r\n\r\n").await.unwrap();
+        });
+        let (tcp, _) = listener.accept().await.unwrap();
+        client.await.unwrap();
+
+        let mut session = ServerSession::new_http1(Box::new(L4Stream::from(tcp)));
+        assert!(session.read_request().await.unwrap());
+
+        // Ответ буферизуется (proxy_buffering) и идет к клиенту медленными частями
+        let budget = Arc::new(BufferBudget::new(1 << 20));
+        let mut ctx = RequestContext::new();
+        ctx.upstream_name = Some("exports".to_string());
+        ctx.response_buffer = Some(ResponseBuffer::new(budget.clone(), 1 << 20));
+        ctx.response_buffer.as_mut().unwrap().push(Some(Bytes::from(vec![b'x'; 4096])), false);
+        assert_eq!(budget.used(), 4096);
+
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        response.insert_header("Transfer-Encoding", "chunked").unwrap();
+        let mut result = session.write_response_header(Box::new(response)).await;
+        for _ in 0..100 {
+            if result.is_err() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            result = session.write_response_body(Bytes::from(vec![b'x'; 1024]), false).await;
+        }
+
+        // Pingora помечает ошибки соединения с клиентом как downstream
+        let e = result.expect_err("writing to a closed client did not fail").into_down();
+        assert!(is_client_abort(&e));
+        assert!(!is_client_abort(&Error::new(ErrorType::ConnectionClosed).into_up()));
+        assert!(!is_client_abort(&Error::new(ErrorType::ReadTimedout).into_down()));
+
+        let aborts = CLIENT_ABORTS.with_label_values(&[ctx.service_label()]).get();
+        record_client_abort(&mut ctx, session.body_bytes_sent());
+        assert!(ctx.client_aborted);
+        assert!(ctx.response_buffer.is_none());
+        assert_eq!(budget.used(), 0);
+        assert_eq!(CLIENT_ABORTS.with_label_values(&[ctx.service_label()]).get(), aborts + 1);
+    }
+
+    #[tokio::test]
+    async fn test_buffered_response_releases_upstream_before_client_reads() {
+        use crate::concurrency::UpstreamLimiter;
+
+        let drain_tracker = Arc::new(DrainTracker::new(Duration::from_secs(30)));
+        let proxy = ProxyBuilder::new(Config::default())
+            .upstream("reports", Arc::new(LoadBalancer::<RoundRobin>::try_from_iter(["10.0.0.7:8080"]).unwrap()))
+            .drain_tracker(drain_tracker.clone())
+            .build()
+            .unwrap()
+            .proxy;
+
+        // Upstream с max_conns 1 и без очереди
+        let limiter = UpstreamLimiter::new("reports", 1, 0, Duration::ZERO);
+        let mut session = crate::stages::test_session("GET /reports/daily HTTP/1.1\r\nHost: api.ad-quest.ru\r\n\r\n").await;
+        let mut ctx = RequestContext::new();
+        ctx.upstream_name = Some("reports".to_string());
+        ctx.upstream_permit = Some(limiter.acquire().await.unwrap());
+        drain_tracker.begin_request("reports", "10.0.0.7:8080");
+        ctx.selected_backend = Some("10.0.0.7:8080".to_string());
+        ctx.response_buffer = Some(ResponseBuffer::new(Arc::new(BufferBudget::new(1 << 20)), 64 * 1024));
+
+        let mut body = Some(Bytes::from_static(b"{\"rows\":"));
+        proxy.response_body_filter(&mut session, &mut body, false, &mut ctx).unwrap();
+        assert!(body.is_none());
+        assert_eq!(drain_tracker.in_flight("reports", "10.0.0.7:8080"), 1);
+        assert!(limiter.acquire().await.is_err());
+
+        // Тело небольшого ответа получено целиком: слоты upstream свободны до того,
+        // как медленный клиент начнет его читать
+        let mut body = Some(Bytes::from_static(b"[]}"));
+        proxy.response_body_filter(&mut session, &mut body, true, &mut ctx).unwrap();
+        assert_eq!(body.as_deref(), Some(&b"{\"rows\":[]}"[..]));
+        assert_eq!(drain_tracker.in_flight("reports", "10.0.0.7:8080"), 0);
+        assert!(ctx.selected_backend.is_none());
+        assert!(limiter.acquire().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_first_byte_timeout_applies_only_before_headers() {
+        // /slow-header: пауза до строки статуса; /stall: пауза посреди тела
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                std::thread::spawn(move || {
+                    let mut buf = [0u8; 1024];
+                    let n = stream.read(&mut buf).unwrap_or(0);
+                    let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                    if request.starts_with("GET /slow-header") {
+                        std::thread::sleep(Duration::from_secs(3));
+                    }
+                    let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello");
+                    let _ = stream.flush();
+                    std::thread::sleep(Duration::from_millis(600));
+                    let _ = stream.write_all(b"world");
+                });
+            }
+        });
+
+        let mut config = Config::default();
+        config.logging.access_log.enabled = false;
+        config.logging.error_log.enabled = false;
+        config.nginx_config = Some(
+            NginxConfig::parse_config_content(
+                "server { server_name 127.0.0.1;\n  location / { proxy_pass api; proxy_first_byte_timeout 300ms; proxy_read_timeout 5s; proxy_next_upstream_tries 1; } }",
+            )
+            .unwrap(),
+        );
+        let upstream = Arc::new(LoadBalancer::<RoundRobin>::try_from_iter([addr.to_string()]).unwrap());
+        let proxy = crate::testing::TestProxy::start(ProxyBuilder::new(config).upstream("api", upstream))
+            .await
+            .unwrap();
+        let client = reqwest::Client::new();
+
+        // Пауза посреди тела ограничена read таймаутом, а не first-byte
+        let response = client.get(proxy.url("/stall")).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "helloworld");
+
+        // Заголовки позже first-byte таймаута - 504 через first-byte, а не через read
+        let timeouts = UPSTREAM_TIMEOUTS.with_label_values(&["first_byte"]).get();
+        let started = std::time::Instant::now();
+        let response = client.get(proxy.url("/slow-header")).send().await.unwrap();
+        assert_eq!(response.status(), 504);
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(300), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1500), "{:?}", elapsed);
+        assert!(UPSTREAM_TIMEOUTS.with_label_values(&["first_byte"]).get() > timeouts);
+    }
+
+    #[tokio::test]
+    async fn test_slow_upstream_yields_504_after_deadline() {
+        // Upstream принимает запрос, но не отвечает
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf);
+                std::thread::sleep(Duration::from_secs(5));
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+            }
+        });
+
+        let mut config = Config::default();
+        config.global.request_timeout = Some(1);
+        let mut ctx = RequestContext::new();
+        ctx.upstream_timeouts = Some(config.resolve_upstream_timeouts(None));
+        let start = std::time::Instant::now();
+        ctx.upstream_deadline = ctx.upstream_timeouts.and_then(|timeouts| timeouts.deadline(start));
+        assert!(!ctx.upstream_deadline_exceeded());
+
+        let mut peer = HttpPeer::new(addr, false, "".to_string());
+        apply_upstream_timeouts(&mut peer, ctx.upstream_timeouts.as_ref().unwrap(), ctx.upstream_time_left(start));
+
+        let connector = pingora_core::connectors::http::Connector::new(None);
+        let (mut upstream, _) = connector.get_http_session(&peer).await.unwrap();
+        let request = RequestHeader::build("GET", b"/slow", None).unwrap();
+        upstream.write_request_header(Box::new(request)).await.unwrap();
+        let e = upstream.read_response_header().await.unwrap_err();
+
+        // Ответ прерван по истечении deadline, а не через 5 секунд
+        let elapsed = start.elapsed();
+        assert_eq!(e.etype(), &ErrorType::ReadTimedout);
+        assert!(elapsed >= Duration::from_secs(1) && elapsed < Duration::from_secs(3), "{:?}", elapsed);
+        assert!(ctx.upstream_deadline_exceeded());
+        // Ошибка фильтров после deadline обрабатывается fail_to_proxy как таймаут upstream
+        let deadline = upstream_deadline_error();
+        assert_eq!(deadline.etype(), &ErrorType::ReadTimedout);
+        assert!(matches!(deadline.esource(), ErrorSource::Upstream));
+    }
+}
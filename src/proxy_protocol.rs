@@ -0,0 +1,366 @@
+use async_trait::async_trait;
+use bytes::{Buf, BytesMut};
+use log::{debug, warn};
+use std::collections::HashSet;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+
+use pingora_core::apps::ServerApp;
+use pingora_core::protocols::digest::{SocketDigest, TimingDigest};
+use pingora_core::protocols::l4::socket::SocketAddr as PeerAddr;
+use pingora_core::protocols::raw_connect::ProxyDigest;
+use pingora_core::protocols::{
+    GetProxyDigest, GetSocketDigest, GetTimingDigest, Peek, Shutdown, Ssl, Stream, UniqueID,
+    UniqueIDType,
+};
+use pingora_core::server::ShutdownWatch;
+
+/// Сигнатура PROXY protocol v2
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+/// Максимальная длина строки PROXY protocol v1 (включая CRLF)
+const V1_MAX_LEN: usize = 107;
+/// Ограничение на размер заголовка, который читаем до HTTP запроса
+const MAX_HEADER_LEN: usize = 4096;
+/// Время ожидания PROXY заголовка после принятия соединения
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Разобранный PROXY protocol заголовок
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProxyHeader {
+    /// Исходный адрес клиента (None для LOCAL / UNKNOWN)
+    pub source: Option<SocketAddr>,
+    /// Длина заголовка в байтах
+    pub len: usize,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Разбирает PROXY protocol v1/v2 заголовок в начале буфера.
+/// Возвращает Ok(None), если данных пока недостаточно.
+pub fn parse_header(buf: &[u8]) -> io::Result<Option<ProxyHeader>> {
+    match buf.first() {
+        None => Ok(None),
+        Some(b'P') => parse_v1(buf),
+        Some(b'\r') => parse_v2(buf),
+        Some(_) => Err(invalid("missing PROXY protocol header")),
+    }
+}
+
+fn parse_v1(buf: &[u8]) -> io::Result<Option<ProxyHeader>> {
+    let prefix = b"PROXY ";
+    if buf.len() < prefix.len() {
+        return if prefix.starts_with(buf) { Ok(None) } else { Err(invalid("invalid PROXY v1 header")) };
+    }
+    if !buf.starts_with(prefix) {
+        return Err(invalid("invalid PROXY v1 header"));
+    }
+
+    let line_end = match buf.windows(2).position(|w| w == b"\r\n") {
+        Some(pos) if pos + 2 <= V1_MAX_LEN => pos,
+        Some(_) => return Err(invalid("PROXY v1 header too long")),
+        None if buf.len() < V1_MAX_LEN => return Ok(None),
+        None => return Err(invalid("PROXY v1 header too long")),
+    };
+    let line = std::str::from_utf8(&buf[..line_end]).map_err(|_| invalid("invalid PROXY v1 header"))?;
+    let parts: Vec<&str> = line.split(' ').collect();
+
+    let source = match parts.get(1).copied() {
+        Some("UNKNOWN") => None,
+        Some("TCP4") | Some("TCP6") if parts.len() == 6 => {
+            let ip: IpAddr = parts[2].parse().map_err(|_| invalid("invalid PROXY v1 source address"))?;
+            let port: u16 = parts[4].parse().map_err(|_| invalid("invalid PROXY v1 source port"))?;
+            Some(SocketAddr::new(ip, port))
+        }
+        _ => return Err(invalid("invalid PROXY v1 header")),
+    };
+
+    Ok(Some(ProxyHeader { source, len: line_end + 2 }))
+}
+
+fn parse_v2(buf: &[u8]) -> io::Result<Option<ProxyHeader>> {
+    if buf.len() < V2_SIGNATURE.len() {
+        return if V2_SIGNATURE.starts_with(buf) { Ok(None) } else { Err(invalid("invalid PROXY v2 signature")) };
+    }
+    if !buf.starts_with(V2_SIGNATURE) {
+        return Err(invalid("invalid PROXY v2 signature"));
+    }
+    if buf.len() < 16 {
+        return Ok(None);
+    }
+
+    let version = buf[12] >> 4;
+    let command = buf[12] & 0x0f;
+    if version != 2 || command > 1 {
+        return Err(invalid("unsupported PROXY v2 version or command"));
+    }
+    let len = 16 + u16::from_be_bytes([buf[14], buf[15]]) as usize;
+    if buf.len() < len {
+        return Ok(None);
+    }
+
+    // LOCAL команда (health check балансировщика) - адрес соединения не меняется
+    if command == 0 {
+        return Ok(Some(ProxyHeader { source: None, len }));
+    }
+
+    let addr = &buf[16..len];
+    let source = match buf[13] >> 4 {
+        // AF_INET: src(4) dst(4) src_port(2) dst_port(2)
+        1 if addr.len() >= 12 => {
+            let ip = Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3]);
+            Some(SocketAddr::new(ip.into(), u16::from_be_bytes([addr[8], addr[9]])))
+        }
+        // AF_INET6: src(16) dst(16) src_port(2) dst_port(2)
+        2 if addr.len() >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&addr[..16]);
+            Some(SocketAddr::new(Ipv6Addr::from(octets).into(), u16::from_be_bytes([addr[32], addr[33]])))
+        }
+        1 | 2 => return Err(invalid("truncated PROXY v2 address block")),
+        _ => None,
+    };
+
+    Ok(Some(ProxyHeader { source, len }))
+}
+
+/// Читает PROXY заголовок из соединения и возвращает поток, в котором адрес клиента
+/// заменен исходным адресом из заголовка
+pub async fn accept_proxy_protocol(mut stream: Stream) -> io::Result<Stream> {
+    let mut buf = BytesMut::with_capacity(256);
+    let header = loop {
+        if let Some(header) = parse_header(&buf)? {
+            break header;
+        }
+        if buf.len() >= MAX_HEADER_LEN {
+            return Err(invalid("PROXY protocol header too long"));
+        }
+        if stream.read_buf(&mut buf).await? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed before PROXY header"));
+        }
+    };
+
+    buf.advance(header.len);
+    debug!("PROXY protocol header accepted, source: {:?}", header.source);
+    Ok(Box::new(ProxyProtocolStream::new(stream, header.source, buf)))
+}
+
+/// Поток после PROXY заголовка: отдает уже прочитанные байты запроса
+/// и подменяет адрес клиента в socket digest
+#[derive(Debug)]
+struct ProxyProtocolStream {
+    inner: Stream,
+    buffered: BytesMut,
+    socket_digest: Option<Arc<SocketDigest>>,
+}
+
+impl ProxyProtocolStream {
+    fn new(inner: Stream, source: Option<SocketAddr>, buffered: BytesMut) -> Self {
+        let socket_digest = match source {
+            Some(source) => {
+                let digest = SocketDigest::from_raw_fd(inner.id());
+                let _ = digest.peer_addr.set(Some(PeerAddr::Inet(source)));
+                if let Some(local_addr) = inner.get_socket_digest().and_then(|d| d.local_addr().cloned()) {
+                    let _ = digest.local_addr.set(Some(local_addr));
+                }
+                Some(Arc::new(digest))
+            }
+            None => inner.get_socket_digest(),
+        };
+
+        Self { inner, buffered, socket_digest }
+    }
+}
+
+impl AsyncRead for ProxyProtocolStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if !self.buffered.is_empty() {
+            let len = self.buffered.len().min(buf.remaining());
+            buf.put_slice(&self.buffered[..len]);
+            self.buffered.advance(len);
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ProxyProtocolStream {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[async_trait]
+impl Shutdown for ProxyProtocolStream {
+    async fn shutdown(&mut self) {
+        self.inner.shutdown().await
+    }
+}
+
+impl UniqueID for ProxyProtocolStream {
+    fn id(&self) -> UniqueIDType {
+        self.inner.id()
+    }
+}
+
+impl Ssl for ProxyProtocolStream {}
+
+impl GetTimingDigest for ProxyProtocolStream {
+    fn get_timing_digest(&self) -> Vec<Option<TimingDigest>> {
+        self.inner.get_timing_digest()
+    }
+}
+
+impl GetProxyDigest for ProxyProtocolStream {
+    fn get_proxy_digest(&self) -> Option<Arc<ProxyDigest>> {
+        self.inner.get_proxy_digest()
+    }
+}
+
+impl GetSocketDigest for ProxyProtocolStream {
+    fn get_socket_digest(&self) -> Option<Arc<SocketDigest>> {
+        self.socket_digest.clone()
+    }
+
+    fn set_socket_digest(&mut self, socket_digest: SocketDigest) {
+        self.socket_digest = Some(Arc::new(socket_digest));
+    }
+}
+
+impl Peek for ProxyProtocolStream {}
+
+/// Обертка над приложением Pingora, принимающая PROXY protocol на указанных портах
+pub struct ProxyProtocolApp<A> {
+    inner: Arc<A>,
+    ports: HashSet<u16>,
+}
+
+impl<A> ProxyProtocolApp<A> {
+    pub fn new(inner: A, ports: HashSet<u16>) -> Self {
+        Self { inner: Arc::new(inner), ports }
+    }
+
+    fn enabled_for(&self, stream: &Stream) -> bool {
+        // Повторно используемое keep-alive соединение: заголовок уже прочитан
+        if stream.as_any().is::<ProxyProtocolStream>() {
+            return false;
+        }
+
+        !self.ports.is_empty()
+            && stream
+                .get_socket_digest()
+                .and_then(|d| d.local_addr().and_then(|addr| addr.as_inet()).map(|addr| addr.port()))
+                .is_some_and(|port| self.ports.contains(&port))
+    }
+}
+
+#[async_trait]
+impl<A> ServerApp for ProxyProtocolApp<A>
+where
+    A: ServerApp + Send + Sync + 'static,
+{
+    async fn process_new(self: &Arc<Self>, stream: Stream, shutdown: &ShutdownWatch) -> Option<Stream> {
+        let stream = if self.enabled_for(&stream) {
+            match tokio::time::timeout(HEADER_TIMEOUT, accept_proxy_protocol(stream)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    warn!("Rejecting connection without valid PROXY protocol header: {}", e);
+                    return None;
+                }
+                Err(_) => {
+                    warn!("Timed out waiting for PROXY protocol header");
+                    return None;
+                }
+            }
+        } else {
+            stream
+        };
+
+        self.inner.process_new(stream, shutdown).await
+    }
+
+    async fn cleanup(&self) {
+        self.inner.cleanup().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingora_core::protocols::l4::stream::Stream as L4Stream;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn test_parse_v1() {
+        let header = parse_header(b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 80\r\nGET / HTTP/1.1\r\n").unwrap().unwrap();
+        assert_eq!(header.source, Some("203.0.113.7:51234".parse().unwrap()));
+        assert_eq!(header.len, 42);
+
+        let header = parse_header(b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 443\r\n").unwrap().unwrap();
+        assert_eq!(header.source, Some("[2001:db8::1]:4000".parse().unwrap()));
+
+        let header = parse_header(b"PROXY UNKNOWN\r\n").unwrap().unwrap();
+        assert_eq!(header.source, None);
+
+        assert_eq!(parse_header(b"PROXY TCP4 203.0.113.7").unwrap(), None);
+        assert!(parse_header(b"GET / HTTP/1.1\r\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_v2() {
+        let mut buf = V2_SIGNATURE.to_vec();
+        buf.extend_from_slice(&[0x21, 0x11, 0x00, 0x0c]);
+        buf.extend_from_slice(&[198, 51, 100, 9, 10, 0, 0, 1]);
+        buf.extend_from_slice(&40000u16.to_be_bytes());
+        buf.extend_from_slice(&443u16.to_be_bytes());
+
+        assert_eq!(parse_header(&buf[..20]).unwrap(), None);
+        let header = parse_header(&buf).unwrap().unwrap();
+        assert_eq!(header.source, Some("198.51.100.9:40000".parse().unwrap()));
+        assert_eq!(header.len, 28);
+
+        // LOCAL команда не меняет адрес клиента
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+        assert_eq!(parse_header(&local).unwrap().unwrap(), ProxyHeader { source: None, len: 16 });
+    }
+
+    #[tokio::test]
+    async fn test_proxy_header_sets_client_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 80\r\nGET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .await
+            .unwrap();
+
+        let (tcp, _) = listener.accept().await.unwrap();
+        let mut stream = accept_proxy_protocol(Box::new(L4Stream::from(tcp))).await.unwrap();
+
+        let digest = stream.get_socket_digest().unwrap();
+        let client_addr = digest.peer_addr().and_then(|a| a.as_inet()).copied();
+        assert_eq!(client_addr, Some("203.0.113.7:51234".parse().unwrap()));
+
+        // Байты HTTP запроса после заголовка не теряются
+        let mut request = vec![0u8; 16];
+        stream.read_exact(&mut request).await.unwrap();
+        assert_eq!(&request, b"GET / HTTP/1.1\r\n");
+    }
+}
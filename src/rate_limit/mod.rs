@@ -1,10 +1,15 @@
+pub mod zones;
+
 use once_cell::sync::Lazy;
 use pingora_limits::rate::Rate;
 use pingora::prelude::*;
-use pingora::http::ResponseHeader;
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::time::Duration;
 use log::info;
+use crate::error_response::{ErrorCode, ErrorResponse};
+use crate::metrics::record_rate_limit_decision;
+use crate::types::RequestContext;
 
 /// Глобальный rate limiter
 static RATE_LIMITER: Lazy<Rate> = Lazy::new(|| Rate::new(Duration::from_secs(1)));
@@ -60,7 +65,7 @@ impl RateLimitConfig {
 
 /// Получает идентификатор клиента для rate limiting
 /// Приоритет: API ключ > IP адрес
-fn get_client_identifier(session: &Session) -> String {
+fn get_client_identifier(session: &Session, client_ip: Option<IpAddr>) -> String {
     // Сначала проверяем API ключ
     if let Some(api_key) = session
         .req_header()
@@ -71,40 +76,53 @@ fn get_client_identifier(session: &Session) -> String {
         return format!("api_key:{}", api_key);
     }
 
-    // Иначе используем IP адрес (извлекаем IP из SocketAddr строки)
-    session
-        .client_addr()
-        .map(|addr| {
-            // SocketAddr.to_string() возвращает "IP:PORT", берем только IP часть
-            let addr_str = addr.to_string();
-            addr_str.split(':').next().unwrap_or("unknown").to_string()
-        })
+    // Иначе используем IP адрес (IPv4 или IPv6, без порта)
+    client_ip
+        .map(|ip| ip.to_string())
         .unwrap_or_else(|| "unknown".to_string())
 }
 
-/// Проверяет rate limit для запроса
-/// Возвращает Ok(true) если запрос был заблокирован (429), Ok(false) если можно продолжить
-pub async fn check_rate_limit(
-    session: &mut Session,
-    config: &RateLimitConfig,
-) -> Result<bool> {
-    // Если rate limiting отключен, пропускаем
-    if !config.enabled {
-        return Ok(false);
+/// Решение rate limiter для запроса. Задержки в пределах burst нет
+/// (`limit_req` без `nodelay` с burst отклоняет `-t`), поэтому `delayed` не бывает
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitDecision {
+    Allowed,
+    Limited,
+}
+
+impl RateLimitDecision {
+    /// Значение метки decision в метриках
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RateLimitDecision::Allowed => "allowed",
+            RateLimitDecision::Limited => "limited",
+        }
     }
+}
 
-    // Получаем идентификатор клиента
-    let client_id = get_client_identifier(session);
+/// Тип ключа rate limiting для метрик: api_key или ip
+fn key_type(client_id: &str) -> &'static str {
+    if client_id.starts_with("api_key:") {
+        "api_key"
+    } else {
+        "ip"
+    }
+}
+
+/// Принимает решение для клиента и учитывает его в метриках.
+/// Возвращает решение и примененный лимит.
+fn decide(config: &RateLimitConfig, client_id: &str, location: &str) -> (RateLimitDecision, isize) {
+    let key_type = key_type(client_id);
 
     // Проверяем whitelist
-    if config.whitelist.contains(&client_id) {
-        return Ok(false); // Пропускаем без ограничений
+    if config.whitelist.iter().any(|ip| ip == client_id) {
+        record_rate_limit_decision(location, key_type, RateLimitDecision::Allowed, None);
+        return (RateLimitDecision::Allowed, config.max_requests_per_second);
     }
 
     // Определяем лимит для клиента
-    let limit = if client_id.starts_with("api_key:") {
+    let limit = if let Some(api_key) = client_id.strip_prefix("api_key:") {
         // Для API ключей используем специальный лимит или дефолтный
-        let api_key = client_id.strip_prefix("api_key:").unwrap_or("");
         config
             .per_api_key_limits
             .get(api_key)
@@ -117,31 +135,46 @@ pub async fn check_rate_limit(
 
     // Проверяем текущее количество запросов
     let current_requests = RATE_LIMITER.observe(&client_id, 1);
-
-    if current_requests > limit {
+    let decision = if current_requests > limit {
         info!(
             "Rate limit exceeded for {}: {} req/s (limit: {})",
             client_id, current_requests, limit
         );
+        RateLimitDecision::Limited
+    } else {
+        RateLimitDecision::Allowed
+    };
 
+    record_rate_limit_decision(location, key_type, decision, Some(current_requests));
+    (decision, limit)
+}
+
+/// Проверяет rate limit для запроса.
+/// `location` - метка маршрута (путь location из конфигурации) для метрик.
+/// Возвращает Ok(true) если запрос был заблокирован (429), Ok(false) если можно продолжить
+pub async fn check_rate_limit(
+    session: &mut Session,
+    config: &RateLimitConfig,
+    location: &str,
+    ctx: &RequestContext,
+) -> Result<bool> {
+    // Если rate limiting отключен, пропускаем
+    if !config.enabled {
+        return Ok(false);
+    }
+
+    // Получаем идентификатор клиента
+    let client_id = get_client_identifier(session, ctx.client_ip);
+
+    let (decision, limit) = decide(config, &client_id, location);
+    if decision == RateLimitDecision::Limited {
         // Возвращаем 429 Too Many Requests
-        let mut response = ResponseHeader::build(429, None)?;
-        response.insert_header("X-Rate-Limit-Limit", limit.to_string())?;
-        response.insert_header("X-Rate-Limit-Remaining", "0")?;
-        response.insert_header("X-Rate-Limit-Reset", "1")?;
-        response.insert_header("Retry-After", "1")?;
-        response.insert_header("Content-Type", "application/json")?;
-
-        // Добавляем CORS заголовки для JSON ответа
-        response.insert_header("Access-Control-Allow-Origin", "*")?;
-
-        let error_body = r#"{"error":"Too Many Requests","message":"Rate limit exceeded"}"#;
-        response.insert_header("Content-Length", error_body.len().to_string())?;
-
-        session.set_keepalive(None);
-        session.write_response_header(Box::new(response), false).await?;
-        session
-            .write_response_body(Some(bytes::Bytes::from(error_body)), true)
+        ErrorResponse::new(ErrorCode::RateLimited)
+            .retry_after(1)
+            .header("X-Rate-Limit-Limit", limit.to_string())
+            .header("X-Rate-Limit-Remaining", "0")
+            .header("X-Rate-Limit-Reset", "1")
+            .send(session, ctx)
             .await?;
 
         return Ok(true); // Запрос обработан (заблокирован)
@@ -174,6 +207,29 @@ mod tests {
         assert!(config.whitelist.contains(&"127.0.0.1".to_string()));
     }
 
+    #[test]
+    fn test_rate_limit_decision_metrics() {
+        use crate::metrics::RATE_LIMIT_DECISIONS;
+
+        let config = RateLimitConfig::with_limit(1);
+        let location = "/test/decision-metrics";
+        let allowed = RATE_LIMIT_DECISIONS.with_label_values(&[location, "ip", "allowed"]);
+        let limited = RATE_LIMIT_DECISIONS.with_label_values(&[location, "ip", "limited"]);
+        let allowed_before = allowed.get();
+        let limited_before = limited.get();
+
+        assert_eq!(decide(&config, "192.0.2.10", location).0, RateLimitDecision::Allowed);
+        assert_eq!(decide(&config, "192.0.2.10", location).0, RateLimitDecision::Limited);
+
+        assert_eq!(allowed.get(), allowed_before + 1);
+        assert_eq!(limited.get(), limited_before + 1);
+
+        let api_key_allowed = RATE_LIMIT_DECISIONS.with_label_values(&[location, "api_key", "allowed"]);
+        let before = api_key_allowed.get();
+        assert_eq!(decide(&config, "api_key:decision-metrics", location).0, RateLimitDecision::Allowed);
+        assert_eq!(api_key_allowed.get(), before + 1);
+    }
+
     #[test]
     fn test_rate_limit_config_api_key() {
         let mut config = RateLimitConfig::new();
@@ -0,0 +1,187 @@
+use once_cell::sync::Lazy;
+use pingora::prelude::*;
+use pingora_limits::rate::Rate;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::RateLimitDecision;
+use crate::config::{LimitKey, LimitReqZone};
+use crate::metrics::record_rate_limit_decision;
+use crate::routing::request_host;
+
+/// Счетчики зон с rate в r/s и r/m; ключ счетчика включает имя зоны
+static PER_SECOND: Lazy<Rate> = Lazy::new(|| Rate::new(Duration::from_secs(1)));
+static PER_MINUTE: Lazy<Rate> = Lazy::new(|| Rate::new(Duration::from_secs(60)));
+
+/// Значение ключа зоны для запроса; пустой ключ не учитывается, как в nginx
+pub fn zone_key(session: &Session, client_ip: Option<IpAddr>, key: &LimitKey) -> Option<String> {
+    let value = match key {
+        LimitKey::RemoteAddr => client_ip.map(|ip| ip.to_string()),
+        LimitKey::Header(name) => session
+            .req_header()
+            .headers
+            .get(name.as_str())
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string),
+        LimitKey::Host => Some(request_host(session).to_ascii_lowercase()),
+    };
+    value.filter(|value| !value.is_empty())
+}
+
+/// Решение limit_req для ключа зоны: в окне зоны принимается rate + burst запросов
+pub fn check_limit_req(zone: &LimitReqZone, burst: u32, key: &str, location: &str) -> RateLimitDecision {
+    let limiter = if zone.period >= Duration::from_secs(60) { &PER_MINUTE } else { &PER_SECOND };
+    let current = limiter.observe(&format!("{}:{}", zone.name, key), 1);
+    let decision = if current > (zone.rate + burst) as isize {
+        log::info!("limit_req zone '{}' exceeded for {}: {} (rate: {}, burst: {})",
+                   zone.name, key, current, zone.rate, burst);
+        RateLimitDecision::Limited
+    } else {
+        RateLimitDecision::Allowed
+    };
+    // Гистограмма наблюдаемой частоты - в запросах в секунду
+    let observed = (zone.period < Duration::from_secs(60)).then_some(current);
+    record_rate_limit_decision(location, zone.key.as_str(), decision, observed);
+    decision
+}
+
+type ConnCounts = Arc<Mutex<HashMap<(String, String), usize>>>;
+
+/// Одновременные запросы по ключам зон limit_conn_zone
+#[derive(Debug, Default)]
+pub struct ConnLimits {
+    counts: ConnCounts,
+}
+
+/// Занятый запросом слот зоны; освобождается при drop (конец запроса)
+#[derive(Debug)]
+pub struct ConnPermit {
+    counts: ConnCounts,
+    key: (String, String),
+}
+
+impl Drop for ConnPermit {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap();
+        if let Some(count) = counts.get_mut(&self.key) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.key);
+            }
+        }
+    }
+}
+
+impl ConnLimits {
+    /// Занимает слот зоны для ключа; None - у ключа уже max запросов в обработке
+    pub fn acquire(&self, zone: &str, key: &str, max: usize) -> Option<ConnPermit> {
+        let key = (zone.to_string(), key.to_string());
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(key.clone()).or_insert(0);
+        if *count >= max {
+            return None;
+        }
+        *count += 1;
+        Some(ConnPermit { counts: self.counts.clone(), key })
+    }
+
+    /// Запросов в обработке для ключа зоны
+    pub fn active(&self, zone: &str, key: &str) -> usize {
+        let counts = self.counts.lock().unwrap();
+        counts.get(&(zone.to_string(), key.to_string())).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NginxConfig;
+
+    #[test]
+    fn test_limit_req_zone_enforced() {
+        let config = NginxConfig::parse_config_content(r#"
+            limit_req_zone $binary_remote_addr zone=login:10m rate=2r/s;
+            limit_req_zone $http_x_api_key zone=partners:1m rate=60r/m;
+
+            server {
+                listen 80;
+                server_name api.example.com;
+
+                location /auth/login {
+                    proxy_pass core_api;
+                    limit_req zone=login burst=1 nodelay;
+                }
+
+                location /partners {
+                    proxy_pass core_api;
+                    limit_req zone=partners;
+                }
+            }
+        "#).unwrap();
+
+        let login = &config.limit_req_zones["login"];
+        assert_eq!(login.key, LimitKey::RemoteAddr);
+        assert_eq!((login.rate, login.period), (2, Duration::from_secs(1)));
+        let partners = &config.limit_req_zones["partners"];
+        assert_eq!(partners.key, LimitKey::Header("x-api-key".to_string()));
+        assert_eq!((partners.rate, partners.period), (60, Duration::from_secs(60)));
+
+        let location = &config.servers[0].locations[0];
+        assert_eq!(location.limit_req.len(), 1);
+        assert_eq!(location.limit_req[0].zone, "login");
+        assert_eq!(location.limit_req[0].burst, 1);
+
+        // rate 2 + burst 1 в одном окне, ключи зоны считаются раздельно
+        let limit = &location.limit_req[0];
+        let decisions: Vec<RateLimitDecision> = (0..4)
+            .map(|_| check_limit_req(login, limit.burst, "198.51.100.7", &location.path))
+            .collect();
+        assert_eq!(
+            decisions,
+            [RateLimitDecision::Allowed, RateLimitDecision::Allowed, RateLimitDecision::Allowed, RateLimitDecision::Limited]
+        );
+        assert_eq!(check_limit_req(login, limit.burst, "198.51.100.8", &location.path), RateLimitDecision::Allowed);
+        // Счетчики зоны общие для всех location, которые на нее ссылаются
+        assert_eq!(check_limit_req(login, limit.burst, "198.51.100.7", "/auth/reset"), RateLimitDecision::Limited);
+        // Тот же ключ в другой зоне - отдельный счетчик
+        assert_eq!(check_limit_req(partners, 0, "198.51.100.7", "/partners"), RateLimitDecision::Allowed);
+    }
+
+    #[test]
+    fn test_limit_conn_zone_enforced() {
+        let config = NginxConfig::parse_config_content(r#"
+            limit_conn_zone $binary_remote_addr zone=addr:10m;
+
+            server {
+                listen 80;
+                location /downloads {
+                    root /var/www;
+                    limit_conn addr 2;
+                }
+            }
+        "#).unwrap();
+        assert_eq!(config.limit_conn_zones["addr"].key, LimitKey::RemoteAddr);
+        let limit = &config.servers[0].locations[0].limit_conn[0];
+        assert_eq!((limit.zone.as_str(), limit.max), ("addr", 2));
+
+        let limits = ConnLimits::default();
+        let first = limits.acquire("addr", "203.0.113.5", limit.max).unwrap();
+        let _second = limits.acquire("addr", "203.0.113.5", limit.max).unwrap();
+        assert!(limits.acquire("addr", "203.0.113.5", limit.max).is_none());
+        assert!(limits.acquire("addr", "203.0.113.6", limit.max).is_some());
+
+        // Завершенный запрос освобождает слот
+        drop(first);
+        assert_eq!(limits.active("addr", "203.0.113.5"), 1);
+        assert!(limits.acquire("addr", "203.0.113.5", limit.max).is_some());
+    }
+
+    #[test]
+    fn test_invalid_limit_directives() {
+        assert!(NginxConfig::parse_config_content("limit_req_zone $binary_remote_addr zone=api:10m rate=10r/h;").is_err());
+        assert!(NginxConfig::parse_config_content("limit_req_zone $cookie_session zone=api:10m rate=10r/s;").is_err());
+        assert!(NginxConfig::parse_config_content("limit_conn_zone $binary_remote_addr;").is_err());
+    }
+}
@@ -0,0 +1,210 @@
+use log::{info, warn};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+
+mod proxy;
+
+pub use proxy::{ReloadableCtx, ReloadableProxy};
+
+use crate::config::Config;
+use crate::config_check::{self, Report};
+use crate::error;
+use crate::metrics::CONFIG_RELOADS;
+
+type Loader = Box<dyn Fn(&str, &[PathBuf]) -> error::Result<Config> + Send + Sync>;
+type ApplyHook = Box<dyn Fn(&Config, &Arc<Config>, &mut Report) -> Result<(), String> + Send + Sync>;
+
+/// Результат reload: отчет проверок `-t` и признак применения новой конфигурации
+pub struct ReloadOutcome {
+    pub applied: bool,
+    pub report: Report,
+}
+
+impl ReloadOutcome {
+    /// Отчет `-t` в JSON с полем `applied`
+    pub fn to_json(&self) -> String {
+        let mut json = self.report.to_json_value();
+        json["applied"] = serde_json::Value::Bool(self.applied);
+        json.to_string()
+    }
+}
+
+/// Перечитывает конфигурацию и проверяет ее как `adq-pingora -t`. Без ошибок - вызывает
+/// обработчики применения и заменяет текущую, с ошибками - ничего не меняет
+pub struct ConfigReloader {
+    config_path: String,
+    config_dirs: Vec<PathBuf>,
+    current: RwLock<Arc<Config>>,
+    loader: Loader,
+    hooks: Vec<ApplyHook>,
+    // Одновременно выполняется один reload
+    reloading: Mutex<()>,
+}
+
+impl ConfigReloader {
+    pub fn new(config_path: &str, config_dirs: &[PathBuf], current: Arc<Config>) -> Self {
+        Self {
+            config_path: config_path.to_string(),
+            config_dirs: config_dirs.to_vec(),
+            current: RwLock::new(current),
+            loader: Box::new(|path, dirs| Config::load_with_fragments(path, dirs)),
+            hooks: Vec::new(),
+            reloading: Mutex::new(()),
+        }
+    }
+
+    /// Обработчик применения: получает текущую и новую конфигурацию до замены и может
+    /// дописать предупреждения в отчет. Ошибка обработчика отменяет reload - конфигурация
+    /// остается прежней, поэтому обработчик должен менять состояние только после всех проверок
+    pub fn on_apply(
+        mut self,
+        hook: impl Fn(&Config, &Arc<Config>, &mut Report) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Источник конфигурации вместо файлов (тесты)
+    #[cfg(test)]
+    pub(crate) fn with_loader(
+        mut self,
+        loader: impl Fn(&str, &[PathBuf]) -> error::Result<Config> + Send + Sync + 'static,
+    ) -> Self {
+        self.loader = Box::new(loader);
+        self
+    }
+
+    pub fn reload(&self) -> ReloadOutcome {
+        let _guard = self.reloading.lock().unwrap_or_else(|e| e.into_inner());
+        let loaded = (self.loader)(&self.config_path, &self.config_dirs);
+        let mut report = config_check::check_loaded(&self.config_path, &loaded);
+        let config = match loaded {
+            Ok(config) if report.errors() == 0 => Arc::new(config),
+            _ => {
+                CONFIG_RELOADS.with_label_values(&["rejected"]).inc();
+                warn!("Configuration reload rejected: {} error(s) in {}", report.errors(), self.config_path);
+                return ReloadOutcome { applied: false, report };
+            }
+        };
+
+        let old = self.current.read().unwrap_or_else(|e| e.into_inner()).clone();
+        for hook in &self.hooks {
+            if let Err(e) = hook(&old, &config, &mut report) {
+                report.error(format!("configuration was not applied: {}", e));
+                CONFIG_RELOADS.with_label_values(&["rejected"]).inc();
+                warn!("Configuration reload of {} failed: {}", self.config_path, e);
+                return ReloadOutcome { applied: false, report };
+            }
+        }
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = config;
+        CONFIG_RELOADS.with_label_values(&["applied"]).inc();
+        info!("Configuration {} reloaded ({} warning(s))", self.config_path, report.warnings());
+        ReloadOutcome { applied: true, report }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NginxConfig;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Reloader, который "читает" nginx конфигурацию из общей строки
+    fn reloader(sites: Arc<Mutex<String>>, applied: Arc<AtomicUsize>) -> ConfigReloader {
+        let mut initial = Config::default();
+        initial.nginx_config = Some(NginxConfig::parse_config_content(&sites.lock().unwrap()).unwrap());
+        ConfigReloader::new("proxy.yaml", &[], Arc::new(initial))
+            .with_loader(move |_, _| {
+                let mut config = Config::default();
+                config.nginx_config = Some(NginxConfig::parse_config_content(&sites.lock().unwrap())?);
+                Ok(config)
+            })
+            .on_apply(move |_, _, _| {
+                applied.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+    }
+
+    fn current(reloader: &ConfigReloader) -> Arc<Config> {
+        reloader.current.read().unwrap().clone()
+    }
+
+    #[test]
+    fn test_valid_reload_swaps_config() {
+        let sites = Arc::new(Mutex::new(
+            "upstream api { server 127.0.0.1:8080; } server { listen 80; server_name old.example.com; location / { proxy_pass api; } }"
+                .to_string(),
+        ));
+        let applied = Arc::new(AtomicUsize::new(0));
+        let reloader = reloader(sites.clone(), applied.clone());
+        assert!(current(&reloader).find_server("new.example.com").is_none());
+
+        *sites.lock().unwrap() =
+            "upstream api { server 127.0.0.1:8080; } server { listen 80; server_name new.example.com; location / { proxy_pass api; } }"
+                .to_string();
+        let outcome = reloader.reload();
+        assert!(outcome.applied);
+        assert_eq!(applied.load(Ordering::SeqCst), 1);
+        let config = current(&reloader);
+        let server = config.find_server("new.example.com").unwrap();
+        assert_eq!(server.server_names, vec!["new.example.com"]);
+
+        let json: serde_json::Value = serde_json::from_str(&outcome.to_json()).unwrap();
+        assert_eq!(json["applied"], true);
+        assert_eq!(json["ok"], true);
+        assert_eq!(json["errors"], 0);
+    }
+
+    #[test]
+    fn test_invalid_reload_rejected_with_details() {
+        let sites = Arc::new(Mutex::new(
+            "upstream api { server 127.0.0.1:8080; } server { listen 80; server_name api.example.com; location / { proxy_pass api; } }"
+                .to_string(),
+        ));
+        let applied = Arc::new(AtomicUsize::new(0));
+        let reloader = reloader(sites.clone(), applied.clone());
+        let before = current(&reloader);
+
+        // proxy_pass на неизвестный upstream - ошибка проверки
+        *sites.lock().unwrap() =
+            "server { listen 80; server_name api.example.com; location /api/ { proxy_pass missing_api; } }".to_string();
+        let outcome = reloader.reload();
+        assert!(!outcome.applied);
+        assert_eq!(applied.load(Ordering::SeqCst), 0);
+        assert!(Arc::ptr_eq(&before, &current(&reloader)));
+
+        let json: serde_json::Value = serde_json::from_str(&outcome.to_json()).unwrap();
+        assert_eq!(json["applied"], false);
+        assert_eq!(json["ok"], false);
+        let diagnostics = json["diagnostics"].as_array().unwrap();
+        assert!(diagnostics.iter().any(|d| d["severity"] == "error"
+            && d["message"].as_str().unwrap().contains("missing_api")), "{}", json);
+
+        // Пропущенный при разборе server блок тоже не применяется
+        *sites.lock().unwrap() =
+            "server { listen 80; server_name api.example.com; location / { proxy_buffering maybe; } }".to_string();
+        assert!(!reloader.reload().applied);
+        assert!(Arc::ptr_eq(&before, &current(&reloader)));
+    }
+
+    #[test]
+    fn test_failed_apply_keeps_config() {
+        let sites = Arc::new(Mutex::new(
+            "upstream api { server 127.0.0.1:8080; } server { listen 80; server_name api.example.com; location / { proxy_pass api; } }"
+                .to_string(),
+        ));
+        let applied = Arc::new(AtomicUsize::new(0));
+        let reloader = reloader(sites.clone(), applied.clone())
+            .on_apply(|_, _, _| Err("upstream 'api': failed to update backends".to_string()));
+        let before = current(&reloader);
+
+        let outcome = reloader.reload();
+        assert!(!outcome.applied);
+        assert!(Arc::ptr_eq(&before, &current(&reloader)));
+        let json: serde_json::Value = serde_json::from_str(&outcome.to_json()).unwrap();
+        assert_eq!(json["errors"], 1);
+        let diagnostics = json["diagnostics"].as_array().unwrap();
+        assert!(diagnostics.iter().any(|d| d["severity"] == "error"
+            && d["message"] == "configuration was not applied: upstream 'api': failed to update backends"), "{}", json);
+    }
+}
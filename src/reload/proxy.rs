@@ -0,0 +1,224 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use pingora::http::ResponseHeader;
+use pingora::prelude::*;
+use pingora_cache::{CacheKey, RespCacheable};
+use pingora_core::modules::http::HttpModules;
+use pingora_core::protocols::Digest;
+use pingora_proxy::{FailToProxy, RangeType};
+
+use crate::proxy::AdQuestProxy;
+use crate::types::RequestContext;
+
+/// Прокси, сборку которого заменяет reload конфигурации. Запрос целиком обслуживается
+/// сборкой, текущей на момент его начала; новые запросы сразу идут в новую
+#[derive(Clone)]
+pub struct ReloadableProxy {
+    current: Arc<RwLock<Arc<AdQuestProxy>>>,
+}
+
+/// Контекст запроса вместе со сборкой, которая его обслуживает
+pub struct ReloadableCtx {
+    proxy: Arc<AdQuestProxy>,
+    ctx: RequestContext,
+}
+
+impl ReloadableProxy {
+    pub fn new(proxy: AdQuestProxy) -> Self {
+        Self { current: Arc::new(RwLock::new(Arc::new(proxy))) }
+    }
+
+    /// Текущая сборка
+    pub fn current(&self) -> Arc<AdQuestProxy> {
+        self.current.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Заменяет сборку для новых запросов и возвращает прежнюю
+    pub fn replace(&self, proxy: AdQuestProxy) -> Arc<AdQuestProxy> {
+        std::mem::replace(&mut *self.current.write().unwrap_or_else(|e| e.into_inner()), Arc::new(proxy))
+    }
+}
+
+#[async_trait]
+impl ProxyHttp for ReloadableProxy {
+    type CTX = ReloadableCtx;
+
+    fn new_ctx(&self) -> Self::CTX {
+        let proxy = self.current();
+        let ctx = proxy.new_ctx();
+        ReloadableCtx { proxy, ctx }
+    }
+
+    fn init_downstream_modules(&self, modules: &mut HttpModules) {
+        // Модули сессий создаются один раз при старте сервиса
+        self.current().init_downstream_modules(modules)
+    }
+
+    async fn early_request_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<()> {
+        ctx.proxy.early_request_filter(session, &mut ctx.ctx).await
+    }
+
+    async fn request_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<bool> {
+        ctx.proxy.request_filter(session, &mut ctx.ctx).await
+    }
+
+    fn request_cache_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<()> {
+        ctx.proxy.request_cache_filter(session, &mut ctx.ctx)
+    }
+
+    fn cache_key_callback(&self, session: &Session, ctx: &mut Self::CTX) -> Result<CacheKey> {
+        ctx.proxy.cache_key_callback(session, &mut ctx.ctx)
+    }
+
+    fn response_cache_filter(
+        &self,
+        session: &Session,
+        resp: &ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<RespCacheable> {
+        ctx.proxy.response_cache_filter(session, resp, &mut ctx.ctx)
+    }
+
+    fn cache_not_modified_filter(
+        &self,
+        session: &Session,
+        resp: &ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<bool> {
+        ctx.proxy.cache_not_modified_filter(session, resp, &mut ctx.ctx)
+    }
+
+    fn range_header_filter(
+        &self,
+        session: &mut Session,
+        resp: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> RangeType {
+        ctx.proxy.range_header_filter(session, resp, &mut ctx.ctx)
+    }
+
+    fn fail_to_connect(
+        &self,
+        session: &mut Session,
+        peer: &HttpPeer,
+        ctx: &mut Self::CTX,
+        e: Box<Error>,
+    ) -> Box<Error> {
+        ctx.proxy.fail_to_connect(session, peer, &mut ctx.ctx, e)
+    }
+
+    fn error_while_proxy(
+        &self,
+        peer: &HttpPeer,
+        session: &mut Session,
+        e: Box<Error>,
+        ctx: &mut Self::CTX,
+        client_reused: bool,
+    ) -> Box<Error> {
+        ctx.proxy.error_while_proxy(peer, session, e, &mut ctx.ctx, client_reused)
+    }
+
+    async fn upstream_peer(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<Box<HttpPeer>> {
+        ctx.proxy.upstream_peer(session, &mut ctx.ctx).await
+    }
+
+    async fn connected_to_upstream(
+        &self,
+        session: &mut Session,
+        reused: bool,
+        peer: &HttpPeer,
+        fd: std::os::unix::io::RawFd,
+        digest: Option<&Digest>,
+        ctx: &mut Self::CTX,
+    ) -> Result<()>
+    where
+        Self::CTX: Send + Sync,
+    {
+        ctx.proxy.connected_to_upstream(session, reused, peer, fd, digest, &mut ctx.ctx).await
+    }
+
+    async fn upstream_request_filter(
+        &self,
+        session: &mut Session,
+        upstream_request: &mut RequestHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        ctx.proxy.upstream_request_filter(session, upstream_request, &mut ctx.ctx).await
+    }
+
+    fn upstream_response_filter(
+        &self,
+        session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        ctx.proxy.upstream_response_filter(session, upstream_response, &mut ctx.ctx)
+    }
+
+    async fn request_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        ctx.proxy.request_body_filter(session, body, end_of_stream, &mut ctx.ctx).await
+    }
+
+    async fn response_filter(
+        &self,
+        session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        ctx.proxy.response_filter(session, upstream_response, &mut ctx.ctx).await
+    }
+
+    fn response_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> Result<Option<Duration>>
+    where
+        Self::CTX: Send + Sync,
+    {
+        ctx.proxy.response_body_filter(session, body, end_of_stream, &mut ctx.ctx)
+    }
+
+    fn upstream_response_trailer_filter(
+        &self,
+        session: &mut Session,
+        upstream_trailers: &mut http::HeaderMap,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        ctx.proxy.upstream_response_trailer_filter(session, upstream_trailers, &mut ctx.ctx)
+    }
+
+    async fn response_trailer_filter(
+        &self,
+        session: &mut Session,
+        upstream_trailers: &mut http::HeaderMap,
+        ctx: &mut Self::CTX,
+    ) -> Result<Option<Bytes>>
+    where
+        Self::CTX: Send + Sync,
+    {
+        ctx.proxy.response_trailer_filter(session, upstream_trailers, &mut ctx.ctx).await
+    }
+
+    async fn fail_to_proxy(&self, session: &mut Session, e: &Error, ctx: &mut Self::CTX) -> FailToProxy
+    where
+        Self::CTX: Send + Sync,
+    {
+        ctx.proxy.fail_to_proxy(session, e, &mut ctx.ctx).await
+    }
+
+    async fn logging(&self, session: &mut Session, e: Option<&Error>, ctx: &mut Self::CTX) {
+        ctx.proxy.logging(session, e, &mut ctx.ctx).await
+    }
+}
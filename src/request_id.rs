@@ -0,0 +1,66 @@
+use pingora::http::{RequestHeader, ResponseHeader};
+use pingora::prelude::*;
+
+use crate::types::RequestContext;
+
+/// Максимальная длина принимаемого от клиента идентификатора запроса
+const MAX_REQUEST_ID_LEN: usize = 256;
+
+/// Идентификатор запроса из входящего заголовка (например, X-Request-Id или X-Amzn-Trace-Id).
+/// Пустые, слишком длинные и содержащие непечатаемые символы значения игнорируются.
+pub fn incoming_request_id(request: &RequestHeader, header_name: &str) -> Option<String> {
+    let value = request.headers.get(header_name)?.to_str().ok()?.trim();
+    let valid = !value.is_empty()
+        && value.len() <= MAX_REQUEST_ID_LEN
+        && value.chars().all(|c| c.is_ascii_graphic() || c == ' ');
+    valid.then(|| value.to_string())
+}
+
+/// Передает идентификатор запроса upstream под настроенным именем заголовка
+pub fn propagate_request_id(upstream_request: &mut RequestHeader, header_name: &str, ctx: &RequestContext) -> Result<()> {
+    upstream_request.insert_header(header_name.to_string(), ctx.request_id.as_str())
+}
+
+/// Возвращает идентификатор запроса клиенту под настроенным именем заголовка
+pub fn add_request_id_header(response: &mut ResponseHeader, header_name: &str, ctx: &RequestContext) -> Result<()> {
+    response.insert_header(header_name.to_string(), ctx.request_id.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_header_name_used_for_propagation() {
+        let header_name = "X-Amzn-Trace-Id";
+        let trace_id = "Root=1-67891233-abcdef012345678912345678";
+
+        let mut request = RequestHeader::build("GET", b"/api/users", None).unwrap();
+        request.insert_header(header_name, trace_id).unwrap();
+
+        let mut ctx = RequestContext::new();
+        ctx.request_id = incoming_request_id(&request, header_name).unwrap();
+        assert_eq!(ctx.request_id, trace_id);
+        // Заголовок с именем по умолчанию не используется
+        assert_eq!(incoming_request_id(&request, "X-Request-Id"), None);
+
+        let mut upstream_request = RequestHeader::build("GET", b"/api/users", None).unwrap();
+        propagate_request_id(&mut upstream_request, header_name, &ctx).unwrap();
+        assert_eq!(upstream_request.headers.get("x-amzn-trace-id").unwrap(), trace_id);
+        assert!(upstream_request.headers.get("x-request-id").is_none());
+
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        add_request_id_header(&mut response, header_name, &ctx).unwrap();
+        assert_eq!(response.headers.get("x-amzn-trace-id").unwrap(), trace_id);
+    }
+
+    #[test]
+    fn test_invalid_incoming_request_id_ignored() {
+        let mut request = RequestHeader::build("GET", b"/", None).unwrap();
+        request.insert_header("X-Request-Id", "x".repeat(MAX_REQUEST_ID_LEN + 1)).unwrap();
+        assert_eq!(incoming_request_id(&request, "X-Request-Id"), None);
+
+        request.insert_header("X-Request-Id", "  ").unwrap();
+        assert_eq!(incoming_request_id(&request, "X-Request-Id"), None);
+    }
+}
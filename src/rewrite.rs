@@ -0,0 +1,290 @@
+use http::HeaderValue;
+use pingora::http::ResponseHeader;
+use pingora::prelude::*;
+
+use crate::config::ProxyRedirect;
+use crate::types::{RequestContext, UpstreamTarget};
+
+/// Отрезает префикс URL без учета регистра (схема и хост регистронезависимы)
+fn strip_prefix_ignore_case<'a>(url: &'a str, prefix: &str) -> Option<&'a str> {
+    url.get(..prefix.len())
+        .filter(|head| head.eq_ignore_ascii_case(prefix))
+        .map(|_| &url[prefix.len()..])
+}
+
+/// Адреса, под которыми upstream может указывать себя в редиректах
+pub fn upstream_hosts(ctx: &RequestContext) -> Vec<String> {
+    let mut hosts = Vec::new();
+    match &ctx.upstream_target {
+        UpstreamTarget::Direct(addr) => hosts.push(addr.to_string()),
+        // nginx передает unix upstream Host: localhost
+        UpstreamTarget::Unix { .. } => hosts.push("localhost".to_string()),
+        UpstreamTarget::Named(name) => {
+            hosts.extend(ctx.selected_backend.clone());
+            hosts.push(name.clone());
+        }
+        UpstreamTarget::None => {}
+    }
+    hosts.extend(ctx.upstream_name.clone());
+    hosts
+}
+
+/// Переписывает URL редиректа upstream по правилам proxy_redirect.
+/// `public_origin` - публичные scheme://host запроса клиента.
+pub fn rewrite_redirect_url(
+    url: &str,
+    redirect: &ProxyRedirect,
+    upstream_hosts: &[String],
+    public_origin: &str,
+) -> Option<String> {
+    match redirect {
+        ProxyRedirect::Off => None,
+        ProxyRedirect::Default => {
+            for host in upstream_hosts {
+                for (scheme, default_port) in [("http://", ":80"), ("https://", ":443")] {
+                    // Порт по умолчанию upstream может не указывать
+                    let origins = [host.as_str(), host.strip_suffix(default_port).unwrap_or(host)];
+                    for origin in origins {
+                        let Some(rest) = strip_prefix_ignore_case(url, &format!("{}{}", scheme, origin)) else {
+                            continue;
+                        };
+                        if rest.is_empty() || rest.starts_with(['/', '?', '#']) {
+                            return Some(format!("{}{}", public_origin, rest));
+                        }
+                    }
+                }
+            }
+            None
+        }
+        ProxyRedirect::Replace(rules) => rules.iter().find_map(|(from, to)| {
+            strip_prefix_ignore_case(url, from).map(|rest| format!("{}{}", to, rest))
+        }),
+    }
+}
+
+/// Переписывает URL в значении Refresh (`5; url=http://...`)
+fn rewrite_refresh(
+    value: &str,
+    redirect: &ProxyRedirect,
+    upstream_hosts: &[String],
+    public_origin: &str,
+) -> Option<String> {
+    let url_start = value.to_ascii_lowercase().find("url=")? + "url=".len();
+    let url = rewrite_redirect_url(&value[url_start..], redirect, upstream_hosts, public_origin)?;
+    Some(format!("{}{}", &value[..url_start], url))
+}
+
+/// Переписывает Location и Refresh заголовки ответа upstream с адреса upstream на публичный хост
+pub fn rewrite_redirect_headers(
+    response: &mut ResponseHeader,
+    redirect: &ProxyRedirect,
+    upstream_hosts: &[String],
+    public_origin: &str,
+) -> Result<()> {
+    let header = |response: &ResponseHeader, name: &str| {
+        response.headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string)
+    };
+
+    if let Some(location) = header(response, "location") {
+        if let Some(rewritten) = rewrite_redirect_url(&location, redirect, upstream_hosts, public_origin) {
+            response.insert_header("Location", rewritten)?;
+        }
+    }
+    if let Some(refresh) = header(response, "refresh") {
+        if let Some(rewritten) = rewrite_refresh(&refresh, redirect, upstream_hosts, public_origin) {
+            response.insert_header("Refresh", rewritten)?;
+        }
+    }
+    Ok(())
+}
+
+/// Домен cookie по правилам proxy_cookie_domain (без учета регистра и ведущей точки)
+fn rewrite_cookie_domain(domain: &str, rules: &[(String, String)], public_host: &str) -> Option<String> {
+    rules
+        .iter()
+        .find(|(from, _)| from.trim_start_matches('.').eq_ignore_ascii_case(domain.trim_start_matches('.')))
+        .map(|(_, to)| to.replace("$host", public_host))
+}
+
+/// Путь cookie по правилам proxy_cookie_path (замена префикса)
+fn rewrite_cookie_path(path: &str, rules: &[(String, String)], public_host: &str) -> Option<String> {
+    rules.iter().find_map(|(from, to)| {
+        path.strip_prefix(from.as_str())
+            .map(|rest| format!("{}{}", to.replace("$host", public_host), rest))
+    })
+}
+
+/// Переписывает атрибуты Domain и Path в значении Set-Cookie
+pub fn rewrite_set_cookie(
+    value: &str,
+    domain_rules: &[(String, String)],
+    path_rules: &[(String, String)],
+    public_host: &str,
+) -> Option<String> {
+    let mut changed = false;
+    let attributes: Vec<String> = value
+        .split(';')
+        .enumerate()
+        .map(|(i, attr)| {
+            // Первая часть - имя и значение cookie
+            if i == 0 {
+                return attr.to_string();
+            }
+            let Some((name, attr_value)) = attr.split_once('=') else {
+                return attr.to_string();
+            };
+            let rewritten = match name.trim().to_ascii_lowercase().as_str() {
+                "domain" => rewrite_cookie_domain(attr_value.trim(), domain_rules, public_host),
+                "path" => rewrite_cookie_path(attr_value.trim(), path_rules, public_host),
+                _ => None,
+            };
+            match rewritten {
+                Some(new_value) => {
+                    changed = true;
+                    format!("{}={}", name, new_value)
+                }
+                None => attr.to_string(),
+            }
+        })
+        .collect();
+
+    changed.then(|| attributes.join(";"))
+}
+
+/// Переписывает все Set-Cookie заголовки ответа upstream (proxy_cookie_domain / proxy_cookie_path)
+pub fn rewrite_cookie_headers(
+    response: &mut ResponseHeader,
+    domain_rules: &[(String, String)],
+    path_rules: &[(String, String)],
+    public_host: &str,
+) -> Result<()> {
+    if domain_rules.is_empty() && path_rules.is_empty() {
+        return Ok(());
+    }
+
+    let mut changed = false;
+    let cookies: Vec<HeaderValue> = response
+        .headers
+        .get_all("set-cookie")
+        .iter()
+        .map(|value| {
+            let rewritten = value
+                .to_str()
+                .ok()
+                .and_then(|cookie| rewrite_set_cookie(cookie, domain_rules, path_rules, public_host))
+                .and_then(|cookie| HeaderValue::from_str(&cookie).ok());
+            match rewritten {
+                Some(cookie) => {
+                    changed = true;
+                    cookie
+                }
+                None => value.clone(),
+            }
+        })
+        .collect();
+
+    if changed {
+        response.remove_header("set-cookie");
+        for cookie in cookies {
+            response.append_header("Set-Cookie", cookie)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn direct_ctx() -> RequestContext {
+        let mut ctx = RequestContext::new();
+        ctx.upstream_target = UpstreamTarget::Direct("127.0.0.1:8080".parse().unwrap());
+        ctx
+    }
+
+    fn location(response: &ResponseHeader) -> &str {
+        response.headers.get("location").unwrap().to_str().unwrap()
+    }
+
+    #[test]
+    fn test_location_rewritten_to_public_host() {
+        let hosts = upstream_hosts(&direct_ctx());
+        let mut response = ResponseHeader::build(302, None).unwrap();
+        response.insert_header("Location", "http://127.0.0.1:8080/x?y=1").unwrap();
+        response.insert_header("Refresh", "5; url=http://127.0.0.1:8080/next").unwrap();
+
+        rewrite_redirect_headers(&mut response, &ProxyRedirect::Default, &hosts, "https://api.ad-quest.ru").unwrap();
+        assert_eq!(location(&response), "https://api.ad-quest.ru/x?y=1");
+        assert_eq!(response.headers.get("refresh").unwrap(), "5; url=https://api.ad-quest.ru/next");
+    }
+
+    #[test]
+    fn test_location_left_untouched() {
+        let hosts = upstream_hosts(&direct_ctx());
+        let origin = "https://api.ad-quest.ru";
+
+        // Внешние и относительные адреса, а также другой порт не переписываются
+        for url in ["https://accounts.example.com/login", "/relative", "http://127.0.0.1:80801/x"] {
+            assert_eq!(rewrite_redirect_url(url, &ProxyRedirect::Default, &hosts, origin), None);
+        }
+        assert_eq!(
+            rewrite_redirect_url("http://127.0.0.1:8080/x", &ProxyRedirect::Off, &hosts, origin),
+            None
+        );
+    }
+
+    #[test]
+    fn test_named_upstream_and_explicit_rules() {
+        let mut ctx = RequestContext::new();
+        ctx.upstream_target = UpstreamTarget::Named("core_api".to_string());
+        ctx.selected_backend = Some("10.0.0.5:80".to_string());
+        let hosts = upstream_hosts(&ctx);
+        let origin = "https://api.ad-quest.ru";
+
+        assert_eq!(
+            rewrite_redirect_url("http://10.0.0.5/login", &ProxyRedirect::Default, &hosts, origin).as_deref(),
+            Some("https://api.ad-quest.ru/login")
+        );
+        assert_eq!(
+            rewrite_redirect_url("HTTP://core_api/login", &ProxyRedirect::Default, &hosts, origin).as_deref(),
+            Some("https://api.ad-quest.ru/login")
+        );
+
+        let rules = ProxyRedirect::Replace(vec![(
+            "http://app.internal/".to_string(),
+            "https://example.com/app/".to_string(),
+        )]);
+        assert_eq!(
+            rewrite_redirect_url("http://app.internal/a", &rules, &hosts, origin).as_deref(),
+            Some("https://example.com/app/a")
+        );
+        assert_eq!(rewrite_redirect_url("http://10.0.0.5/login", &rules, &hosts, origin), None);
+    }
+
+    #[test]
+    fn test_set_cookie_domain_rewritten_to_public_host() {
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        response.append_header("Set-Cookie", "session=abc; Domain=app.internal; Path=/app/login; HttpOnly").unwrap();
+        response.append_header("Set-Cookie", "theme=dark; domain=.APP.internal").unwrap();
+        response.append_header("Set-Cookie", "other=1; Domain=example.org").unwrap();
+
+        let domains = vec![("app.internal".to_string(), "$host".to_string())];
+        let paths = vec![("/app/".to_string(), "/".to_string())];
+        rewrite_cookie_headers(&mut response, &domains, &paths, "api.ad-quest.ru").unwrap();
+
+        let cookies: Vec<&str> = response
+            .headers
+            .get_all("set-cookie")
+            .iter()
+            .map(|v| v.to_str().unwrap())
+            .collect();
+        assert_eq!(
+            cookies,
+            vec![
+                "session=abc; Domain=api.ad-quest.ru; Path=/login; HttpOnly",
+                "theme=dark; domain=api.ad-quest.ru",
+                "other=1; Domain=example.org",
+            ]
+        );
+    }
+}
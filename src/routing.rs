@@ -1,22 +1,34 @@
-use crate::types::{RequestContext, ServiceType};
+use crate::config::ServicesConfig;
+use crate::types::{RequestContext, ServiceType, UpstreamTarget};
 use pingora::prelude::*;
 use log::info;
 
-/// Обрабатывает HTTP -> HTTPS редирект
+/// Получает Host запроса (в HTTP/2 из :authority, в HTTP/1.1 из заголовка Host)
+pub fn request_host(session: &Session) -> &str {
+    session
+        .req_header()
+        .uri
+        .authority()
+        .map(|a| a.as_str())
+        .or_else(|| {
+            session
+                .req_header()
+                .headers
+                .get("host")
+                .and_then(|h| h.to_str().ok())
+        })
+        .unwrap_or("unknown")
+}
+
+/// Обрабатывает HTTP -> HTTPS редирект. Схема запроса определяется заранее
+/// (RequestContext::scheme) с учетом доверенных прокси.
 pub async fn handle_https_redirect(
-    session: &mut Session, 
-    host: &str, 
-    _uri: &str
+    _session: &mut Session,
+    host: &str,
+    _uri: &str,
+    is_https: bool,
 ) -> Result<bool> {
     // ВРЕМЕННО ОТКЛЮЧАЕМ ПРИНУДИТЕЛЬНЫЙ HTTPS РЕДИРЕКТ ДЛЯ ОТЛАДКИ
-    // Проверяем, является ли соединение HTTPS
-    let is_https = session.req_header().uri.scheme().is_some_and(|s| s == "https") ||
-                  session.req_header().headers.get("x-forwarded-proto").is_some_and(|v| v == "https") ||
-                  session.server_addr().is_some_and(|addr| {
-                      // Проверяем порт через строковое представление
-                      addr.to_string().ends_with(":443")
-                  });
-    
     let host_without_port = host.split(':').next().unwrap_or(host);
     
     // Логируем только если это не стандартный HTTP запрос
@@ -26,112 +38,195 @@ pub async fn handle_https_redirect(
     Ok(false)
 }
 
-/// Определяет маршрутизацию запроса
-pub fn route_request(host: &str, uri: &str, ctx: &mut RequestContext) {
+/// Определяет маршрутизацию запроса. Особые правила для localhost (порты 8085/8091,
+/// префиксы Zitadel) действуют только при compat.adquest_legacy_routes
+pub fn route_request(host: &str, uri: &str, services: &ServicesConfig, legacy_routes: bool, ctx: &mut RequestContext) {
     let host_without_port = host.split(':').next().unwrap_or(host);
-    
-    // Сначала проверяем маршрутизацию по URI для localhost/127.0.0.1
-    if (host_without_port == "127.0.0.1" || host_without_port == "localhost") && uri.starts_with("/api/") {
-        // API запросы на localhost идут на Core API, а не на Zitadel
-        route_localhost_api(uri, ctx, host);
-        return;
-    }
-    
-    if host_without_port == "auth.ad-quest.ru" || 
-       (host_without_port == "localhost" && (host.contains(":8085") || host.contains(":8091"))) {
+
+    if legacy_routes && (host_without_port == "127.0.0.1" || host_without_port == "localhost") {
+        route_legacy_localhost(host, uri, services, ctx);
+    } else if host_without_port == "auth.ad-quest.ru" {
         // Zitadel Auth Service
-        ctx.service_type = ServiceType::ZitadelAuth;
-        ctx.upstream_port = 8091;  // Zitadel работает на порту 8091 (маппинг Docker)
+        set_named(ctx, ServiceType::ZitadelAuth);
         info!("Routing to ZITADEL AUTH service for host: {}", host_without_port);
-        
-    } else if host_without_port == "localhost" || host_without_port == "127.0.0.1" {
-        // Для localhost/127.0.0.1 без /api/ - проверяем, может быть Zitadel консоль
-        if uri.starts_with("/ui/") || uri.starts_with("/.well-known/") || uri.starts_with("/oauth/") {
-            ctx.service_type = ServiceType::ZitadelAuth;
-            ctx.upstream_port = 8091;
-            info!("Routing to ZITADEL AUTH service for host: {} (Zitadel endpoint)", host_without_port);
-        } else {
-            // Localhost для разработки
-            ctx.service_type = ServiceType::Static;
-        }
-        
+
     } else if host_without_port == "api.ad-quest.ru" {
-        route_api_domain(uri, ctx);
-        
+        route_api_domain(uri, services, ctx);
+
+    } else {
+        route_by_path(uri, services, ctx, host);
+    }
+}
+
+/// Прежние правила для localhost/127.0.0.1 (compat.adquest_legacy_routes)
+fn route_legacy_localhost(host: &str, uri: &str, services: &ServicesConfig, ctx: &mut RequestContext) {
+    let host_without_port = host.split(':').next().unwrap_or(host);
+
+    if uri.starts_with("/api/") {
+        // API запросы на localhost идут на Core API, а не на Zitadel
+        route_by_path(uri, services, ctx, host);
+
+    } else if host_without_port == "localhost" && (host.contains(":8085") || host.contains(":8091")) {
+        set_named(ctx, ServiceType::ZitadelAuth);
+        info!("Routing to ZITADEL AUTH service for host: {}", host);
+
+    } else if uri.starts_with("/ui/") || uri.starts_with("/.well-known/") || uri.starts_with("/oauth/") {
+        // Консоль и OAuth endpoints Zitadel
+        set_named(ctx, ServiceType::ZitadelAuth);
+        info!("Routing to ZITADEL AUTH service for host: {} (Zitadel endpoint)", host_without_port);
+
     } else {
-        route_localhost_api(uri, ctx, host);
+        // Localhost для разработки
+        set_static(ctx);
     }
 }
 
 /// Маршрутизация для домена api.ad-quest.ru
-fn route_api_domain(uri: &str, ctx: &mut RequestContext) {
+fn route_api_domain(uri: &str, services: &ServicesConfig, ctx: &mut RequestContext) {
     if uri.starts_with("/api/v1/logs") || uri.starts_with("/api/v1/analytics") || uri.starts_with("/api/v1/health") || uri == "/health" {
         // Логирование, аналитика и health check - направляем на Shared Services
-        ctx.service_type = ServiceType::SharedApi;
-        ctx.upstream_port = 8083;
+        set_direct(ctx, ServiceType::SharedApi, services.shared_api);
         info!("Routing to SHARED API service for api.ad-quest.ru logs/analytics/health path: {}", uri);
         
     } else if uri.starts_with("/challenge") {
-        ctx.service_type = ServiceType::ChallengeApi;
-        ctx.upstream_port = 8080;
+        set_direct(ctx, ServiceType::ChallengeApi, services.challenge_api);
         info!("Routing to CHALLENGE API service for api.ad-quest.ru path: {}", uri);
         
     } else if uri.starts_with("/billing") {
-        ctx.service_type = ServiceType::BillingApi;
-        ctx.upstream_port = 8081;
+        set_direct(ctx, ServiceType::BillingApi, services.billing_api);
         info!("Routing to BILLING API service for api.ad-quest.ru path: {}", uri);
         
     } else if uri.starts_with("/erir") {
-        ctx.service_type = ServiceType::ErirApi;
-        ctx.upstream_port = 8082;
+        set_direct(ctx, ServiceType::ErirApi, services.erir_api);
         info!("Routing to ERIR API service for api.ad-quest.ru path: {}", uri);
         
     } else if uri.starts_with("/shared") || uri.starts_with("/tbank") {
-        ctx.service_type = ServiceType::SharedApi;
-        ctx.upstream_port = 8083;
+        set_direct(ctx, ServiceType::SharedApi, services.shared_api);
         info!("Routing to SHARED API service for api.ad-quest.ru path: {}", uri);
         
     } else {
         // Общие API запросы на api.ad-quest.ru - направляем на Core API балансировщик
-        ctx.service_type = ServiceType::CoreApi;
+        set_named(ctx, ServiceType::CoreApi);
         info!("Routing to CORE API service for api.ad-quest.ru path: {}", uri);
     }
 }
 
-/// Маршрутизация для localhost и других доменов
-fn route_localhost_api(uri: &str, ctx: &mut RequestContext, host: &str) {
+/// Маршрутизация по пути для остальных доменов
+fn route_by_path(uri: &str, services: &ServicesConfig, ctx: &mut RequestContext, host: &str) {
     if uri.starts_with("/api/challenge") {
         // Challenge Engine API
-        ctx.service_type = ServiceType::ChallengeApi;
-        ctx.upstream_port = 8080;
+        set_direct(ctx, ServiceType::ChallengeApi, services.challenge_api);
         info!("Routing to CHALLENGE API service for path: {}", uri);
         
     } else if uri.starts_with("/api/billing") {
         // Billing Engine API
-        ctx.service_type = ServiceType::BillingApi;
-        ctx.upstream_port = 8081;
+        set_direct(ctx, ServiceType::BillingApi, services.billing_api);
         info!("Routing to BILLING API service for path: {}", uri);
         
     } else if uri.starts_with("/api/erir") {
         // ERIR Integration API
-        ctx.service_type = ServiceType::ErirApi;
-        ctx.upstream_port = 8082;
+        set_direct(ctx, ServiceType::ErirApi, services.erir_api);
         info!("Routing to ERIR API service for path: {}", uri);
         
     } else if uri.starts_with("/api/shared") || uri.starts_with("/api/tbank") {
         // Shared Services / T-Bank Integration API
-        ctx.service_type = ServiceType::SharedApi;
-        ctx.upstream_port = 8083;
+        set_direct(ctx, ServiceType::SharedApi, services.shared_api);
         info!("Routing to SHARED API service for path: {}", uri);
         
     } else if uri.starts_with("/api/") {
         // Общие API запросы - направляем на Core API балансировщик
-        ctx.service_type = ServiceType::CoreApi;
+        set_named(ctx, ServiceType::CoreApi);
         info!("Routing to CORE API service for path: {}", uri);
         
     } else {
         // Для неопознанных доменов показываем информационную страницу
-        ctx.service_type = ServiceType::Static;
+        set_static(ctx);
         info!("Routing to STATIC page for unknown host: {} (uri: {})", host, uri);
     }
-}
\ No newline at end of file
+}
+
+/// Сервис за балансировщиком нагрузки (имя upstream совпадает с именем сервиса)
+fn set_named(ctx: &mut RequestContext, service_type: ServiceType) {
+    ctx.upstream_target = UpstreamTarget::Named(service_type.as_str().to_string());
+    ctx.service_type = service_type;
+}
+
+/// Сервис с фиксированным адресом
+fn set_direct(ctx: &mut RequestContext, service_type: ServiceType, addr: std::net::SocketAddr) {
+    ctx.service_type = service_type;
+    ctx.upstream_target = UpstreamTarget::Direct(addr);
+}
+
+/// Статическая страница без upstream
+fn set_static(ctx: &mut RequestContext) {
+    ctx.service_type = ServiceType::Static;
+    ctx.upstream_target = UpstreamTarget::None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(host: &str, uri: &str) -> RequestContext {
+        route_with(host, uri, false)
+    }
+
+    fn route_with(host: &str, uri: &str, legacy_routes: bool) -> RequestContext {
+        let mut ctx = RequestContext::new();
+        route_request(host, uri, &ServicesConfig::default(), legacy_routes, &mut ctx);
+        ctx
+    }
+
+    #[test]
+    fn test_direct_routes_use_configured_addresses() {
+        let ctx = route("api.ad-quest.ru", "/billing/invoices");
+        assert_eq!(ctx.service_type, ServiceType::BillingApi);
+        assert_eq!(ctx.upstream_target, UpstreamTarget::Direct("127.0.0.1:8081".parse().unwrap()));
+
+        let ctx = route("localhost", "/api/challenge/1");
+        assert_eq!(ctx.service_type, ServiceType::ChallengeApi);
+        assert_eq!(ctx.upstream_target, UpstreamTarget::Direct("127.0.0.1:8080".parse().unwrap()));
+
+        let mut services = ServicesConfig::default();
+        services.shared_api = "10.0.0.5:9000".parse().unwrap();
+        let mut ctx = RequestContext::new();
+        route_request("api.ad-quest.ru", "/health", &services, false, &mut ctx);
+        assert_eq!(ctx.upstream_target, UpstreamTarget::Direct("10.0.0.5:9000".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_named_and_static_routes() {
+        let ctx = route("api.ad-quest.ru", "/api/v1/users");
+        assert_eq!(ctx.service_type, ServiceType::CoreApi);
+        assert_eq!(ctx.upstream_target, UpstreamTarget::Named("core_api".to_string()));
+
+        let ctx = route("auth.ad-quest.ru", "/oauth/v2/authorize");
+        assert_eq!(ctx.upstream_target, UpstreamTarget::Named("zitadel_auth".to_string()));
+
+        let ctx = route("unknown.example.com", "/");
+        assert_eq!(ctx.service_type, ServiceType::Static);
+        assert_eq!(ctx.upstream_target, UpstreamTarget::None);
+    }
+
+    #[test]
+    fn test_legacy_localhost_routes() {
+        let zitadel = UpstreamTarget::Named("zitadel_auth".to_string());
+        assert_eq!(route_with("localhost:8085", "/", true).upstream_target, zitadel);
+        assert_eq!(route_with("localhost:8091", "/app", true).upstream_target, zitadel);
+        assert_eq!(route_with("127.0.0.1", "/.well-known/openid-configuration", true).upstream_target, zitadel);
+        assert_eq!(route_with("localhost:8085", "/api/v1/users", true).service_type, ServiceType::CoreApi);
+        assert_eq!(route_with("localhost", "/", true).service_type, ServiceType::Static);
+    }
+
+    #[test]
+    fn test_localhost_without_legacy_routes() {
+        // Без флага localhost маршрутизируется как любой другой домен
+        let ctx = route("localhost:8085", "/");
+        assert_eq!(ctx.service_type, ServiceType::Static);
+        assert_eq!(ctx.upstream_target, UpstreamTarget::None);
+        for uri in ["/ui/console", "/oauth/v2/authorize", "/.well-known/openid-configuration"] {
+            assert_eq!(route("localhost", uri).upstream_target, UpstreamTarget::None, "{}", uri);
+        }
+        assert_eq!(route("127.0.0.1", "/api/v1/users").upstream_target, UpstreamTarget::Named("core_api".to_string()));
+    }
+}
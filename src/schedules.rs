@@ -0,0 +1,358 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, Timelike, Utc};
+use chrono_tz::Tz;
+use log::{debug, info};
+use pingora_core::server::ShutdownWatch;
+use pingora_core::services::background::BackgroundService;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::clock::{Clock, SystemClock};
+use crate::config::{Config, ScheduleConfig};
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+const FULL_DAY_NAMES: [&str; 7] = ["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+
+/// Окно расписания в минутах от начала суток в часовом поясе окна
+#[derive(Debug)]
+struct Window {
+    name: String,
+    /// Дни начала окна, с понедельника
+    days: [bool; 7],
+    start: u32,
+    end: u32,
+    timezone: Tz,
+}
+
+fn parse_time(value: &str, allow_midnight_end: bool) -> Option<u32> {
+    let (hours, minutes) = value.trim().split_once(':')?;
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    match (hours, minutes) {
+        (24, 0) if allow_midnight_end => Some(MINUTES_PER_DAY),
+        (0..=23, 0..=59) => Some(hours * 60 + minutes),
+        _ => None,
+    }
+}
+
+/// День недели: mon или monday, без учета регистра
+fn parse_day(value: &str) -> Option<usize> {
+    let value = value.trim().to_ascii_lowercase();
+    DAY_NAMES
+        .iter()
+        .position(|day| *day == value)
+        .or_else(|| FULL_DAY_NAMES.iter().position(|day| *day == value))
+}
+
+fn parse_days(values: &[String]) -> Option<[bool; 7]> {
+    if values.is_empty() {
+        return Some([true; 7]);
+    }
+    let mut days = [false; 7];
+    for value in values {
+        match value.split_once('-') {
+            Some((from, to)) => {
+                let (from, to) = (parse_day(from)?, parse_day(to)?);
+                // mon-fri, а также через воскресенье: fri-mon
+                let mut day = from;
+                loop {
+                    days[day] = true;
+                    if day == to {
+                        break;
+                    }
+                    day = (day + 1) % 7;
+                }
+            }
+            None => days[parse_day(value)?] = true,
+        }
+    }
+    Some(days)
+}
+
+impl Window {
+    fn compile(config: &ScheduleConfig) -> Result<Self, String> {
+        let days = parse_days(&config.days)
+            .ok_or_else(|| format!("invalid days {:?} in schedule '{}': expected mon..sun or ranges like mon-fri", config.days, config.name))?;
+        let start = parse_time(&config.start, false)
+            .ok_or_else(|| format!("invalid start '{}' in schedule '{}': expected HH:MM", config.start, config.name))?;
+        let end = parse_time(&config.end, true)
+            .ok_or_else(|| format!("invalid end '{}' in schedule '{}': expected HH:MM", config.end, config.name))?;
+        if start == end {
+            return Err(format!("schedule '{}' has the same start and end", config.name));
+        }
+        let timezone = config
+            .timezone
+            .parse::<Tz>()
+            .map_err(|_| format!("unknown timezone '{}' in schedule '{}'", config.timezone, config.name))?;
+        Ok(Self { name: config.name.clone(), days, start, end, timezone })
+    }
+
+    /// Время до конца окна; None - окно сейчас не действует.
+    /// Время берется по местным часам пояса, поэтому окно 02:00-03:00
+    /// пропускается в день перехода на летнее время и длится два часа при обратном переходе
+    fn remaining(&self, now: DateTime<Utc>) -> Option<ChronoDuration> {
+        let local = now.with_timezone(&self.timezone);
+        let minute = local.hour() * 60 + local.minute();
+        let today = local.weekday().num_days_from_monday() as usize;
+        let yesterday = (today + 6) % 7;
+
+        let active = if self.start < self.end {
+            self.days[today] && (self.start..self.end).contains(&minute)
+        } else {
+            // Окно через полночь начинается в указанный день и заканчивается на следующий
+            (self.days[today] && minute >= self.start) || (self.days[yesterday] && minute < self.end)
+        };
+        if !active {
+            return None;
+        }
+        let minutes_left = (self.end + MINUTES_PER_DAY - minute) % MINUTES_PER_DAY;
+        let minutes_left = if minutes_left == 0 { MINUTES_PER_DAY } else { minutes_left };
+        Some(ChronoDuration::minutes(i64::from(minutes_left)) - ChronoDuration::seconds(i64::from(local.second())))
+    }
+}
+
+/// Расписания секции schedules и набор действующих сейчас окон.
+/// Набор пересчитывается ScheduleService раз в минуту, запросы только читают его
+pub struct Schedules {
+    windows: Vec<Window>,
+    clock: Arc<dyn Clock>,
+    active: RwLock<HashSet<String>>,
+}
+
+impl std::fmt::Debug for Schedules {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Schedules").field("windows", &self.windows).field("active", &self.active).finish()
+    }
+}
+
+impl Default for Schedules {
+    fn default() -> Self {
+        Self { windows: Vec::new(), clock: Arc::new(SystemClock), active: RwLock::default() }
+    }
+}
+
+impl Schedules {
+    pub fn from_config(configs: &[ScheduleConfig]) -> Result<Self, String> {
+        let mut windows: Vec<Window> = Vec::new();
+        for config in configs {
+            if windows.iter().any(|window| window.name == config.name) {
+                return Err(format!("duplicate schedule '{}'", config.name));
+            }
+            windows.push(Window::compile(config)?);
+        }
+        let schedules = Self { windows, ..Self::default() };
+        schedules.refresh();
+        Ok(schedules)
+    }
+
+    /// Подменяет источник времени и пересчитывает действующие окна
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self.refresh();
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.windows.iter().any(|window| window.name == name)
+    }
+
+    /// Пересчитывает набор действующих окон по текущему времени
+    pub fn refresh(&self) {
+        let now = self.clock.utc();
+        let active: HashSet<String> = self
+            .windows
+            .iter()
+            .filter(|window| window.remaining(now).is_some())
+            .map(|window| window.name.clone())
+            .collect();
+        let mut current = self.active.write().unwrap();
+        if *current != active {
+            debug!("Active schedules: {:?}", active);
+            *current = active;
+        }
+    }
+
+    /// Действует ли окно по последнему пересчету
+    pub fn is_active(&self, name: &str) -> bool {
+        self.active.read().unwrap().contains(name)
+    }
+
+    /// Время до конца действующего окна (для Retry-After)
+    pub fn remaining(&self, name: &str) -> Option<Duration> {
+        let window = self.windows.iter().find(|window| window.name == name)?;
+        window.remaining(self.clock.utc()).and_then(|left| left.to_std().ok())
+    }
+
+    /// Проверяет, что правила ссылаются на объявленные расписания
+    pub fn check_references(&self, config: &Config) -> Result<(), String> {
+        let known = |kind: &str, owner: &str, schedule: &str| {
+            if self.contains(schedule) {
+                Ok(())
+            } else {
+                Err(format!("{} '{}' references unknown schedule '{}'", kind, owner, schedule))
+            }
+        };
+        for rule in &config.header_rules {
+            if let Some(schedule) = &rule.schedule {
+                known("header rule", &rule.name, schedule)?;
+            }
+        }
+        for maintenance in &config.maintenance {
+            known("maintenance", &maintenance.name, &maintenance.schedule)?;
+        }
+        let locations = config.nginx_config.iter().flat_map(|nginx| &nginx.servers).flat_map(|server| &server.locations);
+        for location in locations {
+            for scheduled in &location.rate_limit_schedules {
+                known("rate_limit of location", &location.path, &scheduled.schedule)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Пересчет действующих окон расписаний в начале каждой минуты
+pub struct ScheduleService {
+    schedules: Arc<Schedules>,
+}
+
+impl ScheduleService {
+    pub fn new(schedules: Arc<Schedules>) -> Self {
+        Self { schedules }
+    }
+}
+
+#[async_trait]
+impl BackgroundService for ScheduleService {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        info!("Schedules refreshed every minute: {} window(s)", self.schedules.windows.len());
+        loop {
+            self.schedules.refresh();
+            let second = u64::from(self.schedules.clock.utc().second());
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(60 - second.min(59))) => {}
+                _ = shutdown.changed() => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use chrono::NaiveDateTime;
+
+    fn clock_at(time: &str) -> Arc<MockClock> {
+        Arc::new(MockClock::at(utc(time)))
+    }
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").unwrap().and_utc()
+    }
+
+    fn schedule(name: &str, days: &[&str], start: &str, end: &str, timezone: &str) -> ScheduleConfig {
+        ScheduleConfig {
+            name: name.to_string(),
+            days: days.iter().map(|day| day.to_string()).collect(),
+            start: start.to_string(),
+            end: end.to_string(),
+            timezone: timezone.to_string(),
+        }
+    }
+
+    /// Действует ли единственное окно расписаний в момент `time` (UTC)
+    fn active_at(config: ScheduleConfig, time: &str) -> bool {
+        let name = config.name.clone();
+        let schedules = Schedules::from_config(&[config]).unwrap().with_clock(clock_at(time));
+        schedules.is_active(&name)
+    }
+
+    #[test]
+    fn test_weekly_window() {
+        let sunday = || schedule("partner_maintenance", &["sun"], "02:00", "03:00", "UTC");
+        // 2026-10-18 - воскресенье
+        assert!(!active_at(sunday(), "2026-10-18 01:59:59"));
+        assert!(active_at(sunday(), "2026-10-18 02:00:00"));
+        assert!(active_at(sunday(), "2026-10-18 02:59:59"));
+        assert!(!active_at(sunday(), "2026-10-18 03:00:00"));
+        assert!(!active_at(sunday(), "2026-10-17 02:30:00"));
+
+        let clock = clock_at("2026-10-18 02:15:30");
+        let schedules = Schedules::from_config(&[sunday()]).unwrap().with_clock(clock.clone());
+        assert_eq!(schedules.remaining("partner_maintenance"), Some(Duration::from_secs(44 * 60 + 30)));
+        // Набор окон меняется только при пересчете
+        clock.set(utc("2026-10-18 03:00:00"));
+        assert!(schedules.is_active("partner_maintenance"));
+        schedules.refresh();
+        assert!(!schedules.is_active("partner_maintenance"));
+        assert_eq!(schedules.remaining("partner_maintenance"), None);
+    }
+
+    #[test]
+    fn test_overnight_window_and_day_ranges() {
+        // Сверка биллинга: с понедельника по пятницу 23:00-04:00 по Москве (UTC+3)
+        let billing = || schedule("billing_night", &["mon-fri"], "23:00", "04:00", "Europe/Moscow");
+        // Пятница 23:30 МСК и ночь на субботу
+        assert!(active_at(billing(), "2026-10-16 20:30:00"));
+        assert!(active_at(billing(), "2026-10-17 00:59:00"));
+        assert!(!active_at(billing(), "2026-10-17 01:00:00"));
+        // Окно субботы не объявлено
+        assert!(!active_at(billing(), "2026-10-17 20:30:00"));
+        // Ночь на понедельник относится к окну воскресенья - его нет
+        assert!(!active_at(billing(), "2026-10-18 22:30:00"));
+        assert!(active_at(billing(), "2026-10-19 20:00:00"));
+
+        let weekend = || schedule("weekend", &["Sat-sun"], "00:00", "24:00", "UTC");
+        assert!(active_at(weekend(), "2026-10-17 23:59:59"));
+        assert!(!active_at(weekend(), "2026-10-19 00:00:00"));
+    }
+
+    #[test]
+    fn test_dst_crossing_timezones() {
+        // Нью-Йорк, 09:00-10:00 местного: 14:00 UTC зимой, 13:00 UTC после перехода 8 марта 2026
+        let morning = || schedule("ny_morning", &[], "09:00", "10:00", "America/New_York");
+        assert!(active_at(morning(), "2026-03-06 14:30:00"));
+        assert!(!active_at(morning(), "2026-03-06 13:30:00"));
+        assert!(active_at(morning(), "2026-03-09 13:30:00"));
+        assert!(!active_at(morning(), "2026-03-09 14:30:00"));
+
+        // Берлин, 29 марта 2026 02:00-03:00 местного времени не существует: окно пропускается
+        let night = || schedule("berlin_night", &[], "02:00", "03:00", "Europe/Berlin");
+        for time in ["2026-03-29 00:30:00", "2026-03-29 01:00:00", "2026-03-29 01:30:00"] {
+            assert!(!active_at(night(), time), "{}", time);
+        }
+        assert!(active_at(night(), "2026-03-30 00:30:00"));
+        // 25 октября 2026 02:00-03:00 проходит дважды: окно длится два часа
+        for time in ["2026-10-25 00:00:00", "2026-10-25 00:59:00", "2026-10-25 01:00:00", "2026-10-25 01:59:00"] {
+            assert!(active_at(night(), time), "{}", time);
+        }
+        assert!(!active_at(night(), "2026-10-25 02:00:00"));
+    }
+
+    #[test]
+    fn test_invalid_schedules() {
+        let invalid = |config: ScheduleConfig| Schedules::from_config(&[config]).is_err();
+        assert!(invalid(schedule("a", &["someday"], "02:00", "03:00", "UTC")));
+        assert!(invalid(schedule("a", &[], "2am", "03:00", "UTC")));
+        assert!(invalid(schedule("a", &[], "24:00", "03:00", "UTC")));
+        assert!(invalid(schedule("a", &[], "02:00", "02:00", "UTC")));
+        assert!(invalid(schedule("a", &[], "02:00", "03:00", "Mars/Olympus")));
+        let duplicate = schedule("a", &[], "02:00", "03:00", "UTC");
+        assert!(Schedules::from_config(&[duplicate.clone(), duplicate]).is_err());
+
+        let mut config = Config::default();
+        config.maintenance.push(crate::config::MaintenanceConfig {
+            name: "partners".to_string(),
+            schedule: "missing".to_string(),
+            servers: Vec::new(),
+            locations: Vec::new(),
+            message: String::new(),
+        });
+        assert!(Schedules::default().check_references(&config).is_err());
+    }
+}
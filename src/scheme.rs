@@ -0,0 +1,267 @@
+use pingora::http::RequestHeader;
+use pingora::prelude::*;
+use std::net::IpAddr;
+
+use crate::client_ip::client_ip;
+use crate::config::{ProxyHeadersConfig, RealIpSource};
+use crate::forwarded;
+
+/// Подсеть в CIDR нотации (10.0.0.0/8); адрес без префикса - один хост
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid CIDR '{}'", value);
+        let (addr, prefix) = match value.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix.parse::<u8>().map_err(|_| invalid())?)),
+            None => (value.trim(), None),
+        };
+        let network: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        if prefix > max {
+            return Err(invalid());
+        }
+        Ok(Self { network, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Определение схемы и адреса клиента запроса. X-Forwarded-Proto (или proto из Forwarded)
+/// и заголовки real_ip_from учитываются только от доверенных прокси
+/// (proxy_headers.trusted_proxies), иначе схема определяется по TLS соединению
+/// с клиентом, а адрес - по соединению.
+#[derive(Debug, Clone)]
+pub struct SchemeResolver {
+    trusted_proxies: Vec<Cidr>,
+    forwarded_proto_header: String,
+    forwarded_for_header: String,
+    real_ip_from: Vec<RealIpSource>,
+}
+
+impl SchemeResolver {
+    pub fn from_config(headers: &ProxyHeadersConfig) -> Result<Self, String> {
+        let trusted_proxies = headers
+            .trusted_proxies
+            .iter()
+            .map(|cidr| Cidr::parse(cidr).map_err(|e| format!("proxy_headers.trusted_proxies: {}", e)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            trusted_proxies,
+            forwarded_proto_header: headers.forwarded_proto.clone(),
+            forwarded_for_header: headers.forwarded_for.clone(),
+            real_ip_from: headers.real_ip_from.clone(),
+        })
+    }
+
+    /// Адрес соединения принадлежит доверенному прокси
+    pub fn is_trusted(&self, peer: Option<IpAddr>) -> bool {
+        peer.is_some_and(|peer| self.trusted_proxies.iter().any(|cidr| cidr.contains(peer)))
+    }
+
+    /// Схема запроса: X-Forwarded-Proto доверенного прокси (первое значение списка)
+    /// или https для TLS соединения
+    pub fn real_scheme(&self, peer: Option<IpAddr>, tls: bool, forwarded_proto: Option<&str>) -> &'static str {
+        if let Some(proto) = forwarded_proto.filter(|_| self.is_trusted(peer)) {
+            let proto = proto.split(',').next().unwrap_or_default().trim();
+            return if proto.eq_ignore_ascii_case("https") { "https" } else { "http" };
+        }
+        if tls {
+            "https"
+        } else {
+            "http"
+        }
+    }
+
+    /// Схема запроса сессии; без X-Forwarded-Proto - proto первого элемента Forwarded
+    pub fn session_scheme(&self, session: &Session) -> &'static str {
+        let tls = session.digest().is_some_and(|digest| digest.ssl_digest.is_some());
+        let peer = client_ip(session);
+        let request = session.req_header();
+        let forwarded_proto = request
+            .headers
+            .get(self.forwarded_proto_header.as_str())
+            .and_then(|v| v.to_str().ok());
+        if forwarded_proto.is_none() && self.is_trusted(peer) {
+            let elements = forwarded::from_request(request);
+            let proto = elements.first().and_then(|element| element.get("proto"));
+            return self.real_scheme(peer, tls, proto);
+        }
+        self.real_scheme(peer, tls, forwarded_proto)
+    }
+
+    /// Адрес клиента: за доверенным прокси - из первого источника real_ip_from, который
+    /// его содержит, иначе адрес соединения
+    pub fn real_ip(&self, peer: Option<IpAddr>, request: &RequestHeader) -> Option<IpAddr> {
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+        self.real_ip_from
+            .iter()
+            .find_map(|source| {
+                let chain: Vec<Option<IpAddr>> = match source {
+                    RealIpSource::Forwarded => forwarded::from_request(request)
+                        .iter()
+                        .map(|element| element.get("for").and_then(forwarded::node_ip))
+                        .collect(),
+                    RealIpSource::XForwardedFor => request
+                        .headers
+                        .get_all(self.forwarded_for_header.as_str())
+                        .iter()
+                        .filter_map(|value| value.to_str().ok())
+                        .flat_map(|value| value.split(','))
+                        .map(|item| item.trim().parse().ok())
+                        .collect(),
+                };
+                self.client_in_chain(&chain)
+            })
+            .or(peer)
+    }
+
+    /// Клиент в цепочке адресов: первый справа адрес не из доверенных прокси или самый левый,
+    /// если доверенные все. Скрытый или некорректный узел до клиента - адреса нет
+    fn client_in_chain(&self, chain: &[Option<IpAddr>]) -> Option<IpAddr> {
+        let mut client = None;
+        for ip in chain.iter().rev() {
+            let ip = ip.map(|ip| ip.to_canonical())?;
+            client = Some(ip);
+            if !self.is_trusted(Some(ip)) {
+                break;
+            }
+        }
+        client
+    }
+
+    /// Адрес клиента сессии (real_ip)
+    pub fn session_client_ip(&self, session: &Session) -> Option<IpAddr> {
+        self.real_ip(client_ip(session), session.req_header())
+    }
+}
+
+impl Default for SchemeResolver {
+    fn default() -> Self {
+        Self {
+            trusted_proxies: Vec::new(),
+            forwarded_proto_header: ProxyHeadersConfig::default().forwarded_proto,
+            forwarded_for_header: ProxyHeadersConfig::default().forwarded_for,
+            real_ip_from: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolver(trusted: &[&str]) -> SchemeResolver {
+        let headers = ProxyHeadersConfig {
+            trusted_proxies: trusted.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        };
+        SchemeResolver::from_config(&headers).unwrap()
+    }
+
+    fn ip(value: &str) -> Option<IpAddr> {
+        Some(value.parse().unwrap())
+    }
+
+    #[test]
+    fn test_forwarded_proto_from_trusted_proxy() {
+        let resolver = resolver(&["10.0.0.0/8", "2001:db8::/32"]);
+        // LB завершает TLS и передает запрос по HTTP
+        assert_eq!(resolver.real_scheme(ip("10.1.2.3"), false, Some("https")), "https");
+        assert_eq!(resolver.real_scheme(ip("::ffff:10.1.2.3"), false, Some("https")), "https");
+        assert_eq!(resolver.real_scheme(ip("2001:db8::1"), false, Some("https, http")), "https");
+        assert_eq!(resolver.real_scheme(ip("10.1.2.3"), false, Some("http")), "http");
+        assert_eq!(resolver.real_scheme(ip("10.1.2.3"), false, None), "http");
+    }
+
+    #[test]
+    fn test_forwarded_proto_from_untrusted_source_ignored() {
+        let resolver = resolver(&["10.0.0.0/8"]);
+        assert_eq!(resolver.real_scheme(ip("203.0.113.7"), false, Some("https")), "http");
+        assert_eq!(resolver.real_scheme(None, false, Some("https")), "http");
+        // Без trusted_proxies заголовку не доверяют никому
+        assert_eq!(SchemeResolver::default().real_scheme(ip("10.1.2.3"), false, Some("https")), "http");
+        // Клиент не может понизить схему TLS соединения
+        assert_eq!(resolver.real_scheme(ip("203.0.113.7"), true, Some("http")), "https");
+    }
+
+    #[test]
+    fn test_tls_listener_without_forwarded_proto() {
+        let resolver = resolver(&["10.0.0.0/8"]);
+        assert_eq!(resolver.real_scheme(ip("203.0.113.7"), true, None), "https");
+        assert_eq!(resolver.real_scheme(ip("10.1.2.3"), true, None), "https");
+    }
+
+    #[test]
+    fn test_real_ip_from_forwarded_chain() {
+        let headers = ProxyHeadersConfig {
+            trusted_proxies: vec!["10.0.0.0/8".to_string()],
+            real_ip_from: vec![RealIpSource::Forwarded, RealIpSource::XForwardedFor],
+            ..Default::default()
+        };
+        let chained = SchemeResolver::from_config(&headers).unwrap();
+        let request = |headers: &[(&str, &str)]| {
+            let mut request = RequestHeader::build("GET", b"/", None).unwrap();
+            for (name, value) in headers {
+                request.append_header(name.to_string(), *value).unwrap();
+            }
+            request
+        };
+
+        // Клиент -> CDN (недоверенный) -> внутренний LB 10.0.0.5 -> прокси: адрес клиента - CDN
+        let chain = request(&[(
+            "Forwarded",
+            "for=\"[2001:db8:cafe::17]:4711\";proto=https, for=198.51.100.17;by=10.0.0.5, for=10.0.0.9",
+        )]);
+        assert_eq!(chained.real_ip(ip("10.0.0.5"), &chain), ip("198.51.100.17"));
+        // Недоверенный адрес соединения не может подменить адрес клиента
+        assert_eq!(chained.real_ip(ip("203.0.113.7"), &chain), ip("203.0.113.7"));
+
+        // Все узлы доверенные - самый левый
+        let internal = request(&[("Forwarded", "for=10.1.1.1, for=10.0.0.9")]);
+        assert_eq!(chained.real_ip(ip("10.0.0.5"), &internal), ip("10.1.1.1"));
+
+        // Скрытый узел: Forwarded не дает адреса, используется X-Forwarded-For
+        let hidden = request(&[("Forwarded", "for=_gazonk"), ("X-Forwarded-For", "192.0.2.60, 10.0.0.9")]);
+        assert_eq!(chained.real_ip(ip("10.0.0.5"), &hidden), ip("192.0.2.60"));
+
+        // Без заголовков - адрес соединения
+        assert_eq!(chained.real_ip(ip("10.0.0.5"), &request(&[])), ip("10.0.0.5"));
+        // Без real_ip_from заголовки не используются
+        assert_eq!(resolver(&["10.0.0.0/8"]).real_ip(ip("10.0.0.5"), &chain), ip("10.0.0.5"));
+    }
+
+    #[test]
+    fn test_cidr_parsing() {
+        assert!(Cidr::parse("192.168.1.10").unwrap().contains("192.168.1.10".parse().unwrap()));
+        assert!(!Cidr::parse("192.168.1.10").unwrap().contains("192.168.1.11".parse().unwrap()));
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains("8.8.8.8".parse().unwrap()));
+        assert!(!Cidr::parse("10.0.0.0/8").unwrap().contains("2001:db8::1".parse().unwrap()));
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("not-an-ip/8").is_err());
+        assert!(SchemeResolver::from_config(&ProxyHeadersConfig {
+            trusted_proxies: vec!["10.0.0.0/x".to_string()],
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+
+/// Маршрут, запрашиваемый в режиме --smoke-test
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmokeTarget {
+    pub host: String,
+    pub path: String,
+}
+
+/// Свободный порт на loopback для listener режима --smoke-test
+pub fn ephemeral_addr() -> std::io::Result<SocketAddr> {
+    TcpListener::bind("127.0.0.1:0")?.local_addr()
+}
+
+/// Маршруты для проверки: статическая страница прокси и health location
+/// из nginx конфигурации (путь содержит health)
+pub fn smoke_targets(config: &Config) -> Vec<SmokeTarget> {
+    let mut targets = Vec::new();
+    if config.pipeline.stages.iter().any(|stage| stage == "static") {
+        targets.push(SmokeTarget { host: "localhost".to_string(), path: "/".to_string() });
+    }
+
+    let servers = config.nginx_config.iter().flat_map(|nginx| &nginx.servers);
+    for server in servers {
+        let host = server
+            .server_names
+            .iter()
+            .find(|name| !name.contains('*') && name.as_str() != "_")
+            .cloned()
+            .unwrap_or_else(|| "localhost".to_string());
+        for location in server.locations.iter().filter(|l| l.path.contains("health")) {
+            let target = SmokeTarget { host: host.clone(), path: location.path.clone() };
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+    }
+    targets
+}
+
+/// Ждет, пока listener начнет принимать соединения
+pub fn wait_for_listener(addr: SocketAddr, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if TcpStream::connect_timeout(&addr, Duration::from_millis(200)).is_ok() {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    false
+}
+
+/// GET запрос к маршруту, возвращает статус ответа
+pub fn probe(addr: SocketAddr, target: &SmokeTarget, timeout: Duration) -> Result<u16, String> {
+    let mut stream = TcpStream::connect_timeout(&addr, timeout).map_err(|e| e.to_string())?;
+    stream.set_read_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    stream.set_write_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: adq-pingora-smoke-test\r\nConnection: close\r\n\r\n",
+        target.path, target.host
+    );
+    stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+
+    let mut response = Vec::new();
+    let mut buf = [0u8; 1024];
+    // Достаточно строки статуса
+    while !response.contains(&b'\n') {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => response.extend_from_slice(&buf[..n]),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    let status_line = String::from_utf8_lossy(&response);
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| format!("invalid response: {}", status_line.lines().next().unwrap_or_default()))
+}
+
+/// Проверяет маршруты и печатает результат каждого; true, если все ответили 2xx
+pub fn run_smoke_test(addr: SocketAddr, targets: &[SmokeTarget], timeout: Duration) -> bool {
+    if !wait_for_listener(addr, timeout) {
+        println!("adq-pingora: [error] listener {} did not start within {:?}", addr, timeout);
+        return false;
+    }
+
+    let mut failed = 0;
+    for target in targets {
+        match probe(addr, target, timeout) {
+            Ok(status) if (200..300).contains(&status) => {
+                println!("adq-pingora: smoke test GET http://{}{} ... ok ({})", target.host, target.path, status);
+            }
+            Ok(status) => {
+                println!("adq-pingora: [error] smoke test GET http://{}{} ... failed ({})", target.host, target.path, status);
+                failed += 1;
+            }
+            Err(e) => {
+                println!("adq-pingora: [error] smoke test GET http://{}{} ... failed: {}", target.host, target.path, e);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed == 0 {
+        println!("adq-pingora: smoke test passed ({} route(s))", targets.len());
+    } else {
+        println!("adq-pingora: smoke test failed: {} of {} route(s)", failed, targets.len());
+    }
+    failed == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NginxConfig;
+
+    /// HTTP сервер, отвечающий на каждое соединение заданным статусом
+    fn serve(status: &'static str) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf);
+                let _ = write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_smoke_targets() {
+        assert_eq!(
+            smoke_targets(&Config::default()),
+            vec![SmokeTarget { host: "localhost".to_string(), path: "/".to_string() }]
+        );
+
+        let mut config = Config::default();
+        config.nginx_config = Some(
+            NginxConfig::parse_config_content(
+                "server { listen 80; server_name api.ad-quest.ru; location /health { proxy_pass api; } location /api { proxy_pass api; } }",
+            )
+            .unwrap(),
+        );
+        let targets = smoke_targets(&config);
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[1], SmokeTarget { host: "api.ad-quest.ru".to_string(), path: "/health".to_string() });
+    }
+
+    #[test]
+    fn test_smoke_test_result() {
+        let targets = [SmokeTarget { host: "localhost".to_string(), path: "/".to_string() }];
+        let timeout = Duration::from_secs(2);
+
+        let ok = serve("200 OK");
+        assert_eq!(probe(ok, &targets[0], timeout), Ok(200));
+        assert!(run_smoke_test(ok, &targets, timeout));
+
+        let failing = serve("502 Bad Gateway");
+        assert!(!run_smoke_test(failing, &targets, timeout));
+
+        // Никто не слушает порт
+        let closed = ephemeral_addr().unwrap();
+        assert!(!run_smoke_test(closed, &targets, Duration::from_millis(300)));
+    }
+}
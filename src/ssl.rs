@@ -4,11 +4,15 @@ use pingora_core::services::listening::Service;
 use pingora_proxy::HttpProxy;
 use pingora_core::protocols::tls::TlsRef;
 use pingora_core::tls::ssl::{NameType, SslFiletype};
+use openssl::pkey::PKey;
+use openssl::x509::X509;
 use log::info;
 use std::path::Path;
 use std::collections::HashMap;
 use async_trait::async_trait;
 
+use crate::error::ProxyError;
+
 /// Структура для управления несколькими SSL сертификатами
 pub struct MultiCertManager {
     certificates: HashMap<String, (String, String)>, // domain -> (cert_path, key_path)
@@ -91,30 +95,51 @@ pub fn configure_ssl(proxy_service: &mut Service<HttpProxy<crate::proxy::AdQuest
     
     // Настраиваем TLS с callback для динамического выбора сертификатов
     if let (Some(default_cert), Some(default_key)) = (default_cert_path, default_key_path) {
-        match TlsSettings::with_callbacks(Box::new(cert_manager)) {
-            Ok(mut tls_settings) => {
-                tls_settings.enable_h2();
-                
-                // Устанавливаем default сертификат (будет использован если SNI не совпадает)
-                if let Err(e) = tls_settings.set_certificate_chain_file(default_cert) {
-                    info!("Failed to set default certificate: {}", e);
-                    return;
-                }
-                if let Err(e) = tls_settings.set_private_key_file(default_key, SslFiletype::PEM) {
-                    info!("Failed to set default private key: {}", e);
-                    return;
-                }
-                
+        match tls_settings(cert_manager, default_cert, default_key) {
+            Ok(tls_settings) => {
                 proxy_service.add_tls_with_settings("0.0.0.0:443", None, tls_settings);
                 info!("HTTPS enabled on port 443 with multi-domain certificate support");
                 info!("Default certificate: {}", default_cert);
                 info!("Supported domains: auth.ad-quest.ru, api.ad-quest.ru");
             }
-            Err(e) => {
-                info!("Failed to create TLS settings with callbacks: {}", e);
-            }
+            Err(e) => info!("HTTPS disabled: {}", e),
         }
     } else {
         info!("No valid TLS certificates found, HTTPS disabled");
     }
+}
+
+/// Загружает цепочку сертификатов и приватный ключ так, как их загрузит listener,
+/// и проверяет, что ключ соответствует первому сертификату цепочки
+pub fn check_certificate_pair(cert_path: &str, key_path: &str) -> Result<(), String> {
+    let cert_pem = std::fs::read(cert_path).map_err(|e| format!("cannot read SSL certificate {}: {}", cert_path, e))?;
+    let key_pem = std::fs::read(key_path).map_err(|e| format!("cannot read SSL private key {}: {}", key_path, e))?;
+    let chain = X509::stack_from_pem(&cert_pem)
+        .map_err(|e| format!("cannot load SSL certificate {}: {}", cert_path, e))?;
+    let cert = chain
+        .first()
+        .ok_or_else(|| format!("cannot load SSL certificate {}: no PEM certificates found", cert_path))?;
+    let key = PKey::private_key_from_pem(&key_pem)
+        .map_err(|e| format!("cannot load SSL private key {}: {}", key_path, e))?;
+    let public_key = cert
+        .public_key()
+        .map_err(|e| format!("cannot read public key of SSL certificate {}: {}", cert_path, e))?;
+    if !public_key.public_eq(&key) {
+        return Err(format!("SSL private key {} does not match certificate {}", key_path, cert_path));
+    }
+    Ok(())
+}
+
+/// TLS с выбором сертификата по SNI и default сертификатом (используется, если SNI не совпадает)
+fn tls_settings(cert_manager: MultiCertManager, default_cert: &str, default_key: &str) -> Result<TlsSettings, ProxyError> {
+    let mut tls_settings = TlsSettings::with_callbacks(Box::new(cert_manager))
+        .map_err(|e| ProxyError::Tls(format!("failed to create TLS settings with callbacks: {}", e)))?;
+    tls_settings.enable_h2();
+    tls_settings
+        .set_certificate_chain_file(default_cert)
+        .map_err(|e| ProxyError::Tls(format!("failed to set default certificate {}: {}", default_cert, e)))?;
+    tls_settings
+        .set_private_key_file(default_key, SslFiletype::PEM)
+        .map_err(|e| ProxyError::Tls(format!("failed to set default private key {}: {}", default_key, e)))?;
+    Ok(tls_settings)
 }
\ No newline at end of file
@@ -0,0 +1,132 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use log::debug;
+use pingora::prelude::*;
+use std::sync::Arc;
+
+use super::{RequestStage, StageResult};
+use crate::challenge::{Challenge, Verdict};
+use crate::config::Config;
+use crate::experiments::request_cookie;
+use crate::local_response::ResponseBuilder;
+use crate::metrics::CHALLENGES;
+use crate::routing::request_host;
+use crate::types::RequestContext;
+
+/// Proof-of-work проверка клиентов location с challenge_mode: без действующей cookie
+/// клиент получает 503 страницу со скриптом, который ее устанавливает
+pub struct ChallengeStage {
+    config: Arc<Config>,
+    challenge: Arc<Challenge>,
+}
+
+impl ChallengeStage {
+    pub fn new(config: Arc<Config>, challenge: Arc<Challenge>) -> Self {
+        Self { config, challenge }
+    }
+}
+
+#[async_trait]
+impl RequestStage for ChallengeStage {
+    fn name(&self) -> &'static str {
+        "challenge"
+    }
+
+    async fn handle(&self, session: &mut Session, ctx: &mut RequestContext) -> Result<StageResult> {
+        let enabled = self
+            .config
+            .find_server(request_host(session))
+            .and_then(|server| self.config.find_location(server, session.req_header().uri.path()))
+            .is_some_and(|location| self.challenge.is_enabled(location));
+        if !enabled {
+            return Ok(StageResult::Continue);
+        }
+
+        let ip = ctx.client_ip;
+        let request = session.req_header();
+        let user_agent = request.headers.get("user-agent").and_then(|v| v.to_str().ok());
+        if self.challenge.is_exempt(ip, user_agent) {
+            return Ok(StageResult::Continue);
+        }
+
+        match self.challenge.verify(request_cookie(request, self.challenge.cookie_name()), ip) {
+            Verdict::Valid => {
+                CHALLENGES.with_label_values(&["passed"]).inc();
+                return Ok(StageResult::Continue);
+            }
+            Verdict::Missing => {}
+            verdict => {
+                CHALLENGES.with_label_values(&["failed"]).inc();
+                debug!("Challenge cookie rejected for {:?}: {:?}", ip, verdict);
+            }
+        }
+
+        CHALLENGES.with_label_values(&["issued"]).inc();
+        ctx.handle_locally("challenge");
+        let page = self.challenge.page(&self.challenge.issue(ip));
+        ResponseBuilder::html(503)
+            .header("Cache-Control", "no-store")
+            .send(session, Bytes::from(page), true)
+            .await?;
+        Ok(StageResult::Respond)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NginxConfig;
+    use crate::stages::test_session;
+
+    fn stage() -> ChallengeStage {
+        let mut config = Config::default();
+        config.challenge.difficulty = 4;
+        config.challenge.allow_user_agents = vec!["UptimeRobot".to_string()];
+        config.nginx_config = Some(
+            NginxConfig::parse_config_content(
+                "server { listen 80; server_name shop.example.com; \
+                 location /catalog/ { proxy_pass api; challenge_mode on; } location /api/ { proxy_pass api; } }",
+            )
+            .unwrap(),
+        );
+        let challenge = Arc::new(Challenge::from_config(&config).unwrap());
+        ChallengeStage::new(Arc::new(config), challenge)
+    }
+
+    async fn handle(stage: &ChallengeStage, request: &str) -> (StageResult, RequestContext) {
+        let mut session = test_session(request).await;
+        let mut ctx = RequestContext::new();
+        let result = stage.handle(&mut session, &mut ctx).await.unwrap();
+        (result, ctx)
+    }
+
+    #[tokio::test]
+    async fn test_passes_without_challenge_mode_and_exempt() {
+        let stage = stage();
+        let (result, ctx) = handle(&stage, "GET /api/orders HTTP/1.1\r\nHost: shop.example.com\r\n\r\n").await;
+        assert_eq!(result, StageResult::Continue);
+        assert_eq!(ctx.local_route, None);
+
+        let request = "GET /catalog/ HTTP/1.1\r\nHost: shop.example.com\r\nUser-Agent: UptimeRobot/2.0\r\n\r\n";
+        assert_eq!(handle(&stage, request).await.0, StageResult::Continue);
+    }
+
+    #[tokio::test]
+    async fn test_valid_cookie_passes() {
+        let stage = stage();
+        // Тестовая сессия без адреса клиента: cookie привязана к "unknown"
+        let cookie = stage.challenge.solve(&stage.challenge.issue(None));
+        let request = format!(
+            "GET /catalog/items HTTP/1.1\r\nHost: shop.example.com\r\nCookie: theme=dark; adq_challenge={}\r\n\r\n",
+            cookie
+        );
+        let (result, ctx) = handle(&stage, &request).await;
+        assert_eq!(result, StageResult::Continue);
+        assert_eq!(ctx.local_route, None);
+
+        // Выключенная через admin API проверка пропускает запросы без cookie
+        stage.challenge.set_mode("/catalog/", Some(false)).unwrap();
+        let request = "GET /catalog/items HTTP/1.1\r\nHost: shop.example.com\r\n\r\n";
+        assert_eq!(handle(&stage, request).await.0, StageResult::Continue);
+    }
+}
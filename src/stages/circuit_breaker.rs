@@ -0,0 +1,60 @@
+use async_trait::async_trait;
+use pingora::prelude::*;
+use std::sync::Arc;
+
+use super::{RequestStage, StageResult};
+use crate::circuit_breaker::{send_fallback, Admission, CircuitBreaker};
+use crate::config::{Config, FallbackCondition};
+use crate::fallback::{record_fallback, send_fallback_response, FallbackResponses};
+use crate::routing::request_host;
+use crate::types::RequestContext;
+
+/// При открытом circuit отдает fallback ответ вместо проксирования.
+/// Заглушка location (fallback_response с circuit_open) важнее общего fallback.
+pub struct CircuitBreakerStage {
+    circuit_breaker: Arc<CircuitBreaker>,
+    config: Arc<Config>,
+    fallbacks: Arc<FallbackResponses>,
+}
+
+impl CircuitBreakerStage {
+    pub fn new(circuit_breaker: Arc<CircuitBreaker>, config: Arc<Config>, fallbacks: Arc<FallbackResponses>) -> Self {
+        Self { circuit_breaker, config, fallbacks }
+    }
+}
+
+#[async_trait]
+impl RequestStage for CircuitBreakerStage {
+    fn name(&self) -> &'static str {
+        "circuit_breaker"
+    }
+
+    async fn handle(&self, session: &mut Session, ctx: &mut RequestContext) -> Result<StageResult> {
+        let upstream = ctx.upstream_label().to_string();
+        let admission = self.circuit_breaker.admit(&upstream).await;
+        if let Admission::Probe(token) = admission {
+            // Слот пробы освобождается результатом запроса или в logging()
+            ctx.circuit_probe = Some(token);
+        } else if admission == Admission::Rejected {
+            let retry_after = self.circuit_breaker.retry_after(&upstream).await;
+            let state = self.circuit_breaker.get_state(&upstream).await;
+            ctx.handle_locally("circuit_open");
+
+            let location = self
+                .config
+                .find_server(request_host(session))
+                .and_then(|server| self.config.find_location(server, session.req_header().uri.path()));
+            if let Some(location) = location {
+                if let Some((fallback, condition, body)) = self.fallbacks.find(location, &[FallbackCondition::CircuitOpen]) {
+                    record_fallback(location, condition);
+                    send_fallback_response(session, fallback, body).await?;
+                    return Ok(StageResult::Respond);
+                }
+            }
+
+            send_fallback(session, ctx, self.config.circuit_breaker.fallback.as_ref(), retry_after, &state).await?;
+            return Ok(StageResult::Respond);
+        }
+        Ok(StageResult::Continue)
+    }
+}
@@ -0,0 +1,58 @@
+use async_trait::async_trait;
+use log::info;
+use pingora::prelude::*;
+use std::sync::Arc;
+
+use super::{RequestStage, StageResult};
+use crate::concurrency::UpstreamLimits;
+use crate::error_response::{ErrorCode, ErrorResponse};
+use crate::types::{RequestContext, UpstreamTarget};
+
+/// Занимает слот upstream с max_conns; при переполненной очереди или по таймауту - 503 с Retry-After
+pub struct ConcurrencyStage {
+    limits: Arc<UpstreamLimits>,
+}
+
+impl ConcurrencyStage {
+    pub fn new(limits: Arc<UpstreamLimits>) -> Self {
+        Self { limits }
+    }
+}
+
+#[async_trait]
+impl RequestStage for ConcurrencyStage {
+    fn name(&self) -> &'static str {
+        "concurrency"
+    }
+
+    async fn handle(&self, session: &mut Session, ctx: &mut RequestContext) -> Result<StageResult> {
+        let UpstreamTarget::Named(upstream) = &ctx.upstream_target else {
+            return Ok(StageResult::Continue);
+        };
+        let Some(limiter) = self.limits.get(upstream) else {
+            return Ok(StageResult::Continue);
+        };
+
+        match limiter.acquire().await {
+            Ok(permit) => {
+                if !permit.waited().is_zero() {
+                    ctx.upstream_queue_wait = Some(permit.waited());
+                }
+                ctx.upstream_permit = Some(permit);
+                Ok(StageResult::Continue)
+            }
+            Err(rejection) => {
+                let upstream = upstream.clone();
+                let retry_after = limiter.retry_after();
+                info!("Upstream '{}' is at max_conns, request rejected: {}", upstream, rejection.as_str());
+                ctx.handle_locally("concurrency");
+                ErrorResponse::new(ErrorCode::UpstreamBusy)
+                    .upstream(upstream)
+                    .retry_after(retry_after)
+                    .send(session, ctx)
+                    .await?;
+                Ok(StageResult::Reject)
+            }
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use async_trait::async_trait;
+use pingora::prelude::*;
+
+use super::{RequestStage, StageResult};
+use crate::cors::handle_cors_preflight;
+use crate::types::RequestContext;
+
+/// Ответ на CORS preflight (OPTIONS) запросы
+pub struct CorsStage;
+
+#[async_trait]
+impl RequestStage for CorsStage {
+    fn name(&self) -> &'static str {
+        "cors"
+    }
+
+    async fn handle(&self, session: &mut Session, ctx: &mut RequestContext) -> Result<StageResult> {
+        let uri = session.req_header().uri.path().to_string();
+        if handle_cors_preflight(session, &uri).await? {
+            ctx.handle_locally("cors_preflight");
+            return Ok(StageResult::Respond);
+        }
+        Ok(StageResult::Continue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stages::test_session;
+
+    #[tokio::test]
+    async fn test_non_preflight_request_continues() {
+        let mut session = test_session("GET /api/users HTTP/1.1\r\nHost: api.ad-quest.ru\r\n\r\n").await;
+        let mut ctx = RequestContext::new();
+
+        assert_eq!(CorsStage.handle(&mut session, &mut ctx).await.unwrap(), StageResult::Continue);
+        assert!(ctx.local_route.is_none());
+    }
+}
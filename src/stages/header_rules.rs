@@ -0,0 +1,93 @@
+use async_trait::async_trait;
+use log::info;
+use pingora::prelude::*;
+use std::sync::Arc;
+
+use super::{close_connection, RequestStage, StageResult, CLOSE_CONNECTION};
+use crate::local_response::ResponseBuilder;
+use crate::filter::HeaderRules;
+use crate::routing::request_host;
+use crate::types::RequestContext;
+
+/// Ответ по правилам заголовков запроса (принудительное обновление клиентов)
+pub struct HeaderRulesStage {
+    rules: Arc<HeaderRules>,
+}
+
+impl HeaderRulesStage {
+    pub fn new(rules: Arc<HeaderRules>) -> Self {
+        Self { rules }
+    }
+}
+
+#[async_trait]
+impl RequestStage for HeaderRulesStage {
+    fn name(&self) -> &'static str {
+        "header_rules"
+    }
+
+    async fn handle(&self, session: &mut Session, ctx: &mut RequestContext) -> Result<StageResult> {
+        let host = request_host(session);
+        let host_without_port = host.split(':').next().unwrap_or(host);
+        let headers = &session.req_header().headers;
+
+        let Some(rule) = self.rules.evaluate(
+            |name| headers.get(name).and_then(|v| v.to_str().ok()),
+            host_without_port,
+            session.req_header().uri.path(),
+        ) else {
+            return Ok(StageResult::Continue);
+        };
+
+        if rule.status == CLOSE_CONNECTION {
+            info!("Connection closed by header rule '{}'", rule.name);
+            return Ok(close_connection(session, ctx, "header_rule"));
+        }
+
+        info!("Request answered by header rule '{}' with status {}", rule.name, rule.status);
+        ctx.handle_locally("header_rule");
+
+        ResponseBuilder::json(rule.status)
+            .cors()
+            .send(session, rule.body.clone(), false)
+            .await?;
+        Ok(StageResult::Respond)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HeaderRuleConfig;
+    use crate::stages::test_session;
+
+    #[tokio::test]
+    async fn test_return_444_closes_without_response() {
+        let rules = HeaderRules::from_config(&[HeaderRuleConfig {
+            name: "abusive_client".to_string(),
+            header: "X-Client".to_string(),
+            equals: Some("scraper".to_string()),
+            version_below: None,
+            match_missing: false,
+            servers: Vec::new(),
+            locations: Vec::new(),
+            status: 444,
+            body: serde_json::Value::Null,
+            schedule: None,
+        }])
+        .unwrap();
+        let stage = HeaderRulesStage::new(Arc::new(rules));
+
+        let mut session = test_session("GET / HTTP/1.1\r\nHost: api.ad-quest.ru\r\nX-Client: scraper\r\n\r\n").await;
+        let mut ctx = RequestContext::new();
+        assert_eq!(stage.handle(&mut session, &mut ctx).await.unwrap(), StageResult::Reject);
+        assert!(session.response_written().is_none());
+        assert!(ctx.connection_closed);
+        assert_eq!(ctx.local_route, Some("header_rule"));
+
+        let mut session = test_session("GET / HTTP/1.1\r\nHost: api.ad-quest.ru\r\nX-Client: browser\r\n\r\n").await;
+        let mut ctx = RequestContext::new();
+        assert_eq!(stage.handle(&mut session, &mut ctx).await.unwrap(), StageResult::Continue);
+        assert!(!ctx.connection_closed);
+    }
+}
@@ -0,0 +1,173 @@
+use async_trait::async_trait;
+use log::info;
+use pingora::http::ResponseHeader;
+use pingora::prelude::*;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{RequestStage, StageResult};
+use crate::backend_profile::add_response_headers;
+use crate::config::Config;
+use crate::error_response::{ErrorCode, ErrorResponse};
+use crate::idempotency::{wait_for_completion, IdempotencyStore, Lookup, StoredResponse};
+use crate::metrics::IDEMPOTENCY_REQUESTS;
+use crate::request_id::add_request_id_header;
+use crate::routing::request_host;
+use crate::types::RequestContext;
+
+/// Дедупликация запросов с ключом идемпотентности в location с директивой idempotency
+pub struct IdempotencyStage {
+    config: Arc<Config>,
+    store: Arc<IdempotencyStore>,
+}
+
+impl IdempotencyStage {
+    pub fn new(config: Arc<Config>, store: Arc<IdempotencyStore>) -> Self {
+        Self { config, store }
+    }
+
+    /// Отправляет сохраненный ответ с заголовком Idempotent-Replayed.
+    /// Для tombstone отправляются только статус и заголовки с пустым телом
+    async fn replay(&self, session: &mut Session, ctx: &RequestContext, stored: &StoredResponse) -> Result<()> {
+        let mut response = ResponseHeader::build(stored.status, None)?;
+        for (name, value) in &stored.headers {
+            response.append_header(name.clone(), value.as_slice())?;
+        }
+        let length = stored.body.as_ref().map_or(0, |body| body.len());
+        response.insert_header("Content-Length", length.to_string())?;
+        response.insert_header("Idempotent-Replayed", "true")?;
+        let profile = ctx.backend_profile.as_deref().and_then(|name| self.config.backend_profiles.get(name));
+        add_response_headers(session.req_header(), &mut response, profile)?;
+        add_request_id_header(&mut response, &self.config.proxy_headers.request_id, ctx)?;
+
+        session.write_response_header(Box::new(response), stored.body.is_none()).await?;
+        if let Some(body) = &stored.body {
+            session.write_response_body(Some(body.clone()), true).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RequestStage for IdempotencyStage {
+    fn name(&self) -> &'static str {
+        "idempotency"
+    }
+
+    async fn handle(&self, session: &mut Session, ctx: &mut RequestContext) -> Result<StageResult> {
+        // Безопасные методы не дедуплицируются
+        let method = session.req_header().method.as_str().to_string();
+        if matches!(method.as_str(), "GET" | "HEAD" | "OPTIONS") {
+            return Ok(StageResult::Continue);
+        }
+
+        let host = request_host(session).to_string();
+        let path = session.req_header().uri.path().to_string();
+        let Some(location) = self
+            .config
+            .find_server(&host)
+            .and_then(|server| self.config.find_location(server, &path))
+        else {
+            return Ok(StageResult::Continue);
+        };
+        let Some(ttl) = location.idempotency else {
+            return Ok(StageResult::Continue);
+        };
+        let Some(idempotency_key) = session
+            .req_header()
+            .headers
+            .get(self.config.idempotency.header.as_str())
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty())
+        else {
+            return Ok(StageResult::Continue);
+        };
+
+        let key = IdempotencyStore::key(idempotency_key, &method, &host, &path);
+        let wait = self.config.idempotency.in_flight == "wait";
+        let deadline = Instant::now() + Duration::from_secs(self.config.idempotency.wait_timeout);
+
+        loop {
+            match self.store.begin(key.clone(), ttl, location.idempotency_max_body) {
+                Lookup::Proceed(guard) => {
+                    ctx.idempotency = Some(guard);
+                    return Ok(StageResult::Continue);
+                }
+                Lookup::Replay(stored) => {
+                    info!("Replaying stored response for idempotency key on {} {}", method, path);
+                    IDEMPOTENCY_REQUESTS.with_label_values(&["replayed"]).inc();
+                    ctx.handle_locally("idempotency_replay");
+                    self.replay(session, ctx, &stored).await?;
+                    return Ok(StageResult::Respond);
+                }
+                Lookup::InFlight(done) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if wait && !remaining.is_zero() && wait_for_completion(done, remaining).await {
+                        // Исходный запрос завершился: его ответ сохранен или ключ освобожден
+                        continue;
+                    }
+                    IDEMPOTENCY_REQUESTS.with_label_values(&["conflict"]).inc();
+                    ctx.handle_locally("idempotency_conflict");
+                    ErrorResponse::new(ErrorCode::IdempotencyConflict)
+                        .retry_after(1)
+                        .send(session, ctx)
+                        .await?;
+                    return Ok(StageResult::Reject);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NginxConfig;
+    use crate::stages::test_session;
+
+    fn config() -> Arc<Config> {
+        let mut config = Config::default();
+        config.nginx_config = Some(
+            NginxConfig::parse_config_content(
+                r#"
+                server {
+                    listen 80;
+                    server_name api.ad-quest.ru;
+                    location /billing {
+                        proxy_pass billing;
+                        idempotency 1h;
+                    }
+                }
+                "#,
+            )
+            .unwrap(),
+        );
+        Arc::new(config)
+    }
+
+    #[tokio::test]
+    async fn test_registers_key_for_configured_location() {
+        let store = Arc::new(IdempotencyStore::new(1024 * 1024));
+        let stage = IdempotencyStage::new(config(), store.clone());
+
+        let mut session = test_session("POST /billing/charge HTTP/1.1\r\nHost: api.ad-quest.ru\r\nIdempotency-Key: k1\r\nContent-Length: 0\r\n\r\n").await;
+        let mut ctx = RequestContext::new();
+        assert_eq!(stage.handle(&mut session, &mut ctx).await.unwrap(), StageResult::Continue);
+        assert!(ctx.idempotency.is_some());
+
+        // Повтор с тем же ключом видит выполняющийся исходный запрос
+        let key = IdempotencyStore::key("k1", "POST", "api.ad-quest.ru", "/billing/charge");
+        assert!(matches!(store.begin(key, Duration::from_secs(60), 1024), Lookup::InFlight(_)));
+
+        // Без ключа и для GET запросы проходят как обычно
+        for request in [
+            "POST /billing/charge HTTP/1.1\r\nHost: api.ad-quest.ru\r\nContent-Length: 0\r\n\r\n",
+            "GET /billing/charge HTTP/1.1\r\nHost: api.ad-quest.ru\r\nIdempotency-Key: k2\r\n\r\n",
+        ] {
+            let mut session = test_session(request).await;
+            let mut ctx = RequestContext::new();
+            assert_eq!(stage.handle(&mut session, &mut ctx).await.unwrap(), StageResult::Continue);
+            assert!(ctx.idempotency.is_none());
+        }
+    }
+}
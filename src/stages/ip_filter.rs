@@ -0,0 +1,43 @@
+use async_trait::async_trait;
+use pingora::prelude::*;
+use std::sync::Arc;
+
+use super::{close_connection, RequestStage, StageResult};
+use crate::error_response::{ErrorCode, ErrorResponse};
+use crate::filter::IPFilter;
+use crate::types::RequestContext;
+
+/// Проверка клиента по blacklist/whitelist
+pub struct IpFilterStage {
+    ip_filter: Arc<IPFilter>,
+    /// Закрывать соединение без ответа вместо 403 (deny_status: 444)
+    close_connection: bool,
+}
+
+impl IpFilterStage {
+    pub fn new(ip_filter: Arc<IPFilter>, close_connection: bool) -> Self {
+        Self { ip_filter, close_connection }
+    }
+}
+
+#[async_trait]
+impl RequestStage for IpFilterStage {
+    fn name(&self) -> &'static str {
+        "ip_filter"
+    }
+
+    async fn handle(&self, session: &mut Session, ctx: &mut RequestContext) -> Result<StageResult> {
+        if let Some(ip) = ctx.client_ip {
+            if self.ip_filter.should_block_ip(ip).await {
+                if self.close_connection {
+                    return Ok(close_connection(session, ctx, "ip_filter"));
+                }
+                // IP заблокирован, возвращаем 403 Forbidden
+                ctx.handle_locally("ip_filter");
+                ErrorResponse::new(ErrorCode::IpBlocked).send(session, ctx).await?;
+                return Ok(StageResult::Reject);
+            }
+        }
+        Ok(StageResult::Continue)
+    }
+}
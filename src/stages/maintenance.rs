@@ -0,0 +1,116 @@
+use async_trait::async_trait;
+use log::info;
+use pingora::prelude::*;
+use std::sync::Arc;
+
+use super::{RequestStage, StageResult};
+use crate::config::MaintenanceConfig;
+use crate::error_response::{ErrorCode, ErrorResponse};
+use crate::routing::request_host;
+use crate::schedules::Schedules;
+use crate::types::RequestContext;
+
+/// Retry-After, если конец окна определить не удалось
+const DEFAULT_RETRY_AFTER: u64 = 60;
+
+/// Ответ 503 о техническом обслуживании в окна расписаний (секция maintenance)
+pub struct MaintenanceStage {
+    windows: Vec<MaintenanceConfig>,
+    schedules: Arc<Schedules>,
+}
+
+impl MaintenanceStage {
+    pub fn new(windows: Vec<MaintenanceConfig>, schedules: Arc<Schedules>) -> Self {
+        Self { windows, schedules }
+    }
+
+    /// Действующее окно обслуживания для server name и пути запроса
+    fn active(&self, host: &str, path: &str) -> Option<&MaintenanceConfig> {
+        self.windows.iter().find(|window| {
+            self.schedules.is_active(&window.schedule)
+                && (window.servers.is_empty() || window.servers.iter().any(|server| server == host))
+                && (window.locations.is_empty() || window.locations.iter().any(|prefix| path.starts_with(prefix.as_str())))
+        })
+    }
+}
+
+#[async_trait]
+impl RequestStage for MaintenanceStage {
+    fn name(&self) -> &'static str {
+        "maintenance"
+    }
+
+    async fn handle(&self, session: &mut Session, ctx: &mut RequestContext) -> Result<StageResult> {
+        let host = request_host(session);
+        let host = host.split(':').next().unwrap_or(host);
+        let Some(window) = self.active(host, session.req_header().uri.path()) else {
+            return Ok(StageResult::Continue);
+        };
+
+        // Клиенту сообщается, когда окно закончится
+        let retry_after = self
+            .schedules
+            .remaining(&window.schedule)
+            .map_or(DEFAULT_RETRY_AFTER, |left| left.as_secs_f64().ceil().max(1.0) as u64);
+        info!("Request answered by maintenance '{}', retry after {}s", window.name, retry_after);
+        ctx.handle_locally("maintenance");
+        ErrorResponse::new(ErrorCode::ServiceUnavailable)
+            .message(window.message.clone())
+            .retry_after(retry_after)
+            .send(session, ctx)
+            .await?;
+        Ok(StageResult::Reject)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::config::ScheduleConfig;
+    use chrono::NaiveDateTime;
+
+    fn stage(now: &str) -> MaintenanceStage {
+        let now = NaiveDateTime::parse_from_str(now, "%Y-%m-%d %H:%M:%S").unwrap().and_utc();
+        let schedules = Schedules::from_config(&[ScheduleConfig {
+            name: "partner_sunday".to_string(),
+            days: vec!["sun".to_string()],
+            start: "02:00".to_string(),
+            end: "03:00".to_string(),
+            timezone: "UTC".to_string(),
+        }])
+        .unwrap()
+        .with_clock(Arc::new(MockClock::at(now)));
+        MaintenanceStage::new(
+            vec![MaintenanceConfig {
+                name: "partner_api".to_string(),
+                schedule: "partner_sunday".to_string(),
+                servers: vec!["partners.ad-quest.ru".to_string()],
+                locations: vec!["/api/".to_string()],
+                message: "Partner API maintenance".to_string(),
+            }],
+            Arc::new(schedules),
+        )
+    }
+
+    #[test]
+    fn test_maintenance_only_inside_window() {
+        let sunday = stage("2026-10-18 02:30:00");
+        assert_eq!(sunday.active("partners.ad-quest.ru", "/api/orders").map(|w| w.name.as_str()), Some("partner_api"));
+        assert!(sunday.active("api.ad-quest.ru", "/api/orders").is_none());
+        assert!(sunday.active("partners.ad-quest.ru", "/health").is_none());
+        assert_eq!(sunday.schedules.remaining("partner_sunday").unwrap().as_secs(), 30 * 60);
+
+        assert!(stage("2026-10-18 03:00:00").active("partners.ad-quest.ru", "/api/orders").is_none());
+        assert!(stage("2026-10-17 02:30:00").active("partners.ad-quest.ru", "/api/orders").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_outside_window_continues() {
+        let stage = stage("2026-10-18 04:00:00");
+        let mut session = crate::stages::test_session("GET /api/orders HTTP/1.1\r\nHost: partners.ad-quest.ru\r\n\r\n").await;
+        let mut ctx = RequestContext::new();
+        assert_eq!(stage.handle(&mut session, &mut ctx).await.unwrap(), StageResult::Continue);
+        assert!(ctx.local_route.is_none());
+    }
+}
@@ -0,0 +1,305 @@
+use async_trait::async_trait;
+use log::{debug, warn};
+use pingora::prelude::*;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::challenge::Challenge;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::concurrency::UpstreamLimits;
+use crate::config::{parse_size, Config};
+use crate::fallback::FallbackResponses;
+use crate::filter::{HeaderRules, IPFilter, UaFilter};
+use crate::idempotency::IdempotencyStore;
+use crate::metrics::REQUEST_STAGE_DURATION;
+use crate::schedules::Schedules;
+use crate::types::RequestContext;
+use crate::wasm_filter::{self as wasm, WasmFilters};
+
+mod challenge;
+mod circuit_breaker;
+mod concurrency;
+mod cors;
+mod header_rules;
+mod idempotency;
+mod ip_filter;
+mod maintenance;
+mod rate_limit;
+mod redirect;
+mod request_log;
+mod routing;
+mod static_page;
+mod ua_filter;
+mod wasm_filter;
+
+pub use challenge::ChallengeStage;
+pub use circuit_breaker::CircuitBreakerStage;
+pub use concurrency::ConcurrencyStage;
+pub use cors::CorsStage;
+pub use header_rules::HeaderRulesStage;
+pub use idempotency::IdempotencyStage;
+pub use ip_filter::IpFilterStage;
+pub use maintenance::MaintenanceStage;
+pub use rate_limit::RateLimitStage;
+pub use redirect::RedirectStage;
+pub use request_log::RequestLogStage;
+pub use routing::RoutingStage;
+pub use static_page::StaticStage;
+pub use ua_filter::UaFilterStage;
+pub use wasm_filter::WasmFilterStage;
+
+/// Результат стадии обработки запроса
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StageResult {
+    /// Передать запрос следующей стадии
+    Continue,
+    /// Ответ сформирован стадией (preflight, редирект, статика, fallback)
+    Respond,
+    /// Запрос отклонен, ответ с ошибкой уже отправлен
+    Reject,
+}
+
+/// Статус nginx `return 444`: соединение закрывается без ответа
+pub const CLOSE_CONNECTION: u16 = 444;
+
+/// Закрывает соединение клиента, не отправляя ни байта (как `return 444` в nginx)
+pub(crate) fn close_connection(session: &mut Session, ctx: &mut RequestContext, route: &'static str) -> StageResult {
+    session.set_keepalive(None);
+    ctx.handle_locally(route);
+    ctx.connection_closed = true;
+    StageResult::Reject
+}
+
+/// Стадия обработки запроса в request_filter
+#[async_trait]
+pub trait RequestStage: Send + Sync {
+    /// Имя стадии (как в pipeline.stages) для метрик
+    fn name(&self) -> &'static str;
+
+    async fn handle(&self, session: &mut Session, ctx: &mut RequestContext) -> Result<StageResult>;
+}
+
+/// Собирает стадии в порядке pipeline.stages.
+/// Стадии выключенных компонентов (IP и User-Agent фильтры, правила заголовков,
+/// circuit breaker, дедупликация без location с idempotency, лимит без upstream с max_conns,
+/// maintenance без окон, WASM фильтры без location с wasm_filter, проверка клиентов
+/// без challenge) пропускаются. Состав списка проверяет Config::validate_pipeline.
+pub fn build_stages(
+    config: &Arc<Config>,
+    ip_filter: Option<Arc<IPFilter>>,
+    challenge: Option<Arc<Challenge>>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    fallbacks: Arc<FallbackResponses>,
+    schedules: Arc<Schedules>,
+) -> Vec<Box<dyn RequestStage>> {
+    let mut stages: Vec<Box<dyn RequestStage>> = Vec::new();
+
+    let ua_filter = if config.ua_filter.enabled {
+        match UaFilter::from_config(&config.ua_filter) {
+            Ok(ua_filter) => Some(Arc::new(ua_filter)),
+            Err(e) => {
+                warn!("User-Agent filter disabled: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let header_rules = match HeaderRules::from_config(&config.header_rules) {
+        Ok(rules) if !rules.is_empty() => Some(Arc::new(rules.with_schedules(schedules.clone()))),
+        Ok(_) => None,
+        Err(e) => {
+            warn!("Header rules disabled: {}", e);
+            None
+        }
+    };
+
+    let idempotency_enabled = config.nginx_config.as_ref().is_some_and(|nginx| {
+        nginx.servers.iter().flat_map(|s| &s.locations).any(|l| l.idempotency.is_some())
+    });
+    let idempotency_store = idempotency_enabled.then(|| {
+        let budget = parse_size(&config.idempotency.budget).unwrap_or_else(|| {
+            warn!("Invalid idempotency budget '{}', using 64m", config.idempotency.budget);
+            64 * 1024 * 1024
+        });
+        Arc::new(IdempotencyStore::new(budget))
+    });
+
+    // Модули фильтров компилируются один раз при загрузке конфигурации
+    let wasm_filters = wasm::is_configured(config).then(|| {
+        let filters = WasmFilters::from_config(config);
+        for (path, e) in filters.errors() {
+            warn!("WASM filter {} not loaded: {}", path, e);
+        }
+        Arc::new(filters)
+    });
+
+    let upstream_limits = Some(Arc::new(UpstreamLimits::from_config(config))).filter(|limits| !limits.is_empty());
+
+    for name in &config.pipeline.stages {
+        let stage: Box<dyn RequestStage> = match name.as_str() {
+            "ip_filter" => match &ip_filter {
+                Some(ip_filter) => Box::new(IpFilterStage::new(
+                    ip_filter.clone(),
+                    config.ip_filter.deny_status == CLOSE_CONNECTION,
+                )),
+                None => continue,
+            },
+            "ua_filter" => match &ua_filter {
+                Some(ua_filter) => Box::new(UaFilterStage::new(ua_filter.clone())),
+                None => continue,
+            },
+            "rate_limit" => Box::new(RateLimitStage::new(config.clone(), schedules.clone())),
+            "request_log" => Box::new(RequestLogStage::new(config.clone())),
+            "cors" => Box::new(CorsStage),
+            "redirect" => Box::new(RedirectStage),
+            "maintenance" if config.maintenance.is_empty() => continue,
+            "maintenance" => Box::new(MaintenanceStage::new(config.maintenance.clone(), schedules.clone())),
+            "header_rules" => match &header_rules {
+                Some(rules) => Box::new(HeaderRulesStage::new(rules.clone())),
+                None => continue,
+            },
+            "wasm_filter" => match &wasm_filters {
+                Some(filters) => Box::new(WasmFilterStage::new(config.clone(), filters.clone())),
+                None => continue,
+            },
+            "challenge" => match &challenge {
+                Some(challenge) => Box::new(ChallengeStage::new(config.clone(), challenge.clone())),
+                None => continue,
+            },
+            "routing" => Box::new(RoutingStage::new(config.clone())),
+            "static" => Box::new(StaticStage::new(config.clone())),
+            "circuit_breaker" => match &circuit_breaker {
+                Some(circuit_breaker) => Box::new(CircuitBreakerStage::new(circuit_breaker.clone(), config.clone(), fallbacks.clone())),
+                None => continue,
+            },
+            "idempotency" => match &idempotency_store {
+                Some(store) => Box::new(IdempotencyStage::new(config.clone(), store.clone())),
+                None => continue,
+            },
+            "concurrency" => match &upstream_limits {
+                Some(limits) => Box::new(ConcurrencyStage::new(limits.clone())),
+                None => continue,
+            },
+            other => {
+                warn!("Unknown pipeline stage '{}' skipped", other);
+                continue;
+            }
+        };
+        stages.push(stage);
+    }
+
+    stages
+}
+
+/// Выполняет стадии по порядку. Возвращает true, если ответ уже отправлен клиенту.
+pub async fn run_stages(
+    stages: &[Box<dyn RequestStage>],
+    session: &mut Session,
+    ctx: &mut RequestContext,
+) -> Result<bool> {
+    for stage in stages {
+        let start = Instant::now();
+        let result = stage.handle(session, ctx).await;
+        REQUEST_STAGE_DURATION
+            .with_label_values(&[stage.name()])
+            .observe(start.elapsed().as_secs_f64());
+
+        match result? {
+            StageResult::Continue => {}
+            StageResult::Respond => return Ok(true),
+            StageResult::Reject => {
+                debug!("Request rejected by {} stage", stage.name());
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Сессия Pingora с уже прочитанным запросом для тестов стадий
+#[cfg(test)]
+pub(crate) async fn test_session(request: &str) -> Session {
+    let stream = tokio_test::io::Builder::new().read(request.as_bytes()).build();
+    let mut session = Session::new_h1(Box::new(stream));
+    session.read_request().await.unwrap();
+    session
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Стадия, записывающая свой вызов и возвращающая заданный результат
+    struct RecordingStage {
+        name: &'static str,
+        result: StageResult,
+        calls: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    #[async_trait]
+    impl RequestStage for RecordingStage {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn handle(&self, _session: &mut Session, _ctx: &mut RequestContext) -> Result<StageResult> {
+            self.calls.lock().unwrap().push(self.name);
+            Ok(self.result)
+        }
+    }
+
+    fn stage(name: &'static str, result: StageResult, calls: &Arc<Mutex<Vec<&'static str>>>) -> Box<dyn RequestStage> {
+        Box::new(RecordingStage { name, result, calls: calls.clone() })
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_stops_at_responding_stage() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let stages = vec![
+            stage("first", StageResult::Continue, &calls),
+            stage("second", StageResult::Reject, &calls),
+            stage("third", StageResult::Continue, &calls),
+        ];
+        let mut session = test_session("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        let mut ctx = RequestContext::new();
+
+        assert!(run_stages(&stages, &mut session, &mut ctx).await.unwrap());
+        assert_eq!(*calls.lock().unwrap(), vec!["first", "second"]);
+        assert!(REQUEST_STAGE_DURATION.with_label_values(&["second"]).get_sample_count() >= 1);
+
+        calls.lock().unwrap().clear();
+        let stages = vec![stage("first", StageResult::Continue, &calls)];
+        assert!(!run_stages(&stages, &mut session, &mut ctx).await.unwrap());
+    }
+
+    #[test]
+    fn test_build_stages_follows_config_order() {
+        let default_names: Vec<&str> = build_stages(&Arc::new(Config::default()), None, None, None, Arc::default(), Arc::default())
+            .iter()
+            .map(|stage| stage.name())
+            .collect();
+        // IP и User-Agent фильтры, circuit breaker и лимиты upstream выключены - их стадии не создаются
+        assert_eq!(default_names, vec!["rate_limit", "request_log", "cors", "redirect", "routing", "static"]);
+
+        let mut config = Config::default();
+        config.pipeline.stages = vec!["routing".to_string(), "cors".to_string()];
+        assert!(config.pipeline.validate().is_ok());
+        let names: Vec<&str> = build_stages(&Arc::new(config), None, None, None, Arc::default(), Arc::default())
+            .iter()
+            .map(|stage| stage.name())
+            .collect();
+        assert_eq!(names, vec!["routing", "cors"]);
+    }
+
+    #[test]
+    fn test_pipeline_config_validation() {
+        let mut config = Config::default();
+        config.pipeline.stages = vec!["routing".to_string(), "unknown".to_string()];
+        assert!(config.pipeline.validate().is_err());
+        config.pipeline.stages = vec!["routing".to_string(), "routing".to_string()];
+        assert!(config.pipeline.validate().is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use async_trait::async_trait;
+use log::info;
+use pingora::prelude::*;
+use std::sync::Arc;
+
+use super::{RequestStage, StageResult};
+use crate::config::Config;
+use crate::error_response::{ErrorCode, ErrorResponse};
+use crate::rate_limit::zones::{check_limit_req, zone_key, ConnLimits};
+use crate::rate_limit::{check_rate_limit, RateLimitConfig, RateLimitDecision};
+use crate::routing::request_host;
+use crate::schedules::Schedules;
+use crate::types::RequestContext;
+
+/// Rate limiting по настройкам location из nginx конфигурации:
+/// rate_limit (с заменой на время окон расписаний), limit_req и limit_conn по зонам
+/// limit_req_zone / limit_conn_zone
+pub struct RateLimitStage {
+    config: Arc<Config>,
+    conn_limits: ConnLimits,
+    schedules: Arc<Schedules>,
+}
+
+impl RateLimitStage {
+    pub fn new(config: Arc<Config>, schedules: Arc<Schedules>) -> Self {
+        Self { config, conn_limits: ConnLimits::default(), schedules }
+    }
+}
+
+#[async_trait]
+impl RequestStage for RateLimitStage {
+    fn name(&self) -> &'static str {
+        "rate_limit"
+    }
+
+    async fn handle(&self, session: &mut Session, ctx: &mut RequestContext) -> Result<StageResult> {
+        let Some(nginx_config) = &self.config.nginx_config else {
+            return Ok(StageResult::Continue);
+        };
+
+        // Находим соответствующий server и location
+        let host = request_host(session);
+        let uri = session.req_header().uri.path();
+        let Some(location) = nginx_config
+            .find_server(host)
+            .and_then(|server| nginx_config.find_location(server, uri))
+        else {
+            return Ok(StageResult::Continue);
+        };
+
+        // Лимит действующего окна расписания заменяет обычный rate_limit
+        let scheduled = location
+            .rate_limit_schedules
+            .iter()
+            .find(|scheduled| self.schedules.is_active(&scheduled.schedule))
+            .map(|scheduled| &scheduled.limit);
+        if let Some(rate_limit) = scheduled.or(location.rate_limit.as_ref()) {
+            // Создаем временную конфигурацию rate limit
+            let rate_config = RateLimitConfig {
+                enabled: true,
+                max_requests_per_second: rate_limit.requests_per_second as isize,
+                whitelist: vec!["127.0.0.1".to_string(), "::1".to_string()],
+                per_api_key_limits: std::collections::HashMap::new(),
+            };
+
+            if check_rate_limit(session, &rate_config, &location.path, ctx).await? {
+                ctx.handle_locally("rate_limit");
+                return Ok(StageResult::Reject);
+            }
+        }
+
+        for limit in &location.limit_req {
+            let Some(zone) = nginx_config.limit_req_zones.get(&limit.zone) else {
+                continue;
+            };
+            let Some(key) = zone_key(session, ctx.client_ip, &zone.key) else {
+                continue;
+            };
+            if check_limit_req(zone, limit.burst, &key, &location.path) == RateLimitDecision::Limited {
+                ctx.handle_locally("rate_limit");
+                ErrorResponse::new(ErrorCode::RateLimited)
+                    .retry_after(zone.period.as_secs())
+                    .send(session, ctx)
+                    .await?;
+                return Ok(StageResult::Reject);
+            }
+        }
+
+        for limit in &location.limit_conn {
+            let Some(zone) = nginx_config.limit_conn_zones.get(&limit.zone) else {
+                continue;
+            };
+            let Some(key) = zone_key(session, ctx.client_ip, &zone.key) else {
+                continue;
+            };
+            match self.conn_limits.acquire(&zone.name, &key, limit.max) {
+                Some(permit) => ctx.conn_permits.push(permit),
+                None => {
+                    info!("limit_conn zone '{}' exceeded for {}: {} requests in flight", zone.name, key, limit.max);
+                    ctx.conn_permits.clear();
+                    ctx.handle_locally("rate_limit");
+                    ErrorResponse::new(ErrorCode::RateLimited).retry_after(1).send(session, ctx).await?;
+                    return Ok(StageResult::Reject);
+                }
+            }
+        }
+        Ok(StageResult::Continue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stages::test_session;
+
+    #[tokio::test]
+    async fn test_no_nginx_config_continues() {
+        let stage = RateLimitStage::new(Arc::new(Config::default()), Arc::default());
+        let mut session = test_session("GET /api/users HTTP/1.1\r\nHost: api.ad-quest.ru\r\n\r\n").await;
+        let mut ctx = RequestContext::new();
+
+        assert_eq!(stage.handle(&mut session, &mut ctx).await.unwrap(), StageResult::Continue);
+        assert!(ctx.local_route.is_none());
+    }
+}
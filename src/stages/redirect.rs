@@ -0,0 +1,26 @@
+use async_trait::async_trait;
+use pingora::prelude::*;
+
+use super::{RequestStage, StageResult};
+use crate::routing::{handle_https_redirect, request_host};
+use crate::types::RequestContext;
+
+/// HTTP -> HTTPS редирект для доменов ad-quest.ru
+pub struct RedirectStage;
+
+#[async_trait]
+impl RequestStage for RedirectStage {
+    fn name(&self) -> &'static str {
+        "redirect"
+    }
+
+    async fn handle(&self, session: &mut Session, ctx: &mut RequestContext) -> Result<StageResult> {
+        let host = request_host(session).to_string();
+        let uri = session.req_header().uri.path().to_string();
+        if handle_https_redirect(session, &host, &uri, ctx.scheme == "https").await? {
+            ctx.handle_locally("redirect");
+            return Ok(StageResult::Respond);
+        }
+        Ok(StageResult::Continue)
+    }
+}
@@ -0,0 +1,53 @@
+use async_trait::async_trait;
+use log::info;
+use pingora::prelude::*;
+use std::sync::Arc;
+
+use super::{RequestStage, StageResult};
+use crate::config::Config;
+use crate::grpc_web::is_grpc_web_request;
+use crate::routing::request_host;
+use crate::types::RequestContext;
+
+/// Логирование входящих запросов (кроме health check), с заголовками для gRPC-Web
+pub struct RequestLogStage {
+    config: Arc<Config>,
+}
+
+impl RequestLogStage {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self { config }
+    }
+}
+
+#[async_trait]
+impl RequestStage for RequestLogStage {
+    fn name(&self) -> &'static str {
+        "request_log"
+    }
+
+    async fn handle(&self, session: &mut Session, _ctx: &mut RequestContext) -> Result<StageResult> {
+        let uri = session.req_header().uri.path();
+        let host = request_host(session);
+        let host_without_port = host.split(':').next().unwrap_or(host);
+
+        // Логируем все запросы к Zitadel и gRPC-Web запросы для диагностики
+        let is_grpc_web = is_grpc_web_request(session.req_header(), &self.config.grpc_web);
+        let is_zitadel = host_without_port == "auth.ad-quest.ru";
+
+        if is_grpc_web || is_zitadel || (!uri.starts_with("/health") && !uri.starts_with("/api/heartbeat")) {
+            info!("Request: {} {} (Host: {})", session.req_header().method, uri, host);
+
+            // Для gRPC-Web запросов логируем заголовки
+            if is_grpc_web {
+                if let Some(ct) = session.req_header().headers.get("content-type") {
+                    info!("  Content-Type: {:?}", ct.to_str().unwrap_or("invalid"));
+                }
+                if let Some(origin) = session.req_header().headers.get("origin") {
+                    info!("  Origin: {:?}", origin.to_str().unwrap_or("invalid"));
+                }
+            }
+        }
+        Ok(StageResult::Continue)
+    }
+}
@@ -0,0 +1,151 @@
+use async_trait::async_trait;
+use pingora::prelude::*;
+use std::sync::Arc;
+
+use super::{RequestStage, StageResult};
+use crate::config::{Config, ProxyPassAddr, ProxyPassUrl};
+use crate::routing::{request_host, route_request};
+use crate::types::{RequestContext, ServiceType, UpstreamTarget};
+
+/// Определение upstream, а также имени upstream и таймаутов из nginx конфигурации
+pub struct RoutingStage {
+    config: Arc<Config>,
+}
+
+impl RoutingStage {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self { config }
+    }
+}
+
+#[async_trait]
+impl RequestStage for RoutingStage {
+    fn name(&self) -> &'static str {
+        "routing"
+    }
+
+    async fn handle(&self, session: &mut Session, ctx: &mut RequestContext) -> Result<StageResult> {
+        let host = request_host(session);
+        let uri = session.req_header().uri.path();
+
+        let legacy_routes = self.config.compat.adquest_legacy_routes;
+        route_request(host, uri, &self.config.services, legacy_routes, ctx);
+
+        // Определяем upstream и таймауты из nginx конфигурации
+        let location = self
+            .config
+            .find_server(host)
+            .and_then(|server| self.config.find_location(server, uri));
+        let proxy_pass_url = location.and_then(|l| l.proxy_pass_url.as_ref());
+        // proxy_pass http://host:port идет в upstream блок или неявный upstream адреса
+        ctx.upstream_name = match proxy_pass_url.and_then(|url| url.upstream.as_ref()) {
+            Some(upstream) => Some(upstream.clone()),
+            None => location.and_then(|l| l.proxy_pass.clone()),
+        };
+        // Без прежних правил запрос, не опознанный по домену и пути, идет в proxy_pass location
+        if !legacy_routes && ctx.upstream_target == UpstreamTarget::None {
+            match proxy_pass_url {
+                Some(ProxyPassUrl { tls, addr: ProxyPassAddr::Unix(path), .. }) => {
+                    ctx.upstream_target = UpstreamTarget::Unix { path: path.clone(), tls: *tls };
+                }
+                Some(url) => {
+                    if let Some(name) = &ctx.upstream_name {
+                        ctx.service_type = ServiceType::from_upstream(name);
+                        ctx.upstream_target = UpstreamTarget::Named(name.clone());
+                        ctx.upstream_tls = url.tls_sni();
+                    }
+                }
+                None => {
+                    if let Some(name) = &ctx.upstream_name {
+                        ctx.service_type = ServiceType::from_upstream(name);
+                        ctx.upstream_target = UpstreamTarget::Named(name.clone());
+                    }
+                }
+            }
+        }
+        ctx.upstream_timeouts = Some(self.config.resolve_upstream_timeouts(location));
+        ctx.backend_profile = self
+            .config
+            .backend_profile(location, ctx.upstream_label())
+            .map(|(name, _)| name.to_string());
+
+        Ok(StageResult::Continue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NginxConfig;
+    use crate::stages::test_session;
+
+    #[tokio::test]
+    async fn test_routing_sets_upstream_and_timeouts() {
+        let stage = RoutingStage::new(Arc::new(Config::default()));
+        let mut session = test_session("GET /billing/invoices HTTP/1.1\r\nHost: api.ad-quest.ru\r\n\r\n").await;
+        let mut ctx = RequestContext::new();
+
+        assert_eq!(stage.handle(&mut session, &mut ctx).await.unwrap(), StageResult::Continue);
+        assert_eq!(ctx.service_type, ServiceType::BillingApi);
+        assert_eq!(ctx.upstream_target, UpstreamTarget::Direct("127.0.0.1:8081".parse().unwrap()));
+        assert!(ctx.upstream_name.is_none());
+        assert!(ctx.upstream_timeouts.is_some());
+        assert!(ctx.backend_profile.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_routing_selects_backend_profile() {
+        let stage = RoutingStage::new(Arc::new(Config::default()));
+        let mut session = test_session("GET /oauth/v2/keys HTTP/1.1\r\nHost: auth.ad-quest.ru\r\n\r\n").await;
+        let mut ctx = RequestContext::new();
+
+        stage.handle(&mut session, &mut ctx).await.unwrap();
+        assert_eq!(ctx.backend_profile.as_deref(), Some("zitadel"));
+    }
+
+    fn localhost_dev(legacy_routes: bool) -> RoutingStage {
+        let mut config = Config::default();
+        config.compat.adquest_legacy_routes = legacy_routes;
+        config.nginx_config = Some(
+            NginxConfig::parse_config_content(include_str!("../../sites-available/localhost-dev")).unwrap(),
+        );
+        RoutingStage::new(Arc::new(config))
+    }
+
+    async fn route(stage: &RoutingStage, host: &str, path: &str) -> RequestContext {
+        let mut session = test_session(&format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, host)).await;
+        let mut ctx = RequestContext::new();
+        stage.handle(&mut session, &mut ctx).await.unwrap();
+        ctx
+    }
+
+    #[tokio::test]
+    async fn test_localhost_routes_from_config() {
+        let stage = localhost_dev(false);
+        let ctx = route(&stage, "localhost", "/ui/console").await;
+        assert_eq!(ctx.upstream_target, UpstreamTarget::Named("zitadel_auth".to_string()));
+        assert_eq!(ctx.service_type, ServiceType::ZitadelAuth);
+
+        let ctx = route(&stage, "127.0.0.1", "/oauth/v2/token").await;
+        assert_eq!(ctx.upstream_target, UpstreamTarget::Named("zitadel_auth".to_string()));
+
+        // Без location в конфигурации запрос не уходит в Zitadel по номеру порта
+        let ctx = route(&stage, "localhost:8085", "/").await;
+        assert_eq!(ctx.service_type, ServiceType::Static);
+        assert_eq!(ctx.upstream_target, UpstreamTarget::None);
+
+        let ctx = route(&stage, "localhost", "/api/v1/users").await;
+        assert_eq!(ctx.upstream_target, UpstreamTarget::Named("core_api".to_string()));
+        assert_eq!(ctx.upstream_name.as_deref(), Some("core_api"));
+    }
+
+    #[tokio::test]
+    async fn test_legacy_localhost_routes() {
+        let stage = localhost_dev(true);
+        let ctx = route(&stage, "localhost:8085", "/").await;
+        assert_eq!(ctx.upstream_target, UpstreamTarget::Named("zitadel_auth".to_string()));
+
+        let ctx = route(&stage, "localhost", "/ui/console").await;
+        assert_eq!(ctx.upstream_target, UpstreamTarget::Named("zitadel_auth".to_string()));
+    }
+}
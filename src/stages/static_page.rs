@@ -0,0 +1,167 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use pingora::prelude::*;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::{close_connection, RequestStage, StageResult, CLOSE_CONNECTION};
+use crate::cache::conditional::{not_modified, not_modified_response};
+use crate::config::{Config, LocationBlock, ReturnDirective};
+use crate::cors::add_security_headers;
+use crate::default_page::DefaultPage;
+use crate::error_response::{ErrorCode, ErrorResponse};
+use crate::local_response::ResponseBuilder;
+use crate::routing::request_host;
+use crate::static_files::resolve;
+use crate::types::{RequestContext, ServiceType};
+use crate::variables::RequestVariables;
+
+/// Ответы location с return, статические файлы location с root (с gzip_static/brotli_static)
+/// и страница default_page для запросов, не направленных ни в один сервис
+pub struct StaticStage {
+    config: Arc<Config>,
+    page: DefaultPage,
+}
+
+impl StaticStage {
+    pub fn new(config: Arc<Config>) -> Self {
+        let page = DefaultPage::load(&config.default_page);
+        Self { config, page }
+    }
+
+    /// Отдает файл из root location (GET и HEAD)
+    async fn serve_file(&self, session: &mut Session, ctx: &mut RequestContext, location: &LocationBlock, root: &str) -> Result<StageResult> {
+        let req = session.req_header();
+        let head = req.method == "HEAD";
+        let accept_encoding = req.headers.get("accept-encoding").and_then(|v| v.to_str().ok());
+        ctx.handle_locally("static_file");
+
+        let Some(file) = resolve(location, Path::new(root), req.uri.path(), accept_encoding) else {
+            ErrorResponse::new(ErrorCode::NotFound)
+                .message("File not found")
+                .send(session, ctx)
+                .await?;
+            return Ok(StageResult::Respond);
+        };
+        let mut response = file.response_header()?;
+        if not_modified(session.req_header(), &response) {
+            let mut response = not_modified_response(&response)?;
+            add_security_headers(&mut response)?;
+            session.write_response_header(Box::new(response), true).await?;
+            return Ok(StageResult::Respond);
+        }
+
+        let body = if head {
+            None
+        } else {
+            let content = tokio::fs::read(&file.path)
+                .await
+                .map_err(|e| Error::because(ErrorType::ReadError, format!("failed to read {}", file.path.display()), e))?;
+            Some(Bytes::from(content))
+        };
+
+        add_security_headers(&mut response)?;
+        session.write_response_header(Box::new(response), head).await?;
+        if let Some(body) = body {
+            session.write_response_body(Some(body), true).await?;
+        }
+        Ok(StageResult::Respond)
+    }
+
+    /// Отвечает по директиве return: редирект с Location или статус с текстом
+    async fn send_return(&self, session: &mut Session, ctx: &mut RequestContext, directive: &ReturnDirective) -> Result<StageResult> {
+        if directive.status == CLOSE_CONNECTION {
+            return Ok(close_connection(session, ctx, "return"));
+        }
+        let maps = self.config.nginx_config.as_ref().map(|nginx| &nginx.maps);
+        let value = directive.value.as_deref().map(|value| {
+            let mut variables = RequestVariables::new(session.req_header(), request_host(session), ctx.client_ip, ctx.scheme, maps);
+            variables.expand(value)
+        });
+        ctx.handle_locally("return");
+
+        if directive.is_redirect() {
+            let mut response = ResponseBuilder::new(directive.status, "text/html");
+            if let Some(location) = value {
+                response = response.header("Location", location);
+            }
+            response.send(session, Bytes::new(), true).await?;
+        } else {
+            ResponseBuilder::new(directive.status, "text/plain")
+                .send(session, Bytes::from(value.unwrap_or_default()), true)
+                .await?;
+        }
+        Ok(StageResult::Respond)
+    }
+}
+
+#[async_trait]
+impl RequestStage for StaticStage {
+    fn name(&self) -> &'static str {
+        "static"
+    }
+
+    async fn handle(&self, session: &mut Session, ctx: &mut RequestContext) -> Result<StageResult> {
+        let config = self.config.clone();
+        let location = config
+            .find_server(request_host(session))
+            .and_then(|server| config.find_location(server, session.req_header().uri.path()));
+
+        // Location с return отвечает сам, без проксирования
+        if let Some(directive) = location.and_then(|l| l.return_directive.as_ref()) {
+            return self.send_return(session, ctx, directive).await;
+        }
+
+        // Location с root отдает файлы с диска вместо проксирования
+        let method = &session.req_header().method;
+        if method == "GET" || method == "HEAD" {
+            if let Some((location, root)) = location.and_then(|l| l.root.as_deref().map(|root| (l, root))) {
+                return self.serve_file(session, ctx, location, root).await;
+            }
+        }
+
+        if ctx.service_type != ServiceType::Static {
+            return Ok(StageResult::Continue);
+        }
+
+        ResponseBuilder::new(self.page.status, &self.page.content_type)
+            .send(session, self.page.body.clone(), true)
+            .await?;
+
+        ctx.handle_locally("static");
+        Ok(StageResult::Respond)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stages::test_session;
+
+    #[tokio::test]
+    async fn test_routed_request_continues() {
+        let mut session = test_session("GET /api/users HTTP/1.1\r\nHost: api.ad-quest.ru\r\n\r\n").await;
+        let mut ctx = RequestContext::new();
+        ctx.service_type = ServiceType::CoreApi;
+
+        let stage = StaticStage::new(Arc::new(crate::config::Config::default()));
+        assert_eq!(stage.handle(&mut session, &mut ctx).await.unwrap(), StageResult::Continue);
+        assert!(ctx.local_route.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_serves_configured_default_page() {
+        let mut session = test_session("GET / HTTP/1.1\r\nHost: unknown.example.com\r\n\r\n").await;
+        let mut ctx = RequestContext::new();
+        let mut config = crate::config::Config::default();
+        config.default_page.inline = Some("<h1>Nothing here</h1>".to_string());
+        config.default_page.status = 404;
+
+        let stage = StaticStage::new(Arc::new(config));
+        assert_eq!(stage.handle(&mut session, &mut ctx).await.unwrap(), StageResult::Respond);
+        let response = session.response_written().unwrap();
+        assert_eq!(response.status.as_u16(), 404);
+        assert_eq!(response.headers.get("content-type").unwrap(), "text/html; charset=utf-8");
+        assert_eq!(response.headers.get("content-length").unwrap(), "21");
+    }
+}
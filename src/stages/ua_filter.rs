@@ -0,0 +1,49 @@
+use async_trait::async_trait;
+use log::info;
+use pingora::prelude::*;
+use std::sync::Arc;
+
+use super::{RequestStage, StageResult};
+use crate::error_response::{ErrorCode, ErrorResponse};
+use crate::filter::UaFilter;
+use crate::routing::request_host;
+use crate::types::RequestContext;
+
+/// Блокировка запросов по User-Agent
+pub struct UaFilterStage {
+    ua_filter: Arc<UaFilter>,
+}
+
+impl UaFilterStage {
+    pub fn new(ua_filter: Arc<UaFilter>) -> Self {
+        Self { ua_filter }
+    }
+}
+
+#[async_trait]
+impl RequestStage for UaFilterStage {
+    fn name(&self) -> &'static str {
+        "ua_filter"
+    }
+
+    async fn handle(&self, session: &mut Session, ctx: &mut RequestContext) -> Result<StageResult> {
+        let host = request_host(session);
+        let host_without_port = host.split(':').next().unwrap_or(host);
+        let user_agent = session
+            .req_header()
+            .headers
+            .get("user-agent")
+            .and_then(|v| v.to_str().ok());
+
+        let blocked = self
+            .ua_filter
+            .should_block(user_agent, host_without_port, session.req_header().uri.path());
+        if let Some(rule) = blocked {
+            info!("Request blocked by ua_filter rule '{}' (User-Agent: {:?})", rule, user_agent);
+            ctx.handle_locally("ua_filter");
+            ErrorResponse::new(ErrorCode::UserAgentBlocked).send(session, ctx).await?;
+            return Ok(StageResult::Reject);
+        }
+        Ok(StageResult::Continue)
+    }
+}
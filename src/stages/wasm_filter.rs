@@ -0,0 +1,145 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use log::{info, warn};
+use pingora::prelude::*;
+use std::sync::Arc;
+
+use super::{RequestStage, StageResult};
+use crate::config::Config;
+use crate::error_response::{ErrorCode, ErrorResponse};
+use crate::local_response::ResponseBuilder;
+use crate::metrics::WASM_FILTER_RESULTS;
+use crate::routing::request_host;
+use crate::types::RequestContext;
+use crate::wasm_filter::{FilterDecision, FilterInput, WasmFilters};
+
+/// WASM фильтр запросов location с директивой wasm_filter: меняет заголовки запроса
+/// или отвечает клиенту вместо upstream
+pub struct WasmFilterStage {
+    config: Arc<Config>,
+    filters: Arc<WasmFilters>,
+}
+
+impl WasmFilterStage {
+    pub fn new(config: Arc<Config>, filters: Arc<WasmFilters>) -> Self {
+        Self { config, filters }
+    }
+}
+
+#[async_trait]
+impl RequestStage for WasmFilterStage {
+    fn name(&self) -> &'static str {
+        "wasm_filter"
+    }
+
+    async fn handle(&self, session: &mut Session, ctx: &mut RequestContext) -> Result<StageResult> {
+        let host = request_host(session).to_string();
+        let path = session.req_header().uri.path().to_string();
+        let Some(directive) = self
+            .config
+            .find_server(&host)
+            .and_then(|server| self.config.find_location(server, &path))
+            .and_then(|location| location.wasm_filter.as_ref())
+        else {
+            return Ok(StageResult::Continue);
+        };
+
+        let headers = session
+            .req_header()
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.as_str().to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let input = FilterInput { host, path, headers };
+
+        let output = match self.filters.run(&directive.path, input) {
+            Ok(output) => output,
+            Err(e) => {
+                WASM_FILTER_RESULTS.with_label_values(&["error"]).inc();
+                warn!("WASM filter {} failed: {}", directive.path, e);
+                if directive.fail_open {
+                    return Ok(StageResult::Continue);
+                }
+                ctx.handle_locally("wasm_filter");
+                ErrorResponse::new(ErrorCode::InternalError).send(session, ctx).await?;
+                return Ok(StageResult::Reject);
+            }
+        };
+
+        let request = session.req_header_mut();
+        for (name, value) in output.header_changes {
+            match value {
+                Some(value) => request.insert_header(name, value)?,
+                None => {
+                    request.remove_header(name.as_str());
+                }
+            }
+        }
+
+        match output.decision {
+            FilterDecision::Continue => {
+                WASM_FILTER_RESULTS.with_label_values(&["continue"]).inc();
+                Ok(StageResult::Continue)
+            }
+            FilterDecision::Respond { status, body } => {
+                WASM_FILTER_RESULTS.with_label_values(&["respond"]).inc();
+                info!("Request answered by WASM filter {} with status {}", directive.path, status);
+                ctx.handle_locally("wasm_filter");
+                ResponseBuilder::new(status, "text/plain")
+                    .header("Cache-Control", "no-store")
+                    .cors()
+                    .send(session, Bytes::from(body), true)
+                    .await?;
+                Ok(StageResult::Respond)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NginxConfig;
+    use crate::stages::test_session;
+
+    fn stage(location: &str) -> WasmFilterStage {
+        let mut config = Config::default();
+        config.nginx_config = Some(
+            NginxConfig::parse_config_content(&format!(
+                "server {{ listen 80; server_name api.example.com; location /tenants/ {{ proxy_pass api; {} }} }}",
+                location
+            ))
+            .unwrap(),
+        );
+        let filters = Arc::new(WasmFilters::from_config(&config));
+        WasmFilterStage::new(Arc::new(config), filters)
+    }
+
+    #[tokio::test]
+    async fn test_fail_open_continues() {
+        let stage = stage("wasm_filter /nonexistent/tenant.wasm on_failure=open;");
+        let mut session = test_session("GET /tenants/orders HTTP/1.1\r\nHost: api.example.com\r\n\r\n").await;
+        let mut ctx = RequestContext::new();
+        assert_eq!(stage.handle(&mut session, &mut ctx).await.unwrap(), StageResult::Continue);
+        assert_eq!(ctx.local_route, None);
+    }
+
+    #[cfg(feature = "wasm-filters")]
+    #[tokio::test]
+    async fn test_filter_rewrites_request_headers() {
+        let example = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/wasm-filters/tenant.wat");
+        let stage = stage(&format!("wasm_filter {};", example));
+        let mut session = test_session(
+            "GET /tenants/orders HTTP/1.1\r\nHost: api.example.com\r\nX-Tenant: acme\r\n\r\n",
+        )
+        .await;
+        let mut ctx = RequestContext::new();
+        assert_eq!(stage.handle(&mut session, &mut ctx).await.unwrap(), StageResult::Continue);
+        assert_eq!(session.req_header().headers.get("x-routing-key").unwrap(), "acme");
+
+        // Location без фильтра не затрагивается
+        let mut session = test_session("GET /other HTTP/1.1\r\nHost: api.example.com\r\n\r\n").await;
+        assert_eq!(stage.handle(&mut session, &mut ctx).await.unwrap(), StageResult::Continue);
+        assert!(session.req_header().headers.get("x-routing-key").is_none());
+    }
+}
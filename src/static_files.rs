@@ -0,0 +1,275 @@
+use pingora::http::ResponseHeader;
+use pingora::prelude::*;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::LocationBlock;
+
+/// Предварительно сжатый вариант файла: расширение и Content-Encoding
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Precompressed {
+    Brotli,
+    Gzip,
+}
+
+impl Precompressed {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Precompressed::Brotli => "br",
+            Precompressed::Gzip => "gz",
+        }
+    }
+
+    pub fn content_encoding(&self) -> &'static str {
+        match self {
+            Precompressed::Brotli => "br",
+            Precompressed::Gzip => "gzip",
+        }
+    }
+}
+
+/// Файл, выбранный для ответа
+#[derive(Debug, Clone)]
+pub struct StaticFile {
+    /// Путь к отдаваемому варианту (file, file.br или file.gz)
+    pub path: PathBuf,
+    /// Content-Encoding варианта (None - исходный файл)
+    pub encoding: Option<Precompressed>,
+    /// Content-Type по имени исходного файла
+    pub content_type: String,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    /// Есть варианты по Accept-Encoding (нужен Vary)
+    pub vary: bool,
+}
+
+impl StaticFile {
+    /// ETag в формате nginx: время изменения и размер отдаваемого варианта
+    pub fn etag(&self) -> String {
+        let mtime = self
+            .modified
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        format!("\"{:x}-{:x}\"", mtime, self.len)
+    }
+
+    /// Last-Modified отдаваемого варианта
+    pub fn last_modified(&self) -> Option<String> {
+        self.modified.map(|m| {
+            chrono::DateTime::<chrono::Utc>::from(m)
+                .format("%a, %d %b %Y %H:%M:%S GMT")
+                .to_string()
+        })
+    }
+
+    /// Заголовки ответа 200
+    pub fn response_header(&self) -> Result<ResponseHeader> {
+        let mut response = ResponseHeader::build(200, None)?;
+        response.insert_header("Content-Type", &self.content_type)?;
+        response.insert_header("Content-Length", self.len.to_string())?;
+        response.insert_header("ETag", self.etag())?;
+        if let Some(last_modified) = self.last_modified() {
+            response.insert_header("Last-Modified", last_modified)?;
+        }
+        if let Some(encoding) = self.encoding {
+            response.insert_header("Content-Encoding", encoding.content_encoding())?;
+        }
+        if self.vary {
+            response.insert_header("Vary", "Accept-Encoding")?;
+        }
+        Ok(response)
+    }
+}
+
+/// q-value кодировки в Accept-Encoding; `*` применяется к неупомянутым кодировкам
+pub fn encoding_quality(accept_encoding: &str, coding: &str) -> f32 {
+    let mut wildcard = None;
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or_default().trim();
+        let quality = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(coding) {
+            return quality;
+        }
+        if name == "*" {
+            wildcard = Some(quality);
+        }
+    }
+    wildcard.unwrap_or(0.0)
+}
+
+/// Путь файла под root для пути запроса (как root в nginx); `..` отклоняется
+pub fn file_path(root: &Path, uri_path: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for component in Path::new(uri_path.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if uri_path.ends_with('/') {
+        path.push("index.html");
+    }
+    Some(path)
+}
+
+/// Выбирает вариант файла: file.br и file.gz (по q-values Accept-Encoding, при равных - br),
+/// иначе исходный файл. None - файла нет
+pub fn resolve(location: &LocationBlock, root: &Path, uri_path: &str, accept_encoding: Option<&str>) -> Option<StaticFile> {
+    let path = file_path(root, uri_path)?;
+    let content_type = mime_guess::from_path(&path).first_or_octet_stream().to_string();
+    let vary = location.gzip_static || location.brotli_static;
+
+    let mut candidates: Vec<(Precompressed, f32)> = [
+        (Precompressed::Brotli, location.brotli_static),
+        (Precompressed::Gzip, location.gzip_static),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(encoding, _)| (encoding, accept_encoding.map_or(0.0, |ae| encoding_quality(ae, encoding.content_encoding()))))
+    .filter(|(_, quality)| *quality > 0.0)
+    .collect();
+    // Стабильная сортировка сохраняет приоритет br при равных q
+    candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let variants = candidates
+        .into_iter()
+        .map(|(encoding, _)| {
+            let mut variant = path.clone().into_os_string();
+            variant.push(".");
+            variant.push(encoding.extension());
+            (PathBuf::from(variant), Some(encoding))
+        })
+        .chain(std::iter::once((path.clone(), None)));
+
+    for (variant, encoding) in variants {
+        let Ok(metadata) = std::fs::metadata(&variant) else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+        return Some(StaticFile {
+            path: variant,
+            encoding,
+            content_type,
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            vary,
+        });
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NginxConfig;
+    use tempfile::tempdir;
+
+    fn location() -> LocationBlock {
+        NginxConfig::parse_location_block("/assets/", "root /srv;\ngzip_static on;\nbrotli_static on;").unwrap()
+    }
+
+    #[test]
+    fn test_all_variants_present() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("app.js"), "console.log('adquest');").unwrap();
+        std::fs::write(dir.path().join("app.js.gz"), vec![0x1f; 12]).unwrap();
+        std::fs::write(dir.path().join("app.js.br"), vec![0x0b; 10]).unwrap();
+        let location = location();
+        let resolve = |accept: Option<&str>| resolve(&location, dir.path(), "/app.js", accept).unwrap();
+
+        let file = resolve(Some("gzip, deflate, br"));
+        assert_eq!(file.encoding, Some(Precompressed::Brotli));
+        assert_eq!(file.len, 10);
+        let header = file.response_header().unwrap();
+        assert_eq!(header.headers.get("content-encoding").unwrap(), "br");
+        assert_eq!(header.headers.get("content-type").unwrap(), "application/javascript");
+        assert_eq!(header.headers.get("vary").unwrap(), "Accept-Encoding");
+        assert_eq!(header.headers.get("content-length").unwrap(), "10");
+        assert!(header.headers.get("last-modified").is_some());
+
+        // q-values: gzip предпочтительнее br
+        let gzip = resolve(Some("br;q=0.5, gzip;q=0.9"));
+        assert_eq!(gzip.encoding, Some(Precompressed::Gzip));
+        assert_eq!(gzip.path, dir.path().join("app.js.gz"));
+        // ETag вычисляется по отдаваемому варианту
+        assert_ne!(gzip.etag(), file.etag());
+        assert_eq!(resolve(Some("br;q=0, *")).encoding, Some(Precompressed::Gzip));
+
+        // Клиент не принимает сжатие: исходный файл, но с Vary
+        let plain = resolve(None);
+        assert_eq!(plain.encoding, None);
+        assert_eq!(plain.len, 23);
+        let header = plain.response_header().unwrap();
+        assert!(header.headers.get("content-encoding").is_none());
+        assert_eq!(header.headers.get("vary").unwrap(), "Accept-Encoding");
+        assert_eq!(resolve(Some("identity")).encoding, None);
+
+        // brotli_static off: .br не отдается
+        let mut gzip_only = location.clone();
+        gzip_only.brotli_static = false;
+        let file = super::resolve(&gzip_only, dir.path(), "/app.js", Some("br, gzip")).unwrap();
+        assert_eq!(file.encoding, Some(Precompressed::Gzip));
+    }
+
+    #[test]
+    fn test_pre_gzipped_asset_served() {
+        use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+        use std::io::{Read, Write};
+
+        let dir = tempdir().unwrap();
+        let source = "body { color: #333 }\n".repeat(64);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(source.as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        std::fs::write(dir.path().join("site.css"), &source).unwrap();
+        std::fs::write(dir.path().join("site.css.gz"), &gzipped).unwrap();
+
+        let mut location = location();
+        location.brotli_static = false;
+        let file = resolve(&location, dir.path(), "/site.css", Some("gzip, deflate")).unwrap();
+        assert_eq!(file.path, dir.path().join("site.css.gz"));
+        let header = file.response_header().unwrap();
+        assert_eq!(header.headers.get("content-encoding").unwrap(), "gzip");
+        assert_eq!(header.headers.get("content-type").unwrap(), "text/css");
+        assert_eq!(header.headers.get("content-length").unwrap(), gzipped.len().to_string().as_str());
+
+        // Отдаваемое тело - содержимое .gz, которое клиент распаковывает в исходный файл
+        let body = std::fs::read(&file.path).unwrap();
+        let mut unpacked = String::new();
+        GzDecoder::new(body.as_slice()).read_to_string(&mut unpacked).unwrap();
+        assert_eq!(unpacked, source);
+
+        // Без gzip_static сжатый вариант не используется
+        location.gzip_static = false;
+        let file = resolve(&location, dir.path(), "/site.css", Some("gzip")).unwrap();
+        assert_eq!(file.encoding, None);
+        assert!(file.response_header().unwrap().headers.get("vary").is_none());
+    }
+
+    #[test]
+    fn test_only_plain_file() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("style.css"), "body { margin: 0 }").unwrap();
+        let location = location();
+
+        let file = resolve(&location, dir.path(), "/style.css", Some("br, gzip")).unwrap();
+        assert_eq!(file.encoding, None);
+        assert_eq!(file.path, dir.path().join("style.css"));
+        let header = file.response_header().unwrap();
+        assert_eq!(header.headers.get("content-type").unwrap(), "text/css");
+        assert!(header.headers.get("content-encoding").is_none());
+        assert_eq!(header.headers.get("vary").unwrap(), "Accept-Encoding");
+
+        assert!(resolve(&location, dir.path(), "/missing.css", Some("gzip")).is_none());
+        // Выход за пределы root
+        assert!(file_path(dir.path(), "/../etc/passwd").is_none());
+        assert_eq!(encoding_quality("gzip;q=0.3, *;q=0.1", "br"), 0.1);
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt::Write;
+
+use super::StatusSnapshot;
+
+/// Период обновления страницы (meta refresh), в секундах
+const REFRESH_SECONDS: u32 = 5;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em}\
+table{border-collapse:collapse}\
+th,td{border:1px solid #ccc;padding:4px 10px;text-align:left}\
+.up{color:#1a7f37}.down{color:#cf222e;font-weight:bold}";
+
+/// Экранирование для текста и атрибутов HTML
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn format_uptime(seconds: u64) -> String {
+    let (days, hours, minutes) = (seconds / 86_400, seconds % 86_400 / 3_600, seconds % 3_600 / 60);
+    match (days, hours) {
+        (0, 0) => format!("{}m {}s", minutes, seconds % 60),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h", days, hours),
+    }
+}
+
+/// Страница GET /_admin/health: таблица бэкендов всех upstream (или только `upstream`)
+pub fn render_html(snapshot: &StatusSnapshot, upstream: Option<&str>) -> String {
+    let mut page = String::new();
+    let _ = write!(
+        page,
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <meta http-equiv=\"refresh\" content=\"{}\">\
+         <title>adq-pingora upstream health</title><style>{}</style></head><body>\n",
+        REFRESH_SECONDS, STYLE
+    );
+    let _ = writeln!(
+        page,
+        "<h1>Upstream health</h1>\n<p>adq-pingora {}, uptime {}</p>",
+        escape(snapshot.version),
+        format_uptime(snapshot.uptime_seconds)
+    );
+    if let Some(upstream) = upstream {
+        let _ = writeln!(page, "<p>Upstream: {} (<a href=\"?\">all</a>)</p>", escape(upstream));
+    }
+
+    if snapshot.upstreams.is_empty() {
+        page.push_str("<p>No upstreams</p>\n");
+    } else {
+        page.push_str(
+            "<table>\n<tr><th>Upstream</th><th>Backend</th><th>State</th><th>Last check</th>\
+             <th>Consecutive failures</th><th>Circuit breaker</th><th>In flight</th></tr>\n",
+        );
+        for status in &snapshot.upstreams {
+            let name = escape(&status.name);
+            for backend in &status.backends {
+                let state = if backend.healthy { "up" } else { "down" };
+                let _ = writeln!(
+                    page,
+                    "<tr><td>{}</td><td>{}</td><td class=\"{}\">{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    name,
+                    escape(&backend.addr),
+                    state,
+                    state,
+                    backend.last_check.as_deref().unwrap_or("-"),
+                    backend.consecutive_failures,
+                    status.circuit_breaker,
+                    backend.in_flight
+                );
+            }
+        }
+        page.push_str("</table>\n");
+    }
+    page.push_str("</body></html>\n");
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::{BackendStatus, UpstreamStatus};
+
+    #[test]
+    fn test_names_are_escaped() {
+        let snapshot = StatusSnapshot {
+            version: "0.1.0",
+            uptime_seconds: 3_725,
+            upstreams: vec![UpstreamStatus {
+                name: "<b>api</b>".to_string(),
+                circuit_breaker: "closed",
+                backends: vec![BackendStatus {
+                    addr: "10.0.0.1:8080".to_string(),
+                    healthy: true,
+                    last_check: None,
+                    consecutive_failures: 0,
+                    in_flight: 0,
+                }],
+            }],
+        };
+        let page = render_html(&snapshot, Some("\"><script>"));
+        assert!(page.contains("<td>&lt;b&gt;api&lt;/b&gt;</td>"));
+        assert!(page.contains("&quot;&gt;&lt;script&gt;"));
+        assert!(!page.contains("<script>"));
+        assert!(page.contains("uptime 1h 2m"));
+    }
+}
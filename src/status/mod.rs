@@ -0,0 +1,267 @@
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use pingora_load_balancing::health_check::HealthCheck;
+use pingora_load_balancing::{selection::RoundRobin, Backend, LoadBalancer};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::circuit_breaker::CircuitBreaker;
+use crate::drain::DrainTracker;
+use crate::reload::ReloadableProxy;
+
+pub mod html;
+pub use html::render_html;
+
+/// Результат последней активной проверки бэкенда
+#[derive(Debug, Clone)]
+struct CheckRecord {
+    last_check: DateTime<Utc>,
+    consecutive_failures: u32,
+}
+
+/// Результаты активных health checks по (upstream, адрес бэкенда)
+#[derive(Debug, Default)]
+pub struct HealthChecks {
+    records: Mutex<HashMap<(String, String), CheckRecord>>,
+}
+
+impl HealthChecks {
+    pub fn record(&self, upstream: &str, backend: &str, success: bool) {
+        let mut records = self.records.lock().unwrap();
+        let record = records
+            .entry((upstream.to_string(), backend.to_string()))
+            .or_insert(CheckRecord { last_check: Utc::now(), consecutive_failures: 0 });
+        record.last_check = Utc::now();
+        record.consecutive_failures = if success { 0 } else { record.consecutive_failures + 1 };
+    }
+
+    fn get(&self, upstream: &str, backend: &str) -> Option<CheckRecord> {
+        self.records
+            .lock()
+            .unwrap()
+            .get(&(upstream.to_string(), backend.to_string()))
+            .cloned()
+    }
+}
+
+/// Health check upstream, запоминающий время и результат каждой проверки
+pub struct RecordingHealthCheck {
+    upstream: String,
+    inner: Box<dyn HealthCheck + Send + Sync>,
+    checks: Arc<HealthChecks>,
+}
+
+impl RecordingHealthCheck {
+    pub fn new(upstream: &str, inner: Box<dyn HealthCheck + Send + Sync>, checks: Arc<HealthChecks>) -> Self {
+        Self { upstream: upstream.to_string(), inner, checks }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for RecordingHealthCheck {
+    async fn check(&self, target: &Backend) -> pingora_core::Result<()> {
+        let result = self.inner.check(target).await;
+        self.checks.record(&self.upstream, &target.addr.to_string(), result.is_ok());
+        result
+    }
+
+    async fn health_status_change(&self, target: &Backend, healthy: bool) {
+        self.inner.health_status_change(target, healthy).await
+    }
+
+    fn health_threshold(&self, success: bool) -> usize {
+        self.inner.health_threshold(success)
+    }
+}
+
+/// Состояние бэкенда на странице здоровья
+#[derive(Debug, Clone, Serialize)]
+pub struct BackendStatus {
+    pub addr: String,
+    pub healthy: bool,
+    /// Время последней проверки, RFC 3339; None - проверок еще не было
+    pub last_check: Option<String>,
+    pub consecutive_failures: u32,
+    pub in_flight: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpstreamStatus {
+    pub name: String,
+    /// closed, open, half-open или disabled
+    pub circuit_breaker: &'static str,
+    pub backends: Vec<BackendStatus>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusSnapshot {
+    pub version: &'static str,
+    pub uptime_seconds: u64,
+    pub upstreams: Vec<UpstreamStatus>,
+}
+
+/// Сводка состояния upstream: health checks балансировщиков, circuit breaker
+/// и in-flight запросы по бэкендам
+pub struct StatusSource {
+    upstreams: HashMap<String, Arc<LoadBalancer<RoundRobin>>>,
+    /// Прокси, сборку которого заменяет reload: upstream берутся из текущей сборки
+    proxy: Option<ReloadableProxy>,
+    checks: Arc<HealthChecks>,
+    drain_tracker: Arc<DrainTracker>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    started: Instant,
+}
+
+impl StatusSource {
+    pub fn new(
+        upstreams: HashMap<String, Arc<LoadBalancer<RoundRobin>>>,
+        checks: Arc<HealthChecks>,
+        drain_tracker: Arc<DrainTracker>,
+        circuit_breaker: Option<Arc<CircuitBreaker>>,
+        started: Instant,
+    ) -> Self {
+        Self { upstreams, proxy: None, checks, drain_tracker, circuit_breaker, started }
+    }
+
+    /// Upstream из текущей сборки прокси вместо переданных в `new`
+    pub fn with_proxy(mut self, proxy: ReloadableProxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Снимок состояния; `upstream` оставляет только upstream с этим именем
+    pub async fn snapshot(&self, upstream: Option<&str>) -> StatusSnapshot {
+        let current = self.proxy.as_ref().map(|proxy| proxy.current());
+        let lbs = current.as_ref().map_or(&self.upstreams, |proxy| proxy.upstreams());
+        let mut names: Vec<&String> = lbs
+            .keys()
+            .filter(|name| upstream.is_none() || upstream == Some(name.as_str()))
+            .collect();
+        names.sort();
+
+        let mut upstreams = Vec::with_capacity(names.len());
+        for name in names {
+            let backends = lbs[name].backends();
+            let mut statuses: Vec<BackendStatus> = backends
+                .get_backend()
+                .iter()
+                .map(|backend| {
+                    let addr = backend.addr.to_string();
+                    let check = self.checks.get(name, &addr);
+                    BackendStatus {
+                        healthy: backends.ready(backend),
+                        last_check: check
+                            .as_ref()
+                            .map(|check| check.last_check.to_rfc3339_opts(SecondsFormat::Secs, true)),
+                        consecutive_failures: check.map_or(0, |check| check.consecutive_failures),
+                        in_flight: self.drain_tracker.in_flight(name, &addr),
+                        addr,
+                    }
+                })
+                .collect();
+            statuses.sort_by(|a, b| a.addr.cmp(&b.addr));
+
+            let circuit_breaker = match &self.circuit_breaker {
+                Some(circuit_breaker) => circuit_breaker.get_state(name).await.as_str(),
+                None => "disabled",
+            };
+            upstreams.push(UpstreamStatus { name: name.clone(), circuit_breaker, backends: statuses });
+        }
+
+        StatusSnapshot {
+            version: env!("CARGO_PKG_VERSION"),
+            uptime_seconds: self.started.elapsed().as_secs(),
+            upstreams,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use pingora_load_balancing::health_check::TcpHealthCheck;
+    use std::time::Duration;
+
+    /// Upstream с доступным бэкендом-listener и бэкендом на закрытом порту
+    /// после одного раунда TCP health checks
+    async fn synthetic_state(circuit_breaker: Option<Arc<CircuitBreaker>>) -> (StatusSource, tokio::net::TcpListener) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let alive = listener.local_addr().unwrap().to_string();
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().to_string();
+
+        let checks = Arc::new(HealthChecks::default());
+        let mut lb = LoadBalancer::<RoundRobin>::try_from_iter([alive.as_str(), closed.as_str()]).unwrap();
+        lb.set_health_check(Box::new(RecordingHealthCheck::new("core_api", TcpHealthCheck::new(), checks.clone())));
+        lb.backends().run_health_check(false).await;
+
+        let billing = LoadBalancer::<RoundRobin>::try_from_iter(["10.0.0.9:8081"]).unwrap();
+        let drain_tracker = Arc::new(DrainTracker::new(Duration::from_secs(30)));
+        drain_tracker.begin_request("core_api", &alive);
+        drain_tracker.begin_request("core_api", &alive);
+
+        let upstreams = HashMap::from([
+            ("core_api".to_string(), Arc::new(lb)),
+            ("billing_api".to_string(), Arc::new(billing)),
+        ]);
+        (StatusSource::new(upstreams, checks, drain_tracker, circuit_breaker, Instant::now()), listener)
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_aggregates_backend_state() {
+        let mut config = Config::default().circuit_breaker;
+        config.enabled = true;
+        config.failure_threshold = 1;
+        let circuit_breaker = Arc::new(CircuitBreaker::new(config));
+        circuit_breaker.record_failure("core_api").await;
+
+        let (source, listener) = synthetic_state(Some(circuit_breaker)).await;
+        let alive = listener.local_addr().unwrap().to_string();
+        let snapshot = source.snapshot(None).await;
+        assert_eq!(snapshot.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(snapshot.upstreams.iter().map(|u| u.name.as_str()).collect::<Vec<_>>(), vec!["billing_api", "core_api"]);
+
+        let core_api = &snapshot.upstreams[1];
+        assert_eq!(core_api.circuit_breaker, "open");
+        let up = core_api.backends.iter().find(|b| b.addr == alive).unwrap();
+        assert!(up.healthy);
+        assert_eq!(up.consecutive_failures, 0);
+        assert_eq!(up.in_flight, 2);
+        assert!(up.last_check.is_some());
+        let down = core_api.backends.iter().find(|b| b.addr != alive).unwrap();
+        assert!(!down.healthy);
+        assert_eq!(down.consecutive_failures, 1);
+
+        // Без health check бэкенд считается здоровым, проверок не было
+        let billing = &snapshot.upstreams[0].backends[0];
+        assert!(billing.healthy && billing.last_check.is_none());
+
+        let filtered = source.snapshot(Some("billing_api")).await;
+        assert_eq!(filtered.upstreams.len(), 1);
+        assert!(source.snapshot(Some("missing")).await.upstreams.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_html_rows_for_synthetic_state() {
+        let (source, listener) = synthetic_state(None).await;
+        let alive = listener.local_addr().unwrap().to_string();
+        let page = render_html(&source.snapshot(None).await, None);
+
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(page.contains(r#"<meta http-equiv="refresh" content="5">"#));
+        assert!(page.contains(&format!("adq-pingora {}", env!("CARGO_PKG_VERSION"))));
+        assert!(page.contains(&format!(
+            r#"<tr><td>core_api</td><td>{}</td><td class="up">up</td>"#,
+            alive
+        )));
+        assert!(page.contains(r#"<td class="down">down</td>"#));
+        assert!(page.contains(r#"<tr><td>billing_api</td><td>10.0.0.9:8081</td><td class="up">up</td><td>-</td><td>0</td><td>disabled</td><td>0</td></tr>"#));
+        assert!(!page.contains("<script"));
+
+        let page = render_html(&source.snapshot(Some("billing_api")).await, Some("billing_api"));
+        assert!(page.contains("10.0.0.9:8081"));
+        assert!(!page.contains(&alive));
+    }
+}
@@ -0,0 +1,184 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use pingora::http::ResponseHeader;
+use pingora_core::apps::{HttpServerApp, HttpServerOptions, ServerApp};
+use pingora_core::protocols::digest::SocketDigest;
+use pingora_core::protocols::http::ServerSession;
+use pingora_core::protocols::l4::stream::Stream as L4Stream;
+use pingora_core::protocols::Stream;
+use pingora_core::server::configuration::ServerConf;
+use pingora_core::server::ShutdownWatch;
+use pingora_load_balancing::{selection::RoundRobin, LoadBalancer};
+use pingora_proxy::{http_proxy, ProxyHttp};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+use crate::keepalive::{KeepaliveApp, KeepaliveTracker};
+use crate::builder::ProxyBuilder;
+use crate::proxy::AdQuestProxy;
+use crate::proxy_protocol::ProxyProtocolApp;
+use crate::reload::ReloadableProxy;
+
+/// Принимает соединения и передает их приложению, как listener сервиса Pingora
+fn serve<A>(listener: TcpListener, app: Arc<A>) -> JoinHandle<()>
+where
+    A: ServerApp + Send + Sync + 'static,
+{
+    tokio::spawn(async move {
+        while let Ok((tcp, _)) = listener.accept().await {
+            let app = app.clone();
+            tokio::spawn(async move {
+                let (_tx, shutdown) = tokio::sync::watch::channel(false);
+                let mut l4 = L4Stream::from(tcp);
+                // Адрес клиента для client_ip, IP фильтра и limit_req
+                l4.set_socket_digest(SocketDigest::from_raw_fd(l4.as_raw_fd()));
+                let mut stream: Option<Stream> = Some(Box::new(l4));
+                while let Some(reused) = stream {
+                    stream = app.process_new(reused, &shutdown).await;
+                }
+            });
+        }
+    })
+}
+
+/// Отвечает 200 с телом "<метод> <uri>" и адресом заглушки в X-Mock-Upstream;
+/// на запрос с заголовком X-Mock-Chunked тело передается chunked
+struct MockApp {
+    addr: SocketAddr,
+    requests: Arc<AtomicUsize>,
+    /// Пауза перед ответом (медленный бэкенд)
+    delay: Duration,
+}
+
+#[async_trait]
+impl HttpServerApp for MockApp {
+    async fn process_new_http(self: &Arc<Self>, mut session: ServerSession, _shutdown: &ShutdownWatch) -> Option<Stream> {
+        if !session.read_request().await.ok()? {
+            return None;
+        }
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(self.delay).await;
+        let request = session.req_header();
+        let body = Bytes::from(format!("{} {}", request.method, request.uri));
+        let chunked = request.headers.contains_key("x-mock-chunked");
+        let mut response = ResponseHeader::build(200, None).ok()?;
+        response.insert_header("Content-Type", "text/plain").ok()?;
+        if chunked {
+            response.insert_header("Transfer-Encoding", "chunked").ok()?;
+        } else {
+            response.insert_header("Content-Length", body.len().to_string()).ok()?;
+        }
+        response.insert_header("X-Mock-Upstream", self.addr.to_string()).ok()?;
+        session.write_response_header(Box::new(response)).await.ok()?;
+        session.write_response_body(body, true).await.ok()?;
+        session.finish().await.ok().flatten()
+    }
+}
+
+/// Upstream-заглушка, считающая полученные запросы
+pub struct MockUpstream {
+    addr: SocketAddr,
+    requests: Arc<AtomicUsize>,
+    task: JoinHandle<()>,
+}
+
+impl MockUpstream {
+    pub async fn start() -> std::io::Result<Self> {
+        Self::start_with_delay(Duration::ZERO).await
+    }
+
+    /// Заглушка, отвечающая на каждый запрос через `delay`
+    pub async fn start_with_delay(delay: Duration) -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let requests = Arc::new(AtomicUsize::new(0));
+        let app = Arc::new(MockApp { addr, requests: requests.clone(), delay });
+        Ok(Self { addr, requests, task: serve(listener, app) })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Число запросов, дошедших до заглушки
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+
+    /// Балансировщик с единственным бэкендом - этой заглушкой
+    pub fn load_balancer(&self) -> Arc<LoadBalancer<RoundRobin>> {
+        Arc::new(LoadBalancer::try_from_iter([self.addr.to_string()]).expect("mock upstream address"))
+    }
+}
+
+impl Drop for MockUpstream {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Прокси в том же процессе для интеграционных тестов: те же обертки приложения,
+/// что и у сервиса в main (PROXY protocol без портов, лимит keep-alive запросов),
+/// на свободном порту loopback. Принимает HTTP/1.1 и h2c с prior knowledge, как порт
+/// с `listen ... http2`. Останавливается при drop
+pub struct TestProxy {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl TestProxy {
+    /// Собирает прокси и заполняет бэкенды upstream из конфигурации (без health checks
+    /// и других фоновых сервисов)
+    pub async fn start(builder: ProxyBuilder) -> std::io::Result<Self> {
+        let built = builder.build().map_err(|e| std::io::Error::other(e.to_string()))?;
+        built.update_upstreams().await.map_err(|e| std::io::Error::other(e.to_string()))?;
+        Self::serve(built.proxy).await
+    }
+
+    /// Запускает уже собранный прокси, например `BuiltProxy::proxy`
+    pub async fn serve(proxy: AdQuestProxy) -> std::io::Result<Self> {
+        let keepalive_tracker = proxy.keepalive_tracker();
+        Self::listen(proxy, keepalive_tracker).await
+    }
+
+    /// Запускает прокси, сборку которого заменяет reload (`BuiltProxy::into_reloadable`)
+    pub async fn serve_reloadable(proxy: ReloadableProxy) -> std::io::Result<Self> {
+        let keepalive_tracker = proxy.current().keepalive_tracker();
+        Self::listen(proxy, keepalive_tracker).await
+    }
+
+    async fn listen<P>(proxy: P, keepalive_tracker: Arc<KeepaliveTracker>) -> std::io::Result<Self>
+    where
+        P: ProxyHttp + Send + Sync + 'static,
+        P::CTX: Send + Sync,
+    {
+        let mut app = http_proxy(&Arc::new(ServerConf::default()), proxy);
+        app.server_options = Some(HttpServerOptions { h2c: true, ..Default::default() });
+        let app = Arc::new(ProxyProtocolApp::new(KeepaliveApp::new(app, keepalive_tracker), HashSet::new()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        Ok(Self { addr, task: serve(listener, app) })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// URL пути на прокси
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+}
+
+impl Drop for TestProxy {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
@@ -0,0 +1,104 @@
+use pingora::prelude::*;
+use pingora::http::ResponseHeader;
+use std::time::{Duration, Instant};
+
+use crate::config::ResponseHeadersConfig;
+use crate::types::RequestContext;
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Значение Server-Timing: ожидание слота upstream, длительность обращения к upstream и общая длительность (мс)
+pub fn server_timing_value(ctx: &RequestContext, now: Instant) -> String {
+    let mut timings = Vec::new();
+    if let Some(queue_wait) = ctx.upstream_queue_wait {
+        timings.push(format!("queue;dur={:.1}", millis(queue_wait)));
+    }
+    if let Some(upstream_start) = ctx.upstream_start {
+        timings.push(format!("upstream;dur={:.1}", millis(now.duration_since(upstream_start))));
+    }
+    timings.push(format!("total;dur={:.1}", millis(now.duration_since(ctx.start_time))));
+    timings.join(", ")
+}
+
+/// Добавляет Server-Timing, Alt-Svc и заголовок с адресом бэкенда, если они включены в конфигурации
+pub fn add_timing_headers(
+    response: &mut ResponseHeader,
+    config: &ResponseHeadersConfig,
+    ctx: &RequestContext,
+) -> Result<()> {
+    if config.server_timing {
+        response.insert_header("Server-Timing", server_timing_value(ctx, Instant::now()))?;
+    }
+    if let Some(alt_svc) = &config.alt_svc {
+        response.insert_header("Alt-Svc", alt_svc.as_str())?;
+    }
+    if let (Some(header), Some(addr)) = (&config.upstream_addr, &ctx.upstream_addr) {
+        response.insert_header(header.clone(), addr.as_str())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn durations(value: &str) -> Vec<(String, f64)> {
+        value
+            .split(", ")
+            .map(|metric| {
+                let (name, dur) = metric.split_once(";dur=").unwrap();
+                (name.to_string(), dur.parse().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_server_timing_reflects_durations() {
+        let now = Instant::now();
+        let mut ctx = RequestContext::new();
+        ctx.start_time = now - Duration::from_millis(50);
+        ctx.upstream_start = Some(now - Duration::from_millis(30));
+
+        let timings = durations(&server_timing_value(&ctx, now));
+        assert_eq!(timings[0].0, "upstream");
+        assert!((timings[0].1 - 30.0).abs() < 0.1);
+        assert_eq!(timings[1].0, "total");
+        assert!((timings[1].1 - 50.0).abs() < 0.1);
+
+        ctx.upstream_queue_wait = Some(Duration::from_millis(15));
+        let timings = durations(&server_timing_value(&ctx, now));
+        assert_eq!(timings[0].0, "queue");
+        assert!((timings[0].1 - 15.0).abs() < 0.1);
+        assert_eq!(timings[1].0, "upstream");
+
+        ctx.upstream_queue_wait = None;
+        ctx.upstream_start = None;
+        assert_eq!(server_timing_value(&ctx, now), "total;dur=50.0");
+    }
+
+    #[test]
+    fn test_timing_headers_config_gated() {
+        let ctx = RequestContext::new();
+        let mut response = ResponseHeader::build(200, None).unwrap();
+        add_timing_headers(&mut response, &ResponseHeadersConfig::default(), &ctx).unwrap();
+        assert!(response.headers.get("server-timing").is_none());
+        assert!(response.headers.get("alt-svc").is_none());
+
+        let mut ctx = ctx;
+        ctx.upstream_addr = Some("10.0.0.7:8080".to_string());
+        add_timing_headers(&mut response, &ResponseHeadersConfig::default(), &ctx).unwrap();
+        assert!(response.headers.get("x-upstream-addr").is_none());
+
+        let config = ResponseHeadersConfig {
+            server_timing: true,
+            alt_svc: Some("h3=\":443\"; ma=86400".to_string()),
+            upstream_addr: Some("X-Upstream-Addr".to_string()),
+        };
+        add_timing_headers(&mut response, &config, &ctx).unwrap();
+        assert!(response.headers.get("server-timing").is_some());
+        assert_eq!(response.headers.get("alt-svc").unwrap(), "h3=\":443\"; ma=86400");
+        assert_eq!(response.headers.get("x-upstream-addr").unwrap(), "10.0.0.7:8080");
+    }
+}
@@ -0,0 +1,84 @@
+use http::HeaderMap;
+use pingora::http::{RequestHeader, ResponseHeader};
+
+/// Поля, которые не передаются в трейлерах (RFC 9110 6.5.1): кадрирование, содержимое
+/// и hop-by-hop заголовки
+const FORBIDDEN: [&str; 12] = [
+    "content-length",
+    "content-encoding",
+    "content-range",
+    "content-type",
+    "transfer-encoding",
+    "trailer",
+    "host",
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "upgrade",
+];
+
+fn is_grpc_content_type(content_type: Option<&str>) -> bool {
+    // gRPC-Web передает трейлеры в теле ответа
+    content_type.is_some_and(|value| {
+        let value = value.trim().to_ascii_lowercase();
+        value.starts_with("application/grpc") && !value.starts_with("application/grpc-web")
+    })
+}
+
+/// gRPC запрос (application/grpc, не gRPC-Web): к upstream нужен HTTP/2, статус вызова
+/// приходит в трейлерах
+pub fn is_grpc(req: &RequestHeader) -> bool {
+    is_grpc_content_type(req.headers.get("content-type").and_then(|v| v.to_str().ok()))
+}
+
+/// Ответ upstream, за телом которого могут следовать трейлеры: gRPC или объявленный
+/// заголовок Trailer
+pub fn may_have_trailers(resp: &ResponseHeader) -> bool {
+    resp.headers.contains_key("trailer")
+        || is_grpc_content_type(resp.headers.get("content-type").and_then(|v| v.to_str().ok()))
+}
+
+/// Убирает из трейлеров upstream поля, недопустимые в трейлерах
+pub fn sanitize(trailers: &mut HeaderMap) {
+    for name in FORBIDDEN {
+        trailers.remove(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_trailer_responses() {
+        let mut req = RequestHeader::build("POST", b"/adq.campaigns.v1.Campaigns/Get", None).unwrap();
+        req.insert_header("Content-Type", "application/grpc+proto").unwrap();
+        assert!(is_grpc(&req));
+        req.insert_header("Content-Type", "application/grpc-web+proto").unwrap();
+        assert!(!is_grpc(&req));
+
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("Content-Type", "application/json").unwrap();
+        assert!(!may_have_trailers(&resp));
+        resp.insert_header("Trailer", "Server-Timing").unwrap();
+        assert!(may_have_trailers(&resp));
+
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("Content-Type", "application/grpc").unwrap();
+        assert!(may_have_trailers(&resp));
+    }
+
+    #[test]
+    fn test_sanitize_keeps_grpc_status() {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", "0".parse().unwrap());
+        trailers.insert("grpc-message", "".parse().unwrap());
+        trailers.insert("content-length", "10".parse().unwrap());
+        trailers.insert("connection", "close".parse().unwrap());
+        sanitize(&mut trailers);
+        assert_eq!(trailers.get("grpc-status").unwrap(), "0");
+        assert!(trailers.contains_key("grpc-message"));
+        assert_eq!(trailers.len(), 2);
+    }
+}
@@ -1,3 +1,18 @@
+use bytes::Bytes;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use crate::body_transform::BodyCollector;
+use crate::buffering::ResponseBuffer;
+use crate::circuit_breaker::ProbeToken;
+use crate::compression::{Negotiated, ResponseCompressor};
+use crate::experiments::Assignment;
+use crate::first_byte::FirstByteTimer;
+use crate::concurrency::UpstreamPermit;
+use crate::idempotency::IdempotencyGuard;
+use crate::logging::UpstreamDecision;
+use crate::rate_limit::zones::ConnPermit;
+use crate::config::UpstreamTimeouts;
+
 /// Типы сервисов для маршрутизации
 #[derive(Debug, PartialEq)]
 pub enum ServiceType {
@@ -10,28 +25,239 @@ pub enum ServiceType {
     Static,
 }
 
+impl ServiceType {
+    /// Имя сервиса для метрик и логов
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServiceType::CoreApi => "core_api",
+            ServiceType::ChallengeApi => "challenge_api",
+            ServiceType::BillingApi => "billing_api",
+            ServiceType::ErirApi => "erir_api",
+            ServiceType::SharedApi => "shared_api",
+            ServiceType::ZitadelAuth => "zitadel_auth",
+            ServiceType::Static => "static",
+        }
+    }
+
+    /// Тип сервиса по имени upstream из proxy_pass; прочие upstream считаются API
+    pub fn from_upstream(name: &str) -> Self {
+        match name {
+            "challenge_api" => ServiceType::ChallengeApi,
+            "billing_api" => ServiceType::BillingApi,
+            "erir_api" => ServiceType::ErirApi,
+            "shared_api" => ServiceType::SharedApi,
+            "zitadel_auth" => ServiceType::ZitadelAuth,
+            _ => ServiceType::CoreApi,
+        }
+    }
+}
+
+/// Куда проксируется запрос
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpstreamTarget {
+    /// Upstream с балансировкой нагрузки
+    Named(String),
+    /// Фиксированный адрес сервиса
+    Direct(SocketAddr),
+    /// Unix сокет из proxy_pass (`http[s]://unix:/path.sock`)
+    Unix { path: String, tls: bool },
+    /// Запрос обрабатывается без upstream (статика)
+    None,
+}
+
+impl fmt::Display for UpstreamTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpstreamTarget::Named(name) => write!(f, "{}", name),
+            UpstreamTarget::Direct(addr) => write!(f, "{}", addr),
+            UpstreamTarget::Unix { path, .. } => write!(f, "unix:{}", path),
+            UpstreamTarget::None => write!(f, "-"),
+        }
+    }
+}
+
+/// Кто сформировал ответ на запрос
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HandledBy {
+    /// Ответ получен от upstream
+    Upstream,
+    /// Ответ сформирован самим прокси (preflight, статика, редирект, ошибки)
+    ProxyLocal,
+}
+
+impl HandledBy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HandledBy::Upstream => "upstream",
+            HandledBy::ProxyLocal => "proxy_local",
+        }
+    }
+}
+
 /// Контекст запроса
 #[derive(Debug)]
 pub struct RequestContext {
+    /// Уникальный идентификатор запроса
+    pub request_id: String,
     pub service_type: ServiceType,
-    pub upstream_host: String,
-    pub upstream_port: u16,
+    /// Upstream, выбранный маршрутизацией
+    pub upstream_target: UpstreamTarget,
+    /// SNI для TLS до upstream из `proxy_pass https://host` (None - без TLS, пустой - без SNI)
+    pub upstream_tls: Option<String>,
     /// Количество попыток retry
     pub retries: u32,
     /// Время начала запроса для измерения длительности
     pub start_time: std::time::Instant,
+    /// Время начала обращения к upstream (последней попытки)
+    pub upstream_start: Option<std::time::Instant>,
+    /// Имя upstream из proxy_pass найденного location
+    pub upstream_name: Option<String>,
+    /// Таймауты upstream для запроса (location или глобальные)
+    pub upstream_timeouts: Option<UpstreamTimeouts>,
+    /// Момент, после которого обращение к upstream прерывается с 504 (proxy_request_timeout)
+    pub upstream_deadline: Option<std::time::Instant>,
+    /// Таймер ожидания заголовков ответа в текущей попытке (proxy_first_byte_timeout)
+    pub first_byte_timer: Option<FirstByteTimer>,
+    /// Имя профиля бэкенда (backend_profiles), определенного при маршрутизации
+    pub backend_profile: Option<String>,
+    /// Получены ли заголовки ответа от upstream
+    pub upstream_response_received: bool,
+    /// Статус ответа upstream в текущей попытке (None - ответ из кеша или ответа еще нет)
+    pub upstream_status: Option<u16>,
+    /// Адрес выбранного бэкенда (для учета in-flight запросов)
+    pub selected_backend: Option<String>,
+    /// Слот upstream с max_conns, занятый на время запроса
+    pub upstream_permit: Option<UpstreamPermit>,
+    /// Слот пробного запроса circuit breaker в HalfOpen, пока результат не записан
+    pub circuit_probe: Option<ProbeToken>,
+    /// Слоты зон limit_conn, занятые на время запроса
+    pub conn_permits: Vec<ConnPermit>,
+    /// Время ожидания слота upstream в очереди (queue), для Server-Timing
+    pub upstream_queue_wait: Option<std::time::Duration>,
+    /// Бэкенды предыдущих попыток (proxy_next_upstream выбирает другой)
+    pub tried_backends: Vec<String>,
+    /// Адрес бэкенда последней попытки (upstream_addr в access log)
+    pub upstream_addr: Option<String>,
+    /// Выбор бэкенда для decisions log (только запросы из выборки)
+    pub upstream_decision: Option<UpstreamDecision>,
+    /// Тело страницы ошибки, заменяющее тело перехваченного ответа upstream
+    pub intercepted_body: Option<Bytes>,
+    /// Буфер тела ответа upstream (proxy_buffering on)
+    pub response_buffer: Option<ResponseBuffer>,
+    /// Сохранение ответа для повторов с тем же ключом идемпотентности
+    pub idempotency: Option<IdempotencyGuard>,
+    /// Преобразование JSON тела запроса перед отправкой upstream (redact on)
+    pub request_transform: Option<BodyCollector>,
+    /// Преобразование JSON тела ответа перед отправкой клиенту (redact on)
+    pub response_transform: Option<BodyCollector>,
+    /// Кодировка ответа, согласованная по Accept-Encoding (None - сжатие выключено)
+    pub negotiated_encoding: Option<Negotiated>,
+    /// Сжатие тела ответа для клиента
+    pub response_compressor: Option<ResponseCompressor>,
+    /// Запрос gRPC-Web преобразован bridge в gRPC; трейлеры ответа идут клиенту в теле
+    pub grpc_web: bool,
+    /// Группы экспериментов, переданные upstream
+    pub experiments: Vec<Assignment>,
+    /// Схема запроса клиента (http или https) с учетом доверенных прокси
+    pub scheme: &'static str,
+    /// Адрес клиента с учетом доверенных прокси (proxy_headers.real_ip_from)
+    pub client_ip: Option<IpAddr>,
+    /// Клиент закрыл соединение до окончания ответа
+    pub client_aborted: bool,
+    /// Кто сформировал ответ
+    pub handled_by: HandledBy,
+    /// Маршрут локального ответа (cors_preflight, static, redirect, ip_filter, rate_limit)
+    pub local_route: Option<&'static str>,
+    /// Соединение закрыто без ответа (статус 444)
+    pub connection_closed: bool,
+    /// Соединение клиента остается открытым после ответа (keep-alive)
+    pub downstream_keepalive: bool,
 }
 
 impl RequestContext {
     pub fn new() -> Self {
         Self {
+            request_id: uuid::Uuid::new_v4().to_string(),
             service_type: ServiceType::Static,
-            upstream_host: String::new(),
-            upstream_port: 0,
+            upstream_target: UpstreamTarget::None,
+            upstream_tls: None,
             retries: 0,
             start_time: std::time::Instant::now(),
+            upstream_start: None,
+            upstream_name: None,
+            upstream_timeouts: None,
+            upstream_deadline: None,
+            first_byte_timer: None,
+            backend_profile: None,
+            upstream_response_received: false,
+            upstream_status: None,
+            selected_backend: None,
+            upstream_permit: None,
+            circuit_probe: None,
+            conn_permits: Vec::new(),
+            upstream_queue_wait: None,
+            tried_backends: Vec::new(),
+            upstream_addr: None,
+            upstream_decision: None,
+            intercepted_body: None,
+            response_buffer: None,
+            idempotency: None,
+            request_transform: None,
+            response_transform: None,
+            negotiated_encoding: None,
+            response_compressor: None,
+            grpc_web: false,
+            experiments: Vec::new(),
+            scheme: "http",
+            client_ip: None,
+            client_aborted: false,
+            handled_by: HandledBy::Upstream,
+            local_route: None,
+            connection_closed: false,
+            downstream_keepalive: true,
+        }
+    }
+
+    /// Помечает запрос как обработанный самим прокси без обращения к upstream
+    pub fn handle_locally(&mut self, route: &'static str) {
+        self.handled_by = HandledBy::ProxyLocal;
+        self.local_route = Some(route);
+    }
+
+    /// Метка сервиса для метрик: `local` для ответов, сформированных прокси
+    pub fn service_label(&self) -> &'static str {
+        match self.handled_by {
+            HandledBy::ProxyLocal => "local",
+            HandledBy::Upstream => self.service_type.as_str(),
         }
     }
+
+    /// Освобождает буферизованное состояние запроса: резерв общего лимита
+    /// proxy_buffering и незавершенный ключ идемпотентности
+    pub fn release_buffered_state(&mut self) {
+        self.response_buffer = None;
+        self.idempotency = None;
+        self.intercepted_body = None;
+        self.response_transform = None;
+    }
+
+    /// Оставшееся до upstream_deadline время; None - request timeout не задан
+    pub fn upstream_time_left(&self, now: std::time::Instant) -> Option<std::time::Duration> {
+        self.upstream_deadline.map(|deadline| deadline.saturating_duration_since(now))
+    }
+
+    /// Истекло ли время обращения к upstream (proxy_request_timeout)
+    pub fn upstream_deadline_exceeded(&self) -> bool {
+        self.upstream_time_left(std::time::Instant::now())
+            .is_some_and(|left| left.is_zero())
+    }
+
+    /// Имя upstream для метрик и ошибок: proxy_pass location или тип сервиса
+    pub fn upstream_label(&self) -> &str {
+        self.upstream_name
+            .as_deref()
+            .unwrap_or_else(|| self.service_type.as_str())
+    }
 }
 
 impl Default for RequestContext {
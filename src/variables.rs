@@ -0,0 +1,310 @@
+use pingora::http::RequestHeader;
+use regex::Captures;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use crate::config::MapBlock;
+
+/// Встроенные переменные; кроме них доступны заголовки запроса `$http_<имя>`
+/// и аргументы строки запроса `$arg_<имя>`
+pub const BUILTIN_VARIABLES: [&str; 6] = ["host", "remote_addr", "scheme", "uri", "request_uri", "args"];
+
+/// Глубина вложенности map (map от результата другого map)
+const MAX_MAP_DEPTH: usize = 8;
+
+/// Встроенная переменная, заголовок или аргумент запроса
+pub fn is_builtin(name: &str) -> bool {
+    BUILTIN_VARIABLES.contains(&name)
+        || name.strip_prefix("http_").is_some_and(|header| !header.is_empty())
+        || name.strip_prefix("arg_").is_some_and(|arg| !arg.is_empty())
+}
+
+/// Часть значения директивы: текст или переменная (`$name`, `${name}`)
+#[derive(Debug, PartialEq)]
+enum Part<'a> {
+    Text(&'a str),
+    Variable(&'a str),
+}
+
+fn parts(template: &str) -> Vec<Part<'_>> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(dollar) = rest.find('$') {
+        if dollar > 0 {
+            parts.push(Part::Text(&rest[..dollar]));
+        }
+        let after = &rest[dollar + 1..];
+        let (name, len) = match after.strip_prefix('{').and_then(|braced| braced.find('}').map(|end| &braced[..end])) {
+            Some(name) => (name, name.len() + 2),
+            None => {
+                let end = after.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(after.len());
+                (&after[..end], end)
+            }
+        };
+        if name.is_empty() {
+            parts.push(Part::Text("$"));
+        } else {
+            parts.push(Part::Variable(name));
+        }
+        rest = &after[len..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest));
+    }
+    parts
+}
+
+/// Переменные в значении директивы (без групп регулярных выражений `$1`)
+pub fn referenced(template: &str) -> Vec<&str> {
+    parts(template)
+        .into_iter()
+        .filter_map(|part| match part {
+            Part::Variable(name) if !name.bytes().all(|b| b.is_ascii_digit()) => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Переменные запроса. Значения map вычисляются при первом обращении
+/// и запоминаются до конца запроса
+pub struct RequestVariables<'a> {
+    req: &'a RequestHeader,
+    host: &'a str,
+    remote_addr: Option<IpAddr>,
+    scheme: &'a str,
+    maps: Option<&'a HashMap<String, MapBlock>>,
+    evaluated: HashMap<&'a str, String>,
+}
+
+impl<'a> RequestVariables<'a> {
+    pub fn new(
+        req: &'a RequestHeader,
+        host: &'a str,
+        remote_addr: Option<IpAddr>,
+        scheme: &'a str,
+        maps: Option<&'a HashMap<String, MapBlock>>,
+    ) -> Self {
+        Self { req, host, remote_addr, scheme, maps, evaluated: HashMap::new() }
+    }
+
+    /// Значение переменной; неизвестная переменная - пустая строка
+    pub fn get(&mut self, name: &str) -> String {
+        self.lookup(name, 0)
+    }
+
+    /// Подставляет переменные в значение директивы
+    pub fn expand(&mut self, template: &str) -> String {
+        self.expand_with(template, None, 0)
+    }
+
+    fn lookup(&mut self, name: &str, depth: usize) -> String {
+        let maps = self.maps;
+        let Some((variable, map)) = maps.and_then(|maps| maps.get_key_value(name)) else {
+            return self.builtin(name).unwrap_or_default();
+        };
+        if let Some(value) = self.evaluated.get(variable.as_str()) {
+            return value.clone();
+        }
+        if depth >= MAX_MAP_DEPTH {
+            log::warn!("map ${} is nested too deeply, using an empty value", name);
+            return String::new();
+        }
+
+        let source = self.lookup(&map.source, depth + 1);
+        let (result, captures) = map.find(&source);
+        let value = self.expand_with(result, captures.as_ref(), depth + 1);
+        self.evaluated.insert(variable.as_str(), value.clone());
+        value
+    }
+
+    fn expand_with(&mut self, template: &str, captures: Option<&Captures>, depth: usize) -> String {
+        let mut value = String::new();
+        for part in parts(template) {
+            match part {
+                Part::Text(text) => value.push_str(text),
+                // Группы регулярного выражения map: $1, $name
+                Part::Variable(name) if captures.is_some_and(|c| group(c, name).is_some()) => {
+                    value.push_str(captures.and_then(|c| group(c, name)).unwrap_or_default());
+                }
+                Part::Variable(name) if name.bytes().all(|b| b.is_ascii_digit()) => {}
+                Part::Variable(name) => value.push_str(&self.lookup(name, depth)),
+            }
+        }
+        value
+    }
+
+    fn builtin(&self, name: &str) -> Option<String> {
+        match name {
+            "host" => Some(self.host.split(':').next().unwrap_or_default().to_ascii_lowercase()),
+            "remote_addr" => self.remote_addr.map(|ip| ip.to_string()),
+            "scheme" => Some(self.scheme.to_string()),
+            "uri" => Some(self.req.uri.path().to_string()),
+            "request_uri" => Some(self.req.uri.path_and_query().map_or("/", |pq| pq.as_str()).to_string()),
+            "args" => Some(self.req.uri.query().unwrap_or_default().to_string()),
+            _ if name.starts_with("arg_") => {
+                // Первое вхождение аргумента, без декодирования, как в nginx
+                let arg = &name["arg_".len()..];
+                self.req.uri.query()?.split('&').find_map(|pair| {
+                    let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                    (key == arg).then(|| value.to_string())
+                })
+            }
+            _ => {
+                let header = name.strip_prefix("http_")?.replace('_', "-");
+                self.req.headers.get(header.as_str()).and_then(|v| v.to_str().ok()).map(str::to_string)
+            }
+        }
+    }
+}
+
+fn group<'t>(captures: &Captures<'t>, name: &str) -> Option<&'t str> {
+    match name.parse::<usize>() {
+        Ok(index) => captures.get(index),
+        Err(_) => captures.name(name),
+    }
+    .map(|m| m.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NginxConfig;
+
+    fn config() -> NginxConfig {
+        NginxConfig::parse_config_content(r#"
+            map $http_origin $cors_origin {
+                default "";
+                https://ad-quest.ru $http_origin;
+                ~^https://[a-z0-9-]+\.ad-quest\.ru$ $http_origin;
+            }
+
+            map $host $tenant {
+                default public;
+                "~^(?<name>[a-z0-9-]+)\.tenants\.ad-quest\.ru$" tenant-$name;
+                ~*^API\.(.+)$ api-$1;
+            }
+
+            map $tenant $tenant_upstream {
+                default core_api;
+                ~^tenant- tenants_api;
+            }
+
+            server {
+                listen 80;
+                server_name api.ad-quest.ru;
+
+                location /api/ {
+                    proxy_pass core_api;
+                    add_header Access-Control-Allow-Origin $cors_origin always;
+                    add_header X-Served-By "adq; edge";
+                    proxy_set_header X-Tenant $tenant;
+                    proxy_set_header X-Debug "";
+                }
+
+                location /old {
+                    return 301 https://$host/new$uri;
+                }
+
+                location /blocked {
+                    return 403 "blocked for $remote_addr";
+                }
+            }
+        "#).unwrap()
+    }
+
+    fn request(headers: &[(&str, &str)]) -> RequestHeader {
+        let mut req = RequestHeader::build("GET", b"/api/campaigns?page=2", None).unwrap();
+        for (name, value) in headers {
+            req.insert_header(name.to_string(), *value).unwrap();
+        }
+        req
+    }
+
+    #[test]
+    fn test_parse_maps_and_directives() {
+        let config = config();
+        let cors = &config.maps["cors_origin"];
+        assert_eq!(cors.source, "http_origin");
+        assert_eq!(cors.default, "");
+        assert_eq!(cors.exact["https://ad-quest.ru"], "$http_origin");
+        assert_eq!(cors.patterns.len(), 1);
+        assert_eq!(config.maps["tenant"].patterns.len(), 2);
+
+        let locations = &config.servers[0].locations;
+        assert_eq!(locations[0].add_header.len(), 2);
+        assert!(locations[0].add_header[0].always);
+        assert_eq!(locations[0].add_header[1].value, "adq; edge");
+        assert_eq!(locations[0].proxy_set_header, vec![
+            ("X-Tenant".to_string(), "$tenant".to_string()),
+            ("X-Debug".to_string(), String::new()),
+        ]);
+        let redirect = locations[1].return_directive.as_ref().unwrap();
+        assert_eq!((redirect.status, redirect.value.as_deref()), (301, Some("https://$host/new$uri")));
+        assert!(redirect.is_redirect());
+        assert_eq!(locations[2].return_directive.as_ref().unwrap().value.as_deref(), Some("blocked for $remote_addr"));
+
+        assert!(NginxConfig::parse_config_content("map $http_origin $x { default; }").is_err());
+        assert!(NginxConfig::parse_config_content("map $http_origin $x { ~[ bad; }").is_err());
+        assert!(NginxConfig::parse_config_content("map $host $x { default a; }\nmap $uri $x { default b; }").is_err());
+    }
+
+    #[test]
+    fn test_map_evaluation() {
+        let config = config();
+        let maps = Some(&config.maps);
+        let ip = Some("198.51.100.7".parse().unwrap());
+
+        let req = request(&[("Origin", "https://app.ad-quest.ru")]);
+        let mut vars = RequestVariables::new(&req, "api.ad-quest.ru:443", ip, "https", maps);
+        assert_eq!(vars.get("cors_origin"), "https://app.ad-quest.ru");
+        assert_eq!(vars.expand("https://$host/new$uri"), "https://api.ad-quest.ru/new/api/campaigns");
+        assert_eq!(vars.expand("${scheme}://x $remote_addr $ $unknown."), "https://x 198.51.100.7 $ .");
+
+        let req = request(&[("Origin", "https://evil.example.com")]);
+        let mut vars = RequestVariables::new(&req, "api.ad-quest.ru", ip, "https", maps);
+        assert_eq!(vars.get("cors_origin"), "");
+        let req = request(&[("Origin", "https://ad-quest.ru")]);
+        let mut vars = RequestVariables::new(&req, "api.ad-quest.ru", ip, "https", maps);
+        assert_eq!(vars.get("cors_origin"), "https://ad-quest.ru");
+    }
+
+    #[test]
+    fn test_request_uri_and_args() {
+        let req = RequestHeader::build("GET", b"/api/campaigns?page=2&sort=name&page=3&flag", None).unwrap();
+        let mut vars = RequestVariables::new(&req, "api.ad-quest.ru", None, "https", None);
+        assert_eq!(vars.get("request_uri"), "/api/campaigns?page=2&sort=name&page=3&flag");
+        assert_eq!(vars.get("args"), "page=2&sort=name&page=3&flag");
+        assert_eq!(vars.get("arg_page"), "2");
+        assert_eq!(vars.get("arg_flag"), "");
+        assert_eq!(vars.get("arg_missing"), "");
+
+        let req = RequestHeader::build("GET", b"/health", None).unwrap();
+        let mut vars = RequestVariables::new(&req, "api.ad-quest.ru", None, "https", None);
+        assert_eq!(vars.expand("$request_uri|$args|$arg_page"), "/health||");
+    }
+
+    #[test]
+    fn test_regex_capture_passthrough() {
+        let config = config();
+        let req = request(&[]);
+        let tenant = |host: &str| {
+            let mut vars = RequestVariables::new(&req, host, None, "http", Some(&config.maps));
+            (vars.get("tenant"), vars.get("tenant_upstream"))
+        };
+        assert_eq!(tenant("acme.tenants.ad-quest.ru"), ("tenant-acme".to_string(), "tenants_api".to_string()));
+        // ~* - без учета регистра, $host приводится к нижнему регистру
+        assert_eq!(tenant("API.ad-quest.ru"), ("api-ad-quest.ru".to_string(), "core_api".to_string()));
+        assert_eq!(tenant("ad-quest.ru"), ("public".to_string(), "core_api".to_string()));
+    }
+
+    #[test]
+    fn test_referenced_variables() {
+        assert_eq!(referenced("https://$host${uri}?x=$1"), vec!["host", "uri"]);
+        assert!(is_builtin("http_x_api_key"));
+        assert!(!is_builtin("http_"));
+        assert!(is_builtin("request_uri"));
+        assert!(is_builtin("arg_page"));
+        assert!(!is_builtin("arg_"));
+        assert!(!is_builtin("query_string"));
+    }
+}
@@ -0,0 +1,132 @@
+use async_trait::async_trait;
+use log::{info, warn};
+use pingora_core::server::ShutdownWatch;
+use pingora_core::services::background::BackgroundService;
+use pingora_load_balancing::{selection::RoundRobin, LoadBalancer};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+
+use crate::metrics::UPSTREAM_WARMUP_CONNECTIONS;
+
+/// Пауза перед прогревом: первый раунд TCP health checks (таймаут соединения 1s)
+/// успевает отметить недоступные бэкенды
+const HEALTH_CHECK_GRACE: Duration = Duration::from_secs(2);
+
+/// Таймаут установки одного соединения прогрева
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Открывает `connections` соединений с бэкендом параллельно; возвращает число успешных.
+/// Соединения закрываются сразу после установки
+pub async fn preconnect(addr: SocketAddr, connections: usize, timeout: Duration) -> usize {
+    let mut attempts = JoinSet::new();
+    for _ in 0..connections {
+        attempts.spawn(tokio::time::timeout(timeout, TcpStream::connect(addr)));
+    }
+    let mut connected = 0;
+    while let Some(result) = attempts.join_next().await {
+        if matches!(result, Ok(Ok(Ok(_)))) {
+            connected += 1;
+        }
+    }
+    connected
+}
+
+/// Прогрев upstream при старте: после первого раунда health checks открывает
+/// global.upstream_warmup_connections соединений с каждым здоровым бэкендом
+pub struct WarmupService {
+    upstreams: Vec<(String, Arc<LoadBalancer<RoundRobin>>)>,
+    connections: usize,
+}
+
+impl WarmupService {
+    pub fn new(upstreams: Vec<(String, Arc<LoadBalancer<RoundRobin>>)>, connections: usize) -> Self {
+        Self { upstreams, connections }
+    }
+
+    /// Прогревает все здоровые бэкенды; возвращает число установленных соединений
+    pub async fn warm_up(&self) -> usize {
+        let mut established = 0;
+        for (upstream, lb) in &self.upstreams {
+            let backends = lb.backends();
+            let healthy = backends.get_backend().iter().filter(|backend| backends.ready(backend)).cloned().collect::<Vec<_>>();
+            for backend in healthy {
+                let Some(addr) = backend.addr.as_inet().copied() else { continue };
+                let connected = preconnect(addr, self.connections, CONNECT_TIMEOUT).await;
+                let failed = self.connections - connected;
+                UPSTREAM_WARMUP_CONNECTIONS.with_label_values(&[upstream, "success"]).inc_by(connected as u64);
+                UPSTREAM_WARMUP_CONNECTIONS.with_label_values(&[upstream, "failure"]).inc_by(failed as u64);
+                if failed > 0 {
+                    warn!("Warmup of upstream '{}' backend {}: {} of {} connections failed", upstream, addr, failed, self.connections);
+                }
+                established += connected;
+            }
+        }
+        established
+    }
+}
+
+#[async_trait]
+impl BackgroundService for WarmupService {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        tokio::select! {
+            _ = tokio::time::sleep(HEALTH_CHECK_GRACE) => {}
+            _ = shutdown.changed() => return,
+        }
+        let established = self.warm_up().await;
+        info!("Upstream warmup established {} connection(s)", established);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::TcpListener;
+
+    /// Бэкенд-заглушка, считающий принятые соединения
+    async fn stub_backend() -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            while listener.accept().await.is_ok() {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        (addr, accepted)
+    }
+
+    async fn wait_for(accepted: &AtomicUsize, expected: usize) {
+        for _ in 0..100 {
+            if accepted.load(Ordering::SeqCst) >= expected {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_preconnects_to_each_backend() {
+        let (first, first_accepted) = stub_backend().await;
+        let (second, second_accepted) = stub_backend().await;
+        let lb = LoadBalancer::try_from_iter([first.to_string(), second.to_string()]).unwrap();
+        let warmup = WarmupService::new(vec![("core_api".to_string(), Arc::new(lb))], 3);
+
+        assert_eq!(warmup.warm_up().await, 6);
+        wait_for(&first_accepted, 3).await;
+        wait_for(&second_accepted, 3).await;
+        assert_eq!(first_accepted.load(Ordering::SeqCst), 3);
+        assert_eq!(second_accepted.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_backend_counts_failures() {
+        // Порт освобожден: соединения отклоняются
+        let addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        assert_eq!(preconnect(addr, 2, Duration::from_millis(200)).await, 0);
+    }
+}
@@ -0,0 +1,440 @@
+// Без feature wasm-filters фильтры не выполняются: типы ABI остаются только для конфигурации
+#![cfg_attr(not(feature = "wasm-filters"), allow(dead_code))]
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::config::{parse_size, Config, WasmFiltersConfig};
+
+/// Решение фильтра по запросу
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterDecision {
+    /// Передать запрос дальше (с изменениями заголовков)
+    Continue,
+    /// Ответить клиенту, не проксируя запрос
+    Respond { status: u16, body: Vec<u8> },
+}
+
+/// Данные запроса, доступные фильтру
+#[derive(Debug, Clone, Default)]
+pub struct FilterInput {
+    pub host: String,
+    pub path: String,
+    /// Заголовки запроса; имена в нижнем регистре
+    pub headers: Vec<(String, String)>,
+}
+
+/// Результат работы фильтра
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterOutput {
+    /// Изменения заголовков запроса по порядку: значение - установить, None - удалить
+    pub header_changes: Vec<(String, Option<String>)>,
+    pub decision: FilterDecision,
+}
+
+/// Ограничения выполнения одного вызова фильтра
+#[derive(Debug, Clone, Copy)]
+pub struct FilterLimits {
+    pub timeout: Duration,
+    pub memory: usize,
+}
+
+impl FilterLimits {
+    pub fn from_config(config: &WasmFiltersConfig) -> Result<Self, String> {
+        config.validate()?;
+        Ok(Self {
+            timeout: Duration::from_millis(config.timeout_ms),
+            memory: parse_size(&config.memory_limit).unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(not(feature = "wasm-filters"))]
+const NOT_SUPPORTED: &str = "wasm_filter requires a build with the wasm-filters feature";
+
+/// Модули фильтров всех location конфигурации, скомпилированные при загрузке.
+/// Перезагрузка конфигурации собирает реестр заново, поэтому измененные файлы подхватываются
+pub struct WasmFilters {
+    #[cfg(feature = "wasm-filters")]
+    modules: HashMap<String, runtime::WasmFilter>,
+    /// Модули, которые не удалось загрузить: путь -> ошибка
+    errors: HashMap<String, String>,
+}
+
+impl WasmFilters {
+    pub fn from_config(config: &Config) -> Self {
+        let mut filters = Self {
+            #[cfg(feature = "wasm-filters")]
+            modules: HashMap::new(),
+            errors: HashMap::new(),
+        };
+        let limits = FilterLimits::from_config(&config.wasm_filters);
+        for path in filter_paths(config) {
+            match limits.clone().and_then(|limits| load(&path, limits)) {
+                #[cfg(feature = "wasm-filters")]
+                Ok(filter) => {
+                    filters.modules.insert(path, filter);
+                }
+                #[cfg(not(feature = "wasm-filters"))]
+                Ok(()) => {}
+                Err(e) => {
+                    filters.errors.insert(path, e);
+                }
+            }
+        }
+        filters
+    }
+
+    /// Ошибки загрузки модулей: путь -> ошибка
+    pub fn errors(&self) -> &HashMap<String, String> {
+        &self.errors
+    }
+
+    /// Выполняет фильтр модуля `path` для запроса
+    pub fn run(&self, path: &str, input: FilterInput) -> Result<FilterOutput, String> {
+        if let Some(e) = self.errors.get(path) {
+            return Err(e.clone());
+        }
+        #[cfg(feature = "wasm-filters")]
+        {
+            let filter = self.modules.get(path).ok_or_else(|| format!("wasm filter not loaded: {}", path))?;
+            filter.run(input)
+        }
+        #[cfg(not(feature = "wasm-filters"))]
+        {
+            let _ = input;
+            Err(NOT_SUPPORTED.to_string())
+        }
+    }
+}
+
+/// Пути модулей из директив wasm_filter без повторов
+fn filter_paths(config: &Config) -> Vec<String> {
+    let mut paths: Vec<String> = config
+        .nginx_config
+        .iter()
+        .flat_map(|nginx| &nginx.servers)
+        .flat_map(|server| &server.locations)
+        .filter_map(|location| location.wasm_filter.as_ref())
+        .map(|filter| filter.path.clone())
+        .collect();
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// Есть ли в конфигурации location с wasm_filter
+pub fn is_configured(config: &Config) -> bool {
+    !filter_paths(config).is_empty()
+}
+
+#[cfg(feature = "wasm-filters")]
+fn load(path: &str, limits: FilterLimits) -> Result<runtime::WasmFilter, String> {
+    runtime::WasmFilter::from_file(std::path::Path::new(path), limits)
+}
+
+#[cfg(not(feature = "wasm-filters"))]
+fn load(_path: &str, _limits: FilterLimits) -> Result<(), String> {
+    Err(NOT_SUPPORTED.to_string())
+}
+
+/// Проверка модуля для `-t`: файл компилируется и импортирует только функции ABI
+pub fn check_module(path: &str, config: &WasmFiltersConfig) -> Result<(), String> {
+    let limits = FilterLimits::from_config(config)?;
+    load(path, limits).map(|_| ())
+}
+
+#[cfg(feature = "wasm-filters")]
+mod runtime {
+    use once_cell::sync::Lazy;
+    use std::path::Path;
+    use std::time::Duration;
+    use wasmtime::{Caller, Engine, InstancePre, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+    use super::{FilterDecision, FilterInput, FilterLimits, FilterOutput};
+
+    /// Период счетчика эпох; таймаут фильтра считается в эпохах
+    const EPOCH_TICK: Duration = Duration::from_millis(1);
+
+    /// Пространство имен импортируемых функций ABI
+    const ABI_MODULE: &str = "adq";
+
+    /// Общий движок с прерыванием по эпохам; эпоху увеличивает отдельный поток
+    static ENGINE: Lazy<Engine> = Lazy::new(|| {
+        let mut config = wasmtime::Config::new();
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config).expect("failed to create wasm engine");
+        let ticker = engine.clone();
+        std::thread::Builder::new()
+            .name("wasm-epoch".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(EPOCH_TICK);
+                ticker.increment_epoch();
+            })
+            .expect("failed to start wasm epoch thread");
+        engine
+    });
+
+    /// Состояние одного вызова фильтра
+    struct HostState {
+        input: FilterInput,
+        output: FilterOutput,
+        limits: StoreLimits,
+    }
+
+    /// Скомпилированный модуль фильтра с привязанными функциями ABI
+    pub struct WasmFilter {
+        pre: InstancePre<HostState>,
+        limits: FilterLimits,
+    }
+
+    impl WasmFilter {
+        pub fn from_file(path: &Path, limits: FilterLimits) -> Result<Self, String> {
+            let module = Module::from_file(&ENGINE, path).map_err(|e| format!("{}: {:#}", path.display(), e))?;
+            Self::from_module(module, limits).map_err(|e| format!("{}: {}", path.display(), e))
+        }
+
+        /// Модуль из байтов .wasm или текста .wat
+        pub fn from_bytes(bytes: &[u8], limits: FilterLimits) -> Result<Self, String> {
+            let module = Module::new(&ENGINE, bytes).map_err(|e| format!("{:#}", e))?;
+            Self::from_module(module, limits)
+        }
+
+        fn from_module(module: Module, limits: FilterLimits) -> Result<Self, String> {
+            if module.get_export("on_request").is_none() {
+                return Err("wasm filter does not export on_request".to_string());
+            }
+            let linker = linker().map_err(|e| format!("{:#}", e))?;
+            let pre = linker.instantiate_pre(&module).map_err(|e| format!("{:#}", e))?;
+            Ok(Self { pre, limits })
+        }
+
+        /// Новый экземпляр на каждый запрос: состояние между запросами не сохраняется
+        pub fn run(&self, input: FilterInput) -> Result<FilterOutput, String> {
+            let state = HostState {
+                input,
+                output: FilterOutput { header_changes: Vec::new(), decision: FilterDecision::Continue },
+                limits: StoreLimitsBuilder::new().memory_size(self.limits.memory).instances(1).build(),
+            };
+            let mut store = Store::new(&ENGINE, state);
+            store.limiter(|state| &mut state.limits);
+            let ticks = self.limits.timeout.as_millis().div_ceil(EPOCH_TICK.as_millis()).max(1) as u64;
+            store.set_epoch_deadline(ticks);
+
+            let instance = self.pre.instantiate(&mut store).map_err(|e| format!("{:#}", e))?;
+            let on_request = instance
+                .get_typed_func::<(), ()>(&mut store, "on_request")
+                .map_err(|e| format!("{:#}", e))?;
+            on_request.call(&mut store, ()).map_err(|e| format!("{:#}", e))?;
+            Ok(store.into_data().output)
+        }
+    }
+
+    fn memory(caller: &mut Caller<'_, HostState>) -> wasmtime::Result<wasmtime::Memory> {
+        caller
+            .get_export("memory")
+            .and_then(|export| export.into_memory())
+            .ok_or_else(|| wasmtime::Error::msg("wasm filter does not export memory"))
+    }
+
+    fn read_bytes(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> wasmtime::Result<Vec<u8>> {
+        let memory = memory(caller)?;
+        let start = ptr as u32 as usize;
+        let end = start.saturating_add(len as u32 as usize);
+        memory
+            .data(&*caller)
+            .get(start..end)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| wasmtime::Error::msg("memory access out of bounds"))
+    }
+
+    fn read_string(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> wasmtime::Result<String> {
+        String::from_utf8(read_bytes(caller, ptr, len)?).map_err(|_| wasmtime::Error::msg("string is not valid UTF-8"))
+    }
+
+    /// Копирует значение в буфер фильтра. Возвращает длину значения; если буфер меньше,
+    /// ничего не пишется и фильтр может повторить вызов с буфером нужного размера
+    fn write_bytes(caller: &mut Caller<'_, HostState>, ptr: i32, cap: i32, bytes: &[u8]) -> wasmtime::Result<i32> {
+        if bytes.len() > cap as u32 as usize {
+            return Ok(bytes.len() as i32);
+        }
+        let memory = memory(caller)?;
+        let start = ptr as u32 as usize;
+        memory
+            .data_mut(&mut *caller)
+            .get_mut(start..start + bytes.len())
+            .ok_or_else(|| wasmtime::Error::msg("memory access out of bounds"))?
+            .copy_from_slice(bytes);
+        Ok(bytes.len() as i32)
+    }
+
+    /// Текущее значение заголовка с учетом изменений, сделанных фильтром
+    fn current_header(state: &HostState, name: &str) -> Option<String> {
+        match state.output.header_changes.iter().rev().find(|(key, _)| key.eq_ignore_ascii_case(name)) {
+            Some((_, value)) => value.clone(),
+            None => state
+                .input
+                .headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.clone()),
+        }
+    }
+
+    fn header_name(name: String) -> wasmtime::Result<String> {
+        http::HeaderName::from_bytes(name.as_bytes())
+            .map(|name| name.as_str().to_string())
+            .map_err(|_| wasmtime::Error::msg(format!("invalid header name: {}", name)))
+    }
+
+    /// Функции ABI (модуль "adq"); строки передаются парой (указатель, длина) в памяти фильтра:
+    /// - get_request_header(name_ptr, name_len, buf_ptr, buf_cap) -> длина значения или -1
+    /// - set_request_header(name_ptr, name_len, value_ptr, value_len)
+    /// - remove_request_header(name_ptr, name_len)
+    /// - get_path(buf_ptr, buf_cap) -> длина, get_host(buf_ptr, buf_cap) -> длина
+    /// - respond(status, body_ptr, body_len): ответ клиенту вместо проксирования
+    fn linker() -> wasmtime::Result<Linker<HostState>> {
+        let mut linker = Linker::new(&ENGINE);
+        linker.func_wrap(
+            ABI_MODULE,
+            "get_request_header",
+            |mut caller: Caller<'_, HostState>, name_ptr: i32, name_len: i32, buf_ptr: i32, buf_cap: i32| -> wasmtime::Result<i32> {
+                let name = read_string(&mut caller, name_ptr, name_len)?;
+                match current_header(caller.data(), &name) {
+                    Some(value) => write_bytes(&mut caller, buf_ptr, buf_cap, value.as_bytes()),
+                    None => Ok(-1),
+                }
+            },
+        )?;
+        linker.func_wrap(
+            ABI_MODULE,
+            "set_request_header",
+            |mut caller: Caller<'_, HostState>, name_ptr: i32, name_len: i32, value_ptr: i32, value_len: i32| -> wasmtime::Result<()> {
+                let name = header_name(read_string(&mut caller, name_ptr, name_len)?)?;
+                let value = read_string(&mut caller, value_ptr, value_len)?;
+                if http::HeaderValue::from_str(&value).is_err() {
+                    return Err(wasmtime::Error::msg(format!("invalid value for header {}", name)));
+                }
+                caller.data_mut().output.header_changes.push((name, Some(value)));
+                Ok(())
+            },
+        )?;
+        linker.func_wrap(
+            ABI_MODULE,
+            "remove_request_header",
+            |mut caller: Caller<'_, HostState>, name_ptr: i32, name_len: i32| -> wasmtime::Result<()> {
+                let name = header_name(read_string(&mut caller, name_ptr, name_len)?)?;
+                caller.data_mut().output.header_changes.push((name, None));
+                Ok(())
+            },
+        )?;
+        linker.func_wrap(
+            ABI_MODULE,
+            "get_path",
+            |mut caller: Caller<'_, HostState>, buf_ptr: i32, buf_cap: i32| -> wasmtime::Result<i32> {
+                let path = caller.data().input.path.clone();
+                write_bytes(&mut caller, buf_ptr, buf_cap, path.as_bytes())
+            },
+        )?;
+        linker.func_wrap(
+            ABI_MODULE,
+            "get_host",
+            |mut caller: Caller<'_, HostState>, buf_ptr: i32, buf_cap: i32| -> wasmtime::Result<i32> {
+                let host = caller.data().input.host.clone();
+                write_bytes(&mut caller, buf_ptr, buf_cap, host.as_bytes())
+            },
+        )?;
+        linker.func_wrap(
+            ABI_MODULE,
+            "respond",
+            |mut caller: Caller<'_, HostState>, status: i32, body_ptr: i32, body_len: i32| -> wasmtime::Result<()> {
+                let status = u16::try_from(status)
+                    .ok()
+                    .filter(|status| (200..600).contains(status))
+                    .ok_or_else(|| wasmtime::Error::msg(format!("invalid response status: {}", status)))?;
+                let body = read_bytes(&mut caller, body_ptr, body_len)?;
+                caller.data_mut().output.decision = FilterDecision::Respond { status, body };
+                Ok(())
+            },
+        )?;
+        Ok(linker)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::time::Instant;
+
+        const TENANT_FILTER: &str = include_str!("../examples/wasm-filters/tenant.wat");
+
+        fn limits() -> FilterLimits {
+            FilterLimits { timeout: Duration::from_millis(20), memory: 1024 * 1024 }
+        }
+
+        fn input(headers: &[(&str, &str)]) -> FilterInput {
+            FilterInput {
+                host: "api.example.com".to_string(),
+                path: "/tenants/orders".to_string(),
+                headers: headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            }
+        }
+
+        #[test]
+        fn test_tenant_filter() {
+            let filter = WasmFilter::from_bytes(TENANT_FILTER.as_bytes(), limits()).unwrap();
+
+            let output = filter.run(input(&[("x-tenant", "acme")])).unwrap();
+            assert_eq!(output.decision, FilterDecision::Continue);
+            assert_eq!(output.header_changes, vec![("x-routing-key".to_string(), Some("acme".to_string()))]);
+
+            let output = filter.run(input(&[])).unwrap();
+            assert_eq!(output.decision, FilterDecision::Respond { status: 400, body: b"missing X-Tenant".to_vec() });
+            assert!(output.header_changes.is_empty());
+        }
+
+        #[test]
+        fn test_infinite_loop_interrupted() {
+            let filter = WasmFilter::from_bytes(
+                b"(module (memory (export \"memory\") 1) (func (export \"on_request\") (loop (br 0))))",
+                limits(),
+            )
+            .unwrap();
+            let started = Instant::now();
+            assert!(filter.run(input(&[])).is_err());
+            assert!(started.elapsed() < Duration::from_secs(1));
+        }
+
+        #[test]
+        fn test_memory_limit() {
+            // 32 страницы по 64 KiB - 2 MiB при лимите 1 MiB
+            let filter = WasmFilter::from_bytes(
+                b"(module (memory (export \"memory\") 32) (func (export \"on_request\")))",
+                limits(),
+            )
+            .unwrap();
+            assert!(filter.run(input(&[])).is_err());
+
+            // memory.grow сверх лимита возвращает -1, фильтр сообщает об ошибке через unreachable
+            let filter = WasmFilter::from_bytes(
+                b"(module (memory (export \"memory\") 1)
+                   (func (export \"on_request\")
+                     (if (i32.eq (memory.grow (i32.const 64)) (i32.const -1)) (then unreachable))))",
+                limits(),
+            )
+            .unwrap();
+            assert!(filter.run(input(&[])).is_err());
+        }
+
+        #[test]
+        fn test_unknown_import_rejected() {
+            let e = WasmFilter::from_bytes(
+                b"(module (import \"env\" \"abort\" (func)) (func (export \"on_request\")))",
+                limits(),
+            )
+            .err()
+            .unwrap();
+            assert!(e.contains("abort"), "{}", e);
+            assert!(WasmFilter::from_bytes(b"(module)", limits()).is_err());
+        }
+    }
+}